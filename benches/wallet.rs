@@ -0,0 +1,83 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+
+use walletbase::{conf, db};
+
+// Latency of the LWT paths of a single client against the test keyspace, without contention.
+// Concurrent throughput and LWT retry rates are measured by cmd/loadgen.
+//
+//     cargo bench --bench wallet
+//
+// It requires a ScyllaDB with the walletbase_test keyspace, see config/default.toml.
+// The MAC key is the one of the model tests, so that the system wallet's checksum is verified.
+const MAC_KEY: [u8; 32] = [1u8; 32];
+
+fn setup(rt: &Runtime) -> db::scylladb::ScyllaDB {
+    rt.block_on(async {
+        let cfg = conf::Conf::new().unwrap_or_else(|err| panic!("config error: {}", err));
+        let sess = db::scylladb::ScyllaDB::new(cfg.scylla, "walletbase_test")
+            .await
+            .unwrap();
+        // make sure system wallet exists.
+        let mut wallet = db::Wallet::default();
+        let _ = wallet.save(&sess).await;
+        sess
+    })
+}
+
+async fn award(sess: &db::scylladb::ScyllaDB, mac: &db::HMacTag, payee: xid::Id, amount: i64) {
+    let mut txn = db::Transaction::default();
+    txn.prepare(sess, mac, payee, db::TransactionKind::Award, amount)
+        .await
+        .unwrap();
+    txn.commit(sess, mac).await.unwrap();
+}
+
+fn bench_wallet(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let sess = &setup(&rt);
+    let mac = &db::HMacTag::new(MAC_KEY);
+
+    let mut group = c.benchmark_group("wallet");
+    group.sample_size(50);
+
+    group.bench_function("award_prepare_commit", |b| {
+        b.to_async(&rt).iter(|| award(sess, mac, xid::new(), 100));
+    });
+
+    group.bench_function("spend_prepare", |b| {
+        let uid = xid::new();
+        rt.block_on(award(sess, mac, uid, 1_000_000));
+        b.to_async(&rt).iter(|| async move {
+            let mut txn = db::Transaction::with_uid(uid);
+            txn.prepare(sess, mac, db::SYS_ID, db::TransactionKind::Spend, 1)
+                .await
+                .unwrap();
+        });
+    });
+
+    group.bench_function("spend_commit", |b| {
+        let uid = xid::new();
+        rt.block_on(award(sess, mac, uid, 1_000_000));
+        // only the commit is measured, the spend is prepared before.
+        b.to_async(&rt).iter_custom(|iters| async move {
+            let mut elapsed = Duration::ZERO;
+            for _ in 0..iters {
+                let mut txn = db::Transaction::with_uid(uid);
+                txn.prepare(sess, mac, db::SYS_ID, db::TransactionKind::Spend, 1)
+                    .await
+                    .unwrap();
+                let start = Instant::now();
+                txn.commit(sess, mac).await.unwrap();
+                elapsed += start.elapsed();
+            }
+            elapsed
+        });
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_wallet);
+criterion_main!(benches);
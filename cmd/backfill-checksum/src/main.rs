@@ -0,0 +1,80 @@
+use std::{str::FromStr, time::Duration};
+use structured_logger::{async_json::new_writer, Builder};
+use tokio::{io, time};
+use walletbase::{conf, db, router};
+
+// Backfill checksums of legacy wallets created before checksums, they are at sequence 0 without
+// a checksum and skip the verification. The checksum is written under LWT only if the wallet is
+// unchanged, balances and sequences are not changed, so the job is safe to run on a live cluster
+// and to run again. Wallets are scanned in token order at RATE wallets per second, the progress
+// is logged per page and can be resumed with START_AFTER, the last scanned uid.
+#[tokio::main(flavor = "multi_thread", worker_threads = 4)]
+async fn main() -> anyhow::Result<()> {
+    Builder::with_level("info")
+        .with_target_writer("*", new_writer(io::stdout()))
+        .init();
+
+    // the wallet key is loaded by the server's config, CONFIG_FILE_PATH and YIWEN_MKEK.
+    let cfg = conf::Conf::new()?;
+    let keyspace: String = env_or("SCYLLA_KEYSPACE", "walletbase".to_string());
+    let page_size: u16 = env_or("PAGE_SIZE", 100u16).clamp(2, 1000);
+    let rate: u32 = env_or("RATE", 200u32).max(1);
+    let mut page_token: Option<xid::Id> = std::env::var("START_AFTER")
+        .ok()
+        .map(|v| xid::Id::from_str(&v))
+        .transpose()?;
+
+    let mac = router::new_mac(&cfg)?;
+    if !mac.is_loaded() {
+        anyhow::bail!("wallet key is not loaded");
+    }
+    let sess = db::scylladb::ScyllaDB::new(cfg.scylla, &keyspace).await?;
+
+    let pause = Duration::from_millis(page_size as u64 * 1000 / rate as u64);
+    let (mut scanned, mut backfilled, mut skipped) = (0u64, 0u64, 0u64);
+    loop {
+        let wallets = db::Wallet::scan(&sess, page_size, page_token).await?;
+        let done = wallets.len() < page_size as usize;
+        if let Some(last) = wallets.last() {
+            page_token = Some(last.uid);
+        }
+
+        scanned += wallets.len() as u64;
+        for mut wallet in wallets {
+            if wallet.has_checksum() {
+                continue;
+            }
+            if wallet.backfill_checksum(&sess, &mac).await? {
+                backfilled += 1;
+            } else {
+                skipped += 1;
+            }
+        }
+
+        log::info!(target: "backfill",
+            action = "backfill_checksum",
+            scanned = scanned,
+            backfilled = backfilled,
+            skipped = skipped,
+            last_uid = page_token.map(|v| v.to_string()).unwrap_or_default();
+            "",
+        );
+        if done {
+            break;
+        }
+        time::sleep(pause).await;
+    }
+
+    println!(
+        "wallets: {}, backfilled: {}, skipped: {}",
+        scanned, backfilled, skipped
+    );
+    Ok(())
+}
+
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    std::env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
@@ -0,0 +1,135 @@
+use futures::stream::StreamExt;
+use scylla_orm::{ColumnsMap, ToCqlVal};
+use structured_logger::{async_json::new_writer, Builder};
+use tokio::io;
+use walletbase::{conf, db};
+
+// Backfill updated_at of wallets and transactions created before the column was added.
+// It is approximated by the creation time of the wallet's last txn, or of the transaction itself.
+// Rows are updated only if updated_at is still null, so the job is safe to run on a live cluster
+// and to run again.
+#[tokio::main(flavor = "multi_thread", worker_threads = 4)]
+async fn main() -> anyhow::Result<()> {
+    Builder::with_level("info")
+        .with_target_writer("*", new_writer(io::stdout()))
+        .init();
+
+    let nodes = std::env::var("SCYLLA_NODES")
+        .expect("env SCYLLA_NODES required:\nSCYLLA_NODES=127.0.0.1:9042 ./backfill-updated-at");
+    let keyspace: String = env_or("SCYLLA_KEYSPACE", "walletbase".to_string());
+
+    let cfg = conf::ScyllaDB {
+        nodes: nodes.split(',').map(|s| s.to_string()).collect(),
+        username: "".to_string(),
+        password: "".to_string(),
+        query_timeout_ms: 3000,
+        bypass_cache: true,
+        ..Default::default()
+    };
+    let sess = db::scylladb::ScyllaDB::new(cfg, &keyspace).await?;
+
+    let (wallets, wallets_updated) = backfill_wallets(&sess).await?;
+    let (txns, txns_updated) = backfill_transactions(&sess).await?;
+
+    println!(
+        "wallets: {}, updated: {}; transactions: {}, updated: {}",
+        wallets, wallets_updated, txns, txns_updated
+    );
+    Ok(())
+}
+
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    std::env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+// unix ms when the xid was generated.
+fn xid_ms(id: &xid::Id) -> i64 {
+    let mut secs = [0u8; 4];
+    secs.copy_from_slice(&id.0[..4]);
+    u32::from_be_bytes(secs) as i64 * 1000
+}
+
+async fn backfill_wallets(sess: &db::scylladb::ScyllaDB) -> anyhow::Result<(i64, i64)> {
+    let fields = vec![
+        "uid".to_string(),
+        "txn".to_string(),
+        "updated_at".to_string(),
+    ];
+    let query = format!("SELECT {} FROM wallet", fields.join(","));
+    let mut stream = sess.stream(query, ()).await?;
+    let update = "UPDATE wallet SET updated_at=? WHERE uid=? IF updated_at=null";
+
+    let mut total: i64 = 0;
+    let mut updated: i64 = 0;
+    while let Some(row) = stream.next().await {
+        let mut cols = ColumnsMap::with_capacity(fields.len());
+        cols.fill(row?, &fields)?;
+        total += 1;
+        if cols.has("updated_at") {
+            continue;
+        }
+
+        let uid: xid::Id = cols.get_as("uid")?;
+        let txn: xid::Id = cols.get_as("txn")?;
+        // wallets without any transaction were never updated since created.
+        let updated_at = if txn.is_zero() {
+            xid_ms(&uid)
+        } else {
+            xid_ms(&txn)
+        };
+        let res = sess.execute(update, (updated_at, uid.to_cql())).await?;
+        if db::scylladb::extract_applied(res) {
+            updated += 1;
+        }
+    }
+
+    log::info!(target: "backfill",
+        action = "backfill_wallets",
+        total = total,
+        updated = updated;
+        "",
+    );
+    Ok((total, updated))
+}
+
+async fn backfill_transactions(sess: &db::scylladb::ScyllaDB) -> anyhow::Result<(i64, i64)> {
+    let fields = vec![
+        "uid".to_string(),
+        "id".to_string(),
+        "updated_at".to_string(),
+    ];
+    let query = format!("SELECT {} FROM transaction", fields.join(","));
+    let mut stream = sess.stream(query, ()).await?;
+    let update = "UPDATE transaction SET updated_at=? WHERE uid=? AND id=? IF updated_at=null";
+
+    let mut total: i64 = 0;
+    let mut updated: i64 = 0;
+    while let Some(row) = stream.next().await {
+        let mut cols = ColumnsMap::with_capacity(fields.len());
+        cols.fill(row?, &fields)?;
+        total += 1;
+        if cols.has("updated_at") {
+            continue;
+        }
+
+        let uid: xid::Id = cols.get_as("uid")?;
+        let id: xid::Id = cols.get_as("id")?;
+        let res = sess
+            .execute(update, (xid_ms(&id), uid.to_cql(), id.to_cql()))
+            .await?;
+        if db::scylladb::extract_applied(res) {
+            updated += 1;
+        }
+    }
+
+    log::info!(target: "backfill",
+        action = "backfill_transactions",
+        total = total,
+        updated = updated;
+        "",
+    );
+    Ok((total, updated))
+}
@@ -0,0 +1,164 @@
+use std::{
+    fs,
+    io::{BufWriter, Write},
+    str::FromStr,
+};
+use structured_logger::{async_json::new_writer, Builder};
+use tokio::io;
+use walletbase::{conf, db};
+
+const USAGE: &str = "usage: export-wallets --uids FILE [--out FILE]
+
+Exports the wallets, transactions, credits, charges and customers of the users to
+JSON lines, one snapshot per user, to be imported by import-wallets in another region.
+Writes of the users should be stopped before, users with in-flight transactions or
+charges fail and can be exported again later. The cluster is read by the server's
+config, CONFIG_FILE_PATH and SCYLLA_KEYSPACE.
+
+  --uids  file of user ids, one per line, blank lines and lines starting with # are skipped
+  --out   output file, default to ./wallets.jsonl";
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    uids: String,
+    out: String,
+}
+
+#[tokio::main(flavor = "multi_thread", worker_threads = 4)]
+async fn main() -> anyhow::Result<()> {
+    let opts = parse_args(std::env::args().skip(1))?;
+    Builder::with_level("info")
+        .with_target_writer("*", new_writer(io::stdout()))
+        .init();
+
+    let uids = parse_uids(&fs::read_to_string(&opts.uids)?)?;
+    let cfg = conf::Conf::new()?;
+    let keyspace: String = env_or("SCYLLA_KEYSPACE", "walletbase".to_string());
+    let sess = db::scylladb::ScyllaDB::new(cfg.scylla, &keyspace).await?;
+
+    let mut out = BufWriter::new(fs::File::create(&opts.out)?);
+    let (mut exported, mut failed) = (0u64, 0u64);
+    for uid in uids {
+        match db::WalletSnapshot::export(&sess, uid).await {
+            Ok(snapshot) => {
+                serde_json::to_writer(&mut out, &snapshot)?;
+                out.write_all(b"\n")?;
+                exported += 1;
+                log::info!(target: "migrate",
+                    action = "export_wallet",
+                    uid = uid.to_string(),
+                    transactions = snapshot.transactions.len(),
+                    credits = snapshot.credits.len(),
+                    charges = snapshot.charges.len(),
+                    customers = snapshot.customers.len();
+                    "",
+                );
+            }
+            Err(err) => {
+                failed += 1;
+                log::error!(target: "migrate",
+                    action = "export_wallet",
+                    uid = uid.to_string();
+                    "{}", err,
+                );
+            }
+        }
+    }
+    out.flush()?;
+
+    println!("exported: {}, failed: {}", exported, failed);
+    if failed > 0 {
+        anyhow::bail!("{} wallets failed to export", failed);
+    }
+    Ok(())
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> anyhow::Result<Options> {
+    let mut opts = Options {
+        uids: String::new(),
+        out: "./wallets.jsonl".to_string(),
+    };
+
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            println!("{}", USAGE);
+            std::process::exit(0);
+        }
+
+        let val = args
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("missing value of {}\n\n{}", arg, USAGE))?;
+        match arg.as_str() {
+            "--uids" => opts.uids = val,
+            "--out" => opts.out = val,
+            _ => anyhow::bail!("unknown flag {}\n\n{}", arg, USAGE),
+        }
+    }
+
+    if opts.uids.is_empty() {
+        anyhow::bail!("--uids is required\n\n{}", USAGE);
+    }
+    Ok(opts)
+}
+
+// parses user ids, duplicated ones are exported once.
+fn parse_uids(data: &str) -> anyhow::Result<Vec<xid::Id>> {
+    let mut uids: Vec<xid::Id> = Vec::new();
+    for (i, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let uid = xid::Id::from_str(line)
+            .map_err(|err| anyhow::anyhow!("invalid uid {:?} at line {}: {}", line, i + 1, err))?;
+        if !uids.contains(&uid) {
+            uids.push(uid);
+        }
+    }
+    Ok(uids)
+}
+
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    std::env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(v: &[&str]) -> impl Iterator<Item = String> {
+        v.iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn parse_args_works() {
+        let opts = parse_args(args(&["--uids", "uids.txt"])).unwrap();
+        assert_eq!("uids.txt", opts.uids);
+        assert_eq!("./wallets.jsonl", opts.out);
+
+        let opts = parse_args(args(&["--uids", "uids.txt", "--out", "a.jsonl"])).unwrap();
+        assert_eq!("a.jsonl", opts.out);
+
+        assert!(parse_args(args(&[])).is_err());
+        assert!(parse_args(args(&["--uids"])).is_err());
+        assert!(parse_args(args(&["--uids", "uids.txt", "--in", "x"])).is_err());
+    }
+
+    #[test]
+    fn parse_uids_works() {
+        let a = xid::new();
+        let b = xid::new();
+        let data = format!("# users of batch 1\n{}\n\n  {}  \n{}\n", a, b, a);
+        assert_eq!(vec![a, b], parse_uids(&data).unwrap());
+        assert!(parse_uids("").unwrap().is_empty());
+        assert!(parse_uids("not-an-id").is_err());
+    }
+}
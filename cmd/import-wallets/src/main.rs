@@ -0,0 +1,144 @@
+use std::{
+    fs,
+    io::{BufRead, BufReader},
+};
+use structured_logger::{async_json::new_writer, Builder};
+use tokio::io;
+use walletbase::{conf, db, router};
+
+const USAGE: &str = "usage: import-wallets --in FILE
+
+Imports the snapshots exported by export-wallets into the cluster of this region.
+Each snapshot's wallet checksum and sequence are verified before any row is written,
+rows are inserted only if not exists, so an interrupted import can run again. A wallet
+that exists with a different sequence fails the snapshot. The cluster and the wallet
+key are read by the server's config, CONFIG_FILE_PATH, SCYLLA_KEYSPACE and YIWEN_MKEK.
+
+  --in  JSON lines file of the snapshots";
+
+#[tokio::main(flavor = "multi_thread", worker_threads = 4)]
+async fn main() -> anyhow::Result<()> {
+    let input = parse_args(std::env::args().skip(1))?;
+    Builder::with_level("info")
+        .with_target_writer("*", new_writer(io::stdout()))
+        .init();
+
+    let cfg = conf::Conf::new()?;
+    let keyspace: String = env_or("SCYLLA_KEYSPACE", "walletbase".to_string());
+    let mac = router::new_mac(&cfg)?;
+    if !mac.is_loaded() {
+        anyhow::bail!("wallet key is not loaded");
+    }
+    let sess = db::scylladb::ScyllaDB::new(cfg.scylla, &keyspace).await?;
+
+    let reader = BufReader::new(fs::File::open(&input)?);
+    let (mut imported, mut failed) = (0u64, 0u64);
+    let mut stats = db::ImportStats::default();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let snapshot: db::WalletSnapshot = match serde_json::from_str(&line) {
+            Ok(snapshot) => snapshot,
+            Err(err) => {
+                failed += 1;
+                log::error!(target: "migrate",
+                    action = "import_wallet",
+                    line = i + 1;
+                    "invalid snapshot: {}", err,
+                );
+                continue;
+            }
+        };
+
+        match snapshot.import(&sess, &mac).await {
+            Ok(res) => {
+                imported += 1;
+                stats.inserted += res.inserted;
+                stats.existing += res.existing;
+                log::info!(target: "migrate",
+                    action = "import_wallet",
+                    uid = snapshot.uid.as_str(),
+                    inserted = res.inserted,
+                    existing = res.existing;
+                    "",
+                );
+            }
+            Err(err) => {
+                failed += 1;
+                log::error!(target: "migrate",
+                    action = "import_wallet",
+                    uid = snapshot.uid.as_str(),
+                    line = i + 1;
+                    "{}", err,
+                );
+            }
+        }
+    }
+
+    println!(
+        "imported: {}, failed: {}, rows inserted: {}, rows existing: {}",
+        imported, failed, stats.inserted, stats.existing
+    );
+    if failed > 0 {
+        anyhow::bail!("{} wallets failed to import", failed);
+    }
+    Ok(())
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> anyhow::Result<String> {
+    let mut input = String::new();
+
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            println!("{}", USAGE);
+            std::process::exit(0);
+        }
+
+        let val = args
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("missing value of {}\n\n{}", arg, USAGE))?;
+        match arg.as_str() {
+            "--in" => input = val,
+            _ => anyhow::bail!("unknown flag {}\n\n{}", arg, USAGE),
+        }
+    }
+
+    if input.is_empty() {
+        anyhow::bail!("--in is required\n\n{}", USAGE);
+    }
+    Ok(input)
+}
+
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    std::env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(v: &[&str]) -> impl Iterator<Item = String> {
+        v.iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn parse_args_works() {
+        assert_eq!(
+            "wallets.jsonl",
+            parse_args(args(&["--in", "wallets.jsonl"])).unwrap()
+        );
+        assert!(parse_args(args(&[])).is_err());
+        assert!(parse_args(args(&["--in"])).is_err());
+        assert!(parse_args(args(&["--uids", "uids.txt"])).is_err());
+    }
+}
@@ -0,0 +1,265 @@
+use std::{fs, path::Path};
+use walletbase::crypto::{self, iana};
+
+const USAGE: &str = "usage: keygen [--kek-kid KID] [--kid KID] [--alg direct|a256gcm|hs256] [--wrap a256gcm|a256kw] [--aad AAD] [--out DIR] [--force]
+
+Generates a KEK wrapped by the MKEK, and a wallet HMAC key wrapped by the KEK.
+The MKEK is read from env YIWEN_MKEK, the test key is used if it is not set.
+
+  --kek-kid  key id of the KEK, default to today in YYYYMMDD
+  --kid      key id of the wallet key, default to 1
+  --alg      algorithm of the wallet key, default to direct
+  --wrap     algorithm wrapping the keys, default to a256gcm. a256kw keeps only the
+             key material, the kid and alg of the wrapped keys are not kept
+  --aad      additional authenticated data, default to yiwen.ai, not used by a256kw
+  --out      output directory, default to ./keys
+  --force    overwrite existing key files";
+
+const TEST_MKEK: &str = "YiWenAI-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-LLc";
+
+struct Options {
+    kek_kid: String,
+    kid: String,
+    alg: iana::Algorithm,
+    wrap: iana::Algorithm,
+    aad: String,
+    out: String,
+    force: bool,
+}
+
+// Generates key files in the format read by the server: Encrypt0 + CBOR tag + base64url,
+// the raw CBOR is written to the .cbor file besides.
+fn main() -> anyhow::Result<()> {
+    let opts = parse_args(std::env::args().skip(1))?;
+
+    let mkek = match std::env::var("YIWEN_MKEK") {
+        Ok(v) => v,
+        Err(_) => {
+            eprintln!("env YIWEN_MKEK not set, using the test MKEK");
+            TEST_MKEK.to_string()
+        }
+    };
+    let mkek: [u8; 32] = crypto::base64url_decode(&mkek)?
+        .try_into()
+        .map_err(|_| anyhow::Error::msg("Invalid YIWEN_MKEK, expected 32 bytes"))?;
+
+    let out = Path::new(&opts.out);
+    let kek_file = out.join(format!("encrypted-{}-kek.key", alg_name(opts.wrap)));
+    let wallet_key_file = out.join(format!("encrypted-{}-wallet.key", alg_name(opts.alg)));
+    if !opts.force {
+        for file in [&kek_file, &wallet_key_file] {
+            if file.exists() {
+                anyhow::bail!("{} exists, use --force to overwrite", file.display());
+            }
+        }
+    }
+    fs::create_dir_all(out)?;
+
+    let aad = opts.aad.as_bytes();
+    let kek = crypto::Key::new_sym(opts.wrap, opts.kek_kid.as_bytes())?;
+    let data = wrap_key(opts.wrap, mkek, b"", &kek, aad)?;
+    let kek_data = write_key(&kek_file, &data)?;
+
+    let wallet_key = crypto::Key::new_sym(opts.alg, opts.kid.as_bytes())?;
+    let data = wrap_key(
+        opts.wrap,
+        kek.get_private()?,
+        kek.key_id().as_slice(),
+        &wallet_key,
+        aad,
+    )?;
+    write_key(&wallet_key_file, &data)?;
+
+    // reads the keys back as the server does.
+    let kek2 = read_key(mkek, aad, &kek_data)?;
+    let wallet_key2 = read_key(
+        kek2.get_private()?,
+        aad,
+        &fs::read_to_string(&wallet_key_file)?,
+    )?;
+    if wallet_key2.get_private()? != wallet_key.get_private()? {
+        anyhow::bail!("failed to read back the wallet key");
+    }
+
+    println!("[keys]\naad = \"{}\"", opts.aad);
+    println!("kek = \"{}\"", kek_data);
+    println!("wallet_key_file = \"{}\"", wallet_key_file.display());
+    Ok(())
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> anyhow::Result<Options> {
+    let mut opts = Options {
+        kek_kid: today(),
+        kid: "1".to_string(),
+        alg: iana::Algorithm::Direct,
+        wrap: iana::Algorithm::A256GCM,
+        aad: "yiwen.ai".to_string(),
+        out: "./keys".to_string(),
+        force: false,
+    };
+
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        if arg == "--force" {
+            opts.force = true;
+            continue;
+        }
+        if arg == "-h" || arg == "--help" {
+            println!("{}", USAGE);
+            std::process::exit(0);
+        }
+
+        let val = args
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("missing value of {}\n\n{}", arg, USAGE))?;
+        match arg.as_str() {
+            "--kek-kid" => opts.kek_kid = val,
+            "--kid" => opts.kid = val,
+            "--alg" => opts.alg = parse_alg(&val)?,
+            "--wrap" => opts.wrap = parse_wrap(&val)?,
+            "--aad" => opts.aad = val,
+            "--out" => opts.out = val,
+            _ => anyhow::bail!("unknown flag {}\n\n{}", arg, USAGE),
+        }
+    }
+
+    if opts.kek_kid.is_empty() || opts.kid.is_empty() {
+        anyhow::bail!("key id should not be empty");
+    }
+    Ok(opts)
+}
+
+fn parse_alg(alg: &str) -> anyhow::Result<iana::Algorithm> {
+    match alg {
+        "direct" => Ok(iana::Algorithm::Direct),
+        "a256gcm" => Ok(iana::Algorithm::A256GCM),
+        "hs256" => Ok(iana::Algorithm::HMAC_256_256),
+        _ => anyhow::bail!("unsupported algorithm {}", alg),
+    }
+}
+
+fn parse_wrap(alg: &str) -> anyhow::Result<iana::Algorithm> {
+    match alg {
+        "a256gcm" => Ok(iana::Algorithm::A256GCM),
+        "a256kw" => Ok(iana::Algorithm::A256KW),
+        _ => anyhow::bail!("unsupported wrapping algorithm {}", alg),
+    }
+}
+
+fn alg_name(alg: iana::Algorithm) -> &'static str {
+    match alg {
+        iana::Algorithm::A256GCM => "a256gcm",
+        iana::Algorithm::A256KW => "a256kw",
+        iana::Algorithm::HMAC_256_256 => "hs256",
+        _ => "direct",
+    }
+}
+
+// writes the base64url encoded key, and returns it.
+fn write_key(file: &Path, encrypt0: &[u8]) -> anyhow::Result<String> {
+    let data = crypto::wrap_cbor_tag(encrypt0);
+    let mut cbor_file = file.as_os_str().to_owned();
+    cbor_file.push(".cbor");
+    fs::write(cbor_file, &data)?;
+    let encoded = crypto::base64url_encode(&data);
+    fs::write(file, &encoded)?;
+    Ok(encoded)
+}
+
+// wraps the key into a COSE_Encrypt0 by the wrapping algorithm.
+fn wrap_key(
+    wrap: iana::Algorithm,
+    key: [u8; 32],
+    kid: &[u8],
+    data: &crypto::Key,
+    aad: &[u8],
+) -> anyhow::Result<Vec<u8>> {
+    match wrap {
+        iana::Algorithm::A256KW => crypto::KeyWrap::new(key, kid).wrap(data),
+        _ => crypto::Encrypt0::new(key, kid).encrypt(&data.clone().to_vec()?, aad),
+    }
+}
+
+fn read_key(key: [u8; 32], aad: &[u8], ciphertext: &str) -> anyhow::Result<crypto::Key> {
+    let data = crypto::base64url_decode(ciphertext.trim())?;
+    crypto::decrypt_key(key, crypto::unwrap_cbor_tag(&data), aad)
+}
+
+fn today() -> String {
+    let days = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64 / 86400)
+        .unwrap_or_default();
+    let (y, m, d) = civil_from_days(days);
+    format!("{:04}{:02}{:02}", y, m, d)
+}
+
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    (y, m, d)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(v: &[&str]) -> impl Iterator<Item = String> {
+        v.iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn civil_from_days_works() {
+        assert_eq!((1970, 1, 1), civil_from_days(0));
+        assert_eq!((2023, 5, 11), civil_from_days(19488));
+        assert_eq!((2000, 2, 29), civil_from_days(11016));
+    }
+
+    #[test]
+    fn parse_args_works() {
+        let opts = parse_args(args(&[])).unwrap();
+        assert_eq!("1", opts.kid);
+        assert_eq!(iana::Algorithm::Direct, opts.alg);
+        assert_eq!(iana::Algorithm::A256GCM, opts.wrap);
+        assert_eq!("./keys", opts.out);
+        assert!(!opts.force);
+
+        let opts = parse_args(args(&[
+            "--kek-kid",
+            "20230511",
+            "--kid",
+            "42",
+            "--alg",
+            "hs256",
+            "--wrap",
+            "a256kw",
+            "--out",
+            "/tmp/k",
+            "--force",
+        ]))
+        .unwrap();
+        assert_eq!("20230511", opts.kek_kid);
+        assert_eq!("42", opts.kid);
+        assert_eq!(iana::Algorithm::HMAC_256_256, opts.alg);
+        assert_eq!(iana::Algorithm::A256KW, opts.wrap);
+        assert_eq!("/tmp/k", opts.out);
+        assert!(opts.force);
+
+        assert!(parse_args(args(&["--alg", "rsa"])).is_err());
+        assert!(parse_args(args(&["--wrap", "direct"])).is_err());
+        assert!(parse_args(args(&["--kid"])).is_err());
+        assert!(parse_args(args(&["--kid", ""])).is_err());
+        assert!(parse_args(args(&["--unknown", "1"])).is_err());
+    }
+}
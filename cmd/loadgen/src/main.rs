@@ -0,0 +1,252 @@
+use axum_web::erring::HTTPError;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use structured_logger::{async_json::new_writer, Builder};
+use tokio::io;
+use walletbase::{conf, db};
+
+// Drives concurrent award/spend transactions against a test keyspace, and reports the latency
+// distributions of prepare and commit, and the LWT retry rates, as a JSON object on stdout.
+// Every worker awards a user from the system wallet and spends it back, so that the system
+// wallet is the hot spot, and USERS controls the contention on the user wallets.
+//
+//     SCYLLA_NODES=127.0.0.1:9042 CONCURRENCY=32 USERS=8 DURATION_SECS=60 ./loadgen
+//
+// Never run it against a production keyspace, the MAC key is the one of the model tests.
+#[tokio::main(flavor = "multi_thread", worker_threads = 4)]
+async fn main() -> anyhow::Result<()> {
+    Builder::with_level("info")
+        .with_target_writer("*", new_writer(io::stderr()))
+        .init();
+
+    let nodes = std::env::var("SCYLLA_NODES")
+        .expect("env SCYLLA_NODES required:\nSCYLLA_NODES=127.0.0.1:9042 ./loadgen");
+    let keyspace: String = env_or("SCYLLA_KEYSPACE", "walletbase_test".to_string());
+    let concurrency: usize = env_or("CONCURRENCY", 16);
+    let users: usize = env_or("USERS", 16);
+    let duration_secs: u64 = env_or("DURATION_SECS", 30);
+    let amount: i64 = env_or("AMOUNT", 1);
+    if keyspace == "walletbase" {
+        anyhow::bail!("loadgen should not run against the production keyspace");
+    }
+
+    let cfg = conf::ScyllaDB {
+        nodes: nodes.split(',').map(|s| s.to_string()).collect(),
+        username: "".to_string(),
+        password: "".to_string(),
+        query_timeout_ms: 3000,
+        bypass_cache: true,
+        ..Default::default()
+    };
+    let sess = Arc::new(db::scylladb::ScyllaDB::new(cfg, &keyspace).await?);
+    let mac = Arc::new(db::HMacTag::new([1u8; 32]));
+    // make sure system wallet exists.
+    let mut wallet = db::Wallet::default();
+    let _ = wallet.save(&sess).await;
+
+    let uids: Arc<Vec<xid::Id>> = Arc::new((0..users.max(1)).map(|_| xid::new()).collect());
+    let before = db::lwt_retry_metrics();
+    let start = Instant::now();
+    let deadline = start + Duration::from_secs(duration_secs);
+
+    let mut workers = Vec::with_capacity(concurrency);
+    for i in 0..concurrency.max(1) {
+        let (sess, mac, uids) = (sess.clone(), mac.clone(), uids.clone());
+        workers.push(tokio::spawn(async move {
+            let mut stats = WorkerStats::default();
+            let mut n = i;
+            while Instant::now() < deadline {
+                let uid = uids[n % uids.len()];
+                n += 1;
+                if run_cycle(&sess, &mac, &mut stats, uid, amount).await {
+                    stats.cycles += 1;
+                }
+            }
+            stats
+        }));
+    }
+
+    let mut total = WorkerStats::default();
+    for stats in futures::future::join_all(workers).await {
+        total.merge(stats?);
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    let after = db::lwt_retry_metrics();
+
+    let calls = after.calls - before.calls;
+    let retries = after.retries - before.retries;
+    let report = Report {
+        keyspace,
+        concurrency,
+        users,
+        elapsed_secs: elapsed,
+        cycles: total.cycles,
+        throughput: total.cycles as f64 / elapsed,
+        latency_us: total
+            .latencies
+            .iter_mut()
+            .map(|(op, v)| (op.to_string(), Distribution::from(v)))
+            .collect(),
+        errors: total.errors,
+        lwt: LwtReport {
+            calls,
+            retries,
+            exhausted: after.exhausted - before.exhausted,
+            retry_rate: if calls > 0 {
+                retries as f64 / calls as f64
+            } else {
+                0.0
+            },
+        },
+    };
+
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    std::env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+#[derive(Default)]
+struct WorkerStats {
+    cycles: u64,
+    latencies: HashMap<&'static str, Vec<u64>>, // op -> latencies in µs
+    errors: BTreeMap<String, u64>,              // "op:error" -> count
+}
+
+impl WorkerStats {
+    fn record<T>(&mut self, op: &'static str, start: Instant, res: &anyhow::Result<T>) -> bool {
+        match res {
+            Ok(_) => {
+                self.latencies
+                    .entry(op)
+                    .or_default()
+                    .push(start.elapsed().as_micros() as u64);
+                true
+            }
+            Err(err) => {
+                let code = match err.downcast_ref::<HTTPError>() {
+                    Some(err) => err.code.to_string(),
+                    None => "other".to_string(),
+                };
+                *self.errors.entry(format!("{}:{}", op, code)).or_default() += 1;
+                false
+            }
+        }
+    }
+
+    fn merge(&mut self, other: WorkerStats) {
+        self.cycles += other.cycles;
+        for (op, v) in other.latencies {
+            self.latencies.entry(op).or_default().extend(v);
+        }
+        for (k, n) in other.errors {
+            *self.errors.entry(k).or_default() += n;
+        }
+    }
+}
+
+// awards the user from the system, and spends it back.
+async fn run_cycle(
+    sess: &db::scylladb::ScyllaDB,
+    mac: &db::HMacTag,
+    stats: &mut WorkerStats,
+    uid: xid::Id,
+    amount: i64,
+) -> bool {
+    for (payer, payee, kind, prepare_op, commit_op) in [
+        (
+            db::SYS_ID,
+            uid,
+            db::TransactionKind::Award,
+            "award.prepare",
+            "award.commit",
+        ),
+        (
+            uid,
+            db::SYS_ID,
+            db::TransactionKind::Spend,
+            "spend.prepare",
+            "spend.commit",
+        ),
+    ] {
+        let mut txn = db::Transaction::with_uid(payer);
+        let start = Instant::now();
+        let res = txn.prepare(sess, mac, payee, kind, amount).await;
+        if !stats.record(prepare_op, start, &res) {
+            return false;
+        }
+
+        let start = Instant::now();
+        let res = txn.commit(sess, mac).await;
+        if !stats.record(commit_op, start, &res) {
+            return false;
+        }
+    }
+    true
+}
+
+#[derive(Serialize)]
+struct Report {
+    keyspace: String,
+    concurrency: usize,
+    users: usize,
+    elapsed_secs: f64,
+    cycles: u64,
+    throughput: f64, // cycles per second
+    latency_us: BTreeMap<String, Distribution>,
+    errors: BTreeMap<String, u64>,
+    lwt: LwtReport,
+}
+
+#[derive(Serialize)]
+struct LwtReport {
+    calls: u64,
+    retries: u64,
+    exhausted: u64,
+    retry_rate: f64, // retries per LWT loop
+}
+
+#[derive(Serialize)]
+struct Distribution {
+    count: usize,
+    mean: u64,
+    p50: u64,
+    p90: u64,
+    p99: u64,
+    p999: u64,
+    max: u64,
+}
+
+impl Distribution {
+    fn from(v: &mut [u64]) -> Self {
+        v.sort_unstable();
+        let at = |q: f64| -> u64 {
+            if v.is_empty() {
+                return 0;
+            }
+            v[((v.len() - 1) as f64 * q).round() as usize]
+        };
+        Self {
+            count: v.len(),
+            mean: if v.is_empty() {
+                0
+            } else {
+                v.iter().sum::<u64>() / v.len() as u64
+            },
+            p50: at(0.5),
+            p90: at(0.9),
+            p99: at(0.99),
+            p999: at(0.999),
+            max: v.last().copied().unwrap_or_default(),
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use futures::stream::StreamExt;
+use scylla_orm::{ColumnsMap, ToCqlVal};
+use serde::Serialize;
+use structured_logger::{async_json::new_writer, Builder};
+use tokio::io;
+use walletbase::{conf, db};
+
+// Reconcile wallet.credits with the sum of the credit rows of the wallet.
+// wallet.credits can drift from the credit history when the LWT loop of Credit::save gives up
+// after the credit row was inserted. The credit table is streamed partition by partition,
+// wallets that drift are reported as JSON lines on stdout, and repaired with REPAIR=true.
+#[tokio::main(flavor = "multi_thread", worker_threads = 4)]
+async fn main() -> anyhow::Result<()> {
+    Builder::with_level("info")
+        .with_target_writer("*", new_writer(io::stderr()))
+        .init();
+
+    let nodes = std::env::var("SCYLLA_NODES")
+        .expect("env SCYLLA_NODES required:\nSCYLLA_NODES=127.0.0.1:9042 ./reconcile-credits");
+    let keyspace: String = env_or("SCYLLA_KEYSPACE", "walletbase".to_string());
+    let repair: bool = env_or("REPAIR", false);
+
+    let cfg = conf::ScyllaDB {
+        nodes: nodes.split(',').map(|s| s.to_string()).collect(),
+        username: "".to_string(),
+        password: "".to_string(),
+        query_timeout_ms: 3000,
+        bypass_cache: true,
+        ..Default::default()
+    };
+    let sess = db::scylladb::ScyllaDB::new(cfg, &keyspace).await?;
+
+    let fields = vec!["token".to_string(), "uid".to_string(), "amount".to_string()];
+    let query = "SELECT token(uid),uid,amount FROM credit";
+    let mut stream = sess.stream(query, ()).await?;
+
+    let mut summary = Summary::default();
+    // rows of a partition are contiguous in the token order.
+    let mut current: Option<(xid::Id, i64)> = None;
+    while let Some(row) = stream.next().await {
+        let mut cols = ColumnsMap::with_capacity(fields.len());
+        cols.fill(row?, &fields)?;
+        let uid: xid::Id = cols.get_as("uid")?;
+        let amount: i64 = cols.get_as("amount")?;
+
+        match current.as_mut() {
+            Some((id, sum)) if *id == uid => *sum += amount,
+            _ => {
+                if let Some((id, sum)) = current.take() {
+                    reconcile(&sess, id, sum, repair, &mut summary).await?;
+                }
+                current = Some((uid, amount));
+            }
+        }
+    }
+    if let Some((id, sum)) = current.take() {
+        reconcile(&sess, id, sum, repair, &mut summary).await?;
+    }
+
+    log::info!(target: "reconcile",
+        action = "summary",
+        wallets = summary.wallets,
+        drifted = summary.drifted,
+        repaired = summary.repaired,
+        failed = summary.failed;
+        "",
+    );
+    eprintln!(
+        "wallets: {}, drifted: {}, repaired: {}, failed: {}",
+        summary.wallets, summary.drifted, summary.repaired, summary.failed
+    );
+    if summary.failed > 0 {
+        anyhow::bail!("{} wallets failed to repair, run again", summary.failed);
+    }
+
+    Ok(())
+}
+
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    std::env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+#[derive(Default)]
+struct Summary {
+    wallets: usize,
+    drifted: usize,
+    repaired: usize,
+    failed: usize,
+}
+
+#[derive(Serialize)]
+struct Drift {
+    uid: String,
+    wallet_credits: i64,
+    credit_sum: i64,
+    diff: i64, // credit_sum - wallet_credits
+    repaired: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+async fn reconcile(
+    sess: &db::scylladb::ScyllaDB,
+    uid: xid::Id,
+    credit_sum: i64,
+    repair: bool,
+    summary: &mut Summary,
+) -> anyhow::Result<()> {
+    if uid == db::SYS_ID {
+        return Ok(());
+    }
+
+    summary.wallets += 1;
+    let wallet_credits = wallet_credits(sess, uid).await?;
+    if wallet_credits == credit_sum {
+        return Ok(());
+    }
+
+    summary.drifted += 1;
+    let mut drift = Drift {
+        uid: uid.to_string(),
+        wallet_credits,
+        credit_sum,
+        diff: credit_sum - wallet_credits,
+        repaired: false,
+        error: None,
+    };
+
+    if repair {
+        match repair_wallet(sess, uid).await {
+            Ok(true) => {
+                drift.repaired = true;
+                summary.repaired += 1;
+            }
+            Ok(false) => {
+                drift.error = Some("wallet changed during repair".to_string());
+                summary.failed += 1;
+            }
+            Err(err) => {
+                drift.error = Some(err.to_string());
+                summary.failed += 1;
+            }
+        }
+    }
+
+    println!("{}", serde_json::to_string(&drift)?);
+    Ok(())
+}
+
+async fn wallet_credits(sess: &db::scylladb::ScyllaDB, uid: xid::Id) -> anyhow::Result<i64> {
+    let fields = vec!["credits".to_string()];
+    let query = "SELECT credits FROM wallet WHERE uid=? LIMIT 1";
+    let rows = sess.execute_iter(query, (uid.to_cql(),)).await?;
+    match rows.into_iter().next() {
+        Some(row) => {
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            Ok(cols.get_as("credits")?)
+        }
+        None => Ok(0),
+    }
+}
+
+// credits may be added while the table is streaming, so the credit rows and the wallet
+// are read again, and the wallet is updated only if it is unchanged since then.
+async fn repair_wallet(sess: &db::scylladb::ScyllaDB, uid: xid::Id) -> anyhow::Result<bool> {
+    let wallet_credits = wallet_credits(sess, uid).await?;
+    let fields = vec!["amount".to_string()];
+    let query = "SELECT amount FROM credit WHERE uid=?";
+    let mut stream = sess.stream(query, (uid.to_cql(),)).await?;
+    let mut credit_sum: i64 = 0;
+    while let Some(row) = stream.next().await {
+        let mut cols = ColumnsMap::with_capacity(fields.len());
+        cols.fill(row?, &fields)?;
+        credit_sum += cols.get_as::<i64>("amount")?;
+    }
+    if credit_sum == wallet_credits {
+        return Ok(true);
+    }
+
+    let query = "UPDATE wallet SET credits=? WHERE uid=? IF credits=?";
+    let params = (credit_sum, uid.to_cql(), wallet_credits);
+    let res = sess.execute(query, params).await?;
+    let applied = db::scylladb::extract_applied(res);
+    log::info!(target: "reconcile",
+        action = "repair_wallet",
+        uid = uid.to_string(),
+        from = wallet_credits,
+        to = credit_sum,
+        applied = applied;
+        "",
+    );
+    Ok(applied)
+}
@@ -0,0 +1,523 @@
+use axum_web::erring::HTTPError;
+use futures::stream::{self, StreamExt};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use scylla_orm::ColumnsMap;
+use serde::Serialize;
+use std::{collections::BTreeMap, str::FromStr, sync::Arc, time::Instant};
+use structured_logger::{async_json::new_writer, Builder};
+use tokio::io;
+use walletbase::{conf, db};
+
+// Generates a staging dataset: WALLETS wallets with up to CHARGES topup charges each, then up to
+// TXNS transactions per wallet drawn from the MIX of kinds, all written through the models.
+// Amounts are drawn from distributions, "const:N", "uniform:MIN:MAX" or "exp:MEAN", and the
+// generation is reproducible by SEED. CONCURRENCY wallets are generated at a time.
+// A manifest of the generated ids is written as JSON to MANIFEST, or stdout if not set.
+//
+//     SCYLLA_NODES=127.0.0.1:9042 WALLETS=1000 CHARGES=5 TXNS=20 \
+//     MIX=spend:50,sponsor:20,subscribe:15,award:10,withdraw:5 ./seed
+//
+// Never run it against a production keyspace, the MAC key is the one of the model tests.
+#[tokio::main(flavor = "multi_thread", worker_threads = 4)]
+async fn main() -> anyhow::Result<()> {
+    Builder::with_level("info")
+        .with_target_writer("*", new_writer(io::stderr()))
+        .init();
+
+    let nodes = std::env::var("SCYLLA_NODES")
+        .expect("env SCYLLA_NODES required:\nSCYLLA_NODES=127.0.0.1:9042 ./seed");
+    let keyspace: String = env_or("SCYLLA_KEYSPACE", "walletbase_test".to_string());
+    if keyspace == "walletbase" {
+        anyhow::bail!("seed should not run against the production keyspace");
+    }
+
+    let opts = Arc::new(Options {
+        wallets: env_or("WALLETS", 100usize).max(1),
+        charges: env_or("CHARGES", 3usize),
+        txns: env_or("TXNS", 10usize),
+        concurrency: env_or("CONCURRENCY", 16usize).max(1),
+        seed: env_or("SEED", 42u64),
+        mix: parse_mix(&env_or("MIX", DEFAULT_MIX.to_string()))?,
+        charge_amount: Distribution::from_str(&env_or(
+            "CHARGE_AMOUNT",
+            "uniform:100:10000".to_string(),
+        ))?,
+        txn_amount: Distribution::from_str(&env_or("TXN_AMOUNT", "exp:100".to_string()))?,
+        charge_fail_rate: env_or("CHARGE_FAIL_RATE", 0.1f64).clamp(0.0, 1.0),
+    });
+    let manifest_path = std::env::var("MANIFEST").ok();
+
+    let cfg = conf::ScyllaDB {
+        nodes: nodes.split(',').map(|s| s.to_string()).collect(),
+        username: "".to_string(),
+        password: "".to_string(),
+        query_timeout_ms: 3000,
+        bypass_cache: true,
+        ..Default::default()
+    };
+    let sess = Arc::new(db::scylladb::ScyllaDB::new(cfg, &keyspace).await?);
+    let mac = Arc::new(db::HMacTag::new([1u8; 32]));
+    // make sure system wallet exists.
+    let mut wallet = db::Wallet::default();
+    let _ = wallet.save(&sess).await;
+
+    let start = Instant::now();
+    let uids: Arc<Vec<xid::Id>> = Arc::new((0..opts.wallets).map(|_| xid::new()).collect());
+
+    // charges first, so that wallets have balances to pay with.
+    let mut wallets: Vec<SeededWallet> = stream::iter(0..uids.len())
+        .map(|i| {
+            let (sess, mac, opts, uid) = (sess.clone(), mac.clone(), opts.clone(), uids[i]);
+            async move { seed_charges(&sess, &mac, &opts, i, uid).await }
+        })
+        .buffer_unordered(opts.concurrency)
+        .collect()
+        .await;
+    wallets.sort_by_key(|w| w.index);
+    log::info!(target: "seed",
+        action = "charges",
+        wallets = wallets.len(),
+        elapsed_ms = start.elapsed().as_millis() as u64;
+        "",
+    );
+
+    let mut wallets: Vec<SeededWallet> = stream::iter(wallets)
+        .map(|w| {
+            let (sess, mac, opts, uids) = (sess.clone(), mac.clone(), opts.clone(), uids.clone());
+            async move { seed_transactions(&sess, &mac, &opts, &uids, w).await }
+        })
+        .buffer_unordered(opts.concurrency)
+        .collect()
+        .await;
+    wallets.sort_by_key(|w| w.index);
+
+    let mut totals = Totals::default();
+    for w in &wallets {
+        totals.add(w);
+    }
+    log::info!(target: "seed",
+        action = "summary",
+        wallets = totals.wallets,
+        charges = totals.charges,
+        charges_failed = totals.charges_failed,
+        transactions = totals.transactions,
+        errors = totals.errors.values().sum::<u64>(),
+        elapsed_ms = start.elapsed().as_millis() as u64;
+        "",
+    );
+
+    let manifest = Manifest {
+        keyspace,
+        seed: opts.seed,
+        elapsed_secs: start.elapsed().as_secs_f64(),
+        totals,
+        wallets,
+    };
+    let data = serde_json::to_string_pretty(&manifest)?;
+    match manifest_path {
+        Some(path) => std::fs::write(path, data)?,
+        None => println!("{}", data),
+    }
+    Ok(())
+}
+
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    std::env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+const DEFAULT_MIX: &str = "spend:50,sponsor:20,subscribe:15,award:10,withdraw:5";
+
+struct Options {
+    wallets: usize,
+    charges: usize, // max charges per wallet
+    txns: usize,    // max transactions per wallet
+    concurrency: usize,
+    seed: u64,
+    mix: Vec<(db::TransactionKind, u32)>, // kind -> weight
+    charge_amount: Distribution,
+    txn_amount: Distribution,
+    charge_fail_rate: f64,
+}
+
+// kinds that can be generated between the system and users, or among users.
+fn parse_mix(s: &str) -> anyhow::Result<Vec<(db::TransactionKind, u32)>> {
+    let mut mix = Vec::new();
+    for item in s.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+        let (kind, weight) = item
+            .split_once(':')
+            .ok_or_else(|| anyhow::anyhow!("invalid mix item {:?}, expected kind:weight", item))?;
+        let kind = db::TransactionKind::from_str(kind)?;
+        match kind {
+            db::TransactionKind::Award
+            | db::TransactionKind::Spend
+            | db::TransactionKind::Sponsor
+            | db::TransactionKind::Subscribe
+            | db::TransactionKind::Withdraw => {}
+            _ => anyhow::bail!("{} transactions can not be generated", kind.as_ref()),
+        }
+        let weight: u32 = weight.parse()?;
+        if weight > 0 {
+            mix.push((kind, weight));
+        }
+    }
+    if mix.is_empty() {
+        anyhow::bail!("empty mix {:?}", s);
+    }
+    Ok(mix)
+}
+
+fn pick_kind(mix: &[(db::TransactionKind, u32)], rng: &mut StdRng) -> db::TransactionKind {
+    let total: u32 = mix.iter().map(|(_, w)| w).sum();
+    let mut n = rng.gen_range(0..total);
+    for (kind, w) in mix {
+        if n < *w {
+            return *kind;
+        }
+        n -= w;
+    }
+    mix[mix.len() - 1].0
+}
+
+#[derive(Debug, PartialEq)]
+enum Distribution {
+    Const(i64),
+    Uniform(i64, i64),
+    Exp(f64), // exponential with the mean
+}
+
+impl FromStr for Distribution {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+        let dist = match parts.as_slice() {
+            ["const", v] => Self::Const(v.parse()?),
+            ["uniform", min, max] => Self::Uniform(min.parse()?, max.parse()?),
+            ["exp", mean] => Self::Exp(mean.parse()?),
+            _ => anyhow::bail!("invalid distribution {:?}", s),
+        };
+        match dist {
+            Self::Const(v) if v < 1 => anyhow::bail!("invalid distribution {:?}", s),
+            Self::Uniform(min, max) if min < 1 || max < min => {
+                anyhow::bail!("invalid distribution {:?}", s)
+            }
+            Self::Exp(mean) if mean.is_nan() || mean < 1.0 => {
+                anyhow::bail!("invalid distribution {:?}", s)
+            }
+            _ => Ok(dist),
+        }
+    }
+}
+
+impl Distribution {
+    // a positive amount.
+    fn sample(&self, rng: &mut StdRng) -> i64 {
+        match self {
+            Self::Const(v) => *v,
+            Self::Uniform(min, max) => rng.gen_range(*min..=*max),
+            Self::Exp(mean) => {
+                let u: f64 = rng.gen();
+                ((-mean * (1.0 - u).ln()).round() as i64).max(1)
+            }
+        }
+    }
+}
+
+// every wallet has its own rng, so that the dataset does not depend on the scheduling.
+fn rng_of(seed: u64, index: usize, phase: u64) -> StdRng {
+    StdRng::seed_from_u64(seed ^ (index as u64).rotate_left(16) ^ phase.rotate_left(56))
+}
+
+#[derive(Serialize)]
+struct SeededWallet {
+    #[serde(skip)]
+    index: usize,
+    uid: String,
+    charges: Vec<SeededCharge>,
+    transactions: Vec<SeededTransaction>,
+    errors: BTreeMap<String, u64>, // "op:error" -> count
+}
+
+#[derive(Serialize)]
+struct SeededCharge {
+    id: String,
+    status: i8,
+    quantity: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    txn: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SeededTransaction {
+    id: String,
+    kind: String,
+    payer: String,
+    payee: String,
+    amount: i64,
+}
+
+impl SeededWallet {
+    fn record_error(&mut self, op: &str, err: &anyhow::Error) {
+        let code = match err.downcast_ref::<HTTPError>() {
+            Some(err) => err.code.to_string(),
+            None => "other".to_string(),
+        };
+        *self.errors.entry(format!("{}:{}", op, code)).or_default() += 1;
+    }
+}
+
+async fn seed_charges(
+    sess: &db::scylladb::ScyllaDB,
+    mac: &db::HMacTag,
+    opts: &Options,
+    index: usize,
+    uid: xid::Id,
+) -> SeededWallet {
+    let mut rng = rng_of(opts.seed, index, 1);
+    let mut w = SeededWallet {
+        index,
+        uid: uid.to_string(),
+        charges: Vec::new(),
+        transactions: Vec::new(),
+        errors: BTreeMap::new(),
+    };
+
+    let n = rng.gen_range(0..=opts.charges);
+    for i in 0..n {
+        let quantity = opts.charge_amount.sample(&mut rng);
+        let failed = rng.gen_bool(opts.charge_fail_rate);
+        match seed_charge(sess, mac, uid, i, quantity, failed).await {
+            Ok(charge) => w.charges.push(SeededCharge {
+                id: charge.id.to_string(),
+                status: charge.status,
+                quantity,
+                txn: charge.txn.map(|v| v.to_string()),
+            }),
+            Err(err) => w.record_error("charge", &err),
+        }
+    }
+    w
+}
+
+// a charge through the states of a provider's charge, it tops up the wallet if not failed.
+async fn seed_charge(
+    sess: &db::scylladb::ScyllaDB,
+    mac: &db::HMacTag,
+    uid: xid::Id,
+    i: usize,
+    quantity: i64,
+    failed: bool,
+) -> anyhow::Result<db::Charge> {
+    let mut charge = db::Charge {
+        uid,
+        status: 1,
+        quantity,
+        currency: "usd".to_string(),
+        amount: quantity,
+        provider: "seed".to_string(),
+        charge_id: format!("seed_{}_{}", uid, i),
+        livemode: Some(db::livemode()),
+        ..Default::default()
+    };
+    charge.save(sess).await?;
+
+    let mut cols = ColumnsMap::new();
+    if failed {
+        cols.set_as("status", &-2i8);
+        cols.set_as("failure_code", &"card_declined".to_string());
+        charge.update(sess, cols, 1).await?;
+        return Ok(charge);
+    }
+
+    cols.set_as("status", &2i8);
+    cols.set_as("currency", &charge.currency);
+    cols.set_as("amount", &charge.amount);
+    cols.set_as("charge_payload", &Vec::<u8>::new());
+    charge.update(sess, cols, 1).await?;
+
+    let mut txn = db::Transaction {
+        description: "seed.topup".to_string(),
+        ..Default::default()
+    };
+    txn.prepare(sess, mac, uid, db::TransactionKind::Topup, quantity)
+        .await?;
+    txn.commit(sess, mac).await?;
+
+    let mut cols = ColumnsMap::with_capacity(2);
+    cols.set_as("status", &3i8);
+    cols.set_as("txn", &txn.id);
+    charge.update(sess, cols, 2).await?;
+    charge.txn = Some(txn.id);
+    let _ = db::ChargeDailyTotal::incr(sess, &charge).await;
+    let _ = db::DailyChargeStats::incr(sess, &charge).await;
+    Ok(charge)
+}
+
+async fn seed_transactions(
+    sess: &db::scylladb::ScyllaDB,
+    mac: &db::HMacTag,
+    opts: &Options,
+    uids: &[xid::Id],
+    mut w: SeededWallet,
+) -> SeededWallet {
+    let mut rng = rng_of(opts.seed, w.index, 2);
+    let uid = uids[w.index];
+
+    let n = rng.gen_range(0..=opts.txns);
+    for _ in 0..n {
+        let kind = pick_kind(&opts.mix, &mut rng);
+        let amount = opts.txn_amount.sample(&mut rng);
+        let (payer, payee) = match kind {
+            db::TransactionKind::Award => (db::SYS_ID, uid),
+            db::TransactionKind::Spend | db::TransactionKind::Withdraw => (uid, db::SYS_ID),
+            _ => {
+                if uids.len() < 2 {
+                    continue;
+                }
+                let mut j = rng.gen_range(0..uids.len() - 1);
+                if j >= w.index {
+                    j += 1;
+                }
+                (uid, uids[j])
+            }
+        };
+
+        let mut txn = db::Transaction {
+            description: format!("seed.{}", kind.as_ref()),
+            ..db::Transaction::with_uid(payer)
+        };
+        let op = kind.as_ref().to_string();
+        if let Err(err) = txn.prepare(sess, mac, payee, kind, amount).await {
+            w.record_error(&format!("{}.prepare", op), &err);
+            continue;
+        }
+        if let Err(err) = txn.commit(sess, mac).await {
+            w.record_error(&format!("{}.commit", op), &err);
+            continue;
+        }
+        w.transactions.push(SeededTransaction {
+            id: txn.id.to_string(),
+            kind: op,
+            payer: payer.to_string(),
+            payee: payee.to_string(),
+            amount,
+        });
+    }
+    w
+}
+
+#[derive(Default, Serialize)]
+struct Totals {
+    wallets: usize,
+    charges: usize,
+    charges_failed: usize,
+    transactions: usize,
+    transactions_by_kind: BTreeMap<String, usize>,
+    errors: BTreeMap<String, u64>,
+}
+
+impl Totals {
+    fn add(&mut self, w: &SeededWallet) {
+        self.wallets += 1;
+        self.charges += w.charges.len();
+        self.charges_failed += w.charges.iter().filter(|c| c.status < 0).count();
+        self.transactions += w.transactions.len();
+        for txn in &w.transactions {
+            *self
+                .transactions_by_kind
+                .entry(txn.kind.clone())
+                .or_default() += 1;
+        }
+        for (k, n) in &w.errors {
+            *self.errors.entry(k.clone()).or_default() += n;
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Manifest {
+    keyspace: String,
+    seed: u64,
+    elapsed_secs: f64,
+    totals: Totals,
+    wallets: Vec<SeededWallet>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_mix_works() {
+        let mix = parse_mix(DEFAULT_MIX).unwrap();
+        assert_eq!(5, mix.len());
+        assert_eq!((db::TransactionKind::Spend, 50), mix[0]);
+
+        let mix = parse_mix("award:1, spend:0").unwrap();
+        assert_eq!(vec![(db::TransactionKind::Award, 1)], mix);
+
+        assert!(parse_mix("").is_err());
+        assert!(parse_mix("spend:0").is_err());
+        assert!(parse_mix("spend").is_err());
+        assert!(parse_mix("spend:x").is_err());
+        assert!(parse_mix("topup:1").is_err());
+        assert!(parse_mix("unknown:1").is_err());
+    }
+
+    #[test]
+    fn distribution_works() {
+        assert_eq!(
+            Distribution::Const(5),
+            Distribution::from_str("const:5").unwrap()
+        );
+        assert_eq!(
+            Distribution::Uniform(1, 9),
+            Distribution::from_str("uniform:1:9").unwrap()
+        );
+        assert_eq!(
+            Distribution::Exp(100.0),
+            Distribution::from_str("exp:100").unwrap()
+        );
+        for s in [
+            "",
+            "const:0",
+            "uniform:9:1",
+            "uniform:0:1",
+            "exp:0",
+            "exp:NaN",
+            "normal:1",
+        ] {
+            assert!(Distribution::from_str(s).is_err(), "{}", s);
+        }
+
+        let mut rng = rng_of(42, 0, 1);
+        for _ in 0..1000 {
+            let v = Distribution::Uniform(3, 7).sample(&mut rng);
+            assert!((3..=7).contains(&v));
+            assert!(Distribution::Exp(2.0).sample(&mut rng) >= 1);
+        }
+        assert_eq!(5, Distribution::Const(5).sample(&mut rng));
+    }
+
+    #[test]
+    fn pick_kind_works() {
+        let mix = parse_mix("award:1,spend:3").unwrap();
+        let mut rng = rng_of(42, 0, 2);
+        let spends = (0..4000)
+            .filter(|_| pick_kind(&mix, &mut rng) == db::TransactionKind::Spend)
+            .count();
+        assert!((2700..3300).contains(&spends), "{}", spends);
+
+        // reproducible by the seed.
+        let a: Vec<i64> = (0..10)
+            .map(|_| Distribution::Exp(100.0).sample(&mut rng_of(7, 3, 2)))
+            .collect();
+        let b: Vec<i64> = (0..10)
+            .map(|_| Distribution::Exp(100.0).sample(&mut rng_of(7, 3, 2)))
+            .collect();
+        assert_eq!(a, b);
+    }
+}
@@ -1,10 +1,20 @@
-use futures::stream::StreamExt;
+use futures::stream::{self, StreamExt};
 use scylla_orm::{ColumnsMap, ToCqlVal};
+use std::sync::Arc;
 use structured_logger::{async_json::new_writer, Builder};
 use tokio::io;
 use walletbase::{conf, db};
 
-#[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+const JOB: &str = "sync-to-payee-transaction";
+
+// checkpoints are saved at partition boundaries, every CHECKPOINT_ROWS rows at most.
+const CHECKPOINT_ROWS: usize = 1000;
+
+// Backfill payee_transaction for transactions committed before commit maintained the index,
+// and transaction_by_kind for transactions prepared before prepare maintained it.
+// The token ring is split into shards that are processed concurrently,
+// progress of each shard is saved in sync_checkpoint, so that a restarted job resumes from it.
+#[tokio::main(flavor = "multi_thread", worker_threads = 4)]
 async fn main() -> anyhow::Result<()> {
     Builder::with_level("debug")
         .with_target_writer("*", new_writer(io::stdout()))
@@ -13,52 +23,262 @@ async fn main() -> anyhow::Result<()> {
     let nodes = std::env::var("SCYLLA_NODES").expect(
         "env SCYLLA_NODES required:\nSCYLLA_NODES=127.0.0.1:9042 ./sync-to-payee-transaction",
     );
+    let shards: i64 = env_or("SYNC_SHARDS", 64);
+    let concurrency: usize = env_or("SYNC_CONCURRENCY", 8);
+    if shards < 1 || concurrency < 1 {
+        anyhow::bail!("SYNC_SHARDS and SYNC_CONCURRENCY should be positive");
+    }
 
     let cfg = conf::ScyllaDB {
         nodes: nodes.split(',').map(|s| s.to_string()).collect(),
         username: "".to_string(),
         password: "".to_string(),
+        query_timeout_ms: 3000,
+        bypass_cache: true,
+        ..Default::default()
     };
 
-    let sess = db::scylladb::ScyllaDB::new(cfg, "walletbase").await?;
+    let sess = Arc::new(db::scylladb::ScyllaDB::new(cfg, "walletbase").await?);
+    let mut checkpoints: Vec<Checkpoint> = Vec::with_capacity(shards as usize);
+    for shard in 0..shards {
+        let (start, end) = token_range(shard, shards);
+        checkpoints.push(Checkpoint::load(&sess, shard as i32, start, end).await?);
+    }
+
+    let res: Vec<anyhow::Result<Checkpoint>> = stream::iter(checkpoints)
+        .map(|ck| {
+            let sess = sess.clone();
+            async move { sync_shard(&sess, ck).await }
+        })
+        .buffer_unordered(concurrency)
+        .collect()
+        .await;
+
+    let mut total: i64 = 0;
+    let mut synced: i64 = 0;
+    let mut failed: usize = 0;
+    for r in res {
+        match r {
+            Ok(ck) => {
+                total += ck.total;
+                synced += ck.synced;
+            }
+            Err(err) => {
+                failed += 1;
+                log::error!(target: "sync", action = "sync_shard"; "{}", err);
+            }
+        }
+    }
+
+    println!(
+        "shards: {}, failed: {}, total: {}, synced: {}",
+        shards, failed, total, synced
+    );
+    if failed > 0 {
+        anyhow::bail!(
+            "{} shards failed, run again to resume from checkpoints",
+            failed
+        );
+    }
+
+    Ok(())
+}
+
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    std::env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+fn unix_ms() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+// returns the (start, end] token range of the shard.
+fn token_range(shard: i64, shards: i64) -> (i64, i64) {
+    let span = u64::MAX as i128 + 1;
+    let start = i64::MIN as i128 + span * shard as i128 / shards as i128;
+    let end = i64::MIN as i128 + span * (shard as i128 + 1) / shards as i128;
+    (
+        start as i64,
+        if shard + 1 == shards {
+            i64::MAX
+        } else {
+            end as i64
+        },
+    )
+}
+
+struct Checkpoint {
+    shard: i32,
+    start_token: i64,
+    end_token: i64,
+    last_token: i64, // all partitions with token <= last_token are synced
+    done: bool,
+    total: i64,
+    synced: i64,
+}
+
+impl Checkpoint {
+    async fn load(
+        db: &db::scylladb::ScyllaDB,
+        shard: i32,
+        start_token: i64,
+        end_token: i64,
+    ) -> anyhow::Result<Self> {
+        let mut ck = Self {
+            shard,
+            start_token,
+            end_token,
+            last_token: start_token,
+            done: false,
+            total: 0,
+            synced: 0,
+        };
+
+        let fields = vec![
+            "start_token".to_string(),
+            "end_token".to_string(),
+            "last_token".to_string(),
+            "done".to_string(),
+            "total".to_string(),
+            "synced".to_string(),
+        ];
+        let query = format!(
+            "SELECT {} FROM sync_checkpoint WHERE job=? AND shard=? LIMIT 1",
+            fields.join(",")
+        );
+        let params = (JOB.to_cql(), shard);
+        let rows = db.execute_iter(query, params).await?;
+        if let Some(row) = rows.into_iter().next() {
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            // the shard layout changed, start over.
+            if cols.get_as::<i64>("start_token")? != start_token
+                || cols.get_as::<i64>("end_token")? != end_token
+            {
+                return Err(anyhow::Error::msg(format!(
+                    "checkpoint of shard {} does not match SYNC_SHARDS, truncate sync_checkpoint for job {} to start over",
+                    shard, JOB
+                )));
+            }
+            ck.last_token = cols.get_as("last_token")?;
+            ck.done = cols.get_as("done")?;
+            ck.total = cols.get_as("total")?;
+            ck.synced = cols.get_as("synced")?;
+        }
+
+        Ok(ck)
+    }
+
+    async fn save(&self, db: &db::scylladb::ScyllaDB) -> anyhow::Result<()> {
+        let query = "INSERT INTO sync_checkpoint (job,shard,start_token,end_token,last_token,done,total,synced,updated_at) VALUES (?,?,?,?,?,?,?,?,?)";
+        let params = (
+            JOB.to_cql(),
+            self.shard,
+            self.start_token,
+            self.end_token,
+            self.last_token,
+            self.done,
+            self.total,
+            self.synced,
+            unix_ms(),
+        );
+        db.execute(query, params).await?;
+        Ok(())
+    }
+}
+
+async fn sync_shard(
+    sess: &db::scylladb::ScyllaDB,
+    mut ck: Checkpoint,
+) -> anyhow::Result<Checkpoint> {
+    if ck.done {
+        log::info!(target: "sync", action = "skip_shard", shard = ck.shard; "");
+        return Ok(ck);
+    }
+
+    // "token" is the first column, the rest are filled into the transaction.
     let fields = vec![
+        "token".to_string(),
         "uid".to_string(),
         "id".to_string(),
         "payee".to_string(),
         "sub_payee".to_string(),
+        "kind".to_string(),
         "status".to_string(),
+        "amount".to_string(),
+        "sub_shares".to_string(),
+        "shares".to_string(),
     ];
-    let query = format!("SELECT {} FROM transaction", fields.join(","));
-    let mut stream = sess.stream(query, ()).await?;
-    let mut total: usize = 0;
-    let mut synced: usize = 0;
+    let query = format!(
+        "SELECT token(uid),{} FROM transaction WHERE token(uid)>? AND token(uid)<=?",
+        fields[1..].join(",")
+    );
+    let mut stream = sess.stream(query, (ck.last_token, ck.end_token)).await?;
+    let mut token = ck.last_token;
+    let mut rows: usize = 0;
 
     while let Some(row) = stream.next().await {
         let mut cols = ColumnsMap::with_capacity(fields.len());
         cols.fill(row?, &fields)?;
+
+        // all rows of the previous partition have been processed.
+        let row_token: i64 = cols.get_as("token")?;
+        if row_token != token {
+            ck.last_token = token;
+            token = row_token;
+            if rows >= CHECKPOINT_ROWS {
+                ck.save(sess).await?;
+                rows = 0;
+            }
+        }
+
         let mut doc = db::Transaction::default();
         doc.fill(&cols);
-        total += 1;
+        ck.total += 1;
+        rows += 1;
+
+        db::TransactionByKind::new(doc.uid, &doc.kind, doc.id)
+            .save(sess)
+            .await?;
 
         if doc.status == 3 {
             let ok = db::PayeeTransaction::new(doc.payee, doc.id, doc.uid)
-                .save(&sess)
+                .save(sess)
                 .await?;
             if ok {
-                synced += 1;
+                ck.synced += 1;
             }
-            if let Some(sub_payee) = doc.sub_payee {
+            for (sub_payee, _) in doc.sub_payees() {
                 let ok = db::PayeeTransaction::new(sub_payee, doc.id, doc.uid)
-                    .save(&sess)
+                    .save(sess)
                     .await?;
                 if ok {
-                    synced += 1;
+                    ck.synced += 1;
                 }
             }
+            if doc.kind == db::TransactionKind::Award.as_ref() {
+                db::PayeeTransaction::new(doc.payee, doc.id, doc.uid)
+                    .save_system_award(sess)
+                    .await?;
+            }
         }
     }
 
-    println!("total: {}, synced: {}", total, synced);
-
-    Ok(())
+    ck.last_token = ck.end_token;
+    ck.done = true;
+    ck.save(sess).await?;
+    log::info!(target: "sync",
+        action = "sync_shard",
+        shard = ck.shard,
+        total = ck.total,
+        synced = ck.synced;
+        "",
+    );
+    Ok(ck)
 }
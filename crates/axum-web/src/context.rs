@@ -4,12 +4,37 @@ use axum::{
     response::Response,
 };
 use serde_json::Value;
-use std::{collections::BTreeMap, str::FromStr, sync::Arc, time::Instant};
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
 pub use structured_logger::unix_ms;
 
+// header of the client's timeout of the request in milliseconds.
+pub const REQUEST_TIMEOUT_HEADER: &str = "x-request-timeout-ms";
+
+tokio::task_local! {
+    // deadline of the request being handled by the task, see `deadline`.
+    static DEADLINE: Instant;
+}
+
+// returns the deadline of the current request, it is only available in the request's task,
+// tasks spawned by the handler are not bound by it.
+pub fn deadline() -> Option<Instant> {
+    DEADLINE.try_with(|d| *d).ok()
+}
+
+// runs the future with the deadline, e.g. the handler of a request.
+pub async fn with_deadline<F: Future>(deadline: Instant, f: F) -> F::Output {
+    DEADLINE.scope(deadline, f).await
+}
+
 #[derive(Debug)]
 pub struct ReqContext {
     pub rid: String,   // from x-request-id header
@@ -17,6 +42,7 @@ pub struct ReqContext {
     pub rating: i8,    // from x-auth-user-rating header, 0 if not present
     pub unix_ms: u64,
     pub start: Instant,
+    pub deadline: Option<Instant>, // from x-request-timeout-ms header
     pub kv: RwLock<BTreeMap<String, Value>>,
 }
 
@@ -28,10 +54,22 @@ impl ReqContext {
             rating,
             unix_ms: unix_ms(),
             start: Instant::now(),
+            deadline: None,
             kv: RwLock::new(BTreeMap::new()),
         }
     }
 
+    pub fn with_timeout(mut self, timeout_ms: u64) -> Self {
+        self.deadline = Some(self.start + Duration::from_millis(timeout_ms));
+        self
+    }
+
+    // remaining time before the deadline, None if the request has no deadline.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|d| d.saturating_duration_since(Instant::now()))
+    }
+
     pub async fn get_kv(&self) -> BTreeMap<String, Value> {
         let kv = self.kv.read().await;
         kv.clone()
@@ -61,12 +99,22 @@ pub async fn middleware<B>(mut req: Request<B>, next: Next<B>) -> Response {
     let rating = extract_header(req.headers(), "x-auth-user-rating", || "0".to_string());
     let rating = i8::from_str(&rating).unwrap_or(0);
 
+    let timeout_ms = extract_header(req.headers(), REQUEST_TIMEOUT_HEADER, || "0".to_string());
+    let timeout_ms = u64::from_str(&timeout_ms).unwrap_or(0);
+
     let uid = xid::Id::from_str(&user).unwrap_or_default();
 
-    let ctx = Arc::new(ReqContext::new(rid.clone(), uid, rating));
+    let mut ctx = ReqContext::new(rid.clone(), uid, rating);
+    if timeout_ms > 0 {
+        ctx = ctx.with_timeout(timeout_ms);
+    }
+    let ctx = Arc::new(ctx);
     req.extensions_mut().insert(ctx.clone());
 
-    let res = next.run(req).await;
+    let res = match ctx.deadline {
+        Some(deadline) => with_deadline(deadline, next.run(req)).await,
+        None => next.run(req).await,
+    };
     let kv = ctx.kv.read().await;
     let status = res.status().as_u16();
     let headers = res.headers();
@@ -86,6 +134,7 @@ pub async fn middleware<B>(mut req: Request<B>, next: Next<B>) -> Response {
         status = status,
         start = ctx.unix_ms,
         elapsed = ctx.start.elapsed().as_millis() as u64,
+        timeout = timeout_ms,
         ctype = ct,
         encoding = ce,
         kv = log::as_serde!(*kv);
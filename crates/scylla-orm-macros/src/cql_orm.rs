@@ -1,5 +1,5 @@
 use proc_macro::TokenStream;
-use quote::quote;
+use quote::{format_ident, quote};
 use syn::{parse_macro_input, Data, DeriveInput, Fields, FieldsNamed};
 
 /// #[proc_macro_derive(CqlOrm)]
@@ -7,6 +7,7 @@ pub fn cql_orm(input: TokenStream) -> TokenStream {
     let ast = parse_macro_input!(input as DeriveInput);
 
     let struct_name = &ast.ident;
+    let vis = &ast.vis;
     let fields = match ast.data {
         Data::Struct(data) => match data.fields {
             Fields::Named(FieldsNamed { named, .. }) => named,
@@ -24,6 +25,22 @@ pub fn cql_orm(input: TokenStream) -> TokenStream {
         })
         .collect::<Vec<_>>();
     let fields_num = fields_filtered.len();
+    if fields_num > 64 {
+        panic!("CqlOrm can only be derived for structs with at most 64 fields");
+    }
+
+    // the typed field enum of the struct, e.g. `DocumentField::CreatedAt` for `created_at`.
+    let field_enum = format_ident!("{}Field", struct_name);
+    let variants = fields_filtered
+        .iter()
+        .map(|f| format_ident!("{}", upper_camel(&f.ident.as_ref().unwrap().to_string())))
+        .collect::<Vec<_>>();
+    let variants0 = variants.iter();
+    let variants1 = variants.iter();
+    let variants2 = variants.iter();
+    let field_names_string4 = fields_filtered
+        .iter()
+        .map(|f| f.ident.as_ref().unwrap().to_string());
 
     let field_names0 = fields_filtered.iter().map(|f| &f.ident);
     let field_names1 = field_names0.clone();
@@ -35,6 +52,28 @@ pub fn cql_orm(input: TokenStream) -> TokenStream {
     let field_names_string3 = field_names_string0.clone();
 
     let expanded = quote! {
+        #[allow(dead_code)]
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        #vis enum #field_enum {
+            #(#variants0),*
+        }
+
+        impl scylla_orm::CqlField for #field_enum {
+            const ALL: &'static [Self] = &[
+                #(Self::#variants1),*
+            ];
+
+            fn index(self) -> usize {
+                self as usize
+            }
+
+            fn name(self) -> &'static str {
+                match self {
+                    #(Self::#variants2 => #field_names_string4),*
+                }
+            }
+        }
+
         impl #struct_name {
             pub fn fields() -> Vec<String> {
                 vec![
@@ -62,3 +101,15 @@ pub fn cql_orm(input: TokenStream) -> TokenStream {
 
     TokenStream::from(expanded)
 }
+
+fn upper_camel(name: &str) -> String {
+    name.split('_')
+        .map(|s| {
+            let mut chars = s.chars();
+            match chars.next() {
+                Some(c) => c.to_uppercase().chain(chars).collect::<String>(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
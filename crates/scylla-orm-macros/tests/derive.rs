@@ -1,4 +1,5 @@
 use isolang::Language;
+use scylla_orm::{CqlField, FieldSet};
 use scylla_orm_macros::CqlOrm;
 
 #[derive(Debug, Default, Clone, CqlOrm, PartialEq, Eq)]
@@ -39,3 +40,36 @@ fn derive_cql_orm_works() {
     doc2._fields = doc._fields.clone();
     assert_eq!(doc2, doc);
 }
+
+#[derive(Debug, Default, Clone, CqlOrm)]
+pub struct Event {
+    pub id: xid::Id,
+    pub created_at: i64,
+    pub txn_uid: Option<xid::Id>,
+}
+
+#[test]
+fn derive_cql_field_works() {
+    assert_eq!(
+        &[
+            DocumentField::Id,
+            DocumentField::Status,
+            DocumentField::Language,
+            DocumentField::Authors,
+            DocumentField::Content
+        ],
+        DocumentField::ALL
+    );
+    assert_eq!("authors", DocumentField::Authors.name());
+    assert_eq!(3, DocumentField::Authors.index());
+    assert_eq!(
+        Some(DocumentField::Content),
+        DocumentField::from_name("content")
+    );
+    assert_eq!(None, DocumentField::from_name("_fields"));
+
+    assert_eq!("created_at", EventField::CreatedAt.name());
+    assert_eq!("txn_uid", EventField::TxnUid.name());
+    let fields: Vec<String> = FieldSet::<EventField>::all().names();
+    assert_eq!(Event::fields(), fields);
+}
@@ -28,6 +28,18 @@ impl ToCqlVal for String {
     }
 }
 
+impl FromCqlVal for bool {
+    fn from_cql(cql_val: &CqlValue) -> Result<Self, FromCqlValError> {
+        cql_to_rust::FromCqlVal::from_cql(cql_val.to_owned())
+    }
+}
+
+impl ToCqlVal for bool {
+    fn to_cql(&self) -> CqlValue {
+        CqlValue::Boolean(self.to_owned())
+    }
+}
+
 impl FromCqlVal for i8 {
     fn from_cql(cql_val: &CqlValue) -> Result<Self, FromCqlValError> {
         cql_to_rust::FromCqlVal::from_cql(cql_val.to_owned())
@@ -52,6 +64,20 @@ impl ToCqlVal for i16 {
     }
 }
 
+// u16 is stored as SMALLINT, values greater than i16::MAX are rejected.
+impl FromCqlVal for u16 {
+    fn from_cql(cql_val: &CqlValue) -> Result<Self, FromCqlValError> {
+        let val: i16 = cql_to_rust::FromCqlVal::from_cql(cql_val.to_owned())?;
+        u16::try_from(val).map_err(|_| FromCqlValError::BadVal)
+    }
+}
+
+impl ToCqlVal for u16 {
+    fn to_cql(&self) -> CqlValue {
+        CqlValue::SmallInt(*self as i16)
+    }
+}
+
 impl FromCqlVal for i32 {
     fn from_cql(cql_val: &CqlValue) -> Result<Self, FromCqlValError> {
         cql_to_rust::FromCqlVal::from_cql(cql_val.to_owned())
@@ -138,6 +164,44 @@ impl ToCqlVal for Option<xid::Id> {
     }
 }
 
+impl FromCqlVal for Option<i64> {
+    fn from_cql(val: &CqlValue) -> Result<Self, FromCqlValError> {
+        match val {
+            CqlValue::BigInt(val) => Ok(Some(*val)),
+            CqlValue::Empty => Ok(None),
+            _ => Err(FromCqlValError::BadCqlType),
+        }
+    }
+}
+
+impl ToCqlVal for Option<i64> {
+    fn to_cql(&self) -> CqlValue {
+        match self {
+            Some(val) => CqlValue::BigInt(*val),
+            None => CqlValue::Empty,
+        }
+    }
+}
+
+impl FromCqlVal for Option<bool> {
+    fn from_cql(val: &CqlValue) -> Result<Self, FromCqlValError> {
+        match val {
+            CqlValue::Boolean(val) => Ok(Some(*val)),
+            CqlValue::Empty => Ok(None),
+            _ => Err(FromCqlValError::BadCqlType),
+        }
+    }
+}
+
+impl ToCqlVal for Option<bool> {
+    fn to_cql(&self) -> CqlValue {
+        match self {
+            Some(val) => CqlValue::Boolean(*val),
+            None => CqlValue::Empty,
+        }
+    }
+}
+
 impl FromCqlVal for isolang::Language {
     fn from_cql(val: &CqlValue) -> Result<Self, FromCqlValError> {
         match val {
@@ -217,6 +281,28 @@ impl<T: ToCqlVal> ToCqlVal for Vec<T> {
     }
 }
 
+impl<A: FromCqlVal, B: FromCqlVal> FromCqlVal for (A, B) {
+    fn from_cql(cql_val: &CqlValue) -> Result<Self, FromCqlValError> {
+        match cql_val {
+            CqlValue::Tuple(list) => {
+                if list.len() != 2 {
+                    return Err(FromCqlValError::BadVal);
+                }
+                let a = list[0].as_ref().ok_or(FromCqlValError::ValIsNull)?;
+                let b = list[1].as_ref().ok_or(FromCqlValError::ValIsNull)?;
+                Ok((A::from_cql(a)?, B::from_cql(b)?))
+            }
+            _ => Err(FromCqlValError::BadCqlType),
+        }
+    }
+}
+
+impl<A: ToCqlVal, B: ToCqlVal> ToCqlVal for (A, B) {
+    fn to_cql(&self) -> CqlValue {
+        CqlValue::Tuple(vec![Some(self.0.to_cql()), Some(self.1.to_cql())])
+    }
+}
+
 impl<T: FromCqlVal + std::cmp::Eq + std::hash::Hash> FromCqlVal for HashSet<T> {
     fn from_cql(cql_val: &CqlValue) -> Result<Self, FromCqlValError> {
         match cql_val {
@@ -278,4 +364,43 @@ mod tests {
             CqlValue::Text("hello".to_string())
         );
     }
+
+    #[test]
+    fn u16_and_tuple_works() {
+        assert_eq!(3000u16.to_cql(), CqlValue::SmallInt(3000));
+        assert_eq!(u16::from_cql(&CqlValue::SmallInt(3000)).unwrap(), 3000u16);
+        assert!(u16::from_cql(&CqlValue::SmallInt(-1)).is_err());
+
+        let val = ("a".to_string(), 1000u16);
+        let cql = val.to_cql();
+        assert_eq!(
+            cql,
+            CqlValue::Tuple(vec![
+                Some(CqlValue::Text("a".to_string())),
+                Some(CqlValue::SmallInt(1000))
+            ])
+        );
+        assert_eq!(<(String, u16)>::from_cql(&cql).unwrap(), val);
+        assert!(<(String, u16)>::from_cql(&CqlValue::Tuple(vec![None, None])).is_err());
+
+        let list = vec![val.clone(), ("b".to_string(), 2000u16)];
+        assert_eq!(
+            Vec::<(String, u16)>::from_cql(&list.to_cql()).unwrap(),
+            list
+        );
+    }
+
+    #[test]
+    fn bool_works() {
+        assert_eq!(true.to_cql(), CqlValue::Boolean(true));
+        assert!(!bool::from_cql(&CqlValue::Boolean(false)).unwrap());
+        assert_eq!(Some(true).to_cql(), CqlValue::Boolean(true));
+        assert_eq!(None::<bool>.to_cql(), CqlValue::Empty);
+        assert_eq!(
+            Option::<bool>::from_cql(&CqlValue::Boolean(false)).unwrap(),
+            Some(false)
+        );
+        assert_eq!(Option::<bool>::from_cql(&CqlValue::Empty).unwrap(), None);
+        assert!(Option::<bool>::from_cql(&CqlValue::Int(1)).is_err());
+    }
 }
@@ -0,0 +1,192 @@
+use std::{fmt, marker::PhantomData};
+
+/// A column of a model, the `<Model>Field` enum derived by `#[derive(CqlOrm)]`.
+/// Variants are in the order of the model's fields, at most 64 of them.
+pub trait CqlField: Copy + Eq + fmt::Debug + 'static {
+    const ALL: &'static [Self];
+
+    fn index(self) -> usize;
+
+    fn name(self) -> &'static str;
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|f| f.name() == name)
+    }
+}
+
+/// A set of fields of a model, iterated in the order of the model's fields.
+/// An empty set selects all the fields by convention of the models.
+pub struct FieldSet<F: CqlField> {
+    bits: u64,
+    _field: PhantomData<F>,
+}
+
+impl<F: CqlField> FieldSet<F> {
+    pub fn new() -> Self {
+        Self {
+            bits: 0,
+            _field: PhantomData,
+        }
+    }
+
+    pub fn all() -> Self {
+        F::ALL.iter().copied().collect()
+    }
+
+    /// parses field names, e.g. from a request's `fields` parameter,
+    /// the first unknown name is returned as the error.
+    pub fn parse<S: AsRef<str>>(names: &[S]) -> Result<Self, String> {
+        let mut set = Self::new();
+        for name in names {
+            let name = name.as_ref();
+            match F::from_name(name) {
+                Some(f) => set.insert(f),
+                None => return Err(name.to_string()),
+            }
+        }
+        Ok(set)
+    }
+
+    pub fn len(&self) -> usize {
+        self.bits.count_ones() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits == 0
+    }
+
+    pub fn contains(&self, field: F) -> bool {
+        self.bits & (1 << field.index()) != 0
+    }
+
+    pub fn insert(&mut self, field: F) {
+        self.bits |= 1 << field.index();
+    }
+
+    pub fn remove(&mut self, field: F) {
+        self.bits &= !(1 << field.index());
+    }
+
+    pub fn with(mut self, field: F) -> Self {
+        self.insert(field);
+        self
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = F> + '_ {
+        F::ALL.iter().copied().filter(|f| self.contains(*f))
+    }
+
+    /// names of the fields to select, e.g. for `ColumnsMap::fill`.
+    pub fn names(&self) -> Vec<String> {
+        self.iter().map(|f| f.name().to_string()).collect()
+    }
+}
+
+impl<F: CqlField> Default for FieldSet<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F: CqlField> Clone for FieldSet<F> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<F: CqlField> Copy for FieldSet<F> {}
+
+impl<F: CqlField> PartialEq for FieldSet<F> {
+    fn eq(&self, other: &Self) -> bool {
+        self.bits == other.bits
+    }
+}
+
+impl<F: CqlField> Eq for FieldSet<F> {}
+
+impl<F: CqlField> fmt::Debug for FieldSet<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set()
+            .entries(self.iter().map(|v| v.name()))
+            .finish()
+    }
+}
+
+impl<F: CqlField> FromIterator<F> for FieldSet<F> {
+    fn from_iter<I: IntoIterator<Item = F>>(iter: I) -> Self {
+        let mut set = Self::new();
+        for f in iter {
+            set.insert(f);
+        }
+        set
+    }
+}
+
+impl<F: CqlField, const N: usize> From<[F; N]> for FieldSet<F> {
+    fn from(fields: [F; N]) -> Self {
+        fields.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum DocField {
+        Id,
+        Status,
+        Content,
+    }
+
+    impl CqlField for DocField {
+        const ALL: &'static [Self] = &[Self::Id, Self::Status, Self::Content];
+
+        fn index(self) -> usize {
+            self as usize
+        }
+
+        fn name(self) -> &'static str {
+            match self {
+                Self::Id => "id",
+                Self::Status => "status",
+                Self::Content => "content",
+            }
+        }
+    }
+
+    #[test]
+    fn field_set_works() {
+        let set: FieldSet<DocField> = FieldSet::new();
+        assert!(set.is_empty());
+        assert_eq!(set, FieldSet::default());
+        assert_eq!(3, FieldSet::<DocField>::all().len());
+
+        let set = FieldSet::from([DocField::Content, DocField::Id]);
+        assert_eq!(2, set.len());
+        assert!(set.contains(DocField::Id));
+        assert!(!set.contains(DocField::Status));
+        assert_eq!(vec!["id", "content"], set.names());
+        assert_eq!("{\"id\", \"content\"}", format!("{:?}", set));
+
+        let mut set = set.with(DocField::Status).with(DocField::Id);
+        assert_eq!(FieldSet::all(), set);
+        set.remove(DocField::Id);
+        assert_eq!(
+            vec![DocField::Status, DocField::Content],
+            set.iter().collect::<Vec<_>>()
+        );
+
+        assert_eq!(Some(DocField::Status), DocField::from_name("status"));
+        assert_eq!(None, DocField::from_name("Status"));
+        assert_eq!(
+            FieldSet::from([DocField::Status]),
+            FieldSet::parse(&["status", "status"]).unwrap()
+        );
+        assert!(FieldSet::<DocField>::parse::<&str>(&[]).unwrap().is_empty());
+        assert_eq!(
+            "payload",
+            FieldSet::<DocField>::parse(&["id".to_string(), "payload".to_string()]).unwrap_err()
+        );
+    }
+}
@@ -1,5 +1,7 @@
 mod columns;
 mod cql_value;
+mod fields;
 
 pub use columns::*;
 pub use cql_value::*;
+pub use fields::*;
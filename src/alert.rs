@@ -0,0 +1,170 @@
+use serde_json::json;
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum_web::context::unix_ms;
+
+use crate::{
+    api::AppState,
+    conf, db,
+    notify::{Notification, Sink},
+};
+
+pub const NOTIFICATION_KIND: &str = "system_wallet_alert";
+
+// The system wallet's award and topup go negative by design, as they are issued to users.
+// Crossing below the thresholds indicates runaway issuance. 0 disables the threshold.
+// They are set from conf at startup, and are also exported by /metrics.
+static MIN_SYSTEM_AWARD: AtomicI64 = AtomicI64::new(0);
+static MIN_SYSTEM_TOPUP: AtomicI64 = AtomicI64::new(0);
+
+pub fn set_thresholds(cfg: &conf::Alert) {
+    MIN_SYSTEM_AWARD.store(cfg.min_system_award, Ordering::Relaxed);
+    MIN_SYSTEM_TOPUP.store(cfg.min_system_topup, Ordering::Relaxed);
+}
+
+// returns (field, threshold) pairs, a threshold of 0 is disabled.
+pub fn thresholds() -> [(&'static str, i64); 2] {
+    [
+        ("award", MIN_SYSTEM_AWARD.load(Ordering::Relaxed)),
+        ("topup", MIN_SYSTEM_TOPUP.load(Ordering::Relaxed)),
+    ]
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Breach {
+    pub field: &'static str,
+    pub value: i64,
+    pub threshold: i64,
+}
+
+pub fn breaches(wallet: &db::Wallet, thresholds: &[(&'static str, i64)]) -> Vec<Breach> {
+    thresholds
+        .iter()
+        .filter(|(_, threshold)| *threshold < 0)
+        .filter_map(|(field, threshold)| {
+            let value = match *field {
+                "award" => wallet.award,
+                "topup" => wallet.topup,
+                _ => return None,
+            };
+            if value < *threshold {
+                Some(Breach {
+                    field,
+                    value,
+                    threshold: *threshold,
+                })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+// checks the system wallet once, alerts on the fields that newly crossed their thresholds,
+// and returns the fields that are in breach, so that an ongoing breach is alerted only once.
+pub async fn run_once(
+    db: &db::scylladb::ScyllaDB,
+    sink: &dyn Sink,
+    tenant: &str,
+    alerted: &HashSet<&'static str>,
+) -> anyhow::Result<HashSet<&'static str>> {
+    let mut wallet = db::Wallet::with_pk(db::SYS_ID);
+    wallet.get_one(db).await?;
+
+    let mut res: HashSet<&'static str> = HashSet::new();
+    for breach in breaches(&wallet, &thresholds()) {
+        res.insert(breach.field);
+        if alerted.contains(breach.field) {
+            continue;
+        }
+
+        log::warn!(target: "alert",
+            tenant = tenant,
+            field = breach.field,
+            value = breach.value,
+            threshold = breach.threshold;
+            "system wallet crossed the threshold",
+        );
+        sink.send(&Notification {
+            kind: NOTIFICATION_KIND,
+            uid: db::SYS_ID.to_string(),
+            ref_id: wallet.txn.to_string(),
+            locale: "".to_string(),
+            payload: json!({
+                "tenant": tenant,
+                "field": breach.field,
+                "value": breach.value,
+                "threshold": breach.threshold,
+                "sequence": wallet.sequence,
+            }),
+        })?;
+    }
+    Ok(res)
+}
+
+// runs the checker every interval in the background.
+pub fn spawn(app: Arc<AppState>, cfg: conf::Alert, sink: Arc<dyn Sink>) {
+    tokio::spawn(async move {
+        let interval = cfg.interval_secs.max(10);
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        let mut alerted: HashSet<&'static str> = HashSet::new();
+        loop {
+            ticker.tick().await;
+            if !db::JobLease::hold(&app.scylla, "alert", interval).await {
+                continue;
+            }
+            let start = unix_ms();
+            match run_once(&app.scylla, sink.as_ref(), &app.tenant, &alerted).await {
+                Ok(res) => {
+                    log::info!(target: "alert",
+                        tenant = app.tenant.as_str(),
+                        breaches = res.len(),
+                        elapsed = unix_ms() - start;
+                        "",
+                    );
+                    alerted = res;
+                }
+                Err(err) => log::error!(target: "alert", "system wallet check failed: {}", err),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn breaches_works() {
+        let wallet = db::Wallet {
+            uid: db::SYS_ID,
+            award: -1000,
+            topup: -500,
+            ..Default::default()
+        };
+
+        assert!(breaches(&wallet, &[("award", 0), ("topup", 0)]).is_empty());
+        assert!(breaches(&wallet, &[("award", -1000), ("topup", -500)]).is_empty());
+        assert_eq!(
+            vec![Breach {
+                field: "award",
+                value: -1000,
+                threshold: -999,
+            }],
+            breaches(&wallet, &[("award", -999), ("topup", -500)])
+        );
+        assert_eq!(
+            2,
+            breaches(&wallet, &[("award", -999), ("topup", -1)]).len()
+        );
+        // positive thresholds are invalid and ignored.
+        assert!(breaches(&wallet, &[("award", 1)]).is_empty());
+    }
+}
@@ -0,0 +1,245 @@
+use axum::{
+    extract::{Query, State},
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+use axum_web::context::ReqContext;
+use axum_web::erring::{HTTPError, SuccessResponse};
+use axum_web::object::PackObject;
+use scylla_orm::FieldSet;
+
+use crate::api::{get_fields, AppState};
+use crate::db::{self, SYS_ID};
+
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
+pub struct AdjustInput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub uid: PackObject<xid::Id>,
+    // positive to credit the user, negative to debit the user.
+    // the absolute value is checked by the adjustment kind's max amount.
+    pub amount: i64,
+    #[validate(length(min = 1, max = 1024))]
+    pub reason: String,
+    #[validate(length(min = 1, max = 64))]
+    pub requester: String,
+    // the transaction being corrected, and its payer.
+    #[schema(value_type = Option<super::openapi::Xid>)]
+    pub ref_uid: Option<PackObject<xid::Id>>,
+    #[schema(value_type = Option<super::openapi::Xid>)]
+    pub ref_txn: Option<PackObject<xid::Id>>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct AdjustmentOutput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub id: PackObject<xid::Id>,
+    #[schema(value_type = super::openapi::Xid)]
+    pub txn_uid: PackObject<xid::Id>,
+    #[schema(value_type = super::openapi::Xid)]
+    pub uid: PackObject<xid::Id>,
+    pub status: i8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requester: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub approver: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<i64>,
+}
+
+impl AdjustmentOutput {
+    pub fn from<T>(val: db::AdjustmentApproval, to: &PackObject<T>) -> Self {
+        let mut rt = Self {
+            id: to.with(val.id),
+            txn_uid: to.with(val.txn_uid),
+            uid: to.with(val.uid),
+            status: val.status,
+            ..Default::default()
+        };
+
+        for v in val._fields {
+            match v.as_str() {
+                "amount" => rt.amount = Some(val.amount),
+                "reason" => rt.reason = Some(val.reason.to_owned()),
+                "requester" => rt.requester = Some(val.requester.to_owned()),
+                "approver" => rt.approver = Some(val.approver.to_owned()),
+                "created_at" => rt.created_at = Some(val.created_at),
+                "updated_at" => rt.updated_at = Some(val.updated_at),
+                _ => {}
+            }
+        }
+
+        rt
+    }
+}
+
+// prepares an adjustment transaction, it will be committed after approved by another operator.
+#[utoipa::path(
+    post,
+    path = "/v1/admin/wallet/adjust",
+    tag = "admin",
+    request_body = AdjustInput,
+    responses(
+        (status = 200, body = super::openapi::AdjustmentResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn adjust(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<AdjustInput>,
+) -> Result<PackObject<SuccessResponse<AdjustmentOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    let uid = input.uid.unwrap();
+    if uid == SYS_ID {
+        return Err(HTTPError::new(400, "Invalid uid".to_string()));
+    }
+    db::TransactionKind::Adjustment.check_amount(input.amount.saturating_abs())?;
+    ctx.set_kvs(vec![
+        ("action", "adjust_wallet".into()),
+        ("uid", uid.to_string().into()),
+        ("amount", input.amount.into()),
+        ("requester", input.requester.clone().into()),
+    ])
+    .await;
+
+    let (payer, payee) = if input.amount > 0 {
+        (SYS_ID, uid)
+    } else {
+        (uid, SYS_ID)
+    };
+
+    let mut txn = db::Transaction::with_uid(payer);
+    txn.description = input.reason.clone();
+    txn.ref_uid = input.ref_uid.map(|id| id.unwrap());
+    txn.ref_txn = input.ref_txn.map(|id| id.unwrap());
+    txn.prepare(
+        &app.scylla,
+        &app.mac,
+        payee,
+        db::TransactionKind::Adjustment,
+        input.amount.abs(),
+    )
+    .await?;
+    ctx.set("txn", txn.id.to_string().into()).await;
+
+    let mut doc = db::AdjustmentApproval {
+        id: txn.id,
+        txn_uid: payer,
+        uid,
+        amount: input.amount,
+        reason: input.reason,
+        requester: input.requester,
+        ..Default::default()
+    };
+    if let Err(err) = doc.save(&app.scylla).await {
+        txn.cancel(&app.scylla, &app.mac).await?;
+        return Err(err.into());
+    }
+
+    Ok(to.with(SuccessResponse::new(AdjustmentOutput::from(doc, &to))))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
+pub struct ApproveInput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub id: PackObject<xid::Id>,
+    #[validate(length(min = 1, max = 64))]
+    pub approver: String,
+    pub approved: bool,
+}
+
+// commits or cancels the adjustment transaction, the approver should not be the requester.
+#[utoipa::path(
+    post,
+    path = "/v1/admin/wallet/adjust/approve",
+    tag = "admin",
+    request_body = ApproveInput,
+    responses(
+        (status = 200, body = super::openapi::AdjustmentResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn approve(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<ApproveInput>,
+) -> Result<PackObject<SuccessResponse<AdjustmentOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    let id = input.id.unwrap();
+    ctx.set_kvs(vec![
+        ("action", "approve_adjustment".into()),
+        ("id", id.to_string().into()),
+        ("approver", input.approver.clone().into()),
+        ("approved", input.approved.into()),
+    ])
+    .await;
+
+    let mut doc = db::AdjustmentApproval::with_pk(id);
+    doc.get_one(&app.scylla, FieldSet::new()).await?;
+    let status = if input.approved { 1 } else { -1 };
+    doc.decide(&app.scylla, input.approver, status).await?;
+
+    let mut txn = db::Transaction::with_pk(doc.txn_uid, doc.id);
+    txn._actor = doc.approver.clone();
+    txn.get_one(&app.scylla, FieldSet::new()).await?;
+    if input.approved {
+        txn.commit(&app.scylla, &app.mac).await?;
+    } else {
+        txn.cancel(&app.scylla, &app.mac).await?;
+    }
+
+    Ok(to.with(SuccessResponse::new(AdjustmentOutput::from(doc, &to))))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryAdjustment {
+    #[param(value_type = super::openapi::Xid)]
+    pub id: PackObject<xid::Id>,
+    pub fields: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/admin/wallet/adjust",
+    tag = "admin",
+    params(QueryAdjustment),
+    responses(
+        (status = 200, body = super::openapi::AdjustmentResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn get(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QueryAdjustment>,
+) -> Result<PackObject<SuccessResponse<AdjustmentOutput>>, HTTPError> {
+    input.validate()?;
+    let id = *input.id.to_owned();
+
+    ctx.set_kvs(vec![
+        ("action", "get_adjustment".into()),
+        ("id", id.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::AdjustmentApproval::with_pk(id);
+    doc.get_one(&app.scylla, get_fields(input.fields.clone())?)
+        .await?;
+    Ok(to.with(SuccessResponse::new(AdjustmentOutput::from(doc, &to))))
+}
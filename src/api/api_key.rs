@@ -0,0 +1,267 @@
+use axum::{
+    extract::{Query, State},
+    http::{header, Method, Request},
+    middleware::Next,
+    response::Response,
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, sync::Arc};
+use utoipa::ToSchema;
+use validator::Validate;
+
+use axum_web::context::{unix_ms, ReqContext};
+use axum_web::erring::{HTTPError, SuccessResponse};
+use axum_web::object::PackObject;
+use scylla_orm::FieldSet;
+
+use crate::api::{get_fields, AppState, QueryUid, QueryUidId};
+use crate::db;
+
+// read-only routes that accept api keys, and the scope required.
+// all of them are GET routes scoped by the `uid` query.
+const READ_ONLY_ROUTES: [(&str, &str); 9] = [
+    ("/v1/wallet", "wallet"),
+    ("/v1/wallet/credits/summary", "wallet"),
+    ("/v1/wallet/preferences", "wallet"),
+    ("/v1/transaction", "transaction"),
+    ("/v1/transaction/aggregate", "transaction"),
+    ("/v1/transaction/aggregate_income", "transaction"),
+    ("/v2/transaction", "transaction"),
+    ("/v2/transaction/first_from_system", "transaction"),
+    ("/v1/charge", "charge"),
+];
+
+fn route_scope(method: &Method, path: &str) -> Option<&'static str> {
+    if method != Method::GET {
+        return None;
+    }
+    let path = path.trim_end_matches('/');
+    READ_ONLY_ROUTES
+        .iter()
+        .find(|(p, _)| *p == path)
+        .map(|(_, scope)| *scope)
+}
+
+// requests with `Authorization: Bearer wk_...` are restricted to the read-only routes
+// of the key's scopes and the key's uid, other requests are passed through.
+pub async fn middleware<B>(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Result<Response, HTTPError> {
+    let key = match req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+    {
+        Some(key) if key.starts_with(db::API_KEY_PREFIX) => key.to_string(),
+        _ => return Ok(next.run(req).await),
+    };
+
+    let (uid, id, secret) =
+        db::ApiKey::parse(&key).ok_or(HTTPError::new(401, "Invalid api key".to_string()))?;
+    ctx.set("api_key", id.to_string().into()).await;
+
+    let scope = route_scope(req.method(), req.uri().path()).ok_or(HTTPError::new(
+        403,
+        format!("Api key is not allowed for {}", req.uri().path()),
+    ))?;
+    let Query(input) = Query::<QueryUid>::try_from_uri(req.uri())
+        .map_err(|_| HTTPError::new(403, "Api key requires the uid query".to_string()))?;
+    if *input.uid.unwrap_ref() != uid {
+        return Err(HTTPError::new(
+            403,
+            "Api key is not allowed for the uid".to_string(),
+        ));
+    }
+
+    let mut doc = db::ApiKey::with_pk(uid, id);
+    doc.get_one(&app.scylla, FieldSet::new())
+        .await
+        .map_err(|_| HTTPError::new(401, "Invalid api key".to_string()))?;
+    if !doc.verify(&secret, unix_ms() as i64) {
+        return Err(HTTPError::new(401, "Invalid api key".to_string()));
+    }
+    if !doc.has_scope(scope) {
+        return Err(HTTPError::new(
+            403,
+            format!("Api key has no {} scope", scope),
+        ));
+    }
+
+    Ok(next.run(req).await)
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
+pub struct ApiKeyInput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub uid: PackObject<xid::Id>,
+    #[validate(length(min = 1, max = 64))]
+    pub name: String,
+    #[validate(length(min = 1, max = 3))]
+    pub scopes: Vec<String>, // wallet, transaction, charge
+    // the key never expires if not given.
+    #[validate(range(min = 3600))]
+    pub ttl_secs: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct ApiKeyOutput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub uid: PackObject<xid::Id>,
+    #[schema(value_type = super::openapi::Xid)]
+    pub id: PackObject<xid::Id>,
+    pub revoked_at: i64,
+    // the key is returned only once when it is created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expire_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<i64>,
+}
+
+impl ApiKeyOutput {
+    pub fn from<T>(val: db::ApiKey, to: &PackObject<T>) -> Self {
+        let mut rt = Self {
+            uid: to.with(val.uid),
+            id: to.with(val.id),
+            revoked_at: val.revoked_at,
+            ..Default::default()
+        };
+
+        for v in val._fields {
+            match v.as_str() {
+                "name" => rt.name = Some(val.name.to_owned()),
+                "scopes" => {
+                    let mut scopes: Vec<String> = val.scopes.iter().cloned().collect();
+                    scopes.sort();
+                    rt.scopes = Some(scopes)
+                }
+                "expire_at" => rt.expire_at = Some(val.expire_at),
+                "created_at" => rt.created_at = Some(val.created_at),
+                _ => {}
+            }
+        }
+
+        rt
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/wallet/api_key",
+    tag = "wallet",
+    request_body = ApiKeyInput,
+    responses(
+        (status = 200, body = super::openapi::ApiKeyResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn create(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<ApiKeyInput>,
+) -> Result<PackObject<SuccessResponse<ApiKeyOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    let uid = input.uid.unwrap();
+    ctx.set_kvs(vec![
+        ("action", "create_api_key".into()),
+        ("uid", uid.to_string().into()),
+        ("scopes", input.scopes.join(",").into()),
+    ])
+    .await;
+
+    let mut doc = db::ApiKey {
+        uid,
+        name: input.name,
+        scopes: HashSet::from_iter(input.scopes),
+        expire_at: input
+            .ttl_secs
+            .map(|ttl| unix_ms() as i64 + ttl * 1000)
+            .unwrap_or(0),
+        ..Default::default()
+    };
+    let key = doc.save(&app.scylla).await?;
+    ctx.set("id", doc.id.to_string().into()).await;
+
+    let mut rt = ApiKeyOutput::from(doc, &to);
+    rt.key = Some(key);
+    Ok(to.with(SuccessResponse::new(rt)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/wallet/api_key/list",
+    tag = "wallet",
+    params(QueryUid),
+    responses(
+        (status = 200, body = super::openapi::ApiKeysResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn list(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QueryUid>,
+) -> Result<PackObject<SuccessResponse<Vec<ApiKeyOutput>>>, HTTPError> {
+    input.validate()?;
+
+    let uid = *input.uid.to_owned();
+    ctx.set_kvs(vec![
+        ("action", "list_api_keys".into()),
+        ("uid", uid.to_string().into()),
+    ])
+    .await;
+
+    let res = db::ApiKey::list(&app.scylla, uid, get_fields(input.fields.clone())?).await?;
+    Ok(to.with(SuccessResponse::new(
+        res.into_iter()
+            .map(|r| ApiKeyOutput::from(r, &to))
+            .collect(),
+    )))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/wallet/api_key",
+    tag = "wallet",
+    params(QueryUidId),
+    responses(
+        (status = 200, body = super::openapi::BoolResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn revoke(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QueryUidId>,
+) -> Result<PackObject<SuccessResponse<bool>>, HTTPError> {
+    input.validate()?;
+
+    let uid = *input.uid.to_owned();
+    let id = *input.id.to_owned();
+    ctx.set_kvs(vec![
+        ("action", "revoke_api_key".into()),
+        ("uid", uid.to_string().into()),
+        ("id", id.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::ApiKey::with_pk(uid, id);
+    doc.get_one(&app.scylla, [db::ApiKeyField::RevokedAt].into())
+        .await?;
+    let res = doc.revoke(&app.scylla).await?;
+    Ok(to.with(SuccessResponse::new(res)))
+}
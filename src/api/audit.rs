@@ -0,0 +1,210 @@
+use axum::{
+    extract::{Query, State},
+    http::{Method, Request},
+    middleware::Next,
+    response::Response,
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+use std::{str::FromStr, sync::Arc};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+use axum_web::context::{extract_header, unix_ms, ReqContext};
+use axum_web::erring::{HTTPError, SuccessResponse};
+use axum_web::object::PackObject;
+
+use crate::api::{export::xid_at, token_from_xid, token_to_xid, validate_id, AppState};
+use crate::db;
+
+// actions of POST routes that do not mutate, they are not audited.
+fn is_read_action(action: &str) -> bool {
+    action == "simulate"
+        || action.starts_with("get_")
+        || action.starts_with("list_")
+        || action.starts_with("aggregate_")
+}
+
+// persists the ReqContext kv of mutating requests into the audit_log table, after the
+// response is ready. Requests without an action or a uid are not audited.
+pub async fn middleware<B>(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if !db::audit_log_enabled()
+        || matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS)
+    {
+        return next.run(req).await;
+    }
+
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+    let app_name = extract_header(req.headers(), "x-auth-app", || "".to_string());
+    let res = next.run(req).await;
+
+    let kv = ctx.get_kv().await;
+    let action = match kv.get("action").and_then(|v| v.as_str()) {
+        Some(action) if !is_read_action(action) => action.to_string(),
+        _ => return res,
+    };
+    let uid = kv
+        .get("uid")
+        .and_then(|v| v.as_str())
+        .and_then(|v| xid::Id::from_str(v).ok())
+        .unwrap_or(ctx.user);
+    if uid == xid::Id::default() {
+        return res;
+    }
+
+    let mut doc = db::AuditLog {
+        uid,
+        id: xid::new(),
+        action,
+        rid: ctx.rid.clone(),
+        caller: ctx.user,
+        app: app_name,
+        method,
+        path,
+        status: res.status().as_u16() as i16,
+        kv: serde_json::to_string(&kv).unwrap_or_default(),
+        elapsed: ctx.start.elapsed().as_millis() as i32,
+        created_at: unix_ms() as i64,
+        ..Default::default()
+    };
+    // the response is not delayed by the audit log, a failed write is only logged.
+    tokio::spawn(async move {
+        if let Err(err) = doc.save(&app.scylla).await {
+            log::warn!(target: "audit",
+                action = doc.action,
+                uid = doc.uid.to_string(),
+                rid = doc.rid;
+                "failed to save audit log: {}", err,
+            );
+        }
+    });
+    res
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct AuditLogOutput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub uid: PackObject<xid::Id>,
+    #[schema(value_type = super::openapi::Xid)]
+    pub id: PackObject<xid::Id>,
+    pub action: String,
+    pub rid: String,
+    #[schema(value_type = super::openapi::Xid)]
+    pub caller: PackObject<xid::Id>,
+    pub app: String,
+    pub method: String,
+    pub path: String,
+    pub status: i16,
+    #[schema(value_type = Object)]
+    pub kv: serde_json::Value,
+    pub elapsed: i32,
+    pub created_at: i64,
+}
+
+impl AuditLogOutput {
+    pub fn from<T>(val: db::AuditLog, to: &PackObject<T>) -> Self {
+        Self {
+            uid: to.with(val.uid),
+            id: to.with(val.id),
+            action: val.action,
+            rid: val.rid,
+            caller: to.with(val.caller),
+            app: val.app,
+            method: val.method,
+            path: val.path,
+            status: val.status,
+            kv: serde_json::from_str(&val.kv).unwrap_or_default(),
+            elapsed: val.elapsed,
+            created_at: val.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryAuditLog {
+    #[param(value_type = super::openapi::Xid)]
+    #[validate(custom = "validate_id")]
+    pub uid: PackObject<xid::Id>,
+    #[validate(range(min = 0))]
+    pub start: Option<i64>, // unix ms, inclusive
+    #[validate(range(min = 0))]
+    pub end: Option<i64>, // unix ms, inclusive, default to now
+    #[validate(length(min = 1, max = 64))]
+    pub action: Option<String>,
+    #[validate(range(min = 2, max = 1000))]
+    pub page_size: Option<u16>,
+    #[param(value_type = Option<super::openapi::Base64Url>)]
+    pub page_token: Option<PackObject<Vec<u8>>>,
+}
+
+// lists the audit logs of the wallet within the time range, newest first.
+#[utoipa::path(
+    get,
+    path = "/v1/admin/audit_log",
+    tag = "admin",
+    params(QueryAuditLog),
+    responses(
+        (status = 200, body = super::openapi::AuditLogsResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn list(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QueryAuditLog>,
+) -> Result<PackObject<SuccessResponse<Vec<AuditLogOutput>>>, HTTPError> {
+    input.validate()?;
+    let uid = *input.uid.to_owned();
+    let page_size = input.page_size.unwrap_or(100);
+    let start = input.start.unwrap_or(0);
+    let end = input.end.unwrap_or(unix_ms() as i64);
+    if start > end {
+        return Err(HTTPError::new(
+            400,
+            format!("Invalid time range {} to {}", start, end),
+        ));
+    }
+
+    ctx.set_kvs(vec![
+        ("action", "list_audit_log".into()),
+        ("uid", uid.to_string().into()),
+        ("page_size", page_size.into()),
+    ])
+    .await;
+
+    let kind = "list_audit_log";
+    let page_token = token_to_xid(&app.mac, &uid, kind, &input.page_token)?;
+    let res = db::AuditLog::list(
+        &app.scylla,
+        uid,
+        xid_at(start, 0),
+        xid_at(end, 255),
+        page_size,
+        page_token,
+    )
+    .await?;
+    let next_page_token = if res.len() >= page_size as usize {
+        to.with_option(token_from_xid(&app.mac, &uid, kind, res.last().unwrap().id))
+    } else {
+        None
+    };
+
+    // the action is filtered after the page is read, so a page may be short or empty.
+    Ok(to.with(SuccessResponse {
+        total_size: None,
+        next_page_token,
+        result: res
+            .into_iter()
+            .filter(|r| input.action.as_ref().map_or(true, |a| a == &r.action))
+            .map(|r| AuditLogOutput::from(r, &to))
+            .collect(),
+    }))
+}
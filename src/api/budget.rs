@@ -0,0 +1,229 @@
+use axum::{
+    extract::{Query, State},
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+use axum_web::context::ReqContext;
+use axum_web::erring::{HTTPError, SuccessResponse};
+use axum_web::object::PackObject;
+use scylla_orm::{ColumnsMap, FieldSet};
+
+use crate::api::{get_fields, AppState};
+use crate::db;
+
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
+pub struct BudgetInput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub owner: PackObject<xid::Id>,
+    #[validate(range(min = 1, max = 1_000_000_000))]
+    pub remaining: i64,
+    #[validate(range(min = 0))]
+    pub expire_at: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct BudgetOutput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub id: PackObject<xid::Id>,
+    #[schema(value_type = super::openapi::Xid)]
+    pub owner: PackObject<xid::Id>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remaining: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expire_at: Option<i64>,
+}
+
+impl BudgetOutput {
+    pub fn from<T>(val: db::Budget, to: &PackObject<T>) -> Self {
+        let mut rt = Self {
+            id: to.with(val.id),
+            owner: to.with(val.owner),
+            ..Default::default()
+        };
+
+        for v in val._fields {
+            match v.as_str() {
+                "remaining" => rt.remaining = Some(val.remaining),
+                "expire_at" => rt.expire_at = Some(val.expire_at),
+                _ => {}
+            }
+        }
+
+        rt
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/admin/budget",
+    tag = "admin",
+    request_body = BudgetInput,
+    responses(
+        (status = 200, body = super::openapi::BudgetResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn create(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<BudgetInput>,
+) -> Result<PackObject<SuccessResponse<BudgetOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    let owner = input.owner.unwrap();
+    ctx.set_kvs(vec![
+        ("action", "create_budget".into()),
+        ("owner", owner.to_string().into()),
+        ("remaining", input.remaining.into()),
+    ])
+    .await;
+
+    let mut doc = db::Budget {
+        owner,
+        remaining: input.remaining,
+        expire_at: input.expire_at.unwrap_or_default(),
+        ..Default::default()
+    };
+    doc.save(&app.scylla).await?;
+    ctx.set("id", doc.id.to_string().into()).await;
+    Ok(to.with(SuccessResponse::new(BudgetOutput::from(doc, &to))))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryBudget {
+    #[param(value_type = super::openapi::Xid)]
+    pub id: PackObject<xid::Id>,
+    pub fields: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/admin/budget",
+    tag = "admin",
+    params(QueryBudget),
+    responses(
+        (status = 200, body = super::openapi::BudgetResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn get(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QueryBudget>,
+) -> Result<PackObject<SuccessResponse<BudgetOutput>>, HTTPError> {
+    input.validate()?;
+    let id = *input.id.to_owned();
+
+    ctx.set_kvs(vec![
+        ("action", "get_budget".into()),
+        ("id", id.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::Budget::with_pk(id);
+    doc.get_one(&app.scylla, get_fields(input.fields.clone())?)
+        .await?;
+    Ok(to.with(SuccessResponse::new(BudgetOutput::from(doc, &to))))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
+pub struct UpdateBudgetInput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub id: PackObject<xid::Id>,
+    #[schema(value_type = Option<super::openapi::Xid>)]
+    pub owner: Option<PackObject<xid::Id>>,
+    #[validate(range(min = 0, max = 1_000_000_000))]
+    pub remaining: Option<i64>,
+    #[validate(range(min = 0))]
+    pub expire_at: Option<i64>,
+}
+
+impl UpdateBudgetInput {
+    fn into(self) -> anyhow::Result<ColumnsMap> {
+        let mut cols = ColumnsMap::new();
+        if let Some(owner) = self.owner {
+            cols.set_as("owner", &owner.unwrap());
+        }
+        if let Some(remaining) = self.remaining {
+            cols.set_as("remaining", &remaining);
+        }
+        if let Some(expire_at) = self.expire_at {
+            cols.set_as("expire_at", &expire_at);
+        }
+
+        if cols.is_empty() {
+            return Err(HTTPError::new(400, "No fields to update".to_string()).into());
+        }
+
+        Ok(cols)
+    }
+}
+
+#[utoipa::path(
+    patch,
+    path = "/v1/admin/budget",
+    tag = "admin",
+    request_body = UpdateBudgetInput,
+    responses(
+        (status = 200, body = super::openapi::BudgetResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn update(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<UpdateBudgetInput>,
+) -> Result<PackObject<SuccessResponse<BudgetOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    let id = *input.id.to_owned();
+    let cols = input.into()?;
+    ctx.set_kvs(vec![
+        ("action", "update_budget".into()),
+        ("id", id.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::Budget::with_pk(id);
+    doc.update(&app.scylla, cols).await?;
+    doc.get_one(&app.scylla, FieldSet::new()).await?;
+    Ok(to.with(SuccessResponse::new(BudgetOutput::from(doc, &to))))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/admin/budget",
+    tag = "admin",
+    params(QueryBudget),
+    responses(
+        (status = 200, body = super::openapi::BoolResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn delete(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QueryBudget>,
+) -> Result<PackObject<SuccessResponse<bool>>, HTTPError> {
+    input.validate()?;
+    let id = *input.id.to_owned();
+
+    ctx.set_kvs(vec![
+        ("action", "delete_budget".into()),
+        ("id", id.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::Budget::with_pk(id);
+    let res = doc.delete(&app.scylla).await?;
+    Ok(to.with(SuccessResponse::new(res)))
+}
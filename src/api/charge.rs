@@ -2,8 +2,10 @@ use axum::{
     extract::{Query, State},
     Extension,
 };
+use futures::future::join_all;
 use serde::{Deserialize, Serialize};
 use std::{str::FromStr, sync::Arc, vec};
+use utoipa::{IntoParams, ToSchema};
 use validator::Validate;
 
 use axum_web::erring::{HTTPError, SuccessResponse};
@@ -12,35 +14,57 @@ use axum_web::{
     context::{unix_ms, ReqContext},
     object::cbor_from_slice,
 };
-use scylla_orm::ColumnsMap;
+use scylla_orm::{ColumnsMap, FieldSet};
 
 use crate::api::{
-    currency::Currency, get_fields, token_from_xid, token_to_xid, validate_provider, AppState,
-    Pagination, QueryUidId, TransactionPayload,
+    currency::Currency, get_fields, get_list_fields, resolve_livemode, token_from_xid,
+    token_to_xid, validate_provider, AppState, Pagination, QueryUidId, TransactionPayload,
 };
+use crate::crypto;
 use crate::db;
+use crate::money;
+use crate::receipt;
 
-#[derive(Debug, Deserialize, Validate)]
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
 pub struct ChargeInput {
+    #[schema(value_type = super::openapi::Xid)]
     pub uid: PackObject<xid::Id>,
     #[validate(length(min = 1), custom = "validate_provider")]
     pub provider: String, // stripe
-    #[validate(range(min = 50, max = 1_000_000))]
+    // the max is checked by the topup kind's max amount.
+    #[validate(range(min = 50))]
     pub quantity: i64,
     pub currency: Option<String>,
     #[validate(range(min = 1))]
     pub amount: Option<i64>,
     pub charge_id: Option<String>,
+    #[schema(value_type = Option<super::openapi::Base64Url>)]
     pub charge_payload: Option<PackObject<Vec<u8>>>,
+    // false for provider test mode, default to the charge_payload's livemode or the deployment's.
+    pub livemode: Option<bool>,
+    // creates the provider's checkout session for the charge, charge_id and charge_payload
+    // should not be given, the amount is priced by the provider.
+    pub checkout: Option<bool>,
+    // seconds before the charge expires if it is not completed, default to the deployment's,
+    // bounded by its min and max.
+    #[validate(range(min = 1))]
+    pub expire_in: Option<i64>,
+    // caller's unique reference of the charge per uid, a retried create with the same
+    // reference returns the charge created before.
+    #[validate(length(min = 1, max = 128))]
+    pub external_ref: Option<String>,
 }
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
 pub struct ChargeOutput {
+    #[schema(value_type = super::openapi::Xid)]
     pub uid: PackObject<xid::Id>,
+    #[schema(value_type = super::openapi::Xid)]
     pub id: PackObject<xid::Id>,
     pub status: i8,
     pub quantity: i64,
     pub provider: String,
+    pub livemode: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub updated_at: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -54,15 +78,31 @@ pub struct ChargeOutput {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub charge_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<super::openapi::Base64Url>)]
     pub charge_payload: Option<PackObject<Vec<u8>>>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<super::openapi::Xid>)]
     pub txn: Option<PackObject<xid::Id>>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<super::openapi::Xid>)]
     pub txn_refunded: Option<PackObject<xid::Id>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub failure_code: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub failure_msg: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider_fee: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub net_amount: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reminders: Option<i8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reminded_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_ref: Option<String>,
+    // the checkout page of the session created with the charge.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checkout_url: Option<String>,
 }
 
 impl ChargeOutput {
@@ -72,6 +112,7 @@ impl ChargeOutput {
             id: to.with(val.id),
             status: val.status,
             quantity: val.quantity,
+            livemode: val.is_livemode(),
             provider: val.provider,
             ..Default::default()
         };
@@ -91,6 +132,13 @@ impl ChargeOutput {
                 "txn_refunded" => rt.txn_refunded = to.with_option(val.txn_refunded),
                 "failure_code" => rt.failure_code = Some(val.failure_code.to_owned()),
                 "failure_msg" => rt.failure_msg = Some(val.failure_msg.to_owned()),
+                "provider_fee" => rt.provider_fee = Some(val.provider_fee),
+                "net_amount" => rt.net_amount = Some(val.net_amount),
+                "reminders" => rt.reminders = Some(val.reminders),
+                "reminded_at" => rt.reminded_at = Some(val.reminded_at),
+                "external_ref" if !val.external_ref.is_empty() => {
+                    rt.external_ref = Some(val.external_ref.to_owned())
+                }
                 _ => {}
             }
         }
@@ -99,6 +147,16 @@ impl ChargeOutput {
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/v1/charge",
+    tag = "charge",
+    request_body = ChargeInput,
+    responses(
+        (status = 200, body = super::openapi::ChargeResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
 pub async fn create(
     State(app): State<Arc<AppState>>,
     Extension(ctx): Extension<Arc<ReqContext>>,
@@ -107,7 +165,20 @@ pub async fn create(
     let (to, input) = to.unpack();
     input.validate()?;
 
+    db::TransactionKind::Topup.check_amount(input.quantity)?;
+
+    if input.checkout.unwrap_or(false) {
+        return create_checkout(app, ctx, to, input).await;
+    }
+
     let uid = input.uid.unwrap();
+    let livemode = resolve_livemode(
+        input.livemode,
+        input
+            .charge_payload
+            .as_ref()
+            .map(|v| v.unwrap_ref().as_slice()),
+    )?;
     ctx.set_kvs(vec![
         ("action", "create_charge".into()),
         ("uid", uid.to_string().into()),
@@ -115,6 +186,8 @@ pub async fn create(
         ("currency", input.currency.clone().into()),
         ("amount", input.amount.into()),
         ("quantity", input.quantity.into()),
+        ("livemode", livemode.into()),
+        ("external_ref", input.external_ref.clone().into()),
     ])
     .await;
 
@@ -122,6 +195,9 @@ pub async fn create(
         uid,
         quantity: input.quantity,
         provider: input.provider,
+        livemode: Some(livemode),
+        external_ref: input.external_ref.unwrap_or_default(),
+        _expire_in: input.expire_in.unwrap_or_default(),
         ..Default::default()
     };
 
@@ -131,6 +207,7 @@ pub async fn create(
                 .currency
                 .ok_or(HTTPError::new(400, "currency required".to_string()))?,
         )?;
+        cur.check_enabled()?;
         doc.amount = amount;
         doc.currency = cur.alpha.to_lowercase();
     }
@@ -145,10 +222,208 @@ pub async fn create(
             .unwrap();
     }
 
-    doc.save(&app.scylla).await?;
+    if let Some(existing) = claim_external_ref(&app, &mut doc).await? {
+        ctx.set("id", existing.id.to_string().into()).await;
+        return Ok(to.with(SuccessResponse::new(ChargeOutput::from(existing, &to))));
+    }
+    save_charge(&app, &mut doc).await?;
     Ok(to.with(SuccessResponse::new(ChargeOutput::from(doc, &to))))
 }
 
+// claims the charge's external_ref, returns the charge that claimed it before if any.
+// a retry should not change the charge, so the provider and quantity must match.
+async fn claim_external_ref(
+    app: &AppState,
+    doc: &mut db::Charge,
+) -> Result<Option<db::Charge>, HTTPError> {
+    if doc.external_ref.is_empty() {
+        return Ok(None);
+    }
+
+    let id = match doc.claim_external_ref(&app.scylla).await? {
+        Some(id) => id,
+        None => return Ok(None),
+    };
+    let mut existing = db::Charge::with_pk(doc.uid, id);
+    match existing.get_one(&app.scylla, FieldSet::new()).await {
+        Ok(()) => {}
+        Err(err) => {
+            let err = HTTPError::from(err);
+            if err.code == 404 {
+                return Err(HTTPError::new(
+                    409,
+                    format!(
+                        "Charge {} with external_ref {} is being created, please try again",
+                        id, doc.external_ref
+                    ),
+                ));
+            }
+            return Err(err);
+        }
+    }
+    if existing.provider != doc.provider || existing.quantity != doc.quantity {
+        return Err(HTTPError::new(
+            409,
+            format!(
+                "external_ref {} is used by charge {} with provider {} and quantity {}",
+                doc.external_ref, id, existing.provider, existing.quantity
+            ),
+        ));
+    }
+    existing.mark_expired(unix_ms() as i64);
+    Ok(Some(existing))
+}
+
+// saves the new charge, the claimed external_ref is released if it failed.
+async fn save_charge(app: &AppState, doc: &mut db::Charge) -> Result<(), HTTPError> {
+    if let Err(err) = doc.save(&app.scylla).await {
+        if !doc.external_ref.is_empty() {
+            if let Err(err) = doc.release_external_ref(&app.scylla).await {
+                log::error!(target: "charge",
+                    uid = doc.uid.to_string(),
+                    id = doc.id.to_string();
+                    "failed to release the external_ref: {}", err);
+            }
+        }
+        return Err(err.into());
+    }
+    Ok(())
+}
+
+// creates the charge and its Stripe Checkout session, the charge is prepared with the session.
+async fn create_checkout(
+    app: Arc<AppState>,
+    ctx: Arc<ReqContext>,
+    to: PackObject<()>,
+    input: ChargeInput,
+) -> Result<PackObject<SuccessResponse<ChargeOutput>>, HTTPError> {
+    let stripe = match (input.provider.as_str(), &app.stripe) {
+        ("stripe", Some(stripe)) => stripe.clone(),
+        _ => {
+            return Err(HTTPError::new(
+                400,
+                format!("Checkout is not supported by provider {}", input.provider),
+            ))
+        }
+    };
+    if input.charge_id.is_some() || input.charge_payload.is_some() || input.amount.is_some() {
+        return Err(HTTPError::new(
+            400,
+            "charge_id, charge_payload and amount are given by the checkout session".to_string(),
+        ));
+    }
+
+    let livemode = stripe.livemode();
+    if input.livemode.is_some() && input.livemode != Some(livemode) {
+        return Err(HTTPError::new(
+            400,
+            format!("livemode mismatch with the provider {}", livemode),
+        ));
+    }
+
+    let cur = Currency::from_str(
+        &input
+            .currency
+            .ok_or(HTTPError::new(400, "currency required".to_string()))?,
+    )?;
+    cur.check_enabled()?;
+
+    let uid = input.uid.unwrap();
+    ctx.set_kvs(vec![
+        ("action", "create_charge_checkout".into()),
+        ("uid", uid.to_string().into()),
+        ("provider", input.provider.clone().into()),
+        ("currency", cur.alpha.to_lowercase().into()),
+        ("quantity", input.quantity.into()),
+        ("livemode", livemode.into()),
+        ("external_ref", input.external_ref.clone().into()),
+    ])
+    .await;
+
+    let mut doc = db::Charge {
+        uid,
+        quantity: input.quantity,
+        provider: input.provider,
+        currency: cur.alpha.to_lowercase(),
+        livemode: Some(livemode),
+        external_ref: input.external_ref.unwrap_or_default(),
+        _expire_in: input.expire_in.unwrap_or_default(),
+        ..Default::default()
+    };
+    // checks the price before saving the charge.
+    stripe.line_item(&doc.currency, doc.quantity)?;
+    if let Some(existing) = claim_external_ref(&app, &mut doc).await? {
+        ctx.set("id", existing.id.to_string().into()).await;
+        // the checkout page of the prepared charge from its session.
+        let checkout_url = if existing.status == 1 {
+            serde_json::from_slice::<crate::stripe::CheckoutSession>(&existing.charge_payload)
+                .ok()
+                .and_then(|s| s.url)
+        } else {
+            None
+        };
+        let mut output = ChargeOutput::from(existing, &to);
+        output.checkout_url = checkout_url;
+        return Ok(to.with(SuccessResponse::new(output)));
+    }
+    save_charge(&app, &mut doc).await?;
+    ctx.set("id", doc.id.to_string().into()).await;
+
+    let (session, payload) = match stripe.create_checkout_session(&doc).await {
+        Ok(res) => res,
+        Err(err) => {
+            let err = HTTPError::from(err);
+            let mut cols = ColumnsMap::new();
+            cols.set_as("status", &-2i8);
+            cols.set_as("failure_code", &"checkout.create_failed".to_string());
+            cols.set_as("failure_msg", &err.message);
+            let mut failed = db::Charge::with_pk(doc.uid, doc.id);
+            if let Err(err) = failed.update(&app.scylla, cols, 0).await {
+                log::error!(target: "charge",
+                    uid = doc.uid.to_string(),
+                    id = doc.id.to_string();
+                    "failed to mark the charge failed: {}", err);
+            }
+            return Err(err);
+        }
+    };
+    ctx.set("charge_id", session.id.clone().into()).await;
+
+    doc.status = 1;
+    doc.charge_id = session.id;
+    doc.charge_payload = payload;
+    if let Some(amount) = session.amount_total {
+        doc.amount = amount;
+    }
+    if let Some(currency) = session.currency {
+        doc.currency = currency.to_lowercase();
+    }
+
+    let mut cols = ColumnsMap::new();
+    cols.set_as("status", &doc.status);
+    cols.set_as("charge_id", &doc.charge_id);
+    cols.set_as("charge_payload", &doc.charge_payload);
+    cols.set_as("amount", &doc.amount);
+    cols.set_as("currency", &doc.currency);
+    let mut prepared = db::Charge::with_pk(doc.uid, doc.id);
+    prepared.update(&app.scylla, cols, 0).await?;
+    doc.updated_at = prepared.updated_at;
+
+    let mut output = ChargeOutput::from(doc, &to);
+    output.checkout_url = session.url;
+    Ok(to.with(SuccessResponse::new(output)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/charge",
+    tag = "charge",
+    params(QueryUidId),
+    responses(
+        (status = 200, body = super::openapi::ChargeResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
 pub async fn get(
     State(app): State<Arc<AppState>>,
     Extension(ctx): Extension<Arc<ReqContext>>,
@@ -167,16 +442,111 @@ pub async fn get(
     .await;
 
     let mut doc = db::Charge::with_pk(uid, id);
-    doc.get_one(&app.scylla, get_fields(input.fields.clone()))
+    doc.get_one(&app.scylla, get_fields(input.fields.clone())?)
         .await?;
     let now = unix_ms() as i64;
-    if (doc.status == 0 || doc.status == 1) && doc.expire_at > 0 && doc.expire_at <= now {
-        doc.status = -2;
-        doc.failure_msg = "checkout.expired".to_string();
-    }
+    doc.mark_expired(now);
     Ok(to.with(SuccessResponse::new(ChargeOutput::from(doc, &to))))
 }
 
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct ReceiptOutput {
+    pub receipt: receipt::Receipt,
+    // the CBOR of the receipt that is signed.
+    #[schema(value_type = super::openapi::Base64Url)]
+    pub signed: PackObject<Vec<u8>>,
+    // Ed25519 signature of signed.
+    #[schema(value_type = super::openapi::Base64Url)]
+    pub signature: PackObject<Vec<u8>>,
+    #[schema(value_type = super::openapi::Base64Url)]
+    pub public_key: PackObject<Vec<u8>>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/charge/receipt",
+    tag = "charge",
+    params(QueryUidId),
+    responses(
+        (status = 200, body = super::openapi::ReceiptResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn receipt(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QueryUidId>,
+) -> Result<PackObject<SuccessResponse<ReceiptOutput>>, HTTPError> {
+    input.validate()?;
+    let uid = *input.uid.to_owned();
+    let id = *input.id.to_owned();
+
+    ctx.set_kvs(vec![
+        ("action", "get_receipt".into()),
+        ("uid", uid.to_string().into()),
+        ("id", id.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::Charge::with_pk(uid, id);
+    doc.get_one(
+        &app.scylla,
+        [
+            db::ChargeField::Currency,
+            db::ChargeField::Amount,
+            db::ChargeField::AmountRefunded,
+            db::ChargeField::Txn,
+        ]
+        .into(),
+    )
+    .await?;
+
+    let rt = receipt::Receipt::from_charge(&doc)?;
+    let (signed, signature) = receipt::sign(&app.mac, &rt)?;
+    Ok(to.with(SuccessResponse::new(ReceiptOutput {
+        receipt: rt,
+        signed: to.with(signed),
+        signature: to.with(signature),
+        public_key: to.with(receipt::public_key(&app.mac)),
+    })))
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct ReceiptKeyOutput {
+    // Ed25519 public key to verify receipts offline.
+    #[schema(value_type = super::openapi::Base64Url)]
+    pub public_key: PackObject<Vec<u8>>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/charge/receipt/key",
+    tag = "charge",
+    responses(
+        (status = 200, body = super::openapi::ReceiptKeyResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn receipt_key(
+    State(app): State<Arc<AppState>>,
+    to: PackObject<()>,
+) -> Result<PackObject<SuccessResponse<ReceiptKeyOutput>>, HTTPError> {
+    Ok(to.with(SuccessResponse::new(ReceiptKeyOutput {
+        public_key: to.with(receipt::public_key(&app.mac)),
+    })))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/charge/list",
+    tag = "charge",
+    request_body = Pagination,
+    responses(
+        (status = 200, body = super::openapi::ChargesResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
 pub async fn list(
     State(app): State<Arc<AppState>>,
     Extension(ctx): Extension<Arc<ReqContext>>,
@@ -190,31 +560,37 @@ pub async fn list(
         ("action", "list_charge".into()),
         ("uid", input.uid.to_string().into()),
         ("page_size", page_size.into()),
+        ("livemode", input.livemode.into()),
     ])
     .await;
 
-    let fields = input.fields.unwrap_or_default();
+    let uid = *input.uid.unwrap_ref();
+    let page_token = token_to_xid(&app.mac, &uid, "list_charge", &input.page_token)?;
+    let fields = get_list_fields(input.fields)?;
     let mut res = db::Charge::list(
         &app.scylla,
-        input.uid.unwrap(),
+        uid,
         fields,
         page_size,
-        token_to_xid(&input.page_token),
+        page_token,
         input.status,
+        input.livemode,
     )
     .await?;
     let next_page_token = if res.len() >= page_size as usize {
-        to.with_option(token_from_xid(res.last().unwrap().id))
+        to.with_option(token_from_xid(
+            &app.mac,
+            &uid,
+            "list_charge",
+            res.last().unwrap().id,
+        ))
     } else {
         None
     };
 
     let now = unix_ms() as i64;
     for doc in res.iter_mut() {
-        if (doc.status == 0 || doc.status == 1) && doc.expire_at > 0 && doc.expire_at <= now {
-            doc.status = -2;
-            doc.failure_msg = "checkout.expired".to_string();
-        }
+        doc.mark_expired(now);
     }
 
     Ok(to.with(SuccessResponse {
@@ -227,9 +603,102 @@ pub async fn list(
     }))
 }
 
-#[derive(Debug, Deserialize, Validate)]
+#[derive(Debug, Deserialize, Serialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryChargesByDay {
+    #[validate(range(min = 0))]
+    pub start: i64, // unix time, ms, inclusive
+    #[validate(range(min = 1))]
+    pub end: i64, // unix time, ms, exclusive
+    #[validate(range(min = -2, max = 3))]
+    pub status: Option<i8>,
+    pub livemode: Option<bool>,
+    #[validate(range(min = 2, max = 1000))]
+    pub page_size: Option<u16>,
+    #[param(value_type = Option<super::openapi::Base64Url>)]
+    pub page_token: Option<PackObject<Vec<u8>>>,
+    pub fields: Option<String>,
+}
+
+// lists charges across all users for finance.
+#[utoipa::path(
+    get,
+    path = "/v1/admin/charges",
+    tag = "admin",
+    params(QueryChargesByDay),
+    responses(
+        (status = 200, body = super::openapi::ChargesResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn list_by_day(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QueryChargesByDay>,
+) -> Result<PackObject<SuccessResponse<Vec<ChargeOutput>>>, HTTPError> {
+    input.validate()?;
+
+    let page_size = input.page_size.unwrap_or(100);
+    ctx.set_kvs(vec![
+        ("action", "list_charges_by_day".into()),
+        ("start", input.start.into()),
+        ("end", input.end.into()),
+        ("status", input.status.into()),
+        ("livemode", input.livemode.into()),
+        ("page_size", page_size.into()),
+    ])
+    .await;
+
+    let kind = "list_charges_by_day";
+    let index = db::Charge::list_by_day(
+        &app.scylla,
+        input.start,
+        input.end,
+        input.status,
+        input.livemode,
+        page_size,
+        token_to_xid(&app.mac, &db::SYS_ID, kind, &input.page_token)?,
+    )
+    .await?;
+    let next_page_token = if index.len() >= page_size as usize {
+        to.with_option(token_from_xid(
+            &app.mac,
+            &db::SYS_ID,
+            kind,
+            index.last().unwrap().id,
+        ))
+    } else {
+        None
+    };
+
+    let fields = get_fields::<db::ChargeField>(input.fields.clone())?;
+    let res = join_all(index.into_iter().map(|doc| {
+        let db = app.scylla.clone();
+        async move {
+            let mut doc = db::Charge::with_pk(doc.uid, doc.id);
+            doc.get_one(&db, fields).await.map(|_| doc)
+        }
+    }))
+    .await;
+
+    let mut result: Vec<ChargeOutput> = Vec::with_capacity(res.len());
+    for doc in res {
+        result.push(ChargeOutput::from(doc?, &to));
+    }
+
+    Ok(to.with(SuccessResponse {
+        total_size: None,
+        next_page_token,
+        result,
+    }))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
 pub struct UpdateChargeInput {
+    #[schema(value_type = super::openapi::Xid)]
     pub uid: PackObject<xid::Id>,
+    #[schema(value_type = super::openapi::Xid)]
     pub id: PackObject<xid::Id>,
     #[validate(range(min = 0, max = 1))]
     pub current_status: i8,
@@ -241,18 +710,23 @@ pub struct UpdateChargeInput {
     #[validate(range(min = 1))]
     pub amount_refunded: Option<i64>,
     pub charge_id: Option<String>,
+    #[schema(value_type = Option<super::openapi::Base64Url>)]
     pub charge_payload: Option<PackObject<Vec<u8>>>,
     pub failure_code: Option<String>,
     pub failure_msg: Option<String>,
+    // validated against the amount, one derives the other if only one is given.
+    #[validate(range(min = 0))]
+    pub provider_fee: Option<i64>,
+    #[validate(range(min = 0))]
+    pub net_amount: Option<i64>,
 }
 
 impl UpdateChargeInput {
-    fn into(self) -> anyhow::Result<ColumnsMap> {
+    // fees are the resolved provider_fee and net_amount.
+    fn into(self, fees: Option<(i64, i64)>) -> anyhow::Result<ColumnsMap> {
         let mut cols = ColumnsMap::new();
+        // transitions and their fields are checked by db::Charge::update.
         if let Some(status) = self.status {
-            if status == -1 || status > 1 {
-                return Err(HTTPError::new(400, format!("Invalid status: {}", status)).into());
-            }
             cols.set_as("status", &status);
         }
         if let Some(currency) = self.currency {
@@ -271,18 +745,15 @@ impl UpdateChargeInput {
             cols.set_as("charge_payload", &charge_payload.unwrap());
         }
         if let Some(failure_code) = self.failure_code {
-            if self.status != Some(-2) {
-                return Err(HTTPError::new(
-                    400,
-                    "failure_code can only be set with status -2".to_string(),
-                )
-                .into());
-            }
             cols.set_as("failure_code", &failure_code);
         }
         if let Some(failure_msg) = self.failure_msg {
             cols.set_as("failure_msg", &failure_msg);
         }
+        if let Some((provider_fee, net_amount)) = fees {
+            cols.set_as("provider_fee", &provider_fee);
+            cols.set_as("net_amount", &net_amount);
+        }
 
         if cols.is_empty() {
             return Err(HTTPError::new(400, "No fields to update".to_string()).into());
@@ -292,6 +763,16 @@ impl UpdateChargeInput {
     }
 }
 
+#[utoipa::path(
+    patch,
+    path = "/v1/charge",
+    tag = "charge",
+    request_body = UpdateChargeInput,
+    responses(
+        (status = 200, body = super::openapi::ChargeResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
 pub async fn update(
     State(app): State<Arc<AppState>>,
     Extension(ctx): Extension<Arc<ReqContext>>,
@@ -303,7 +784,6 @@ pub async fn update(
     let uid = *input.uid.to_owned();
     let id = *input.id.to_owned();
     let status = input.current_status;
-    let cols = input.into()?;
     ctx.set_kvs(vec![
         ("action", "update_charge".into()),
         ("uid", uid.to_string().into()),
@@ -312,21 +792,55 @@ pub async fn update(
     .await;
 
     let mut doc = db::Charge::with_pk(uid, id);
+    let fees = if input.provider_fee.is_some() || input.net_amount.is_some() {
+        let amount = match input.amount {
+            Some(amount) => amount,
+            None => {
+                doc.get_one(&app.scylla, [db::ChargeField::Amount].into())
+                    .await?;
+                doc.amount
+            }
+        };
+        db::Charge::resolve_fees(amount, input.provider_fee, input.net_amount)?
+    } else {
+        None
+    };
+    let cols = input.into(fees)?;
     doc.update(&app.scylla, cols, status).await?;
     Ok(to.with(SuccessResponse::new(ChargeOutput::from(doc, &to))))
 }
 
-#[derive(Debug, Deserialize, Validate)]
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
 pub struct CompleteChargeInput {
+    #[schema(value_type = super::openapi::Xid)]
     pub uid: PackObject<xid::Id>,
+    #[schema(value_type = super::openapi::Xid)]
     pub id: PackObject<xid::Id>,
     pub currency: String,
     #[validate(range(min = 1))]
     pub amount: i64,
     pub charge_id: String,
+    #[schema(value_type = super::openapi::Base64Url)]
     pub charge_payload: PackObject<Vec<u8>>,
+    // default to the charge_payload's livemode or the deployment's.
+    pub livemode: Option<bool>,
+    // validated against the amount, one derives the other if only one is given.
+    #[validate(range(min = 0))]
+    pub provider_fee: Option<i64>,
+    #[validate(range(min = 0))]
+    pub net_amount: Option<i64>,
 }
 
+#[utoipa::path(
+    post,
+    path = "/v1/charge/complete",
+    tag = "charge",
+    request_body = CompleteChargeInput,
+    responses(
+        (status = 200, body = super::openapi::ChargeResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
 pub async fn complete(
     State(app): State<Arc<AppState>>,
     Extension(ctx): Extension<Arc<ReqContext>>,
@@ -346,16 +860,18 @@ pub async fn complete(
     ])
     .await;
 
+    let fees = db::Charge::resolve_fees(input.amount, input.provider_fee, input.net_amount)?;
     let mut doc = db::Charge::with_pk(uid, id);
     doc.get_one(
         &app.scylla,
-        vec![
-            "quantity".to_string(),
-            "provider".to_string(),
-            "currency".to_string(),
-            "amount".to_string(),
-            "charge_id".to_string(),
-        ],
+        [
+            db::ChargeField::Quantity,
+            db::ChargeField::Provider,
+            db::ChargeField::Currency,
+            db::ChargeField::Amount,
+            db::ChargeField::ChargeId,
+        ]
+        .into(),
     )
     .await?;
 
@@ -369,11 +885,41 @@ pub async fn complete(
         ));
     }
 
+    // test charges must not topup with live transactions, and vice versa.
+    let livemode = resolve_livemode(
+        input.livemode,
+        Some(input.charge_payload.unwrap_ref().as_slice()),
+    )?;
+    if livemode != doc.is_livemode() {
+        return Err(HTTPError::new(
+            400,
+            format!(
+                "livemode mismatch, expected {}, got {}",
+                doc.is_livemode(),
+                livemode
+            ),
+        ));
+    }
+    if livemode != db::livemode() {
+        return Err(HTTPError::new(
+            400,
+            format!(
+                "Can not complete charge with livemode {} in deployment with livemode {}",
+                livemode,
+                db::livemode()
+            ),
+        ));
+    }
+
     let mut cols = ColumnsMap::new();
     cols.set_as("status", &2i8);
     cols.set_as("currency", &input.currency);
     cols.set_as("amount", &input.amount);
     cols.set_as("charge_payload", &input.charge_payload.unwrap());
+    if let Some((provider_fee, net_amount)) = fees {
+        cols.set_as("provider_fee", &provider_fee);
+        cols.set_as("net_amount", &net_amount);
+    }
 
     let ok = doc.update(&app.scylla, cols, 1).await?;
     if !ok {
@@ -414,6 +960,22 @@ pub async fn complete(
     cols.set_as("status", &3i8);
     cols.set_as("txn", &txn.id);
     doc.update(&app.scylla, cols, 2i8).await?;
+    if let Err(err) = db::ChargeDailyTotal::incr(&app.scylla, &doc).await {
+        log::error!(target: "scylladb",
+            action = "save_charge_daily_total",
+            uid = uid.to_string(),
+            id = id.to_string();
+            "{}", err,
+        );
+    }
+    if let Err(err) = db::DailyChargeStats::incr(&app.scylla, &doc).await {
+        log::error!(target: "scylladb",
+            action = "save_daily_charge_stats",
+            uid = uid.to_string(),
+            id = id.to_string();
+            "{}", err,
+        );
+    }
 
     if wallet.map(|w| w.credits == 0) == Some(true) {
         tokio::spawn(award_first_topup(
@@ -424,14 +986,147 @@ pub async fn complete(
     }
 
     if let Ok(cur) = Currency::from_str(&input.currency) {
-        let amount = input.amount as f32 / 10f32.powi(cur.decimals as i32);
-        ctx.set(
-            "message",
-            format!("{:.2} {}", amount.to_string(), cur.name).into(),
-        )
-        .await;
+        let amount = money::format_amount(input.amount, cur.decimals, money::DEFAULT_LOCALE);
+        ctx.set("message", format!("{} {}", amount, cur.name).into())
+            .await;
+    }
+
+    Ok(to.with(SuccessResponse::new(ChargeOutput::from(doc, &to))))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
+pub struct RefundChargeInput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub uid: PackObject<xid::Id>,
+    #[schema(value_type = super::openapi::Xid)]
+    pub id: PackObject<xid::Id>,
+    // fiat amount refunded by the provider this time, in the charge's currency.
+    // multiple partial refunds are allowed until the charged amount is fully refunded.
+    #[validate(range(min = 1))]
+    pub amount: i64,
+}
+
+// refunds a completed charge partially or fully, coins are clawed back from the wallet's topup
+// in proportion to the refunded amount.
+#[utoipa::path(
+    post,
+    path = "/v1/charge/refund",
+    tag = "charge",
+    request_body = RefundChargeInput,
+    responses(
+        (status = 200, body = super::openapi::ChargeResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn refund(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<RefundChargeInput>,
+) -> Result<PackObject<SuccessResponse<ChargeOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    let uid = input.uid.unwrap();
+    let id = input.id.unwrap();
+    ctx.set_kvs(vec![
+        ("action", "refund_charge".into()),
+        ("uid", uid.to_string().into()),
+        ("id", id.to_string().into()),
+        ("amount", input.amount.into()),
+    ])
+    .await;
+
+    let mut doc = db::Charge::with_pk(uid, id);
+    doc.get_one(
+        &app.scylla,
+        [
+            db::ChargeField::Currency,
+            db::ChargeField::Amount,
+            db::ChargeField::AmountRefunded,
+            db::ChargeField::Txn,
+            db::ChargeField::TxnRefunded,
+        ]
+        .into(),
+    )
+    .await?;
+
+    if doc.status != 3 {
+        return Err(HTTPError::new(
+            409,
+            format!("Invalid status {} for refunding charge", doc.status),
+        ));
+    }
+    let amount_refunded = doc.amount_refunded + input.amount;
+    if amount_refunded > doc.amount {
+        return Err(HTTPError::new(
+            400,
+            format!(
+                "Refund amount {} exceeds the refundable amount {}",
+                input.amount,
+                doc.amount - doc.amount_refunded
+            ),
+        ));
+    }
+
+    let quantity = doc.refund_quantity(amount_refunded);
+    ctx.set_kvs(vec![
+        ("amount_refunded", amount_refunded.into()),
+        ("quantity", quantity.into()),
+    ])
+    .await;
+
+    // the refund may be too small to claw back a coin, it is still recorded on the charge.
+    if quantity == 0 {
+        if !doc.refund(&app.scylla, amount_refunded, None).await? {
+            return Err(HTTPError::new(
+                409,
+                "Charge refund conflict, please try again".to_string(),
+            ));
+        }
+        return Ok(to.with(SuccessResponse::new(ChargeOutput::from(doc, &to))));
+    }
+
+    db::TransactionKind::Refund.check_amount(quantity)?;
+    // the topup of the charge is paid by the system.
+    let mut txn = db::Transaction {
+        uid,
+        description: format!("{}.refund", doc.provider),
+        ref_uid: doc.txn.map(|_| db::SYS_ID),
+        ref_txn: doc.txn,
+        payload: cbor_to_vec(&TransactionPayload {
+            kind: "charge".to_string(),
+            id: PackObject::Cbor(doc.id),
+            provider: Some(doc.provider.clone()),
+            currency: Some(doc.currency.clone()),
+            amount: Some(input.amount),
+        })
+        .unwrap_or_default(),
+        ..Default::default()
+    };
+    txn.prepare(
+        &app.scylla,
+        &app.mac,
+        db::SYS_ID,
+        db::TransactionKind::Refund,
+        quantity,
+    )
+    .await?;
+    ctx.set("txn", txn.id.to_string().into()).await;
+
+    // the cumulative amount_refunded is guarded by LWT, so that concurrent refunds
+    // can not exceed the charged amount.
+    if !doc
+        .refund(&app.scylla, amount_refunded, Some(txn.id))
+        .await?
+    {
+        txn.cancel(&app.scylla, &app.mac).await?;
+        return Err(HTTPError::new(
+            409,
+            "Charge refund conflict, please try again".to_string(),
+        ));
     }
 
+    txn.commit(&app.scylla, &app.mac).await?;
     Ok(to.with(SuccessResponse::new(ChargeOutput::from(doc, &to))))
 }
 
@@ -450,8 +1145,16 @@ async fn award_first_topup(app: Arc<AppState>, ctx: ReqContext, txn: xid::Id) {
         ctx.set("init_credits", res.is_ok().into()).await;
 
         if res.is_ok() {
-            let tx0 = db::Transaction::first_from_system(&app.scylla, ctx.user).await?;
-            if let Ok(payload) = cbor_from_slice::<AwardPayload>(&tx0.payload) {
+            let tx0 = db::Transaction::first_from_system(
+                &app.scylla,
+                ctx.user,
+                [db::TransactionField::Payload].into(),
+            )
+            .await?;
+            // award payloads from the input are tagged as self-described CBOR.
+            if let Ok(payload) =
+                cbor_from_slice::<AwardPayload>(crypto::unwrap_cbor_tag(&tx0.payload))
+            {
                 if let Some(referrer) = payload.referrer {
                     ctx.set("referrer", referrer.to_string().into()).await;
                     let mut wallet = db::Wallet::with_pk(referrer.unwrap());
@@ -459,6 +1162,8 @@ async fn award_first_topup(app: Arc<AppState>, ctx: ReqContext, txn: xid::Id) {
                     if wallet.credits > 0 {
                         let mut txn = db::Transaction {
                             description: "Referral reward".to_string(),
+                            ref_uid: Some(db::SYS_ID),
+                            ref_txn: Some(txn),
                             payload: cbor_to_vec(&TransactionPayload {
                                 kind: "transaction".to_string(),
                                 id: PackObject::Cbor(txn),
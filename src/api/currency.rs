@@ -1,28 +1,92 @@
 use std::str::FromStr;
 
-use axum::extract::State;
+use axum::extract::{Query, State};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc,
+};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
 
 use axum_web::erring::{HTTPError, SuccessResponse};
 use axum_web::object::PackObject;
 
 use crate::api::AppState;
+use crate::money;
 
-#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[derive(Debug, Default, Clone, Deserialize, Serialize, ToSchema)]
 pub struct Currency {
-    pub name: &'static str,
+    pub name: &'static str, // deprecated, same as name_native
+    pub name_en: &'static str,
+    pub name_native: &'static str,
+    pub symbol: &'static str,
     pub alpha: &'static str,
     pub decimals: u8, // 0..3
     pub code: u16,
+    pub enabled: bool, // from conf, disabled currencies can not be used for new charges
+}
+
+// bit i is set if CURRENCIES[i] is enabled, it is set from conf at startup.
+static ENABLED: AtomicU32 = AtomicU32::new(u32::MAX);
+
+// enables the given currencies by alpha or numeric code, empty to enable all.
+pub fn set_enabled_currencies(codes: &[String]) -> anyhow::Result<()> {
+    if codes.is_empty() {
+        ENABLED.store(u32::MAX, Ordering::Relaxed);
+        return Ok(());
+    }
+
+    let mut mask = 0u32;
+    for code in codes {
+        let i = CURRENCIES
+            .iter()
+            .position(|c| c.matches(code))
+            .ok_or_else(|| anyhow::anyhow!("Invalid currency in conf: {}", code))?;
+        mask |= 1 << i;
+    }
+    ENABLED.store(mask, Ordering::Relaxed);
+    Ok(())
+}
+
+impl Currency {
+    // matches alpha code case insensitively, or numeric code, e.g. "usd", "USD" or "840".
+    fn matches(&self, s: &str) -> bool {
+        match s.parse::<u16>() {
+            Ok(code) => self.code == code,
+            Err(_) => self.alpha.eq_ignore_ascii_case(s),
+        }
+    }
+
+    pub fn check_enabled(&self) -> Result<(), HTTPError> {
+        if self.enabled {
+            return Ok(());
+        }
+        Err(HTTPError::new(
+            400,
+            format!("Currency {} is not enabled", self.alpha),
+        ))
+    }
+
+    pub fn list() -> Vec<Currency> {
+        let mask = ENABLED.load(Ordering::Relaxed);
+        CURRENCIES
+            .iter()
+            .enumerate()
+            .map(|(i, c)| Currency {
+                enabled: mask & (1 << i) != 0,
+                ..c.clone()
+            })
+            .collect()
+    }
 }
 
 impl FromStr for Currency {
     type Err = HTTPError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        for currency in CURRENCIES.iter() {
-            if currency.alpha == s.to_ascii_uppercase() {
-                return Ok(currency.clone());
+        for currency in Currency::list() {
+            if currency.matches(s) {
+                return Ok(currency);
             }
         }
         Err(HTTPError::new(400, format!("Invalid currency: {}", s)))
@@ -35,81 +99,202 @@ impl FromStr for Currency {
 pub const CURRENCIES: [Currency; 12] = [
     Currency {
         name: "港幣",
+        name_en: "Hong Kong Dollar",
+        name_native: "港幣",
+        symbol: "HK$",
         alpha: "HKD",
         decimals: 2,
         code: 344,
+        enabled: true,
     },
     Currency {
         name: "US Dollar",
+        name_en: "US Dollar",
+        name_native: "US Dollar",
+        symbol: "$",
         alpha: "USD",
         decimals: 2,
         code: 840,
+        enabled: true,
     },
     Currency {
         name: "人民币",
+        name_en: "Chinese Yuan",
+        name_native: "人民币",
+        symbol: "¥",
         alpha: "CNY",
         decimals: 2,
         code: 156,
+        enabled: true,
     },
     Currency {
         name: "Euro",
+        name_en: "Euro",
+        name_native: "Euro",
+        symbol: "€",
         alpha: "EUR",
         decimals: 2,
         code: 978,
+        enabled: true,
     },
     Currency {
         name: "日本円",
+        name_en: "Japanese Yen",
+        name_native: "日本円",
+        symbol: "¥",
         alpha: "JPY",
         decimals: 0,
         code: 392,
+        enabled: true,
     },
     Currency {
         name: "Pound Sterling",
+        name_en: "Pound Sterling",
+        name_native: "Pound Sterling",
+        symbol: "£",
         alpha: "GBP",
         decimals: 2,
         code: 826,
+        enabled: true,
     },
     Currency {
         name: "Canadian Dollar",
+        name_en: "Canadian Dollar",
+        name_native: "Canadian Dollar",
+        symbol: "CA$",
         alpha: "CAD",
         decimals: 2,
         code: 124,
+        enabled: true,
     },
     Currency {
         name: "Singapore Dollar",
+        name_en: "Singapore Dollar",
+        name_native: "Singapore Dollar",
+        symbol: "S$",
         alpha: "SGD",
         decimals: 2,
         code: 702,
+        enabled: true,
     },
     Currency {
         name: "Australian Dollar",
+        name_en: "Australian Dollar",
+        name_native: "Australian Dollar",
+        symbol: "A$",
         alpha: "AUD",
         decimals: 2,
         code: 36,
+        enabled: true,
     },
     Currency {
         name: "درهم إماراتي",
+        name_en: "UAE Dirham",
+        name_native: "درهم إماراتي",
+        symbol: "د.إ",
         alpha: "AED",
         decimals: 2,
         code: 784,
+        enabled: true,
     },
     Currency {
         name: "원",
+        name_en: "South Korean Won",
+        name_native: "원",
+        symbol: "₩",
         alpha: "KRW",
         decimals: 0,
         code: 410,
+        enabled: true,
     },
     Currency {
         name: "рубль",
+        name_en: "Russian Ruble",
+        name_native: "рубль",
+        symbol: "₽",
         alpha: "RUB",
         decimals: 2,
         code: 643,
+        enabled: true,
     },
 ];
 
+#[derive(Debug, Deserialize, Serialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryCurrencies {
+    pub enabled: Option<bool>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/currencies",
+    tag = "app",
+    params(QueryCurrencies),
+    responses(
+        (status = 200, body = super::openapi::CurrenciesResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
 pub async fn currencies(
     to: PackObject<()>,
     State(_app): State<Arc<AppState>>,
+    Query(input): Query<QueryCurrencies>,
 ) -> Result<PackObject<SuccessResponse<Vec<Currency>>>, HTTPError> {
-    Ok(to.with(SuccessResponse::new(CURRENCIES.into())))
+    let res = Currency::list()
+        .into_iter()
+        .filter(|c| input.enabled.is_none() || input.enabled == Some(c.enabled))
+        .collect();
+    Ok(to.with(SuccessResponse::new(res)))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryFormatAmount {
+    pub amount: i64, // in the smallest currency unit
+    #[validate(length(min = 1))]
+    pub currency: String,
+    // BCP 47 language tag, default to "en".
+    pub locale: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct FormatAmountOutput {
+    pub amount: i64,
+    pub currency: String,
+    pub decimals: u8,
+    pub symbol: String,
+    pub locale: String,
+    pub formatted: String, // e.g. "1,234.56"
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/format_amount",
+    tag = "app",
+    params(QueryFormatAmount),
+    responses(
+        (status = 200, body = super::openapi::FormatAmountResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn format_amount(
+    to: PackObject<()>,
+    State(_app): State<Arc<AppState>>,
+    Query(input): Query<QueryFormatAmount>,
+) -> Result<PackObject<SuccessResponse<FormatAmountOutput>>, HTTPError> {
+    input.validate()?;
+    let cur = Currency::from_str(&input.currency)?;
+    let locale = input
+        .locale
+        .filter(|l| !l.is_empty())
+        .unwrap_or_else(|| money::DEFAULT_LOCALE.to_string());
+
+    Ok(to.with(SuccessResponse::new(FormatAmountOutput {
+        amount: input.amount,
+        currency: cur.alpha.to_string(),
+        decimals: cur.decimals,
+        symbol: cur.symbol.to_string(),
+        formatted: money::format_amount(input.amount, cur.decimals, &locale),
+        locale,
+    })))
 }
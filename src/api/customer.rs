@@ -4,43 +4,59 @@ use axum::{
 };
 use serde::{Deserialize, Serialize};
 use std::{sync::Arc, vec};
+use utoipa::{IntoParams, ToSchema};
 use validator::Validate;
 
 use axum_web::context::ReqContext;
 use axum_web::erring::{HTTPError, SuccessResponse};
 use axum_web::object::PackObject;
 
-use crate::api::{get_fields, validate_provider, AppState};
+use crate::api::{
+    capabilities_of, get_fields, resolve_livemode, validate_provider, validate_uid, AppState,
+};
 use crate::db;
 
-#[derive(Debug, Deserialize, Validate)]
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
 pub struct CustomerInput {
+    #[schema(value_type = super::openapi::Xid)]
     pub uid: PackObject<xid::Id>,
     #[validate(length(min = 1), custom = "validate_provider")]
     pub provider: String, // stripe
     pub customer: String,
+    #[schema(value_type = super::openapi::Base64Url)]
     pub payload: PackObject<Vec<u8>>,
+    // false for provider test mode, default to the payload's livemode or the deployment's.
+    pub livemode: Option<bool>,
 }
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
 pub struct CustomerOutput {
+    #[schema(value_type = super::openapi::Xid)]
     pub uid: PackObject<xid::Id>,
     pub provider: String,
     pub customer: String,
+    pub livemode: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub created_at: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub updated_at: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<super::openapi::Base64Url>)]
     pub payload: Option<PackObject<Vec<u8>>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub customers: Option<Vec<String>>,
+    // capabilities from the provider payload, listing only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub charges_enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payouts_enabled: Option<bool>,
 }
 
 impl CustomerOutput {
     pub fn from<T>(val: db::Customer, to: &PackObject<T>) -> Self {
         let mut rt = Self {
             uid: to.with(val.uid),
+            livemode: val.is_livemode(),
             provider: val.provider,
             customer: val.customer,
             ..Default::default()
@@ -60,6 +76,16 @@ impl CustomerOutput {
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/v1/customer",
+    tag = "customer",
+    request_body = CustomerInput,
+    responses(
+        (status = 200, body = super::openapi::CustomerResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
 pub async fn upsert(
     State(app): State<Arc<AppState>>,
     Extension(ctx): Extension<Arc<ReqContext>>,
@@ -69,29 +95,49 @@ pub async fn upsert(
     input.validate()?;
 
     let uid = input.uid.unwrap();
+    let livemode = resolve_livemode(input.livemode, Some(input.payload.unwrap_ref().as_slice()))?;
     ctx.set_kvs(vec![
         ("action", "upsert_customer".into()),
         ("uid", uid.to_string().into()),
         ("provider", input.provider.to_string().into()),
         ("customer", input.customer.clone().into()),
+        ("livemode", livemode.into()),
     ])
     .await;
 
     let mut doc = db::Customer::with_pk(uid, input.provider);
 
-    doc.upsert(&app.scylla, input.customer, input.payload.unwrap())
-        .await?;
+    doc.upsert(
+        &app.scylla,
+        input.customer,
+        input.payload.unwrap(),
+        livemode,
+    )
+    .await?;
 
     Ok(to.with(SuccessResponse::new(CustomerOutput::from(doc, &to))))
 }
 
-#[derive(Debug, Deserialize, Validate)]
+#[derive(Debug, Deserialize, Serialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct QueryCustomer {
+    #[param(value_type = super::openapi::Xid)]
+    #[validate(custom = "validate_uid")]
     pub uid: PackObject<xid::Id>,
     pub provider: String,
     pub fields: Option<String>,
 }
 
+#[utoipa::path(
+    get,
+    path = "/v1/customer",
+    tag = "customer",
+    params(QueryCustomer),
+    responses(
+        (status = 200, body = super::openapi::CustomerResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
 pub async fn get(
     State(app): State<Arc<AppState>>,
     Extension(ctx): Extension<Arc<ReqContext>>,
@@ -110,7 +156,160 @@ pub async fn get(
     .await;
 
     let mut doc = db::Customer::with_pk(uid, provider);
-    doc.get_one(&app.scylla, get_fields(input.fields.clone()))
+    doc.get_one(&app.scylla, get_fields(input.fields.clone())?)
+        .await?;
+    Ok(to.with(SuccessResponse::new(CustomerOutput::from(doc, &to))))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryCustomerList {
+    #[validate(custom = "validate_uid")]
+    #[param(value_type = super::openapi::Xid)]
+    pub uid: PackObject<xid::Id>,
+    // created_at and updated_at are always returned, the payload only if selected.
+    pub fields: Option<String>,
+}
+
+// lists the customers linked to the user across providers.
+#[utoipa::path(
+    get,
+    path = "/v1/customer/list",
+    tag = "customer",
+    params(QueryCustomerList),
+    responses(
+        (status = 200, body = super::openapi::CustomersResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn list(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QueryCustomerList>,
+) -> Result<PackObject<SuccessResponse<Vec<CustomerOutput>>>, HTTPError> {
+    input.validate()?;
+    let uid = *input.uid.to_owned();
+
+    let mut fields = get_fields::<db::CustomerField>(input.fields.clone())?;
+    let with_payload = fields.is_empty() || fields.contains(db::CustomerField::Payload);
+    if !fields.is_empty() {
+        fields = fields
+            .with(db::CustomerField::CreatedAt)
+            .with(db::CustomerField::UpdatedAt)
+            .with(db::CustomerField::Payload);
+    }
+
+    let docs = db::Customer::list_by_uid(&app.scylla, uid, fields).await?;
+    ctx.set_kvs(vec![
+        ("action", "list_customers".into()),
+        ("uid", uid.to_string().into()),
+        ("total", docs.len().into()),
+    ])
+    .await;
+
+    let mut res: Vec<CustomerOutput> = Vec::with_capacity(docs.len());
+    for doc in docs {
+        let (charges_enabled, payouts_enabled) = capabilities_of(&doc.payload);
+        let mut item = CustomerOutput::from(doc, &to);
+        item.charges_enabled = charges_enabled;
+        item.payouts_enabled = payouts_enabled;
+        if !with_payload {
+            item.payload = None;
+        }
+        res.push(item);
+    }
+
+    Ok(to.with(SuccessResponse {
+        total_size: Some(res.len() as u64),
+        next_page_token: None,
+        result: res,
+    }))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryByCustomer {
+    #[validate(length(min = 1), custom = "validate_provider")]
+    pub provider: String,
+    // the provider's customer id, current or used before by the user.
+    #[validate(length(min = 1))]
+    pub customer: String,
+    pub fields: Option<String>,
+}
+
+// resolves the user by the provider's customer id, e.g. for provider webhooks.
+#[utoipa::path(
+    get,
+    path = "/v1/customer/by_customer",
+    tag = "customer",
+    params(QueryByCustomer),
+    responses(
+        (status = 200, body = super::openapi::CustomerResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn get_by_customer(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QueryByCustomer>,
+) -> Result<PackObject<SuccessResponse<CustomerOutput>>, HTTPError> {
+    input.validate()?;
+    let provider = input.provider.to_owned();
+
+    ctx.set_kvs(vec![
+        ("action", "get_customer_by_customer".into()),
+        ("provider", provider.clone().into()),
+        ("customer", input.customer.clone().into()),
+    ])
+    .await;
+
+    let uid = db::Customer::find_uid(&app.scylla, &provider, &input.customer).await?;
+    ctx.set("uid", uid.to_string().into()).await;
+
+    let mut doc = db::Customer::with_pk(uid, provider);
+    doc.get_one(&app.scylla, get_fields(input.fields.clone())?)
         .await?;
     Ok(to.with(SuccessResponse::new(CustomerOutput::from(doc, &to))))
 }
+
+// refuses deletion if there are pending charges for the provider.
+#[utoipa::path(
+    delete,
+    path = "/v1/customer",
+    tag = "customer",
+    params(QueryCustomer),
+    responses(
+        (status = 200, body = super::openapi::BoolResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn delete(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QueryCustomer>,
+) -> Result<PackObject<SuccessResponse<bool>>, HTTPError> {
+    input.validate()?;
+    let uid = *input.uid.to_owned();
+    let provider = input.provider.to_owned();
+
+    ctx.set_kvs(vec![
+        ("action", "delete_customer".into()),
+        ("uid", uid.to_string().into()),
+        ("provider", provider.clone().into()),
+    ])
+    .await;
+
+    if db::Charge::has_pending(&app.scylla, uid, &provider).await? {
+        return Err(HTTPError::new(
+            409,
+            format!("Customer has pending {} charges", provider),
+        ));
+    }
+
+    let mut doc = db::Customer::with_pk(uid, provider);
+    let res = doc.delete(&app.scylla).await?;
+    Ok(to.with(SuccessResponse::new(res)))
+}
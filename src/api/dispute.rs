@@ -0,0 +1,422 @@
+use axum::{
+    extract::{Query, State},
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+use std::{str::FromStr, sync::Arc};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+use axum_web::context::{unix_ms, ReqContext};
+use axum_web::erring::{HTTPError, SuccessResponse};
+use axum_web::object::{cbor_to_vec, PackObject};
+use scylla_orm::FieldSet;
+
+use crate::api::{get_fields, token_from_xid, token_to_xid, AppState, TransactionPayload};
+use crate::db::{self, SYS_ID};
+
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
+pub struct DisputeInput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub uid: PackObject<xid::Id>,
+    #[schema(value_type = super::openapi::Xid)]
+    pub id: PackObject<xid::Id>,
+    #[validate(length(min = 1, max = 1024))]
+    pub reason: String,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct DisputeOutput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub id: PackObject<xid::Id>,
+    #[schema(value_type = super::openapi::Xid)]
+    pub uid: PackObject<xid::Id>,
+    // -1: released, 0: open, 2: refunding, 1: refunded.
+    pub status: i8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<super::openapi::Xid>)]
+    pub payee: Option<PackObject<xid::Id>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frozen: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<super::openapi::Xid>)]
+    pub hold: Option<PackObject<xid::Id>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolver: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<super::openapi::Xid>)]
+    pub refund_txn: Option<PackObject<xid::Id>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<super::openapi::Xid>)]
+    pub clawback_txn: Option<PackObject<xid::Id>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<i64>,
+}
+
+impl DisputeOutput {
+    pub fn from<T>(val: db::Dispute, to: &PackObject<T>) -> Self {
+        let mut rt = Self {
+            id: to.with(val.id),
+            uid: to.with(val.uid),
+            status: val.status,
+            ..Default::default()
+        };
+
+        for v in val._fields {
+            match v.as_str() {
+                "payee" => rt.payee = Some(to.with(val.payee)),
+                "kind" => rt.kind = Some(val.kind.to_owned()),
+                "amount" => rt.amount = Some(val.amount),
+                "frozen" => rt.frozen = Some(val.frozen),
+                "hold" => rt.hold = Some(to.with(val.hold)),
+                "reason" => rt.reason = Some(val.reason.to_owned()),
+                "resolver" => rt.resolver = Some(val.resolver.to_owned()),
+                "note" => rt.note = Some(val.note.to_owned()),
+                "refund_txn" => rt.refund_txn = Some(to.with(val.refund_txn)),
+                "clawback_txn" => rt.clawback_txn = Some(to.with(val.clawback_txn)),
+                "created_at" => rt.created_at = Some(val.created_at),
+                "updated_at" => rt.updated_at = Some(val.updated_at),
+                _ => {}
+            }
+        }
+
+        rt
+    }
+}
+
+// flags a committed transaction as disputed by its payer, and freezes the payee's income of
+// the transaction by a hold until the dispute is resolved by admin.
+#[utoipa::path(
+    post,
+    path = "/v1/transaction/dispute",
+    tag = "transaction",
+    request_body = DisputeInput,
+    responses(
+        (status = 200, body = super::openapi::DisputeResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn dispute(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<DisputeInput>,
+) -> Result<PackObject<SuccessResponse<DisputeOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    let uid = input.uid.unwrap();
+    let id = input.id.unwrap();
+    ctx.set_kvs(vec![
+        ("action", "dispute_transaction".into()),
+        ("payer", uid.to_string().into()),
+        ("id", id.to_string().into()),
+    ])
+    .await;
+
+    let mut txn = db::Transaction::with_pk(uid, id);
+    txn.get_one(&app.scylla, FieldSet::new()).await?;
+    if txn.status != 3 {
+        return Err(HTTPError::new(
+            400,
+            format!("Transaction {} is not committed", id),
+        ));
+    }
+    let kind = db::TransactionKind::from_str(&txn.kind)
+        .map_err(|_| HTTPError::new(400, format!("Invalid kind {}", txn.kind)))?;
+    if !kind.rules().disputable() {
+        return Err(HTTPError::new(
+            400,
+            format!("{} transaction can not be disputed", txn.kind),
+        ));
+    }
+    if unix_ms() as i64 - txn.updated_at > db::MAX_DISPUTE_AGE_MS {
+        return Err(HTTPError::new(
+            400,
+            format!("Transaction {} is too old to dispute", id),
+        ));
+    }
+
+    let mut doc = db::Dispute {
+        id,
+        uid,
+        payee: txn.payee,
+        kind: txn.kind.clone(),
+        amount: txn.amount,
+        reason: input.reason,
+        ..Default::default()
+    };
+
+    let mut hold = db::WalletHold {
+        uid: txn.payee,
+        amount: txn.amount - txn.sys_fee - txn.sub_shares,
+        description: format!("dispute.{}", id),
+        ..Default::default()
+    };
+    if txn.payee != SYS_ID && hold.amount > 0 {
+        hold.freeze(&app.scylla).await?;
+        doc.frozen = hold.amount;
+        doc.hold = hold.id;
+        ctx.set_kvs(vec![
+            ("hold", hold.id.to_string().into()),
+            ("frozen", hold.amount.into()),
+        ])
+        .await;
+    }
+
+    if let Err(err) = doc.save(&app.scylla).await {
+        if doc.frozen > 0 {
+            hold.release(&app.scylla).await?;
+        }
+        return Err(err.into());
+    }
+
+    Ok(to.with(SuccessResponse::new(DisputeOutput::from(doc, &to))))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryDispute {
+    #[param(value_type = super::openapi::Xid)]
+    pub id: PackObject<xid::Id>,
+    pub fields: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/admin/dispute",
+    tag = "admin",
+    params(QueryDispute),
+    responses(
+        (status = 200, body = super::openapi::DisputeResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn get(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QueryDispute>,
+) -> Result<PackObject<SuccessResponse<DisputeOutput>>, HTTPError> {
+    input.validate()?;
+    let id = *input.id.to_owned();
+
+    ctx.set_kvs(vec![
+        ("action", "get_dispute".into()),
+        ("id", id.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::Dispute::with_pk(id);
+    doc.get_one(&app.scylla, get_fields(input.fields.clone())?)
+        .await?;
+    Ok(to.with(SuccessResponse::new(DisputeOutput::from(doc, &to))))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryPendingDisputes {
+    #[validate(range(min = 2, max = 1000))]
+    pub page_size: Option<u16>,
+    #[param(value_type = Option<super::openapi::Base64Url>)]
+    pub page_token: Option<PackObject<Vec<u8>>>,
+}
+
+// lists open disputes, oldest first.
+#[utoipa::path(
+    get,
+    path = "/v1/admin/dispute/queue",
+    tag = "admin",
+    params(QueryPendingDisputes),
+    responses(
+        (status = 200, body = super::openapi::DisputesResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn list_pending(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QueryPendingDisputes>,
+) -> Result<PackObject<SuccessResponse<Vec<DisputeOutput>>>, HTTPError> {
+    input.validate()?;
+
+    let page_size = input.page_size.unwrap_or(100);
+    ctx.set_kvs(vec![
+        ("action", "list_pending_disputes".into()),
+        ("page_size", page_size.into()),
+    ])
+    .await;
+
+    let kind = "list_pending_disputes";
+    let page_token = token_to_xid(&app.mac, &db::SYS_ID, kind, &input.page_token)?;
+    let res = db::Dispute::list_pending(&app.scylla, page_size, page_token).await?;
+    let next_page_token = if res.len() >= page_size as usize {
+        to.with_option(token_from_xid(
+            &app.mac,
+            &db::SYS_ID,
+            kind,
+            res.last().unwrap().id,
+        ))
+    } else {
+        None
+    };
+
+    Ok(to.with(SuccessResponse {
+        total_size: None,
+        next_page_token,
+        result: res
+            .iter()
+            .map(|r| DisputeOutput::from(r.to_owned(), &to))
+            .collect(),
+    }))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
+pub struct ResolveInput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub id: PackObject<xid::Id>,
+    #[validate(length(min = 1, max = 64))]
+    pub resolver: String,
+    // refunds the payer if true, otherwise releases the frozen income to the payee.
+    pub refund: bool,
+    #[validate(length(max = 1024))]
+    pub note: Option<String>,
+}
+
+// resolves the open dispute by refunding or releasing.
+// refunding is idempotent, a refunding dispute is resumed by resolving it with refund again.
+#[utoipa::path(
+    post,
+    path = "/v1/admin/dispute/resolve",
+    tag = "admin",
+    request_body = ResolveInput,
+    responses(
+        (status = 200, body = super::openapi::DisputeResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn resolve(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<ResolveInput>,
+) -> Result<PackObject<SuccessResponse<DisputeOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    let id = input.id.unwrap();
+    ctx.set_kvs(vec![
+        ("action", "resolve_dispute".into()),
+        ("id", id.to_string().into()),
+        ("resolver", input.resolver.clone().into()),
+        ("refund", input.refund.into()),
+    ])
+    .await;
+
+    let mut doc = db::Dispute::with_pk(id);
+    doc.get_one(&app.scylla, FieldSet::new()).await?;
+    match doc.status {
+        0 => {
+            let status = if input.refund { 2 } else { -1 };
+            doc.decide(
+                &app.scylla,
+                input.resolver,
+                status,
+                input.note.unwrap_or_default(),
+            )
+            .await?;
+        }
+        2 if input.refund => {}
+        _ => {
+            return Err(HTTPError::new(
+                409,
+                format!("Dispute {} has been resolved", id),
+            ));
+        }
+    }
+
+    let (uid, payee, amount, frozen) = (doc.uid, doc.payee, doc.amount, doc.frozen);
+    if doc.status == 2 {
+        chargeback(&app, &mut doc, "refund_txn", SYS_ID, uid, amount).await?;
+        if frozen > 0 {
+            chargeback(&app, &mut doc, "clawback_txn", payee, SYS_ID, frozen).await?;
+        }
+    }
+
+    // the frozen income has been clawed back or is released to the payee.
+    if frozen > 0 {
+        db::WalletHold::with_pk(payee, doc.hold)
+            .release(&app.scylla)
+            .await?;
+    }
+    if doc.status == 2 {
+        doc.refunded(&app.scylla).await?;
+    }
+
+    Ok(to.with(SuccessResponse::new(DisputeOutput::from(doc, &to))))
+}
+
+// records the chargeback transaction before committed, so that it can be retried.
+async fn chargeback(
+    app: &AppState,
+    doc: &mut db::Dispute,
+    field: &str,
+    payer: xid::Id,
+    payee: xid::Id,
+    amount: i64,
+) -> anyhow::Result<()> {
+    let recorded = |doc: &db::Dispute| {
+        if field == "refund_txn" {
+            doc.refund_txn
+        } else {
+            doc.clawback_txn
+        }
+    };
+
+    if recorded(doc) == xid::Id::default() {
+        let mut txn = db::Transaction::with_uid(payer);
+        txn.ref_uid = Some(doc.uid);
+        txn.ref_txn = Some(doc.id);
+        txn.description = if payer == SYS_ID {
+            "payee.dispute.refund".to_string()
+        } else {
+            "payer.dispute.clawback".to_string()
+        };
+        txn.payload = cbor_to_vec(&TransactionPayload {
+            kind: "dispute".to_string(),
+            id: PackObject::Cbor(doc.id),
+            provider: None,
+            currency: None,
+            amount: None,
+        })
+        .unwrap_or_default();
+        txn.prepare(
+            &app.scylla,
+            &app.mac,
+            payee,
+            db::TransactionKind::Chargeback,
+            amount,
+        )
+        .await?;
+        if !doc.set_txn(&app.scylla, field, txn.id).await? {
+            // recorded by others.
+            txn.cancel(&app.scylla, &app.mac).await?;
+        }
+    }
+
+    let mut txn = db::Transaction::with_pk(payer, recorded(doc));
+    txn.get_one(&app.scylla, FieldSet::new()).await?;
+    if txn.status == 1 || txn.status == 2 {
+        txn.commit(&app.scylla, &app.mac).await?;
+    }
+    Ok(())
+}
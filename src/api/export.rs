@@ -0,0 +1,182 @@
+use axum::{
+    body::{Bytes, StreamBody},
+    extract::{Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use futures::stream::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicI64, AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+use axum_web::context::{unix_ms, ReqContext};
+use axum_web::erring::HTTPError;
+use axum_web::object::{cbor_to_vec, PackObject};
+
+use crate::api::{transaction::TransactionOutput, AppState};
+use crate::db;
+use crate::money;
+
+// exports allowed per minute of the instance, and max rows of an export.
+// they are set from conf at startup.
+static EXPORT_RATE_LIMIT: AtomicU32 = AtomicU32::new(10);
+static EXPORT_MAX_ROWS: AtomicU64 = AtomicU64::new(100_000);
+
+// fixed window of the rate limit.
+static EXPORT_WINDOW: AtomicI64 = AtomicI64::new(0);
+static EXPORT_COUNT: AtomicU32 = AtomicU32::new(0);
+
+pub fn set_export_limits(rate_limit: u32, max_rows: u64) {
+    EXPORT_RATE_LIMIT.store(rate_limit, Ordering::Relaxed);
+    EXPORT_MAX_ROWS.store(max_rows, Ordering::Relaxed);
+}
+
+fn acquire(now_ms: i64) -> bool {
+    let window = now_ms / 60_000;
+    if EXPORT_WINDOW.swap(window, Ordering::Relaxed) != window {
+        EXPORT_COUNT.store(0, Ordering::Relaxed);
+    }
+    EXPORT_COUNT.fetch_add(1, Ordering::Relaxed) < EXPORT_RATE_LIMIT.load(Ordering::Relaxed)
+}
+
+// the smallest (fill 0) or the largest (fill 255) xid at the unix ms.
+pub fn xid_at(ms: i64, fill: u8) -> xid::Id {
+    let mut id = xid::Id([fill; 12]);
+    id.0[..4].copy_from_slice(&((ms / 1000).clamp(0, u32::MAX as i64) as u32).to_be_bytes());
+    id
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryExportTransactions {
+    #[param(value_type = Option<super::openapi::Xid>)]
+    pub uid: Option<PackObject<xid::Id>>, // payer, all payers if not given
+    pub kind: Option<String>,
+    #[validate(range(min = 0))]
+    pub start: Option<i64>, // unix ms, inclusive
+    #[validate(range(min = 0))]
+    pub end: Option<i64>, // unix ms, inclusive, default to now
+    #[validate(range(min = 1))]
+    pub limit: Option<u64>, // capped by the max rows of the conf
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct ExportTransactionRow {
+    #[schema(value_type = super::openapi::Xid)]
+    pub uid: PackObject<xid::Id>,
+    #[serde(flatten)]
+    pub txn: TransactionOutput,
+    // the coin of the amounts, missing in exports of older servers.
+    #[serde(default)]
+    pub denomination: money::Denomination,
+}
+
+// streams transactions as NDJSON, or as CBOR sequence (RFC 8742) if CBOR is accepted.
+// an error after the response started aborts the stream.
+#[utoipa::path(
+    get,
+    path = "/v1/admin/export/transactions",
+    tag = "admin",
+    params(QueryExportTransactions),
+    responses(
+        (status = 200, body = ExportTransactionRow, content_type = "application/x-ndjson"),
+        (status = 200, body = ExportTransactionRow, content_type = "application/cbor-seq"),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn transactions(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QueryExportTransactions>,
+) -> Result<Response, HTTPError> {
+    input.validate()?;
+
+    let kind = match &input.kind {
+        Some(kind) => Some(
+            db::TransactionKind::from_str(kind)
+                .map_err(|_| HTTPError::new(400, format!("Invalid transaction kind {}", kind)))?,
+        ),
+        None => None,
+    };
+    let now = unix_ms() as i64;
+    let start = input.start.unwrap_or(0);
+    let end = input.end.unwrap_or(now);
+    if start > end {
+        return Err(HTTPError::new(
+            400,
+            format!("Invalid time range [{}, {}]", start, end),
+        ));
+    }
+    let max_rows = EXPORT_MAX_ROWS.load(Ordering::Relaxed);
+    let limit = input.limit.unwrap_or(max_rows).min(max_rows);
+    let uid = input.uid.as_ref().map(|v| *v.unwrap_ref());
+
+    ctx.set_kvs(vec![
+        ("action", "export_transactions".into()),
+        ("uid", uid.map(|v| v.to_string()).unwrap_or_default().into()),
+        ("kind", input.kind.clone().unwrap_or_default().into()),
+        ("start", start.into()),
+        ("end", end.into()),
+        ("limit", limit.into()),
+    ])
+    .await;
+
+    if !acquire(now) {
+        return Err(HTTPError::new(
+            429,
+            "Too many export requests, retry later".to_string(),
+        ));
+    }
+
+    let rows =
+        db::Transaction::stream(&app.scylla, uid, xid_at(start, 0), xid_at(end, 255)).await?;
+
+    let denomination = money::denomination();
+    let is_cbor = matches!(to, PackObject::Cbor(_));
+    let content_type = if is_cbor {
+        "application/cbor-seq"
+    } else {
+        "application/x-ndjson"
+    };
+    let body = rows
+        .filter(move |doc| {
+            let keep = match (doc, &kind) {
+                (Ok(doc), Some(kind)) => doc.kind == kind.as_ref(),
+                _ => true,
+            };
+            async move { keep }
+        })
+        .take(limit as usize)
+        .map(move |doc| -> Result<Bytes, HTTPError> {
+            let doc = doc.map_err(HTTPError::from)?;
+            let row = ExportTransactionRow {
+                uid: to.with(doc.uid),
+                txn: TransactionOutput::from(doc, &to),
+                denomination: denomination.clone(),
+            };
+            let data = if is_cbor {
+                cbor_to_vec(&row)?
+            } else {
+                let mut data =
+                    serde_json::to_vec(&row).map_err(|err| HTTPError::new(500, err.to_string()))?;
+                data.push(b'\n');
+                data
+            };
+            Ok(Bytes::from(data))
+        });
+
+    Ok((
+        [(header::CONTENT_TYPE, content_type)],
+        StreamBody::new(body),
+    )
+        .into_response())
+}
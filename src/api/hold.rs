@@ -0,0 +1,249 @@
+use axum::{extract::State, Extension};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::ToSchema;
+use validator::Validate;
+
+use axum_web::context::ReqContext;
+use axum_web::erring::{HTTPError, SuccessResponse};
+use axum_web::object::PackObject;
+
+use crate::api::{check_payload, transaction::TransactionOutput, AppState};
+use crate::db::{self, SYS_ID};
+
+const DEFAULT_HOLD_TTL_SECS: i64 = 600;
+
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
+pub struct HoldInput {
+    #[schema(value_type = super::openapi::Xid)]
+    #[validate(custom = "super::validate_uid")]
+    pub uid: PackObject<xid::Id>,
+    // checked by the spend kind's max amount.
+    #[validate(range(min = 1))]
+    pub amount: i64,
+    // the hold is released automatically after ttl_secs, default to 600.
+    #[validate(range(min = 1, max = 3600))]
+    pub ttl_secs: Option<i64>,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct HoldOutput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub uid: PackObject<xid::Id>,
+    #[schema(value_type = super::openapi::Xid)]
+    pub id: PackObject<xid::Id>,
+    pub amount: i64,
+    pub description: String,
+    pub created_at: i64,
+    pub expire_at: i64,
+}
+
+impl HoldOutput {
+    pub fn from<T>(val: db::WalletHold, to: &PackObject<T>) -> Self {
+        Self {
+            uid: to.with(val.uid),
+            id: to.with(val.id),
+            amount: val.amount,
+            description: val.description,
+            created_at: val.created_at,
+            expire_at: val.expire_at,
+        }
+    }
+}
+
+// places a hold on the wallet's funds, it reduces the available balance of the wallet
+// until it is captured, released or expired.
+#[utoipa::path(
+    post,
+    path = "/v1/wallet/hold",
+    tag = "wallet",
+    request_body = HoldInput,
+    responses(
+        (status = 200, body = super::openapi::HoldResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn hold(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<HoldInput>,
+) -> Result<PackObject<SuccessResponse<HoldOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    db::TransactionKind::Spend.check_amount(input.amount)?;
+
+    let uid = input.uid.unwrap();
+    ctx.set_kvs(vec![
+        ("action", "hold".into()),
+        ("uid", uid.to_string().into()),
+        ("amount", input.amount.into()),
+    ])
+    .await;
+
+    // holds are soft reservations, concurrent holds are checked against the same balance.
+    let mut wallet = db::Wallet::with_pk(uid);
+    wallet.get_one(&app.scylla).await?;
+    wallet.verify_checksum(&app.mac)?;
+    wallet._held = db::WalletHold::held_amount(&app.scylla, uid, None).await?;
+    db::TransactionKind::Spend.sub_payer_balance(&mut wallet, input.amount)?;
+
+    let mut doc = db::WalletHold {
+        uid,
+        amount: input.amount,
+        description: input.description.unwrap_or_default(),
+        ..Default::default()
+    };
+    doc.save(&app.scylla, input.ttl_secs.unwrap_or(DEFAULT_HOLD_TTL_SECS))
+        .await?;
+    ctx.set("id", doc.id.to_string().into()).await;
+
+    Ok(to.with(SuccessResponse::new(HoldOutput::from(doc, &to))))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
+pub struct CaptureInput {
+    #[schema(value_type = super::openapi::Xid)]
+    #[validate(custom = "super::validate_uid")]
+    pub uid: PackObject<xid::Id>,
+    #[schema(value_type = super::openapi::Xid)]
+    pub id: PackObject<xid::Id>,
+    // default to the hold's amount, it can not exceed the hold's amount.
+    #[validate(range(min = 1))]
+    pub amount: Option<i64>,
+    pub description: Option<String>,
+    // self-described CBOR, limited by the max payload size.
+    #[schema(value_type = Option<super::openapi::Base64Url>)]
+    pub payload: Option<PackObject<Vec<u8>>>,
+}
+
+// captures the hold as a committed spend transaction, the rest of the hold is released.
+#[utoipa::path(
+    post,
+    path = "/v1/wallet/hold/capture",
+    tag = "wallet",
+    request_body = CaptureInput,
+    responses(
+        (status = 200, body = super::openapi::TransactionResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn capture(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<CaptureInput>,
+) -> Result<PackObject<SuccessResponse<TransactionOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    let uid = input.uid.unwrap();
+    let id = input.id.unwrap();
+    ctx.set_kvs(vec![
+        ("action", "capture_hold".into()),
+        ("uid", uid.to_string().into()),
+        ("id", id.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::WalletHold::with_pk(uid, id);
+    doc.get_one(&app.scylla).await?;
+    if doc.is_frozen() {
+        return Err(HTTPError::new(403, format!("Hold {} is frozen", id)));
+    }
+    let amount = input.amount.unwrap_or(doc.amount);
+    if amount > doc.amount {
+        return Err(HTTPError::new(
+            400,
+            format!("Capture amount {} exceeds hold {}", amount, doc.amount),
+        ));
+    }
+    ctx.set("amount", amount.into()).await;
+
+    let mut txn = db::Transaction::with_uid(uid);
+    txn._hold = Some(id);
+    txn.description = input.description.unwrap_or_else(|| doc.description.clone());
+    if let Some(payload) = input.payload {
+        let payload = payload.unwrap();
+        check_payload(&payload)?;
+        txn.payload = payload;
+    }
+    txn.prepare(
+        &app.scylla,
+        &app.mac,
+        SYS_ID,
+        db::TransactionKind::Spend,
+        amount,
+    )
+    .await?;
+    ctx.set("txn", txn.id.to_string().into()).await;
+
+    // the hold is removed after the spend prepared, so that its funds are always reserved.
+    // it may be captured or released concurrently, or expired.
+    if !doc.release(&app.scylla).await? {
+        txn.cancel(&app.scylla, &app.mac).await?;
+        return Err(HTTPError::new(
+            409,
+            format!("Hold {} has been captured, released or expired", id),
+        ));
+    }
+
+    txn.commit(&app.scylla, &app.mac).await?;
+    Ok(to.with(SuccessResponse::new(TransactionOutput::from(txn, &to))))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
+pub struct ReleaseInput {
+    #[schema(value_type = super::openapi::Xid)]
+    #[validate(custom = "super::validate_uid")]
+    pub uid: PackObject<xid::Id>,
+    #[schema(value_type = super::openapi::Xid)]
+    pub id: PackObject<xid::Id>,
+}
+
+// returns false if the hold has been captured, released or expired.
+#[utoipa::path(
+    post,
+    path = "/v1/wallet/hold/release",
+    tag = "wallet",
+    request_body = ReleaseInput,
+    responses(
+        (status = 200, body = super::openapi::BoolResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn release(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<ReleaseInput>,
+) -> Result<PackObject<SuccessResponse<bool>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    let uid = input.uid.unwrap();
+    let id = input.id.unwrap();
+    ctx.set_kvs(vec![
+        ("action", "release_hold".into()),
+        ("uid", uid.to_string().into()),
+        ("id", id.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::WalletHold::with_pk(uid, id);
+    match doc.get_one(&app.scylla).await {
+        Ok(_) if doc.is_frozen() => {
+            return Err(HTTPError::new(403, format!("Hold {} is frozen", id)));
+        }
+        Ok(_) => {}
+        Err(err) => {
+            let err: HTTPError = err.into();
+            // released, captured or expired.
+            if err.code == 404 {
+                return Ok(to.with(SuccessResponse::new(false)));
+            }
+            return Err(err);
+        }
+    }
+    let res = doc.release(&app.scylla).await?;
+    Ok(to.with(SuccessResponse::new(res)))
+}
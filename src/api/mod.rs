@@ -1,34 +1,80 @@
-use axum::extract::State;
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    Extension,
+};
+use scylla_orm::{CqlField, FieldSet};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::fmt::Write;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use utoipa::{IntoParams, ToSchema};
 use validator::{Validate, ValidationError};
 
+use axum_web::context::{unix_ms, ReqContext};
+use axum_web::erring::{HTTPError, SuccessResponse};
 use axum_web::object::{cbor_from_slice, cbor_to_vec, PackObject};
 
+use crate::alert;
+use crate::crypto;
 use crate::db::{self};
+use crate::moderation;
+use crate::stripe;
 
+pub mod adjustment;
+pub mod api_key;
+pub mod audit;
+pub mod budget;
 pub mod charge;
 pub mod currency;
 pub mod customer;
+pub mod dispute;
+pub mod export;
+pub mod hold;
+pub mod openapi;
+#[cfg(feature = "penny-test")]
+pub mod penny_test;
+pub mod pool;
+pub mod redpacket;
+pub mod subscription;
 pub mod transaction;
+pub mod v2;
 pub mod wallet;
+pub mod wallet_pref;
+pub mod webhook;
+pub mod withdrawal;
 
 pub const APP_NAME: &str = env!("CARGO_PKG_NAME");
 pub const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+// version of the CBOR encoding of the outputs pinned by tests/golden/cbor_schema.json.
+// bump it when a field of an output is renamed, removed or changes its type, then
+// regenerate the golden file with `UPDATE_GOLDEN=1 cargo test --test cbor_schema`.
+pub const OUTPUT_SCHEMA_VERSION: u32 = 1;
+
+// header of the calling service (auth principal) resolved by the gateway.
+pub const SERVICE_HEADER: &str = "x-auth-service";
+// max allowed clock skew between the server and ScyllaDB.
+const MAX_CLOCK_SKEW_MS: i64 = 3000;
+
 #[derive(Clone)]
 pub struct AppState {
     pub scylla: Arc<db::scylladb::ScyllaDB>,
     pub mac: Arc<db::HMacTag>,
+    pub tenant: String,                      // empty for the default tenant
+    pub stripe: Option<Arc<stripe::Client>>, // None if Stripe checkout is not configured
+    pub moderation: Option<Arc<moderation::Moderation>>, // None if moderation is not configured
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct AppVersion {
     pub name: String,
     pub version: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct AppInfo {
     // https://docs.rs/scylla/latest/scylla/struct.Metrics.html
     pub scylla_latency_avg_ms: u64,
@@ -39,8 +85,63 @@ pub struct AppInfo {
     pub scylla_errors_iter_num: u64,
     pub scylla_queries_iter_num: u64,
     pub scylla_retries_num: u64,
+    pub scylla_breaker_state: String, // "closed", "open" or "half_open"
+    pub scylla_breaker_opened_num: u64,
+    pub scylla_breaker_rejected_num: u64,
+    pub lwt_calls_num: u64,
+    pub lwt_retries_num: u64,
+    pub lwt_exhausted_num: u64,
+    pub load_shedding: bool,
+    pub load_shed_num: u64,
+    pub load_shed_rejected_num: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checks: Option<HealthChecks>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct HealthChecks {
+    pub scylla: CheckStatus,
+    pub hmac: CheckStatus,
+    pub clock: CheckStatus,
+}
+
+#[derive(Default, Serialize, Deserialize, ToSchema)]
+pub struct CheckStatus {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl CheckStatus {
+    fn ok() -> Self {
+        Self {
+            ok: true,
+            error: None,
+        }
+    }
+
+    fn err(error: String) -> Self {
+        Self {
+            ok: false,
+            error: Some(error),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryHealthz {
+    pub deep: Option<bool>,
 }
 
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "app",
+    responses(
+        (status = 200, body = AppVersion)
+    )
+)]
 pub async fn version(to: PackObject<()>, State(_): State<Arc<AppState>>) -> PackObject<AppVersion> {
     to.with(AppVersion {
         name: APP_NAME.to_string(),
@@ -48,9 +149,37 @@ pub async fn version(to: PackObject<()>, State(_): State<Arc<AppState>>) -> Pack
     })
 }
 
-pub async fn healthz(to: PackObject<()>, State(app): State<Arc<AppState>>) -> PackObject<AppInfo> {
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "app",
+    params(QueryHealthz),
+    responses(
+        (status = 200, body = AppInfo),
+        (status = 503, body = AppInfo)
+    )
+)]
+pub async fn healthz(
+    to: PackObject<()>,
+    State(app): State<Arc<AppState>>,
+    Query(input): Query<QueryHealthz>,
+) -> (StatusCode, PackObject<AppInfo>) {
+    let checks = if input.deep.unwrap_or_default() {
+        Some(deep_checks(&app).await)
+    } else {
+        None
+    };
+    let breaker = app.scylla.breaker_status();
+    let shed = db::shed_status();
+    let status = match &checks {
+        _ if breaker.state == "open" => StatusCode::SERVICE_UNAVAILABLE,
+        Some(c) if !(c.scylla.ok && c.hmac.ok && c.clock.ok) => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::OK,
+    };
+
     let m = app.scylla.metrics();
-    to.with(AppInfo {
+    let lwt = db::lwt_retry_metrics();
+    let info = AppInfo {
         scylla_latency_avg_ms: m.get_latency_avg_ms().unwrap_or(0),
         scylla_latency_p99_ms: m.get_latency_percentile_ms(99.0f64).unwrap_or(0),
         scylla_latency_p90_ms: m.get_latency_percentile_ms(90.0f64).unwrap_or(0),
@@ -59,49 +188,275 @@ pub async fn healthz(to: PackObject<()>, State(app): State<Arc<AppState>>) -> Pa
         scylla_errors_iter_num: m.get_errors_iter_num(),
         scylla_queries_iter_num: m.get_queries_iter_num(),
         scylla_retries_num: m.get_retries_num(),
-    })
+        scylla_breaker_state: breaker.state.to_string(),
+        scylla_breaker_opened_num: breaker.opened,
+        scylla_breaker_rejected_num: breaker.rejected,
+        lwt_calls_num: lwt.calls,
+        lwt_retries_num: lwt.retries,
+        lwt_exhausted_num: lwt.exhausted,
+        load_shedding: shed.shedding,
+        load_shed_num: shed.shed,
+        load_shed_rejected_num: shed.rejected,
+        checks,
+    };
+    (status, to.with(info))
+}
+
+// metrics of the tenant in the Prometheus text format.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "app",
+    responses(
+        (status = 200, body = String, content_type = "text/plain")
+    )
+)]
+pub async fn metrics(
+    State(app): State<Arc<AppState>>,
+) -> ([(header::HeaderName, &'static str); 1], String) {
+    let m = app.scylla.metrics();
+    let lwt = db::lwt_retry_metrics();
+    let shadow = db::shadow_metrics();
+    let breaker = app.scylla.breaker_status();
+    let shed = db::shed_status();
+    let mut out = String::new();
+    for (name, val) in [
+        ("scylla_queries_total", m.get_queries_num()),
+        ("scylla_errors_total", m.get_errors_num()),
+        ("scylla_retries_total", m.get_retries_num()),
+        ("scylla_breaker_opened_total", breaker.opened),
+        ("scylla_breaker_rejected_total", breaker.rejected),
+        ("lwt_calls_total", lwt.calls),
+        ("lwt_retries_total", lwt.retries),
+        ("lwt_exhausted_total", lwt.exhausted),
+        ("load_shed_total", shed.shed),
+        ("load_shed_rejected_total", shed.rejected),
+        ("shadow_writes_total", shadow.writes),
+        ("shadow_errors_total", shadow.errors),
+        ("shadow_samples_total", shadow.samples),
+        ("shadow_mismatches_total", shadow.mismatches),
+    ] {
+        let _ = writeln!(out, "# TYPE walletbase_{} counter", name);
+        let _ = writeln!(out, "walletbase_{} {}", name, val);
+    }
+    let _ = writeln!(out, "# TYPE walletbase_load_shedding gauge");
+    let _ = writeln!(out, "walletbase_load_shedding {}", shed.shedding as u8);
+    let _ = writeln!(out, "# TYPE walletbase_scylla_breaker_state gauge");
+    for state in ["closed", "open", "half_open"] {
+        let _ = writeln!(
+            out,
+            "walletbase_scylla_breaker_state{{state=\"{}\"}} {}",
+            state,
+            (breaker.state == state) as u8
+        );
+    }
+
+    let mut wallet = db::Wallet::with_pk(db::SYS_ID);
+    match wallet.get_one(&app.scylla).await {
+        Ok(_) => {
+            let _ = writeln!(out, "# TYPE walletbase_system_wallet_balance gauge");
+            for (field, val) in [
+                ("award", wallet.award),
+                ("topup", wallet.topup),
+                ("income", wallet.income),
+            ] {
+                let _ = writeln!(
+                    out,
+                    "walletbase_system_wallet_balance{{field=\"{}\"}} {}",
+                    field, val
+                );
+            }
+
+            let thresholds = alert::thresholds();
+            let breaches = alert::breaches(&wallet, &thresholds);
+            let _ = writeln!(out, "# TYPE walletbase_system_wallet_threshold gauge");
+            for (field, val) in &thresholds {
+                let _ = writeln!(
+                    out,
+                    "walletbase_system_wallet_threshold{{field=\"{}\"}} {}",
+                    field, val
+                );
+            }
+            let _ = writeln!(out, "# TYPE walletbase_system_wallet_breached gauge");
+            for (field, _) in &thresholds {
+                let breached = breaches.iter().any(|b| b.field == *field);
+                let _ = writeln!(
+                    out,
+                    "walletbase_system_wallet_breached{{field=\"{}\"}} {}",
+                    field, breached as u8
+                );
+            }
+        }
+        Err(err) => {
+            log::error!(target: "metrics", "load system wallet failed: {}", err);
+        }
+    }
+
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], out)
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct SchemaOutput {
+    pub keyspace: String,
+    pub drifted: bool, // any table is missing or has missing/extra columns
+    pub tables: Vec<TableSchemaOutput>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct TableSchemaOutput {
+    pub table: String,
+    pub exists: bool,
+    pub columns: Vec<ColumnOutput>,
+    pub missing: Vec<String>, // model fields without a column
+    pub extra: Vec<String>,   // columns without a model field
 }
 
-pub fn get_fields(fields: Option<String>) -> Vec<String> {
-    if fields.is_none() {
-        return vec![];
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct ColumnOutput {
+    pub name: String,
+    pub kind: String, // partition_key, clustering, regular or static
+    #[serde(rename = "type")]
+    pub cql_type: String,
+}
+
+// the actual schema of the model tables in the keyspace, compared with the models of the running version.
+#[utoipa::path(
+    get,
+    path = "/v1/admin/schema",
+    tag = "admin",
+    responses(
+        (status = 200, body = openapi::SchemaResponse),
+        (status = "default", body = openapi::ErrorResponse)
+    )
+)]
+pub async fn schema(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+) -> Result<PackObject<SuccessResponse<SchemaOutput>>, HTTPError> {
+    ctx.set("action", "admin_schema".into()).await;
+
+    let res = db::schema::inspect(&app.scylla).await?;
+    let drifted = res.iter().any(|t| t.drifted());
+    ctx.set("drifted", drifted.into()).await;
+
+    Ok(to.with(SuccessResponse::new(SchemaOutput {
+        keyspace: app.scylla.keyspace().to_string(),
+        drifted,
+        tables: res
+            .into_iter()
+            .map(|t| TableSchemaOutput {
+                table: t.table,
+                exists: t.exists,
+                columns: t
+                    .columns
+                    .into_iter()
+                    .map(|c| ColumnOutput {
+                        name: c.name,
+                        kind: c.kind,
+                        cql_type: c.cql_type,
+                    })
+                    .collect(),
+                missing: t.missing,
+                extra: t.extra,
+            })
+            .collect(),
+    })))
+}
+
+async fn deep_checks(app: &AppState) -> HealthChecks {
+    let hmac = if app.mac.is_loaded() {
+        CheckStatus::ok()
+    } else {
+        CheckStatus::err("wallet key not loaded".to_string())
+    };
+
+    let (scylla, clock) = match app.scylla.now_ms().await {
+        Ok(db_now) => {
+            let skew = unix_ms() as i64 - db_now;
+            let clock = if skew.abs() <= MAX_CLOCK_SKEW_MS {
+                CheckStatus::ok()
+            } else {
+                CheckStatus::err(format!("clock skew {}ms with scylla", skew))
+            };
+            (CheckStatus::ok(), clock)
+        }
+        Err(err) => (
+            CheckStatus::err(err.to_string()),
+            CheckStatus::err("unknown, scylla unavailable".to_string()),
+        ),
+    };
+
+    HealthChecks {
+        scylla,
+        hmac,
+        clock,
     }
-    let fields = fields.unwrap();
+}
+
+// parses the comma separated fields of the model once, empty for all the fields.
+pub fn get_fields<F: CqlField>(fields: Option<String>) -> Result<FieldSet<F>, HTTPError> {
+    let fields = fields.unwrap_or_default();
     let fields = fields.trim();
     if fields.is_empty() {
-        return vec![];
+        return Ok(FieldSet::new());
     }
-    fields.split(',').map(|s| s.trim().to_string()).collect()
+    let names: Vec<&str> = fields.split(',').map(|s| s.trim()).collect();
+    parse_fields(&names)
+}
+
+// parses the fields of the model from `Pagination`, empty for all the fields.
+pub fn get_list_fields<F: CqlField>(fields: Option<Vec<String>>) -> Result<FieldSet<F>, HTTPError> {
+    parse_fields(&fields.unwrap_or_default())
+}
+
+fn parse_fields<F: CqlField, S: AsRef<str>>(names: &[S]) -> Result<FieldSet<F>, HTTPError> {
+    FieldSet::parse(names).map_err(|field| HTTPError::new(400, format!("Invalid field: {}", field)))
 }
 
-#[derive(Debug, Deserialize, Validate)]
+#[derive(Debug, Deserialize, Serialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct QueryUid {
+    #[param(value_type = openapi::Xid)]
+    #[validate(custom = "validate_uid")]
     pub uid: PackObject<xid::Id>,
     pub fields: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Validate)]
+#[derive(Debug, Deserialize, Serialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct QueryUidId {
+    // transactions of the system, e.g. topups, are addressed by SYS_ID.
+    #[param(value_type = openapi::Xid)]
+    #[validate(custom = "validate_id")]
     pub uid: PackObject<xid::Id>,
+    #[param(value_type = openapi::Xid)]
+    #[validate(custom = "validate_id")]
     pub id: PackObject<xid::Id>,
     pub fields: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Validate)]
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
 pub struct Pagination {
+    #[schema(value_type = openapi::Xid)]
+    #[validate(custom = "validate_uid")]
     pub uid: PackObject<xid::Id>,
+    #[schema(value_type = Option<openapi::Base64Url>)]
     pub page_token: Option<PackObject<Vec<u8>>>,
     #[validate(range(min = 2, max = 1000))]
     pub page_size: Option<u16>,
     #[validate(range(min = -1, max = 2))]
     pub status: Option<i8>,
     pub kind: Option<String>,
+    pub livemode: Option<bool>, // charges only, false for provider test mode charges
+    pub order: Option<String>,  // credits only, "asc" or "desc", default to "desc"
     pub fields: Option<Vec<String>>,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 pub struct TransactionPayload {
     pub kind: String,
+    #[schema(value_type = openapi::Xid)]
     pub id: PackObject<xid::Id>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub provider: Option<String>,
@@ -111,17 +466,61 @@ pub struct TransactionPayload {
     pub amount: Option<i64>,
 }
 
-pub fn token_to_xid(page_token: &Option<PackObject<Vec<u8>>>) -> Option<xid::Id> {
-    match page_token.as_ref().map(|v| v.unwrap_ref()) {
-        Some(v) => cbor_from_slice::<PackObject<xid::Id>>(v)
-            .ok()
-            .map(|v| v.unwrap()),
-        _ => None,
+// a page token is the CBOR encoded cursor followed by a HMAC tag binding the uid and the
+// listing kind, so that it can not be forged or replayed on other listings.
+// listings across all users use SYS_ID.
+pub fn token_to_xid(
+    mac: &db::HMacTag,
+    uid: &xid::Id,
+    kind: &str,
+    page_token: &Option<PackObject<Vec<u8>>>,
+) -> Result<Option<xid::Id>, HTTPError> {
+    let v = match page_token.as_ref().map(|v| v.unwrap_ref()) {
+        Some(v) if !v.is_empty() => v,
+        _ => return Ok(None),
+    };
+
+    let invalid = || HTTPError::new(400, "Invalid page_token".to_string());
+    if v.len() <= db::CURSOR_TAG_LEN {
+        return Err(invalid());
+    }
+    let (data, tag) = v.split_at(v.len() - db::CURSOR_TAG_LEN);
+    let id = cbor_from_slice::<PackObject<xid::Id>>(data)
+        .map_err(|_| invalid())?
+        .unwrap();
+    if !mac.verify_cursor(uid, kind, &id, tag) {
+        return Err(invalid());
+    }
+    Ok(Some(id))
+}
+
+pub fn token_from_xid(
+    mac: &db::HMacTag,
+    uid: &xid::Id,
+    kind: &str,
+    id: xid::Id,
+) -> Option<Vec<u8>> {
+    let mut data = cbor_to_vec(&PackObject::Cbor(id)).ok()?;
+    data.extend_from_slice(&mac.tag_cursor(uid, kind, &id));
+    Some(data)
+}
+
+// rejects the MIN_ID and MAX_ID sentinels of range queries.
+pub(crate) fn validate_id(id: &PackObject<xid::Id>) -> Result<(), ValidationError> {
+    let id = id.unwrap_ref();
+    if *id == db::MIN_ID || *id == db::MAX_ID {
+        return Err(ValidationError::new("reserved id"));
     }
+    Ok(())
 }
 
-pub fn token_from_xid(id: xid::Id) -> Option<Vec<u8>> {
-    cbor_to_vec(&PackObject::Cbor(id)).ok()
+// rejects SYS_ID and the sentinels as a user's uid on user-facing routes,
+// admin routes have their own inputs and are not restricted.
+pub(crate) fn validate_uid(uid: &PackObject<xid::Id>) -> Result<(), ValidationError> {
+    if *uid.unwrap_ref() == db::SYS_ID {
+        return Err(ValidationError::new("reserved uid"));
+    }
+    validate_id(uid)
 }
 
 static PROVIDERS: [&str; 1] = ["stripe"];
@@ -132,3 +531,187 @@ pub(crate) fn validate_provider(provider: &str) -> Result<(), ValidationError> {
     }
     Err(ValidationError::new("unsupported provider"))
 }
+
+#[derive(Deserialize)]
+struct ProviderObject {
+    livemode: Option<bool>,
+    charges_enabled: Option<bool>,
+    payouts_enabled: Option<bool>,
+}
+
+// livemode of the provider's object, e.g. Stripe charges and customers, in CBOR or JSON.
+fn livemode_of(payload: &[u8]) -> Option<bool> {
+    if let Ok(obj) = cbor_from_slice::<ProviderObject>(payload) {
+        if obj.livemode.is_some() {
+            return obj.livemode;
+        }
+    }
+    serde_json::from_slice::<ProviderObject>(payload)
+        .ok()
+        .and_then(|obj| obj.livemode)
+}
+
+// capabilities of the provider's object, e.g. Stripe connected accounts, in CBOR or JSON,
+// as (charges_enabled, payouts_enabled). None if the object has no such flags.
+pub(crate) fn capabilities_of(payload: &[u8]) -> (Option<bool>, Option<bool>) {
+    let has_flags =
+        |obj: &ProviderObject| obj.charges_enabled.is_some() || obj.payouts_enabled.is_some();
+    match cbor_from_slice::<ProviderObject>(payload)
+        .ok()
+        .filter(has_flags)
+        .or_else(|| serde_json::from_slice::<ProviderObject>(payload).ok())
+    {
+        Some(obj) => (obj.charges_enabled, obj.payouts_enabled),
+        None => (None, None),
+    }
+}
+
+// livemode from the input or the provider payload, default to the deployment's livemode.
+pub(crate) fn resolve_livemode(
+    livemode: Option<bool>,
+    payload: Option<&[u8]>,
+) -> Result<bool, HTTPError> {
+    match (livemode, payload.and_then(livemode_of)) {
+        (Some(a), Some(b)) if a != b => Err(HTTPError::new(
+            400,
+            format!("livemode {} mismatch with provider payload {}", a, b),
+        )),
+        (Some(v), _) | (None, Some(v)) => Ok(v),
+        (None, None) => Ok(db::livemode()),
+    }
+}
+
+// max size of a transaction payload in bytes, it is set from conf at startup.
+static MAX_PAYLOAD_SIZE: AtomicUsize = AtomicUsize::new(16 * 1024);
+
+pub fn set_max_payload_size(size: usize) {
+    MAX_PAYLOAD_SIZE.store(size, Ordering::Relaxed);
+}
+
+// transaction payloads from the input must be self-described CBOR (tagged with crypto::CBOR_TAG),
+// they are stored as is, never truncated.
+pub(crate) fn check_payload(payload: &[u8]) -> Result<(), HTTPError> {
+    let max_size = MAX_PAYLOAD_SIZE.load(Ordering::Relaxed);
+    if payload.len() > max_size {
+        return Err(HTTPError::new(
+            413,
+            format!(
+                "payload size {} exceeds the limit {}",
+                payload.len(),
+                max_size
+            ),
+        ));
+    }
+    if payload.len() <= crypto::CBOR_TAG.len() || payload[..3] != crypto::CBOR_TAG {
+        return Err(HTTPError::new(
+            400,
+            "payload must be self-described CBOR".to_string(),
+        ));
+    }
+    cbor_from_slice::<ciborium::value::Value>(crypto::unwrap_cbor_tag(payload))
+        .map_err(|err| HTTPError::new(400, format!("invalid CBOR payload: {}", err)))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rejects_uid<T: Validate>(input: T) -> bool {
+        match input.validate() {
+            Ok(()) => false,
+            Err(errs) => errs.field_errors().contains_key("uid"),
+        }
+    }
+
+    #[test]
+    fn validate_uid_works() {
+        assert!(validate_uid(&PackObject::Cbor(xid::new())).is_ok());
+
+        for id in [db::SYS_ID, db::MIN_ID, db::MAX_ID] {
+            let uid = PackObject::Cbor(id);
+            let id = PackObject::Cbor(xid::new());
+            assert!(validate_uid(&uid).is_err());
+
+            assert!(rejects_uid(QueryUid {
+                uid: uid.clone(),
+                fields: None,
+            }));
+            assert!(rejects_uid(Pagination {
+                uid: uid.clone(),
+                page_token: None,
+                page_size: None,
+                status: None,
+                kind: None,
+                livemode: None,
+                order: None,
+                fields: None,
+            }));
+            assert!(rejects_uid(hold::HoldInput {
+                uid: uid.clone(),
+                amount: 1,
+                ttl_secs: None,
+                description: None,
+            }));
+            assert!(rejects_uid(hold::CaptureInput {
+                uid: uid.clone(),
+                id: id.clone(),
+                amount: None,
+                description: None,
+                payload: None,
+            }));
+            assert!(rejects_uid(hold::ReleaseInput {
+                uid: uid.clone(),
+                id: id.clone(),
+            }));
+            assert!(rejects_uid(transaction::QueryPending {
+                uid: uid.clone(),
+                page_size: None,
+                page_token: None,
+                fields: None,
+            }));
+            assert!(rejects_uid(transaction::QueryAggregate {
+                uid: uid.clone(),
+                payee: None,
+                page_size: None,
+                page_token: None,
+            }));
+            assert!(rejects_uid(transaction::QueryAggregateIncome {
+                uid: uid.clone(),
+                payer: None,
+                page_size: None,
+                page_token: None,
+            }));
+            assert!(rejects_uid(subscription::QuerySubscription {
+                uid: uid.clone(),
+                payee: id.clone(),
+            }));
+            assert!(rejects_uid(customer::QueryCustomer {
+                uid: uid.clone(),
+                provider: "stripe".to_string(),
+                fields: None,
+            }));
+            assert!(rejects_uid(customer::QueryCustomerList {
+                uid: uid.clone(),
+                fields: None,
+            }));
+            assert!(rejects_uid(withdrawal::WithdrawInput {
+                uid: uid.clone(),
+                amount: 1,
+                description: None,
+                payload: None,
+            }));
+            assert!(rejects_uid(wallet_pref::PreferencesInput {
+                uid: uid.clone(),
+                display_currency: None,
+                locale: None,
+                reminders_opt_out: None,
+            }));
+            assert!(rejects_uid(webhook::WebhookInput {
+                uid: uid.clone(),
+                url: "https://example.com/webhook".to_string(),
+                secret: "0123456789abcdef".to_string(),
+            }));
+        }
+    }
+}
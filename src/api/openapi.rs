@@ -0,0 +1,363 @@
+use axum::extract::State;
+use serde::Serialize;
+use std::sync::Arc;
+use utoipa::{
+    openapi::{ObjectBuilder, RefOr, Schema, SchemaFormat, SchemaType},
+    OpenApi, ToSchema,
+};
+
+use crate::api::{self, AppState};
+
+// xid is a 12 bytes globally unique id, https://github.com/yiwen-ai/xid-rs
+// it is encoded as a 20 chars string in JSON, and as raw bytes in CBOR.
+pub struct Xid;
+
+impl<'s> ToSchema<'s> for Xid {
+    fn schema() -> (&'s str, RefOr<Schema>) {
+        (
+            "Xid",
+            ObjectBuilder::new()
+                .schema_type(SchemaType::String)
+                .format(Some(SchemaFormat::Custom("xid".to_string())))
+                .description(Some(
+                    "12 bytes xid, a 20 chars string in JSON, raw bytes in CBOR",
+                ))
+                .example(Some("9m4e2mr0ui3e8a215n4g".into()))
+                .into(),
+        )
+    }
+}
+
+// bytes are encoded as a base64url string without padding in JSON, and as raw bytes in CBOR.
+pub struct Base64Url;
+
+impl<'s> ToSchema<'s> for Base64Url {
+    fn schema() -> (&'s str, RefOr<Schema>) {
+        (
+            "Base64Url",
+            ObjectBuilder::new()
+                .schema_type(SchemaType::String)
+                .format(Some(SchemaFormat::Custom("base64url".to_string())))
+                .description(Some(
+                    "bytes, a base64url string without padding in JSON, raw bytes in CBOR",
+                ))
+                .into(),
+        )
+    }
+}
+
+// mirrors axum_web::erring::SuccessResponse for the document.
+#[derive(Serialize, ToSchema)]
+#[aliases(
+    CurrenciesResponse = SuccessResponse<Vec<api::currency::Currency>>,
+    AdjustmentResponse = SuccessResponse<api::adjustment::AdjustmentOutput>,
+    ApiKeyResponse = SuccessResponse<api::api_key::ApiKeyOutput>,
+    ApiKeysResponse = SuccessResponse<Vec<api::api_key::ApiKeyOutput>>,
+    AuditLogsResponse = SuccessResponse<Vec<api::audit::AuditLogOutput>>,
+    BudgetResponse = SuccessResponse<api::budget::BudgetOutput>,
+    BoolResponse = SuccessResponse<bool>,
+    ChargeResponse = SuccessResponse<api::charge::ChargeOutput>,
+    ChargesResponse = SuccessResponse<Vec<api::charge::ChargeOutput>>,
+    FormatAmountResponse = SuccessResponse<api::currency::FormatAmountOutput>,
+    CustomerResponse = SuccessResponse<api::customer::CustomerOutput>,
+    CustomersResponse = SuccessResponse<Vec<api::customer::CustomerOutput>>,
+    DisputeResponse = SuccessResponse<api::dispute::DisputeOutput>,
+    DisputesResponse = SuccessResponse<Vec<api::dispute::DisputeOutput>>,
+    HoldResponse = SuccessResponse<api::hold::HoldOutput>,
+    PoolResponse = SuccessResponse<api::pool::PoolOutput>,
+    ReceiptResponse = SuccessResponse<api::charge::ReceiptOutput>,
+    ReceiptKeyResponse = SuccessResponse<api::charge::ReceiptKeyOutput>,
+    ContributionResponse = SuccessResponse<api::pool::ContributionOutput>,
+    RedpacketResponse = SuccessResponse<api::redpacket::RedpacketOutput>,
+    RedpacketClaimResponse = SuccessResponse<api::redpacket::ClaimOutput>,
+    SubscriptionResponse = SuccessResponse<api::subscription::SubscriptionOutput>,
+    TransactionResponse = SuccessResponse<api::transaction::TransactionOutput>,
+    TransactionsResponse = SuccessResponse<Vec<api::transaction::TransactionOutput>>,
+    TransactionV2Response = SuccessResponse<api::v2::transaction::TransactionOutput>,
+    TransactionsV2Response = SuccessResponse<Vec<api::v2::transaction::TransactionOutput>>,
+    TxnHistoryResponse = SuccessResponse<Vec<api::transaction::TxnHistoryOutput>>,
+    AggregatesResponse = SuccessResponse<Vec<api::transaction::AggregateOutput>>,
+    WalletResponse = SuccessResponse<api::wallet::WalletOutput>,
+    DenominationResponse = SuccessResponse<crate::money::Denomination>,
+    SimulationResponse = SuccessResponse<api::wallet::SimulationOutput>,
+    SystemStatsResponse = SuccessResponse<api::wallet::SystemStatsOutput>,
+    DailyStatsResponse = SuccessResponse<api::wallet::DailyStatsOutput>,
+    LiabilitiesResponse = SuccessResponse<Vec<api::wallet::LiabilityOutput>>,
+    SchemaResponse = SuccessResponse<api::SchemaOutput>,
+    IntegrityResponse = SuccessResponse<Vec<api::wallet::IntegrityOutput>>,
+    BackfillChecksumResponse = SuccessResponse<api::wallet::BackfillChecksumOutput>,
+    CreditsResponse = SuccessResponse<Vec<api::wallet::CreditOutput>>,
+    CreditSummariesResponse = SuccessResponse<Vec<api::wallet::CreditSummaryOutput>>,
+    WeeklySummaryResponse = SuccessResponse<api::wallet::WeeklySummaryOutput>,
+    PreferencesResponse = SuccessResponse<api::wallet_pref::PreferencesOutput>,
+    WebhookResponse = SuccessResponse<api::webhook::WebhookOutput>,
+    WithdrawalResponse = SuccessResponse<api::withdrawal::WithdrawalOutput>,
+    WithdrawalsResponse = SuccessResponse<Vec<api::withdrawal::WithdrawalOutput>>
+)]
+pub struct SuccessResponse<T> {
+    pub total_size: Option<u64>,
+    #[schema(value_type = Option<Base64Url>)]
+    pub next_page_token: Option<Vec<u8>>,
+    pub result: T,
+}
+
+// mirrors axum_web::erring::ErrorResponse for the document.
+#[derive(Serialize, ToSchema)]
+pub struct ErrorResponse {
+    pub error: HTTPError,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct HTTPError {
+    pub code: u16,
+    pub message: String,
+    #[schema(value_type = Option<Object>)]
+    pub data: Option<serde_json::Value>,
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "walletbase",
+        description = "Wallet service of yiwen.ai. Request and response bodies are JSON, or CBOR with `Content-Type: application/cbor` and `Accept: application/cbor`."
+    ),
+    paths(
+        api::version,
+        api::healthz,
+        api::metrics,
+        api::currency::currencies,
+        api::currency::format_amount,
+        api::wallet::get,
+        api::wallet::denomination,
+        api::wallet::list_credits,
+        api::wallet::credits_summary,
+        api::wallet::weekly_summary,
+        api::wallet::award,
+        api::wallet::spend,
+        api::wallet::sponsor,
+        api::wallet::subscribe,
+        api::wallet::simulate,
+        api::wallet_pref::get,
+        api::wallet_pref::update,
+        api::webhook::get,
+        api::webhook::update,
+        api::webhook::delete,
+        api::api_key::create,
+        api::api_key::list,
+        api::api_key::revoke,
+        api::withdrawal::withdraw,
+        api::hold::hold,
+        api::hold::capture,
+        api::hold::release,
+        api::redpacket::create,
+        api::redpacket::get,
+        api::redpacket::claim,
+        api::subscription::create,
+        api::subscription::get,
+        api::subscription::cancel,
+        api::charge::create,
+        api::charge::get,
+        api::charge::update,
+        api::charge::list,
+        api::charge::complete,
+        api::charge::refund,
+        api::transaction::get,
+        api::transaction::first_from_system,
+        api::transaction::list_children,
+        api::transaction::list_pending,
+        api::transaction::list_outgo,
+        api::transaction::list_income,
+        api::transaction::aggregate,
+        api::transaction::aggregate_income,
+        api::transaction::commit,
+        api::transaction::cancel,
+        api::dispute::dispute,
+        api::v2::transaction::get,
+        api::v2::transaction::first_from_system,
+        api::v2::transaction::list_children,
+        api::v2::transaction::list_pending,
+        api::v2::transaction::list_outgo,
+        api::v2::transaction::list_income,
+        api::v2::transaction::commit,
+        api::v2::transaction::cancel,
+        api::customer::upsert,
+        api::customer::get,
+        api::customer::list,
+        api::customer::get_by_customer,
+        api::customer::delete,
+        api::pool::create,
+        api::pool::get,
+        api::pool::contribute,
+        api::pool::finalize,
+        api::budget::create,
+        api::budget::get,
+        api::budget::update,
+        api::budget::delete,
+        api::charge::list_by_day,
+        api::wallet::update_max_overdraw,
+        api::wallet::close,
+        api::wallet::integrity,
+        api::wallet::backfill_checksum,
+        api::wallet::system_stats,
+        api::wallet::daily_stats,
+        api::wallet::liabilities,
+        api::schema,
+        api::export::transactions,
+        api::adjustment::adjust,
+        api::adjustment::get,
+        api::adjustment::approve,
+        api::withdrawal::get,
+        api::withdrawal::list_pending,
+        api::withdrawal::review,
+        api::dispute::get,
+        api::dispute::list_pending,
+        api::dispute::resolve,
+        api::audit::list,
+        api::transaction::history,
+    ),
+    components(schemas(
+        Xid,
+        Base64Url,
+        ErrorResponse,
+        HTTPError,
+        CurrenciesResponse,
+        AdjustmentResponse,
+        ApiKeyResponse,
+        ApiKeysResponse,
+        AuditLogsResponse,
+        BudgetResponse,
+        BoolResponse,
+        ChargeResponse,
+        ChargesResponse,
+        CustomerResponse,
+        CustomersResponse,
+        FormatAmountResponse,
+        DisputeResponse,
+        DisputesResponse,
+        HoldResponse,
+        PoolResponse,
+        ReceiptResponse,
+        ReceiptKeyResponse,
+        ContributionResponse,
+        TransactionResponse,
+        TransactionsResponse,
+        TransactionV2Response,
+        TransactionsV2Response,
+        TxnHistoryResponse,
+        AggregatesResponse,
+        WalletResponse,
+        DenominationResponse,
+        SimulationResponse,
+        SystemStatsResponse,
+        DailyStatsResponse,
+        LiabilitiesResponse,
+        IntegrityResponse,
+        BackfillChecksumResponse,
+        CreditsResponse,
+        CreditSummariesResponse,
+        WeeklySummaryResponse,
+        PreferencesResponse,
+        WebhookResponse,
+        WithdrawalResponse,
+        WithdrawalsResponse,
+        api::AppVersion,
+        api::AppInfo,
+        api::HealthChecks,
+        api::CheckStatus,
+        api::Pagination,
+        api::TransactionPayload,
+        api::adjustment::AdjustInput,
+        api::adjustment::AdjustmentOutput,
+        api::adjustment::ApproveInput,
+        api::api_key::ApiKeyInput,
+        api::api_key::ApiKeyOutput,
+        api::budget::BudgetInput,
+        api::budget::BudgetOutput,
+        api::budget::UpdateBudgetInput,
+        api::charge::ChargeInput,
+        api::charge::ChargeOutput,
+        api::charge::UpdateChargeInput,
+        api::charge::CompleteChargeInput,
+        api::charge::RefundChargeInput,
+        api::charge::ReceiptOutput,
+        api::charge::ReceiptKeyOutput,
+        api::currency::Currency,
+        api::currency::FormatAmountOutput,
+        crate::receipt::Receipt,
+        crate::money::Denomination,
+        api::customer::CustomerInput,
+        api::customer::CustomerOutput,
+        api::export::ExportTransactionRow,
+        api::hold::HoldInput,
+        api::hold::HoldOutput,
+        api::hold::CaptureInput,
+        api::hold::ReleaseInput,
+        api::redpacket::RedpacketInput,
+        api::redpacket::RedpacketOutput,
+        api::redpacket::ClaimInput,
+        api::redpacket::ClaimOutput,
+        api::subscription::SubscriptionInput,
+        api::subscription::SubscriptionOutput,
+        api::subscription::CancelSubscriptionInput,
+        api::pool::PoolInput,
+        api::pool::PoolOutput,
+        api::pool::ContributeInput,
+        api::pool::ContributionOutput,
+        api::pool::FinalizeInput,
+        api::transaction::TransactionInput,
+        api::transaction::TransactionOutput,
+        api::transaction::AggregateOutput,
+        api::v2::transaction::TransactionOutput,
+        api::wallet::WalletOutput,
+        api::wallet::MaxOverdrawInput,
+        api::wallet::CloseWalletInput,
+        api::wallet::CreditOutput,
+        api::wallet::CreditSummaryOutput,
+        api::wallet::TopPayerOutput,
+        api::wallet::WeeklySummaryOutput,
+        api::wallet::AwardInput,
+        api::wallet::SpendInput,
+        api::wallet::SimulateInput,
+        api::wallet::SimulationOutput,
+        api::wallet::DailyTotalOutput,
+        api::wallet::ChargeDailyTotalOutput,
+        api::wallet::SystemStatsOutput,
+        api::wallet::DailyTxnStatsOutput,
+        api::wallet::DailyChargeStatsOutput,
+        api::wallet::DailyStatsOutput,
+        api::wallet::LiabilityOutput,
+        api::SchemaOutput,
+        api::TableSchemaOutput,
+        api::ColumnOutput,
+        api::wallet::IntegrityOutput,
+        api::wallet::BackfillChecksumInput,
+        api::wallet::BackfillChecksumOutput,
+        api::wallet_pref::PreferencesInput,
+        api::wallet_pref::PreferencesOutput,
+        api::webhook::WebhookInput,
+        api::webhook::WebhookOutput,
+        api::withdrawal::WithdrawInput,
+        api::withdrawal::WithdrawalOutput,
+        api::withdrawal::ReviewInput,
+        api::dispute::DisputeInput,
+        api::dispute::DisputeOutput,
+        api::audit::AuditLogOutput,
+        api::transaction::TxnHistoryOutput,
+        api::dispute::ResolveInput,
+    )),
+    tags(
+        (name = "app"),
+        (name = "wallet"),
+        (name = "charge"),
+        (name = "transaction"),
+        (name = "customer"),
+        (name = "pool"),
+        (name = "admin"),
+    )
+)]
+pub struct ApiDoc;
+
+// the document is always JSON.
+pub async fn openapi(State(_): State<Arc<AppState>>) -> axum::Json<utoipa::openapi::OpenApi> {
+    axum::Json(ApiDoc::openapi())
+}
@@ -0,0 +1,205 @@
+use axum::{extract::State, Extension};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use validator::Validate;
+
+use axum_web::context::{unix_ms, ReqContext};
+use axum_web::erring::{HTTPError, SuccessResponse};
+use axum_web::object::PackObject;
+use scylla_orm::ColumnsMap;
+
+use crate::api::AppState;
+use crate::db;
+
+// external_ref marker of penny test charges, they belong to the system wallet's uid and
+// are never applied to a wallet.
+pub const PENNY_TEST_REF: &str = "penny_test";
+
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct PennyTestInput {
+    #[validate(length(min = 3, max = 3))]
+    pub currency: String,
+    // fiat amount in the currency's minor unit, it should be above the provider's minimum.
+    #[validate(range(min = 1, max = 1000))]
+    pub amount: i64,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct PennyTestStep {
+    pub step: String,
+    pub ok: bool,
+    pub elapsed: u64, // ms
+    pub message: String,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct PennyTestOutput {
+    pub id: PackObject<xid::Id>, // the penny test charge
+    pub provider: String,
+    pub ok: bool,                  // all steps succeeded
+    pub steps: Vec<PennyTestStep>, // steps after a failed one are not run
+}
+
+impl PennyTestOutput {
+    // records the result of the step started at `start`, returns whether it succeeded.
+    fn record(&mut self, step: &str, start: u64, res: anyhow::Result<String>) -> bool {
+        let (ok, message) = match res {
+            Ok(msg) => (true, msg),
+            Err(err) => (false, err.to_string()),
+        };
+        self.steps.push(PennyTestStep {
+            step: step.to_string(),
+            ok,
+            elapsed: unix_ms() - start,
+            message,
+        });
+        self.ok = ok;
+        ok
+    }
+}
+
+// creates a minimal charge marked with PENNY_TEST_REF and drives it through the provider
+// sandbox: payment, complete, refund. No transaction is made, the charge is committed with
+// the zero txn. It validates a provider integration before enabling it.
+// It is compiled only with the "penny-test" feature, and is not in the OpenAPI document.
+pub async fn run(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<PennyTestInput>,
+) -> Result<PackObject<SuccessResponse<PennyTestOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    ctx.set_kvs(vec![
+        ("action", "penny_test".into()),
+        ("currency", input.currency.clone().into()),
+        ("amount", input.amount.into()),
+    ])
+    .await;
+
+    let stripe = app
+        .stripe
+        .clone()
+        .ok_or_else(|| HTTPError::new(400, "Stripe is not configured".to_string()))?;
+    if stripe.livemode() {
+        return Err(HTTPError::new(
+            400,
+            "Penny test runs only against the Stripe sandbox".to_string(),
+        ));
+    }
+
+    let mut doc = db::Charge {
+        uid: db::SYS_ID,
+        provider: "stripe".to_string(),
+        currency: input.currency.to_uppercase(),
+        amount: input.amount,
+        livemode: Some(false),
+        external_ref: PENNY_TEST_REF.to_string(),
+        ..Default::default()
+    };
+    let mut out = PennyTestOutput {
+        provider: doc.provider.clone(),
+        ..Default::default()
+    };
+
+    let start = unix_ms();
+    let res = doc.save(&app.scylla).await.map(|_| doc.id.to_string());
+    out.id = to.with(doc.id);
+    ctx.set("id", doc.id.to_string().into()).await;
+    if !out.record("create_charge", start, res) {
+        return Ok(to.with(SuccessResponse::new(out)));
+    }
+
+    let start = unix_ms();
+    let (intent, payload) = match stripe.create_test_payment(&doc).await {
+        Ok((intent, payload)) if intent.status == "succeeded" && !intent.livemode => {
+            (intent, payload)
+        }
+        Ok((intent, _)) => {
+            out.record(
+                "provider_payment",
+                start,
+                Err(anyhow::anyhow!(
+                    "payment {} is {}, livemode {}",
+                    intent.id,
+                    intent.status,
+                    intent.livemode
+                )),
+            );
+            return Ok(to.with(SuccessResponse::new(out)));
+        }
+        Err(err) => {
+            out.record("provider_payment", start, Err(err));
+            return Ok(to.with(SuccessResponse::new(out)));
+        }
+    };
+    out.record(
+        "provider_payment",
+        start,
+        Ok(format!("payment {} {}", intent.id, intent.status)),
+    );
+
+    let start = unix_ms();
+    let mut cols = ColumnsMap::with_capacity(3);
+    cols.set_as("status", &1i8);
+    cols.set_as("charge_id", &intent.id);
+    cols.set_as("charge_payload", &payload);
+    let res = doc
+        .update(&app.scylla, cols, 0)
+        .await
+        .map(|_| format!("charge {} prepared", doc.id));
+    if !out.record("prepare_charge", start, res) {
+        return Ok(to.with(SuccessResponse::new(out)));
+    }
+
+    let start = unix_ms();
+    let mut cols = ColumnsMap::with_capacity(4);
+    cols.set_as("status", &2i8);
+    cols.set_as("currency", &intent.currency.to_uppercase());
+    cols.set_as("amount", &intent.amount);
+    cols.set_as("charge_payload", &payload);
+    let mut res = doc.update(&app.scylla, cols, 1).await;
+    if res.is_ok() {
+        let mut cols = ColumnsMap::with_capacity(2);
+        cols.set_as("status", &3i8);
+        cols.set_as("txn", &xid::Id::default());
+        res = doc.update(&app.scylla, cols, 2).await;
+    }
+    let res = res.map(|_| format!("charge {} completed without transaction", doc.id));
+    if !out.record("complete_charge", start, res) {
+        return Ok(to.with(SuccessResponse::new(out)));
+    }
+
+    let start = unix_ms();
+    let res = stripe
+        .create_refund(&intent.id, doc.amount, &format!("{}.refund", doc.id))
+        .await
+        .and_then(|refund| match refund.status.as_deref() {
+            Some("succeeded") | Some("pending") => Ok(format!(
+                "refund {} {} of {}",
+                refund.id,
+                refund.status.unwrap_or_default(),
+                refund.amount
+            )),
+            status => Err(anyhow::anyhow!("refund {} is {:?}", refund.id, status)),
+        });
+    if !out.record("provider_refund", start, res) {
+        return Ok(to.with(SuccessResponse::new(out)));
+    }
+
+    let start = unix_ms();
+    let amount = doc.amount;
+    let res = match doc.refund(&app.scylla, amount, None).await {
+        Ok(true) => Ok(format!("charge {} refunded", doc.id)),
+        Ok(false) => Err(anyhow::anyhow!("charge {} refund conflict", doc.id)),
+        Err(err) => Err(err),
+    };
+    out.record("refund_charge", start, res);
+
+    log::info!(target: "penny_test",
+        id = doc.id.to_string(),
+        ok = out.ok;
+        "",
+    );
+    Ok(to.with(SuccessResponse::new(out)))
+}
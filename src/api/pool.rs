@@ -0,0 +1,384 @@
+use axum::{
+    extract::{Query, State},
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+use axum_web::context::{unix_ms, ReqContext};
+use axum_web::erring::{HTTPError, SuccessResponse};
+use axum_web::object::{cbor_to_vec, PackObject};
+use scylla_orm::FieldSet;
+
+use crate::api::{get_fields, AppState, TransactionPayload};
+use crate::db;
+
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
+pub struct PoolInput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub owner: PackObject<xid::Id>,
+    // checked by the sponsor kind's max amount.
+    #[validate(range(min = 1))]
+    pub goal: i64,
+    // contributions are cancelled if the goal is not reached in ttl_secs, max to 90 days.
+    #[validate(range(min = 60, max = 7776000))]
+    pub ttl_secs: i64,
+    #[validate(length(max = 1024))]
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct PoolOutput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub id: PackObject<xid::Id>,
+    #[schema(value_type = super::openapi::Xid)]
+    pub owner: PackObject<xid::Id>,
+    pub status: i8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub goal: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raised: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contributors: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expire_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<i64>,
+}
+
+impl PoolOutput {
+    pub fn from<T>(val: db::Pool, to: &PackObject<T>) -> Self {
+        let mut rt = Self {
+            id: to.with(val.id),
+            owner: to.with(val.owner),
+            status: val.status,
+            ..Default::default()
+        };
+
+        for v in val._fields {
+            match v.as_str() {
+                "goal" => rt.goal = Some(val.goal),
+                "raised" => rt.raised = Some(val.raised),
+                "contributors" => rt.contributors = Some(val.contributors),
+                "expire_at" => rt.expire_at = Some(val.expire_at),
+                "description" => rt.description = Some(val.description.to_owned()),
+                "created_at" => rt.created_at = Some(val.created_at),
+                "updated_at" => rt.updated_at = Some(val.updated_at),
+                _ => {}
+            }
+        }
+
+        rt
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/pool",
+    tag = "pool",
+    request_body = PoolInput,
+    responses(
+        (status = 200, body = super::openapi::PoolResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn create(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<PoolInput>,
+) -> Result<PackObject<SuccessResponse<PoolOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    db::TransactionKind::Sponsor.check_amount(input.goal)?;
+    let owner = input.owner.unwrap();
+    if owner == db::SYS_ID {
+        return Err(HTTPError::new(400, "Invalid owner".to_string()));
+    }
+    ctx.set_kvs(vec![
+        ("action", "create_pool".into()),
+        ("owner", owner.to_string().into()),
+        ("goal", input.goal.into()),
+        ("ttl_secs", input.ttl_secs.into()),
+    ])
+    .await;
+
+    db::Wallet::check_open_by_uid(&app.scylla, owner).await?;
+    let mut doc = db::Pool {
+        owner,
+        goal: input.goal,
+        expire_at: unix_ms() as i64 + input.ttl_secs.min(db::MAX_POOL_TTL_SECS) * 1000,
+        description: input.description.unwrap_or_default(),
+        ..Default::default()
+    };
+    doc.save(&app.scylla).await?;
+    ctx.set("id", doc.id.to_string().into()).await;
+    Ok(to.with(SuccessResponse::new(PoolOutput::from(doc, &to))))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryPool {
+    #[param(value_type = super::openapi::Xid)]
+    pub id: PackObject<xid::Id>,
+    pub fields: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/pool",
+    tag = "pool",
+    params(QueryPool),
+    responses(
+        (status = 200, body = super::openapi::PoolResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn get(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QueryPool>,
+) -> Result<PackObject<SuccessResponse<PoolOutput>>, HTTPError> {
+    input.validate()?;
+
+    let id = *input.id.to_owned();
+    ctx.set_kvs(vec![
+        ("action", "get_pool".into()),
+        ("id", id.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::Pool::with_pk(id);
+    doc.get_one(&app.scylla, get_fields(input.fields.clone())?)
+        .await?;
+    Ok(to.with(SuccessResponse::new(PoolOutput::from(doc, &to))))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
+pub struct ContributeInput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub id: PackObject<xid::Id>,
+    #[schema(value_type = super::openapi::Xid)]
+    pub uid: PackObject<xid::Id>,
+    // checked by the sponsor kind's max amount.
+    #[validate(range(min = 1))]
+    pub amount: i64,
+    // hide payer from payee-facing listings
+    pub anonymous: Option<bool>,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct ContributionOutput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub pool: PackObject<xid::Id>,
+    #[schema(value_type = super::openapi::Xid)]
+    pub txn: PackObject<xid::Id>,
+    #[schema(value_type = super::openapi::Xid)]
+    pub uid: PackObject<xid::Id>,
+    pub amount: i64,
+    pub status: i8,
+    pub created_at: i64,
+}
+
+impl ContributionOutput {
+    pub fn from<T>(val: db::PoolContribution, to: &PackObject<T>) -> Self {
+        Self {
+            pool: to.with(val.pool),
+            txn: to.with(val.txn),
+            uid: to.with(val.uid),
+            amount: val.amount,
+            status: val.status,
+            created_at: val.created_at,
+        }
+    }
+}
+
+// prepares a sponsor transaction from the contributor to the pool's owner,
+// it is committed or cancelled when the pool is finalized.
+#[utoipa::path(
+    post,
+    path = "/v1/pool/contribute",
+    tag = "pool",
+    request_body = ContributeInput,
+    responses(
+        (status = 200, body = super::openapi::ContributionResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn contribute(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<ContributeInput>,
+) -> Result<PackObject<SuccessResponse<ContributionOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    db::TransactionKind::Sponsor.check_amount(input.amount)?;
+    let id = input.id.unwrap();
+    let uid = input.uid.unwrap();
+    ctx.set_kvs(vec![
+        ("action", "contribute_pool".into()),
+        ("id", id.to_string().into()),
+        ("payer", uid.to_string().into()),
+        ("amount", input.amount.into()),
+    ])
+    .await;
+
+    let mut pool = db::Pool::with_pk(id);
+    pool.get_one(&app.scylla, FieldSet::new()).await?;
+    pool.check_open(unix_ms() as i64)?;
+
+    let mut txn = db::Transaction::with_uid(uid);
+    txn.anonymous = input.anonymous.unwrap_or_default();
+    txn.description = input
+        .description
+        .unwrap_or_else(|| pool.description.clone());
+    txn.payload = cbor_to_vec(&TransactionPayload {
+        kind: "pool".to_string(),
+        id: PackObject::Cbor(pool.id),
+        provider: None,
+        currency: None,
+        amount: None,
+    })
+    .unwrap_or_default();
+    txn.prepare(
+        &app.scylla,
+        &app.mac,
+        pool.owner,
+        db::TransactionKind::Sponsor,
+        input.amount,
+    )
+    .await?;
+    ctx.set("txn", txn.id.to_string().into()).await;
+
+    let mut doc = db::PoolContribution {
+        pool: pool.id,
+        txn: txn.id,
+        uid,
+        amount: input.amount,
+        ..Default::default()
+    };
+    if let Err(err) = doc.save(&app.scylla).await {
+        txn.cancel(&app.scylla, &app.mac).await?;
+        return Err(err.into());
+    }
+
+    // the contribution is counted if the pool is still open, or cancelled.
+    // the finalizing may have cancelled the pending contribution already.
+    match pool.increment(&app.scylla, input.amount).await {
+        Ok(_) => {
+            if !doc.update_status(&app.scylla, 0, 1).await? {
+                return Err(HTTPError::new(
+                    409,
+                    format!("Pool {} is finalizing, contribution cancelled", pool.id),
+                ));
+            }
+        }
+        Err(err) => {
+            doc.update_status(&app.scylla, 0, -1).await?;
+            txn.cancel(&app.scylla, &app.mac).await?;
+            return Err(err.into());
+        }
+    }
+
+    Ok(to.with(SuccessResponse::new(ContributionOutput::from(doc, &to))))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
+pub struct FinalizeInput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub id: PackObject<xid::Id>,
+}
+
+// commits all contributions if the goal is reached, or cancels them when the pool expired.
+// it can be retried until the pool is succeeded or failed.
+#[utoipa::path(
+    post,
+    path = "/v1/pool/finalize",
+    tag = "pool",
+    request_body = FinalizeInput,
+    responses(
+        (status = 200, body = super::openapi::PoolResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn finalize(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<FinalizeInput>,
+) -> Result<PackObject<SuccessResponse<PoolOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    let id = input.id.unwrap();
+    ctx.set_kvs(vec![
+        ("action", "finalize_pool".into()),
+        ("id", id.to_string().into()),
+    ])
+    .await;
+
+    let mut pool = db::Pool::with_pk(id);
+    pool.close(&app.scylla).await?;
+    if pool.status != 2 {
+        // already finalized
+        return Ok(to.with(SuccessResponse::new(PoolOutput::from(pool, &to))));
+    }
+
+    // pending contributions are not counted, they are cancelled.
+    let list = db::PoolContribution::list(&app.scylla, id).await?;
+    for mut doc in list {
+        if doc.status == 0 {
+            doc.update_status(&app.scylla, 0, -1).await?;
+        }
+    }
+
+    let list = db::PoolContribution::list(&app.scylla, id).await?;
+    let counted: i64 = list
+        .iter()
+        .filter(|c| c.status == 1 || c.status == 2)
+        .map(|c| c.amount)
+        .sum();
+    let succeeded = counted >= pool.goal || list.iter().any(|c| c.status == 2);
+    ctx.set_kvs(vec![
+        ("counted", counted.into()),
+        ("succeeded", succeeded.into()),
+    ])
+    .await;
+
+    for mut doc in list {
+        if doc.status == 1 {
+            let to_status = if succeeded { 2 } else { -1 };
+            doc.update_status(&app.scylla, 1, to_status).await?;
+        }
+
+        // the transactions are committed or cancelled idempotently, so that it can be retried.
+        let mut txn = db::Transaction::with_pk(doc.uid, doc.txn);
+        txn.get_one(&app.scylla, FieldSet::new()).await?;
+        match (doc.status, txn.status) {
+            (2, 1) | (2, 3) => {
+                txn.commit(&app.scylla, &app.mac).await?;
+            }
+            (-1, 1) => {
+                txn.cancel(&app.scylla, &app.mac).await?;
+            }
+            _ => {}
+        }
+    }
+
+    pool.finish(
+        &app.scylla,
+        if succeeded { 1 } else { -1 },
+        if succeeded { counted } else { 0 },
+    )
+    .await?;
+    pool.get_one(&app.scylla, FieldSet::new()).await?;
+    Ok(to.with(SuccessResponse::new(PoolOutput::from(pool, &to))))
+}
@@ -0,0 +1,453 @@
+use axum::{
+    extract::{Query, State},
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+use axum_web::context::{unix_ms, ReqContext};
+use axum_web::erring::{HTTPError, SuccessResponse};
+use axum_web::object::{cbor_to_vec, PackObject};
+use scylla_orm::FieldSet;
+
+use crate::api::{get_fields, AppState, TransactionPayload};
+use crate::db;
+
+const DEFAULT_REDPACKET_TTL_SECS: i64 = 24 * 3600;
+
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
+pub struct RedpacketInput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub uid: PackObject<xid::Id>,
+    // checked by the redpacket kind's max amount, every share is at least 1.
+    #[validate(range(min = 1))]
+    pub amount: i64,
+    #[validate(range(min = 1, max = 1000))]
+    pub count: i32,
+    // random shares, or equal shares by default.
+    pub random: Option<bool>,
+    // unclaimed shares are refunded to the sender after ttl_secs, default to 1 day, max to 7 days.
+    #[validate(range(min = 60, max = 604800))]
+    pub ttl_secs: Option<i64>,
+    #[validate(length(max = 1024))]
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct RedpacketOutput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub id: PackObject<xid::Id>,
+    #[schema(value_type = super::openapi::Xid)]
+    pub sender: PackObject<xid::Id>,
+    pub status: i8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<super::openapi::Xid>)]
+    pub txn: Option<PackObject<xid::Id>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub random: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub claimed: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub claimed_amount: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expire_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<i64>,
+}
+
+impl RedpacketOutput {
+    pub fn from<T>(val: db::Redpacket, to: &PackObject<T>) -> Self {
+        let mut rt = Self {
+            id: to.with(val.id),
+            sender: to.with(val.sender),
+            status: val.status,
+            ..Default::default()
+        };
+
+        // unclaimed allocations are not exposed.
+        for v in val._fields {
+            match v.as_str() {
+                "txn" => rt.txn = Some(to.with(val.txn)),
+                "amount" => rt.amount = Some(val.amount),
+                "random" => rt.random = Some(val.random),
+                "allocations" => rt.count = Some(val.allocations.len() as i32),
+                "claimed" => rt.claimed = Some(val.claimed),
+                "claimed_amount" => rt.claimed_amount = Some(val.claimed_amount),
+                "expire_at" => rt.expire_at = Some(val.expire_at),
+                "description" => rt.description = Some(val.description.to_owned()),
+                "created_at" => rt.created_at = Some(val.created_at),
+                "updated_at" => rt.updated_at = Some(val.updated_at),
+                _ => {}
+            }
+        }
+
+        rt
+    }
+}
+
+fn redpacket_payload(id: xid::Id) -> Vec<u8> {
+    cbor_to_vec(&TransactionPayload {
+        kind: "redpacket".to_string(),
+        id: PackObject::Cbor(id),
+        provider: None,
+        currency: None,
+        amount: None,
+    })
+    .unwrap_or_default()
+}
+
+// escrows the amount from the sender to the system by a committed redpacket transaction.
+#[utoipa::path(
+    post,
+    path = "/v1/wallet/redpacket",
+    tag = "wallet",
+    request_body = RedpacketInput,
+    responses(
+        (status = 200, body = super::openapi::RedpacketResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn create(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<RedpacketInput>,
+) -> Result<PackObject<SuccessResponse<RedpacketOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    db::TransactionKind::Redpacket.check_amount(input.amount)?;
+    let uid = input.uid.unwrap();
+    if uid == db::SYS_ID {
+        return Err(HTTPError::new(400, "Invalid sender".to_string()));
+    }
+    let random = input.random.unwrap_or_default();
+    let allocations = db::split_shares(input.amount, input.count, random)?;
+    let ttl_secs = input
+        .ttl_secs
+        .unwrap_or(DEFAULT_REDPACKET_TTL_SECS)
+        .min(db::MAX_REDPACKET_TTL_SECS);
+    ctx.set_kvs(vec![
+        ("action", "create_redpacket".into()),
+        ("sender", uid.to_string().into()),
+        ("amount", input.amount.into()),
+        ("count", input.count.into()),
+        ("random", random.into()),
+    ])
+    .await;
+
+    let id = xid::new();
+    let description = input.description.unwrap_or_default();
+    let mut txn = db::Transaction::with_uid(uid);
+    txn.description = if description.is_empty() {
+        "payer.redpacket".to_string()
+    } else {
+        description.clone()
+    };
+    txn.payload = redpacket_payload(id);
+    txn.prepare(
+        &app.scylla,
+        &app.mac,
+        db::SYS_ID,
+        db::TransactionKind::Redpacket,
+        input.amount,
+    )
+    .await?;
+    ctx.set_kvs(vec![
+        ("id", id.to_string().into()),
+        ("txn", txn.id.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::Redpacket {
+        id,
+        sender: uid,
+        txn: txn.id,
+        amount: input.amount,
+        random,
+        allocations,
+        expire_at: unix_ms() as i64 + ttl_secs * 1000,
+        description,
+        ..Default::default()
+    };
+    if let Err(err) = doc.save(&app.scylla).await {
+        txn.cancel(&app.scylla, &app.mac).await?;
+        return Err(err.into());
+    }
+
+    // an escrow left prepared is committed when the redpacket is settled.
+    txn.commit(&app.scylla, &app.mac).await?;
+    Ok(to.with(SuccessResponse::new(RedpacketOutput::from(doc, &to))))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryRedpacket {
+    #[param(value_type = super::openapi::Xid)]
+    pub id: PackObject<xid::Id>,
+    pub fields: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/wallet/redpacket",
+    tag = "wallet",
+    params(QueryRedpacket),
+    responses(
+        (status = 200, body = super::openapi::RedpacketResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn get(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QueryRedpacket>,
+) -> Result<PackObject<SuccessResponse<RedpacketOutput>>, HTTPError> {
+    input.validate()?;
+
+    let id = *input.id.to_owned();
+    ctx.set_kvs(vec![
+        ("action", "get_redpacket".into()),
+        ("id", id.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::Redpacket::with_pk(id);
+    doc.get_one(&app.scylla, get_fields(input.fields.clone())?)
+        .await?;
+    Ok(to.with(SuccessResponse::new(RedpacketOutput::from(doc, &to))))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
+pub struct ClaimInput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub id: PackObject<xid::Id>,
+    #[schema(value_type = super::openapi::Xid)]
+    pub uid: PackObject<xid::Id>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct ClaimOutput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub redpacket: PackObject<xid::Id>,
+    #[schema(value_type = super::openapi::Xid)]
+    pub uid: PackObject<xid::Id>,
+    #[schema(value_type = super::openapi::Xid)]
+    pub txn: PackObject<xid::Id>,
+    pub amount: i64,
+    pub status: i8,
+    pub created_at: i64,
+}
+
+impl ClaimOutput {
+    pub fn from<T>(val: db::RedpacketClaim, to: &PackObject<T>) -> Self {
+        Self {
+            redpacket: to.with(val.redpacket),
+            uid: to.with(val.uid),
+            txn: to.with(val.txn),
+            amount: val.amount,
+            status: val.status,
+            created_at: val.created_at,
+        }
+    }
+}
+
+// takes the next share atomically and pays it by a committed redpacket transaction from the
+// system, a recipient claims a redpacket at most once. the transaction id is claimed on the
+// claim before prepared, so that a retried claim resumes that transaction rather than paying
+// again. a paid claim is returned as it is.
+#[utoipa::path(
+    post,
+    path = "/v1/wallet/redpacket/claim",
+    tag = "wallet",
+    request_body = ClaimInput,
+    responses(
+        (status = 200, body = super::openapi::RedpacketClaimResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn claim(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<ClaimInput>,
+) -> Result<PackObject<SuccessResponse<ClaimOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    let id = input.id.unwrap();
+    let uid = input.uid.unwrap();
+    if uid == db::SYS_ID {
+        return Err(HTTPError::new(400, "Invalid recipient".to_string()));
+    }
+    ctx.set_kvs(vec![
+        ("action", "claim_redpacket".into()),
+        ("id", id.to_string().into()),
+        ("payee", uid.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::Redpacket::with_pk(id);
+    doc.get_one(&app.scylla, FieldSet::new()).await?;
+
+    let busy = || {
+        HTTPError::new(
+            409,
+            format!("Redpacket {} is being claimed, please try again", id),
+        )
+    };
+    let mut claim = db::RedpacketClaim::with_pk(id, uid);
+    if claim.save(&app.scylla).await? {
+        let share = match doc.take_share(&app.scylla).await {
+            Ok(share) => share,
+            Err(err) => {
+                claim.delete(&app.scylla).await?;
+                return Err(err.into());
+            }
+        };
+        match claim.set_amount(&app.scylla, share).await {
+            Ok(true) => {}
+            Ok(false) => {
+                release_share(&app, &mut doc, &mut claim, share).await;
+                return Err(busy());
+            }
+            Err(err) => {
+                release_share(&app, &mut doc, &mut claim, share).await;
+                return Err(err.into());
+            }
+        }
+    } else {
+        claim.get_one(&app.scylla).await?;
+        if claim.status == 1 {
+            return Ok(to.with(SuccessResponse::new(ClaimOutput::from(claim, &to))));
+        }
+        if claim.amount == 0 {
+            return Err(busy());
+        }
+    }
+    ctx.set("amount", claim.amount.into()).await;
+
+    if claim.txn == xid::Id::default() && !claim.set_txn(&app.scylla, xid::new()).await? {
+        // claimed by a concurrent retry.
+        claim.get_one(&app.scylla).await?;
+        if claim.status == 1 {
+            return Ok(to.with(SuccessResponse::new(ClaimOutput::from(claim, &to))));
+        }
+    }
+    ctx.set("txn", claim.txn.to_string().into()).await;
+
+    pay_claim(&app, &doc, &claim).await?;
+    if !claim.paid(&app.scylla).await? {
+        // paid by a concurrent retry.
+        claim.get_one(&app.scylla).await?;
+    }
+    Ok(to.with(SuccessResponse::new(ClaimOutput::from(claim, &to))))
+}
+
+// prepares the claimed transaction if it does not exist, and commits it. an interrupted
+// commit is resumed, a committed transaction is not paid again.
+async fn pay_claim(
+    app: &AppState,
+    doc: &db::Redpacket,
+    claim: &db::RedpacketClaim,
+) -> Result<(), HTTPError> {
+    let mut txn = db::Transaction::with_pk(db::SYS_ID, claim.txn);
+    if let Err(err) = txn.get_one(&app.scylla, FieldSet::new()).await {
+        let err = HTTPError::from(err);
+        if err.code != 404 {
+            return Err(err);
+        }
+
+        // not prepared yet, or the failed prepare was deleted.
+        txn.description = if doc.description.is_empty() {
+            "payee.redpacket".to_string()
+        } else {
+            doc.description.clone()
+        };
+        txn.payload = redpacket_payload(doc.id);
+        txn.prepare(
+            &app.scylla,
+            &app.mac,
+            claim.uid,
+            db::TransactionKind::Redpacket,
+            claim.amount,
+        )
+        .await?;
+    }
+
+    match txn.status {
+        1 | 2 => {
+            txn.commit(&app.scylla, &app.mac).await?;
+        }
+        3 => {}
+        0 => {
+            return Err(HTTPError::new(
+                409,
+                format!("Transaction {} is being prepared", txn.id),
+            ));
+        }
+        status => {
+            return Err(HTTPError::new(
+                500,
+                format!(
+                    "Invalid transaction {} of redpacket {} claim, status {}",
+                    txn.id, doc.id, status
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+// releases the share taken by a claim whose amount was not set. the claim is deleted first,
+// so that a timed out but applied set_amount keeps the share for the retried claim. the share
+// is given back if no other share was taken since, otherwise it is logged to be reconciled.
+async fn release_share(
+    app: &AppState,
+    doc: &mut db::Redpacket,
+    claim: &mut db::RedpacketClaim,
+    share: i64,
+) {
+    match claim.delete(&app.scylla).await {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(err) => {
+            log::error!(target: "redpacket",
+                action = "release_share",
+                id = doc.id.to_string(),
+                uid = claim.uid.to_string(),
+                share = share;
+                "failed to delete the claim: {}", err);
+            return;
+        }
+    }
+
+    match doc.give_back_share(&app.scylla, share).await {
+        Ok(true) => {}
+        Ok(false) => {
+            log::error!(target: "redpacket",
+                action = "release_share",
+                id = doc.id.to_string(),
+                uid = claim.uid.to_string(),
+                share = share;
+                "the share was not given back, other shares were taken");
+        }
+        Err(err) => {
+            log::error!(target: "redpacket",
+                action = "release_share",
+                id = doc.id.to_string(),
+                uid = claim.uid.to_string(),
+                share = share;
+                "failed to give back the share: {}", err);
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use axum::{
+    extract::{Query, State},
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+use axum_web::context::ReqContext;
+use axum_web::erring::{HTTPError, SuccessResponse};
+use axum_web::object::PackObject;
+
+use crate::api::AppState;
+use crate::{db, subscription};
+
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
+pub struct SubscriptionInput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub uid: PackObject<xid::Id>,
+    #[schema(value_type = super::openapi::Xid)]
+    pub payee: PackObject<xid::Id>,
+    // amount of a period, checked by the subscribe kind's max amount.
+    #[validate(range(min = 1))]
+    pub amount: i64,
+    #[validate(range(min = 1, max = 366))]
+    pub period_days: i32,
+    #[validate(length(max = 1024))]
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct SubscriptionOutput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub uid: PackObject<xid::Id>,
+    #[schema(value_type = super::openapi::Xid)]
+    pub payee: PackObject<xid::Id>,
+    #[schema(value_type = super::openapi::Xid)]
+    pub id: PackObject<xid::Id>,
+    pub amount: i64,
+    pub period_days: i32,
+    pub status: i8, // 0: active, 1: past_due, 2: renewing, -1: canceled, -2: lapsed
+    pub next_renewal_at: i64,
+    pub grace_until: i64,
+    pub retries: i32,
+    #[schema(value_type = super::openapi::Xid)]
+    pub last_txn: PackObject<xid::Id>,
+    pub description: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+impl SubscriptionOutput {
+    pub fn from<T>(val: db::Subscription, to: &PackObject<T>) -> Self {
+        Self {
+            uid: to.with(val.uid),
+            payee: to.with(val.payee),
+            id: to.with(val.id),
+            amount: val.amount,
+            period_days: val.period_days,
+            status: val.status,
+            next_renewal_at: val.next_renewal_at,
+            grace_until: val.grace_until,
+            retries: val.retries,
+            last_txn: to.with(val.last_txn),
+            description: val.description,
+            created_at: val.created_at,
+            updated_at: val.updated_at,
+        }
+    }
+}
+
+// subscribes to the payee, the first period is paid by a committed subscribe transaction,
+// the next ones are renewed by the subscription job.
+#[utoipa::path(
+    post,
+    path = "/v1/wallet/subscription",
+    tag = "wallet",
+    request_body = SubscriptionInput,
+    responses(
+        (status = 200, body = super::openapi::SubscriptionResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn create(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<SubscriptionInput>,
+) -> Result<PackObject<SuccessResponse<SubscriptionOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    db::TransactionKind::Subscribe.check_amount(input.amount)?;
+    let uid = input.uid.unwrap();
+    let payee = input.payee.unwrap();
+    if uid == db::SYS_ID || uid == payee {
+        return Err(HTTPError::new(400, "Invalid subscriber".to_string()));
+    }
+    ctx.set_kvs(vec![
+        ("action", "create_subscription".into()),
+        ("payer", uid.to_string().into()),
+        ("payee", payee.to_string().into()),
+        ("amount", input.amount.into()),
+        ("period_days", input.period_days.into()),
+    ])
+    .await;
+
+    let id = xid::new();
+    let description = input.description.unwrap_or_default();
+    let mut txn = db::Transaction::with_uid(uid);
+    txn.description = if description.is_empty() {
+        "payer.subscription".to_string()
+    } else {
+        description.clone()
+    };
+    txn.payload = subscription::subscription_payload(id);
+    txn.prepare(
+        &app.scylla,
+        &app.mac,
+        payee,
+        db::TransactionKind::Subscribe,
+        input.amount,
+    )
+    .await?;
+    ctx.set_kvs(vec![
+        ("id", id.to_string().into()),
+        ("txn", txn.id.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::Subscription {
+        uid,
+        payee,
+        id,
+        amount: input.amount,
+        period_days: input.period_days,
+        last_txn: txn.id,
+        description,
+        ..Default::default()
+    };
+    if let Err(err) = doc.save(&app.scylla).await {
+        txn.cancel(&app.scylla, &app.mac).await?;
+        return Err(err.into());
+    }
+
+    txn.commit(&app.scylla, &app.mac).await?;
+    Ok(to.with(SuccessResponse::new(SubscriptionOutput::from(doc, &to))))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QuerySubscription {
+    #[param(value_type = super::openapi::Xid)]
+    #[validate(custom = "super::validate_uid")]
+    pub uid: PackObject<xid::Id>,
+    #[param(value_type = super::openapi::Xid)]
+    pub payee: PackObject<xid::Id>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/wallet/subscription",
+    tag = "wallet",
+    params(QuerySubscription),
+    responses(
+        (status = 200, body = super::openapi::SubscriptionResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn get(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QuerySubscription>,
+) -> Result<PackObject<SuccessResponse<SubscriptionOutput>>, HTTPError> {
+    input.validate()?;
+
+    let uid = *input.uid.to_owned();
+    let payee = *input.payee.to_owned();
+    ctx.set_kvs(vec![
+        ("action", "get_subscription".into()),
+        ("payer", uid.to_string().into()),
+        ("payee", payee.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::Subscription::with_pk(uid, payee);
+    doc.get_one(&app.scylla).await?;
+    Ok(to.with(SuccessResponse::new(SubscriptionOutput::from(doc, &to))))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
+pub struct CancelSubscriptionInput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub uid: PackObject<xid::Id>,
+    #[schema(value_type = super::openapi::Xid)]
+    pub payee: PackObject<xid::Id>,
+}
+
+// stops the renewals, the paid period is not refunded.
+// returns false if the subscription was canceled or lapsed.
+#[utoipa::path(
+    post,
+    path = "/v1/wallet/subscription/cancel",
+    tag = "wallet",
+    request_body = CancelSubscriptionInput,
+    responses(
+        (status = 200, body = super::openapi::BoolResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn cancel(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<CancelSubscriptionInput>,
+) -> Result<PackObject<SuccessResponse<bool>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    let uid = input.uid.unwrap();
+    let payee = input.payee.unwrap();
+    ctx.set_kvs(vec![
+        ("action", "cancel_subscription".into()),
+        ("payer", uid.to_string().into()),
+        ("payee", payee.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::Subscription::with_pk(uid, payee);
+    let res = doc.cancel(&app.scylla).await?;
+    Ok(to.with(SuccessResponse::new(res)))
+}
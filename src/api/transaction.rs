@@ -4,40 +4,101 @@ use axum::{
 };
 use serde::{Deserialize, Serialize};
 use std::{str::FromStr, sync::Arc};
+use utoipa::{IntoParams, ToSchema};
 use validator::Validate;
 
-use axum_web::context::ReqContext;
+use axum_web::context::{unix_ms, ReqContext};
 use axum_web::erring::{HTTPError, SuccessResponse};
 use axum_web::object::PackObject;
 
 use crate::db;
 use crate::{
-    api::{get_fields, token_from_xid, token_to_xid, AppState, Pagination, QueryUidId},
+    api::{
+        get_fields, get_list_fields, token_from_xid, token_to_xid, AppState, Pagination, QueryUid,
+        QueryUidId,
+    },
     db::TransactionKind,
 };
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
 pub struct TransactionOutput {
+    #[schema(value_type = super::openapi::Xid)]
     pub id: PackObject<xid::Id>,
     pub sequence: i64,
+    #[schema(value_type = super::openapi::Xid)]
     pub payee: PackObject<xid::Id>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<super::openapi::Xid>)]
     pub sub_payee: Option<PackObject<xid::Id>>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<super::openapi::Xid>)]
     pub payer: Option<PackObject<xid::Id>>,
     pub status: i8,
     pub kind: String,
     pub amount: i64,
     pub sys_fee: i64,
     pub sub_shares: i64,
+    // income view only, the amount received by the payee after the fee and shares.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub net_amount: Option<i64>,
+    // income view only, the amount received by the viewer as a sub payee.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub your_share: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Vec<Object>>)]
+    pub shares: Option<Vec<(PackObject<xid::Id>, u16)>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anonymous: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
+    // 1 if the description was redacted by moderation, 2 if blocked.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub moderation: Option<i8>,
+    // the original transaction of a refund, adjustment or bonus, and its payer.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<super::openapi::Xid>)]
+    pub ref_uid: Option<PackObject<xid::Id>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<super::openapi::Xid>)]
+    pub ref_txn: Option<PackObject<xid::Id>>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<super::openapi::Base64Url>)]
     pub payload: Option<PackObject<Vec<u8>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<i64>, // unix ms, 0 for transactions not backfilled yet
+    // the viewer's balances after the transaction, the payer's after prepared or the payee's
+    // after committed in the income view. None for transactions before they were recorded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub balance_award: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub balance_topup: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub balance_income: Option<i64>,
+}
+
+// the listing a transaction comes from, the fee breakdown is derived for the viewer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum View {
+    Payer,
+    Income(xid::Id), // the payee or a sub payee
 }
 
+// required fields of the income view, besides the selected fields.
+pub const INCOME_VIEW_FIELDS: [db::TransactionField; 6] = [
+    db::TransactionField::Payee,
+    db::TransactionField::Amount,
+    db::TransactionField::SysFee,
+    db::TransactionField::SubShares,
+    db::TransactionField::SubPayee,
+    db::TransactionField::Shares,
+];
+
 impl TransactionOutput {
     pub fn from<T>(val: db::Transaction, to: &PackObject<T>) -> Self {
+        Self::from_view(val, to, View::Payer)
+    }
+
+    pub fn from_view<T>(val: db::Transaction, to: &PackObject<T>, view: View) -> Self {
         let mut rt = Self {
             id: to.with(val.id),
             sequence: val.sequence,
@@ -54,15 +115,49 @@ impl TransactionOutput {
             Ok(TransactionKind::Award)
             | Ok(TransactionKind::Topup)
             | Ok(TransactionKind::Sponsor)
-            | Ok(TransactionKind::Subscribe) => rt.payer = to.with_option(Some(val.uid)),
+            | Ok(TransactionKind::Subscribe)
+            | Ok(TransactionKind::Redpacket)
+            | Ok(TransactionKind::Chargeback) => rt.payer = to.with_option(Some(val.uid)),
             _ => {}
         }
 
+        // the payer's balances are never listed to the payees.
+        let balance = match view {
+            View::Payer => val.balance(),
+            View::Income(_) => val._payee_balance,
+        };
+        if let View::Income(uid) = view {
+            if val.payee == uid {
+                rt.net_amount = Some(val.amount - val.sys_fee - val.sub_shares);
+            }
+            rt.your_share = val
+                .sub_payees()
+                .into_iter()
+                .find(|(sub_payee, _)| *sub_payee == uid)
+                .map(|(_, amount)| amount);
+        }
+
         for v in val._fields {
             match v.as_str() {
                 "sub_payee" => rt.sub_payee = to.with_option(val.sub_payee),
+                "shares" => {
+                    rt.shares = Some(
+                        val.shares
+                            .iter()
+                            .map(|(uid, bps)| (to.with(*uid), *bps))
+                            .collect(),
+                    )
+                }
+                "anonymous" => rt.anonymous = Some(val.anonymous),
+                "moderation" => rt.moderation = Some(val.moderation),
+                "ref_uid" => rt.ref_uid = to.with_option(val.ref_uid),
+                "ref_txn" => rt.ref_txn = to.with_option(val.ref_txn),
                 "description" => rt.description = Some(val.description.to_owned()),
                 "payload" => rt.payload = Some(to.with(val.payload.to_owned())),
+                "updated_at" => rt.updated_at = Some(val.updated_at),
+                "balance_award" => rt.balance_award = balance.map(|b| b.award),
+                "balance_topup" => rt.balance_topup = balance.map(|b| b.topup),
+                "balance_income" => rt.balance_income = balance.map(|b| b.income),
                 _ => {}
             }
         }
@@ -71,12 +166,49 @@ impl TransactionOutput {
     }
 }
 
-pub async fn get(
+// a versioned output of transactions, the handlers are shared by API versions through it.
+pub trait TransactionView: Serialize + Send + Sized + 'static {
+    fn from_txn<T>(val: db::Transaction, to: &PackObject<T>) -> Self;
+
+    // the output of the listing, fee breakdown fields are derived for the viewer if supported.
+    fn from_view<T>(val: db::Transaction, to: &PackObject<T>, _view: View) -> Self {
+        Self::from_txn(val, to)
+    }
+
+    // hides the payer of an anonymous sponsorship from the payee.
+    fn hide_payer(&mut self);
+}
+
+impl TransactionView for TransactionOutput {
+    fn from_txn<T>(val: db::Transaction, to: &PackObject<T>) -> Self {
+        Self::from(val, to)
+    }
+
+    fn from_view<T>(val: db::Transaction, to: &PackObject<T>, view: View) -> Self {
+        Self::from_view(val, to, view)
+    }
+
+    fn hide_payer(&mut self) {
+        self.payer = None;
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/transaction",
+    tag = "transaction",
+    params(QueryUidId),
+    responses(
+        (status = 200, body = super::openapi::TransactionResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn get<O: TransactionView>(
     State(app): State<Arc<AppState>>,
     Extension(ctx): Extension<Arc<ReqContext>>,
     to: PackObject<()>,
     input: Query<QueryUidId>,
-) -> Result<PackObject<SuccessResponse<TransactionOutput>>, HTTPError> {
+) -> Result<PackObject<SuccessResponse<O>>, HTTPError> {
     input.validate()?;
 
     let uid = *input.uid.to_owned();
@@ -89,16 +221,155 @@ pub async fn get(
     .await;
 
     let mut doc = db::Transaction::with_pk(uid, id);
-    doc.get_one(&app.scylla, get_fields(input.fields.clone()))
+    doc.get_one(&app.scylla, get_fields(input.fields.clone())?)
         .await?;
-    Ok(to.with(SuccessResponse::new(TransactionOutput::from(doc, &to))))
+    Ok(to.with(SuccessResponse::new(O::from_txn(doc, &to))))
+}
+
+// lists the transactions derived from the transaction, e.g. its refunds, adjustments or bonuses.
+#[utoipa::path(
+    get,
+    path = "/v1/transaction/children",
+    tag = "transaction",
+    params(QueryUidId),
+    responses(
+        (status = 200, body = super::openapi::TransactionsResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn list_children<O: TransactionView>(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QueryUidId>,
+) -> Result<PackObject<SuccessResponse<Vec<O>>>, HTTPError> {
+    input.validate()?;
+
+    let uid = *input.uid.to_owned();
+    let id = *input.id.to_owned();
+    ctx.set_kvs(vec![
+        ("action", "list_children".into()),
+        ("uid", uid.to_string().into()),
+        ("id", id.to_string().into()),
+    ])
+    .await;
+
+    let res =
+        db::Transaction::list_children(&app.scylla, uid, id, get_fields(input.fields.clone())?)
+            .await?;
+    Ok(to.with(SuccessResponse::new(
+        res.into_iter().map(|r| O::from_txn(r, &to)).collect(),
+    )))
+}
+
+// returns the wallet's first award from the system, its payload carries the referral attribution.
+#[utoipa::path(
+    get,
+    path = "/v1/transaction/first_from_system",
+    tag = "transaction",
+    params(QueryUid),
+    responses(
+        (status = 200, body = super::openapi::TransactionResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn first_from_system<O: TransactionView>(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QueryUid>,
+) -> Result<PackObject<SuccessResponse<O>>, HTTPError> {
+    input.validate()?;
+
+    let uid = *input.uid.to_owned();
+    ctx.set_kvs(vec![
+        ("action", "first_from_system".into()),
+        ("uid", uid.to_string().into()),
+    ])
+    .await;
+
+    let doc =
+        db::Transaction::first_from_system(&app.scylla, uid, get_fields(input.fields.clone())?)
+            .await?;
+    ctx.set("id", doc.id.to_string().into()).await;
+    Ok(to.with(SuccessResponse::new(O::from_txn(doc, &to))))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryPending {
+    #[param(value_type = super::openapi::Xid)]
+    #[validate(custom = "super::validate_uid")]
+    pub uid: PackObject<xid::Id>,
+    #[validate(range(min = 2, max = 1000))]
+    pub page_size: Option<u16>,
+    #[param(value_type = Option<super::openapi::Base64Url>)]
+    pub page_token: Option<PackObject<Vec<u8>>>,
+    pub fields: Option<String>,
+}
+
+// lists the payer's prepared transactions that are not committed or cancelled yet,
+// so that a caller lost the txn id can commit or cancel it.
+// a page may be short, the listing ends when there is no next_page_token.
+#[utoipa::path(
+    get,
+    path = "/v1/transaction/pending",
+    tag = "transaction",
+    params(QueryPending),
+    responses(
+        (status = 200, body = super::openapi::TransactionsResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn list_pending<O: TransactionView>(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QueryPending>,
+) -> Result<PackObject<SuccessResponse<Vec<O>>>, HTTPError> {
+    input.validate()?;
+
+    let uid = *input.uid.to_owned();
+    let page_size = input.page_size.unwrap_or(10);
+    ctx.set_kvs(vec![
+        ("action", "list_pending".into()),
+        ("uid", uid.to_string().into()),
+        ("page_size", page_size.into()),
+    ])
+    .await;
+
+    let (res, next) = db::Transaction::list_pending(
+        &app.scylla,
+        uid,
+        get_fields(input.fields.clone())?,
+        page_size,
+        token_to_xid(&app.mac, &uid, "list_pending", &input.page_token)?,
+    )
+    .await?;
+
+    Ok(to.with(SuccessResponse {
+        total_size: None,
+        next_page_token: next
+            .and_then(|id| to.with_option(token_from_xid(&app.mac, &uid, "list_pending", id))),
+        result: res.into_iter().map(|r| O::from_txn(r, &to)).collect(),
+    }))
 }
 
-pub async fn list_outgo(
+#[utoipa::path(
+    post,
+    path = "/v1/transaction/list_outgo",
+    tag = "transaction",
+    request_body = Pagination,
+    responses(
+        (status = 200, body = super::openapi::TransactionsResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn list_outgo<O: TransactionView>(
     State(app): State<Arc<AppState>>,
     Extension(ctx): Extension<Arc<ReqContext>>,
     to: PackObject<Pagination>,
-) -> Result<PackObject<SuccessResponse<Vec<TransactionOutput>>>, HTTPError> {
+) -> Result<PackObject<SuccessResponse<Vec<O>>>, HTTPError> {
     let (to, input) = to.unpack();
     input.validate()?;
 
@@ -110,7 +381,7 @@ pub async fn list_outgo(
     ])
     .await;
 
-    let fields = input.fields.unwrap_or_default();
+    let fields = get_list_fields(input.fields)?;
     let kind = if input.kind.is_some() {
         Some(
             db::TransactionKind::from_str(&input.kind.unwrap())
@@ -120,17 +391,23 @@ pub async fn list_outgo(
         None
     };
 
+    let uid = *input.uid.unwrap_ref();
     let res = db::Transaction::list(
         &app.scylla,
-        input.uid.unwrap(),
+        uid,
         fields,
         page_size,
-        token_to_xid(&input.page_token),
+        token_to_xid(&app.mac, &uid, "list_outgo", &input.page_token)?,
         kind,
     )
     .await?;
     let next_page_token = if res.len() >= page_size as usize {
-        to.with_option(token_from_xid(res.last().unwrap().id))
+        to.with_option(token_from_xid(
+            &app.mac,
+            &uid,
+            "list_outgo",
+            res.last().unwrap().id,
+        ))
     } else {
         None
     };
@@ -138,18 +415,25 @@ pub async fn list_outgo(
     Ok(to.with(SuccessResponse {
         total_size: None,
         next_page_token,
-        result: res
-            .iter()
-            .map(|r| TransactionOutput::from(r.to_owned(), &to))
-            .collect(),
+        result: res.iter().map(|r| O::from_txn(r.to_owned(), &to)).collect(),
     }))
 }
 
-pub async fn list_income(
+#[utoipa::path(
+    post,
+    path = "/v1/transaction/list_income",
+    tag = "transaction",
+    request_body = Pagination,
+    responses(
+        (status = 200, body = super::openapi::TransactionsResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn list_income<O: TransactionView>(
     State(app): State<Arc<AppState>>,
     Extension(ctx): Extension<Arc<ReqContext>>,
     to: PackObject<Pagination>,
-) -> Result<PackObject<SuccessResponse<Vec<TransactionOutput>>>, HTTPError> {
+) -> Result<PackObject<SuccessResponse<Vec<O>>>, HTTPError> {
     let (to, input) = to.unpack();
     input.validate()?;
 
@@ -161,7 +445,13 @@ pub async fn list_income(
     ])
     .await;
 
-    let fields = input.fields.unwrap_or_default();
+    let mut fields = get_list_fields(input.fields)?;
+    // anonymous is required to hide the payer, and the fee breakdown requires the amounts.
+    if !fields.is_empty() {
+        for field in std::iter::once(db::TransactionField::Anonymous).chain(INCOME_VIEW_FIELDS) {
+            fields.insert(field);
+        }
+    }
     let kind = if input.kind.is_some() {
         Some(
             db::TransactionKind::from_str(&input.kind.unwrap())
@@ -171,16 +461,192 @@ pub async fn list_income(
         None
     };
 
-    let res = db::Transaction::list_by_payee(
+    let uid = *input.uid.unwrap_ref();
+    let (res, next) = db::Transaction::list_by_payee(
         &app.scylla,
-        input.uid.unwrap(),
+        uid,
         fields,
         page_size,
-        token_to_xid(&input.page_token),
+        token_to_xid(&app.mac, &uid, "list_income", &input.page_token)?,
+        kind,
+    )
+    .await?;
+    let next_page_token =
+        next.and_then(|id| to.with_option(token_from_xid(&app.mac, &uid, "list_income", id)));
+
+    Ok(to.with(SuccessResponse {
+        total_size: None,
+        next_page_token,
+        result: res
+            .iter()
+            .map(|r| {
+                let mut rt = O::from_view(r.to_owned(), &to, View::Income(uid));
+                if r.anonymous {
+                    rt.hide_payer();
+                }
+                rt
+            })
+            .collect(),
+    }))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryAggregate {
+    #[param(value_type = super::openapi::Xid)]
+    #[validate(custom = "super::validate_uid")]
+    pub uid: PackObject<xid::Id>,
+    #[param(value_type = Option<super::openapi::Xid>)]
+    pub payee: Option<PackObject<xid::Id>>,
+    #[validate(range(min = 2, max = 1000))]
+    pub page_size: Option<u16>,
+    #[param(value_type = Option<super::openapi::Base64Url>)]
+    pub page_token: Option<PackObject<Vec<u8>>>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryAggregateIncome {
+    #[param(value_type = super::openapi::Xid)]
+    #[validate(custom = "super::validate_uid")]
+    pub uid: PackObject<xid::Id>,
+    #[param(value_type = Option<super::openapi::Xid>)]
+    pub payer: Option<PackObject<xid::Id>>,
+    #[validate(range(min = 2, max = 1000))]
+    pub page_size: Option<u16>,
+    #[param(value_type = Option<super::openapi::Base64Url>)]
+    pub page_token: Option<PackObject<Vec<u8>>>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct AggregateOutput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub payer: PackObject<xid::Id>,
+    #[schema(value_type = super::openapi::Xid)]
+    pub payee: PackObject<xid::Id>,
+    pub amount: i64,
+    pub txns: i64,
+}
+
+impl AggregateOutput {
+    pub fn from<T>(val: db::PayerPayeeTotal, to: &PackObject<T>) -> Self {
+        Self {
+            payer: to.with(val.payer),
+            payee: to.with(val.payee),
+            amount: val.amount,
+            txns: val.txns,
+        }
+    }
+}
+
+// committed outgoing amounts of the uid grouped by payee.
+#[utoipa::path(
+    get,
+    path = "/v1/transaction/aggregate",
+    tag = "transaction",
+    params(QueryAggregate),
+    responses(
+        (status = 200, body = super::openapi::AggregatesResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn aggregate(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QueryAggregate>,
+) -> Result<PackObject<SuccessResponse<Vec<AggregateOutput>>>, HTTPError> {
+    input.validate()?;
+
+    let uid = *input.uid.to_owned();
+    let page_size = input.page_size.unwrap_or(100);
+    ctx.set_kvs(vec![
+        ("action", "aggregate_outgo".into()),
+        ("uid", uid.to_string().into()),
+        ("page_size", page_size.into()),
+    ])
+    .await;
+
+    if let Some(payee) = input.payee.to_owned() {
+        let doc = db::PayerPayeeTotal::get(&app.scylla, uid, payee.unwrap()).await?;
+        return Ok(to.with(SuccessResponse::new(vec![AggregateOutput::from(doc, &to)])));
+    }
+
+    let res = db::PayerPayeeTotal::list_by_payer(
+        &app.scylla,
+        uid,
+        page_size,
+        token_to_xid(&app.mac, &uid, "aggregate_outgo", &input.page_token)?,
+    )
+    .await?;
+    let next_page_token = if res.len() >= page_size as usize {
+        to.with_option(token_from_xid(
+            &app.mac,
+            &uid,
+            "aggregate_outgo",
+            res.last().unwrap().payee,
+        ))
+    } else {
+        None
+    };
+
+    Ok(to.with(SuccessResponse {
+        total_size: None,
+        next_page_token,
+        result: res
+            .iter()
+            .map(|r| AggregateOutput::from(r.to_owned(), &to))
+            .collect(),
+    }))
+}
+
+// committed incoming amounts of the uid grouped by payer, anonymous sponsors are not counted.
+#[utoipa::path(
+    get,
+    path = "/v1/transaction/aggregate_income",
+    tag = "transaction",
+    params(QueryAggregateIncome),
+    responses(
+        (status = 200, body = super::openapi::AggregatesResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn aggregate_income(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QueryAggregateIncome>,
+) -> Result<PackObject<SuccessResponse<Vec<AggregateOutput>>>, HTTPError> {
+    input.validate()?;
+
+    let uid = *input.uid.to_owned();
+    let page_size = input.page_size.unwrap_or(100);
+    ctx.set_kvs(vec![
+        ("action", "aggregate_income".into()),
+        ("uid", uid.to_string().into()),
+        ("page_size", page_size.into()),
+    ])
+    .await;
+
+    if let Some(payer) = input.payer.to_owned() {
+        let doc = db::PayerPayeeTotal::get_income(&app.scylla, payer.unwrap(), uid).await?;
+        return Ok(to.with(SuccessResponse::new(vec![AggregateOutput::from(doc, &to)])));
+    }
+
+    let res = db::PayerPayeeTotal::list_by_payee(
+        &app.scylla,
+        uid,
+        page_size,
+        token_to_xid(&app.mac, &uid, "aggregate_income", &input.page_token)?,
     )
     .await?;
     let next_page_token = if res.len() >= page_size as usize {
-        to.with_option(token_from_xid(res.last().unwrap().id))
+        to.with_option(token_from_xid(
+            &app.mac,
+            &uid,
+            "aggregate_income",
+            res.last().unwrap().payer,
+        ))
     } else {
         None
     };
@@ -190,24 +656,46 @@ pub async fn list_income(
         next_page_token,
         result: res
             .iter()
-            .map(|r| TransactionOutput::from(r.to_owned(), &to))
+            .map(|r| AggregateOutput::from(r.to_owned(), &to))
             .collect(),
     }))
 }
 
-#[derive(Debug, Deserialize, Validate)]
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
 pub struct TransactionInput {
+    #[schema(value_type = super::openapi::Xid)]
     pub uid: PackObject<xid::Id>,
+    #[schema(value_type = super::openapi::Xid)]
     pub id: PackObject<xid::Id>,
+    // proceeds only if the payer's wallet has the sequence and the total balance, 409 with the
+    // current ones in the error's data otherwise. the sequence is checked in the LWT condition
+    // of the payer's wallet, see db::Transaction::check_expected_payer.
+    pub expected_sequence: Option<i64>,
+    // see expected_sequence.
+    pub expected_balance: Option<i64>,
 }
 
-pub async fn commit(
+#[utoipa::path(
+    post,
+    path = "/v1/transaction/commit",
+    tag = "transaction",
+    request_body = TransactionInput,
+    responses(
+        (status = 200, body = super::openapi::TransactionResponse),
+        // partly or not yet committed, the recovery job finishes it, the error's data has the
+        // applied and pending legs.
+        (status = 202, body = super::openapi::ErrorResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn commit<O: TransactionView>(
     State(app): State<Arc<AppState>>,
     Extension(ctx): Extension<Arc<ReqContext>>,
     to: PackObject<TransactionInput>,
-) -> Result<PackObject<SuccessResponse<TransactionOutput>>, HTTPError> {
+) -> Result<PackObject<SuccessResponse<O>>, HTTPError> {
     let (to, input) = to.unpack();
     input.validate()?;
+    db::shed_load()?;
 
     let uid = input.uid.unwrap();
     let id = input.id.unwrap();
@@ -221,30 +709,60 @@ pub async fn commit(
     let mut doc = db::Transaction::with_pk(uid, id);
     doc.get_one(
         &app.scylla,
-        vec![
-            "sequence".to_string(),
-            "payee".to_string(),
-            "sub_payee".to_string(),
-            "status".to_string(),
-            "kind".to_string(),
-            "amount".to_string(),
-            "sys_fee".to_string(),
-            "sub_shares".to_string(),
-        ],
+        [
+            db::TransactionField::Sequence,
+            db::TransactionField::Payee,
+            db::TransactionField::SubPayee,
+            db::TransactionField::Status,
+            db::TransactionField::Kind,
+            db::TransactionField::Amount,
+            db::TransactionField::SysFee,
+            db::TransactionField::SubShares,
+            db::TransactionField::Shares,
+            db::TransactionField::Anonymous,
+        ]
+        .into(),
     )
     .await?;
+    if doc.kind == db::TransactionKind::Adjustment.as_ref() {
+        return Err(HTTPError::new(
+            403,
+            "Adjustment transaction should be approved by admin".to_string(),
+        ));
+    }
+    if doc.kind == db::TransactionKind::Withdraw.as_ref()
+        && db::WithdrawalReview::status_of(&app.scylla, id)
+            .await?
+            .is_some()
+    {
+        return Err(HTTPError::new(
+            403,
+            "Withdrawal transaction should be reviewed by admin".to_string(),
+        ));
+    }
 
+    doc._actor = ctx.user.to_string();
+    doc._expected_sequence = input.expected_sequence;
+    doc._expected_balance = input.expected_balance;
     doc.commit(&app.scylla, &app.mac).await?;
-    let mut credits = doc.credits();
-    db::Credit::save_all(&app.scylla, &mut credits).await?;
-    Ok(to.with(SuccessResponse::new(TransactionOutput::from(doc, &to))))
+    Ok(to.with(SuccessResponse::new(O::from_txn(doc, &to))))
 }
 
-pub async fn cancel(
+#[utoipa::path(
+    post,
+    path = "/v1/transaction/cancel",
+    tag = "transaction",
+    request_body = TransactionInput,
+    responses(
+        (status = 200, body = super::openapi::TransactionResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn cancel<O: TransactionView>(
     State(app): State<Arc<AppState>>,
     Extension(ctx): Extension<Arc<ReqContext>>,
     to: PackObject<TransactionInput>,
-) -> Result<PackObject<SuccessResponse<TransactionOutput>>, HTTPError> {
+) -> Result<PackObject<SuccessResponse<O>>, HTTPError> {
     let (to, input) = to.unpack();
     input.validate()?;
 
@@ -260,19 +778,97 @@ pub async fn cancel(
     let mut doc = db::Transaction::with_pk(uid, id);
     doc.get_one(
         &app.scylla,
-        vec![
-            "sequence".to_string(),
-            "payee".to_string(),
-            "sub_payee".to_string(),
-            "status".to_string(),
-            "kind".to_string(),
-            "amount".to_string(),
-            "sys_fee".to_string(),
-            "sub_shares".to_string(),
-        ],
+        [
+            db::TransactionField::Sequence,
+            db::TransactionField::Payee,
+            db::TransactionField::SubPayee,
+            db::TransactionField::Status,
+            db::TransactionField::Kind,
+            db::TransactionField::Amount,
+            db::TransactionField::SysFee,
+            db::TransactionField::SubShares,
+            db::TransactionField::Shares,
+            db::TransactionField::Anonymous,
+        ]
+        .into(),
     )
     .await?;
+    if doc.kind == db::TransactionKind::Adjustment.as_ref() {
+        return Err(HTTPError::new(
+            403,
+            "Adjustment transaction should be approved by admin".to_string(),
+        ));
+    }
+    if doc.kind == db::TransactionKind::Withdraw.as_ref()
+        && db::WithdrawalReview::status_of(&app.scylla, id)
+            .await?
+            .is_some()
+    {
+        return Err(HTTPError::new(
+            403,
+            "Withdrawal transaction should be reviewed by admin".to_string(),
+        ));
+    }
+    if doc.status == 1 {
+        doc.check_cancel_deadline(unix_ms() as i64)?;
+    }
 
+    doc._actor = ctx.user.to_string();
+    doc._expected_sequence = input.expected_sequence;
+    doc._expected_balance = input.expected_balance;
     doc.cancel(&app.scylla, &app.mac).await?;
-    Ok(to.with(SuccessResponse::new(TransactionOutput::from(doc, &to))))
+    Ok(to.with(SuccessResponse::new(O::from_txn(doc, &to))))
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct TxnHistoryOutput {
+    pub changed_at: i64, // unix ms
+    pub from_status: i8,
+    pub to_status: i8,
+    pub actor: String,
+}
+
+impl TxnHistoryOutput {
+    pub fn from(val: db::TxnHistory) -> Self {
+        Self {
+            changed_at: val.changed_at,
+            from_status: val.from_status,
+            to_status: val.to_status,
+            actor: val.actor,
+        }
+    }
+}
+
+// the status transitions of the transaction in order, for support investigations.
+#[utoipa::path(
+    get,
+    path = "/v1/admin/transaction/history",
+    tag = "admin",
+    params(QueryUidId),
+    responses(
+        (status = 200, body = super::openapi::TxnHistoryResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn history(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QueryUidId>,
+) -> Result<PackObject<SuccessResponse<Vec<TxnHistoryOutput>>>, HTTPError> {
+    input.validate()?;
+
+    let uid = *input.uid.to_owned();
+    let id = *input.id.to_owned();
+    ctx.set_kvs(vec![
+        ("action", "get_transaction_history".into()),
+        ("uid", uid.to_string().into()),
+        ("id", id.to_string().into()),
+    ])
+    .await;
+
+    let res = db::TxnHistory::list(&app.scylla, uid, id).await?;
+    Ok(to.with(SuccessResponse::new(
+        res.into_iter().map(TxnHistoryOutput::from).collect(),
+    )))
 }
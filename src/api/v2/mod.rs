@@ -0,0 +1,38 @@
+// API v2 revises the outputs: enums as strings, times as RFC3339, and fields always presented.
+// the handlers are shared with v1 through generics, only the outputs differ.
+
+pub mod transaction;
+
+// RFC3339 UTC time of the unix seconds, e.g. "2023-08-01T09:30:00Z".
+pub fn rfc3339(secs: i64) -> String {
+    let days = secs.div_euclid(86400);
+    let rem = secs.rem_euclid(86400);
+
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+// RFC3339 creation time of the xid.
+pub fn xid_time(id: &xid::Id) -> String {
+    let mut secs = [0u8; 4];
+    secs.copy_from_slice(&id.0[..4]);
+    rfc3339(u32::from_be_bytes(secs) as i64)
+}
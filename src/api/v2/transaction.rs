@@ -0,0 +1,268 @@
+use axum::{
+    extract::{Query, State},
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+use axum_web::context::ReqContext;
+use axum_web::erring::{HTTPError, SuccessResponse};
+use axum_web::object::PackObject;
+
+use crate::api::{
+    transaction::{self, QueryPending, TransactionInput, TransactionView},
+    AppState, Pagination, QueryUid, QueryUidId,
+};
+use crate::db;
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+#[schema(as = v2::TransactionOutput)]
+pub struct TransactionOutput {
+    #[schema(value_type = crate::api::openapi::Xid)]
+    pub id: PackObject<xid::Id>,
+    pub sequence: i64,
+    // null for an anonymous sponsorship listed to the payee.
+    #[schema(value_type = Option<crate::api::openapi::Xid>)]
+    pub payer: Option<PackObject<xid::Id>>,
+    #[schema(value_type = crate::api::openapi::Xid)]
+    pub payee: PackObject<xid::Id>,
+    #[schema(value_type = Option<crate::api::openapi::Xid>)]
+    pub sub_payee: Option<PackObject<xid::Id>>,
+    pub status: String,
+    pub kind: String,
+    pub amount: i64,
+    pub sys_fee: i64,
+    pub sub_shares: i64,
+    pub created_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Vec<Object>>)]
+    pub shares: Option<Vec<(PackObject<xid::Id>, u16)>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anonymous: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    // 1 if the description was redacted by moderation, 2 if blocked.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub moderation: Option<i8>,
+    // the original transaction of a refund, adjustment or bonus, and its payer.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<crate::api::openapi::Xid>)]
+    pub ref_uid: Option<PackObject<xid::Id>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<crate::api::openapi::Xid>)]
+    pub ref_txn: Option<PackObject<xid::Id>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<crate::api::openapi::Base64Url>)]
+    pub payload: Option<PackObject<Vec<u8>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<String>,
+}
+
+pub fn status_name(status: i8) -> &'static str {
+    match status {
+        0 => "preparing",
+        1 => "prepared",
+        2 => "committing",
+        3 => "committed",
+        -1 => "canceling",
+        -2 => "canceled",
+        _ => "unknown",
+    }
+}
+
+impl TransactionView for TransactionOutput {
+    fn from_txn<T>(val: db::Transaction, to: &PackObject<T>) -> Self {
+        let mut rt = Self {
+            id: to.with(val.id),
+            sequence: val.sequence,
+            payer: to.with_option(Some(val.uid)),
+            payee: to.with(val.payee),
+            sub_payee: to.with_option(val.sub_payee),
+            status: status_name(val.status).to_string(),
+            kind: val.kind.clone(),
+            amount: val.amount,
+            sys_fee: val.sys_fee,
+            sub_shares: val.sub_shares,
+            created_at: super::xid_time(&val.id),
+            ..Default::default()
+        };
+
+        for v in val._fields {
+            match v.as_str() {
+                "shares" => {
+                    rt.shares = Some(
+                        val.shares
+                            .iter()
+                            .map(|(uid, bps)| (to.with(*uid), *bps))
+                            .collect(),
+                    )
+                }
+                "anonymous" => rt.anonymous = Some(val.anonymous),
+                "moderation" => rt.moderation = Some(val.moderation),
+                "ref_uid" => rt.ref_uid = to.with_option(val.ref_uid),
+                "ref_txn" => rt.ref_txn = to.with_option(val.ref_txn),
+                "description" => rt.description = Some(val.description.to_owned()),
+                "updated_at" if val.updated_at > 0 => {
+                    rt.updated_at = Some(super::rfc3339(val.updated_at / 1000))
+                }
+                "payload" => rt.payload = Some(to.with(val.payload.to_owned())),
+                _ => {}
+            }
+        }
+
+        rt
+    }
+
+    fn hide_payer(&mut self) {
+        self.payer = None;
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/v2/transaction",
+    tag = "transaction",
+    params(QueryUidId),
+    responses(
+        (status = 200, body = crate::api::openapi::TransactionV2Response),
+        (status = "default", body = crate::api::openapi::ErrorResponse)
+    )
+)]
+pub async fn get(
+    state: State<Arc<AppState>>,
+    ctx: Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QueryUidId>,
+) -> Result<PackObject<SuccessResponse<TransactionOutput>>, HTTPError> {
+    transaction::get(state, ctx, to, input).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/v2/transaction/first_from_system",
+    tag = "transaction",
+    params(QueryUid),
+    responses(
+        (status = 200, body = crate::api::openapi::TransactionV2Response),
+        (status = "default", body = crate::api::openapi::ErrorResponse)
+    )
+)]
+pub async fn first_from_system(
+    state: State<Arc<AppState>>,
+    ctx: Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QueryUid>,
+) -> Result<PackObject<SuccessResponse<TransactionOutput>>, HTTPError> {
+    transaction::first_from_system(state, ctx, to, input).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/v2/transaction/children",
+    tag = "transaction",
+    params(QueryUidId),
+    responses(
+        (status = 200, body = crate::api::openapi::TransactionsV2Response),
+        (status = "default", body = crate::api::openapi::ErrorResponse)
+    )
+)]
+pub async fn list_children(
+    state: State<Arc<AppState>>,
+    ctx: Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QueryUidId>,
+) -> Result<PackObject<SuccessResponse<Vec<TransactionOutput>>>, HTTPError> {
+    transaction::list_children(state, ctx, to, input).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/v2/transaction/pending",
+    tag = "transaction",
+    params(QueryPending),
+    responses(
+        (status = 200, body = crate::api::openapi::TransactionsV2Response),
+        (status = "default", body = crate::api::openapi::ErrorResponse)
+    )
+)]
+pub async fn list_pending(
+    state: State<Arc<AppState>>,
+    ctx: Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QueryPending>,
+) -> Result<PackObject<SuccessResponse<Vec<TransactionOutput>>>, HTTPError> {
+    transaction::list_pending(state, ctx, to, input).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/v2/transaction/list_outgo",
+    tag = "transaction",
+    request_body = Pagination,
+    responses(
+        (status = 200, body = crate::api::openapi::TransactionsV2Response),
+        (status = "default", body = crate::api::openapi::ErrorResponse)
+    )
+)]
+pub async fn list_outgo(
+    state: State<Arc<AppState>>,
+    ctx: Extension<Arc<ReqContext>>,
+    to: PackObject<Pagination>,
+) -> Result<PackObject<SuccessResponse<Vec<TransactionOutput>>>, HTTPError> {
+    transaction::list_outgo(state, ctx, to).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/v2/transaction/list_income",
+    tag = "transaction",
+    request_body = Pagination,
+    responses(
+        (status = 200, body = crate::api::openapi::TransactionsV2Response),
+        (status = "default", body = crate::api::openapi::ErrorResponse)
+    )
+)]
+pub async fn list_income(
+    state: State<Arc<AppState>>,
+    ctx: Extension<Arc<ReqContext>>,
+    to: PackObject<Pagination>,
+) -> Result<PackObject<SuccessResponse<Vec<TransactionOutput>>>, HTTPError> {
+    transaction::list_income(state, ctx, to).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/v2/transaction/commit",
+    tag = "transaction",
+    request_body = TransactionInput,
+    responses(
+        (status = 200, body = crate::api::openapi::TransactionV2Response),
+        (status = "default", body = crate::api::openapi::ErrorResponse)
+    )
+)]
+pub async fn commit(
+    state: State<Arc<AppState>>,
+    ctx: Extension<Arc<ReqContext>>,
+    to: PackObject<TransactionInput>,
+) -> Result<PackObject<SuccessResponse<TransactionOutput>>, HTTPError> {
+    transaction::commit(state, ctx, to).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/v2/transaction/cancel",
+    tag = "transaction",
+    request_body = TransactionInput,
+    responses(
+        (status = 200, body = crate::api::openapi::TransactionV2Response),
+        (status = "default", body = crate::api::openapi::ErrorResponse)
+    )
+)]
+pub async fn cancel(
+    state: State<Arc<AppState>>,
+    ctx: Extension<Arc<ReqContext>>,
+    to: PackObject<TransactionInput>,
+) -> Result<PackObject<SuccessResponse<TransactionOutput>>, HTTPError> {
+    transaction::cancel(state, ctx, to).await
+}
@@ -1,29 +1,50 @@
 use axum::{
     extract::{Query, State},
+    http::{HeaderMap, HeaderValue},
+    response::{IntoResponse, Response},
     Extension,
 };
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::{str::FromStr, sync::Arc};
+use utoipa::{IntoParams, ToSchema};
 use validator::Validate;
 
-use axum_web::context::ReqContext;
+use axum_web::context::{extract_header, unix_ms, ReqContext};
 use axum_web::erring::{HTTPError, SuccessResponse};
 use axum_web::object::PackObject;
 
 use crate::db;
+use crate::moderation;
+use crate::money;
 use crate::{
-    api::{token_from_xid, token_to_xid, AppState, Pagination, QueryUid},
+    api::{
+        check_payload, get_list_fields, token_from_xid, token_to_xid,
+        transaction::TransactionOutput, AppState, Pagination, QueryUid, SERVICE_HEADER,
+    },
     db::SYS_ID,
 };
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
 pub struct WalletOutput {
     pub sequence: i64,
     pub award: i64,
     pub topup: i64,
     pub income: i64,
     pub credits: i64,
+    #[schema(value_type = super::openapi::Xid)]
     pub txn: PackObject<xid::Id>,
+    pub pending_out: i64, // prepared but uncommitted outgoing amount, already deducted from balance
+    pub max_overdraw: i64,
+    pub sys_fee_rate: u16,    // basis points
+    pub income_fee_rate: u16, // basis points
+    pub closed_at: i64,       // 0 for open wallets
+    pub updated_at: i64,      // unix ms, 0 for wallets not updated since the column was added
+    // the committed transaction of an auto_commit spending.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transaction: Option<TransactionOutput>,
+    // unix ms after which the prepared spend can not be canceled by the caller.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cancel_deadline: Option<i64>,
 }
 
 impl WalletOutput {
@@ -35,10 +56,28 @@ impl WalletOutput {
             income: val.income,
             credits: val.credits,
             txn: to.with(val.txn),
+            pending_out: val.pending_out,
+            max_overdraw: val.overdraw_limit(),
+            sys_fee_rate: db::SYS_FEE_RATE,
+            income_fee_rate: db::income_fee_rate(val.credits),
+            closed_at: val.closed_at,
+            updated_at: val.updated_at,
+            transaction: None,
+            cancel_deadline: None,
         }
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/v1/wallet",
+    tag = "wallet",
+    params(QueryUid),
+    responses(
+        (status = 200, body = super::openapi::WalletResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
 pub async fn get(
     State(app): State<Arc<AppState>>,
     Extension(ctx): Extension<Arc<ReqContext>>,
@@ -60,8 +99,660 @@ pub async fn get(
     Ok(to.with(SuccessResponse::new(WalletOutput::from(doc, &to))))
 }
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+// the coin of wallet amounts from conf.
+#[utoipa::path(
+    get,
+    path = "/v1/wallet/denomination",
+    tag = "wallet",
+    responses(
+        (status = 200, body = super::openapi::DenominationResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn denomination(
+    to: PackObject<()>,
+    State(_app): State<Arc<AppState>>,
+) -> Result<PackObject<SuccessResponse<money::Denomination>>, HTTPError> {
+    Ok(to.with(SuccessResponse::new(money::denomination())))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
+pub struct MaxOverdrawInput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub uid: PackObject<xid::Id>,
+    // None to use the global max overdraw.
+    #[validate(range(min = 0, max = 1000000))]
+    pub max_overdraw: Option<i64>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/admin/wallet/max_overdraw",
+    tag = "admin",
+    request_body = MaxOverdrawInput,
+    responses(
+        (status = 200, body = super::openapi::WalletResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn update_max_overdraw(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<MaxOverdrawInput>,
+) -> Result<PackObject<SuccessResponse<WalletOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    let uid = input.uid.unwrap();
+    ctx.set_kvs(vec![
+        ("action", "update_max_overdraw".into()),
+        ("uid", uid.to_string().into()),
+        ("max_overdraw", input.max_overdraw.into()),
+    ])
+    .await;
+
+    let mut doc = db::Wallet::with_pk(uid);
+    doc.update_max_overdraw(&app.scylla, input.max_overdraw)
+        .await?;
+    doc.get_one(&app.scylla).await?;
+    Ok(to.with(SuccessResponse::new(WalletOutput::from(doc, &to))))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
+pub struct CloseWalletInput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub uid: PackObject<xid::Id>,
+    pub description: Option<String>,
+}
+
+// closes the wallet on account deletion, the remaining balance is swept to the system wallet
+// with a final transaction. transactions can not be prepared with a closed wallet after that.
+#[utoipa::path(
+    post,
+    path = "/v1/admin/wallet/close",
+    tag = "admin",
+    request_body = CloseWalletInput,
+    responses(
+        (status = 200, body = super::openapi::WalletResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn close(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<CloseWalletInput>,
+) -> Result<PackObject<SuccessResponse<WalletOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    let uid = input.uid.unwrap();
+    if uid == SYS_ID {
+        return Err(HTTPError::new(
+            400,
+            "Can not close system wallet".to_string(),
+        ));
+    }
+    ctx.set_kvs(vec![
+        ("action", "close_wallet".into()),
+        ("uid", uid.to_string().into()),
+    ])
+    .await;
+
+    let mut wallet = db::Wallet::with_pk(uid);
+    wallet.get_one(&app.scylla).await?;
+    wallet.verify_checksum(&app.mac)?;
+    wallet.check_open()?;
+    if wallet.pending_out != 0 {
+        return Err(HTTPError::new(
+            409,
+            format!(
+                "Wallet has outstanding prepared transactions, pending_out {}",
+                wallet.pending_out
+            ),
+        ));
+    }
+    let balance = wallet.balance();
+    if balance < 0 {
+        return Err(HTTPError::new(
+            409,
+            format!("Wallet has negative balance {}", balance),
+        ));
+    }
+    ctx.set("balance", balance.into()).await;
+
+    if balance == 0 {
+        if !wallet.close(&app.scylla).await? {
+            return Err(HTTPError::new(
+                409,
+                "Wallet was updated while closing, please try again".to_string(),
+            ));
+        }
+        return Ok(to.with(SuccessResponse::new(WalletOutput::from(wallet, &to))));
+    }
+
+    // no outgoing transaction can be prepared with a zero balance after the sweep prepared.
+    let mut txn = db::Transaction::with_uid(uid);
+    txn.description = input
+        .description
+        .unwrap_or_else(|| "wallet.close".to_string());
+    txn.prepare(
+        &app.scylla,
+        &app.mac,
+        SYS_ID,
+        db::TransactionKind::Sweep,
+        balance,
+    )
+    .await?;
+    ctx.set("txn", txn.id.to_string().into()).await;
+
+    wallet.get_one(&app.scylla).await?;
+    if wallet.txn != txn.id || !wallet.close(&app.scylla).await? {
+        txn.cancel(&app.scylla, &app.mac).await?;
+        return Err(HTTPError::new(
+            409,
+            "Wallet was updated while closing, please try again".to_string(),
+        ));
+    }
+
+    txn.commit(&app.scylla, &app.mac).await?;
+    wallet.get_one(&app.scylla).await?;
+    Ok(to.with(SuccessResponse::new(WalletOutput::from(wallet, &to))))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QuerySystemStats {
+    // number of recent days of daily totals, default to 7.
+    #[validate(range(min = 1, max = 93))]
+    pub days: Option<u16>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct DailyTotalOutput {
+    pub day: i32, // days since unix epoch
+    pub kind: String,
+    pub amount: i64,
+    pub sys_fee: i64,
+    pub txns: i64,
+}
+
+impl DailyTotalOutput {
+    pub fn from(val: db::SystemDailyTotal) -> Self {
+        Self {
+            day: val.day,
+            kind: val.kind,
+            amount: val.amount,
+            sys_fee: val.sys_fee,
+            txns: val.txns,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct ChargeDailyTotalOutput {
+    pub day: i32, // days since unix epoch
+    pub currency: String,
+    pub amount: i64,
+    pub provider_fee: i64,
+    pub net_amount: i64,
+    pub charges: i64,
+}
+
+impl ChargeDailyTotalOutput {
+    pub fn from(val: db::ChargeDailyTotal) -> Self {
+        Self {
+            day: val.day,
+            currency: val.currency,
+            amount: val.amount,
+            provider_fee: val.provider_fee,
+            net_amount: val.net_amount,
+            charges: val.charges,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct SystemStatsOutput {
+    pub sequence: i64,       // number of committed updates of the system wallet
+    pub awards_issued: i64,  // outstanding awards, including adjustments
+    pub topups_issued: i64,  // topups issued
+    pub fees_collected: i64, // accumulated system fees and system income
+    pub pending_out: i64,    // prepared but uncommitted, already counted in issued
+    pub daily: Vec<DailyTotalOutput>, // newest day first
+    pub charges: Vec<ChargeDailyTotalOutput>, // completed charges, newest day first
+}
+
+// system wide totals from the system wallet, and daily totals maintained at commit time.
+#[utoipa::path(
+    get,
+    path = "/v1/admin/system_stats",
+    tag = "admin",
+    params(QuerySystemStats),
+    responses(
+        (status = 200, body = super::openapi::SystemStatsResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn system_stats(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    Query(input): Query<QuerySystemStats>,
+) -> Result<PackObject<SuccessResponse<SystemStatsOutput>>, HTTPError> {
+    input.validate()?;
+
+    let days = input.days.unwrap_or(7) as i32;
+    ctx.set_kvs(vec![
+        ("action", "system_stats".into()),
+        ("days", days.into()),
+    ])
+    .await;
+
+    let mut wallet = db::Wallet::with_pk(SYS_ID);
+    wallet.get_one(&app.scylla).await?;
+    wallet.verify_checksum(&app.mac)?;
+
+    let last_day = (unix_ms() as i64 / db::DAY_MS) as i32;
+    let daily = db::SystemDailyTotal::list(&app.scylla, last_day - days + 1, last_day).await?;
+    let charges = db::ChargeDailyTotal::list(&app.scylla, last_day - days + 1, last_day).await?;
+    Ok(to.with(SuccessResponse::new(SystemStatsOutput {
+        sequence: wallet.sequence,
+        awards_issued: -wallet.award,
+        topups_issued: -wallet.topup,
+        fees_collected: wallet.income,
+        pending_out: wallet.pending_out,
+        daily: daily.into_iter().map(DailyTotalOutput::from).collect(),
+        charges: charges
+            .into_iter()
+            .map(ChargeDailyTotalOutput::from)
+            .collect(),
+    })))
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct LiabilityOutput {
+    pub bucket: String,     // award, topup or income
+    pub amount: i64,        // total owed to users, maintained on each balance delta
+    pub expected: i64,      // sum of user wallets at the last reconciliation
+    pub drift: i64,         // amount minus expected found by the last reconciliation
+    pub reconciled_at: i64, // unix ms, 0 if never reconciled
+}
+
+impl LiabilityOutput {
+    pub fn from(val: db::Liability) -> Self {
+        Self {
+            bucket: val.bucket,
+            amount: val.amount,
+            expected: val.expected,
+            drift: val.drift,
+            reconciled_at: val.reconciled_at,
+        }
+    }
+}
+
+// funds owed to users per balance bucket, for the segregation of wallet funds.
+#[utoipa::path(
+    get,
+    path = "/v1/admin/liabilities",
+    tag = "admin",
+    responses(
+        (status = 200, body = super::openapi::LiabilitiesResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn liabilities(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+) -> Result<PackObject<SuccessResponse<Vec<LiabilityOutput>>>, HTTPError> {
+    ctx.set_kvs(vec![("action", "get_liabilities".into())])
+        .await;
+
+    let res = db::Liability::list(&app.scylla).await?;
+    Ok(to.with(SuccessResponse::new(
+        res.into_iter().map(LiabilityOutput::from).collect(),
+    )))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryDailyStats {
+    // number of days of the series, default to 30.
+    #[validate(range(min = 1, max = 366))]
+    pub days: Option<u16>,
+    // the last day of the series, days since unix epoch, default to today.
+    pub last_day: Option<i32>,
+    // comma separated transaction kinds, e.g. "sponsor,subscribe".
+    #[validate(length(min = 1, max = 256))]
+    pub kind: Option<String>,
+    // charge provider, the charge series requires both provider and currency.
+    #[validate(length(min = 1, max = 32))]
+    pub provider: Option<String>,
+    #[validate(length(min = 1, max = 16))]
+    pub currency: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct DailyTxnStatsOutput {
+    pub kind: String,
+    pub day: i32, // days since unix epoch
+    pub txns: i64,
+    pub amount: i64,
+    pub sys_fee: i64,
+    pub sub_shares: i64,
+}
+
+impl DailyTxnStatsOutput {
+    pub fn from(val: db::DailyTxnStats) -> Self {
+        Self {
+            kind: val.kind,
+            day: val.day,
+            txns: val.txns,
+            amount: val.amount,
+            sys_fee: val.sys_fee,
+            sub_shares: val.sub_shares,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct DailyChargeStatsOutput {
+    pub provider: String,
+    pub currency: String,
+    pub day: i32, // days since unix epoch
+    pub charges: i64,
+    pub amount: i64,
+    pub quantity: i64,
+    pub provider_fee: i64,
+}
+
+impl DailyChargeStatsOutput {
+    pub fn from(val: db::DailyChargeStats) -> Self {
+        Self {
+            provider: val.provider,
+            currency: val.currency,
+            day: val.day,
+            charges: val.charges,
+            amount: val.amount,
+            quantity: val.quantity,
+            provider_fee: val.provider_fee,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct DailyStatsOutput {
+    pub first_day: i32,
+    pub last_day: i32,
+    // a series per kind, oldest day first, days without transactions are zeros.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub transactions: Vec<Vec<DailyTxnStatsOutput>>,
+    // oldest day first, days without charges are zeros.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub charges: Vec<DailyChargeStatsOutput>,
+}
+
+// daily rollups of committed transactions by kind and completed charges by provider,
+// maintained at commit and complete time, as time series for dashboards.
+#[utoipa::path(
+    get,
+    path = "/v1/admin/stats/daily",
+    tag = "admin",
+    params(QueryDailyStats),
+    responses(
+        (status = 200, body = super::openapi::DailyStatsResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn daily_stats(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    Query(input): Query<QueryDailyStats>,
+) -> Result<PackObject<SuccessResponse<DailyStatsOutput>>, HTTPError> {
+    input.validate()?;
+
+    let mut kinds: Vec<String> = Vec::new();
+    if let Some(kind) = &input.kind {
+        for k in kind.split(',').map(|k| k.trim()).filter(|k| !k.is_empty()) {
+            let k = db::TransactionKind::from_str(k)
+                .map_err(|_| HTTPError::new(400, format!("invalid kind {}", k)))?;
+            let k = k.as_ref().to_string();
+            if !kinds.contains(&k) {
+                kinds.push(k);
+            }
+        }
+    }
+    let charge = match (&input.provider, &input.currency) {
+        (Some(provider), Some(currency)) => Some((provider.to_owned(), currency.to_lowercase())),
+        (None, None) => None,
+        _ => {
+            return Err(HTTPError::new(
+                400,
+                "provider and currency should be given together".to_string(),
+            ))
+        }
+    };
+    if kinds.is_empty() && charge.is_none() {
+        return Err(HTTPError::new(
+            400,
+            "kind or provider and currency required".to_string(),
+        ));
+    }
+
+    let days = input.days.unwrap_or(30) as i32;
+    let last_day = input
+        .last_day
+        .unwrap_or((unix_ms() as i64 / db::DAY_MS) as i32);
+    let first_day = last_day - days + 1;
+    ctx.set_kvs(vec![
+        ("action", "daily_stats".into()),
+        ("first_day", first_day.into()),
+        ("last_day", last_day.into()),
+        ("kinds", kinds.join(",").into()),
+    ])
+    .await;
+
+    let mut output = DailyStatsOutput {
+        first_day,
+        last_day,
+        ..Default::default()
+    };
+    for kind in &kinds {
+        let series = db::DailyTxnStats::list(&app.scylla, kind, first_day, last_day).await?;
+        output
+            .transactions
+            .push(series.into_iter().map(DailyTxnStatsOutput::from).collect());
+    }
+    if let Some((provider, currency)) = charge {
+        let series =
+            db::DailyChargeStats::list(&app.scylla, &provider, &currency, first_day, last_day)
+                .await?;
+        output.charges = series
+            .into_iter()
+            .map(DailyChargeStatsOutput::from)
+            .collect();
+    }
+    Ok(to.with(SuccessResponse::new(output)))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryIntegrity {
+    // number of wallets scanned per page, default to 100.
+    #[validate(range(min = 2, max = 1000))]
+    pub page_size: Option<u16>,
+    #[param(value_type = Option<super::openapi::Base64Url>)]
+    pub page_token: Option<PackObject<Vec<u8>>>,
+    // number of recent outgoing transactions matched per wallet, default to 10.
+    #[validate(range(min = 1, max = 100))]
+    pub depth: Option<u16>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct IntegrityOutput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub uid: PackObject<xid::Id>,
+    pub sequence: i64,
+    #[schema(value_type = super::openapi::Xid)]
+    pub txn: PackObject<xid::Id>,
+    pub issue: String,
+}
+
+// scans a page of wallets and lists the ones whose txn or sequence can not be matched against
+// the transaction table. the result may be empty while next_page_token is present.
+#[utoipa::path(
+    get,
+    path = "/v1/admin/wallet/integrity",
+    tag = "admin",
+    params(QueryIntegrity),
+    responses(
+        (status = 200, body = super::openapi::IntegrityResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn integrity(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QueryIntegrity>,
+) -> Result<PackObject<SuccessResponse<Vec<IntegrityOutput>>>, HTTPError> {
+    input.validate()?;
+
+    let page_size = input.page_size.unwrap_or(100);
+    let depth = input.depth.unwrap_or(10);
+    ctx.set_kvs(vec![
+        ("action", "scan_integrity".into()),
+        ("page_size", page_size.into()),
+        ("depth", depth.into()),
+    ])
+    .await;
+
+    let kind = "scan_integrity";
+    let page_token = token_to_xid(&app.mac, &SYS_ID, kind, &input.page_token)?;
+    let wallets = db::Wallet::scan(&app.scylla, page_size, page_token).await?;
+    let next_page_token = if wallets.len() >= page_size as usize {
+        to.with_option(token_from_xid(
+            &app.mac,
+            &SYS_ID,
+            kind,
+            wallets.last().unwrap().uid,
+        ))
+    } else {
+        None
+    };
+
+    let mut res: Vec<IntegrityOutput> = Vec::new();
+    for wallet in wallets {
+        if wallet.is_system() || wallet.sequence == 0 {
+            continue;
+        }
+
+        let issue = match wallet.check_sequence(&app.scylla, depth).await? {
+            Some(issue) => Some(issue),
+            None => wallet.check_last_txn(&app.scylla).await?,
+        };
+        if let Some(issue) = issue {
+            res.push(IntegrityOutput {
+                uid: to.with(wallet.uid),
+                sequence: wallet.sequence,
+                txn: to.with(wallet.txn),
+                issue,
+            });
+        }
+    }
+    ctx.set("issues", res.len().into()).await;
+
+    Ok(to.with(SuccessResponse {
+        total_size: None,
+        next_page_token,
+        result: res,
+    }))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
+pub struct BackfillChecksumInput {
+    // number of wallets scanned per call, default to 100.
+    #[validate(range(min = 2, max = 1000))]
+    pub page_size: Option<u16>,
+    #[schema(value_type = Option<super::openapi::Base64Url>)]
+    pub page_token: Option<PackObject<Vec<u8>>>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct BackfillChecksumOutput {
+    pub scanned: u32,
+    pub backfilled: u32,
+    pub skipped: u32, // updated meanwhile, they have a checksum
+    // continue with it until it is absent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<super::openapi::Base64Url>)]
+    pub next_page_token: Option<PackObject<Vec<u8>>>,
+}
+
+// scans a page of wallets and writes the checksum of legacy wallets created before checksums,
+// balances and sequences are not changed. it is throttled by the page size per call, the
+// cmd/backfill-checksum command walks all wallets at a given rate.
+#[utoipa::path(
+    post,
+    path = "/v1/admin/wallet/checksum/backfill",
+    tag = "admin",
+    request_body = BackfillChecksumInput,
+    responses(
+        (status = 200, body = super::openapi::BackfillChecksumResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn backfill_checksum(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<BackfillChecksumInput>,
+) -> Result<PackObject<SuccessResponse<BackfillChecksumOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    let page_size = input.page_size.unwrap_or(100);
+    let kind = "backfill_checksum";
+    let page_token = token_to_xid(&app.mac, &SYS_ID, kind, &input.page_token)?;
+    let wallets = db::Wallet::scan(&app.scylla, page_size, page_token).await?;
+
+    let mut output = BackfillChecksumOutput {
+        scanned: wallets.len() as u32,
+        ..Default::default()
+    };
+    if wallets.len() >= page_size as usize {
+        output.next_page_token = to.with_option(token_from_xid(
+            &app.mac,
+            &SYS_ID,
+            kind,
+            wallets.last().unwrap().uid,
+        ));
+    }
+    for mut wallet in wallets {
+        if wallet.has_checksum() {
+            continue;
+        }
+        if wallet.backfill_checksum(&app.scylla, &app.mac).await? {
+            output.backfilled += 1;
+        } else {
+            output.skipped += 1;
+        }
+    }
+
+    ctx.set_kvs(vec![
+        ("action", "backfill_checksum".into()),
+        ("page_size", page_size.into()),
+        ("scanned", output.scanned.into()),
+        ("backfilled", output.backfilled.into()),
+        ("skipped", output.skipped.into()),
+    ])
+    .await;
+    Ok(to.with(SuccessResponse::new(output)))
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
 pub struct CreditOutput {
+    #[schema(value_type = super::openapi::Xid)]
     pub txn: PackObject<xid::Id>,
     pub kind: String,
     pub amount: i64,
@@ -79,6 +770,16 @@ impl CreditOutput {
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/v1/wallet/list_credits",
+    tag = "wallet",
+    request_body = Pagination,
+    responses(
+        (status = 200, body = super::openapi::CreditsResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
 pub async fn list_credits(
     State(app): State<Arc<AppState>>,
     Extension(ctx): Extension<Arc<ReqContext>>,
@@ -95,18 +796,43 @@ pub async fn list_credits(
     ])
     .await;
 
-    let fields = input.fields.unwrap_or_default();
-    let res = db::Credit::list(
-        &app.scylla,
-        input.uid.unwrap(),
-        fields,
-        page_size,
-        token_to_xid(&input.page_token),
-        None,
-    )
-    .await?;
+    let kind = match input.kind {
+        Some(kind) => {
+            ctx.set("kind", kind.clone().into()).await;
+            Some(
+                db::CreditKind::from_str(&kind)
+                    .map_err(|_| HTTPError::new(400, format!("Invalid credit kind {}", kind)))?,
+            )
+        }
+        None => None,
+    };
+
+    let order = match input.order {
+        Some(order) => {
+            ctx.set("order", order.clone().into()).await;
+            db::CreditOrder::from_str(&order)
+                .map_err(|_| HTTPError::new(400, format!("Invalid order {}", order)))?
+        }
+        None => db::CreditOrder::default(),
+    };
+    // page tokens of one order are not valid for the other.
+    let token_kind = match order {
+        db::CreditOrder::Asc => "list_credit_asc",
+        db::CreditOrder::Desc => "list_credit",
+    };
+
+    let uid = *input.uid.unwrap_ref();
+    let page_token = token_to_xid(&app.mac, &uid, token_kind, &input.page_token)?;
+    let fields = get_list_fields(input.fields)?;
+    let res =
+        db::Credit::list(&app.scylla, uid, fields, page_size, page_token, kind, order).await?;
     let next_page_token = if res.len() >= page_size as usize {
-        to.with_option(token_from_xid(res.last().unwrap().txn))
+        to.with_option(token_from_xid(
+            &app.mac,
+            &uid,
+            token_kind,
+            res.last().unwrap().txn,
+        ))
     } else {
         None
     };
@@ -121,35 +847,227 @@ pub async fn list_credits(
     }))
 }
 
-#[derive(Debug, Deserialize, Validate)]
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct CreditSummaryOutput {
+    pub kind: String,
+    pub amount: i64, // total amount of the kind
+    pub count: i64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/wallet/credits/summary",
+    tag = "wallet",
+    params(QueryUid),
+    responses(
+        (status = 200, body = super::openapi::CreditSummariesResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn credits_summary(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    Query(input): Query<QueryUid>,
+) -> Result<PackObject<SuccessResponse<Vec<CreditSummaryOutput>>>, HTTPError> {
+    input.validate()?;
+
+    ctx.set_kvs(vec![
+        ("action", "credits_summary".into()),
+        ("uid", input.uid.to_string().into()),
+    ])
+    .await;
+
+    let res = db::Credit::summary(&app.scylla, input.uid.unwrap()).await?;
+    Ok(to.with(SuccessResponse::new(
+        res.into_iter()
+            .map(|(kind, amount, count)| CreditSummaryOutput {
+                kind,
+                amount,
+                count,
+            })
+            .collect(),
+    )))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryWeeklySummary {
+    #[param(value_type = super::openapi::Xid)]
+    #[validate(custom = "super::validate_uid")]
+    pub uid: PackObject<xid::Id>,
+    // weeks since unix epoch, weeks start on Monday, default to the last complete week.
+    #[validate(range(min = 0))]
+    pub week: Option<i32>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct TopPayerOutput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub uid: PackObject<xid::Id>,
+    pub amount: i64,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct WeeklySummaryOutput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub uid: PackObject<xid::Id>,
+    pub week: i32,
+    pub start_at: i64, // unix ms of the Monday the week starts
+    pub income: i64,
+    pub sponsors: i32,
+    pub subscribers: i32,
+    pub top_payers: Vec<TopPayerOutput>,
+    pub created_at: i64,
+}
+
+impl WeeklySummaryOutput {
+    fn from<T>(val: db::PayeeWeeklySummary, to: &PackObject<T>) -> Self {
+        Self {
+            uid: to.with(val.uid),
+            week: val.week,
+            start_at: db::week_start_ms(val.week),
+            income: val.income,
+            sponsors: val.sponsors,
+            subscribers: val.subscribers,
+            top_payers: val
+                .top_payers
+                .into_iter()
+                .map(|(uid, amount)| TopPayerOutput {
+                    uid: to.with(uid),
+                    amount,
+                })
+                .collect(),
+            created_at: val.created_at,
+        }
+    }
+}
+
+// the payee's income digest of the week, it is written by the summary job after the week ends.
+#[utoipa::path(
+    get,
+    path = "/v1/wallet/summary/weekly",
+    tag = "wallet",
+    params(QueryWeeklySummary),
+    responses(
+        (status = 200, body = super::openapi::WeeklySummaryResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn weekly_summary(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    Query(input): Query<QueryWeeklySummary>,
+) -> Result<PackObject<SuccessResponse<WeeklySummaryOutput>>, HTTPError> {
+    input.validate()?;
+    let uid = input.uid.unwrap();
+    let week = input
+        .week
+        .unwrap_or_else(|| db::week_of(unix_ms() as i64) - 1);
+
+    ctx.set_kvs(vec![
+        ("action", "weekly_summary".into()),
+        ("uid", uid.to_string().into()),
+        ("week", week.into()),
+    ])
+    .await;
+
+    let mut doc = db::PayeeWeeklySummary::with_pk(uid, week);
+    doc.get_one(&app.scylla).await?;
+    Ok(to.with(SuccessResponse::new(WeeklySummaryOutput::from(doc, &to))))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
 pub struct AwardInput {
+    #[schema(value_type = super::openapi::Xid)]
     pub payee: PackObject<xid::Id>,
-    #[validate(range(min = 1, max = 1000000))]
+    // checked by the kind's max amount.
+    #[validate(range(min = 1))]
     pub amount: i64,
     #[validate(range(min = 0, max = 1000000))]
     pub credits: u64,
     pub description: Option<String>,
+    // self-described CBOR, limited by the max payload size.
+    #[schema(value_type = Option<super::openapi::Base64Url>)]
     pub payload: Option<PackObject<Vec<u8>>>,
+    #[schema(value_type = Option<super::openapi::Xid>)]
+    pub budget_id: Option<PackObject<xid::Id>>,
+}
+
+// quota headers of the calling service, they are omitted if the quota is unlimited.
+fn award_quota_headers(quota: &db::AwardQuota, now_ms: i64) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    if quota.quota > 0 {
+        headers.insert("x-award-quota-limit", HeaderValue::from(quota.quota));
+        headers.insert(
+            "x-award-quota-remaining",
+            HeaderValue::from(quota.remaining()),
+        );
+        headers.insert(
+            "x-award-quota-reset",
+            HeaderValue::from(db::quota_reset_secs(now_ms)),
+        );
+    }
+    headers
+}
+
+// the award's error is returned, a failed release of the quota is only logged.
+async fn release_award_quota(app: &AppState, quota: &mut db::AwardQuota, amount: i64) {
+    if let Err(err) = quota.release(&app.scylla, amount).await {
+        log::error!(target: "award_quota",
+            action = "release_award_quota",
+            service = quota.service,
+            amount = amount;
+            "failed to release the award quota: {}", err);
+    }
 }
 
 // the txn is committed.
 // returns payee's wallet
+// awards are limited by the daily quota of the calling service, 429 if exceeded.
+#[utoipa::path(
+    post,
+    path = "/v1/wallet/award",
+    tag = "wallet",
+    request_body = AwardInput,
+    responses(
+        (status = 200, body = super::openapi::WalletResponse),
+        (status = 429, body = super::openapi::ErrorResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
 pub async fn award(
     State(app): State<Arc<AppState>>,
     Extension(ctx): Extension<Arc<ReqContext>>,
+    headers: HeaderMap,
     to: PackObject<AwardInput>,
-) -> Result<PackObject<SuccessResponse<WalletOutput>>, HTTPError> {
+) -> Result<Response, HTTPError> {
     let (to, input) = to.unpack();
     input.validate()?;
 
+    db::TransactionKind::Award.check_amount(input.amount)?;
+
     let payee = input.payee.unwrap();
+    let service = extract_header(&headers, SERVICE_HEADER, || "".to_string());
+    let now_ms = unix_ms() as i64;
+    let mut quota = db::AwardQuota::new(&service, now_ms);
     ctx.set_kvs(vec![
         ("action", "award".into()),
         ("payee", payee.to_string().into()),
         ("amount", input.amount.into()),
+        ("service", quota.service.clone().into()),
     ])
     .await;
 
+    if let Err(err) = quota.reserve(&app.scylla, input.amount).await {
+        let err: HTTPError = err.into();
+        if err.code == 429 {
+            return Ok((award_quota_headers(&quota, now_ms), err).into_response());
+        }
+        return Err(err);
+    }
+
     let mut txn: db::Transaction = Default::default();
     if let Some(description) = input.description {
         txn.description = description;
@@ -157,17 +1075,45 @@ pub async fn award(
         txn.description = "payee.award".to_string();
     }
     if let Some(payload) = input.payload {
-        txn.payload = payload.unwrap();
+        let payload = payload.unwrap();
+        check_payload(&payload)?;
+        txn.payload = payload;
     }
 
-    txn.prepare(
-        &app.scylla,
-        &app.mac,
-        payee,
-        db::TransactionKind::Award,
-        input.amount,
-    )
-    .await?;
+    let mut budget = None;
+    if let Some(budget_id) = input.budget_id {
+        let mut doc = db::Budget::with_pk(budget_id.unwrap());
+        ctx.set("budget", doc.id.to_string().into()).await;
+        if let Err(err) = doc.decrement(&app.scylla, input.amount).await {
+            release_award_quota(&app, &mut quota, input.amount).await;
+            return Err(err.into());
+        }
+        budget = Some(doc);
+    }
+
+    if let Err(err) = txn
+        .prepare(
+            &app.scylla,
+            &app.mac,
+            payee,
+            db::TransactionKind::Award,
+            input.amount,
+        )
+        .await
+    {
+        // the prepare error is returned, a failed refund of the budget is only logged.
+        if let Some(mut doc) = budget {
+            if let Err(e) = doc.increment(&app.scylla, input.amount).await {
+                log::error!(target: "budget",
+                    action = "refund_budget",
+                    budget = doc.id.to_string(),
+                    amount = input.amount;
+                    "failed to refund the budget: {}", e);
+            }
+        }
+        release_award_quota(&app, &mut quota, input.amount).await;
+        return Err(err.into());
+    }
     txn.commit(&app.scylla, &app.mac).await?;
 
     if input.credits > 0 {
@@ -182,22 +1128,96 @@ pub async fn award(
     let mut wallet = db::Wallet::with_pk(payee);
     wallet.get_one(&app.scylla).await?;
     wallet.txn = txn.id; // txn.id may be not the walllet.txn, return the txn.id to the caller
-    Ok(to.with(SuccessResponse::new(WalletOutput::from(wallet, &to))))
+    Ok((
+        award_quota_headers(&quota, now_ms),
+        to.with(SuccessResponse::new(WalletOutput::from(wallet, &to))),
+    )
+        .into_response())
 }
 
-#[derive(Debug, Deserialize, Validate)]
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
 pub struct SpendInput {
+    #[schema(value_type = super::openapi::Xid)]
     pub uid: PackObject<xid::Id>,
+    #[schema(value_type = Option<super::openapi::Xid>)]
     pub payee: Option<PackObject<xid::Id>>,
+    #[schema(value_type = Option<super::openapi::Xid>)]
     pub sub_payee: Option<PackObject<xid::Id>>,
-    #[validate(range(min = 1, max = 1000000))]
+    // share recipients with basis points, can not be used with sub_payee
+    #[schema(value_type = Option<Vec<Object>>)]
+    pub shares: Option<Vec<(PackObject<xid::Id>, u16)>>,
+    // sponsor only, hide payer from payee-facing listings
+    pub anonymous: Option<bool>,
+    // checked by the kind's max amount.
+    #[validate(range(min = 1))]
     pub amount: i64,
     pub description: Option<String>,
+    // self-described CBOR, limited by the max payload size.
+    #[schema(value_type = Option<super::openapi::Base64Url>)]
     pub payload: Option<PackObject<Vec<u8>>>,
+    // commits the txn in the same request, the sponsor txn is always committed.
+    pub auto_commit: Option<bool>,
+}
+
+// sets the user-supplied description of the txn after moderation, the txn is flagged if the
+// description was redacted or blocked.
+async fn moderate_description(
+    app: &AppState,
+    ctx: &ReqContext,
+    kind: db::TransactionKind,
+    txn: &mut db::Transaction,
+    description: String,
+) {
+    match &app.moderation {
+        None => txn.description = description,
+        Some(moderation) => {
+            let (description, flag) = moderation
+                .moderate(kind.as_ref(), &txn.uid, description)
+                .await;
+            if flag != moderation::FLAG_NONE {
+                ctx.set("moderation", flag.into()).await;
+            }
+            txn.description = description;
+            txn.moderation = flag;
+        }
+    }
 }
 
-// the txn is not committed, it should be committed or cancelled by the caller
+// commits the prepared txn for auto_commit, credits are saved by the commit.
+// returns payer's wallet with the committed txn.
+async fn commit_prepared<T>(
+    app: &AppState,
+    ctx: &ReqContext,
+    mut txn: db::Transaction,
+    to: &PackObject<T>,
+) -> Result<WalletOutput, HTTPError> {
+    ctx.set_kvs(vec![
+        ("txn", txn.id.to_string().into()),
+        ("auto_commit", true.into()),
+    ])
+    .await;
+    txn.commit(&app.scylla, &app.mac).await?;
+
+    let mut wallet = db::Wallet::with_pk(txn.uid);
+    wallet.get_one(&app.scylla).await?;
+    wallet.txn = txn.id;
+    let mut rt = WalletOutput::from(wallet, to);
+    rt.transaction = Some(TransactionOutput::from(txn, to));
+    Ok(rt)
+}
+
+// the txn is not committed unless auto_commit, it should be committed or cancelled by the caller
 // returns payer's wallet
+#[utoipa::path(
+    post,
+    path = "/v1/wallet/spend",
+    tag = "wallet",
+    request_body = SpendInput,
+    responses(
+        (status = 200, body = super::openapi::WalletResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
 pub async fn spend(
     State(app): State<Arc<AppState>>,
     Extension(ctx): Extension<Arc<ReqContext>>,
@@ -206,6 +1226,8 @@ pub async fn spend(
     let (to, input) = to.unpack();
     input.validate()?;
 
+    db::TransactionKind::Spend.check_amount(input.amount)?;
+
     let uid = input.uid.unwrap();
     ctx.set_kvs(vec![
         ("action", "spend".into()),
@@ -219,7 +1241,9 @@ pub async fn spend(
         txn.description = description;
     }
     if let Some(payload) = input.payload {
-        txn.payload = payload.unwrap();
+        let payload = payload.unwrap();
+        check_payload(&payload)?;
+        txn.payload = payload;
     }
 
     txn.prepare(
@@ -230,15 +1254,31 @@ pub async fn spend(
         input.amount,
     )
     .await?;
+    if input.auto_commit.unwrap_or_default() {
+        let rt = commit_prepared(&app, &ctx, txn, &to).await?;
+        return Ok(to.with(SuccessResponse::new(rt)));
+    }
 
     let mut wallet = db::Wallet::with_pk(uid);
     wallet.get_one(&app.scylla).await?;
     wallet.txn = txn.id; // txn.id may be not the walllet.txn, return the txn.id to the caller
-    Ok(to.with(SuccessResponse::new(WalletOutput::from(wallet, &to))))
+    let mut rt = WalletOutput::from(wallet, &to);
+    rt.cancel_deadline = txn.cancel_deadline();
+    Ok(to.with(SuccessResponse::new(rt)))
 }
 
-// the txn is not committed, it should be committed or cancelled by the caller
+// the txn is not committed unless auto_commit, it should be committed or cancelled by the caller
 // returns payer's wallet
+#[utoipa::path(
+    post,
+    path = "/v1/wallet/subscribe",
+    tag = "wallet",
+    request_body = SpendInput,
+    responses(
+        (status = 200, body = super::openapi::WalletResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
 pub async fn subscribe(
     State(app): State<Arc<AppState>>,
     Extension(ctx): Extension<Arc<ReqContext>>,
@@ -247,6 +1287,8 @@ pub async fn subscribe(
     let (to, input) = to.unpack();
     input.validate()?;
 
+    db::TransactionKind::Subscribe.check_amount(input.amount)?;
+
     let uid = input.uid.unwrap();
     if input.payee.is_none() {
         return Err(HTTPError::new(400, "payee is required".to_string()));
@@ -262,15 +1304,31 @@ pub async fn subscribe(
 
     let mut txn = db::Transaction::with_uid(uid);
     if let Some(description) = input.description {
-        txn.description = description;
+        moderate_description(
+            &app,
+            &ctx,
+            db::TransactionKind::Subscribe,
+            &mut txn,
+            description,
+        )
+        .await;
     }
     if let Some(payload) = input.payload {
-        txn.payload = payload.unwrap();
+        let payload = payload.unwrap();
+        check_payload(&payload)?;
+        txn.payload = payload;
     }
     if let Some(sub_payee) = input.sub_payee {
         ctx.set("sub_payee", sub_payee.to_string().into()).await;
         txn.sub_payee = Some(sub_payee.unwrap());
     }
+    if let Some(shares) = input.shares {
+        ctx.set("shares", shares.len().into()).await;
+        txn.shares = shares
+            .into_iter()
+            .map(|(id, bps)| (id.unwrap(), bps))
+            .collect();
+    }
 
     txn.prepare(
         &app.scylla,
@@ -280,6 +1338,10 @@ pub async fn subscribe(
         input.amount,
     )
     .await?;
+    if input.auto_commit.unwrap_or_default() {
+        let rt = commit_prepared(&app, &ctx, txn, &to).await?;
+        return Ok(to.with(SuccessResponse::new(rt)));
+    }
 
     let mut wallet = db::Wallet::with_pk(uid);
     wallet.get_one(&app.scylla).await?;
@@ -289,6 +1351,16 @@ pub async fn subscribe(
 
 // the txn is committed.
 // returns payer's wallet
+#[utoipa::path(
+    post,
+    path = "/v1/wallet/sponsor",
+    tag = "wallet",
+    request_body = SpendInput,
+    responses(
+        (status = 200, body = super::openapi::WalletResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
 pub async fn sponsor(
     State(app): State<Arc<AppState>>,
     Extension(ctx): Extension<Arc<ReqContext>>,
@@ -297,6 +1369,8 @@ pub async fn sponsor(
     let (to, input) = to.unpack();
     input.validate()?;
 
+    db::TransactionKind::Sponsor.check_amount(input.amount)?;
+
     let uid = input.uid.unwrap();
     if input.payee.is_none() {
         return Err(HTTPError::new(400, "payee is required".to_string()));
@@ -312,15 +1386,35 @@ pub async fn sponsor(
 
     let mut txn = db::Transaction::with_uid(uid);
     if let Some(description) = input.description {
-        txn.description = description;
+        moderate_description(
+            &app,
+            &ctx,
+            db::TransactionKind::Sponsor,
+            &mut txn,
+            description,
+        )
+        .await;
     }
     if let Some(payload) = input.payload {
-        txn.payload = payload.unwrap();
+        let payload = payload.unwrap();
+        check_payload(&payload)?;
+        txn.payload = payload;
     }
     if let Some(sub_payee) = input.sub_payee {
         ctx.set("sub_payee", sub_payee.to_string().into()).await;
         txn.sub_payee = Some(sub_payee.unwrap());
     }
+    if let Some(shares) = input.shares {
+        ctx.set("shares", shares.len().into()).await;
+        txn.shares = shares
+            .into_iter()
+            .map(|(id, bps)| (id.unwrap(), bps))
+            .collect();
+    }
+    if input.anonymous.unwrap_or_default() {
+        ctx.set("anonymous", true.into()).await;
+        txn.anonymous = true;
+    }
 
     txn.prepare(
         &app.scylla,
@@ -332,11 +1426,122 @@ pub async fn sponsor(
     .await?;
     txn.commit(&app.scylla, &app.mac).await?;
 
-    let mut credits = txn.credits();
-    db::Credit::save_all(&app.scylla, &mut credits).await?;
-
     let mut wallet = db::Wallet::with_pk(uid);
     wallet.get_one(&app.scylla).await?;
     wallet.txn = txn.id; // txn.id may be not the walllet.txn, return the txn.id to the caller
     Ok(to.with(SuccessResponse::new(WalletOutput::from(wallet, &to))))
 }
+
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
+pub struct SimulateInput {
+    // "spend", "sponsor" or "subscribe"
+    pub kind: String,
+    #[schema(value_type = super::openapi::Xid)]
+    pub uid: PackObject<xid::Id>,
+    #[schema(value_type = Option<super::openapi::Xid>)]
+    pub payee: Option<PackObject<xid::Id>>,
+    #[schema(value_type = Option<super::openapi::Xid>)]
+    pub sub_payee: Option<PackObject<xid::Id>>,
+    #[schema(value_type = Option<Vec<Object>>)]
+    pub shares: Option<Vec<(PackObject<xid::Id>, u16)>>,
+    pub anonymous: Option<bool>,
+    // checked by the kind's max amount.
+    #[validate(range(min = 1))]
+    pub amount: i64,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct SimulationOutput {
+    pub kind: String,
+    pub amount: i64,
+    pub sys_fee: i64,
+    pub sub_shares: i64,
+    #[schema(value_type = Vec<Object>)]
+    pub shares: Vec<(PackObject<xid::Id>, i64)>,
+    pub balance: i64, // payer's balance after the transaction
+    pub overdraw: bool,
+}
+
+impl SimulationOutput {
+    pub fn from<T>(kind: String, val: db::Simulation, to: &PackObject<T>) -> Self {
+        Self {
+            kind,
+            amount: val.amount,
+            sys_fee: val.sys_fee,
+            sub_shares: val.sub_shares,
+            shares: val
+                .shares
+                .into_iter()
+                .map(|(uid, amount)| (to.with(uid), amount))
+                .collect(),
+            balance: val.balance,
+            overdraw: val.overdraw,
+        }
+    }
+}
+
+// dry-run of spend, sponsor or subscribe, nothing is written.
+#[utoipa::path(
+    post,
+    path = "/v1/wallet/simulate",
+    tag = "wallet",
+    request_body = SimulateInput,
+    responses(
+        (status = 200, body = super::openapi::SimulationResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn simulate(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<SimulateInput>,
+) -> Result<PackObject<SuccessResponse<SimulationOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    let uid = input.uid.unwrap();
+    let kind = db::TransactionKind::from_str(&input.kind)
+        .map_err(|e| HTTPError::new(400, format!("Invalid kind: {}", e)))?;
+    kind.check_amount(input.amount)?;
+    let payee = match kind {
+        db::TransactionKind::Spend => SYS_ID,
+        db::TransactionKind::Sponsor | db::TransactionKind::Subscribe => match input.payee {
+            Some(payee) => payee.unwrap(),
+            None => return Err(HTTPError::new(400, "payee is required".to_string())),
+        },
+        _ => {
+            return Err(HTTPError::new(
+                400,
+                format!("Invalid kind: {}", kind.as_ref()),
+            ))
+        }
+    };
+    ctx.set_kvs(vec![
+        ("action", "simulate".into()),
+        ("kind", kind.as_ref().to_string().into()),
+        ("payer", uid.to_string().into()),
+        ("payee", payee.to_string().into()),
+        ("amount", input.amount.into()),
+    ])
+    .await;
+
+    let mut txn = db::Transaction::with_uid(uid);
+    if let Some(sub_payee) = input.sub_payee {
+        txn.sub_payee = Some(sub_payee.unwrap());
+    }
+    if let Some(shares) = input.shares {
+        txn.shares = shares
+            .into_iter()
+            .map(|(id, bps)| (id.unwrap(), bps))
+            .collect();
+    }
+    txn.anonymous = input.anonymous.unwrap_or_default();
+
+    let kind_name = kind.as_ref().to_string();
+    let res = txn
+        .simulate(&app.scylla, &app.mac, payee, kind, input.amount)
+        .await?;
+    Ok(to.with(SuccessResponse::new(SimulationOutput::from(
+        kind_name, res, &to,
+    ))))
+}
@@ -0,0 +1,151 @@
+use axum::{
+    extract::{Query, State},
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+use std::{str::FromStr, sync::Arc};
+use utoipa::ToSchema;
+use validator::{Validate, ValidationError};
+
+use axum_web::context::ReqContext;
+use axum_web::erring::{HTTPError, SuccessResponse};
+use axum_web::object::PackObject;
+use scylla_orm::ColumnsMap;
+
+use crate::api::{currency::Currency, AppState, QueryUid};
+use crate::db;
+
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
+pub struct PreferencesInput {
+    #[schema(value_type = super::openapi::Xid)]
+    #[validate(custom = "super::validate_uid")]
+    pub uid: PackObject<xid::Id>,
+    // alpha code of a supported currency, empty string to unset.
+    pub display_currency: Option<String>,
+    // BCP 47 language tag, empty string to unset.
+    #[validate(length(max = 35), custom = "validate_locale")]
+    pub locale: Option<String>,
+    // opt out of reminders of pending checkouts.
+    pub reminders_opt_out: Option<bool>,
+}
+
+impl PreferencesInput {
+    fn into(self) -> anyhow::Result<ColumnsMap> {
+        let mut cols = ColumnsMap::new();
+        if let Some(currency) = self.display_currency {
+            let currency = if currency.is_empty() {
+                currency
+            } else {
+                Currency::from_str(&currency)?.alpha.to_string()
+            };
+            cols.set_as("display_currency", &currency);
+        }
+        if let Some(locale) = self.locale {
+            cols.set_as("locale", &locale);
+        }
+        if let Some(opt_out) = self.reminders_opt_out {
+            cols.set_as("reminders_opt_out", &opt_out);
+        }
+
+        if cols.is_empty() {
+            return Err(HTTPError::new(400, "No fields to update".to_string()).into());
+        }
+        Ok(cols)
+    }
+}
+
+fn validate_locale(locale: &str) -> Result<(), ValidationError> {
+    if locale.is_empty()
+        || locale
+            .split('-')
+            .all(|s| !s.is_empty() && s.len() <= 8 && s.chars().all(|c| c.is_ascii_alphanumeric()))
+    {
+        return Ok(());
+    }
+    Err(ValidationError::new("invalid locale"))
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct PreferencesOutput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub uid: PackObject<xid::Id>,
+    pub display_currency: String,
+    pub locale: String,
+    pub reminders_opt_out: bool,
+    pub updated_at: i64,
+}
+
+impl PreferencesOutput {
+    pub fn from<T>(val: db::WalletPref, to: &PackObject<T>) -> Self {
+        Self {
+            uid: to.with(val.uid),
+            display_currency: val.display_currency,
+            locale: val.locale,
+            reminders_opt_out: val.reminders_opt_out,
+            updated_at: val.updated_at,
+        }
+    }
+}
+
+// returns empty preferences if they are not set.
+#[utoipa::path(
+    get,
+    path = "/v1/wallet/preferences",
+    tag = "wallet",
+    params(QueryUid),
+    responses(
+        (status = 200, body = super::openapi::PreferencesResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn get(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    Query(input): Query<QueryUid>,
+) -> Result<PackObject<SuccessResponse<PreferencesOutput>>, HTTPError> {
+    input.validate()?;
+
+    ctx.set_kvs(vec![
+        ("action", "get_wallet_preferences".into()),
+        ("uid", input.uid.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::WalletPref::with_pk(input.uid.unwrap());
+    let exists = doc.get_one(&app.scylla).await?;
+    ctx.set("exists", exists.into()).await;
+
+    Ok(to.with(SuccessResponse::new(PreferencesOutput::from(doc, &to))))
+}
+
+#[utoipa::path(
+    put,
+    path = "/v1/wallet/preferences",
+    tag = "wallet",
+    request_body = PreferencesInput,
+    responses(
+        (status = 200, body = super::openapi::PreferencesResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn update(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<PreferencesInput>,
+) -> Result<PackObject<SuccessResponse<PreferencesOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    let uid = *input.uid.to_owned();
+    ctx.set_kvs(vec![
+        ("action", "update_wallet_preferences".into()),
+        ("uid", uid.to_string().into()),
+    ])
+    .await;
+
+    let cols = input.into()?;
+    let mut doc = db::WalletPref::with_pk(uid);
+    doc.upsert(&app.scylla, cols).await?;
+    Ok(to.with(SuccessResponse::new(PreferencesOutput::from(doc, &to))))
+}
@@ -0,0 +1,164 @@
+use axum::{
+    extract::{Query, State},
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::ToSchema;
+use validator::{Validate, ValidationError};
+
+use axum_web::context::ReqContext;
+use axum_web::erring::{HTTPError, SuccessResponse};
+use axum_web::object::PackObject;
+
+use crate::api::{AppState, QueryUid};
+use crate::db;
+use crate::webhook;
+
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
+pub struct WebhookInput {
+    #[schema(value_type = super::openapi::Xid)]
+    #[validate(custom = "super::validate_uid")]
+    pub uid: PackObject<xid::Id>,
+    // https url the income events are posted to, redirects are not followed.
+    #[validate(length(min = 12, max = 512), custom = "validate_webhook_url")]
+    pub url: String,
+    // the secret signing the events, see webhook::sign. it is not returned.
+    #[validate(length(min = 16, max = 128))]
+    pub secret: String,
+}
+
+fn validate_webhook_url(url: &str) -> Result<(), ValidationError> {
+    match url.strip_prefix("https://") {
+        Some(rest)
+            if !rest.is_empty()
+                && !rest.starts_with('/')
+                && !rest.chars().any(|c| c.is_whitespace() || c.is_control()) =>
+        {
+            Ok(())
+        }
+        _ => Err(ValidationError::new("invalid https url")),
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct WebhookOutput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub uid: PackObject<xid::Id>,
+    pub url: String,
+    pub enabled: bool,
+    pub failures: i32,    // consecutive failed deliveries
+    pub disabled_at: i64, // unix ms, 0 for enabled webhooks
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+impl WebhookOutput {
+    pub fn from<T>(val: db::WalletWebhook, to: &PackObject<T>) -> Self {
+        Self {
+            uid: to.with(val.uid),
+            enabled: val.is_enabled(),
+            url: val.url,
+            failures: val.failures,
+            disabled_at: val.disabled_at,
+            created_at: val.created_at,
+            updated_at: val.updated_at,
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/wallet/webhook",
+    tag = "wallet",
+    params(QueryUid),
+    responses(
+        (status = 200, body = super::openapi::WebhookResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn get(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    Query(input): Query<QueryUid>,
+) -> Result<PackObject<SuccessResponse<WebhookOutput>>, HTTPError> {
+    input.validate()?;
+
+    ctx.set_kvs(vec![
+        ("action", "get_wallet_webhook".into()),
+        ("uid", input.uid.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::WalletWebhook::with_pk(input.uid.unwrap());
+    if !doc.get_one(&app.scylla).await? {
+        return Err(HTTPError::new(404, "Webhook not found".to_string()));
+    }
+    Ok(to.with(SuccessResponse::new(WebhookOutput::from(doc, &to))))
+}
+
+// registers the wallet's webhook for income and sub_shares events, a registered one is
+// replaced and enabled again.
+#[utoipa::path(
+    put,
+    path = "/v1/wallet/webhook",
+    tag = "wallet",
+    request_body = WebhookInput,
+    responses(
+        (status = 200, body = super::openapi::WebhookResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn update(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<WebhookInput>,
+) -> Result<PackObject<SuccessResponse<WebhookOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    let uid = *input.uid.to_owned();
+    ctx.set_kvs(vec![
+        ("action", "update_wallet_webhook".into()),
+        ("uid", uid.to_string().into()),
+    ])
+    .await;
+
+    db::Wallet::check_open_by_uid(&app.scylla, uid).await?;
+    let mut doc = db::WalletWebhook::with_pk(uid);
+    doc.get_one(&app.scylla).await?;
+    doc.url = input.url;
+    doc.secret = webhook::seal_secret(&app.mac, &uid, input.secret.as_bytes())?;
+    doc.save(&app.scylla).await?;
+    Ok(to.with(SuccessResponse::new(WebhookOutput::from(doc, &to))))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/wallet/webhook",
+    tag = "wallet",
+    params(QueryUid),
+    responses(
+        (status = 200, body = super::openapi::BoolResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn delete(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    Query(input): Query<QueryUid>,
+) -> Result<PackObject<SuccessResponse<bool>>, HTTPError> {
+    input.validate()?;
+
+    ctx.set_kvs(vec![
+        ("action", "delete_wallet_webhook".into()),
+        ("uid", input.uid.to_string().into()),
+    ])
+    .await;
+
+    let doc = db::WalletWebhook::with_pk(input.uid.unwrap());
+    doc.delete(&app.scylla).await?;
+    Ok(to.with(SuccessResponse::new(true)))
+}
@@ -0,0 +1,327 @@
+use axum::{
+    extract::{Query, State},
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+use axum_web::context::ReqContext;
+use axum_web::erring::{HTTPError, SuccessResponse};
+use axum_web::object::PackObject;
+use scylla_orm::FieldSet;
+
+use crate::api::{check_payload, get_fields, token_from_xid, token_to_xid, AppState};
+use crate::db::{self, SYS_ID};
+
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
+pub struct WithdrawInput {
+    #[schema(value_type = super::openapi::Xid)]
+    #[validate(custom = "super::validate_uid")]
+    pub uid: PackObject<xid::Id>,
+    // checked by the withdraw kind's max amount.
+    #[validate(range(min = 1))]
+    pub amount: i64,
+    pub description: Option<String>,
+    // self-described CBOR, limited by the max payload size.
+    #[schema(value_type = Option<super::openapi::Base64Url>)]
+    pub payload: Option<PackObject<Vec<u8>>>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+pub struct WithdrawalOutput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub id: PackObject<xid::Id>,
+    #[schema(value_type = super::openapi::Xid)]
+    pub uid: PackObject<xid::Id>,
+    // review status, -1: rejected, 0: pending review, 1: approved.
+    // none if the withdrawal does not need a review, it should be committed or cancelled by the caller.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<i8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reviewer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<i64>,
+}
+
+impl WithdrawalOutput {
+    pub fn from<T>(val: db::WithdrawalReview, to: &PackObject<T>) -> Self {
+        let mut rt = Self {
+            id: to.with(val.id),
+            uid: to.with(val.uid),
+            ..Default::default()
+        };
+
+        for v in val._fields {
+            match v.as_str() {
+                "status" => rt.status = Some(val.status),
+                "amount" => rt.amount = Some(val.amount),
+                "reviewer" => rt.reviewer = Some(val.reviewer.to_owned()),
+                "note" => rt.note = Some(val.note.to_owned()),
+                "created_at" => rt.created_at = Some(val.created_at),
+                "updated_at" => rt.updated_at = Some(val.updated_at),
+                _ => {}
+            }
+        }
+
+        rt
+    }
+}
+
+// the txn is not committed.
+// if the amount reaches the review threshold, it will be committed or cancelled by the reviewer,
+// otherwise it should be committed or cancelled by the caller.
+#[utoipa::path(
+    post,
+    path = "/v1/wallet/withdraw",
+    tag = "wallet",
+    request_body = WithdrawInput,
+    responses(
+        (status = 200, body = super::openapi::WithdrawalResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn withdraw(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<WithdrawInput>,
+) -> Result<PackObject<SuccessResponse<WithdrawalOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    db::TransactionKind::Withdraw.check_amount(input.amount)?;
+
+    let uid = input.uid.unwrap();
+    ctx.set_kvs(vec![
+        ("action", "withdraw".into()),
+        ("payer", uid.to_string().into()),
+        ("amount", input.amount.into()),
+    ])
+    .await;
+
+    let mut txn = db::Transaction::with_uid(uid);
+    if let Some(description) = input.description {
+        txn.description = description;
+    }
+    if let Some(payload) = input.payload {
+        let payload = payload.unwrap();
+        check_payload(&payload)?;
+        txn.payload = payload;
+    }
+
+    txn.prepare(
+        &app.scylla,
+        &app.mac,
+        SYS_ID,
+        db::TransactionKind::Withdraw,
+        input.amount,
+    )
+    .await?;
+    ctx.set("txn", txn.id.to_string().into()).await;
+
+    let mut doc = db::WithdrawalReview {
+        id: txn.id,
+        uid,
+        amount: input.amount,
+        ..Default::default()
+    };
+    if !db::WithdrawalReview::required(input.amount) {
+        doc._fields = vec!["amount".to_string()];
+        return Ok(to.with(SuccessResponse::new(WithdrawalOutput::from(doc, &to))));
+    }
+
+    ctx.set("review", true.into()).await;
+    if let Err(err) = doc.save(&app.scylla).await {
+        txn.cancel(&app.scylla, &app.mac).await?;
+        return Err(err.into());
+    }
+
+    Ok(to.with(SuccessResponse::new(WithdrawalOutput::from(doc, &to))))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, ToSchema)]
+pub struct ReviewInput {
+    #[schema(value_type = super::openapi::Xid)]
+    pub id: PackObject<xid::Id>,
+    #[validate(length(min = 1, max = 64))]
+    pub reviewer: String,
+    pub approved: bool,
+    #[validate(length(max = 1024))]
+    pub note: Option<String>,
+}
+
+// commits the withdraw transaction if approved, otherwise cancels it. the decision is recorded
+// first, a retry with the same decision drives the transaction again, so that an interrupted
+// commit or cancel is finished.
+#[utoipa::path(
+    post,
+    path = "/v1/admin/withdrawal/review",
+    tag = "admin",
+    request_body = ReviewInput,
+    responses(
+        (status = 200, body = super::openapi::WithdrawalResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn review(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<ReviewInput>,
+) -> Result<PackObject<SuccessResponse<WithdrawalOutput>>, HTTPError> {
+    let (to, input) = to.unpack();
+    input.validate()?;
+
+    let id = input.id.unwrap();
+    ctx.set_kvs(vec![
+        ("action", "review_withdrawal".into()),
+        ("id", id.to_string().into()),
+        ("reviewer", input.reviewer.clone().into()),
+        ("approved", input.approved.into()),
+    ])
+    .await;
+
+    let mut doc = db::WithdrawalReview::with_pk(id);
+    doc.get_one(&app.scylla, FieldSet::new()).await?;
+    let status = if input.approved { 1 } else { -1 };
+    if doc.status == 0 {
+        if let Err(err) = doc
+            .decide(
+                &app.scylla,
+                input.reviewer,
+                status,
+                input.note.unwrap_or_default(),
+            )
+            .await
+        {
+            let err = HTTPError::from(err);
+            if err.code != 409 {
+                return Err(err);
+            }
+            // decided by a concurrent review.
+            doc.get_one(&app.scylla, FieldSet::new()).await?;
+        }
+    }
+    if doc.status != status {
+        return Err(HTTPError::new(
+            409,
+            format!("Withdrawal review {} has been decided", id),
+        ));
+    }
+
+    let mut txn = db::Transaction::with_pk(doc.uid, doc.id);
+    txn._actor = doc.reviewer.clone();
+    txn.get_one(&app.scylla, FieldSet::new()).await?;
+    if input.approved {
+        // a committed transaction is repaired only.
+        txn.commit(&app.scylla, &app.mac).await?;
+    } else if txn.status == 1 {
+        txn.cancel(&app.scylla, &app.mac).await?;
+    }
+
+    Ok(to.with(SuccessResponse::new(WithdrawalOutput::from(doc, &to))))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryWithdrawal {
+    #[param(value_type = super::openapi::Xid)]
+    pub id: PackObject<xid::Id>,
+    pub fields: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/admin/withdrawal",
+    tag = "admin",
+    params(QueryWithdrawal),
+    responses(
+        (status = 200, body = super::openapi::WithdrawalResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn get(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QueryWithdrawal>,
+) -> Result<PackObject<SuccessResponse<WithdrawalOutput>>, HTTPError> {
+    input.validate()?;
+    let id = *input.id.to_owned();
+
+    ctx.set_kvs(vec![
+        ("action", "get_withdrawal".into()),
+        ("id", id.to_string().into()),
+    ])
+    .await;
+
+    let mut doc = db::WithdrawalReview::with_pk(id);
+    doc.get_one(&app.scylla, get_fields(input.fields.clone())?)
+        .await?;
+    Ok(to.with(SuccessResponse::new(WithdrawalOutput::from(doc, &to))))
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryPendingWithdrawals {
+    #[validate(range(min = 2, max = 1000))]
+    pub page_size: Option<u16>,
+    #[param(value_type = Option<super::openapi::Base64Url>)]
+    pub page_token: Option<PackObject<Vec<u8>>>,
+}
+
+// lists withdrawals pending review, oldest first.
+#[utoipa::path(
+    get,
+    path = "/v1/admin/withdrawal/queue",
+    tag = "admin",
+    params(QueryPendingWithdrawals),
+    responses(
+        (status = 200, body = super::openapi::WithdrawalsResponse),
+        (status = "default", body = super::openapi::ErrorResponse)
+    )
+)]
+pub async fn list_pending(
+    State(app): State<Arc<AppState>>,
+    Extension(ctx): Extension<Arc<ReqContext>>,
+    to: PackObject<()>,
+    input: Query<QueryPendingWithdrawals>,
+) -> Result<PackObject<SuccessResponse<Vec<WithdrawalOutput>>>, HTTPError> {
+    input.validate()?;
+
+    let page_size = input.page_size.unwrap_or(100);
+    ctx.set_kvs(vec![
+        ("action", "list_pending_withdrawals".into()),
+        ("page_size", page_size.into()),
+    ])
+    .await;
+
+    let kind = "list_pending_withdrawals";
+    let page_token = token_to_xid(&app.mac, &db::SYS_ID, kind, &input.page_token)?;
+    let res = db::WithdrawalReview::list_pending(&app.scylla, page_size, page_token).await?;
+    let next_page_token = if res.len() >= page_size as usize {
+        to.with_option(token_from_xid(
+            &app.mac,
+            &db::SYS_ID,
+            kind,
+            res.last().unwrap().id,
+        ))
+    } else {
+        None
+    };
+
+    Ok(to.with(SuccessResponse {
+        total_size: None,
+        next_page_token,
+        result: res
+            .iter()
+            .map(|r| WithdrawalOutput::from(r.to_owned(), &to))
+            .collect(),
+    }))
+}
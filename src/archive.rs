@@ -0,0 +1,97 @@
+use std::{sync::Arc, time::Duration};
+
+use axum_web::context::unix_ms;
+
+use crate::{api::AppState, conf, db};
+
+const MONTH_MS: i64 = 30 * db::DAY_MS;
+
+// wallets not updated since the returned time are idle.
+pub fn idle_before(now: i64, idle_months: u32) -> i64 {
+    now - idle_months.max(1) as i64 * MONTH_MS
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct RunStats {
+    pub scanned: u64,
+    pub archived: u64,
+    pub skipped: u64, // updated concurrently
+    pub failed: u64,
+}
+
+// scans wallets and moves the dead ones into wallet_archive.
+pub async fn run_once(
+    db: &db::scylladb::ScyllaDB,
+    cfg: &conf::Archive,
+) -> anyhow::Result<RunStats> {
+    let mut stats = RunStats::default();
+    let idle_before = idle_before(unix_ms() as i64, cfg.idle_months);
+    let page_size = cfg.page_size.max(1);
+    let mut page_token: Option<xid::Id> = None;
+    loop {
+        let wallets = db::Wallet::scan(db, page_size, page_token).await?;
+        let has_next = wallets.len() >= page_size as usize;
+        page_token = wallets.last().map(|w| w.uid);
+
+        for mut wallet in wallets {
+            stats.scanned += 1;
+            if !wallet.is_dead(idle_before) {
+                continue;
+            }
+
+            match wallet.archive(db).await {
+                Ok(true) => stats.archived += 1,
+                Ok(false) => stats.skipped += 1,
+                Err(err) => {
+                    stats.failed += 1;
+                    log::error!(target: "archive",
+                        uid = wallet.uid.to_string();
+                        "{}", err);
+                }
+            }
+        }
+
+        if !has_next {
+            return Ok(stats);
+        }
+    }
+}
+
+// runs the archival job every interval in the background.
+pub fn spawn(app: Arc<AppState>, cfg: conf::Archive) {
+    tokio::spawn(async move {
+        let interval = cfg.interval_secs.max(3600);
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            if !db::JobLease::hold(&app.scylla, "archive", interval).await {
+                continue;
+            }
+            let start = unix_ms();
+            match run_once(&app.scylla, &cfg).await {
+                Ok(stats) => log::info!(target: "archive",
+                    scanned = stats.scanned,
+                    archived = stats.archived,
+                    skipped = stats.skipped,
+                    failed = stats.failed,
+                    elapsed = unix_ms() - start;
+                    "",
+                ),
+                Err(err) => log::error!(target: "archive", "archive job failed: {}", err),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idle_before_works() {
+        let now = 1_700_000_000_000i64;
+        assert_eq!(now - 6 * MONTH_MS, idle_before(now, 6));
+        assert_eq!(now - MONTH_MS, idle_before(now, 1));
+        assert_eq!(now - MONTH_MS, idle_before(now, 0));
+    }
+}
@@ -0,0 +1,942 @@
+// Typed async client of walletbase for peer services, enabled by the `client` feature:
+//
+//     let cli = walletbase::client::Client::new("http://walletbase:8080")?;
+//     let wallet = cli.get_wallet(&QueryUid { uid: PackObject::Json(uid), fields: None }).await?;
+//     let wallet = cli.with_idempotency_key("order-123").spend(&input).await?;
+//
+// Request and response bodies are encoded in CBOR, so PackObject fields of inputs should be
+// PackObject::Cbor. Query structs are URL encoded, their PackObject fields should be PackObject::Json.
+// Errors responded by walletbase are returned as HTTPError in anyhow::Error.
+use reqwest::{header, Method, StatusCode};
+use serde::{de::DeserializeOwned, Serialize};
+use std::time::Duration;
+
+use axum_web::context::REQUEST_TIMEOUT_HEADER;
+use axum_web::erring::{ErrorResponse, HTTPError, SuccessResponse};
+
+use crate::api::{
+    adjustment, api_key, audit, budget, charge, currency, customer, dispute, export, hold, pool,
+    redpacket, subscription, transaction, wallet, wallet_pref, webhook, withdrawal, AppInfo,
+    AppVersion, Pagination, QueryHealthz, QueryUid, QueryUidId,
+};
+use crate::money;
+
+pub const IDEMPOTENCY_KEY: &str = "idempotency-key";
+
+const DEFAULT_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_MAX_RETRIES: u32 = 2;
+const DEFAULT_BACKOFF_MS: u64 = 100;
+const MAX_BACKOFF_MS: u64 = 3000;
+
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    endpoint: String,
+    max_retries: u32,
+    backoff_ms: u64,
+    user: Option<xid::Id>,
+    idempotency_key: Option<String>,
+}
+
+impl Client {
+    pub fn new(endpoint: &str) -> anyhow::Result<Self> {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(DEFAULT_TIMEOUT_SECS))
+            .build()?;
+        Ok(Self {
+            http,
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            max_retries: DEFAULT_MAX_RETRIES,
+            backoff_ms: DEFAULT_BACKOFF_MS,
+            user: None,
+            idempotency_key: None,
+        })
+    }
+
+    // 0 disables retries.
+    pub fn with_retries(mut self, max_retries: u32, backoff_ms: u64) -> Self {
+        self.max_retries = max_retries;
+        self.backoff_ms = backoff_ms;
+        self
+    }
+
+    // requests are made on behalf of the user, sent as the x-auth-user header.
+    pub fn with_user(&self, uid: xid::Id) -> Self {
+        let mut cli = self.clone();
+        cli.user = Some(uid);
+        cli
+    }
+
+    // the key is sent with every attempt of the requests made by the returned client,
+    // it should be unique per operation, e.g. the caller's order id.
+    // a random key is used per request if it is not set.
+    pub fn with_idempotency_key(&self, key: &str) -> Self {
+        let mut cli = self.clone();
+        cli.idempotency_key = Some(key.to_string());
+        cli
+    }
+
+    pub async fn version(&self) -> anyhow::Result<AppVersion> {
+        self.send::<(), (), AppVersion>(Method::GET, "/", None, None, true)
+            .await
+    }
+
+    pub async fn healthz(&self, query: &QueryHealthz) -> anyhow::Result<AppInfo> {
+        self.send::<_, (), AppInfo>(Method::GET, "/healthz", Some(query), None, true)
+            .await
+    }
+
+    pub async fn currencies(
+        &self,
+        query: &currency::QueryCurrencies,
+    ) -> anyhow::Result<Vec<currency::Currency>> {
+        self.get("/currencies", query).await
+    }
+
+    pub async fn format_amount(
+        &self,
+        query: &currency::QueryFormatAmount,
+    ) -> anyhow::Result<currency::FormatAmountOutput> {
+        self.get("/v1/format_amount", query).await
+    }
+
+    // wallet
+
+    pub async fn get_wallet(&self, query: &QueryUid) -> anyhow::Result<wallet::WalletOutput> {
+        self.get("/v1/wallet", query).await
+    }
+
+    pub async fn get_denomination(&self) -> anyhow::Result<money::Denomination> {
+        self.get("/v1/wallet/denomination", &()).await
+    }
+
+    pub async fn list_credits(
+        &self,
+        input: &Pagination,
+    ) -> anyhow::Result<SuccessResponse<Vec<wallet::CreditOutput>>> {
+        self.list("/v1/wallet/list_credits", input).await
+    }
+
+    pub async fn credits_summary(
+        &self,
+        query: &QueryUid,
+    ) -> anyhow::Result<Vec<wallet::CreditSummaryOutput>> {
+        self.get("/v1/wallet/credits/summary", query).await
+    }
+
+    pub async fn weekly_summary(
+        &self,
+        query: &wallet::QueryWeeklySummary,
+    ) -> anyhow::Result<wallet::WeeklySummaryOutput> {
+        self.get("/v1/wallet/summary/weekly", query).await
+    }
+
+    // the award is not committed, it should be committed or cancelled by the caller.
+    pub async fn award(&self, input: &wallet::AwardInput) -> anyhow::Result<wallet::WalletOutput> {
+        self.post("/v1/wallet/award", input).await
+    }
+
+    // the spend is not committed, it should be committed or cancelled by the caller.
+    pub async fn spend(&self, input: &wallet::SpendInput) -> anyhow::Result<wallet::WalletOutput> {
+        self.post("/v1/wallet/spend", input).await
+    }
+
+    pub async fn sponsor(
+        &self,
+        input: &wallet::SpendInput,
+    ) -> anyhow::Result<wallet::WalletOutput> {
+        self.post("/v1/wallet/sponsor", input).await
+    }
+
+    pub async fn subscribe(
+        &self,
+        input: &wallet::SpendInput,
+    ) -> anyhow::Result<wallet::WalletOutput> {
+        self.post("/v1/wallet/subscribe", input).await
+    }
+
+    pub async fn simulate(
+        &self,
+        input: &wallet::SimulateInput,
+    ) -> anyhow::Result<wallet::SimulationOutput> {
+        self.post_idempotent("/v1/wallet/simulate", input).await
+    }
+
+    pub async fn get_preferences(
+        &self,
+        query: &QueryUid,
+    ) -> anyhow::Result<wallet_pref::PreferencesOutput> {
+        self.get("/v1/wallet/preferences", query).await
+    }
+
+    pub async fn update_preferences(
+        &self,
+        input: &wallet_pref::PreferencesInput,
+    ) -> anyhow::Result<wallet_pref::PreferencesOutput> {
+        self.put("/v1/wallet/preferences", input).await
+    }
+
+    pub async fn get_webhook(&self, query: &QueryUid) -> anyhow::Result<webhook::WebhookOutput> {
+        self.get("/v1/wallet/webhook", query).await
+    }
+
+    pub async fn update_webhook(
+        &self,
+        input: &webhook::WebhookInput,
+    ) -> anyhow::Result<webhook::WebhookOutput> {
+        self.put("/v1/wallet/webhook", input).await
+    }
+
+    pub async fn delete_webhook(&self, query: &QueryUid) -> anyhow::Result<bool> {
+        self.delete("/v1/wallet/webhook", query).await
+    }
+
+    pub async fn create_api_key(
+        &self,
+        input: &api_key::ApiKeyInput,
+    ) -> anyhow::Result<api_key::ApiKeyOutput> {
+        self.post("/v1/wallet/api_key", input).await
+    }
+
+    pub async fn list_api_keys(
+        &self,
+        query: &QueryUid,
+    ) -> anyhow::Result<Vec<api_key::ApiKeyOutput>> {
+        self.get("/v1/wallet/api_key/list", query).await
+    }
+
+    pub async fn revoke_api_key(&self, query: &QueryUidId) -> anyhow::Result<bool> {
+        self.delete("/v1/wallet/api_key", query).await
+    }
+
+    pub async fn withdraw(
+        &self,
+        input: &withdrawal::WithdrawInput,
+    ) -> anyhow::Result<withdrawal::WithdrawalOutput> {
+        self.post("/v1/wallet/withdraw", input).await
+    }
+
+    pub async fn hold(&self, input: &hold::HoldInput) -> anyhow::Result<hold::HoldOutput> {
+        self.post("/v1/wallet/hold", input).await
+    }
+
+    pub async fn capture_hold(
+        &self,
+        input: &hold::CaptureInput,
+    ) -> anyhow::Result<transaction::TransactionOutput> {
+        self.post("/v1/wallet/hold/capture", input).await
+    }
+
+    pub async fn release_hold(&self, input: &hold::ReleaseInput) -> anyhow::Result<bool> {
+        self.post_idempotent("/v1/wallet/hold/release", input).await
+    }
+
+    // the escrow is committed, the unclaimed remainder is refunded to the sender after expired.
+    pub async fn create_redpacket(
+        &self,
+        input: &redpacket::RedpacketInput,
+    ) -> anyhow::Result<redpacket::RedpacketOutput> {
+        self.post("/v1/wallet/redpacket", input).await
+    }
+
+    pub async fn get_redpacket(
+        &self,
+        query: &redpacket::QueryRedpacket,
+    ) -> anyhow::Result<redpacket::RedpacketOutput> {
+        self.get("/v1/wallet/redpacket", query).await
+    }
+
+    // claiming can be retried, a paid claim is returned as it is.
+    pub async fn claim_redpacket(
+        &self,
+        input: &redpacket::ClaimInput,
+    ) -> anyhow::Result<redpacket::ClaimOutput> {
+        self.post_idempotent("/v1/wallet/redpacket/claim", input)
+            .await
+    }
+
+    // the first period is paid, the next ones are renewed by the subscription job.
+    pub async fn create_subscription(
+        &self,
+        input: &subscription::SubscriptionInput,
+    ) -> anyhow::Result<subscription::SubscriptionOutput> {
+        self.post("/v1/wallet/subscription", input).await
+    }
+
+    pub async fn get_subscription(
+        &self,
+        query: &subscription::QuerySubscription,
+    ) -> anyhow::Result<subscription::SubscriptionOutput> {
+        self.get("/v1/wallet/subscription", query).await
+    }
+
+    pub async fn cancel_subscription(
+        &self,
+        input: &subscription::CancelSubscriptionInput,
+    ) -> anyhow::Result<bool> {
+        self.post("/v1/wallet/subscription/cancel", input).await
+    }
+
+    // charge
+
+    pub async fn create_charge(
+        &self,
+        input: &charge::ChargeInput,
+    ) -> anyhow::Result<charge::ChargeOutput> {
+        self.post("/v1/charge", input).await
+    }
+
+    pub async fn get_charge(&self, query: &QueryUidId) -> anyhow::Result<charge::ChargeOutput> {
+        self.get("/v1/charge", query).await
+    }
+
+    // the update is conditional on the charge's current status, so it is safe to retry.
+    pub async fn update_charge(
+        &self,
+        input: &charge::UpdateChargeInput,
+    ) -> anyhow::Result<charge::ChargeOutput> {
+        self.patch("/v1/charge", input).await
+    }
+
+    pub async fn list_charges(
+        &self,
+        input: &Pagination,
+    ) -> anyhow::Result<SuccessResponse<Vec<charge::ChargeOutput>>> {
+        self.list("/v1/charge/list", input).await
+    }
+
+    pub async fn complete_charge(
+        &self,
+        input: &charge::CompleteChargeInput,
+    ) -> anyhow::Result<charge::ChargeOutput> {
+        self.post_idempotent("/v1/charge/complete", input).await
+    }
+
+    pub async fn refund_charge(
+        &self,
+        input: &charge::RefundChargeInput,
+    ) -> anyhow::Result<charge::ChargeOutput> {
+        self.post("/v1/charge/refund", input).await
+    }
+
+    // the receipt of a completed charge, verify it with receipt::verify.
+    pub async fn get_receipt(&self, query: &QueryUidId) -> anyhow::Result<charge::ReceiptOutput> {
+        self.get("/v1/charge/receipt", query).await
+    }
+
+    pub async fn get_receipt_key(&self) -> anyhow::Result<charge::ReceiptKeyOutput> {
+        self.get("/v1/charge/receipt/key", &()).await
+    }
+
+    // transaction
+
+    pub async fn get_transaction(
+        &self,
+        query: &QueryUidId,
+    ) -> anyhow::Result<transaction::TransactionOutput> {
+        self.get("/v1/transaction", query).await
+    }
+
+    pub async fn first_from_system(
+        &self,
+        query: &QueryUid,
+    ) -> anyhow::Result<transaction::TransactionOutput> {
+        self.get("/v1/transaction/first_from_system", query).await
+    }
+
+    pub async fn list_transaction_children(
+        &self,
+        query: &QueryUidId,
+    ) -> anyhow::Result<Vec<transaction::TransactionOutput>> {
+        self.get("/v1/transaction/children", query).await
+    }
+
+    pub async fn list_pending(
+        &self,
+        query: &transaction::QueryPending,
+    ) -> anyhow::Result<SuccessResponse<Vec<transaction::TransactionOutput>>> {
+        self.send(
+            Method::GET,
+            "/v1/transaction/pending",
+            Some(query),
+            None::<&()>,
+            true,
+        )
+        .await
+    }
+
+    pub async fn list_outgo(
+        &self,
+        input: &Pagination,
+    ) -> anyhow::Result<SuccessResponse<Vec<transaction::TransactionOutput>>> {
+        self.list("/v1/transaction/list_outgo", input).await
+    }
+
+    pub async fn list_income(
+        &self,
+        input: &Pagination,
+    ) -> anyhow::Result<SuccessResponse<Vec<transaction::TransactionOutput>>> {
+        self.list("/v1/transaction/list_income", input).await
+    }
+
+    pub async fn aggregate(
+        &self,
+        query: &transaction::QueryAggregate,
+    ) -> anyhow::Result<SuccessResponse<Vec<transaction::AggregateOutput>>> {
+        self.send(
+            Method::GET,
+            "/v1/transaction/aggregate",
+            Some(query),
+            None::<&()>,
+            true,
+        )
+        .await
+    }
+
+    pub async fn aggregate_income(
+        &self,
+        query: &transaction::QueryAggregateIncome,
+    ) -> anyhow::Result<SuccessResponse<Vec<transaction::AggregateOutput>>> {
+        self.send(
+            Method::GET,
+            "/v1/transaction/aggregate_income",
+            Some(query),
+            None::<&()>,
+            true,
+        )
+        .await
+    }
+
+    pub async fn dispute(
+        &self,
+        input: &dispute::DisputeInput,
+    ) -> anyhow::Result<dispute::DisputeOutput> {
+        self.post("/v1/transaction/dispute", input).await
+    }
+
+    // committing a committed transaction is a no-op, so it is safe to retry.
+    pub async fn commit(
+        &self,
+        input: &transaction::TransactionInput,
+    ) -> anyhow::Result<transaction::TransactionOutput> {
+        self.post_idempotent("/v1/transaction/commit", input).await
+    }
+
+    pub async fn cancel(
+        &self,
+        input: &transaction::TransactionInput,
+    ) -> anyhow::Result<transaction::TransactionOutput> {
+        self.post_idempotent("/v1/transaction/cancel", input).await
+    }
+
+    // customer
+
+    pub async fn upsert_customer(
+        &self,
+        input: &customer::CustomerInput,
+    ) -> anyhow::Result<customer::CustomerOutput> {
+        self.post_idempotent("/v1/customer", input).await
+    }
+
+    pub async fn get_customer(
+        &self,
+        query: &customer::QueryCustomer,
+    ) -> anyhow::Result<customer::CustomerOutput> {
+        self.get("/v1/customer", query).await
+    }
+
+    pub async fn list_customers(
+        &self,
+        query: &customer::QueryCustomerList,
+    ) -> anyhow::Result<Vec<customer::CustomerOutput>> {
+        self.get("/v1/customer/list", query).await
+    }
+
+    pub async fn get_customer_by_customer(
+        &self,
+        query: &customer::QueryByCustomer,
+    ) -> anyhow::Result<customer::CustomerOutput> {
+        self.get("/v1/customer/by_customer", query).await
+    }
+
+    pub async fn delete_customer(&self, query: &customer::QueryCustomer) -> anyhow::Result<bool> {
+        self.delete("/v1/customer", query).await
+    }
+
+    // pool
+
+    pub async fn create_pool(&self, input: &pool::PoolInput) -> anyhow::Result<pool::PoolOutput> {
+        self.post("/v1/pool", input).await
+    }
+
+    pub async fn get_pool(&self, query: &pool::QueryPool) -> anyhow::Result<pool::PoolOutput> {
+        self.get("/v1/pool", query).await
+    }
+
+    pub async fn contribute_pool(
+        &self,
+        input: &pool::ContributeInput,
+    ) -> anyhow::Result<pool::ContributionOutput> {
+        self.post("/v1/pool/contribute", input).await
+    }
+
+    // finalizing can be retried.
+    pub async fn finalize_pool(
+        &self,
+        input: &pool::FinalizeInput,
+    ) -> anyhow::Result<pool::PoolOutput> {
+        self.post_idempotent("/v1/pool/finalize", input).await
+    }
+
+    // admin
+
+    pub async fn create_budget(
+        &self,
+        input: &budget::BudgetInput,
+    ) -> anyhow::Result<budget::BudgetOutput> {
+        self.post("/v1/admin/budget", input).await
+    }
+
+    pub async fn get_budget(
+        &self,
+        query: &budget::QueryBudget,
+    ) -> anyhow::Result<budget::BudgetOutput> {
+        self.get("/v1/admin/budget", query).await
+    }
+
+    pub async fn update_budget(
+        &self,
+        input: &budget::UpdateBudgetInput,
+    ) -> anyhow::Result<budget::BudgetOutput> {
+        self.patch("/v1/admin/budget", input).await
+    }
+
+    pub async fn delete_budget(&self, query: &budget::QueryBudget) -> anyhow::Result<bool> {
+        self.delete("/v1/admin/budget", query).await
+    }
+
+    pub async fn list_charges_by_day(
+        &self,
+        query: &charge::QueryChargesByDay,
+    ) -> anyhow::Result<SuccessResponse<Vec<charge::ChargeOutput>>> {
+        self.send(
+            Method::GET,
+            "/v1/admin/charges",
+            Some(query),
+            None::<&()>,
+            true,
+        )
+        .await
+    }
+
+    pub async fn system_stats(
+        &self,
+        query: &wallet::QuerySystemStats,
+    ) -> anyhow::Result<wallet::SystemStatsOutput> {
+        self.get("/v1/admin/system_stats", query).await
+    }
+
+    pub async fn daily_stats(
+        &self,
+        query: &wallet::QueryDailyStats,
+    ) -> anyhow::Result<wallet::DailyStatsOutput> {
+        self.get("/v1/admin/stats/daily", query).await
+    }
+
+    pub async fn get_liabilities(&self) -> anyhow::Result<Vec<wallet::LiabilityOutput>> {
+        self.get("/v1/admin/liabilities", &()).await
+    }
+
+    pub async fn update_max_overdraw(
+        &self,
+        input: &wallet::MaxOverdrawInput,
+    ) -> anyhow::Result<wallet::WalletOutput> {
+        self.post_idempotent("/v1/admin/wallet/max_overdraw", input)
+            .await
+    }
+
+    pub async fn close_wallet(
+        &self,
+        input: &wallet::CloseWalletInput,
+    ) -> anyhow::Result<wallet::WalletOutput> {
+        self.post("/v1/admin/wallet/close", input).await
+    }
+
+    pub async fn scan_integrity(
+        &self,
+        query: &wallet::QueryIntegrity,
+    ) -> anyhow::Result<SuccessResponse<Vec<wallet::IntegrityOutput>>> {
+        self.send(
+            Method::GET,
+            "/v1/admin/wallet/integrity",
+            Some(query),
+            None::<&()>,
+            true,
+        )
+        .await
+    }
+
+    pub async fn backfill_checksum(
+        &self,
+        input: &wallet::BackfillChecksumInput,
+    ) -> anyhow::Result<wallet::BackfillChecksumOutput> {
+        self.post_idempotent("/v1/admin/wallet/checksum/backfill", input)
+            .await
+    }
+
+    // reads all the exported rows of the CBOR sequence into memory, it is not retried.
+    pub async fn export_transactions(
+        &self,
+        query: &export::QueryExportTransactions,
+    ) -> anyhow::Result<Vec<export::ExportTransactionRow>> {
+        let url = format!("{}/v1/admin/export/transactions", self.endpoint);
+        let mut req = self
+            .http
+            .get(&url)
+            .header(header::ACCEPT, "application/cbor")
+            .query(query);
+        if let Some(uid) = self.user {
+            req = req.header("x-auth-user", uid.to_string());
+        }
+
+        let res = req.send().await?;
+        let status = res.status();
+        let data = res.bytes().await?;
+        if !status.is_success() {
+            return Err(decode_error(status, &data).into());
+        }
+
+        let mut rows: Vec<export::ExportTransactionRow> = Vec::new();
+        let mut rd = &data[..];
+        while !rd.is_empty() {
+            rows.push(ciborium::from_reader(&mut rd)?);
+        }
+        Ok(rows)
+    }
+
+    pub async fn adjust(
+        &self,
+        input: &adjustment::AdjustInput,
+    ) -> anyhow::Result<adjustment::AdjustmentOutput> {
+        self.post("/v1/admin/wallet/adjust", input).await
+    }
+
+    pub async fn get_adjustment(
+        &self,
+        query: &adjustment::QueryAdjustment,
+    ) -> anyhow::Result<adjustment::AdjustmentOutput> {
+        self.get("/v1/admin/wallet/adjust", query).await
+    }
+
+    pub async fn approve_adjustment(
+        &self,
+        input: &adjustment::ApproveInput,
+    ) -> anyhow::Result<adjustment::AdjustmentOutput> {
+        self.post("/v1/admin/wallet/adjust/approve", input).await
+    }
+
+    pub async fn get_withdrawal(
+        &self,
+        query: &withdrawal::QueryWithdrawal,
+    ) -> anyhow::Result<withdrawal::WithdrawalOutput> {
+        self.get("/v1/admin/withdrawal", query).await
+    }
+
+    pub async fn list_pending_withdrawals(
+        &self,
+        query: &withdrawal::QueryPendingWithdrawals,
+    ) -> anyhow::Result<SuccessResponse<Vec<withdrawal::WithdrawalOutput>>> {
+        self.send(
+            Method::GET,
+            "/v1/admin/withdrawal/queue",
+            Some(query),
+            None::<&()>,
+            true,
+        )
+        .await
+    }
+
+    pub async fn review_withdrawal(
+        &self,
+        input: &withdrawal::ReviewInput,
+    ) -> anyhow::Result<withdrawal::WithdrawalOutput> {
+        self.post("/v1/admin/withdrawal/review", input).await
+    }
+
+    pub async fn get_dispute(
+        &self,
+        query: &dispute::QueryDispute,
+    ) -> anyhow::Result<dispute::DisputeOutput> {
+        self.get("/v1/admin/dispute", query).await
+    }
+
+    pub async fn list_pending_disputes(
+        &self,
+        query: &dispute::QueryPendingDisputes,
+    ) -> anyhow::Result<SuccessResponse<Vec<dispute::DisputeOutput>>> {
+        self.send(
+            Method::GET,
+            "/v1/admin/dispute/queue",
+            Some(query),
+            None::<&()>,
+            true,
+        )
+        .await
+    }
+
+    pub async fn resolve_dispute(
+        &self,
+        input: &dispute::ResolveInput,
+    ) -> anyhow::Result<dispute::DisputeOutput> {
+        self.post("/v1/admin/dispute/resolve", input).await
+    }
+
+    pub async fn get_transaction_history(
+        &self,
+        query: &QueryUidId,
+    ) -> anyhow::Result<Vec<transaction::TxnHistoryOutput>> {
+        self.get("/v1/admin/transaction/history", query).await
+    }
+
+    pub async fn list_audit_log(
+        &self,
+        query: &audit::QueryAuditLog,
+    ) -> anyhow::Result<SuccessResponse<Vec<audit::AuditLogOutput>>> {
+        self.send(
+            Method::GET,
+            "/v1/admin/audit_log",
+            Some(query),
+            None::<&()>,
+            true,
+        )
+        .await
+    }
+
+    // generic requests, they return the result of the success response.
+
+    pub async fn get<Q: Serialize, O: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &Q,
+    ) -> anyhow::Result<O> {
+        let res: SuccessResponse<O> = self
+            .send(Method::GET, path, Some(query), None::<&()>, true)
+            .await?;
+        Ok(res.result)
+    }
+
+    // the request is not idempotent, it is retried only if it was not sent.
+    pub async fn post<I: Serialize, O: DeserializeOwned>(
+        &self,
+        path: &str,
+        input: &I,
+    ) -> anyhow::Result<O> {
+        let res: SuccessResponse<O> = self
+            .send(Method::POST, path, None::<&()>, Some(input), false)
+            .await?;
+        Ok(res.result)
+    }
+
+    pub async fn post_idempotent<I: Serialize, O: DeserializeOwned>(
+        &self,
+        path: &str,
+        input: &I,
+    ) -> anyhow::Result<O> {
+        let res: SuccessResponse<O> = self
+            .send(Method::POST, path, None::<&()>, Some(input), true)
+            .await?;
+        Ok(res.result)
+    }
+
+    pub async fn put<I: Serialize, O: DeserializeOwned>(
+        &self,
+        path: &str,
+        input: &I,
+    ) -> anyhow::Result<O> {
+        let res: SuccessResponse<O> = self
+            .send(Method::PUT, path, None::<&()>, Some(input), true)
+            .await?;
+        Ok(res.result)
+    }
+
+    pub async fn patch<I: Serialize, O: DeserializeOwned>(
+        &self,
+        path: &str,
+        input: &I,
+    ) -> anyhow::Result<O> {
+        let res: SuccessResponse<O> = self
+            .send(Method::PATCH, path, None::<&()>, Some(input), true)
+            .await?;
+        Ok(res.result)
+    }
+
+    pub async fn delete<Q: Serialize, O: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &Q,
+    ) -> anyhow::Result<O> {
+        let res: SuccessResponse<O> = self
+            .send(Method::DELETE, path, Some(query), None::<&()>, true)
+            .await?;
+        Ok(res.result)
+    }
+
+    async fn list<I: Serialize, O: DeserializeOwned>(
+        &self,
+        path: &str,
+        input: &I,
+    ) -> anyhow::Result<SuccessResponse<Vec<O>>> {
+        self.send(Method::POST, path, None::<&()>, Some(input), true)
+            .await
+    }
+
+    // idempotent requests are retried on transport errors and retryable status,
+    // others are retried only when the connection failed before the request was sent.
+    pub async fn send<Q: Serialize, I: Serialize, O: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        query: Option<&Q>,
+        input: Option<&I>,
+        idempotent: bool,
+    ) -> anyhow::Result<O> {
+        let url = format!("{}{}", self.endpoint, path);
+        let body = match input {
+            Some(input) => {
+                let mut buf: Vec<u8> = Vec::new();
+                ciborium::into_writer(input, &mut buf)?;
+                Some(buf)
+            }
+            None => None,
+        };
+        // the same key is sent with every attempt, so that retries can be correlated.
+        let key = self
+            .idempotency_key
+            .clone()
+            .unwrap_or_else(|| xid::new().to_string());
+
+        let mut attempt: u32 = 0;
+        loop {
+            let mut req = self
+                .http
+                .request(method.clone(), &url)
+                .header(header::ACCEPT, "application/cbor")
+                .header("x-request-id", &key)
+                .header(IDEMPOTENCY_KEY, &key)
+                // the server stops querying when the client gives up.
+                .header(REQUEST_TIMEOUT_HEADER, DEFAULT_TIMEOUT_SECS * 1000);
+            if let Some(uid) = self.user {
+                req = req.header("x-auth-user", uid.to_string());
+            }
+            if let Some(query) = query {
+                req = req.query(query);
+            }
+            if let Some(body) = &body {
+                req = req
+                    .header(header::CONTENT_TYPE, "application/cbor")
+                    .body(body.clone());
+            }
+
+            let can_retry = attempt < self.max_retries;
+            match req.send().await {
+                Ok(res) => {
+                    let status = res.status();
+                    if can_retry && idempotent && is_retryable(status) {
+                        attempt += 1;
+                        tokio::time::sleep(backoff(self.backoff_ms, attempt)).await;
+                        continue;
+                    }
+
+                    let data = res.bytes().await?;
+                    // 202 is a partly committed transaction with the pending legs in the error's data.
+                    if status.is_success() && status != StatusCode::ACCEPTED {
+                        return Ok(ciborium::from_reader(&data[..])?);
+                    }
+                    return Err(decode_error(status, &data).into());
+                }
+                Err(err) => {
+                    if can_retry && (idempotent || err.is_connect()) {
+                        attempt += 1;
+                        tokio::time::sleep(backoff(self.backoff_ms, attempt)).await;
+                        continue;
+                    }
+                    return Err(err.into());
+                }
+            }
+        }
+    }
+}
+
+// 500 is not retried, it may be an invariant violation that should be recovered manually.
+fn is_retryable(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+fn backoff(base_ms: u64, attempt: u32) -> Duration {
+    let exp = attempt.saturating_sub(1).min(16);
+    Duration::from_millis(base_ms.saturating_mul(1 << exp).min(MAX_BACKOFF_MS))
+}
+
+// error responses are encoded in JSON.
+fn decode_error(status: StatusCode, data: &[u8]) -> HTTPError {
+    match serde_json::from_slice::<ErrorResponse>(data) {
+        Ok(res) => res.error,
+        Err(_) => HTTPError::new(status.as_u16(), String::from_utf8_lossy(data).into_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_retryable_works() {
+        assert!(is_retryable(StatusCode::TOO_MANY_REQUESTS));
+        assert!(is_retryable(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(!is_retryable(StatusCode::OK));
+        assert!(!is_retryable(StatusCode::BAD_REQUEST));
+        assert!(!is_retryable(StatusCode::CONFLICT));
+        assert!(!is_retryable(StatusCode::INTERNAL_SERVER_ERROR));
+    }
+
+    #[test]
+    fn backoff_works() {
+        assert_eq!(Duration::from_millis(100), backoff(100, 1));
+        assert_eq!(Duration::from_millis(200), backoff(100, 2));
+        assert_eq!(Duration::from_millis(MAX_BACKOFF_MS), backoff(100, 10));
+        assert_eq!(Duration::from_millis(0), backoff(0, 3));
+    }
+
+    #[test]
+    fn decode_error_works() {
+        let err = decode_error(
+            StatusCode::BAD_REQUEST,
+            br#"{"error":{"code":400,"message":"invalid amount"}}"#,
+        );
+        assert_eq!(400, err.code);
+        assert_eq!("invalid amount", err.message);
+
+        let err = decode_error(StatusCode::BAD_GATEWAY, b"bad gateway");
+        assert_eq!(502, err.code);
+        assert_eq!("bad gateway", err.message);
+    }
+
+    #[test]
+    fn client_works() {
+        let cli = Client::new("http://127.0.0.1:8080/").unwrap();
+        assert_eq!("http://127.0.0.1:8080", cli.endpoint);
+        assert!(cli.idempotency_key.is_none());
+
+        let uid = xid::new();
+        let cli2 = cli.with_user(uid).with_idempotency_key("order-1");
+        assert_eq!(Some(uid), cli2.user);
+        assert_eq!(Some("order-1".to_string()), cli2.idempotency_key);
+        assert!(cli.user.is_none());
+    }
+}
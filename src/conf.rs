@@ -1,5 +1,6 @@
 use config::{Config, ConfigError, File, FileFormat};
 use serde::Deserialize;
+use std::{collections::HashMap, str::FromStr};
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Log {
@@ -12,6 +13,10 @@ pub struct Server {
     pub cert_file: String,
     pub key_file: String,
     pub graceful_shutdown: usize,
+    #[serde(default)]
+    pub unix_socket: String,
+    #[serde(default)]
+    pub http2: bool,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -19,6 +24,65 @@ pub struct ScyllaDB {
     pub nodes: Vec<String>,
     pub username: String,
     pub password: String,
+    #[serde(default = "default_query_timeout_ms")]
+    pub query_timeout_ms: u64,
+    #[serde(default = "default_bypass_cache")]
+    pub bypass_cache: bool,
+    #[serde(default = "default_breaker_error_rate")]
+    pub breaker_error_rate: u32,
+    #[serde(default = "default_breaker_min_requests")]
+    pub breaker_min_requests: u32,
+    #[serde(default = "default_breaker_window_secs")]
+    pub breaker_window_secs: u64,
+    #[serde(default = "default_breaker_open_secs")]
+    pub breaker_open_secs: u64,
+    #[serde(default = "default_breaker_half_open_probes")]
+    pub breaker_half_open_probes: u32,
+}
+
+fn default_query_timeout_ms() -> u64 {
+    3000
+}
+
+fn default_bypass_cache() -> bool {
+    true
+}
+
+fn default_breaker_error_rate() -> u32 {
+    50
+}
+
+fn default_breaker_min_requests() -> u32 {
+    20
+}
+
+fn default_breaker_window_secs() -> u64 {
+    10
+}
+
+fn default_breaker_open_secs() -> u64 {
+    5
+}
+
+fn default_breaker_half_open_probes() -> u32 {
+    3
+}
+
+impl Default for ScyllaDB {
+    fn default() -> Self {
+        Self {
+            nodes: Vec::new(),
+            username: "".to_string(),
+            password: "".to_string(),
+            query_timeout_ms: default_query_timeout_ms(),
+            bypass_cache: default_bypass_cache(),
+            breaker_error_rate: default_breaker_error_rate(),
+            breaker_min_requests: default_breaker_min_requests(),
+            breaker_window_secs: default_breaker_window_secs(),
+            breaker_open_secs: default_breaker_open_secs(),
+            breaker_half_open_probes: default_breaker_half_open_probes(),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -28,13 +92,782 @@ pub struct Keys {
     pub wallet_key_file: String,
 }
 
+#[derive(Debug, Deserialize, Clone)]
+pub struct Wallet {
+    pub max_overdraw: i64,
+    #[serde(default)]
+    pub withdraw_review_threshold: i64,
+    #[serde(default = "default_lwt_max_attempts")]
+    pub lwt_max_attempts: u32,
+    #[serde(default = "default_lwt_backoff_ms")]
+    pub lwt_backoff_ms: u64,
+    #[serde(default = "default_livemode")]
+    pub livemode: bool,
+    #[serde(default)]
+    pub max_amounts: HashMap<String, i64>,
+    #[serde(default)]
+    pub min_credits: HashMap<String, i64>,
+    #[serde(default)]
+    pub currencies: Vec<String>,
+    #[serde(default = "default_max_payload_size")]
+    pub max_payload_size: usize,
+    #[serde(default)]
+    pub integrity_check_depth: u16,
+    #[serde(default = "default_export_rate_limit")]
+    pub export_rate_limit: u32,
+    #[serde(default = "default_export_max_rows")]
+    pub export_max_rows: u64,
+    #[serde(default = "default_charge_expire_secs")]
+    pub charge_expire_secs: i64,
+    #[serde(default = "default_charge_min_expire_secs")]
+    pub charge_min_expire_secs: i64,
+    #[serde(default = "default_charge_max_expire_secs")]
+    pub charge_max_expire_secs: i64,
+    #[serde(default = "default_cancel_window_secs")]
+    pub cancel_window_secs: i64,
+    #[serde(default)]
+    pub award_quota: i64,
+    #[serde(default)]
+    pub award_quotas: HashMap<String, i64>,
+}
+
+fn default_lwt_max_attempts() -> u32 {
+    5
+}
+
+fn default_lwt_backoff_ms() -> u64 {
+    10
+}
+
+fn default_livemode() -> bool {
+    true
+}
+
+fn default_max_payload_size() -> usize {
+    16 * 1024
+}
+
+fn default_export_rate_limit() -> u32 {
+    10
+}
+
+fn default_export_max_rows() -> u64 {
+    100_000
+}
+
+fn default_cancel_window_secs() -> i64 {
+    24 * 3600
+}
+
+fn default_charge_expire_secs() -> i64 {
+    24 * 3600
+}
+
+fn default_charge_min_expire_secs() -> i64 {
+    300
+}
+
+fn default_charge_max_expire_secs() -> i64 {
+    7 * 24 * 3600
+}
+
+impl Default for Wallet {
+    fn default() -> Self {
+        Self {
+            max_overdraw: 100,
+            withdraw_review_threshold: 0,
+            lwt_max_attempts: default_lwt_max_attempts(),
+            lwt_backoff_ms: default_lwt_backoff_ms(),
+            livemode: default_livemode(),
+            max_amounts: HashMap::new(),
+            min_credits: HashMap::new(),
+            currencies: Vec::new(),
+            max_payload_size: default_max_payload_size(),
+            integrity_check_depth: 0,
+            export_rate_limit: default_export_rate_limit(),
+            export_max_rows: default_export_max_rows(),
+            charge_expire_secs: default_charge_expire_secs(),
+            charge_min_expire_secs: default_charge_min_expire_secs(),
+            charge_max_expire_secs: default_charge_max_expire_secs(),
+            cancel_window_secs: default_cancel_window_secs(),
+            award_quota: 0,
+            award_quotas: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct PolicyRule {
+    pub name: String,
+    pub kind: String, // "decay", "interest" or "expiry"
+    #[serde(default)]
+    pub inactive_days: i64,
+    #[serde(default)]
+    pub bps: u16,
+    #[serde(default = "default_policy_period_days")]
+    pub period_days: i64,
+    #[serde(default)]
+    pub min_balance: i64,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Policy {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_policy_interval_secs")]
+    pub interval_secs: u64,
+    #[serde(default = "default_policy_page_size")]
+    pub page_size: u16,
+    #[serde(default)]
+    pub rules: Vec<PolicyRule>,
+}
+
+fn default_policy_period_days() -> i64 {
+    30
+}
+
+fn default_policy_interval_secs() -> u64 {
+    3600
+}
+
+fn default_policy_page_size() -> u16 {
+    100
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: default_policy_interval_secs(),
+            page_size: default_policy_page_size(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Reminder {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_reminder_interval_secs")]
+    pub interval_secs: u64,
+    #[serde(default = "default_reminder_lead_secs")]
+    pub lead_secs: i64,
+    #[serde(default = "default_reminder_max_per_charge")]
+    pub max_per_charge: i8,
+    #[serde(default = "default_reminder_max_per_user_daily")]
+    pub max_per_user_daily: i64,
+    #[serde(default = "default_policy_page_size")]
+    pub page_size: u16,
+}
+
+fn default_reminder_interval_secs() -> u64 {
+    600
+}
+
+fn default_reminder_lead_secs() -> i64 {
+    3600
+}
+
+fn default_reminder_max_per_charge() -> i8 {
+    1
+}
+
+fn default_reminder_max_per_user_daily() -> i64 {
+    3
+}
+
+impl Default for Reminder {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: default_reminder_interval_secs(),
+            lead_secs: default_reminder_lead_secs(),
+            max_per_charge: default_reminder_max_per_charge(),
+            max_per_user_daily: default_reminder_max_per_user_daily(),
+            page_size: default_policy_page_size(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Recovery {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_recovery_interval_secs")]
+    pub interval_secs: u64,
+    #[serde(default = "default_policy_page_size")]
+    pub page_size: u16,
+    #[serde(default = "default_expired_spend")]
+    pub expired_spend: String,
+}
+
+fn default_recovery_interval_secs() -> u64 {
+    60
+}
+
+fn default_expired_spend() -> String {
+    "cancel".to_string()
+}
+
+impl Default for Recovery {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: default_recovery_interval_secs(),
+            page_size: default_policy_page_size(),
+            expired_spend: default_expired_spend(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Summary {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_summary_interval_secs")]
+    pub interval_secs: u64,
+    #[serde(default = "default_summary_top_payers")]
+    pub top_payers: usize,
+    #[serde(default = "default_policy_page_size")]
+    pub page_size: u16,
+}
+
+fn default_summary_interval_secs() -> u64 {
+    3600
+}
+
+fn default_summary_top_payers() -> usize {
+    5
+}
+
+impl Default for Summary {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: default_summary_interval_secs(),
+            top_payers: default_summary_top_payers(),
+            page_size: default_policy_page_size(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Alert {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_alert_interval_secs")]
+    pub interval_secs: u64,
+    #[serde(default)]
+    pub min_system_award: i64,
+    #[serde(default)]
+    pub min_system_topup: i64,
+}
+
+fn default_alert_interval_secs() -> u64 {
+    300
+}
+
+impl Default for Alert {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: default_alert_interval_secs(),
+            min_system_award: 0,
+            min_system_topup: 0,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Redpacket {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_redpacket_interval_secs")]
+    pub interval_secs: u64,
+    #[serde(default = "default_policy_page_size")]
+    pub page_size: u16,
+}
+
+fn default_redpacket_interval_secs() -> u64 {
+    300
+}
+
+impl Default for Redpacket {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: default_redpacket_interval_secs(),
+            page_size: default_policy_page_size(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Subscription {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_subscription_interval_secs")]
+    pub interval_secs: u64,
+    // a renewal failed for insufficient balance is retried in the grace window.
+    #[serde(default = "default_subscription_grace_secs")]
+    pub grace_secs: i64,
+    #[serde(default = "default_subscription_retry_secs")]
+    pub retry_secs: i64,
+    #[serde(default = "default_policy_page_size")]
+    pub page_size: u16,
+}
+
+fn default_subscription_interval_secs() -> u64 {
+    300
+}
+
+fn default_subscription_grace_secs() -> i64 {
+    3 * 24 * 3600
+}
+
+fn default_subscription_retry_secs() -> i64 {
+    24 * 3600
+}
+
+impl Default for Subscription {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: default_subscription_interval_secs(),
+            grace_secs: default_subscription_grace_secs(),
+            retry_secs: default_subscription_retry_secs(),
+            page_size: default_policy_page_size(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Moderation {
+    // empty disables moderation.
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub token: String,
+    #[serde(default = "default_moderation_timeout_secs")]
+    pub timeout_secs: u64,
+    // keeps the text if the moderation service fails, otherwise it is blocked.
+    #[serde(default = "default_moderation_fail_open")]
+    pub fail_open: bool,
+}
+
+fn default_moderation_timeout_secs() -> u64 {
+    3
+}
+
+fn default_moderation_fail_open() -> bool {
+    true
+}
+
+impl Default for Moderation {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            token: String::new(),
+            timeout_secs: default_moderation_timeout_secs(),
+            fail_open: default_moderation_fail_open(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Archive {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_archive_interval_secs")]
+    pub interval_secs: u64,
+    #[serde(default = "default_archive_idle_months")]
+    pub idle_months: u32,
+    #[serde(default = "default_policy_page_size")]
+    pub page_size: u16,
+}
+
+fn default_archive_interval_secs() -> u64 {
+    86400
+}
+
+fn default_archive_idle_months() -> u32 {
+    6
+}
+
+impl Default for Archive {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: default_archive_interval_secs(),
+            idle_months: default_archive_idle_months(),
+            page_size: default_policy_page_size(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Liability {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_liability_interval_secs")]
+    pub interval_secs: u64,
+    #[serde(default = "default_policy_page_size")]
+    pub page_size: u16,
+}
+
+fn default_liability_interval_secs() -> u64 {
+    86400
+}
+
+impl Default for Liability {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: default_liability_interval_secs(),
+            page_size: default_policy_page_size(),
+        }
+    }
+}
+
+// delivery of wallet webhooks, events are queued on commit regardless of `enabled`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Webhook {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_webhook_interval_secs")]
+    pub interval_secs: u64,
+    #[serde(default = "default_policy_page_size")]
+    pub page_size: u16,
+    #[serde(default = "default_webhook_timeout_secs")]
+    pub timeout_secs: u64,
+    // an event is dropped after max_attempts failed deliveries.
+    #[serde(default = "default_webhook_max_attempts")]
+    pub max_attempts: u32,
+    // delay before the first retry, doubled per attempt up to max_backoff_secs.
+    #[serde(default = "default_webhook_backoff_secs")]
+    pub backoff_secs: u64,
+    #[serde(default = "default_webhook_max_backoff_secs")]
+    pub max_backoff_secs: u64,
+    // a webhook is disabled after disable_after consecutive failed deliveries.
+    #[serde(default = "default_webhook_disable_after")]
+    pub disable_after: u32,
+}
+
+fn default_webhook_interval_secs() -> u64 {
+    10
+}
+
+fn default_webhook_timeout_secs() -> u64 {
+    5
+}
+
+fn default_webhook_max_attempts() -> u32 {
+    8
+}
+
+fn default_webhook_backoff_secs() -> u64 {
+    30
+}
+
+fn default_webhook_max_backoff_secs() -> u64 {
+    3600
+}
+
+fn default_webhook_disable_after() -> u32 {
+    20
+}
+
+impl Default for Webhook {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: default_webhook_interval_secs(),
+            page_size: default_policy_page_size(),
+            timeout_secs: default_webhook_timeout_secs(),
+            max_attempts: default_webhook_max_attempts(),
+            backoff_secs: default_webhook_backoff_secs(),
+            max_backoff_secs: default_webhook_max_backoff_secs(),
+            disable_after: default_webhook_disable_after(),
+        }
+    }
+}
+
+// audit trail of mutating requests persisted in the audit_log table.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct AuditLog {
+    // days the audit logs are kept, 0 disables the audit log.
+    #[serde(default = "default_audit_log_ttl_days")]
+    pub ttl_days: i64,
+}
+
+fn default_audit_log_ttl_days() -> i64 {
+    90
+}
+
+impl Default for AuditLog {
+    fn default() -> Self {
+        Self {
+            ttl_days: default_audit_log_ttl_days(),
+        }
+    }
+}
+
+// what a process runs, the role in the config can be overridden by `--role` of the binary.
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    All, // the HTTP server and the enabled jobs
+    Serve,  // the HTTP server only
+    Worker, // the enabled jobs only
+}
+
+impl Role {
+    pub fn serves(self) -> bool {
+        self != Role::Worker
+    }
+
+    pub fn runs_jobs(self) -> bool {
+        self != Role::Serve
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(Role::All),
+            "serve" => Ok(Role::Serve),
+            "worker" => Ok(Role::Worker),
+            _ => Err(format!(
+                "invalid role {:?}, expected \"serve\", \"worker\" or \"all\"",
+                s
+            )),
+        }
+    }
+}
+
+// coordination of the scheduled jobs among the processes that run them.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct Worker {
+    // whether a job holds a lease in the job_lease table before a run, so that it runs in
+    // one process at a time. false runs the jobs in every process.
+    #[serde(default = "default_worker_lease")]
+    pub lease: bool,
+}
+
+fn default_worker_lease() -> bool {
+    true
+}
+
+impl Default for Worker {
+    fn default() -> Self {
+        Self {
+            lease: default_worker_lease(),
+        }
+    }
+}
+
+// rejects new prepares and commits with 503 while LWT retries or query errors are high.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct LoadShedding {
+    // LWT retries per 100 LWT wallet updates in a window, 0 disables the retry rate.
+    #[serde(default = "default_load_shedding_retry_rate")]
+    pub retry_rate: u32,
+    // percent of failed queries in a window, 0 disables the error rate.
+    #[serde(default = "default_load_shedding_error_rate")]
+    pub error_rate: u32,
+    #[serde(default = "default_load_shedding_min_requests")]
+    pub min_requests: u32,
+    #[serde(default = "default_load_shedding_window_secs")]
+    pub window_secs: u64,
+}
+
+fn default_load_shedding_retry_rate() -> u32 {
+    100
+}
+
+fn default_load_shedding_error_rate() -> u32 {
+    20
+}
+
+fn default_load_shedding_min_requests() -> u32 {
+    50
+}
+
+fn default_load_shedding_window_secs() -> u64 {
+    10
+}
+
+impl Default for LoadShedding {
+    fn default() -> Self {
+        Self {
+            retry_rate: default_load_shedding_retry_rate(),
+            error_rate: default_load_shedding_error_rate(),
+            min_requests: default_load_shedding_min_requests(),
+            window_secs: default_load_shedding_window_secs(),
+        }
+    }
+}
+
+// secondary tables mirrored from their primary tables during a table layout migration.
+#[derive(Debug, Default, Deserialize, Clone, PartialEq)]
+pub struct Shadow {
+    // secondary table per primary table.
+    #[serde(default)]
+    pub tables: HashMap<String, String>,
+    // reads of the primary tables compared with the secondary tables, in basis points.
+    #[serde(default)]
+    pub sample_bps: u32,
+}
+
+// the coin of wallet amounts, transactions amounts are integers in its smallest unit.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct Denomination {
+    #[serde(default = "default_denomination_name")]
+    pub name: String,
+    #[serde(default)]
+    pub symbol: String,
+    // name of the smallest unit that amounts count.
+    #[serde(default = "default_denomination_unit")]
+    pub unit: String,
+    // amounts are displayed as amount / 10^exponent coins, 0 if the unit is the coin.
+    #[serde(default)]
+    pub exponent: u8,
+}
+
+fn default_denomination_name() -> String {
+    "Yiwen Coin".to_string()
+}
+
+fn default_denomination_unit() -> String {
+    "coin".to_string()
+}
+
+impl Default for Denomination {
+    fn default() -> Self {
+        Self {
+            name: default_denomination_name(),
+            symbol: String::new(),
+            unit: default_denomination_unit(),
+            exponent: 0,
+        }
+    }
+}
+
+// seller and tax fields printed on receipts of completed charges.
+#[derive(Debug, Default, Deserialize, Clone, PartialEq)]
+pub struct Receipt {
+    #[serde(default)]
+    pub seller: String,
+    #[serde(default)]
+    pub tax_id: String,
+    #[serde(default)]
+    pub tax_name: String,
+    // tax included in the charged amount, in basis points, 0 for no tax.
+    #[serde(default)]
+    pub tax_rate_bps: u16,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Stripe {
+    // empty disables creating Checkout sessions by the wallet.
+    #[serde(default)]
+    pub secret_key: String,
+    #[serde(default = "default_stripe_api_base")]
+    pub api_base: String,
+    #[serde(default)]
+    pub success_url: String,
+    #[serde(default)]
+    pub cancel_url: String,
+    #[serde(default = "default_stripe_timeout_secs")]
+    pub timeout_secs: u64,
+    // topup quantity per unit of the prices.
+    #[serde(default = "default_stripe_unit_quantity")]
+    pub unit_quantity: i64,
+    // currency -> price id.
+    #[serde(default)]
+    pub prices: HashMap<String, String>,
+}
+
+fn default_stripe_api_base() -> String {
+    "https://api.stripe.com".to_string()
+}
+
+fn default_stripe_timeout_secs() -> u64 {
+    10
+}
+
+fn default_stripe_unit_quantity() -> i64 {
+    1
+}
+
+impl Default for Stripe {
+    fn default() -> Self {
+        Self {
+            secret_key: "".to_string(),
+            api_base: default_stripe_api_base(),
+            success_url: "".to_string(),
+            cancel_url: "".to_string(),
+            timeout_secs: default_stripe_timeout_secs(),
+            unit_quantity: default_stripe_unit_quantity(),
+            prices: HashMap::new(),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct Conf {
     pub env: String,
+    #[serde(default)]
+    pub role: Role,
     pub log: Log,
     pub server: Server,
     pub scylla: ScyllaDB,
     pub keys: Keys,
+    #[serde(default)]
+    pub worker: Worker,
+    #[serde(default)]
+    pub wallet: Wallet,
+    #[serde(default)]
+    pub policy: Policy,
+    #[serde(default)]
+    pub reminder: Reminder,
+    #[serde(default)]
+    pub summary: Summary,
+    #[serde(default)]
+    pub recovery: Recovery,
+    #[serde(default)]
+    pub alert: Alert,
+    #[serde(default)]
+    pub redpacket: Redpacket,
+    #[serde(default)]
+    pub subscription: Subscription,
+    #[serde(default)]
+    pub archive: Archive,
+    #[serde(default)]
+    pub liability: Liability,
+    #[serde(default)]
+    pub webhook: Webhook,
+    #[serde(default)]
+    pub moderation: Moderation,
+    #[serde(default)]
+    pub stripe: Stripe,
+    #[serde(default)]
+    pub receipt: Receipt,
+    #[serde(default)]
+    pub denomination: Denomination,
+    #[serde(default)]
+    pub shadow: Shadow,
+    #[serde(default)]
+    pub load_shedding: LoadShedding,
+    #[serde(default)]
+    pub audit_log: AuditLog,
+    // allowed tenants besides the default one, each has its own keyspace.
+    #[serde(default)]
+    pub tenants: Vec<String>,
 }
 
 impl Conf {
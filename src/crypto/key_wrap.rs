@@ -0,0 +1,108 @@
+use aes_kw::KekAes256;
+use coset::{
+    iana, CoseEncrypt0, CoseEncrypt0Builder, CoseKeyBuilder, HeaderBuilder, TaggedCborSerializable,
+};
+
+use super::Key;
+
+// AES key wrap (RFC 3394) of 256-bit keys, COSE alg A256KW (RFC 9053 section 6.2.1).
+// The wrapped key material is carried by a COSE_Encrypt0 with the alg in the protected
+// header, so that key files are read by their alg, see `super::decrypt_key`.
+// Only the key material is wrapped, the wrapped key's kid and alg are not kept.
+// AES-KW has no AAD, the integrity is checked by the unwrap.
+pub struct KeyWrap {
+    kid: Vec<u8>,
+    kek: KekAes256,
+}
+
+impl KeyWrap {
+    pub fn new(key: [u8; 32], kid: &[u8]) -> Self {
+        Self {
+            kid: kid.to_vec(),
+            kek: KekAes256::from(key),
+        }
+    }
+
+    pub fn wrap(&self, key: &Key) -> anyhow::Result<Vec<u8>> {
+        let wrapped = self
+            .kek
+            .wrap_vec(&key.get_private()?)
+            .map_err(|err| anyhow::anyhow!("key wrap failed: {:?}", err))?;
+
+        let protected = HeaderBuilder::new()
+            .algorithm(iana::Algorithm::A256KW)
+            .build();
+        let unprotected = HeaderBuilder::new().key_id(self.kid.clone()).build();
+        let e0 = CoseEncrypt0Builder::new()
+            .protected(protected)
+            .unprotected(unprotected)
+            .ciphertext(wrapped)
+            .build();
+        e0.to_tagged_vec().map_err(anyhow::Error::msg)
+    }
+
+    pub fn unwrap(&self, encrypt0_data: &[u8]) -> anyhow::Result<Key> {
+        let e0 = CoseEncrypt0::from_tagged_slice(encrypt0_data).map_err(anyhow::Error::msg)?;
+        if super::alg_of(&e0) != Some(iana::Algorithm::A256KW) {
+            return Err(anyhow::Error::msg("invalid alg, expected A256KW"));
+        }
+        let wrapped = e0
+            .ciphertext
+            .ok_or_else(|| anyhow::Error::msg("missing wrapped key"))?;
+        let key = self
+            .kek
+            .unwrap_vec(&wrapped)
+            .map_err(|err| anyhow::anyhow!("key unwrap failed: {:?}", err))?;
+        if key.len() != 32 {
+            return Err(anyhow::Error::msg("Invalid key length, expected 32"));
+        }
+        Ok(Key(CoseKeyBuilder::new_symmetric_key(key).build()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand_core::{OsRng, RngCore};
+
+    #[test]
+    fn key_wrap_works() {
+        let mut kek = [0u8; 32];
+        OsRng.fill_bytes(&mut kek);
+        let key_wrap = KeyWrap::new(kek, b"kek");
+
+        let key = Key::new_sym(iana::Algorithm::Direct, b"42").unwrap();
+        let data = key_wrap.wrap(&key).unwrap();
+        let res = key_wrap.unwrap(&data).unwrap();
+        assert_eq!(key.get_private().unwrap(), res.get_private().unwrap());
+        assert!(key_wrap.unwrap(&data[1..]).is_err());
+
+        OsRng.fill_bytes(&mut kek);
+        assert!(KeyWrap::new(kek, b"kek").unwrap(&data).is_err());
+    }
+
+    // https://www.rfc-editor.org/rfc/rfc3394#section-4.6
+    #[test]
+    fn key_wrap_rfc3394_vector_works() {
+        let mut kek = [0u8; 32];
+        for (i, b) in kek.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        // 00112233445566778899AABBCCDDEEFF000102030405060708090A0B0C0D0E0F
+        let mut key = [0u8; 32];
+        for (i, b) in key.iter_mut().enumerate() {
+            *b = if i < 16 { i as u8 * 0x11 } else { i as u8 - 16 };
+        }
+        let key = Key(CoseKeyBuilder::new_symmetric_key(key.to_vec()).build());
+        let data = KeyWrap::new(kek, b"").wrap(&key).unwrap();
+        let e0 = CoseEncrypt0::from_tagged_slice(&data).unwrap();
+        assert_eq!(
+            vec![
+                0x28, 0xc9, 0xf4, 0x04, 0xc4, 0xb8, 0x10, 0xf4, 0xcb, 0xcc, 0xb3, 0x5c, 0xfb, 0x87,
+                0xf8, 0x26, 0x3f, 0x57, 0x86, 0xe2, 0xd8, 0x0e, 0xd3, 0x26, 0xcb, 0xc7, 0xf0, 0xe7,
+                0x1a, 0x99, 0xf4, 0x3b, 0xfb, 0x98, 0x8b, 0x9b, 0x7a, 0x02, 0xdd, 0x21,
+            ],
+            e0.ciphertext.unwrap()
+        );
+    }
+}
@@ -1,11 +1,14 @@
 // use hex_literal::hex;
 use base64ct::{Base64UrlUnpadded, Encoding};
+use coset::{CoseEncrypt0, RegisteredLabelWithPrivate, TaggedCborSerializable};
 mod cose_key;
 mod encrypt;
+mod key_wrap;
 
 pub use cose_key::Key;
 pub use coset::iana;
 pub use encrypt::Encrypt0;
+pub use key_wrap::KeyWrap;
 
 // https://www.rfc-editor.org/rfc/rfc8949.html#name-self-described-cbor
 pub const CBOR_TAG: [u8; 3] = [0xd9, 0xd9, 0xf7];
@@ -32,13 +35,53 @@ pub fn unwrap_cbor_tag(data: &[u8]) -> &[u8] {
     data
 }
 
+fn alg_of(e0: &CoseEncrypt0) -> Option<iana::Algorithm> {
+    match e0.protected.header.alg {
+        Some(RegisteredLabelWithPrivate::Assigned(alg)) => Some(alg),
+        _ => None,
+    }
+}
+
+// decrypts a key file by the alg of its COSE_Encrypt0: A256GCM (Encrypt0) or A256KW (KeyWrap).
+pub fn decrypt_key(key: [u8; 32], encrypt0_data: &[u8], aad: &[u8]) -> anyhow::Result<Key> {
+    let e0 = CoseEncrypt0::from_tagged_slice(encrypt0_data).map_err(anyhow::Error::msg)?;
+    match alg_of(&e0) {
+        Some(iana::Algorithm::A256GCM) => {
+            let data = Encrypt0::new(key, b"").decrypt(encrypt0_data, aad)?;
+            Key::from_slice(&data)
+        }
+        Some(iana::Algorithm::A256KW) => KeyWrap::new(key, b"").unwrap(encrypt0_data),
+        alg => Err(anyhow::anyhow!("Unsupported key encryption alg {:?}", alg)),
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use rand_core::{OsRng, RngCore};
     use std::path::Path;
     use std::{env, fs};
 
     use super::*;
 
+    #[test]
+    fn decrypt_key_works() {
+        let aad = b"yiwen.ai".as_slice();
+        let mut kek = [0u8; 32];
+        OsRng.fill_bytes(&mut kek);
+        let key = Key::new_sym(iana::Algorithm::Direct, b"42").unwrap();
+
+        let data = Encrypt0::new(kek, b"kek")
+            .encrypt(&key.clone().to_vec().unwrap(), aad)
+            .unwrap();
+        assert_eq!(key, decrypt_key(kek, &data, aad).unwrap());
+
+        let data = KeyWrap::new(kek, b"kek").wrap(&key).unwrap();
+        let res = decrypt_key(kek, &data, aad).unwrap();
+        assert_eq!(key.get_private().unwrap(), res.get_private().unwrap());
+
+        assert!(decrypt_key(kek, &data[1..], aad).is_err());
+    }
+
     /// Check that the generated files are up to date.
     #[test]
     #[ignore]
@@ -0,0 +1,274 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use axum_web::erring::HTTPError;
+
+// the circuit breaker fails queries fast while ScyllaDB is unavailable:
+// it opens when the error rate of a window exceeds the threshold, rejects queries
+// with 503 for the open duration, then lets a few probes through (half-open) and
+// closes again once they all succeed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BreakerConf {
+    pub error_rate: u32, // percent of failed queries in a window to open, 0 disables it
+    pub min_requests: u32, // queries in a window before the error rate applies
+    pub window: Duration,
+    pub open: Duration,
+    pub half_open_probes: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Closed {
+        since: Instant,
+        requests: u32,
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    HalfOpen {
+        probes: u32,
+        successes: u32,
+    },
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BreakerStatus {
+    pub state: &'static str, // "closed", "open" or "half_open"
+    pub retry_after_secs: u64,
+    pub opened: u64,   // times the breaker opened
+    pub rejected: u64, // queries rejected while open
+}
+
+pub struct Breaker {
+    conf: BreakerConf,
+    state: Mutex<State>,
+    opened: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl Breaker {
+    pub fn new(conf: BreakerConf) -> Self {
+        Self {
+            conf,
+            state: Mutex::new(State::Closed {
+                since: Instant::now(),
+                requests: 0,
+                failures: 0,
+            }),
+            opened: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    // returns 503 if the query should not be sent.
+    pub fn allow(&self) -> Result<(), HTTPError> {
+        if self.conf.error_rate == 0 {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        if let State::Open { until } = *state {
+            if now < until {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                return Err(unavailable(until - now));
+            }
+            *state = State::HalfOpen {
+                probes: 0,
+                successes: 0,
+            };
+        }
+
+        if let State::HalfOpen { probes, successes } = *state {
+            if probes >= self.conf.half_open_probes.max(1) {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                return Err(unavailable(self.conf.open));
+            }
+            *state = State::HalfOpen {
+                probes: probes + 1,
+                successes,
+            };
+        }
+        Ok(())
+    }
+
+    // records the outcome of an allowed query, ok is false only if ScyllaDB was unavailable.
+    pub fn record(&self, ok: bool) {
+        if self.conf.error_rate == 0 {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            State::Closed {
+                since,
+                requests,
+                failures,
+            } => {
+                let (since, requests, failures) = if now.duration_since(since) >= self.conf.window {
+                    (now, 1, !ok as u32)
+                } else {
+                    (since, requests + 1, failures + !ok as u32)
+                };
+                if requests >= self.conf.min_requests.max(1)
+                    && failures as u64 * 100 >= self.conf.error_rate as u64 * requests as u64
+                {
+                    self.trip(&mut state, now);
+                } else {
+                    *state = State::Closed {
+                        since,
+                        requests,
+                        failures,
+                    };
+                }
+            }
+            State::HalfOpen { probes, successes } => {
+                if !ok {
+                    self.trip(&mut state, now);
+                } else if successes + 1 >= self.conf.half_open_probes.max(1) {
+                    *state = State::Closed {
+                        since: now,
+                        requests: 0,
+                        failures: 0,
+                    };
+                    log::info!(target: "scylladb", "circuit breaker closed");
+                } else {
+                    *state = State::HalfOpen {
+                        probes,
+                        successes: successes + 1,
+                    };
+                }
+            }
+            // queries sent before the breaker opened.
+            State::Open { .. } => {}
+        }
+    }
+
+    pub fn status(&self) -> BreakerStatus {
+        let now = Instant::now();
+        let (state, retry_after_secs) = match *self.state.lock().unwrap() {
+            State::Closed { .. } => ("closed", 0),
+            State::Open { until } if now < until => ("open", retry_after_secs(until - now)),
+            State::Open { .. } | State::HalfOpen { .. } => ("half_open", 0),
+        };
+        BreakerStatus {
+            state,
+            retry_after_secs,
+            opened: self.opened.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+
+    fn trip(&self, state: &mut State, now: Instant) {
+        *state = State::Open {
+            until: now + self.conf.open,
+        };
+        self.opened.fetch_add(1, Ordering::Relaxed);
+        log::warn!(target: "scylladb",
+            open_secs = self.conf.open.as_secs();
+            "circuit breaker opened",
+        );
+    }
+}
+
+fn retry_after_secs(d: Duration) -> u64 {
+    (d.as_millis() as u64 + 999) / 1000
+}
+
+fn unavailable(retry_after: Duration) -> HTTPError {
+    HTTPError::new(
+        503,
+        format!(
+            "ScyllaDB unavailable, retry after {}s",
+            retry_after_secs(retry_after).max(1)
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker(open: Duration) -> Breaker {
+        Breaker::new(BreakerConf {
+            error_rate: 50,
+            min_requests: 4,
+            window: Duration::from_secs(60),
+            open,
+            half_open_probes: 2,
+        })
+    }
+
+    #[test]
+    fn breaker_opens_on_error_rate() {
+        let b = breaker(Duration::from_secs(60));
+        for ok in [true, false, false] {
+            assert!(b.allow().is_ok());
+            b.record(ok);
+        }
+        assert_eq!("closed", b.status().state);
+
+        assert!(b.allow().is_ok());
+        b.record(true);
+        let status = b.status();
+        assert_eq!("open", status.state);
+        assert_eq!(60, status.retry_after_secs);
+        assert_eq!(1, status.opened);
+
+        let err = b.allow().unwrap_err();
+        assert_eq!(503, err.code);
+        assert_eq!(1, b.status().rejected);
+    }
+
+    #[test]
+    fn breaker_half_open_probes() {
+        let b = breaker(Duration::ZERO);
+        for _ in 0..4 {
+            assert!(b.allow().is_ok());
+            b.record(false);
+        }
+        assert_eq!(1, b.status().opened);
+        assert_eq!("half_open", b.status().state);
+
+        // only the probes are allowed.
+        assert!(b.allow().is_ok());
+        assert!(b.allow().is_ok());
+        assert!(b.allow().is_err());
+        b.record(true);
+        assert_eq!("half_open", b.status().state);
+        b.record(true);
+        assert_eq!("closed", b.status().state);
+
+        // a failed probe opens it again.
+        for _ in 0..4 {
+            assert!(b.allow().is_ok());
+            b.record(false);
+        }
+        assert!(b.allow().is_ok());
+        b.record(false);
+        assert_eq!(3, b.status().opened);
+    }
+
+    #[test]
+    fn breaker_disabled() {
+        let b = Breaker::new(BreakerConf {
+            error_rate: 0,
+            min_requests: 1,
+            window: Duration::from_secs(60),
+            open: Duration::from_secs(60),
+            half_open_probes: 1,
+        });
+        for _ in 0..10 {
+            assert!(b.allow().is_ok());
+            b.record(false);
+        }
+        assert_eq!("closed", b.status().state);
+    }
+}
@@ -0,0 +1,105 @@
+use std::{sync::Mutex, time::Duration};
+
+// Fault injection for testing the multi-wallet commit path under partial failure.
+// It is only compiled with the "fault-injection" feature, never enable it in production.
+// Rules match queries by a substring of the CQL, e.g. "UPDATE wallet SET sequence".
+#[derive(Default)]
+pub struct Faults {
+    rules: Mutex<Vec<Rule>>,
+}
+
+struct Rule {
+    pattern: String,
+    nth: usize, // fails the nth matched query, 1-based, 0 never fails
+    latency: Duration,
+    seen: usize,
+}
+
+impl Faults {
+    // fails the nth query matching the pattern, counting from 1.
+    pub fn fail_nth(&self, pattern: &str, nth: usize) {
+        self.add(Rule {
+            pattern: pattern.to_string(),
+            nth,
+            latency: Duration::ZERO,
+            seen: 0,
+        });
+    }
+
+    // delays every query matching the pattern.
+    pub fn delay(&self, pattern: &str, latency: Duration) {
+        self.add(Rule {
+            pattern: pattern.to_string(),
+            nth: 0,
+            latency,
+            seen: 0,
+        });
+    }
+
+    pub fn clear(&self) {
+        self.rules.lock().unwrap().clear();
+    }
+
+    fn add(&self, rule: Rule) {
+        self.rules.lock().unwrap().push(rule);
+    }
+
+    // applies the matched rules to the query, it is called before the query is sent.
+    pub async fn inject(&self, query: &str) -> anyhow::Result<()> {
+        let mut latency = Duration::ZERO;
+        let mut fail = false;
+        {
+            let mut rules = self.rules.lock().unwrap();
+            for rule in rules.iter_mut().filter(|r| query.contains(&r.pattern)) {
+                rule.seen += 1;
+                latency += rule.latency;
+                if rule.seen == rule.nth {
+                    fail = true;
+                }
+            }
+        }
+
+        if !latency.is_zero() {
+            tokio::time::sleep(latency).await;
+        }
+        if fail {
+            return Err(anyhow::Error::msg(format!("injected fault: {}", query)));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn fail_nth_works() {
+        let faults = Faults::default();
+        faults.fail_nth("UPDATE wallet", 2);
+
+        assert!(faults.inject("SELECT * FROM wallet").await.is_ok());
+        assert!(faults.inject("UPDATE wallet SET credits=?").await.is_ok());
+        assert!(faults.inject("UPDATE wallet SET credits=?").await.is_err());
+        assert!(faults.inject("UPDATE wallet SET credits=?").await.is_ok());
+
+        faults.clear();
+        faults.fail_nth("UPDATE wallet", 1);
+        assert!(faults.inject("UPDATE wallet SET credits=?").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn delay_works() {
+        let faults = Faults::default();
+        faults.delay("UPDATE transaction", Duration::from_millis(50));
+
+        let start = std::time::Instant::now();
+        assert!(faults.inject("SELECT * FROM transaction").await.is_ok());
+        assert!(start.elapsed() < Duration::from_millis(50));
+        assert!(faults
+            .inject("UPDATE transaction SET status=?")
+            .await
+            .is_ok());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,253 @@
+use super::{income_credits, payout_credit, KindRules, Party};
+use crate::db::{apply_bps, income_fee_rate, Credit, Transaction, Wallet, SYS_FEE_RATE};
+
+// the system awards users.
+pub struct Award;
+
+impl KindRules for Award {
+    fn payer(&self) -> Party {
+        Party::System
+    }
+
+    fn debit_system(&self, wallet: &mut Wallet, amount: i64) -> bool {
+        wallet.award -= amount;
+        true
+    }
+
+    fn debit_payer(&self, _wallet: &mut Wallet, _amount: i64) -> bool {
+        false
+    }
+
+    fn rollback_payer(&self, wallet: &mut Wallet, amount: i64) {
+        wallet.award += amount;
+    }
+
+    fn credit_payee(&self, wallet: &mut Wallet, amount: i64) {
+        wallet.award += amount;
+    }
+}
+
+// users top up by charges.
+pub struct Topup;
+
+impl KindRules for Topup {
+    fn payer(&self) -> Party {
+        Party::System
+    }
+
+    fn debit_system(&self, wallet: &mut Wallet, amount: i64) -> bool {
+        wallet.topup -= amount;
+        true
+    }
+
+    fn debit_payer(&self, _wallet: &mut Wallet, _amount: i64) -> bool {
+        false
+    }
+
+    fn rollback_payer(&self, wallet: &mut Wallet, amount: i64) {
+        wallet.topup += amount;
+    }
+
+    fn credit_payee(&self, wallet: &mut Wallet, amount: i64) {
+        wallet.topup += amount;
+    }
+}
+
+// refunds of charges claw back the topup.
+pub struct Refund;
+
+impl KindRules for Refund {
+    fn payee(&self) -> Party {
+        Party::System
+    }
+
+    fn payer_quota(&self, wallet: &Wallet) -> i64 {
+        wallet.topup
+    }
+
+    fn debit_payer(&self, wallet: &mut Wallet, amount: i64) -> bool {
+        wallet.topup -= amount;
+        true
+    }
+
+    fn credit_payee(&self, wallet: &mut Wallet, amount: i64) {
+        wallet.topup += amount;
+    }
+}
+
+pub struct Withdraw;
+
+impl KindRules for Withdraw {
+    fn default_max_amount(&self) -> i64 {
+        100_000_000
+    }
+
+    fn payee(&self) -> Party {
+        Party::System
+    }
+
+    fn payer_quota(&self, wallet: &Wallet) -> i64 {
+        wallet.income
+    }
+
+    fn debit_payer(&self, wallet: &mut Wallet, amount: i64) -> bool {
+        wallet.income -= amount;
+        true
+    }
+
+    fn rollback_payer(&self, wallet: &mut Wallet, amount: i64) {
+        wallet.income += amount;
+    }
+
+    fn credit_payee(&self, wallet: &mut Wallet, amount: i64) {
+        wallet.topup += amount;
+    }
+
+    fn fee_and_shares(&self, amount: i64, _credits: i64, _shares: &[u16]) -> (i64, i64) {
+        (apply_bps(amount, SYS_FEE_RATE).max(1), 0)
+    }
+}
+
+// users spend on the system's products, it may overdraw.
+pub struct Spend;
+
+impl KindRules for Spend {
+    fn disputable(&self) -> bool {
+        true
+    }
+
+    fn payee(&self) -> Party {
+        Party::System
+    }
+
+    fn requires_credits(&self) -> bool {
+        false
+    }
+
+    fn payer_quota(&self, wallet: &Wallet) -> i64 {
+        wallet.balance() + wallet.overdraw_limit()
+    }
+
+    fn credits(&self, txn: &Transaction) -> Vec<Credit> {
+        vec![payout_credit(txn)]
+    }
+}
+
+fn income_fee_and_shares(amount: i64, credits: i64, shares: &[u16]) -> (i64, i64) {
+    let sys_fee = apply_bps(amount, income_fee_rate(credits)).max(1);
+    let sub_shares = shares.iter().map(|bps| apply_bps(amount, *bps)).sum();
+    (sys_fee, sub_shares)
+}
+
+fn payout_and_income_credits(txn: &Transaction) -> Vec<Credit> {
+    let mut credits = vec![payout_credit(txn)];
+    credits.extend(income_credits(txn));
+    credits
+}
+
+pub struct Sponsor;
+
+impl KindRules for Sponsor {
+    fn disputable(&self) -> bool {
+        true
+    }
+
+    fn allows_sub_payees(&self) -> bool {
+        true
+    }
+
+    fn allows_anonymous(&self) -> bool {
+        true
+    }
+
+    fn fee_and_shares(&self, amount: i64, credits: i64, shares: &[u16]) -> (i64, i64) {
+        income_fee_and_shares(amount, credits, shares)
+    }
+
+    fn credits(&self, txn: &Transaction) -> Vec<Credit> {
+        payout_and_income_credits(txn)
+    }
+}
+
+pub struct Subscribe;
+
+impl KindRules for Subscribe {
+    fn disputable(&self) -> bool {
+        true
+    }
+
+    fn allows_sub_payees(&self) -> bool {
+        true
+    }
+
+    fn requires_credits(&self) -> bool {
+        false
+    }
+
+    fn fee_and_shares(&self, amount: i64, credits: i64, shares: &[u16]) -> (i64, i64) {
+        income_fee_and_shares(amount, credits, shares)
+    }
+
+    fn credits(&self, txn: &Transaction) -> Vec<Credit> {
+        payout_and_income_credits(txn)
+    }
+}
+
+// admin only, requires a second approver.
+pub struct Adjustment;
+
+impl KindRules for Adjustment {
+    fn payer(&self) -> Party {
+        Party::Any
+    }
+
+    fn payee(&self) -> Party {
+        Party::Any
+    }
+
+    fn requires_system_party(&self) -> bool {
+        true
+    }
+
+    fn requires_credits(&self) -> bool {
+        false
+    }
+
+    fn debit_system(&self, wallet: &mut Wallet, amount: i64) -> bool {
+        wallet.award -= amount;
+        true
+    }
+
+    fn rollback_payer(&self, wallet: &mut Wallet, amount: i64) {
+        if wallet.is_system() {
+            wallet.award += amount;
+        } else {
+            wallet.topup += amount;
+        }
+    }
+
+    fn credit_payee(&self, wallet: &mut Wallet, amount: i64) {
+        wallet.award += amount;
+    }
+}
+
+// sweeps the whole balance to the system when the wallet is closed.
+pub struct Sweep;
+
+impl KindRules for Sweep {
+    fn default_max_amount(&self) -> i64 {
+        i64::MAX
+    }
+
+    fn payee(&self) -> Party {
+        Party::System
+    }
+
+    fn requires_credits(&self) -> bool {
+        false
+    }
+
+    fn requires_open_payer(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,67 @@
+use super::{KindRules, Party};
+use crate::db::Wallet;
+
+// resolves a dispute by refunding, the disputed amount is refunded to the payer from the
+// system's income, and the payee's income frozen by the dispute is clawed back to the system.
+// the fee and the sub payees' shares of the disputed transaction are borne by the system.
+pub struct Chargeback;
+
+impl KindRules for Chargeback {
+    // bounded by the disputed transaction.
+    fn default_max_amount(&self) -> i64 {
+        i64::MAX
+    }
+
+    fn payer(&self) -> Party {
+        Party::Any
+    }
+
+    fn payee(&self) -> Party {
+        Party::Any
+    }
+
+    fn requires_system_party(&self) -> bool {
+        true
+    }
+
+    fn requires_credits(&self) -> bool {
+        false
+    }
+
+    fn requires_open_payer(&self) -> bool {
+        false
+    }
+
+    // the clawback is forced, the frozen income may have been spent before the dispute.
+    fn checks_balance(&self) -> bool {
+        false
+    }
+
+    fn debit_system(&self, wallet: &mut Wallet, amount: i64) -> bool {
+        wallet.income -= amount;
+        true
+    }
+
+    // deducts income first, the overdraw is recorded on topup.
+    fn debit_payer(&self, wallet: &mut Wallet, amount: i64) -> bool {
+        wallet.income -= amount;
+        if wallet.income < 0 {
+            wallet.topup += wallet.income;
+            wallet.income = 0;
+        }
+        true
+    }
+
+    fn rollback_payer(&self, wallet: &mut Wallet, amount: i64) {
+        wallet.income += amount;
+    }
+
+    // the refunded amount can not be withdrawn as income.
+    fn credit_payee(&self, wallet: &mut Wallet, amount: i64) {
+        if wallet.is_system() {
+            wallet.income += amount;
+        } else {
+            wallet.topup += amount;
+        }
+    }
+}
@@ -0,0 +1,177 @@
+// Behavior of transaction kinds: who pays and receives, balance rules, fees and credits.
+//
+// A new kind implements `KindRules` in its own module, and is registered by a variant of
+// TransactionKind and an arm of `rules`. TransactionKind delegates to the rules, so that
+// Transaction and the wallet APIs do not match on kinds.
+mod builtin;
+mod chargeback;
+mod redpacket;
+
+use super::{Credit, CreditKind, Transaction, TransactionKind, Wallet};
+
+// the party of a transaction side.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Party {
+    System,
+    User,
+    Any,
+}
+
+pub trait KindRules: Send + Sync {
+    // max amount of a transaction unless it is configured.
+    fn default_max_amount(&self) -> i64 {
+        1_000_000
+    }
+
+    fn payer(&self) -> Party {
+        Party::User
+    }
+
+    fn payee(&self) -> Party {
+        Party::User
+    }
+
+    // one of the payer and the payee should be the system, for kinds with Party::Any sides.
+    fn requires_system_party(&self) -> bool {
+        false
+    }
+
+    fn allows_sub_payees(&self) -> bool {
+        false
+    }
+
+    fn allows_anonymous(&self) -> bool {
+        false
+    }
+
+    // users without credits can not pay by the kind.
+    fn requires_credits(&self) -> bool {
+        true
+    }
+
+    // closed wallets can not pay by the kind.
+    fn requires_open_payer(&self) -> bool {
+        true
+    }
+
+    // the user payer's balance and holds are checked, the debit may overdraw otherwise.
+    fn checks_balance(&self) -> bool {
+        true
+    }
+
+    // committed transactions of the kind can be disputed by the payer.
+    fn disputable(&self) -> bool {
+        false
+    }
+
+    // deducts the amount from the system wallet as the payer, false if the system can not pay.
+    fn debit_system(&self, _wallet: &mut Wallet, _amount: i64) -> bool {
+        false
+    }
+
+    // the user payer's balance available to the kind, active holds are deducted by the caller.
+    fn payer_quota(&self, wallet: &Wallet) -> i64 {
+        wallet.balance()
+    }
+
+    // deducts the amount from the user payer's wallet, false if users can not pay.
+    fn debit_payer(&self, wallet: &mut Wallet, amount: i64) -> bool {
+        debit_in_order(wallet, amount);
+        true
+    }
+
+    // returns the amount to the payer when the transaction is cancelled.
+    fn rollback_payer(&self, wallet: &mut Wallet, amount: i64) {
+        // can not rollback to award or income balance.
+        wallet.topup += amount;
+    }
+
+    fn credit_payee(&self, wallet: &mut Wallet, amount: i64) {
+        wallet.income += amount;
+    }
+
+    // shares are the basis points of every share recipient, empty if no sub payee.
+    // returns (sys_fee, sub_shares), sub_shares is the total of all recipients.
+    fn fee_and_shares(&self, _amount: i64, _credits: i64, _shares: &[u16]) -> (i64, i64) {
+        (0, 0)
+    }
+
+    // credits of the committed transaction paid by a user.
+    fn credits(&self, _txn: &Transaction) -> Vec<Credit> {
+        Vec::new()
+    }
+}
+
+// the registry of kinds.
+pub fn rules(kind: &TransactionKind) -> &'static dyn KindRules {
+    match kind {
+        TransactionKind::Award => &builtin::Award,
+        TransactionKind::Topup => &builtin::Topup,
+        TransactionKind::Refund => &builtin::Refund,
+        TransactionKind::Withdraw => &builtin::Withdraw,
+        TransactionKind::Spend => &builtin::Spend,
+        TransactionKind::Sponsor => &builtin::Sponsor,
+        TransactionKind::Subscribe => &builtin::Subscribe,
+        TransactionKind::Adjustment => &builtin::Adjustment,
+        TransactionKind::Sweep => &builtin::Sweep,
+        TransactionKind::Redpacket => &redpacket::Redpacket,
+        TransactionKind::Chargeback => &chargeback::Chargeback,
+    }
+}
+
+// deducts award first, then topup and income, the overdraw is recorded on topup.
+pub fn debit_in_order(wallet: &mut Wallet, amount: i64) {
+    wallet.award -= amount;
+    if wallet.award < 0 {
+        wallet.topup -= -wallet.award;
+        wallet.award = 0;
+        if wallet.topup < 0 {
+            wallet.income -= -wallet.topup;
+            wallet.topup = 0;
+
+            if wallet.income < 0 {
+                (wallet.topup, wallet.income) = (wallet.income, 0);
+            }
+        }
+    }
+}
+
+// the payout credit of the payer.
+pub fn payout_credit(txn: &Transaction) -> Credit {
+    Credit {
+        uid: txn.uid,
+        txn: txn.id,
+        kind: CreditKind::Payout.to_string(),
+        amount: txn.amount,
+        description: format!("payer.{}", txn.kind),
+        ..Default::default()
+    }
+}
+
+// the income credits of the payee and the sub payees.
+pub fn income_credits(txn: &Transaction) -> Vec<Credit> {
+    let sub_payees = txn.sub_payees();
+    let mut credits: Vec<Credit> = Vec::with_capacity(1 + sub_payees.len());
+    credits.push(Credit {
+        uid: txn.payee,
+        txn: txn.id,
+        kind: CreditKind::Income.to_string(),
+        amount: txn.amount - txn.sys_fee - txn.sub_shares,
+        description: format!("payee.{}", txn.kind),
+        ..Default::default()
+    });
+
+    for (uid, amount) in sub_payees {
+        if amount > 0 {
+            credits.push(Credit {
+                uid,
+                txn: txn.id,
+                kind: CreditKind::Income.to_string(),
+                amount,
+                description: format!("sub_payee.{}", txn.kind),
+                ..Default::default()
+            });
+        }
+    }
+    credits
+}
@@ -0,0 +1,44 @@
+use super::{payout_credit, KindRules, Party};
+use crate::db::{Credit, Transaction, Wallet};
+
+// lucky money, the sender's amount is escrowed by the system and claimed by recipients in shares.
+// the escrow is a transaction from the sender to the system, every claim (and the refund of
+// the unclaimed remainder) is a transaction from the system to the recipient.
+// the escrowed amount is held in the system's award, so that claimed shares are awards of the
+// recipients, they can be spent but not withdrawn or refunded.
+pub struct Redpacket;
+
+impl KindRules for Redpacket {
+    fn payer(&self) -> Party {
+        Party::Any
+    }
+
+    fn payee(&self) -> Party {
+        Party::Any
+    }
+
+    fn requires_system_party(&self) -> bool {
+        true
+    }
+
+    fn debit_system(&self, wallet: &mut Wallet, amount: i64) -> bool {
+        wallet.award -= amount;
+        true
+    }
+
+    fn rollback_payer(&self, wallet: &mut Wallet, amount: i64) {
+        if wallet.is_system() {
+            wallet.award += amount;
+        } else {
+            wallet.topup += amount;
+        }
+    }
+
+    fn credit_payee(&self, wallet: &mut Wallet, amount: i64) {
+        wallet.award += amount;
+    }
+
+    fn credits(&self, txn: &Transaction) -> Vec<Credit> {
+        vec![payout_credit(txn)]
+    }
+}
@@ -0,0 +1,512 @@
+use anyhow::anyhow;
+use futures::stream::TryStreamExt;
+use scylla::frame::response::result::Row;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::{collections::HashMap, str::FromStr};
+
+use axum_web::{context::unix_ms, erring::HTTPError};
+use scylla_orm::{ColumnsMap, CqlValue, ToCqlVal};
+
+use super::{
+    match_sequence,
+    scylladb::{self, extract_applied},
+    Charge, Credit, Customer, HMacTag, Transaction, TransactionByKind, TransactionRef, Wallet,
+    SYS_ID,
+};
+use crate::crypto::{base64url_decode, base64url_encode};
+
+// the wallet is read again after the other rows, the export retries if it changed meanwhile.
+const EXPORT_ATTEMPTS: usize = 3;
+
+// columns of a row, the values are tagged JSON, see encode_value.
+pub type SnapshotRow = Map<String, Value>;
+
+// a consistent snapshot of a user's wallet and the rows partitioned by the user, to move users
+// across regions. Writes of the user should be stopped during the migration, see export.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+pub struct WalletSnapshot {
+    pub uid: String,
+    pub exported_at: i64, // unix ms
+    pub wallet: SnapshotRow,
+    pub transactions: Vec<SnapshotRow>,
+    pub credits: Vec<SnapshotRow>,
+    pub charges: Vec<SnapshotRow>, // payloads offloaded to blob are inlined
+    pub customers: Vec<SnapshotRow>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ImportStats {
+    pub inserted: u64, // rows inserted
+    pub existing: u64, // rows that existed in the target, from an earlier import
+}
+
+impl ImportStats {
+    fn add(&mut self, inserted: bool) {
+        if inserted {
+            self.inserted += 1;
+        } else {
+            self.existing += 1;
+        }
+    }
+}
+
+impl WalletSnapshot {
+    // exports the user's rows, archived wallets are restored first. It fails with 409 if the
+    // wallet has in-flight transactions or charges, or kept changing during the export.
+    pub async fn export(db: &scylladb::ScyllaDB, uid: xid::Id) -> anyhow::Result<Self> {
+        if uid == SYS_ID {
+            return Err(
+                HTTPError::new(400, "System wallet can not be exported".to_string()).into(),
+            );
+        }
+
+        for _ in 0..EXPORT_ATTEMPTS {
+            let mut wallet = Wallet::with_pk(uid);
+            wallet.get_one(db).await?;
+            if wallet.pending_out != 0 {
+                return Err(in_flight_error(uid, "outgoing transactions"));
+            }
+
+            let transactions = read_rows(db, "transaction", Transaction::fields(), uid).await?;
+            let credits = read_rows(db, "credit", Credit::fields(), uid).await?;
+            let charges = read_rows(db, "charge", Charge::fields(), uid).await?;
+            let customers = read_rows(db, "customer", Customer::fields(), uid).await?;
+
+            let mut current = Wallet::with_pk(uid);
+            current.get_one(db).await?;
+            if (current.sequence, current.txn, current.updated_at)
+                != (wallet.sequence, wallet.txn, wallet.updated_at)
+            {
+                continue;
+            }
+
+            let now = unix_ms() as i64;
+            let mut snapshot = Self {
+                uid: uid.to_string(),
+                exported_at: now,
+                wallet: encode_row(&wallet.to())?,
+                ..Default::default()
+            };
+            for cols in transactions {
+                let mut doc = Transaction::default();
+                doc.fill(&cols);
+                // -1: canceling, 0: preparing, 1: prepared, 2: committing
+                if (-1..=2).contains(&doc.status) {
+                    return Err(in_flight_error(uid, "transactions"));
+                }
+                snapshot.transactions.push(encode_row(&doc.to())?);
+            }
+            for cols in credits {
+                let mut doc = Credit::default();
+                doc.fill(&cols);
+                snapshot.credits.push(encode_row(&doc.to())?);
+            }
+            for cols in charges {
+                let mut doc = Charge::default();
+                doc.fill(&cols);
+                if (0..=2).contains(&doc.status) && !doc.is_expired(now) {
+                    return Err(in_flight_error(uid, "charges"));
+                }
+                doc._fields = Charge::fields();
+                doc.hydrate_payload(db).await?;
+                doc.charge_payload_ref = Vec::new();
+                snapshot.charges.push(encode_row(&doc.to())?);
+            }
+            for cols in customers {
+                let mut doc = Customer::default();
+                doc.fill(&cols);
+                snapshot.customers.push(encode_row(&doc.to())?);
+            }
+            return Ok(snapshot);
+        }
+
+        Err(HTTPError::new(
+            409,
+            format!("Wallet {} kept changing during the export", uid),
+        )
+        .into())
+    }
+
+    // validates the snapshot and inserts its rows into the target cluster with their indexes.
+    // Rows are inserted if not exists, so an interrupted import can run again. The wallet is
+    // inserted last and counts its balances into the liability totals, see Wallet::save.
+    pub async fn import(
+        &self,
+        db: &scylladb::ScyllaDB,
+        mac: &HMacTag,
+    ) -> anyhow::Result<ImportStats> {
+        let uid = xid::Id::from_str(&self.uid)?;
+        if uid == SYS_ID {
+            return Err(
+                HTTPError::new(400, "System wallet can not be imported".to_string()).into(),
+            );
+        }
+
+        let mut wallet = Wallet::default();
+        wallet.fill(&decode_row(&self.wallet)?);
+        check_uid(uid, wallet.uid, "wallet")?;
+        wallet.verify_checksum(mac)?;
+
+        let mut transactions: Vec<Transaction> = Vec::with_capacity(self.transactions.len());
+        for row in &self.transactions {
+            let mut doc = Transaction::default();
+            doc.fill(&decode_row(row)?);
+            check_uid(uid, doc.uid, "transaction")?;
+            transactions.push(doc);
+        }
+        let outgo: Vec<(xid::Id, i64, i8)> = transactions
+            .iter()
+            .map(|t| (t.id, t.sequence, t.status))
+            .collect();
+        match_sequence(wallet.sequence, wallet.txn, &outgo)
+            .map_err(|err| HTTPError::new(400, format!("wallet {} {}", uid, err)))?;
+
+        let mut credits: Vec<Credit> = Vec::with_capacity(self.credits.len());
+        for row in &self.credits {
+            let mut doc = Credit::default();
+            doc.fill(&decode_row(row)?);
+            check_uid(uid, doc.uid, "credit")?;
+            credits.push(doc);
+        }
+        let mut charges: Vec<Charge> = Vec::with_capacity(self.charges.len());
+        for row in &self.charges {
+            let mut doc = Charge::default();
+            doc.fill(&decode_row(row)?);
+            check_uid(uid, doc.uid, "charge")?;
+            charges.push(doc);
+        }
+        let mut customers: Vec<Customer> = Vec::with_capacity(self.customers.len());
+        for row in &self.customers {
+            let mut doc = Customer::default();
+            doc.fill(&decode_row(row)?);
+            check_uid(uid, doc.uid, "customer")?;
+            customers.push(doc);
+        }
+
+        // a wallet in the target is only the same wallet from an earlier import.
+        if let Some((sequence, checksum)) = target_wallet(db, uid).await? {
+            if sequence != wallet.sequence || checksum != wallet.checksum {
+                return Err(HTTPError::new(
+                    409,
+                    format!(
+                        "Wallet {} exists in the target at sequence {}, snapshot at {}",
+                        uid, sequence, wallet.sequence
+                    ),
+                )
+                .into());
+            }
+        }
+
+        let mut stats = ImportStats::default();
+        for doc in &credits {
+            stats.add(insert_row(db, "credit", &doc.to()).await?);
+        }
+        for doc in &mut charges {
+            let mut cols = doc.to();
+            Charge::offload_payload(db, &mut cols).await?;
+            stats.add(insert_row(db, "charge", &cols).await?);
+            doc.save_day_index(db).await;
+            if !doc.external_ref.is_empty() {
+                if let Some(id) = doc.claim_external_ref(db).await? {
+                    if id != doc.id {
+                        log::warn!(target: "migrate",
+                            uid = uid.to_string(),
+                            id = doc.id.to_string(),
+                            claimed_by = id.to_string();
+                            "external_ref {:?} is claimed by another charge", doc.external_ref,
+                        );
+                    }
+                }
+            }
+        }
+        for doc in &customers {
+            stats.add(insert_row(db, "customer", &doc.to()).await?);
+            Customer::save_index(db, &doc.provider, &doc.customer, uid).await?;
+            for customer in &doc.customers {
+                Customer::save_index(db, &doc.provider, customer, uid).await?;
+            }
+        }
+        for doc in &transactions {
+            stats.add(insert_row(db, "transaction", &doc.to()).await?);
+            TransactionByKind::new(uid, &doc.kind, doc.id)
+                .save(db)
+                .await?;
+            if let (Some(ref_uid), Some(ref_txn)) = (doc.ref_uid, doc.ref_txn) {
+                TransactionRef::new(ref_uid, ref_txn, uid, doc.id)
+                    .save(db)
+                    .await?;
+            }
+            if doc.status == 3 {
+                doc.save_payee_index(db, &HashMap::new()).await;
+            }
+        }
+        stats.add(wallet.save(db).await?);
+
+        Ok(stats)
+    }
+}
+
+fn in_flight_error(uid: xid::Id, what: &str) -> anyhow::Error {
+    HTTPError::new(
+        409,
+        format!(
+            "Wallet {} has in-flight {}, retry after they complete",
+            uid, what
+        ),
+    )
+    .into()
+}
+
+fn check_uid(uid: xid::Id, row_uid: xid::Id, table: &str) -> Result<(), HTTPError> {
+    if row_uid != uid {
+        return Err(HTTPError::new(
+            400,
+            format!("{} of {} in the snapshot of wallet {}", table, row_uid, uid),
+        ));
+    }
+    Ok(())
+}
+
+async fn read_rows(
+    db: &scylladb::ScyllaDB,
+    table: &str,
+    fields: Vec<String>,
+    uid: xid::Id,
+) -> anyhow::Result<Vec<ColumnsMap>> {
+    let query = format!("SELECT {} FROM {} WHERE uid=?", fields.join(","), table);
+    let params = (uid.to_cql(),);
+    let rows: Vec<Row> = db.stream(query, params).await?.try_collect().await?;
+
+    let mut res: Vec<ColumnsMap> = Vec::with_capacity(rows.len());
+    for row in rows {
+        let mut cols = ColumnsMap::with_capacity(fields.len());
+        cols.fill(row, &fields)?;
+        res.push(cols);
+    }
+    Ok(res)
+}
+
+// returns the sequence and checksum of the wallet in the target.
+async fn target_wallet(
+    db: &scylladb::ScyllaDB,
+    uid: xid::Id,
+) -> anyhow::Result<Option<(i64, Vec<u8>)>> {
+    let fields = vec!["sequence".to_string(), "checksum".to_string()];
+    let query = "SELECT sequence,checksum FROM wallet WHERE uid=? LIMIT 1";
+    let params = (uid.to_cql(),);
+    match db.execute_iter(query, params).await?.into_iter().next() {
+        Some(row) => {
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            Ok(Some((
+                cols.get_as("sequence").unwrap_or_default(),
+                cols.get_as("checksum").unwrap_or_default(),
+            )))
+        }
+        None => Ok(None),
+    }
+}
+
+// inserts the row if not exists, null columns are not written.
+async fn insert_row(
+    db: &scylladb::ScyllaDB,
+    table: &str,
+    cols: &ColumnsMap,
+) -> anyhow::Result<bool> {
+    let mut cols_name: Vec<&str> = Vec::with_capacity(cols.len());
+    let mut vals_name: Vec<&str> = Vec::with_capacity(cols.len());
+    let mut params: Vec<&CqlValue> = Vec::with_capacity(cols.len());
+    for (name, val) in cols.iter() {
+        if val == &CqlValue::Empty {
+            continue;
+        }
+
+        cols_name.push(name);
+        vals_name.push("?");
+        params.push(val);
+    }
+
+    let query = format!(
+        "INSERT INTO {} ({}) VALUES ({}) IF NOT EXISTS",
+        table,
+        cols_name.join(","),
+        vals_name.join(",")
+    );
+    let res = db.execute(query, params).await?;
+    Ok(extract_applied(res))
+}
+
+fn encode_row(cols: &ColumnsMap) -> anyhow::Result<SnapshotRow> {
+    let mut names = cols.keys();
+    names.sort();
+
+    let mut row = SnapshotRow::with_capacity(names.len());
+    for name in names {
+        let val = encode_value(cols.get(&name).unwrap())?;
+        row.insert(name, val);
+    }
+    Ok(row)
+}
+
+fn decode_row(row: &SnapshotRow) -> anyhow::Result<ColumnsMap> {
+    let mut cols = ColumnsMap::with_capacity(row.len());
+    for (name, val) in row {
+        cols.set_as(name, &decode_value(val)?);
+    }
+    Ok(cols)
+}
+
+// encodes a column value as {"<cql type>": value}, so that it is written back in the same
+// CQL type. Blobs are in base64url, null is Empty.
+pub fn encode_value(val: &CqlValue) -> anyhow::Result<Value> {
+    Ok(match val {
+        CqlValue::Empty => Value::Null,
+        CqlValue::Boolean(v) => json!({ "boolean": v }),
+        CqlValue::TinyInt(v) => json!({ "tinyint": v }),
+        CqlValue::SmallInt(v) => json!({ "smallint": v }),
+        CqlValue::Int(v) => json!({ "int": v }),
+        CqlValue::BigInt(v) => json!({ "bigint": v }),
+        CqlValue::Text(v) => json!({ "text": v }),
+        CqlValue::Blob(v) => json!({ "blob": base64url_encode(v) }),
+        CqlValue::List(v) => json!({ "list": encode_values(v)? }),
+        CqlValue::Set(v) => json!({ "set": encode_values(v)? }),
+        CqlValue::Map(v) => {
+            let mut pairs: Vec<Value> = Vec::with_capacity(v.len());
+            for (key, val) in v {
+                pairs.push(json!([encode_value(key)?, encode_value(val)?]));
+            }
+            json!({ "map": pairs })
+        }
+        CqlValue::Tuple(v) => {
+            let mut items: Vec<Value> = Vec::with_capacity(v.len());
+            for item in v {
+                items.push(match item {
+                    Some(item) => encode_value(item)?,
+                    None => Value::Null,
+                });
+            }
+            json!({ "tuple": items })
+        }
+        other => return Err(anyhow!("unsupported CQL value {:?}", other)),
+    })
+}
+
+pub fn decode_value(val: &Value) -> anyhow::Result<CqlValue> {
+    let (tag, v) = match val {
+        Value::Null => return Ok(CqlValue::Empty),
+        Value::Object(obj) if obj.len() == 1 => obj.iter().next().unwrap(),
+        _ => return Err(anyhow!("invalid tagged value {}", val)),
+    };
+
+    let invalid = || anyhow!("invalid {} value {}", tag, v);
+    Ok(match tag.as_str() {
+        "boolean" => CqlValue::Boolean(v.as_bool().ok_or_else(invalid)?),
+        "tinyint" => CqlValue::TinyInt(int_of(v).ok_or_else(invalid)?),
+        "smallint" => CqlValue::SmallInt(int_of(v).ok_or_else(invalid)?),
+        "int" => CqlValue::Int(int_of(v).ok_or_else(invalid)?),
+        "bigint" => CqlValue::BigInt(v.as_i64().ok_or_else(invalid)?),
+        "text" => CqlValue::Text(v.as_str().ok_or_else(invalid)?.to_string()),
+        "blob" => CqlValue::Blob(base64url_decode(v.as_str().ok_or_else(invalid)?)?),
+        "list" => CqlValue::List(decode_values(v.as_array().ok_or_else(invalid)?)?),
+        "set" => CqlValue::Set(decode_values(v.as_array().ok_or_else(invalid)?)?),
+        "map" => {
+            let list = v.as_array().ok_or_else(invalid)?;
+            let mut pairs: Vec<(CqlValue, CqlValue)> = Vec::with_capacity(list.len());
+            for pair in list {
+                match pair.as_array().map(|p| p.as_slice()) {
+                    Some([key, val]) => pairs.push((decode_value(key)?, decode_value(val)?)),
+                    _ => return Err(invalid()),
+                }
+            }
+            CqlValue::Map(pairs)
+        }
+        "tuple" => {
+            let list = v.as_array().ok_or_else(invalid)?;
+            let mut items: Vec<Option<CqlValue>> = Vec::with_capacity(list.len());
+            for item in list {
+                items.push(match item {
+                    Value::Null => None,
+                    item => Some(decode_value(item)?),
+                });
+            }
+            CqlValue::Tuple(items)
+        }
+        _ => return Err(invalid()),
+    })
+}
+
+fn encode_values(list: &[CqlValue]) -> anyhow::Result<Vec<Value>> {
+    list.iter().map(encode_value).collect()
+}
+
+fn decode_values(list: &[Value]) -> anyhow::Result<Vec<CqlValue>> {
+    list.iter().map(decode_value).collect()
+}
+
+fn int_of<T: TryFrom<i64>>(v: &Value) -> Option<T> {
+    v.as_i64().and_then(|n| T::try_from(n).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn snapshot_row_works() {
+        let mut doc = Transaction::with_pk(xid::new(), xid::new());
+        doc.sequence = 7;
+        doc.payee = xid::new();
+        doc.status = 3;
+        doc.kind = "sponsor".to_string();
+        doc.amount = 100;
+        doc.shares = vec![(xid::new(), 1000u16)];
+        doc.legs = HashSet::from(["payee".to_string(), "system".to_string()]);
+        doc.payload = vec![0xa0, 0x01, 0xff];
+        doc.balance_award = Some(-1);
+
+        let row = encode_row(&doc.to()).unwrap();
+        assert_eq!(json!({ "tinyint": 3 }), row["status"]);
+        assert_eq!(json!({ "blob": "oAH_" }), row["payload"]);
+        assert_eq!(Value::Null, row["ref_txn"]);
+
+        let data = serde_json::to_string(&row).unwrap();
+        let row: SnapshotRow = serde_json::from_str(&data).unwrap();
+        let mut got = Transaction::default();
+        got.fill(&decode_row(&row).unwrap());
+        assert_eq!(
+            (doc.uid, doc.id, doc.sequence),
+            (got.uid, got.id, got.sequence)
+        );
+        assert_eq!(
+            (doc.status, doc.kind, doc.amount),
+            (got.status, got.kind, got.amount)
+        );
+        assert_eq!(doc.payload, got.payload);
+        assert_eq!(doc.balance_award, got.balance_award);
+        assert_eq!(doc.shares, got.shares);
+        assert_eq!(doc.legs, got.legs);
+        assert_eq!(None, got.ref_txn);
+    }
+
+    #[test]
+    fn decode_value_works() {
+        let val = CqlValue::Map(vec![(
+            CqlValue::Text("a".to_string()),
+            CqlValue::Tuple(vec![Some(CqlValue::SmallInt(-2)), None]),
+        )]);
+        assert_eq!(val, decode_value(&encode_value(&val).unwrap()).unwrap());
+        assert_eq!(CqlValue::Empty, decode_value(&Value::Null).unwrap());
+
+        assert!(decode_value(&json!(1)).is_err());
+        assert!(decode_value(&json!({ "tinyint": 128 })).is_err());
+        assert!(decode_value(&json!({ "int": "1" })).is_err());
+        assert!(decode_value(&json!({ "blob": "+/" })).is_err());
+        assert!(decode_value(&json!({ "map": [[{ "int": 1 }]] })).is_err());
+        assert!(decode_value(&json!({ "uuid": "x" })).is_err());
+        assert!(decode_value(&json!({ "int": 1, "text": "a" })).is_err());
+        assert!(encode_value(&CqlValue::Double(1.0)).is_err());
+    }
+}
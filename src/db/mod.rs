@@ -1,16 +1,92 @@
+mod breaker;
+mod kinds;
+mod migrate;
+mod model_adjustment;
+mod model_api_key;
+mod model_audit_log;
+mod model_award_quota;
+mod model_blob;
+mod model_budget;
 mod model_charge;
 mod model_credit;
 mod model_customer;
+mod model_daily_stats;
+mod model_dispute;
+mod model_hold;
+mod model_job_lease;
+mod model_liability;
+mod model_payee_summary;
+mod model_policy_audit;
+mod model_pool;
+mod model_redpacket;
+mod model_subscription;
 mod model_transaction;
+mod model_txn_history;
 mod model_wallet;
+mod model_wallet_pref;
+mod model_webhook;
+mod model_withdrawal;
+mod retry;
+mod shadow;
+mod shed;
 
+#[cfg(feature = "fault-injection")]
+pub mod fault;
+pub mod schema;
 pub mod scylladb;
 
-pub use model_charge::Charge;
-pub use model_credit::{Credit, CreditKind};
-pub use model_customer::Customer;
-pub use model_transaction::{Transaction, TransactionKind, PayeeTransaction};
-pub use model_wallet::{income_fee_rate, HMacTag, Wallet, SYS_FEE_RATE, SYS_ID};
+pub use breaker::BreakerStatus;
+pub use kinds::{KindRules, Party};
+pub use migrate::{ImportStats, SnapshotRow, WalletSnapshot};
+pub use model_adjustment::{AdjustmentApproval, AdjustmentApprovalField};
+pub use model_api_key::{ApiKey, ApiKeyField, API_KEY_PREFIX, API_KEY_SCOPES, MAX_API_KEYS};
+pub use model_audit_log::{audit_log_enabled, set_audit_log_ttl, AuditLog, MAX_AUDIT_LOG_TTL_DAYS};
+pub use model_award_quota::{
+    award_quota_of, quota_reset_secs, set_award_quotas, AwardQuota, ANONYMOUS_SERVICE,
+};
+pub use model_blob::{Blob, MAX_INLINE_PAYLOAD};
+pub use model_budget::{Budget, BudgetField};
+pub use model_charge::{
+    day_of, livemode, max_charge_expire_ms, set_charge_expiry, set_livemode, Charge,
+    ChargeDailyTotal, ChargeField, DAY_MS, MAX_EXTERNAL_REF_LEN,
+};
+pub use model_credit::{Credit, CreditField, CreditKind, CreditOrder};
+pub use model_customer::{Customer, CustomerField};
+pub use model_daily_stats::{DailyChargeStats, DailyTxnStats};
+pub use model_dispute::{Dispute, DisputeField, MAX_DISPUTE_AGE_MS};
+pub use model_hold::{WalletHold, MAX_HOLD_TTL_SECS};
+pub use model_job_lease::{set_job_lease, JobLease};
+pub use model_liability::{Liability, LIABILITY_BUCKETS};
+pub use model_payee_summary::{week_of, week_start_ms, PayeeWeeklySummary, MAX_TOP_PAYERS};
+pub use model_policy_audit::PolicyAudit;
+pub use model_pool::{Pool, PoolContribution, PoolField, MAX_POOL_TTL_SECS};
+pub use model_redpacket::{
+    split_shares, Redpacket, RedpacketClaim, RedpacketField, MAX_REDPACKET_SHARES,
+    MAX_REDPACKET_TTL_SECS,
+};
+pub use model_subscription::{Subscription, MAX_SUBSCRIPTION_PERIOD_DAYS};
+pub use model_transaction::{
+    cancel_window_ms, set_cancel_window, set_max_amounts, set_min_credits, Balance, InvariantError,
+    PayeeTransaction, PayerPayeeTotal, Simulation, SystemDailyTotal, Transaction,
+    TransactionByKind, TransactionField, TransactionKind, TransactionRef,
+    EXPIRED_SPEND_LOOKBACK_DAYS,
+};
+pub use model_txn_history::{TxnHistory, MAX_TXN_HISTORY};
+pub use model_wallet::{
+    apply_bps, income_fee_rate, match_sequence, set_integrity_check_depth, set_max_overdraw,
+    HMacTag, Wallet, BPS_DENOMINATOR, CURSOR_TAG_LEN, SYS_FEE_RATE, SYS_ID,
+};
+pub use model_wallet_pref::WalletPref;
+pub use model_webhook::{
+    WalletWebhook, WebhookEvent, WEBHOOK_EVENT_INCOME, WEBHOOK_EVENT_SUBSCRIPTION_LAPSED,
+    WEBHOOK_EVENT_SUB_SHARES,
+};
+pub use model_withdrawal::{
+    set_withdraw_review_threshold, WithdrawalReview, WithdrawalReviewField,
+};
+pub use retry::{lwt_retry_metrics, retry_lwt, set_lwt_retry, RetryMetrics};
+pub use shadow::{set_shadow_tables, shadow_metrics, ShadowMetrics};
+pub use shed::{set_load_shedding, shed_load, shed_status, ShedConf, ShedStatus};
 
 pub static MAX_ID: xid::Id = xid::Id([255; 12]);
 pub static MIN_ID: xid::Id = xid::Id([0, 0, 0, 0, 255, 255, 255, 255, 255, 255, 255, 255]);
@@ -0,0 +1,203 @@
+use axum_web::{context::unix_ms, erring::HTTPError};
+use scylla_orm::{ColumnsMap, CqlValue, FieldSet, ToCqlVal};
+use scylla_orm_macros::CqlOrm;
+
+use crate::db::scylladb::{self, extract_applied};
+
+// two-person rule for adjustment transactions.
+// the transaction is prepared by the requester, and committed or canceled by another approver.
+#[derive(Debug, Default, Clone, CqlOrm)]
+pub struct AdjustmentApproval {
+    pub id: xid::Id, // adjustment transaction id
+    pub txn_uid: xid::Id,
+    pub uid: xid::Id,
+    pub amount: i64,
+    pub reason: String,
+    pub requester: String,
+    pub approver: String,
+    pub status: i8,
+    pub created_at: i64,
+    pub updated_at: i64,
+
+    pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+}
+
+impl AdjustmentApproval {
+    pub fn with_pk(id: xid::Id) -> Self {
+        Self {
+            id,
+            ..Default::default()
+        }
+    }
+
+    pub fn select_fields(
+        select_fields: FieldSet<AdjustmentApprovalField>,
+        with_pk: bool,
+    ) -> Vec<String> {
+        if select_fields.is_empty() {
+            return Self::fields();
+        }
+
+        let mut select_fields = select_fields
+            .with(AdjustmentApprovalField::TxnUid)
+            .with(AdjustmentApprovalField::Uid)
+            .with(AdjustmentApprovalField::Status);
+        if with_pk {
+            select_fields.insert(AdjustmentApprovalField::Id);
+        }
+
+        select_fields.names()
+    }
+
+    pub async fn get_one(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        select_fields: FieldSet<AdjustmentApprovalField>,
+    ) -> anyhow::Result<()> {
+        let fields = Self::select_fields(select_fields, false);
+        self._fields = fields.clone();
+
+        let query = format!(
+            "SELECT {} FROM adjustment_approval WHERE id=? LIMIT 1",
+            fields.join(",")
+        );
+        let params = (self.id.to_cql(),);
+        let res = db.execute(query, params).await?.single_row()?;
+
+        let mut cols = ColumnsMap::with_capacity(fields.len());
+        cols.fill(res, &fields)?;
+        self.fill(&cols);
+
+        Ok(())
+    }
+
+    pub async fn save(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        if self.reason.is_empty() || self.requester.is_empty() {
+            return Err(
+                HTTPError::new(400, "reason and requester are required".to_string()).into(),
+            );
+        }
+
+        self.status = 0;
+        self.approver = "".to_string();
+        self.created_at = unix_ms() as i64;
+        self.updated_at = self.created_at;
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let mut cols_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut vals_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut params: Vec<&CqlValue> = Vec::with_capacity(fields.len());
+        let cols = self.to();
+
+        for field in &fields {
+            cols_name.push(field);
+            vals_name.push("?");
+            params.push(cols.get(field).unwrap());
+        }
+
+        let query = format!(
+            "INSERT INTO adjustment_approval ({}) VALUES ({}) IF NOT EXISTS",
+            cols_name.join(","),
+            vals_name.join(",")
+        );
+
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Err(HTTPError::new(
+                409,
+                format!("Adjustment approval {} already exists", self.id),
+            )
+            .into());
+        }
+
+        Ok(true)
+    }
+
+    // status: 1 approved, -1 rejected.
+    pub async fn decide(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        approver: String,
+        status: i8,
+    ) -> anyhow::Result<()> {
+        if status != 1 && status != -1 {
+            return Err(HTTPError::new(400, format!("Invalid status {}", status)).into());
+        }
+        if approver.is_empty() || approver == self.requester {
+            return Err(HTTPError::new(
+                403,
+                format!("Adjustment {} requires a second approver", self.id),
+            )
+            .into());
+        }
+
+        let updated_at = unix_ms() as i64;
+        let query =
+            "UPDATE adjustment_approval SET approver=?,status=?,updated_at=? WHERE id=? IF status=0";
+        let params = (approver.to_cql(), status, updated_at, self.id.to_cql());
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Err(
+                HTTPError::new(409, format!("Adjustment {} has been decided", self.id)).into(),
+            );
+        }
+
+        self.approver = approver;
+        self.status = status;
+        self.updated_at = updated_at;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conf;
+
+    use super::*;
+
+    async fn get_db() -> scylladb::ScyllaDB {
+        let cfg = conf::Conf::new().unwrap_or_else(|err| panic!("config error: {}", err));
+        let res = scylladb::ScyllaDB::new(cfg.scylla, "walletbase_test").await;
+        res.unwrap()
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn adjustment_approval_model_works() {
+        let db = get_db().await;
+
+        let mut doc = AdjustmentApproval {
+            id: xid::new(),
+            txn_uid: xid::new(),
+            uid: xid::new(),
+            amount: -100,
+            reason: "".to_string(),
+            requester: "alice".to_string(),
+            ..Default::default()
+        };
+        assert!(doc.save(&db).await.is_err());
+
+        doc.reason = "duplicate topup".to_string();
+        doc.save(&db).await.unwrap();
+        assert!(doc.save(&db).await.is_err());
+
+        let mut doc2 = AdjustmentApproval::with_pk(doc.id);
+        doc2.get_one(&db, FieldSet::new()).await.unwrap();
+        assert_eq!(-100, doc2.amount);
+        assert_eq!(0, doc2.status);
+
+        let res = doc2.decide(&db, "alice".to_string(), 1).await;
+        assert!(res.is_err());
+        assert!(res.unwrap_err().to_string().contains("second approver"));
+
+        doc2.decide(&db, "bob".to_string(), 1).await.unwrap();
+        assert_eq!(1, doc2.status);
+        let res = doc2.decide(&db, "carol".to_string(), -1).await;
+        assert!(res.is_err());
+
+        doc.get_one(&db, FieldSet::new()).await.unwrap();
+        assert_eq!(1, doc.status);
+        assert_eq!("bob", doc.approver);
+    }
+}
@@ -0,0 +1,298 @@
+use axum_web::{context::unix_ms, erring::HTTPError};
+use rand_core::{OsRng, RngCore};
+use scylla_orm::{ColumnsMap, CqlValue, FieldSet, ToCqlVal};
+use scylla_orm_macros::CqlOrm;
+use sha3::{Digest, Sha3_256};
+use std::collections::HashSet;
+use subtle::ConstantTimeEq;
+
+use crate::crypto;
+use crate::db::scylladb::{self, extract_applied};
+
+pub const API_KEY_PREFIX: &str = "wk_";
+// max api keys of a wallet, revoked keys included.
+pub const MAX_API_KEYS: usize = 20;
+// read-only scopes an api key can be granted.
+pub const API_KEY_SCOPES: [&str; 3] = ["wallet", "transaction", "charge"];
+
+const SECRET_LEN: usize = 24;
+
+// api keys grant third-party tools read-only access to the wallet of the uid.
+// the key is "wk_" + base64url(uid, id, secret), only the SHA3-256 hash of the secret is stored.
+#[derive(Debug, Default, Clone, CqlOrm)]
+pub struct ApiKey {
+    pub uid: xid::Id,
+    pub id: xid::Id,
+    pub hash: Vec<u8>,
+    pub name: String,
+    pub scopes: HashSet<String>,
+    pub expire_at: i64, // unix ms, 0 for never
+    pub created_at: i64,
+    pub revoked_at: i64, // unix ms, 0 for active
+
+    pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+}
+
+impl ApiKey {
+    pub fn with_pk(uid: xid::Id, id: xid::Id) -> Self {
+        Self {
+            uid,
+            id,
+            ..Default::default()
+        }
+    }
+
+    // parses the key into (uid, id, secret), returns None if the key is malformed.
+    pub fn parse(key: &str) -> Option<(xid::Id, xid::Id, Vec<u8>)> {
+        let data = crypto::base64url_decode(key.strip_prefix(API_KEY_PREFIX)?).ok()?;
+        if data.len() != 24 + SECRET_LEN {
+            return None;
+        }
+
+        let mut uid = xid::Id::default();
+        uid.0.copy_from_slice(&data[..12]);
+        let mut id = xid::Id::default();
+        id.0.copy_from_slice(&data[12..24]);
+        Some((uid, id, data[24..].to_vec()))
+    }
+
+    pub fn hash_secret(secret: &[u8]) -> Vec<u8> {
+        Sha3_256::digest(secret).to_vec()
+    }
+
+    pub fn verify(&self, secret: &[u8], now_ms: i64) -> bool {
+        let hash = Self::hash_secret(secret);
+        bool::from(self.hash.ct_eq(&hash))
+            && self.revoked_at == 0
+            && (self.expire_at == 0 || self.expire_at > now_ms)
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.contains(scope)
+    }
+
+    pub fn select_fields(select_fields: FieldSet<ApiKeyField>, with_pk: bool) -> Vec<String> {
+        if select_fields.is_empty() {
+            return Self::fields();
+        }
+
+        let mut select_fields = select_fields.with(ApiKeyField::RevokedAt);
+        if with_pk {
+            select_fields.insert(ApiKeyField::Uid);
+            select_fields.insert(ApiKeyField::Id);
+        }
+
+        select_fields.names()
+    }
+
+    pub async fn get_one(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        select_fields: FieldSet<ApiKeyField>,
+    ) -> anyhow::Result<()> {
+        let fields = Self::select_fields(select_fields, false);
+        self._fields = fields.clone();
+
+        let query = format!(
+            "SELECT {} FROM api_key WHERE uid=? AND id=? LIMIT 1",
+            fields.join(",")
+        );
+        let params = (self.uid.to_cql(), self.id.to_cql());
+        let res = db.execute(query, params).await?.single_row()?;
+
+        let mut cols = ColumnsMap::with_capacity(fields.len());
+        cols.fill(res, &fields)?;
+        self.fill(&cols);
+
+        Ok(())
+    }
+
+    // saves a new api key and returns the key, it is shown only once.
+    pub async fn save(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<String> {
+        if self.scopes.is_empty() {
+            return Err(HTTPError::new(400, "Api key scopes required".to_string()).into());
+        }
+        for scope in &self.scopes {
+            if !API_KEY_SCOPES.contains(&scope.as_str()) {
+                return Err(HTTPError::new(400, format!("Invalid scope: {}", scope)).into());
+            }
+        }
+        let keys = Self::list(db, self.uid, [ApiKeyField::Id].into()).await?;
+        if keys.len() >= MAX_API_KEYS {
+            return Err(HTTPError::new(
+                400,
+                format!("Too many api keys, at most {}", MAX_API_KEYS),
+            )
+            .into());
+        }
+
+        let mut secret = [0u8; SECRET_LEN];
+        OsRng.fill_bytes(&mut secret);
+        self.id = xid::new();
+        self.hash = Self::hash_secret(&secret);
+        self.created_at = unix_ms() as i64;
+        self.revoked_at = 0;
+
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let mut cols_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut vals_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut params: Vec<&CqlValue> = Vec::with_capacity(fields.len());
+        let cols = self.to();
+
+        for field in &fields {
+            cols_name.push(field);
+            vals_name.push("?");
+            params.push(cols.get(field).unwrap());
+        }
+
+        let query = format!(
+            "INSERT INTO api_key ({}) VALUES ({}) IF NOT EXISTS",
+            cols_name.join(","),
+            vals_name.join(",")
+        );
+
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Err(
+                HTTPError::new(409, "Api key save failed, please try again".to_string()).into(),
+            );
+        }
+
+        let mut data = Vec::with_capacity(24 + SECRET_LEN);
+        data.extend_from_slice(&self.uid.0);
+        data.extend_from_slice(&self.id.0);
+        data.extend_from_slice(&secret);
+        Ok(format!(
+            "{}{}",
+            API_KEY_PREFIX,
+            crypto::base64url_encode(&data)
+        ))
+    }
+
+    // returns false if the key has been revoked.
+    pub async fn revoke(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        let revoked_at = unix_ms() as i64;
+        let query = "UPDATE api_key SET revoked_at=? WHERE uid=? AND id=? IF revoked_at=0";
+        let params = (revoked_at, self.uid.to_cql(), self.id.to_cql());
+        let res = db.execute(query, params).await?;
+        let ok = extract_applied(res);
+        if ok {
+            self.revoked_at = revoked_at;
+        }
+        Ok(ok)
+    }
+
+    // lists all the api keys of the uid, newest first.
+    pub async fn list(
+        db: &scylladb::ScyllaDB,
+        uid: xid::Id,
+        select_fields: FieldSet<ApiKeyField>,
+    ) -> anyhow::Result<Vec<Self>> {
+        let fields = Self::select_fields(select_fields, true);
+
+        let query = db.list_query(&format!(
+            "SELECT {} FROM api_key WHERE uid=? LIMIT ?",
+            fields.join(",")
+        ));
+        let params = (uid.to_cql(), MAX_API_KEYS as i32);
+        let rows = db.execute_iter(query, params).await?;
+
+        let mut res: Vec<Self> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Self::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conf;
+
+    use super::*;
+
+    async fn get_db() -> scylladb::ScyllaDB {
+        let cfg = conf::Conf::new().unwrap_or_else(|err| panic!("config error: {}", err));
+        let res = scylladb::ScyllaDB::new(cfg.scylla, "walletbase_test").await;
+        res.unwrap()
+    }
+
+    #[test]
+    fn parse_and_verify_works() {
+        assert!(ApiKey::parse("").is_none());
+        assert!(ApiKey::parse("wk_").is_none());
+        assert!(ApiKey::parse("sk_AAAA").is_none());
+
+        let uid = xid::new();
+        let id = xid::new();
+        let secret = [7u8; SECRET_LEN];
+        let mut data = Vec::new();
+        data.extend_from_slice(&uid.0);
+        data.extend_from_slice(&id.0);
+        data.extend_from_slice(&secret);
+        let key = format!("{}{}", API_KEY_PREFIX, crypto::base64url_encode(&data));
+        assert!(ApiKey::parse(&key[..key.len() - 2]).is_none());
+
+        let (uid2, id2, secret2) = ApiKey::parse(&key).unwrap();
+        assert_eq!(uid, uid2);
+        assert_eq!(id, id2);
+        assert_eq!(secret.to_vec(), secret2);
+
+        let mut doc = ApiKey::with_pk(uid, id);
+        doc.hash = ApiKey::hash_secret(&secret);
+        assert!(doc.verify(&secret2, 1000));
+        assert!(!doc.verify(&[8u8; SECRET_LEN], 1000));
+
+        doc.expire_at = 1000;
+        assert!(!doc.verify(&secret2, 1000));
+        assert!(doc.verify(&secret2, 999));
+
+        doc.expire_at = 0;
+        doc.revoked_at = 1;
+        assert!(!doc.verify(&secret2, 1000));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn api_key_model_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+
+        let mut doc = ApiKey {
+            uid,
+            name: "analytics".to_string(),
+            ..Default::default()
+        };
+        assert!(doc.save(&db).await.is_err());
+        doc.scopes = HashSet::from(["admin".to_string()]);
+        assert!(doc.save(&db).await.is_err());
+
+        doc.scopes = HashSet::from(["wallet".to_string(), "transaction".to_string()]);
+        let key = doc.save(&db).await.unwrap();
+        let (uid2, id2, secret) = ApiKey::parse(&key).unwrap();
+        assert_eq!(uid, uid2);
+        assert_eq!(doc.id, id2);
+
+        let mut doc2 = ApiKey::with_pk(uid2, id2);
+        doc2.get_one(&db, FieldSet::new()).await.unwrap();
+        assert!(doc2.verify(&secret, unix_ms() as i64));
+        assert!(doc2.has_scope("wallet"));
+        assert!(!doc2.has_scope("charge"));
+
+        let res = ApiKey::list(&db, uid, FieldSet::new()).await.unwrap();
+        assert_eq!(1, res.len());
+
+        assert!(doc2.revoke(&db).await.unwrap());
+        assert!(!doc2.revoke(&db).await.unwrap());
+        doc2.get_one(&db, FieldSet::new()).await.unwrap();
+        assert!(!doc2.verify(&secret, unix_ms() as i64));
+    }
+}
@@ -0,0 +1,182 @@
+use scylla_orm::{ColumnsMap, CqlValue, ToCqlVal};
+use scylla_orm_macros::CqlOrm;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use crate::db::scylladb;
+
+// seconds the audit logs are kept, 0 disables the audit log.
+static AUDIT_LOG_TTL_SECS: AtomicI64 = AtomicI64::new(0);
+
+// the longest TTL of ScyllaDB is 20 years, audit logs are kept for at most 10 years.
+pub const MAX_AUDIT_LOG_TTL_DAYS: i64 = 3650;
+
+pub fn set_audit_log_ttl(days: i64) -> anyhow::Result<()> {
+    if !(0..=MAX_AUDIT_LOG_TTL_DAYS).contains(&days) {
+        return Err(anyhow::anyhow!("Invalid audit log ttl_days {}", days));
+    }
+    AUDIT_LOG_TTL_SECS.store(days * 86400, Ordering::Relaxed);
+    Ok(())
+}
+
+pub fn audit_log_enabled() -> bool {
+    AUDIT_LOG_TTL_SECS.load(Ordering::Relaxed) > 0
+}
+
+// audit trail of a mutating request, the kv of its ReqContext, for support investigations.
+// logs of a wallet are ordered by id, which is created when the request finished.
+#[derive(Debug, Default, Clone, CqlOrm, PartialEq)]
+pub struct AuditLog {
+    pub uid: xid::Id,
+    pub id: xid::Id,
+    pub action: String,
+    pub rid: String,     // x-request-id
+    pub caller: xid::Id, // x-auth-user
+    pub app: String,     // x-auth-app
+    pub method: String,  // HTTP method
+    pub path: String,    // HTTP path
+    pub status: i16,     // HTTP status of the response
+    pub kv: String,      // ReqContext kv in JSON, e.g. amounts and ids
+    pub elapsed: i32,    // ms
+    pub created_at: i64, // unix ms
+
+    pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+}
+
+impl AuditLog {
+    pub async fn save(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+        let ttl_secs = AUDIT_LOG_TTL_SECS.load(Ordering::Relaxed);
+        if ttl_secs <= 0 {
+            return Ok(());
+        }
+
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let mut cols_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut vals_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut params: Vec<&CqlValue> = Vec::with_capacity(fields.len() + 1);
+        let cols = self.to();
+
+        for field in &fields {
+            cols_name.push(field);
+            vals_name.push("?");
+            params.push(cols.get(field).unwrap());
+        }
+
+        let ttl = CqlValue::Int(ttl_secs as i32);
+        params.push(&ttl);
+        let query = format!(
+            "INSERT INTO audit_log ({}) VALUES ({}) USING TTL ?",
+            cols_name.join(","),
+            vals_name.join(",")
+        );
+
+        db.execute(query, params).await?;
+        Ok(())
+    }
+
+    // lists the logs of the wallet in (start, end), newest first.
+    // the page token is the id of the last log of the previous page.
+    pub async fn list(
+        db: &scylladb::ScyllaDB,
+        uid: xid::Id,
+        start: xid::Id,
+        end: xid::Id,
+        page_size: u16,
+        page_token: Option<xid::Id>,
+    ) -> anyhow::Result<Vec<Self>> {
+        let fields = Self::fields();
+        let end = match page_token {
+            Some(id) if id.0 < end.0 => id,
+            _ => end,
+        };
+
+        let query = db.list_query(&format!(
+            "SELECT {} FROM audit_log WHERE uid=? AND id<? AND id>? LIMIT ?",
+            fields.join(",")
+        ));
+        let params = (uid.to_cql(), end.to_cql(), start.to_cql(), page_size as i32);
+        let rows = db.execute_iter(query, params).await?;
+
+        let mut res: Vec<Self> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Self::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conf;
+
+    use super::*;
+
+    async fn get_db() -> scylladb::ScyllaDB {
+        let cfg = conf::Conf::new().unwrap_or_else(|err| panic!("config error: {}", err));
+        let res = scylladb::ScyllaDB::new(cfg.scylla, "walletbase_test").await;
+        res.unwrap()
+    }
+
+    #[test]
+    fn set_audit_log_ttl_works() {
+        assert!(set_audit_log_ttl(-1).is_err());
+        assert!(set_audit_log_ttl(MAX_AUDIT_LOG_TTL_DAYS + 1).is_err());
+        set_audit_log_ttl(0).unwrap();
+        assert!(!audit_log_enabled());
+        set_audit_log_ttl(90).unwrap();
+        assert!(audit_log_enabled());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn audit_log_model_works() {
+        let db = get_db().await;
+        set_audit_log_ttl(1).unwrap();
+        let uid = xid::new();
+
+        let mut logs: Vec<AuditLog> = Vec::new();
+        for action in ["spend", "commit_transaction", "award"] {
+            let mut doc = AuditLog {
+                uid,
+                id: xid::new(),
+                action: action.to_string(),
+                rid: "rid".to_string(),
+                method: "POST".to_string(),
+                path: "/v1/wallet/spend".to_string(),
+                status: 200,
+                kv: r#"{"amount":100}"#.to_string(),
+                created_at: 1,
+                ..Default::default()
+            };
+            doc.save(&db).await.unwrap();
+            logs.push(doc);
+        }
+        logs.reverse();
+
+        let start = xid::Id([0u8; 12]);
+        let end = xid::Id([255u8; 12]);
+        let res = AuditLog::list(&db, uid, start, end, 10, None)
+            .await
+            .unwrap();
+        assert_eq!(logs, res);
+
+        let res = AuditLog::list(&db, uid, start, end, 2, None).await.unwrap();
+        assert_eq!(logs[..2], res);
+        let res = AuditLog::list(&db, uid, start, end, 2, Some(res[1].id))
+            .await
+            .unwrap();
+        assert_eq!(logs[2..], res);
+
+        let res = AuditLog::list(&db, uid, start, logs[0].id, 10, None)
+            .await
+            .unwrap();
+        assert_eq!(logs[1..], res);
+    }
+}
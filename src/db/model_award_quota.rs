@@ -0,0 +1,176 @@
+use axum_web::erring::HTTPError;
+use std::{collections::HashMap, sync::RwLock};
+
+use super::{model_transaction::counter_of, DAY_MS};
+use crate::db::scylladb;
+
+// the principal of requests without the service header.
+pub const ANONYMOUS_SERVICE: &str = "-";
+
+// (default quota, quotas per service), a quota of 0 is unlimited.
+static QUOTAS: RwLock<Option<(i64, HashMap<String, i64>)>> = RwLock::new(None);
+
+pub fn set_award_quotas(default: i64, services: &HashMap<String, i64>) -> anyhow::Result<()> {
+    if default < 0 {
+        return Err(anyhow::anyhow!("Invalid default award quota {}", default));
+    }
+    for (service, quota) in services {
+        if service.is_empty() || *quota < 0 {
+            return Err(anyhow::anyhow!(
+                "Invalid award quota {} for service {:?}",
+                quota,
+                service
+            ));
+        }
+    }
+    *QUOTAS.write().unwrap() = Some((default, services.clone()));
+    Ok(())
+}
+
+// the daily award quota of the service, 0 for unlimited.
+pub fn award_quota_of(service: &str) -> i64 {
+    match QUOTAS.read().unwrap().as_ref() {
+        Some((default, services)) => services.get(service).copied().unwrap_or(*default),
+        None => 0,
+    }
+}
+
+// seconds until the quota of the day resets at 00:00 UTC.
+pub fn quota_reset_secs(now_ms: i64) -> i64 {
+    let next = (now_ms.div_euclid(DAY_MS) + 1) * DAY_MS;
+    (next - now_ms + 999) / 1000
+}
+
+// awarded amount of a service (auth principal) per day, a bug in one of the calling
+// services should not mint unlimited coins.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AwardQuota {
+    pub service: String,
+    pub day: i32,
+    pub quota: i64,  // 0 for unlimited
+    pub amount: i64, // awarded amount of the day, including the reserved
+    pub awards: i64, // number of awards of the day
+}
+
+impl AwardQuota {
+    pub fn new(service: &str, now_ms: i64) -> Self {
+        let service = if service.is_empty() {
+            ANONYMOUS_SERVICE
+        } else {
+            service
+        };
+        Self {
+            service: service.to_string(),
+            day: now_ms.div_euclid(DAY_MS) as i32,
+            quota: award_quota_of(service),
+            ..Default::default()
+        }
+    }
+
+    pub fn remaining(&self) -> i64 {
+        (self.quota - self.amount).max(0)
+    }
+
+    pub async fn get_one(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+        let query = "SELECT amount,awards FROM award_quota_daily WHERE service=? AND day=? LIMIT 1";
+        let params = (self.service.to_owned(), self.day);
+        let rows = db.execute_iter(query, params).await?;
+        if let Some(row) = rows.first() {
+            self.amount = counter_of(row.columns.first());
+            self.awards = counter_of(row.columns.get(1));
+        }
+        Ok(())
+    }
+
+    // reserves the amount before the award transaction is prepared, returns 429 and
+    // gives the amount back if it exceeds the quota.
+    // concurrent reservations near the quota may all be rejected, but never all accepted.
+    pub async fn reserve(&mut self, db: &scylladb::ScyllaDB, amount: i64) -> anyhow::Result<()> {
+        if self.quota == 0 {
+            return Ok(());
+        }
+
+        self.incr(db, amount, 1).await?;
+        self.get_one(db).await?;
+        if self.amount <= self.quota {
+            return Ok(());
+        }
+
+        self.release(db, amount).await?;
+        let mut err = HTTPError::new(
+            429,
+            format!(
+                "Award quota of service {} exceeded, remaining {}",
+                self.service,
+                self.remaining()
+            ),
+        );
+        err.data = Some(serde_json::json!({
+            "service": self.service,
+            "quota": self.quota,
+            "remaining": self.remaining(),
+            "amount": amount,
+        }));
+        Err(err.into())
+    }
+
+    // gives back the amount when the award failed.
+    pub async fn release(&mut self, db: &scylladb::ScyllaDB, amount: i64) -> anyhow::Result<()> {
+        if self.quota == 0 {
+            return Ok(());
+        }
+
+        self.incr(db, -amount, -1).await?;
+        self.amount -= amount;
+        self.awards -= 1;
+        Ok(())
+    }
+
+    async fn incr(&self, db: &scylladb::ScyllaDB, amount: i64, awards: i64) -> anyhow::Result<()> {
+        let query =
+            "UPDATE award_quota_daily SET amount=amount+?,awards=awards+? WHERE service=? AND day=?";
+        let params = (amount, awards, self.service.to_owned(), self.day);
+        db.execute(query, params).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn award_quota_works() {
+        assert!(set_award_quotas(-1, &HashMap::new()).is_err());
+        assert!(set_award_quotas(0, &HashMap::from([("".to_string(), 100)])).is_err());
+        assert!(set_award_quotas(0, &HashMap::from([("creation".to_string(), -1)])).is_err());
+
+        set_award_quotas(
+            1000,
+            &HashMap::from([("creation".to_string(), 5000), ("trusted".to_string(), 0)]),
+        )
+        .unwrap();
+        assert_eq!(5000, award_quota_of("creation"));
+        assert_eq!(0, award_quota_of("trusted"));
+        assert_eq!(1000, award_quota_of("other"));
+
+        let now = 100 * DAY_MS + 1;
+        let doc = AwardQuota::new("", now);
+        assert_eq!(ANONYMOUS_SERVICE, doc.service);
+        assert_eq!(100, doc.day);
+        assert_eq!(1000, doc.quota);
+
+        let mut doc = AwardQuota::new("creation", now);
+        assert_eq!(5000, doc.remaining());
+        doc.amount = 6000;
+        assert_eq!(0, doc.remaining());
+    }
+
+    #[test]
+    fn quota_reset_secs_works() {
+        assert_eq!(86400, quota_reset_secs(0));
+        assert_eq!(86400, quota_reset_secs(1));
+        assert_eq!(1, quota_reset_secs(DAY_MS - 1));
+        assert_eq!(3600, quota_reset_secs(DAY_MS * 10 + 23 * 3600 * 1000));
+    }
+}
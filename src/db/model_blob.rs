@@ -0,0 +1,132 @@
+use axum_web::{context::unix_ms, erring::HTTPError};
+use scylla_orm::{ColumnsMap, ToCqlVal};
+use scylla_orm_macros::CqlOrm;
+use sha3::{Digest, Sha3_256};
+
+use crate::db::scylladb;
+
+// payloads larger than this are stored in the blob table, and referenced by their hash.
+pub const MAX_INLINE_PAYLOAD: usize = 4096;
+
+// cold storage of large payloads, e.g. the provider's JSON of a charge.
+// blobs are content addressed by the SHA3-256 of the data, so that saving is idempotent and
+// the data is verified when loaded. they are never updated.
+#[derive(Debug, Default, Clone, CqlOrm)]
+pub struct Blob {
+    pub id: Vec<u8>, // SHA3-256 of data
+    pub data: Vec<u8>,
+    pub created_at: i64,
+
+    pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+}
+
+impl Blob {
+    pub fn hash(data: &[u8]) -> Vec<u8> {
+        Sha3_256::digest(data).to_vec()
+    }
+
+    pub fn new(data: Vec<u8>) -> Self {
+        Self {
+            id: Self::hash(&data),
+            data,
+            ..Default::default()
+        }
+    }
+
+    pub async fn get_one(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let query = format!("SELECT {} FROM blob WHERE id=? LIMIT 1", fields.join(","));
+        let params = (self.id.to_cql(),);
+        let res = db.execute(query, params).await?.single_row()?;
+
+        let mut cols = ColumnsMap::with_capacity(fields.len());
+        cols.fill(res, &fields)?;
+        self.fill(&cols);
+
+        if Self::hash(&self.data) != self.id {
+            return Err(HTTPError::new(500, "Blob data corrupted".to_string()).into());
+        }
+        Ok(())
+    }
+
+    // the same data is saved to the same row, so it is not a LWT.
+    pub async fn save(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+        if self.data.is_empty() {
+            return Err(HTTPError::new(400, "Empty blob".to_string()).into());
+        }
+
+        self.id = Self::hash(&self.data);
+        self.created_at = unix_ms() as i64;
+        let query = "INSERT INTO blob (id,data,created_at) VALUES (?,?,?)";
+        let params = (self.id.to_cql(), self.data.to_cql(), self.created_at);
+        db.execute(query, params).await?;
+        Ok(())
+    }
+
+    // returns the reference of the payload if it is stored in the blob table, None if it is
+    // small enough to be inline.
+    pub async fn offload(
+        db: &scylladb::ScyllaDB,
+        payload: &[u8],
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        if payload.len() <= MAX_INLINE_PAYLOAD {
+            return Ok(None);
+        }
+
+        let mut doc = Self::new(payload.to_vec());
+        doc.save(db).await?;
+        Ok(Some(doc.id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conf;
+
+    use super::*;
+
+    async fn get_db() -> scylladb::ScyllaDB {
+        let cfg = conf::Conf::new().unwrap_or_else(|err| panic!("config error: {}", err));
+        let res = scylladb::ScyllaDB::new(cfg.scylla, "walletbase_test").await;
+        res.unwrap()
+    }
+
+    #[test]
+    fn hash_works() {
+        let doc = Blob::new(vec![1, 2, 3]);
+        assert_eq!(32, doc.id.len());
+        assert_eq!(doc.id, Blob::hash(&[1, 2, 3]));
+        assert_ne!(doc.id, Blob::hash(&[1, 2, 3, 4]));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn blob_model_works() {
+        let db = get_db().await;
+
+        let mut doc = Blob::default();
+        assert!(doc.save(&db).await.is_err());
+
+        assert_eq!(None, Blob::offload(&db, &[1u8; 100]).await.unwrap());
+        let data = vec![7u8; MAX_INLINE_PAYLOAD + 1];
+        let id = Blob::offload(&db, &data).await.unwrap().unwrap();
+        assert_eq!(Blob::hash(&data), id);
+        // idempotent
+        assert_eq!(id, Blob::offload(&db, &data).await.unwrap().unwrap());
+
+        let mut doc = Blob {
+            id,
+            ..Default::default()
+        };
+        doc.get_one(&db).await.unwrap();
+        assert_eq!(data, doc.data);
+
+        let mut doc = Blob {
+            id: Blob::hash(&[0u8; 8]),
+            ..Default::default()
+        };
+        assert!(doc.get_one(&db).await.is_err());
+    }
+}
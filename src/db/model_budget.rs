@@ -0,0 +1,264 @@
+use axum_web::{context::unix_ms, erring::HTTPError};
+use scylla_orm::{ColumnsMap, CqlValue, FieldSet, ToCqlVal};
+use scylla_orm_macros::CqlOrm;
+
+use crate::db::{
+    retry_lwt,
+    scylladb::{self, extract_applied},
+};
+
+#[derive(Debug, Default, Clone, CqlOrm)]
+pub struct Budget {
+    pub id: xid::Id,
+    pub owner: xid::Id,
+    pub remaining: i64,
+    pub expire_at: i64,
+
+    pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+}
+
+impl Budget {
+    pub fn with_pk(id: xid::Id) -> Self {
+        Self {
+            id,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expire_at > 0 && self.expire_at <= now
+    }
+
+    pub fn select_fields(select_fields: FieldSet<BudgetField>, with_pk: bool) -> Vec<String> {
+        if select_fields.is_empty() {
+            return Self::fields();
+        }
+
+        let mut select_fields = select_fields.with(BudgetField::Owner);
+        if with_pk {
+            select_fields.insert(BudgetField::Id);
+        }
+
+        select_fields.names()
+    }
+
+    pub async fn get_one(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        select_fields: FieldSet<BudgetField>,
+    ) -> anyhow::Result<()> {
+        let fields = Self::select_fields(select_fields, false);
+        self._fields = fields.clone();
+
+        let query = format!("SELECT {} FROM budget WHERE id=? LIMIT 1", fields.join(","));
+        let params = (self.id.to_cql(),);
+        let res = db.execute(query, params).await?.single_row()?;
+
+        let mut cols = ColumnsMap::with_capacity(fields.len());
+        cols.fill(res, &fields)?;
+        self.fill(&cols);
+
+        Ok(())
+    }
+
+    pub async fn save(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        if self.remaining <= 0 {
+            return Err(
+                HTTPError::new(400, format!("Invalid remaining {}", self.remaining)).into(),
+            );
+        }
+
+        self.id = xid::new();
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let mut cols_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut vals_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut params: Vec<&CqlValue> = Vec::with_capacity(fields.len());
+        let cols = self.to();
+
+        for field in &fields {
+            cols_name.push(field);
+            vals_name.push("?");
+            params.push(cols.get(field).unwrap());
+        }
+
+        let query = format!(
+            "INSERT INTO budget ({}) VALUES ({}) IF NOT EXISTS",
+            cols_name.join(","),
+            vals_name.join(",")
+        );
+
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Err(
+                HTTPError::new(409, "Budget save failed, please try again".to_string()).into(),
+            );
+        }
+
+        Ok(true)
+    }
+
+    pub async fn update(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        cols: ColumnsMap,
+    ) -> anyhow::Result<bool> {
+        let valid_fields = ["owner", "remaining", "expire_at"];
+        let update_fields = cols.keys();
+        for field in &update_fields {
+            if !valid_fields.contains(&field.as_str()) {
+                return Err(HTTPError::new(400, format!("Invalid field: {}", field)).into());
+            }
+        }
+
+        let mut set_fields: Vec<String> = Vec::with_capacity(update_fields.len());
+        let mut params: Vec<CqlValue> = Vec::with_capacity(update_fields.len() + 1);
+        for field in &update_fields {
+            set_fields.push(format!("{}=?", field));
+            params.push(cols.get(field).unwrap().to_owned());
+        }
+
+        let query = format!(
+            "UPDATE budget SET {} WHERE id=? IF EXISTS",
+            set_fields.join(",")
+        );
+        params.push(self.id.to_cql());
+
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Err(HTTPError::new(404, format!("Budget {} not found", self.id)).into());
+        }
+
+        self.fill(&cols);
+        Ok(true)
+    }
+
+    pub async fn delete(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        let query = "DELETE FROM budget WHERE id=? IF EXISTS";
+        let params = (self.id.to_cql(),);
+        let res = db.execute(query, params).await?;
+        Ok(extract_applied(res))
+    }
+
+    // decrease the remaining before preparing an award transaction.
+    pub async fn decrement(&mut self, db: &scylladb::ScyllaDB, amount: i64) -> anyhow::Result<()> {
+        if amount <= 0 {
+            return Err(HTTPError::new(400, format!("Invalid amount {}", amount)).into());
+        }
+
+        let query = "UPDATE budget SET remaining=? WHERE id=? IF remaining=?";
+        let mut retry = retry_lwt("decrement_budget");
+        while retry.next().await {
+            self.get_one(db, [BudgetField::Remaining, BudgetField::ExpireAt].into())
+                .await?;
+            if self.is_expired(unix_ms() as i64) {
+                return Err(HTTPError::new(400, format!("Budget {} expired", self.id)).into());
+            }
+            if self.remaining < amount {
+                return Err(HTTPError::new(
+                    400,
+                    format!(
+                        "Insufficient budget {}, expected {}, got {}",
+                        self.id, amount, self.remaining
+                    ),
+                )
+                .into());
+            }
+
+            let params = (self.remaining - amount, self.id.to_cql(), self.remaining);
+            let res = db.execute(query, params).await?;
+            if extract_applied(res) {
+                self.remaining -= amount;
+                return Ok(());
+            }
+        }
+
+        Err(HTTPError::new(429, format!("Failed to decrement budget {}", self.id)).into())
+    }
+
+    // give back the amount when the award transaction failed.
+    pub async fn increment(&mut self, db: &scylladb::ScyllaDB, amount: i64) -> anyhow::Result<()> {
+        let query = "UPDATE budget SET remaining=? WHERE id=? IF remaining=?";
+        let mut retry = retry_lwt("increment_budget");
+        while retry.next().await {
+            self.get_one(db, [BudgetField::Remaining].into()).await?;
+            let params = (self.remaining + amount, self.id.to_cql(), self.remaining);
+            let res = db.execute(query, params).await?;
+            if extract_applied(res) {
+                self.remaining += amount;
+                return Ok(());
+            }
+        }
+
+        log::error!(target: "scylladb",
+            action = "increment_budget",
+            id = self.id.to_string(),
+            amount = amount;
+            "increment_budget failed",
+        );
+
+        Err(HTTPError::new(500, format!("Failed to increment budget {}", self.id)).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conf;
+
+    use super::*;
+
+    async fn get_db() -> scylladb::ScyllaDB {
+        let cfg = conf::Conf::new().unwrap_or_else(|err| panic!("config error: {}", err));
+        let res = scylladb::ScyllaDB::new(cfg.scylla, "walletbase_test").await;
+        res.unwrap()
+    }
+
+    #[test]
+    fn budget_expired_works() {
+        let mut budget = Budget::with_pk(xid::new());
+        assert!(!budget.is_expired(1000));
+        budget.expire_at = 1000;
+        assert!(budget.is_expired(1000));
+        assert!(!budget.is_expired(999));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn budget_model_works() {
+        let db = get_db().await;
+
+        let mut budget = Budget {
+            owner: xid::new(),
+            ..Default::default()
+        };
+        assert!(budget.save(&db).await.is_err());
+
+        budget.remaining = 100;
+        budget.save(&db).await.unwrap();
+
+        let mut doc = Budget::with_pk(budget.id);
+        doc.get_one(&db, FieldSet::new()).await.unwrap();
+        assert_eq!(budget.owner, doc.owner);
+        assert_eq!(100, doc.remaining);
+
+        doc.decrement(&db, 60).await.unwrap();
+        assert_eq!(40, doc.remaining);
+        let res = doc.decrement(&db, 60).await;
+        assert!(res.is_err());
+        assert!(res.unwrap_err().to_string().contains("Insufficient budget"));
+
+        doc.increment(&db, 60).await.unwrap();
+        assert_eq!(100, doc.remaining);
+
+        let mut cols = ColumnsMap::new();
+        cols.set_as("expire_at", &1i64);
+        doc.update(&db, cols).await.unwrap();
+        let res = doc.decrement(&db, 10).await;
+        assert!(res.is_err());
+        assert!(res.unwrap_err().to_string().contains("expired"));
+
+        assert!(doc.delete(&db).await.unwrap());
+        assert!(doc.get_one(&db, FieldSet::new()).await.is_err());
+    }
+}
@@ -1,10 +1,214 @@
 use axum_web::{context::unix_ms, erring::HTTPError};
-use scylla_orm::{ColumnsMap, CqlValue, ToCqlVal};
+use scylla_orm::{ColumnsMap, CqlValue, FieldSet, ToCqlVal};
 use scylla_orm_macros::CqlOrm;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
 
-use super::MAX_ID;
+use super::{model_transaction::counter_of, Blob, MAX_ID};
 use crate::db::scylladb::{self, extract_applied};
 
+pub const DAY_MS: i64 = 24 * 3600 * 1000;
+// max days to scan in one list_by_day request.
+const MAX_DAYS: i32 = 93;
+// max length of a charge's external_ref.
+pub const MAX_EXTERNAL_REF_LEN: usize = 128;
+
+// whether this deployment commits live transactions, it is set from conf at startup.
+// charges are completed only when their livemode matches.
+static LIVEMODE: AtomicBool = AtomicBool::new(true);
+
+pub fn set_livemode(val: bool) {
+    LIVEMODE.store(val, Ordering::Relaxed);
+}
+
+pub fn livemode() -> bool {
+    LIVEMODE.load(Ordering::Relaxed)
+}
+
+// default, min and max seconds before a preparing charge expires, they are set from conf at startup.
+static EXPIRE_SECS: AtomicI64 = AtomicI64::new(24 * 3600);
+static MIN_EXPIRE_SECS: AtomicI64 = AtomicI64::new(300);
+static MAX_EXPIRE_SECS: AtomicI64 = AtomicI64::new(7 * 24 * 3600);
+
+pub fn set_charge_expiry(default_secs: i64, min_secs: i64, max_secs: i64) -> anyhow::Result<()> {
+    if min_secs <= 0 || default_secs < min_secs || default_secs > max_secs {
+        return Err(anyhow::Error::msg(format!(
+            "invalid charge expiry {}s, it should be in [{}s, {}s] and positive",
+            default_secs, min_secs, max_secs
+        )));
+    }
+    EXPIRE_SECS.store(default_secs, Ordering::Relaxed);
+    MIN_EXPIRE_SECS.store(min_secs, Ordering::Relaxed);
+    MAX_EXPIRE_SECS.store(max_secs, Ordering::Relaxed);
+    Ok(())
+}
+
+pub fn max_charge_expire_ms() -> i64 {
+    MAX_EXPIRE_SECS.load(Ordering::Relaxed) * 1000
+}
+
+fn resolve_expire_secs(
+    expire_in: i64,
+    default_secs: i64,
+    min_secs: i64,
+    max_secs: i64,
+) -> Result<i64, HTTPError> {
+    match expire_in {
+        0 => Ok(default_secs),
+        v if v >= min_secs && v <= max_secs => Ok(v),
+        v => Err(HTTPError::new(
+            400,
+            format!(
+                "Invalid expire_in {}, it should be in [{}, {}] seconds",
+                v, min_secs, max_secs
+            ),
+        )),
+    }
+}
+
+// day bucket of charge_by_day, days since unix epoch from the xid timestamp.
+pub fn day_of(id: &xid::Id) -> i32 {
+    let mut secs = [0u8; 4];
+    secs.copy_from_slice(&id.0[..4]);
+    (u32::from_be_bytes(secs) as i64 * 1000 / DAY_MS) as i32
+}
+
+// a transition of the charge status, with the fields required and allowed to be updated by it.
+struct Transition {
+    from: i8,
+    to: i8,
+    required: &'static [&'static str],
+    optional: &'static [&'static str],
+}
+
+const PENDING_FIELDS: &[&str] = &[
+    "currency",
+    "amount",
+    "charge_id",
+    "charge_payload",
+    "provider_fee",
+    "net_amount",
+];
+
+// the state machine of charges:
+//   0 (preparing) -> 1 (prepared) -> 2 (committing) -> 3 (committed) -> -1 (refunded)
+//   0 | 1 -> -2 (failed)
+// a charge is updated without changing its status only while it is preparing or prepared,
+// or partially refunded when committed. refunds are made by Charge::refund only.
+static TRANSITIONS: [Transition; 9] = [
+    Transition {
+        from: 0,
+        to: 0,
+        required: &[],
+        optional: PENDING_FIELDS,
+    },
+    Transition {
+        from: 1,
+        to: 1,
+        required: &[],
+        optional: PENDING_FIELDS,
+    },
+    Transition {
+        from: 0,
+        to: 1,
+        required: &["charge_id", "charge_payload"],
+        optional: &["currency", "amount", "provider_fee", "net_amount"],
+    },
+    Transition {
+        from: 1,
+        to: 2,
+        required: &["currency", "amount", "charge_payload"],
+        optional: &["provider_fee", "net_amount"],
+    },
+    Transition {
+        from: 2,
+        to: 3,
+        required: &["txn"],
+        optional: &[],
+    },
+    Transition {
+        from: 0,
+        to: -2,
+        required: &["failure_code"],
+        optional: &["failure_msg"],
+    },
+    Transition {
+        from: 1,
+        to: -2,
+        required: &["failure_code"],
+        optional: &["failure_msg"],
+    },
+    Transition {
+        from: 3,
+        to: 3,
+        required: &["amount_refunded"],
+        optional: &["txn_refunded"],
+    },
+    Transition {
+        from: 3,
+        to: -1,
+        required: &["amount_refunded"],
+        optional: &["txn_refunded"],
+    },
+];
+
+// checks the transition of the charge status with the updated fields,
+// errors carry the transition and the offending fields in data.
+pub fn check_transition(from: i8, to: i8, fields: &[String]) -> Result<(), HTTPError> {
+    let transition = TRANSITIONS
+        .iter()
+        .find(|t| t.from == from && t.to == to)
+        .ok_or_else(|| {
+            let allowed: Vec<i8> = TRANSITIONS
+                .iter()
+                .filter(|t| t.from == from && t.to != from)
+                .map(|t| t.to)
+                .collect();
+            HTTPError {
+                code: 409,
+                message: format!("Invalid charge transition from {} to {}", from, to),
+                data: Some(serde_json::json!({
+                    "from": from,
+                    "to": to,
+                    "allowed": allowed,
+                })),
+            }
+        })?;
+
+    let fields: Vec<&str> = fields
+        .iter()
+        .map(|f| f.as_str())
+        .filter(|f| *f != "status" && *f != "updated_at")
+        .collect();
+    let missing: Vec<&str> = transition
+        .required
+        .iter()
+        .filter(|f| !fields.contains(f))
+        .copied()
+        .collect();
+    let invalid: Vec<&str> = fields
+        .iter()
+        .filter(|f| !transition.required.contains(f) && !transition.optional.contains(f))
+        .copied()
+        .collect();
+    if missing.is_empty() && invalid.is_empty() && (from != to || !fields.is_empty()) {
+        return Ok(());
+    }
+
+    Err(HTTPError {
+        code: 400,
+        message: format!(
+            "Invalid fields for charge transition from {} to {}, missing {:?}, invalid {:?}",
+            from, to, missing, invalid
+        ),
+        data: Some(serde_json::json!({
+            "from": from,
+            "to": to,
+            "missing": missing,
+            "invalid": invalid,
+        })),
+    })
+}
+
 #[derive(Debug, Default, Clone, CqlOrm)]
 pub struct Charge {
     pub uid: xid::Id,
@@ -19,15 +223,36 @@ pub struct Charge {
     pub provider: String,
     pub charge_id: String,
     pub charge_payload: Vec<u8>,
+    pub charge_payload_ref: Vec<u8>, // hash of the large charge_payload in blob, empty if inline
     pub txn: Option<xid::Id>,
     pub txn_refunded: Option<xid::Id>,
     pub failure_code: String,
     pub failure_msg: String,
+    pub livemode: Option<bool>, // None for charges created before livemode, they are live
+    pub provider_fee: i64,      // fee taken by the provider, in the charge's currency
+    pub net_amount: i64,        // amount minus provider_fee
+    pub reminders: i8,          // number of reminders sent for the pending checkout
+    pub reminded_at: i64,       // unix ms of the last reminder, 0 for never
+    pub external_ref: String,   // caller's unique reference per uid, empty if not given
 
     pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+    pub _expire_in: i64,      // seconds before the charge expires on save, 0 for the default
 }
 
 impl Charge {
+    // preparing or prepared charges are expired after expire_at.
+    pub fn is_expired(&self, now: i64) -> bool {
+        (self.status == 0 || self.status == 1) && self.expire_at > 0 && self.expire_at <= now
+    }
+
+    // marks the expired charge failed for reading, it is not saved.
+    pub fn mark_expired(&mut self, now: i64) {
+        if self.is_expired(now) {
+            self.status = -2;
+            self.failure_msg = "checkout.expired".to_string();
+        }
+    }
+
     pub fn with_pk(uid: xid::Id, id: xid::Id) -> Self {
         Self {
             uid,
@@ -36,55 +261,80 @@ impl Charge {
         }
     }
 
-    pub fn select_fields(select_fields: Vec<String>, with_pk: bool) -> anyhow::Result<Vec<String>> {
-        if select_fields.is_empty() {
-            return Ok(Self::fields());
-        }
+    pub fn is_livemode(&self) -> bool {
+        self.livemode.unwrap_or(true)
+    }
 
-        let fields = Self::fields();
-        for field in &select_fields {
-            if !fields.contains(field) {
-                return Err(HTTPError::new(400, format!("Invalid field: {}", field)).into());
-            }
+    // coins to claw back when the cumulative fiat refund grows to amount_refunded.
+    // it is the difference of the floored proportional quantities, so that the clawbacks of
+    // multiple partial refunds always sum up to the full quantity on a full refund.
+    pub fn refund_quantity(&self, amount_refunded: i64) -> i64 {
+        if self.amount <= 0 {
+            return 0;
         }
+        let floor = |refunded: i64| {
+            (self.quantity as i128 * refunded.clamp(0, self.amount) as i128 / self.amount as i128)
+                as i64
+        };
+        floor(amount_refunded) - floor(self.amount_refunded)
+    }
 
-        let mut select_fields = select_fields;
-        let field = "status".to_string();
-        if !select_fields.contains(&field) {
-            select_fields.push(field);
-        }
-        let field = "quantity".to_string();
-        if !select_fields.contains(&field) {
-            select_fields.push(field);
+    // resolves provider_fee and net_amount against the charged amount, one derives the other.
+    // returns None if neither is given.
+    pub fn resolve_fees(
+        amount: i64,
+        provider_fee: Option<i64>,
+        net_amount: Option<i64>,
+    ) -> anyhow::Result<Option<(i64, i64)>> {
+        let (fee, net) = match (provider_fee, net_amount) {
+            (None, None) => return Ok(None),
+            (Some(fee), None) => (fee, amount - fee),
+            (None, Some(net)) => (amount - net, net),
+            (Some(fee), Some(net)) => (fee, net),
+        };
+
+        if fee < 0 || net < 0 || fee + net != amount {
+            return Err(HTTPError::new(
+                400,
+                format!(
+                    "Invalid provider_fee {} and net_amount {} for amount {}",
+                    fee, net, amount
+                ),
+            )
+            .into());
         }
-        let field = "provider".to_string();
-        if !select_fields.contains(&field) {
-            select_fields.push(field);
+        Ok(Some((fee, net)))
+    }
+
+    pub fn select_fields(select_fields: FieldSet<ChargeField>, with_pk: bool) -> Vec<String> {
+        if select_fields.is_empty() {
+            return Self::fields();
         }
-        let field = "expire_at".to_string();
-        if !select_fields.contains(&field) {
-            select_fields.push(field);
+
+        let mut select_fields = select_fields
+            .with(ChargeField::Status)
+            .with(ChargeField::Quantity)
+            .with(ChargeField::Provider)
+            .with(ChargeField::ExpireAt)
+            .with(ChargeField::Livemode);
+        // the large charge_payload is hydrated from blob.
+        if select_fields.contains(ChargeField::ChargePayload) {
+            select_fields.insert(ChargeField::ChargePayloadRef);
         }
         if with_pk {
-            let field = "uid".to_string();
-            if !select_fields.contains(&field) {
-                select_fields.push(field);
-            }
-            let field = "id".to_string();
-            if !select_fields.contains(&field) {
-                select_fields.push(field);
-            }
+            select_fields.insert(ChargeField::Uid);
+            select_fields.insert(ChargeField::Id);
         }
 
-        Ok(select_fields)
+        select_fields.names()
     }
 
     pub async fn get_one(
         &mut self,
         db: &scylladb::ScyllaDB,
-        select_fields: Vec<String>,
+        select_fields: FieldSet<ChargeField>,
     ) -> anyhow::Result<()> {
-        let fields = Self::select_fields(select_fields, false)?;
+        let fields = Self::select_fields(select_fields, false);
         self._fields = fields.clone();
 
         let query = format!(
@@ -97,7 +347,47 @@ impl Charge {
         let mut cols = ColumnsMap::with_capacity(fields.len());
         cols.fill(res, &fields)?;
         self.fill(&cols);
+        self.hydrate_payload(db).await?;
+
+        Ok(())
+    }
+
+    // loads the charge_payload from blob if it was offloaded and is selected.
+    pub async fn hydrate_payload(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+        if self.charge_payload_ref.is_empty()
+            || !self.charge_payload.is_empty()
+            || !self._fields.iter().any(|f| f == "charge_payload")
+        {
+            return Ok(());
+        }
+
+        let mut blob = Blob {
+            id: self.charge_payload_ref.clone(),
+            ..Default::default()
+        };
+        blob.get_one(db).await?;
+        self.charge_payload = blob.data;
+        Ok(())
+    }
+
+    // stores the large charge_payload in blob, and keeps the reference in the columns.
+    // a small one is kept inline, and clears the reference of the previous one.
+    pub(super) async fn offload_payload(
+        db: &scylladb::ScyllaDB,
+        cols: &mut ColumnsMap,
+    ) -> anyhow::Result<()> {
+        if !cols.has("charge_payload") {
+            return Ok(());
+        }
 
+        let payload: Vec<u8> = cols.get_as("charge_payload")?;
+        match Blob::offload(db, &payload).await? {
+            Some(id) => {
+                cols.set_as("charge_payload", &Vec::<u8>::new());
+                cols.set_as("charge_payload_ref", &id);
+            }
+            None => cols.set_as("charge_payload_ref", &Vec::<u8>::new()),
+        }
         Ok(())
     }
 
@@ -113,9 +403,14 @@ impl Charge {
         let res = extract_applied(res);
         if res {
             self.status = to;
+            let query = "UPDATE charge_by_day SET status=? WHERE day=? AND id=?";
+            let params = (to, day_of(&self.id), self.id.to_cql());
+            if let Err(err) = db.execute(query, params).await {
+                self.log_day_index_error(err);
+            }
         } else {
             // get the current status
-            self.get_one(db, vec!["status".to_string()]).await?;
+            self.get_one(db, [ChargeField::Status].into()).await?;
         }
         Ok(res)
     }
@@ -126,26 +421,22 @@ impl Charge {
         cols: ColumnsMap,
         status: i8,
     ) -> anyhow::Result<bool> {
-        let valid_fields = [
-            "status",
-            "currency",
-            "amount",
-            "amount_refunded",
-            "charge_id",
-            "charge_payload",
-            "txn",
-            "txn_refunded",
-            "failure_code",
-            "failure_msg",
-        ];
         let update_fields = cols.keys();
-        for field in &update_fields {
-            if !valid_fields.contains(&field.as_str()) {
-                return Err(HTTPError::new(400, format!("Invalid field: {}", field)).into());
-            }
+        let to: i8 = if cols.has("status") {
+            cols.get_as("status")?
+        } else {
+            status
+        };
+        check_transition(status, to, &update_fields)?;
+        if cols.has("amount_refunded") || cols.has("txn_refunded") {
+            return Err(HTTPError::new(
+                400,
+                "Refunds should be made by refunding the charge".to_string(),
+            )
+            .into());
         }
 
-        self.get_one(db, vec!["status".to_string()]).await?;
+        self.get_one(db, [ChargeField::Status].into()).await?;
         if self.status != status {
             return Err(HTTPError::new(
                 409,
@@ -157,6 +448,11 @@ impl Charge {
             .into());
         }
 
+        let mut cols = cols;
+        let payload = cols.get_as::<Vec<u8>>("charge_payload").ok();
+        Self::offload_payload(db, &mut cols).await?;
+        let update_fields = cols.keys();
+
         let mut set_fields: Vec<String> = Vec::with_capacity(update_fields.len() + 1);
         let mut params: Vec<CqlValue> = Vec::with_capacity(update_fields.len() + 1 + 3);
 
@@ -185,7 +481,70 @@ impl Charge {
         }
 
         self.fill(&cols); // fill for meilisearch update
+        if let Some(payload) = payload {
+            self.charge_payload = payload;
+        }
+        self.updated_at = new_updated_at;
+        if !cols.has("status") {
+            self.status = status;
+        }
+        self.save_day_index(db).await;
+        Ok(true)
+    }
+
+    // records a (partial) refund of a completed charge, the charge is refunded (status -1)
+    // when amount_refunded reaches amount. returns false if the charge was updated concurrently.
+    pub async fn refund(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        amount_refunded: i64,
+        txn_refunded: Option<xid::Id>,
+    ) -> anyhow::Result<bool> {
+        let status: i8 = if amount_refunded == self.amount {
+            -1
+        } else {
+            3
+        };
+        let mut fields = vec!["amount_refunded".to_string()];
+        if txn_refunded.is_some() {
+            fields.push("txn_refunded".to_string());
+        }
+        check_transition(self.status, status, &fields)?;
+
+        if amount_refunded <= self.amount_refunded || amount_refunded > self.amount {
+            return Err(HTTPError::new(
+                400,
+                format!(
+                    "Invalid amount_refunded {}, refunded {}, charged {}",
+                    amount_refunded, self.amount_refunded, self.amount
+                ),
+            )
+            .into());
+        }
+
+        let txn_refunded = txn_refunded.or(self.txn_refunded);
+        let new_updated_at = unix_ms() as i64;
+        let query = "UPDATE charge SET updated_at=?,status=?,amount_refunded=?,txn_refunded=? WHERE uid=? AND id=? IF status=3 AND amount_refunded=?";
+        let params = (
+            new_updated_at,
+            status,
+            amount_refunded,
+            txn_refunded.to_cql(),
+            self.uid.to_cql(),
+            self.id.to_cql(),
+            self.amount_refunded,
+        );
+
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Ok(false);
+        }
+
         self.updated_at = new_updated_at;
+        self.status = status;
+        self.amount_refunded = amount_refunded;
+        self.txn_refunded = txn_refunded;
+        self.save_day_index(db).await;
         Ok(true)
     }
 
@@ -194,9 +553,18 @@ impl Charge {
             return Err(HTTPError::new(400, format!("Invalid status {}", self.status)).into());
         }
 
-        self.id = xid::new();
+        let expire_secs = resolve_expire_secs(
+            self._expire_in,
+            EXPIRE_SECS.load(Ordering::Relaxed),
+            MIN_EXPIRE_SECS.load(Ordering::Relaxed),
+            MAX_EXPIRE_SECS.load(Ordering::Relaxed),
+        )?;
+        // the id is generated before the save when the external_ref is claimed.
+        if self.id == xid::Id::default() {
+            self.id = xid::new();
+        }
         self.updated_at = unix_ms() as i64;
-        self.expire_at = self.updated_at + 24 * 3600 * 1000;
+        self.expire_at = self.updated_at + expire_secs * 1000;
 
         let fields = Self::fields();
         self._fields = fields.clone();
@@ -204,7 +572,9 @@ impl Charge {
         let mut cols_name: Vec<&str> = Vec::with_capacity(fields.len());
         let mut vals_name: Vec<&str> = Vec::with_capacity(fields.len());
         let mut params: Vec<&CqlValue> = Vec::with_capacity(fields.len());
-        let cols = self.to();
+        let mut cols = self.to();
+        Self::offload_payload(db, &mut cols).await?;
+        self.charge_payload_ref = cols.get_as("charge_payload_ref")?;
 
         for field in &fields {
             cols_name.push(field);
@@ -225,50 +595,589 @@ impl Charge {
             );
         }
 
+        self.save_day_index(db).await;
         Ok(true)
     }
 
+    // charge_by_day is an index for finance, failing to update it should not fail the charge.
+    pub(super) async fn save_day_index(&self, db: &scylladb::ScyllaDB) {
+        let query = "INSERT INTO charge_by_day (day,id,uid,status,updated_at,livemode) VALUES (?,?,?,?,?,?)";
+        let params = (
+            day_of(&self.id),
+            self.id.to_cql(),
+            self.uid.to_cql(),
+            self.status,
+            self.updated_at,
+            self.livemode.to_cql(),
+        );
+        if let Err(err) = db.execute(query, params).await {
+            self.log_day_index_error(err);
+        }
+    }
+
+    // claims the external_ref for the new charge before it is saved, so that a retried
+    // create finds the charge instead of inserting another one.
+    // returns the id of the charge that claimed it before.
+    pub async fn claim_external_ref(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+    ) -> anyhow::Result<Option<xid::Id>> {
+        if self.external_ref.is_empty() || self.external_ref.len() > MAX_EXTERNAL_REF_LEN {
+            return Err(HTTPError::new(
+                400,
+                format!("Invalid external_ref {:?}", self.external_ref),
+            )
+            .into());
+        }
+        if self.id == xid::Id::default() {
+            self.id = xid::new();
+        }
+
+        let query = "INSERT INTO charge_external_ref (uid,external_ref,id,created_at) VALUES (?,?,?,?) IF NOT EXISTS";
+        let params = (
+            self.uid.to_cql(),
+            self.external_ref.to_cql(),
+            self.id.to_cql(),
+            unix_ms() as i64,
+        );
+        if extract_applied(db.execute(query, params).await?) {
+            return Ok(None);
+        }
+
+        let query = "SELECT id FROM charge_external_ref WHERE uid=? AND external_ref=? LIMIT 1";
+        let params = (self.uid.to_cql(), self.external_ref.to_cql());
+        let row = db.execute(query, params).await?.single_row()?;
+        let fields = vec!["id".to_string()];
+        let mut cols = ColumnsMap::with_capacity(1);
+        cols.fill(row, &fields)?;
+        Ok(Some(cols.get_as("id")?))
+    }
+
+    // releases the claimed external_ref when the charge failed to save, a retry claims it again.
+    pub async fn release_external_ref(&self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+        let query = "DELETE FROM charge_external_ref WHERE uid=? AND external_ref=? IF id=?";
+        let params = (
+            self.uid.to_cql(),
+            self.external_ref.to_cql(),
+            self.id.to_cql(),
+        );
+        db.execute(query, params).await?;
+        Ok(())
+    }
+
+    fn log_day_index_error(&self, err: anyhow::Error) {
+        log::error!(target: "scylladb",
+            action = "save_charge_by_day",
+            uid = self.uid.to_string(),
+            id = self.id.to_string();
+            "{}", err,
+        );
+    }
+
+    // lists charges across all users updated in [start, end), newest first.
+    // returns charges with uid, id, status, updated_at and livemode only.
+    pub async fn list_by_day(
+        db: &scylladb::ScyllaDB,
+        start: i64,
+        end: i64,
+        status: Option<i8>,
+        livemode: Option<bool>,
+        page_size: u16,
+        page_token: Option<xid::Id>,
+    ) -> anyhow::Result<Vec<Self>> {
+        if start < 0 || end <= start {
+            return Err(HTTPError::new(400, format!("Invalid range {}..{}", start, end)).into());
+        }
+
+        // a charge may be updated in the day after it was created.
+        let first_day = (start / DAY_MS) as i32 - 1;
+        let last_day = ((end - 1) / DAY_MS) as i32;
+        if last_day - first_day > MAX_DAYS {
+            return Err(
+                HTTPError::new(400, format!("Invalid range, at most {} days", MAX_DAYS)).into(),
+            );
+        }
+
+        let (mut day, mut token) = match page_token {
+            Some(id) => (day_of(&id), id),
+            None => (last_day, MAX_ID),
+        };
+
+        let fields = vec![
+            "uid".to_string(),
+            "id".to_string(),
+            "status".to_string(),
+            "updated_at".to_string(),
+            "livemode".to_string(),
+        ];
+        let query = db.list_query("SELECT uid,id,status,updated_at,livemode FROM charge_by_day WHERE day=? AND id<? LIMIT ?");
+        let mut res: Vec<Self> = Vec::with_capacity(page_size as usize);
+        while day >= first_day && res.len() < page_size as usize {
+            let params = (day, token.to_cql(), page_size as i32);
+            let rows = db.execute_iter(query.as_str(), params).await?;
+            let exhausted = rows.len() < page_size as usize;
+            for row in rows {
+                let mut doc = Self::default();
+                let mut cols = ColumnsMap::with_capacity(fields.len());
+                cols.fill(row, &fields)?;
+                doc.fill(&cols);
+                doc._fields = fields.clone();
+                token = doc.id;
+
+                if doc.updated_at < start || doc.updated_at >= end {
+                    continue;
+                }
+                if status.is_some() && status != Some(doc.status) {
+                    continue;
+                }
+                if livemode.is_some() && livemode != Some(doc.is_livemode()) {
+                    continue;
+                }
+                res.push(doc);
+                if res.len() >= page_size as usize {
+                    break;
+                }
+            }
+
+            if exhausted && res.len() < page_size as usize {
+                day -= 1;
+                token = MAX_ID;
+            }
+        }
+
+        Ok(res)
+    }
+
+    // test charges are filtered out here when livemode is given, so a page may scan more rows.
     pub async fn list(
         db: &scylladb::ScyllaDB,
         uid: xid::Id,
-        select_fields: Vec<String>,
+        select_fields: FieldSet<ChargeField>,
         page_size: u16,
         page_token: Option<xid::Id>,
         status: Option<i8>,
+        livemode: Option<bool>,
     ) -> anyhow::Result<Vec<Self>> {
-        let fields = Self::select_fields(select_fields, true)?;
+        let fields = Self::select_fields(select_fields, true);
 
-        let token = match page_token {
+        let mut token = match page_token {
             Some(id) => id,
             None => MAX_ID,
         };
 
-        let rows = if let Some(status) = status {
-            let query = format!(
-                "SELECT {} FROM charge WHERE uid=? AND status=? AND id<? LIMIT ? USING TIMEOUT 3s",
-                fields.clone().join(",")
+        let mut res: Vec<Self> = Vec::with_capacity(page_size as usize);
+        loop {
+            let rows = if let Some(status) = status {
+                let query = db.list_query(&format!(
+                    "SELECT {} FROM charge WHERE uid=? AND status=? AND id<? LIMIT ?",
+                    fields.clone().join(",")
+                ));
+                let params = (uid.to_cql(), status, token.to_cql(), page_size as i32);
+                db.execute_iter(query, params).await?
+            } else {
+                let query = db.list_query(&format!(
+                    "SELECT {} FROM charge WHERE uid=? AND id<? LIMIT ?",
+                    fields.clone().join(",")
+                ));
+                let params = (uid.to_cql(), token.to_cql(), page_size as i32);
+                db.execute_iter(query, params).await?
+            };
+
+            let exhausted = rows.len() < page_size as usize;
+            for row in rows {
+                let mut doc = Self::default();
+                let mut cols = ColumnsMap::with_capacity(fields.len());
+                cols.fill(row, &fields)?;
+                doc.fill(&cols);
+                doc._fields = fields.clone();
+                token = doc.id;
+
+                if livemode.is_some() && livemode != Some(doc.is_livemode()) {
+                    continue;
+                }
+                doc.hydrate_payload(db).await?;
+                res.push(doc);
+                if res.len() >= page_size as usize {
+                    break;
+                }
+            }
+
+            if exhausted || res.len() >= page_size as usize {
+                break;
+            }
+        }
+
+        Ok(res)
+    }
+
+    // claims the next reminder of a preparing charge, returns false if the charge is no longer
+    // preparing or another job has sent the reminder. it also counts the user's reminders of the day.
+    // charges created before reminders existed have null reminders and are never claimed.
+    pub async fn mark_reminded(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        let reminded_at = unix_ms() as i64;
+        let query = "UPDATE charge SET reminders=?,reminded_at=? WHERE uid=? AND id=? IF status=0 AND reminders=?";
+        let params = (
+            self.reminders + 1,
+            reminded_at,
+            self.uid.to_cql(),
+            self.id.to_cql(),
+            self.reminders,
+        );
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Ok(false);
+        }
+
+        self.reminders += 1;
+        self.reminded_at = reminded_at;
+        let query = "UPDATE charge_reminder_daily SET reminders=reminders+1 WHERE uid=? AND day=?";
+        let params = (self.uid.to_cql(), (reminded_at / DAY_MS) as i32);
+        if let Err(err) = db.execute(query, params).await {
+            log::error!(target: "scylladb",
+                action = "incr_charge_reminder_daily",
+                uid = self.uid.to_string(),
+                id = self.id.to_string();
+                "{}", err,
             );
-            let params = (uid.to_cql(), status, token.to_cql(), page_size as i32);
-            db.execute_iter(query, params).await?
+        }
+        Ok(true)
+    }
+
+    // number of charge reminders sent to the user in the day.
+    pub async fn reminders_of_day(
+        db: &scylladb::ScyllaDB,
+        uid: xid::Id,
+        day: i32,
+    ) -> anyhow::Result<i64> {
+        let query = "SELECT reminders FROM charge_reminder_daily WHERE uid=? AND day=? LIMIT 1";
+        let params = (uid.to_cql(), day);
+        let rows = db.execute_iter(query, params).await?;
+        Ok(rows
+            .first()
+            .map(|row| counter_of(row.columns.first()))
+            .unwrap_or(0))
+    }
+
+    // pending charges are preparing, prepared or committing, expired preparing charges are ignored.
+    pub async fn has_pending(
+        db: &scylladb::ScyllaDB,
+        uid: xid::Id,
+        provider: &str,
+    ) -> anyhow::Result<bool> {
+        let now = unix_ms() as i64;
+        let fields: FieldSet<ChargeField> = [ChargeField::Provider, ChargeField::ExpireAt].into();
+        for status in [0i8, 1, 2] {
+            let mut token: Option<xid::Id> = None;
+            loop {
+                let res = Self::list(db, uid, fields, 1000, token, Some(status), None).await?;
+                if res
+                    .iter()
+                    .any(|doc| doc.provider == provider && (status == 2 || !doc.is_expired(now)))
+                {
+                    return Ok(true);
+                }
+                if res.len() < 1000 {
+                    break;
+                }
+                token = res.last().map(|doc| doc.id);
+            }
+        }
+        Ok(false)
+    }
+}
+
+// completed charges totals per day and currency, for finance.
+// the day is from the charge id, the same as charge_by_day.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ChargeDailyTotal {
+    pub day: i32,
+    pub currency: String,
+    pub amount: i64,
+    pub provider_fee: i64,
+    pub net_amount: i64,
+    pub charges: i64,
+}
+
+impl ChargeDailyTotal {
+    // counter updates are not idempotent, it should be called once after the charge completed.
+    pub async fn incr(db: &scylladb::ScyllaDB, doc: &Charge) -> anyhow::Result<()> {
+        if doc.amount <= 0 || doc.currency.is_empty() {
+            return Ok(());
+        }
+
+        // net_amount is zero for charges completed without provider fees.
+        let net_amount = if doc.provider_fee == 0 && doc.net_amount == 0 {
+            doc.amount
         } else {
-            let query = format!(
-                "SELECT {} FROM charge WHERE uid=? AND id<? LIMIT ? USING TIMEOUT 3s",
-                fields.clone().join(",")
-            );
-            let params = (uid.to_cql(), token.to_cql(), page_size as i32);
-            db.execute_iter(query, params).await?
+            doc.net_amount
         };
+        let query = "UPDATE charge_daily_total SET amount=amount+?,provider_fee=provider_fee+?,net_amount=net_amount+?,charges=charges+1 WHERE day=? AND currency=?";
+        let params = (
+            doc.amount,
+            doc.provider_fee,
+            net_amount,
+            day_of(&doc.id),
+            doc.currency.to_owned(),
+        );
+        db.execute(query, params).await?;
+        Ok(())
+    }
 
-        let mut res: Vec<Self> = Vec::with_capacity(rows.len());
-        for row in rows {
-            let mut doc = Self::default();
-            let mut cols = ColumnsMap::with_capacity(fields.len());
-            cols.fill(row, &fields)?;
-            doc.fill(&cols);
-            doc._fields = fields.clone();
-            res.push(doc);
+    // lists totals in days [first_day, last_day], newest day first.
+    pub async fn list(
+        db: &scylladb::ScyllaDB,
+        first_day: i32,
+        last_day: i32,
+    ) -> anyhow::Result<Vec<Self>> {
+        let query = db.list_query(
+            "SELECT currency,amount,provider_fee,net_amount,charges FROM charge_daily_total WHERE day=?",
+        );
+        let mut res: Vec<Self> = Vec::new();
+        let mut day = last_day;
+        while day >= first_day {
+            let rows = db.execute_iter(query.as_str(), (day,)).await?;
+            for row in rows {
+                let currency = match row.columns.first() {
+                    Some(Some(v)) => v.as_text().cloned().unwrap_or_default(),
+                    _ => continue,
+                };
+                res.push(Self {
+                    day,
+                    currency,
+                    amount: counter_of(row.columns.get(1)),
+                    provider_fee: counter_of(row.columns.get(2)),
+                    net_amount: counter_of(row.columns.get(3)),
+                    charges: counter_of(row.columns.get(4)),
+                });
+            }
+            day -= 1;
         }
-
         Ok(res)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_expire_secs_works() {
+        assert_eq!(86400, resolve_expire_secs(0, 86400, 300, 604800).unwrap());
+        assert_eq!(300, resolve_expire_secs(300, 86400, 300, 604800).unwrap());
+        assert_eq!(
+            604800,
+            resolve_expire_secs(604800, 86400, 300, 604800).unwrap()
+        );
+        assert_eq!(
+            400,
+            resolve_expire_secs(299, 86400, 300, 604800)
+                .unwrap_err()
+                .code
+        );
+        assert_eq!(
+            400,
+            resolve_expire_secs(604801, 86400, 300, 604800)
+                .unwrap_err()
+                .code
+        );
+        assert_eq!(
+            400,
+            resolve_expire_secs(-1, 86400, 300, 604800)
+                .unwrap_err()
+                .code
+        );
+
+        assert!(set_charge_expiry(86400, 0, 604800).is_err());
+        assert!(set_charge_expiry(60, 300, 604800).is_err());
+        assert!(set_charge_expiry(86400 * 8, 300, 604800).is_err());
+    }
+
+    #[test]
+    fn charge_is_expired_works() {
+        let now = 1_700_000_000_000i64;
+        let mut doc = Charge {
+            status: 1,
+            expire_at: now,
+            ..Default::default()
+        };
+        assert!(doc.is_expired(now));
+        assert!(!doc.is_expired(now - 1));
+        doc.mark_expired(now - 1);
+        assert_eq!(1, doc.status);
+        doc.mark_expired(now);
+        assert_eq!(-2, doc.status);
+        assert_eq!("checkout.expired", doc.failure_msg);
+
+        let doc = Charge {
+            status: 2,
+            expire_at: now,
+            ..Default::default()
+        };
+        assert!(!doc.is_expired(now));
+        let doc = Charge {
+            status: 0,
+            ..Default::default()
+        };
+        assert!(!doc.is_expired(now));
+    }
+
+    #[test]
+    fn day_of_works() {
+        let mut id = xid::Id([0u8; 12]);
+        assert_eq!(0, day_of(&id));
+
+        // 2023-06-01T00:00:00Z
+        id.0[..4].copy_from_slice(&1685577600u32.to_be_bytes());
+        assert_eq!(19509, day_of(&id));
+        id.0[..4].copy_from_slice(&(1685577600u32 + 86399).to_be_bytes());
+        assert_eq!(19509, day_of(&id));
+        id.0[..4].copy_from_slice(&(1685577600u32 + 86400).to_be_bytes());
+        assert_eq!(19510, day_of(&id));
+    }
+
+    #[test]
+    fn check_transition_works() {
+        let fields = |v: &[&str]| -> Vec<String> { v.iter().map(|f| f.to_string()).collect() };
+
+        assert!(
+            check_transition(0, 1, &fields(&["status", "charge_id", "charge_payload"])).is_ok()
+        );
+        assert!(check_transition(0, 0, &fields(&["charge_id"])).is_ok());
+        assert!(check_transition(1, 1, &fields(&["provider_fee", "net_amount"])).is_ok());
+        assert!(check_transition(
+            1,
+            2,
+            &fields(&["status", "currency", "amount", "charge_payload"])
+        )
+        .is_ok());
+        assert!(check_transition(2, 3, &fields(&["status", "txn"])).is_ok());
+        assert!(check_transition(0, -2, &fields(&["status", "failure_code"])).is_ok());
+        assert!(check_transition(1, -2, &fields(&["failure_code", "failure_msg"])).is_ok());
+        assert!(check_transition(3, -1, &fields(&["amount_refunded"])).is_ok());
+        assert!(check_transition(3, 3, &fields(&["amount_refunded", "txn_refunded"])).is_ok());
+
+        // rewinds and skips.
+        for (from, to) in [
+            (1, 0),
+            (2, 1),
+            (3, 2),
+            (-2, 0),
+            (-1, 3),
+            (0, 2),
+            (0, 3),
+            (2, -2),
+        ] {
+            let err = check_transition(from, to, &fields(&["txn"])).unwrap_err();
+            assert_eq!(409, err.code, "{} -> {}", from, to);
+        }
+        let err = check_transition(1, 0, &[]).unwrap_err();
+        assert_eq!(
+            serde_json::json!({"from": 1, "to": 0, "allowed": [2, -2]}),
+            err.data.unwrap()
+        );
+
+        // required and invalid fields.
+        let err = check_transition(0, 1, &fields(&["status", "charge_id"])).unwrap_err();
+        assert_eq!(400, err.code);
+        assert_eq!(
+            serde_json::json!({"from": 0, "to": 1, "missing": ["charge_payload"], "invalid": []}),
+            err.data.unwrap()
+        );
+        let err = check_transition(0, 0, &fields(&["failure_code"])).unwrap_err();
+        assert_eq!(
+            serde_json::json!({"from": 0, "to": 0, "missing": [], "invalid": ["failure_code"]}),
+            err.data.unwrap()
+        );
+        assert_eq!(400, check_transition(2, 3, &[]).unwrap_err().code);
+        assert_eq!(400, check_transition(1, 1, &[]).unwrap_err().code);
+        assert_eq!(
+            409,
+            check_transition(2, 2, &fields(&["amount"]))
+                .unwrap_err()
+                .code
+        );
+    }
+
+    #[test]
+    fn resolve_fees_works() {
+        assert_eq!(None, Charge::resolve_fees(1000, None, None).unwrap());
+        assert_eq!(
+            Some((30, 970)),
+            Charge::resolve_fees(1000, Some(30), None).unwrap()
+        );
+        assert_eq!(
+            Some((30, 970)),
+            Charge::resolve_fees(1000, None, Some(970)).unwrap()
+        );
+        assert_eq!(
+            Some((30, 970)),
+            Charge::resolve_fees(1000, Some(30), Some(970)).unwrap()
+        );
+        assert_eq!(
+            Some((0, 1000)),
+            Charge::resolve_fees(1000, Some(0), None).unwrap()
+        );
+
+        assert!(Charge::resolve_fees(1000, Some(30), Some(960)).is_err());
+        assert!(Charge::resolve_fees(1000, Some(1001), None).is_err());
+        assert!(Charge::resolve_fees(1000, None, Some(1001)).is_err());
+        assert!(Charge::resolve_fees(1000, Some(-1), None).is_err());
+    }
+
+    #[test]
+    fn select_fields_works() {
+        assert_eq!(
+            Charge::fields(),
+            Charge::select_fields(FieldSet::new(), false)
+        );
+
+        let fields = Charge::select_fields([ChargeField::Amount].into(), false);
+        assert!(fields.contains(&"amount".to_string()));
+        assert!(fields.contains(&"status".to_string()));
+        assert!(!fields.contains(&"uid".to_string()));
+        assert!(!fields.contains(&"charge_payload_ref".to_string()));
+
+        let fields = Charge::select_fields([ChargeField::ChargePayload].into(), true);
+        assert!(fields.contains(&"charge_payload_ref".to_string()));
+        assert!(fields.contains(&"uid".to_string()));
+        assert_eq!(
+            "payload",
+            FieldSet::<ChargeField>::parse(&["amount", "payload"]).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn is_livemode_works() {
+        let mut doc = Charge::default();
+        assert!(doc.is_livemode());
+        doc.livemode = Some(false);
+        assert!(!doc.is_livemode());
+        doc.livemode = Some(true);
+        assert!(doc.is_livemode());
+    }
+
+    #[test]
+    fn refund_quantity_works() {
+        let mut doc = Charge {
+            quantity: 1000,
+            amount: 300,
+            ..Default::default()
+        };
+        assert_eq!(0, doc.refund_quantity(0));
+        assert_eq!(3, doc.refund_quantity(1));
+        assert_eq!(333, doc.refund_quantity(100));
+        assert_eq!(1000, doc.refund_quantity(300));
+        assert_eq!(1000, doc.refund_quantity(301));
+
+        // partial refunds sum up to the full quantity
+        let mut total = 0;
+        for refunded in [1, 100, 101, 299, 300] {
+            total += doc.refund_quantity(refunded);
+            doc.amount_refunded = refunded;
+        }
+        assert_eq!(1000, total);
+
+        doc.amount = 0;
+        assert_eq!(0, doc.refund_quantity(100));
+    }
+}
@@ -4,10 +4,10 @@ use std::str::FromStr;
 use strum_macros::{AsRefStr, EnumString};
 
 use axum_web::erring::HTTPError;
-use scylla_orm::{ColumnsMap, CqlValue, ToCqlVal};
+use scylla_orm::{ColumnsMap, CqlValue, FieldSet, ToCqlVal};
 use scylla_orm_macros::CqlOrm;
 
-use super::{Wallet, MAX_ID, SYS_ID};
+use super::{retry_lwt, Wallet, MAX_ID, SYS_ID};
 use crate::db::scylladb::{self, extract_applied};
 
 #[derive(AsRefStr, Debug, EnumString, PartialEq)]
@@ -24,6 +24,15 @@ impl ToString for CreditKind {
     }
 }
 
+// order of credits by txn, newest first by default.
+#[derive(AsRefStr, Debug, Default, Clone, Copy, EnumString, PartialEq)]
+#[strum(serialize_all = "lowercase")]
+pub enum CreditOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
 #[derive(Debug, Default, Clone, CqlOrm)]
 pub struct Credit {
     pub uid: xid::Id,
@@ -44,43 +53,26 @@ impl Credit {
         }
     }
 
-    pub fn select_fields(select_fields: Vec<String>, with_pk: bool) -> anyhow::Result<Vec<String>> {
+    pub fn select_fields(select_fields: FieldSet<CreditField>, with_pk: bool) -> Vec<String> {
         if select_fields.is_empty() {
-            return Ok(Self::fields());
+            return Self::fields();
         }
 
-        let fields = Self::fields();
-        for field in &select_fields {
-            if !fields.contains(field) {
-                return Err(HTTPError::new(400, format!("Invalid field: {}", field)).into());
-            }
-        }
-
-        let mut select_fields = select_fields;
-        let field = "kind".to_string();
-        if !select_fields.contains(&field) {
-            select_fields.push(field);
-        }
+        let mut select_fields = select_fields.with(CreditField::Kind);
         if with_pk {
-            let field = "uid".to_string();
-            if !select_fields.contains(&field) {
-                select_fields.push(field);
-            }
-            let field = "txn".to_string();
-            if !select_fields.contains(&field) {
-                select_fields.push(field);
-            }
+            select_fields.insert(CreditField::Uid);
+            select_fields.insert(CreditField::Txn);
         }
 
-        Ok(select_fields)
+        select_fields.names()
     }
 
     pub async fn get_one(
         &mut self,
         db: &scylladb::ScyllaDB,
-        select_fields: Vec<String>,
+        select_fields: FieldSet<CreditField>,
     ) -> anyhow::Result<()> {
-        let fields = Self::select_fields(select_fields, false)?;
+        let fields = Self::select_fields(select_fields, false);
         self._fields = fields.clone();
 
         let query = format!(
@@ -137,7 +129,8 @@ impl Credit {
         let res = db.execute(insert_query, insert_params).await?;
         if extract_applied(res) {
             let query = "UPDATE wallet SET credits=? WHERE uid=? IF credits=?";
-            for _ in 0..5 {
+            let mut retry = retry_lwt("add_credit");
+            while retry.next().await {
                 wallet.get_one(db).await?;
                 let params = (
                     self.amount + wallet.credits,
@@ -208,34 +201,41 @@ impl Credit {
     pub async fn list(
         db: &scylladb::ScyllaDB,
         uid: xid::Id,
-        select_fields: Vec<String>,
+        select_fields: FieldSet<CreditField>,
         page_size: u16,
         page_token: Option<xid::Id>,
         kind: Option<CreditKind>,
+        order: CreditOrder,
     ) -> anyhow::Result<Vec<Self>> {
-        let fields = Self::select_fields(select_fields, true)?;
-
-        let token = match page_token {
-            Some(id) => id,
-            None => MAX_ID,
+        let fields = Self::select_fields(select_fields, true);
+
+        // pages after the token in the order, the clustering order is txn DESC.
+        let (token, cond) = match (page_token, order) {
+            (Some(id), CreditOrder::Asc) => (id, "txn>? ORDER BY txn ASC"),
+            (None, CreditOrder::Asc) => (xid::Id::default(), "txn>? ORDER BY txn ASC"),
+            (Some(id), CreditOrder::Desc) => (id, "txn<?"),
+            (None, CreditOrder::Desc) => (MAX_ID, "txn<?"),
         };
 
         let rows = if let Some(kind) = kind {
-            let query = format!(
-                    "SELECT {} FROM credit WHERE uid=? AND kind=? AND txn<? LIMIT ? ALLOW FILTERING USING TIMEOUT 3s",
-                    fields.clone().join(","));
+            let query = db.list_query(&format!(
+                "SELECT {} FROM credit WHERE uid=? AND kind=? AND {} LIMIT ? ALLOW FILTERING",
+                fields.clone().join(","),
+                cond
+            ));
             let params = (
                 uid.to_cql(),
-                token.to_cql(),
                 kind.to_string(),
+                token.to_cql(),
                 page_size as i32,
             );
             db.execute_iter(query, params).await?
         } else {
-            let query = format!(
-                "SELECT {} FROM credit WHERE uid=? AND txn<? LIMIT ? USING TIMEOUT 3s",
-                fields.clone().join(",")
-            );
+            let query = db.list_query(&format!(
+                "SELECT {} FROM credit WHERE uid=? AND {} LIMIT ?",
+                fields.clone().join(","),
+                cond
+            ));
             let params = (uid.to_cql(), token.to_cql(), page_size as i32);
             db.execute_iter(query, params).await?
         };
@@ -252,6 +252,33 @@ impl Credit {
 
         Ok(res)
     }
+
+    // returns (kind, total amount, count) of the user's credits per kind, in the order of CreditKind.
+    pub async fn summary(
+        db: &scylladb::ScyllaDB,
+        uid: xid::Id,
+    ) -> anyhow::Result<Vec<(String, i64, i64)>> {
+        let mut res: Vec<(String, i64, i64)> =
+            [CreditKind::Award, CreditKind::Payout, CreditKind::Income]
+                .iter()
+                .map(|k| (k.to_string(), 0, 0))
+                .collect();
+
+        let fields = vec!["kind".to_string(), "amount".to_string()];
+        let query = db.list_query("SELECT kind,amount FROM credit WHERE uid=?");
+        let rows = db.execute_iter(query, (uid.to_cql(),)).await?;
+        for row in rows {
+            let mut doc = Self::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            if let Some(v) = res.iter_mut().find(|v| v.0 == doc.kind) {
+                v.1 += doc.amount;
+                v.2 += 1;
+            }
+        }
+        Ok(res)
+    }
 }
 
 #[cfg(test)]
@@ -277,6 +304,13 @@ mod tests {
             assert_eq!(CreditKind::Payout, CreditKind::from_str("payout").unwrap());
             assert_eq!(CreditKind::Income, CreditKind::from_str("income").unwrap());
         }
+
+        {
+            assert_eq!(CreditOrder::Desc, CreditOrder::default());
+            assert_eq!(CreditOrder::Asc, CreditOrder::from_str("asc").unwrap());
+            assert_eq!(CreditOrder::Desc, CreditOrder::from_str("desc").unwrap());
+            assert!(CreditOrder::from_str("ASC").is_err());
+        }
     }
 
     #[tokio::test(flavor = "current_thread")]
@@ -293,17 +327,17 @@ mod tests {
         assert!(res.unwrap_err().to_string().contains("Invalid amount 0"));
         credit.amount = 10;
         credit.save(&db).await.unwrap();
-        assert!(credit.get_one(&db, vec![]).await.is_err());
+        assert!(credit.get_one(&db, FieldSet::new()).await.is_err());
 
         let mut credit = Credit::with_pk(wallet.uid, xid::new());
         credit.amount = 10;
         credit.kind = CreditKind::Payout.to_string();
         credit.save(&db).await.unwrap();
-        assert!(credit.get_one(&db, vec![]).await.is_err());
+        assert!(credit.get_one(&db, FieldSet::new()).await.is_err());
 
         credit.kind = CreditKind::Award.to_string();
         credit.save(&db).await.unwrap();
-        credit.get_one(&db, vec![]).await.unwrap();
+        credit.get_one(&db, FieldSet::new()).await.unwrap();
         wallet.get_one(&db).await.unwrap();
         assert_eq!(10, wallet.credits);
 
@@ -323,13 +357,50 @@ mod tests {
         wallet.get_one(&db).await.unwrap();
         assert_eq!(110, wallet.credits);
 
-        let logs = Credit::list(&db, wallet.uid, vec![], 10, None, None)
-            .await
-            .unwrap();
+        let logs = Credit::list(
+            &db,
+            wallet.uid,
+            FieldSet::new(),
+            10,
+            None,
+            None,
+            CreditOrder::Desc,
+        )
+        .await
+        .unwrap();
         assert_eq!(2, logs.len());
         assert_eq!(CreditKind::Payout.to_string(), logs[0].kind);
         assert_eq!(100i64, logs[0].amount);
         assert_eq!(CreditKind::Award.to_string(), logs[1].kind);
         assert_eq!(10i64, logs[1].amount);
+
+        let logs = Credit::list(
+            &db,
+            wallet.uid,
+            FieldSet::new(),
+            10,
+            None,
+            None,
+            CreditOrder::Asc,
+        )
+        .await
+        .unwrap();
+        assert_eq!(2, logs.len());
+        assert_eq!(CreditKind::Award.to_string(), logs[0].kind);
+        assert_eq!(CreditKind::Payout.to_string(), logs[1].kind);
+
+        let logs = Credit::list(
+            &db,
+            wallet.uid,
+            FieldSet::new(),
+            10,
+            Some(logs[0].txn),
+            None,
+            CreditOrder::Asc,
+        )
+        .await
+        .unwrap();
+        assert_eq!(1, logs.len());
+        assert_eq!(100i64, logs[0].amount);
     }
 }
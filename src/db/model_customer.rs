@@ -1,5 +1,5 @@
 use axum_web::{context::unix_ms, erring::HTTPError};
-use scylla_orm::{ColumnsMap, CqlValue, ToCqlVal};
+use scylla_orm::{ColumnsMap, CqlValue, FieldSet, ToCqlVal};
 use scylla_orm_macros::CqlOrm;
 use std::collections::HashSet;
 
@@ -14,6 +14,7 @@ pub struct Customer {
     pub customer: String,
     pub payload: Vec<u8>,
     pub customers: HashSet<String>,
+    pub livemode: Option<bool>, // None for customers created before livemode, they are live
 
     pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
 }
@@ -27,43 +28,32 @@ impl Customer {
         }
     }
 
-    pub fn select_fields(select_fields: Vec<String>, with_pk: bool) -> anyhow::Result<Vec<String>> {
-        if select_fields.is_empty() {
-            return Ok(Self::fields());
-        }
+    pub fn is_livemode(&self) -> bool {
+        self.livemode.unwrap_or(true)
+    }
 
-        let fields = Self::fields();
-        for field in &select_fields {
-            if !fields.contains(field) {
-                return Err(HTTPError::new(400, format!("Invalid field: {}", field)).into());
-            }
+    pub fn select_fields(select_fields: FieldSet<CustomerField>, with_pk: bool) -> Vec<String> {
+        if select_fields.is_empty() {
+            return Self::fields();
         }
 
-        let mut select_fields = select_fields;
-        let field = "customer".to_string();
-        if !select_fields.contains(&field) {
-            select_fields.push(field);
-        }
+        let mut select_fields = select_fields
+            .with(CustomerField::Customer)
+            .with(CustomerField::Livemode);
         if with_pk {
-            let field = "uid".to_string();
-            if !select_fields.contains(&field) {
-                select_fields.push(field);
-            }
-            let field = "provider".to_string();
-            if !select_fields.contains(&field) {
-                select_fields.push(field);
-            }
+            select_fields.insert(CustomerField::Uid);
+            select_fields.insert(CustomerField::Provider);
         }
 
-        Ok(select_fields)
+        select_fields.names()
     }
 
     pub async fn get_one(
         &mut self,
         db: &scylladb::ScyllaDB,
-        select_fields: Vec<String>,
+        select_fields: FieldSet<CustomerField>,
     ) -> anyhow::Result<()> {
-        let fields = Self::select_fields(select_fields, false)?;
+        let fields = Self::select_fields(select_fields, false);
         self._fields = fields.clone();
 
         let query = format!(
@@ -80,14 +70,41 @@ impl Customer {
         Ok(())
     }
 
+    // lists the customers of the user across providers, a scan within the uid partition.
+    pub async fn list_by_uid(
+        db: &scylladb::ScyllaDB,
+        uid: xid::Id,
+        select_fields: FieldSet<CustomerField>,
+    ) -> anyhow::Result<Vec<Self>> {
+        let fields = Self::select_fields(select_fields, true);
+        let query = db.list_query(&format!(
+            "SELECT {} FROM customer WHERE uid=?",
+            fields.join(",")
+        ));
+        let params = (uid.to_cql(),);
+        let rows = db.execute_iter(query, params).await?;
+
+        let mut res: Vec<Self> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Self::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+        Ok(res)
+    }
+
     pub async fn upsert(
         &mut self,
         db: &scylladb::ScyllaDB,
         customer: String,
         payload: Vec<u8>,
+        livemode: bool,
     ) -> anyhow::Result<bool> {
         if self
-            .get_one(db, vec!["customer".to_string()])
+            .get_one(db, [CustomerField::Customer].into())
             .await
             .is_err()
         {
@@ -95,6 +112,7 @@ impl Customer {
             self.updated_at = self.created_at;
             self.customer = customer.clone();
             self.payload = payload.clone();
+            self.livemode = Some(livemode);
 
             let fields = Self::fields();
             self._fields = fields.clone();
@@ -118,23 +136,27 @@ impl Customer {
 
             let res = db.execute(query, params).await?;
             if extract_applied(res) {
+                Self::save_index(db, &self.provider, &customer, self.uid).await?;
                 return Ok(true);
             }
 
             // data exists, we try to update it
-            self.get_one(db, vec!["customer".to_string()]).await?;
+            self.get_one(db, [CustomerField::Customer].into()).await?;
         }
 
         if self.customer == customer {
+            // customers created before the index existed are indexed on upsert.
+            Self::save_index(db, &self.provider, &customer, self.uid).await?;
             return Ok(false);
         }
 
         let new_updated_at = unix_ms() as i64;
-        let query = "UPDATE customer SET updated_at=?,customer=?,payload=?,customers=customers+{?} WHERE uid=? AND provider=? IF customer=?";
+        let query = "UPDATE customer SET updated_at=?,customer=?,payload=?,livemode=?,customers=customers+{?} WHERE uid=? AND provider=? IF customer=?";
         let params = (
             new_updated_at,
             customer.to_cql(),
             payload.to_cql(),
+            livemode,
             self.customer.to_cql(),
             self.uid.to_cql(),
             self.provider.to_cql(),
@@ -150,8 +172,118 @@ impl Customer {
             .into());
         }
 
+        // the old customer id is kept in the history set, it still resolves to the user.
+        Self::save_index(db, &self.provider, &customer, self.uid).await?;
+        Self::save_index(db, &self.provider, &self.customer, self.uid).await?;
+
         self._fields.push("updated_at".to_string());
         self.updated_at = new_updated_at;
+        self.livemode = Some(livemode);
+        Ok(true)
+    }
+
+    // resolves the user by the provider's customer id, e.g. for provider webhooks.
+    pub async fn find_uid(
+        db: &scylladb::ScyllaDB,
+        provider: &str,
+        customer: &str,
+    ) -> anyhow::Result<xid::Id> {
+        let query = "SELECT uid FROM customer_index WHERE provider=? AND customer=? LIMIT 1";
+        let params = (provider.to_cql(), customer.to_cql());
+        let rows = db.execute_iter(query, params).await?;
+        match rows.into_iter().next() {
+            Some(row) => {
+                let mut doc = Self::default();
+                let mut cols = ColumnsMap::with_capacity(1);
+                cols.fill(row, &vec!["uid".to_string()])?;
+                doc.fill(&cols);
+                Ok(doc.uid)
+            }
+            None => Err(HTTPError::new(
+                404,
+                format!("{} customer {} not found", provider, customer),
+            )
+            .into()),
+        }
+    }
+
+    // a customer id belongs to one user, it is moved if another user upserts it.
+    pub(super) async fn save_index(
+        db: &scylladb::ScyllaDB,
+        provider: &str,
+        customer: &str,
+        uid: xid::Id,
+    ) -> anyhow::Result<()> {
+        if customer.is_empty() {
+            return Ok(());
+        }
+
+        let query = "INSERT INTO customer_index (provider,customer,uid) VALUES (?,?,?)";
+        let params = (provider.to_cql(), customer.to_cql(), uid.to_cql());
+        let _ = db.execute(query, params).await?;
+        Ok(())
+    }
+
+    // removes the index only if it still belongs to the user.
+    async fn delete_index(
+        db: &scylladb::ScyllaDB,
+        provider: &str,
+        customer: &str,
+        uid: xid::Id,
+    ) -> anyhow::Result<()> {
+        let query = "DELETE FROM customer_index WHERE provider=? AND customer=? IF uid=?";
+        let params = (provider.to_cql(), customer.to_cql(), uid.to_cql());
+        let _ = db.execute(query, params).await?;
+        Ok(())
+    }
+
+    // archives the customer into customer_deleted for audit, then removes the live row.
+    pub async fn delete(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        self.get_one(db, FieldSet::new()).await?;
+
+        let fields = Self::fields();
+        let mut cols_name: Vec<&str> = Vec::with_capacity(fields.len() + 1);
+        let mut vals_name: Vec<&str> = Vec::with_capacity(fields.len() + 1);
+        let mut params: Vec<&CqlValue> = Vec::with_capacity(fields.len() + 1);
+        let cols = self.to();
+
+        for field in &fields {
+            cols_name.push(field);
+            vals_name.push("?");
+            params.push(cols.get(field).unwrap());
+        }
+        let deleted_at = CqlValue::BigInt(unix_ms() as i64);
+        cols_name.push("deleted_at");
+        vals_name.push("?");
+        params.push(&deleted_at);
+
+        let query = format!(
+            "INSERT INTO customer_deleted ({}) VALUES ({})",
+            cols_name.join(","),
+            vals_name.join(",")
+        );
+        db.execute(query, params).await?;
+
+        // the customer may be updated after archived.
+        let query = "DELETE FROM customer WHERE uid=? AND provider=? IF customer=?";
+        let params = (
+            self.uid.to_cql(),
+            self.provider.to_cql(),
+            self.customer.to_cql(),
+        );
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Err(HTTPError::new(
+                409,
+                "Customer delete failed, please try again".to_string(),
+            )
+            .into());
+        }
+
+        Self::delete_index(db, &self.provider, &self.customer, self.uid).await?;
+        for customer in &self.customers {
+            Self::delete_index(db, &self.provider, customer, self.uid).await?;
+        }
         Ok(true)
     }
 }
@@ -176,23 +308,28 @@ mod tests {
         let provider = "stripe".to_string();
 
         let mut customer = Customer::with_pk(uid, provider.clone());
-        let res = customer.get_one(&db, vec![]).await;
+        let res = customer.get_one(&db, FieldSet::new()).await;
         assert!(res.is_err());
         let err: HTTPError = res.unwrap_err().into();
         assert_eq!(err.code, 404);
 
         let res = customer
-            .upsert(&db, "cus_123".to_string(), vec![0xa0])
+            .upsert(&db, "cus_123".to_string(), vec![0xa0], true)
             .await
             .unwrap();
         assert!(res);
 
-        customer.get_one(&db, vec![]).await.unwrap();
+        customer.get_one(&db, FieldSet::new()).await.unwrap();
         assert!(customer.created_at > 0);
         assert_eq!(customer.created_at, customer.updated_at);
         assert_eq!(customer.customer, "cus_123");
         assert_eq!(customer.payload, vec![0xa0]);
         assert_eq!(customer.customers.len(), 0);
+        assert!(customer.is_livemode());
+        assert_eq!(
+            uid,
+            Customer::find_uid(&db, "stripe", "cus_123").await.unwrap()
+        );
 
         let mut c2 = Customer::with_pk(uid, provider);
 
@@ -201,16 +338,39 @@ mod tests {
                 &db,
                 "cus_456".to_string(),
                 vec![0xa2, 0x01, 0x02, 0x03, 0x04],
+                false,
             )
             .await
             .unwrap();
         assert!(res);
 
-        c2.get_one(&db, vec![]).await.unwrap();
+        c2.get_one(&db, FieldSet::new()).await.unwrap();
         assert!(c2.updated_at > customer.updated_at);
         assert_eq!(c2.customer, "cus_456");
         assert_eq!(c2.payload, vec![0xa2, 0x01, 0x02, 0x03, 0x04]);
         assert_eq!(c2.customers.len(), 1);
         assert!(c2.customers.contains("cus_123"));
+        assert_eq!(c2.livemode, Some(false));
+        assert_eq!(
+            uid,
+            Customer::find_uid(&db, "stripe", "cus_456").await.unwrap()
+        );
+        assert_eq!(
+            uid,
+            Customer::find_uid(&db, "stripe", "cus_123").await.unwrap()
+        );
+
+        assert!(c2.delete(&db).await.unwrap());
+        let err: HTTPError = Customer::find_uid(&db, "stripe", "cus_456")
+            .await
+            .unwrap_err()
+            .into();
+        assert_eq!(err.code, 404);
+        assert!(Customer::find_uid(&db, "stripe", "cus_123").await.is_err());
+        let res = c2.get_one(&db, FieldSet::new()).await;
+        let err: HTTPError = res.unwrap_err().into();
+        assert_eq!(err.code, 404);
+        let err: HTTPError = c2.delete(&db).await.unwrap_err().into();
+        assert_eq!(err.code, 404);
     }
 }
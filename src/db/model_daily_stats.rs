@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+
+use super::{day_of, model_transaction::counter_of, Charge, Transaction};
+use crate::db::scylladb;
+
+// completed charges per day of a provider and currency, a partition is a time series.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DailyChargeStats {
+    pub provider: String,
+    pub currency: String,
+    pub day: i32,
+    pub charges: i64,
+    pub amount: i64,
+    pub quantity: i64,
+    pub provider_fee: i64,
+}
+
+impl DailyChargeStats {
+    // counter updates are not idempotent, it should be called once after the charge completed.
+    pub async fn incr(db: &scylladb::ScyllaDB, doc: &Charge) -> anyhow::Result<()> {
+        if doc.amount <= 0 || doc.provider.is_empty() || doc.currency.is_empty() {
+            return Ok(());
+        }
+
+        let query = "UPDATE daily_charge_stats SET charges=charges+1,amount=amount+?,quantity=quantity+?,provider_fee=provider_fee+? WHERE provider=? AND currency=? AND day=?";
+        let params = (
+            doc.amount,
+            doc.quantity,
+            doc.provider_fee,
+            doc.provider.to_owned(),
+            doc.currency.to_owned(),
+            day_of(&doc.id),
+        );
+        db.execute(query, params).await?;
+        Ok(())
+    }
+
+    // lists the series in days [first_day, last_day], oldest day first, days without
+    // charges are filled with zeros.
+    pub async fn list(
+        db: &scylladb::ScyllaDB,
+        provider: &str,
+        currency: &str,
+        first_day: i32,
+        last_day: i32,
+    ) -> anyhow::Result<Vec<Self>> {
+        let query = db.list_query(
+            "SELECT day,charges,amount,quantity,provider_fee FROM daily_charge_stats WHERE provider=? AND currency=? AND day>=? AND day<=?",
+        );
+        let params = (
+            provider.to_owned(),
+            currency.to_owned(),
+            first_day,
+            last_day,
+        );
+        let rows = db.execute_iter(query.as_str(), params).await?;
+        let mut found: HashMap<i32, Self> = HashMap::with_capacity(rows.len());
+        for row in rows {
+            let day = match row.columns.first() {
+                Some(Some(v)) => v.as_int().unwrap_or_default(),
+                _ => continue,
+            };
+            found.insert(
+                day,
+                Self {
+                    provider: provider.to_string(),
+                    currency: currency.to_string(),
+                    day,
+                    charges: counter_of(row.columns.get(1)),
+                    amount: counter_of(row.columns.get(2)),
+                    quantity: counter_of(row.columns.get(3)),
+                    provider_fee: counter_of(row.columns.get(4)),
+                },
+            );
+        }
+
+        Ok(fill_days(first_day, last_day, found, |day| Self {
+            provider: provider.to_string(),
+            currency: currency.to_string(),
+            day,
+            ..Default::default()
+        }))
+    }
+}
+
+// committed transactions per day of a kind, a partition is a time series.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DailyTxnStats {
+    pub kind: String,
+    pub day: i32,
+    pub txns: i64,
+    pub amount: i64,
+    pub sys_fee: i64,
+    pub sub_shares: i64,
+}
+
+impl DailyTxnStats {
+    // counter updates are not idempotent, it should be called once after the transaction committed.
+    pub async fn incr(db: &scylladb::ScyllaDB, txn: &Transaction) -> anyhow::Result<()> {
+        let query = "UPDATE daily_txn_stats SET txns=txns+1,amount=amount+?,sys_fee=sys_fee+?,sub_shares=sub_shares+? WHERE kind=? AND day=?";
+        let params = (
+            txn.amount,
+            txn.sys_fee,
+            txn.sub_shares,
+            txn.kind.to_owned(),
+            day_of(&txn.id),
+        );
+        db.execute(query, params).await?;
+        Ok(())
+    }
+
+    // lists the series in days [first_day, last_day], oldest day first, days without
+    // transactions are filled with zeros.
+    pub async fn list(
+        db: &scylladb::ScyllaDB,
+        kind: &str,
+        first_day: i32,
+        last_day: i32,
+    ) -> anyhow::Result<Vec<Self>> {
+        let query = db.list_query(
+            "SELECT day,txns,amount,sys_fee,sub_shares FROM daily_txn_stats WHERE kind=? AND day>=? AND day<=?",
+        );
+        let params = (kind.to_owned(), first_day, last_day);
+        let rows = db.execute_iter(query.as_str(), params).await?;
+        let mut found: HashMap<i32, Self> = HashMap::with_capacity(rows.len());
+        for row in rows {
+            let day = match row.columns.first() {
+                Some(Some(v)) => v.as_int().unwrap_or_default(),
+                _ => continue,
+            };
+            found.insert(
+                day,
+                Self {
+                    kind: kind.to_string(),
+                    day,
+                    txns: counter_of(row.columns.get(1)),
+                    amount: counter_of(row.columns.get(2)),
+                    sys_fee: counter_of(row.columns.get(3)),
+                    sub_shares: counter_of(row.columns.get(4)),
+                },
+            );
+        }
+
+        Ok(fill_days(first_day, last_day, found, |day| Self {
+            kind: kind.to_string(),
+            day,
+            ..Default::default()
+        }))
+    }
+}
+
+fn fill_days<T>(
+    first_day: i32,
+    last_day: i32,
+    mut found: HashMap<i32, T>,
+    zero: impl Fn(i32) -> T,
+) -> Vec<T> {
+    (first_day..=last_day)
+        .map(|day| found.remove(&day).unwrap_or_else(|| zero(day)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fill_days_works() {
+        let mut found: HashMap<i32, DailyTxnStats> = HashMap::new();
+        found.insert(
+            11,
+            DailyTxnStats {
+                kind: "sponsor".to_string(),
+                day: 11,
+                txns: 2,
+                amount: 300,
+                ..Default::default()
+            },
+        );
+        // out of the range
+        found.insert(
+            20,
+            DailyTxnStats {
+                day: 20,
+                txns: 1,
+                ..Default::default()
+            },
+        );
+
+        let res = fill_days(10, 12, found, |day| DailyTxnStats {
+            kind: "sponsor".to_string(),
+            day,
+            ..Default::default()
+        });
+        assert_eq!(
+            vec![10, 11, 12],
+            res.iter().map(|v| v.day).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![0, 2, 0],
+            res.iter().map(|v| v.txns).collect::<Vec<_>>()
+        );
+        assert_eq!(300, res[1].amount);
+        assert!(res.iter().all(|v| v.kind == "sponsor"));
+
+        assert!(fill_days(12, 10, HashMap::new(), |day| DailyTxnStats {
+            day,
+            ..Default::default()
+        })
+        .is_empty());
+    }
+}
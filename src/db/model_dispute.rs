@@ -0,0 +1,368 @@
+use axum_web::{context::unix_ms, erring::HTTPError};
+use scylla_orm::{ColumnsMap, CqlValue, FieldSet, ToCqlVal};
+use scylla_orm_macros::CqlOrm;
+
+use crate::db::{
+    scylladb::{self, extract_applied},
+    MAX_ID,
+};
+
+// committed transactions older than this can not be disputed.
+pub const MAX_DISPUTE_AGE_MS: i64 = 90 * 24 * 3600 * 1000;
+
+// all open disputes are in one partition of dispute_queue, it should be small.
+const QUEUE_BUCKET: i8 = 0;
+
+// a payer's dispute of a committed transaction.
+// the payee's income of the transaction is frozen by a hold until the dispute is resolved,
+// by refunding the payer with chargeback transactions, or by releasing the hold.
+#[derive(Debug, Default, Clone, CqlOrm)]
+pub struct Dispute {
+    pub id: xid::Id,  // disputed transaction id
+    pub uid: xid::Id, // payer of the disputed transaction
+    pub payee: xid::Id,
+    pub kind: String,
+    pub amount: i64, // refunded to the payer if the dispute is accepted
+    pub frozen: i64, // payee's income frozen by the hold, 0 if the payee is the system
+    pub hold: xid::Id,
+    pub reason: String,
+    pub resolver: String,
+    pub note: String,
+    pub status: i8, // -1: released, 0: open, 2: refunding, 1: refunded
+    pub refund_txn: xid::Id,
+    pub clawback_txn: xid::Id,
+    pub created_at: i64,
+    pub updated_at: i64,
+
+    pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+}
+
+impl Dispute {
+    pub fn with_pk(id: xid::Id) -> Self {
+        Self {
+            id,
+            ..Default::default()
+        }
+    }
+
+    pub fn select_fields(select_fields: FieldSet<DisputeField>, with_pk: bool) -> Vec<String> {
+        if select_fields.is_empty() {
+            return Self::fields();
+        }
+
+        let mut select_fields = select_fields
+            .with(DisputeField::Uid)
+            .with(DisputeField::Status);
+        if with_pk {
+            select_fields.insert(DisputeField::Id);
+        }
+
+        select_fields.names()
+    }
+
+    pub async fn get_one(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        select_fields: FieldSet<DisputeField>,
+    ) -> anyhow::Result<()> {
+        let fields = Self::select_fields(select_fields, false);
+        self._fields = fields.clone();
+
+        let query = format!(
+            "SELECT {} FROM dispute WHERE id=? LIMIT 1",
+            fields.join(",")
+        );
+        let params = (self.id.to_cql(),);
+        let res = db.execute(query, params).await?.single_row()?;
+
+        let mut cols = ColumnsMap::with_capacity(fields.len());
+        cols.fill(res, &fields)?;
+        self.fill(&cols);
+
+        Ok(())
+    }
+
+    // a transaction is disputed at most once.
+    pub async fn save(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        if self.amount <= 0 || self.frozen < 0 || self.frozen > self.amount {
+            return Err(HTTPError::new(
+                400,
+                format!("Invalid amount {}, frozen {}", self.amount, self.frozen),
+            )
+            .into());
+        }
+
+        self.status = 0;
+        self.resolver = "".to_string();
+        self.note = "".to_string();
+        self.refund_txn = xid::Id::default();
+        self.clawback_txn = xid::Id::default();
+        self.created_at = unix_ms() as i64;
+        self.updated_at = self.created_at;
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let mut cols_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut vals_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut params: Vec<&CqlValue> = Vec::with_capacity(fields.len());
+        let cols = self.to();
+
+        for field in &fields {
+            cols_name.push(field);
+            vals_name.push("?");
+            params.push(cols.get(field).unwrap());
+        }
+
+        let query = format!(
+            "INSERT INTO dispute ({}) VALUES ({}) IF NOT EXISTS",
+            cols_name.join(","),
+            vals_name.join(",")
+        );
+
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Err(
+                HTTPError::new(409, format!("Transaction {} has been disputed", self.id)).into(),
+            );
+        }
+
+        let query =
+            "INSERT INTO dispute_queue (bucket,id,uid,amount,created_at) VALUES (?,?,?,?,?)";
+        let params = (
+            QUEUE_BUCKET,
+            self.id.to_cql(),
+            self.uid.to_cql(),
+            self.amount,
+            self.created_at,
+        );
+        db.execute(query, params).await?;
+        Ok(true)
+    }
+
+    // status: 2 refunding, -1 released, from the open status.
+    pub async fn decide(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        resolver: String,
+        status: i8,
+        note: String,
+    ) -> anyhow::Result<()> {
+        if status != 2 && status != -1 {
+            return Err(HTTPError::new(400, format!("Invalid status {}", status)).into());
+        }
+        if resolver.is_empty() {
+            return Err(HTTPError::new(400, "resolver is required".to_string()).into());
+        }
+
+        let updated_at = unix_ms() as i64;
+        let query =
+            "UPDATE dispute SET resolver=?,note=?,status=?,updated_at=? WHERE id=? IF status=0";
+        let params = (
+            resolver.to_cql(),
+            note.to_cql(),
+            status,
+            updated_at,
+            self.id.to_cql(),
+        );
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Err(
+                HTTPError::new(409, format!("Dispute {} has been resolved", self.id)).into(),
+            );
+        }
+
+        self.resolver = resolver;
+        self.note = note;
+        self.status = status;
+        self.updated_at = updated_at;
+
+        // the resolved dispute is still in dispute, removing it from the queue is best effort.
+        let query = "DELETE FROM dispute_queue WHERE bucket=? AND id=?";
+        let params = (QUEUE_BUCKET, self.id.to_cql());
+        if let Err(err) = db.execute(query, params).await {
+            log::error!(target: "scylladb",
+                action = "delete_dispute_queue",
+                uid = self.uid.to_string(),
+                id = self.id.to_string();
+                "{}", err,
+            );
+        }
+        Ok(())
+    }
+
+    // records the chargeback transaction of the refunding dispute before it is committed,
+    // field is refund_txn or clawback_txn.
+    // returns false if another one has been recorded, the dispute is reloaded then.
+    pub async fn set_txn(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        field: &str,
+        txn: xid::Id,
+    ) -> anyhow::Result<bool> {
+        if field != "refund_txn" && field != "clawback_txn" {
+            return Err(HTTPError::new(400, format!("Invalid field {}", field)).into());
+        }
+
+        let updated_at = unix_ms() as i64;
+        let query = format!(
+            "UPDATE dispute SET {}=?,updated_at=? WHERE id=? IF status=2 AND {}=?",
+            field, field
+        );
+        let params = (
+            txn.to_cql(),
+            updated_at,
+            self.id.to_cql(),
+            xid::Id::default().to_cql(),
+        );
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            self.get_one(db, FieldSet::new()).await?;
+            return Ok(false);
+        }
+
+        if field == "refund_txn" {
+            self.refund_txn = txn;
+        } else {
+            self.clawback_txn = txn;
+        }
+        self.updated_at = updated_at;
+        Ok(true)
+    }
+
+    // the refunding dispute is refunded.
+    pub async fn refunded(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+        let updated_at = unix_ms() as i64;
+        let query = "UPDATE dispute SET status=1,updated_at=? WHERE id=? IF status IN (1,2)";
+        let params = (updated_at, self.id.to_cql());
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Err(
+                HTTPError::new(409, format!("Dispute {} is not refunding", self.id)).into(),
+            );
+        }
+
+        self.status = 1;
+        self.updated_at = updated_at;
+        Ok(())
+    }
+
+    // lists open disputes, oldest first.
+    // returns disputes with id, uid, amount and created_at only.
+    pub async fn list_pending(
+        db: &scylladb::ScyllaDB,
+        page_size: u16,
+        page_token: Option<xid::Id>,
+    ) -> anyhow::Result<Vec<Self>> {
+        let fields = vec![
+            "id".to_string(),
+            "uid".to_string(),
+            "amount".to_string(),
+            "created_at".to_string(),
+        ];
+
+        let rows = match page_token {
+            Some(id) => {
+                let query = db.list_query("SELECT id,uid,amount,created_at FROM dispute_queue WHERE bucket=? AND id>? LIMIT ?");
+                let params = (QUEUE_BUCKET, id.to_cql(), page_size as i32);
+                db.execute_iter(query, params).await?
+            }
+            None => {
+                let query = db.list_query("SELECT id,uid,amount,created_at FROM dispute_queue WHERE bucket=? AND id<? LIMIT ?");
+                let params = (QUEUE_BUCKET, MAX_ID.to_cql(), page_size as i32);
+                db.execute_iter(query, params).await?
+            }
+        };
+
+        let mut res: Vec<Self> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Self::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conf;
+
+    use super::*;
+
+    async fn get_db() -> scylladb::ScyllaDB {
+        let cfg = conf::Conf::new().unwrap_or_else(|err| panic!("config error: {}", err));
+        let res = scylladb::ScyllaDB::new(cfg.scylla, "walletbase_test").await;
+        res.unwrap()
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn dispute_model_works() {
+        let db = get_db().await;
+
+        let mut doc = Dispute {
+            id: xid::new(),
+            uid: xid::new(),
+            payee: xid::new(),
+            kind: "sponsor".to_string(),
+            amount: 100,
+            frozen: 101,
+            ..Default::default()
+        };
+        assert!(doc.save(&db).await.is_err());
+
+        doc.frozen = 90;
+        doc.save(&db).await.unwrap();
+        assert!(doc.save(&db).await.is_err());
+
+        let pending = Dispute::list_pending(&db, 1000, None).await.unwrap();
+        assert!(pending.iter().any(|v| v.id == doc.id));
+
+        let mut doc2 = Dispute::with_pk(doc.id);
+        doc2.get_one(&db, FieldSet::new()).await.unwrap();
+        assert_eq!(100, doc2.amount);
+        assert_eq!(90, doc2.frozen);
+        assert_eq!(0, doc2.status);
+
+        // chargebacks are recorded only when refunding.
+        let txn = xid::new();
+        assert!(!doc2.set_txn(&db, "refund_txn", txn).await.unwrap());
+        assert!(doc2.set_txn(&db, "status", txn).await.is_err());
+        assert!(doc2.refunded(&db).await.is_err());
+
+        assert!(doc2
+            .decide(&db, "".to_string(), 2, "".to_string())
+            .await
+            .is_err());
+        assert!(doc2
+            .decide(&db, "bob".to_string(), 1, "".to_string())
+            .await
+            .is_err());
+        doc2.decide(&db, "bob".to_string(), 2, "unauthorized".to_string())
+            .await
+            .unwrap();
+        assert!(doc2
+            .decide(&db, "carol".to_string(), -1, "".to_string())
+            .await
+            .is_err());
+
+        let pending = Dispute::list_pending(&db, 1000, None).await.unwrap();
+        assert!(!pending.iter().any(|v| v.id == doc.id));
+
+        assert!(doc2.set_txn(&db, "refund_txn", txn).await.unwrap());
+        assert!(!doc2.set_txn(&db, "refund_txn", xid::new()).await.unwrap());
+        assert_eq!(txn, doc2.refund_txn);
+        doc2.refunded(&db).await.unwrap();
+        doc2.refunded(&db).await.unwrap();
+
+        doc.get_one(&db, FieldSet::new()).await.unwrap();
+        assert_eq!(1, doc.status);
+        assert_eq!("bob", doc.resolver);
+        assert_eq!("unauthorized", doc.note);
+        assert_eq!(txn, doc.refund_txn);
+    }
+}
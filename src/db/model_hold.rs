@@ -0,0 +1,254 @@
+use axum_web::{context::unix_ms, erring::HTTPError};
+use scylla_orm::{ColumnsMap, CqlValue, ToCqlVal};
+use scylla_orm_macros::CqlOrm;
+
+use crate::db::scylladb::{self, extract_applied};
+
+pub const MAX_HOLD_TTL_SECS: i64 = 3600;
+
+// soft reservation of a wallet's funds, e.g. during checkout.
+// a hold does not change the wallet, it only reduces the available balance in transaction checks.
+// the row is written with TTL, so that it is released automatically on expiry.
+#[derive(Debug, Default, Clone, CqlOrm)]
+pub struct WalletHold {
+    pub uid: xid::Id,
+    pub id: xid::Id,
+    pub amount: i64,
+    pub description: String,
+    pub created_at: i64,
+    pub expire_at: i64,
+
+    pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+}
+
+impl WalletHold {
+    pub fn with_pk(uid: xid::Id, id: xid::Id) -> Self {
+        Self {
+            uid,
+            id,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_expired(&self, now_ms: i64) -> bool {
+        self.expire_at <= now_ms
+    }
+
+    // frozen holds are released by their owner, e.g. a dispute, not by the wallet APIs.
+    pub fn is_frozen(&self) -> bool {
+        self.expire_at == i64::MAX
+    }
+
+    pub async fn get_one(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let query = format!(
+            "SELECT {} FROM wallet_hold WHERE uid=? AND id=? LIMIT 1",
+            fields.join(",")
+        );
+        let params = (self.uid.to_cql(), self.id.to_cql());
+        let res = db.execute(query, params).await?.single_row()?;
+
+        let mut cols = ColumnsMap::with_capacity(fields.len());
+        cols.fill(res, &fields)?;
+        self.fill(&cols);
+
+        if self.is_expired(unix_ms() as i64) {
+            return Err(HTTPError::new(404, format!("Hold {} expired", self.id)).into());
+        }
+        Ok(())
+    }
+
+    pub async fn save(&mut self, db: &scylladb::ScyllaDB, ttl_secs: i64) -> anyhow::Result<()> {
+        if self.amount <= 0 {
+            return Err(HTTPError::new(400, format!("Invalid amount {}", self.amount)).into());
+        }
+        if ttl_secs <= 0 || ttl_secs > MAX_HOLD_TTL_SECS {
+            return Err(HTTPError::new(400, format!("Invalid ttl {}", ttl_secs)).into());
+        }
+
+        self.id = xid::new();
+        self.created_at = unix_ms() as i64;
+        self.expire_at = self.created_at + ttl_secs * 1000;
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let mut cols_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut vals_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut params: Vec<&CqlValue> = Vec::with_capacity(fields.len() + 1);
+        let cols = self.to();
+
+        for field in &fields {
+            cols_name.push(field);
+            vals_name.push("?");
+            params.push(cols.get(field).unwrap());
+        }
+
+        let ttl = CqlValue::Int(ttl_secs as i32);
+        params.push(&ttl);
+        let query = format!(
+            "INSERT INTO wallet_hold ({}) VALUES ({}) IF NOT EXISTS USING TTL ?",
+            cols_name.join(","),
+            vals_name.join(",")
+        );
+
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Err(HTTPError::new(409, format!("Hold {} already exists", self.id)).into());
+        }
+        Ok(())
+    }
+
+    // saves a hold without TTL, e.g. the payee's income frozen by a dispute.
+    // it never expires, and should be released explicitly.
+    pub async fn freeze(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+        if self.amount <= 0 {
+            return Err(HTTPError::new(400, format!("Invalid amount {}", self.amount)).into());
+        }
+
+        self.id = xid::new();
+        self.created_at = unix_ms() as i64;
+        self.expire_at = i64::MAX;
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let mut cols_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut vals_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut params: Vec<&CqlValue> = Vec::with_capacity(fields.len());
+        let cols = self.to();
+
+        for field in &fields {
+            cols_name.push(field);
+            vals_name.push("?");
+            params.push(cols.get(field).unwrap());
+        }
+
+        let query = format!(
+            "INSERT INTO wallet_hold ({}) VALUES ({}) IF NOT EXISTS",
+            cols_name.join(","),
+            vals_name.join(",")
+        );
+
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Err(HTTPError::new(409, format!("Hold {} already exists", self.id)).into());
+        }
+        Ok(())
+    }
+
+    // returns false if the hold was released, captured or expired.
+    pub async fn release(&self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        let query = "DELETE FROM wallet_hold WHERE uid=? AND id=? IF EXISTS";
+        let params = (self.uid.to_cql(), self.id.to_cql());
+        let res = db.execute(query, params).await?;
+        Ok(extract_applied(res))
+    }
+
+    // total amount of the wallet's active holds, the excluded hold is not counted.
+    pub async fn held_amount(
+        db: &scylladb::ScyllaDB,
+        uid: xid::Id,
+        exclude: Option<xid::Id>,
+    ) -> anyhow::Result<i64> {
+        let now = unix_ms() as i64;
+        let fields = vec![
+            "id".to_string(),
+            "amount".to_string(),
+            "expire_at".to_string(),
+        ];
+        let query = db.list_query("SELECT id,amount,expire_at FROM wallet_hold WHERE uid=?");
+        let params = (uid.to_cql(),);
+        let rows = db.execute_iter(query, params).await?;
+
+        let mut held: i64 = 0;
+        for row in rows {
+            let mut doc = Self::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            if Some(doc.id) != exclude && !doc.is_expired(now) {
+                held += doc.amount;
+            }
+        }
+        Ok(held)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conf;
+
+    use super::*;
+
+    async fn get_db() -> scylladb::ScyllaDB {
+        let cfg = conf::Conf::new().unwrap_or_else(|err| panic!("config error: {}", err));
+        let res = scylladb::ScyllaDB::new(cfg.scylla, "walletbase_test").await;
+        res.unwrap()
+    }
+
+    #[test]
+    fn is_expired_works() {
+        let doc = WalletHold {
+            expire_at: 1000,
+            ..Default::default()
+        };
+        assert!(!doc.is_expired(999));
+        assert!(doc.is_expired(1000));
+        assert!(doc.is_expired(1001));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn wallet_hold_model_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+
+        let mut doc = WalletHold::with_pk(uid, xid::new());
+        assert!(doc.get_one(&db).await.is_err());
+        assert!(doc.save(&db, 60).await.is_err());
+
+        doc.amount = 100;
+        assert!(doc.save(&db, 0).await.is_err());
+        assert!(doc.save(&db, MAX_HOLD_TTL_SECS + 1).await.is_err());
+        doc.save(&db, 60).await.unwrap();
+
+        let mut doc2 = WalletHold {
+            uid,
+            amount: 50,
+            ..Default::default()
+        };
+        doc2.save(&db, 60).await.unwrap();
+
+        assert_eq!(150, WalletHold::held_amount(&db, uid, None).await.unwrap());
+        assert_eq!(
+            50,
+            WalletHold::held_amount(&db, uid, Some(doc.id))
+                .await
+                .unwrap()
+        );
+
+        let mut doc3 = WalletHold::with_pk(uid, doc.id);
+        doc3.get_one(&db).await.unwrap();
+        assert_eq!(100, doc3.amount);
+
+        assert!(doc.release(&db).await.unwrap());
+        assert!(!doc.release(&db).await.unwrap());
+        assert!(doc3.get_one(&db).await.is_err());
+        assert_eq!(50, WalletHold::held_amount(&db, uid, None).await.unwrap());
+
+        let mut doc4 = WalletHold {
+            uid,
+            amount: 30,
+            ..Default::default()
+        };
+        doc4.freeze(&db).await.unwrap();
+        assert!(doc4.is_frozen());
+        assert_eq!(80, WalletHold::held_amount(&db, uid, None).await.unwrap());
+        let mut doc5 = WalletHold::with_pk(uid, doc4.id);
+        doc5.get_one(&db).await.unwrap();
+        assert!(doc5.is_frozen());
+        assert!(doc4.release(&db).await.unwrap());
+        assert_eq!(50, WalletHold::held_amount(&db, uid, None).await.unwrap());
+    }
+}
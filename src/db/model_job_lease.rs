@@ -0,0 +1,152 @@
+use axum_web::context::unix_ms;
+use scylla_orm::{ColumnsMap, ToCqlVal};
+use scylla_orm_macros::CqlOrm;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+};
+
+use crate::db::scylladb::{self, extract_applied};
+
+static LEASE_ENABLED: AtomicBool = AtomicBool::new(true);
+static LEASE_OWNER: Mutex<String> = Mutex::new(String::new());
+
+// scheduled jobs of every process hold a lease before a run when enabled, so that each
+// job runs in one worker process at a time. The owner is the process's instance id.
+pub fn set_job_lease(enabled: bool) {
+    LEASE_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+fn lease_owner() -> String {
+    let mut owner = LEASE_OWNER.lock().unwrap();
+    if owner.is_empty() {
+        *owner = xid::new().to_string();
+    }
+    owner.clone()
+}
+
+// the lease of a scheduled job in the keyspace, it expires by the TTL unless renewed by
+// the owner's next run.
+#[derive(Debug, Default, Clone, CqlOrm, PartialEq)]
+pub struct JobLease {
+    pub job: String,
+    pub owner: String,
+    pub acquired_at: i64, // acquired or renewed at, unix ms
+
+    pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+}
+
+impl JobLease {
+    pub fn with_pk(job: &str) -> Self {
+        Self {
+            job: job.to_string(),
+            ..Default::default()
+        }
+    }
+
+    // returns true if the job should run now in this process. The lease outlives two
+    // missed runs, then another process takes it over. Errors are logged and skip the run.
+    pub async fn hold(db: &scylladb::ScyllaDB, job: &str, interval_secs: u64) -> bool {
+        if !LEASE_ENABLED.load(Ordering::Relaxed) {
+            return true;
+        }
+
+        let ttl = interval_secs.saturating_mul(3).clamp(30, i32::MAX as u64) as i32;
+        let mut lease = Self::with_pk(job);
+        lease.owner = lease_owner();
+        match lease.acquire(db, ttl).await {
+            Ok(held) => held,
+            Err(err) => {
+                log::error!(target: "job_lease",
+                    job = job;
+                    "acquire lease failed: {}", err,
+                );
+                false
+            }
+        }
+    }
+
+    // renews the lease if owned, or acquires it if free.
+    pub async fn acquire(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        ttl_secs: i32,
+    ) -> anyhow::Result<bool> {
+        self.acquired_at = unix_ms() as i64;
+
+        let query = "UPDATE job_lease USING TTL ? SET owner=?,acquired_at=? WHERE job=? IF owner=?";
+        let params = (
+            ttl_secs,
+            self.owner.to_cql(),
+            self.acquired_at,
+            self.job.to_cql(),
+            self.owner.to_cql(),
+        );
+        let res = db.execute(query, params).await?;
+        if extract_applied(res) {
+            return Ok(true);
+        }
+
+        let query = "INSERT INTO job_lease (job,owner,acquired_at) VALUES (?,?,?) IF NOT EXISTS USING TTL ?";
+        let params = (
+            self.job.to_cql(),
+            self.owner.to_cql(),
+            self.acquired_at,
+            ttl_secs,
+        );
+        let res = db.execute(query, params).await?;
+        Ok(extract_applied(res))
+    }
+
+    pub async fn get_one(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let query = format!(
+            "SELECT {} FROM job_lease WHERE job=? LIMIT 1",
+            fields.join(",")
+        );
+        let params = (self.job.to_cql(),);
+        let res = db.execute(query, params).await?.single_row()?;
+
+        let mut cols = ColumnsMap::with_capacity(fields.len());
+        cols.fill(res, &fields)?;
+        self.fill(&cols);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conf;
+
+    use super::*;
+
+    async fn get_db() -> scylladb::ScyllaDB {
+        let cfg = conf::Conf::new().unwrap_or_else(|err| panic!("config error: {}", err));
+        let res = scylladb::ScyllaDB::new(cfg.scylla, "walletbase_test").await;
+        res.unwrap()
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn job_lease_model_works() {
+        let db = get_db().await;
+        let job = format!("test_{}", xid::new());
+
+        let mut lease = JobLease::with_pk(&job);
+        lease.owner = "a".to_string();
+        assert!(lease.acquire(&db, 30).await.unwrap());
+        // renewed by the owner.
+        assert!(lease.acquire(&db, 30).await.unwrap());
+
+        let mut other = JobLease::with_pk(&job);
+        other.owner = "b".to_string();
+        assert!(!other.acquire(&db, 30).await.unwrap());
+
+        let mut doc = JobLease::with_pk(&job);
+        doc.get_one(&db).await.unwrap();
+        assert_eq!("a", doc.owner);
+        assert_eq!(lease.acquired_at, doc.acquired_at);
+    }
+}
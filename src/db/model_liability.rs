@@ -0,0 +1,167 @@
+use axum_web::context::unix_ms;
+
+use super::model_transaction::counter_of;
+use crate::db::{scylladb, Balance};
+
+// balance buckets of user wallets, the system wallet is not a liability.
+pub const LIABILITY_BUCKETS: [&str; 3] = ["award", "topup", "income"];
+
+// the total owed to users per balance bucket, maintained on each committed balance delta,
+// with the last reconciliation against the sum of wallets.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Liability {
+    pub bucket: String,
+    pub amount: i64,        // maintained total
+    pub expected: i64,      // sum of wallets at the last reconciliation
+    pub drift: i64,         // maintained total minus expected at the last reconciliation, corrected
+    pub reconciled_at: i64, // unix ms, 0 if never reconciled
+}
+
+fn amount_of(balance: &Balance, bucket: &str) -> i64 {
+    match bucket {
+        "award" => balance.award,
+        "topup" => balance.topup,
+        "income" => balance.income,
+        _ => 0,
+    }
+}
+
+fn set_amount(balance: &mut Balance, bucket: &str, amount: i64) {
+    match bucket {
+        "award" => balance.award = amount,
+        "topup" => balance.topup = amount,
+        "income" => balance.income = amount,
+        _ => {}
+    }
+}
+
+// balances of the wallet after minus before, per bucket.
+pub fn balance_delta(before: &Balance, after: &Balance) -> Balance {
+    Balance {
+        award: after.award - before.award,
+        topup: after.topup - before.topup,
+        income: after.income - before.income,
+    }
+}
+
+impl Liability {
+    // counter updates are not idempotent, it should be called once after the balances updated.
+    pub async fn incr(db: &scylladb::ScyllaDB, delta: &Balance) -> anyhow::Result<()> {
+        let query = "UPDATE liability_total SET amount=amount+? WHERE bucket=?";
+        for bucket in LIABILITY_BUCKETS {
+            let amount = amount_of(delta, bucket);
+            if amount != 0 {
+                db.execute(query, (amount, bucket.to_string())).await?;
+            }
+        }
+        Ok(())
+    }
+
+    // lists the totals of all buckets in LIABILITY_BUCKETS order.
+    pub async fn list(db: &scylladb::ScyllaDB) -> anyhow::Result<Vec<Self>> {
+        let mut res: Vec<Self> = LIABILITY_BUCKETS
+            .iter()
+            .map(|bucket| Self {
+                bucket: bucket.to_string(),
+                ..Default::default()
+            })
+            .collect();
+
+        let rows = db
+            .execute_iter("SELECT bucket,amount FROM liability_total", ())
+            .await?;
+        for row in rows {
+            let bucket = match row.columns.first() {
+                Some(Some(v)) => v.as_text().cloned().unwrap_or_default(),
+                _ => continue,
+            };
+            if let Some(doc) = res.iter_mut().find(|doc| doc.bucket == bucket) {
+                doc.amount = counter_of(row.columns.get(1));
+            }
+        }
+
+        let rows = db
+            .execute_iter(
+                "SELECT bucket,expected,drift,reconciled_at FROM liability_reconcile",
+                (),
+            )
+            .await?;
+        for row in rows {
+            let bucket = match row.columns.first() {
+                Some(Some(v)) => v.as_text().cloned().unwrap_or_default(),
+                _ => continue,
+            };
+            if let Some(doc) = res.iter_mut().find(|doc| doc.bucket == bucket) {
+                let int_of = |i: usize| match row.columns.get(i) {
+                    Some(Some(v)) => v.as_bigint().unwrap_or(0),
+                    _ => 0,
+                };
+                doc.expected = int_of(1);
+                doc.drift = int_of(2);
+                doc.reconciled_at = int_of(3);
+            }
+        }
+        Ok(res)
+    }
+
+    // corrects the maintained totals to the expected sum of wallets and records the drift.
+    // balances committed during the scan of wallets are seen as drift, they are corrected
+    // back by the next reconciliation.
+    pub async fn reconcile(
+        db: &scylladb::ScyllaDB,
+        expected: &Balance,
+    ) -> anyhow::Result<Vec<Self>> {
+        let mut res = Self::list(db).await?;
+        let reconciled_at = unix_ms() as i64;
+        let query = "INSERT INTO liability_reconcile (bucket,expected,drift,reconciled_at) VALUES (?,?,?,?)";
+        let mut maintained = Balance::default();
+        for doc in &mut res {
+            set_amount(&mut maintained, &doc.bucket, doc.amount);
+            doc.expected = amount_of(expected, &doc.bucket);
+            doc.drift = doc.amount - doc.expected;
+            doc.reconciled_at = reconciled_at;
+            db.execute(
+                query,
+                (doc.bucket.clone(), doc.expected, doc.drift, reconciled_at),
+            )
+            .await?;
+        }
+
+        Self::incr(db, &balance_delta(&maintained, expected)).await?;
+        for doc in &mut res {
+            doc.amount = doc.expected;
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn balance_delta_works() {
+        let before = Balance {
+            award: 100,
+            topup: 50,
+            income: 0,
+        };
+        let after = Balance {
+            award: 80,
+            topup: 50,
+            income: 10,
+        };
+        let delta = balance_delta(&before, &after);
+        assert_eq!(-20, amount_of(&delta, "award"));
+        assert_eq!(0, amount_of(&delta, "topup"));
+        assert_eq!(10, amount_of(&delta, "income"));
+        assert_eq!(0, amount_of(&delta, "unknown"));
+        assert_eq!(Balance::default(), balance_delta(&after, &after));
+
+        let mut maintained = Balance::default();
+        for bucket in LIABILITY_BUCKETS {
+            set_amount(&mut maintained, bucket, amount_of(&after, bucket));
+        }
+        assert_eq!(after, maintained);
+    }
+}
@@ -0,0 +1,222 @@
+use axum_web::context::unix_ms;
+use scylla_orm::{ColumnsMap, CqlValue, ToCqlVal};
+use scylla_orm_macros::CqlOrm;
+use std::collections::{HashMap, HashSet};
+
+use super::{Transaction, TransactionKind, DAY_MS};
+use crate::db::scylladb::{self, extract_applied};
+
+// max payers kept in a summary.
+pub const MAX_TOP_PAYERS: usize = 20;
+
+// weeks since unix epoch, weeks start on Monday (1970-01-01 is a Thursday).
+pub fn week_of(ms: i64) -> i32 {
+    (ms.div_euclid(DAY_MS) + 3).div_euclid(7) as i32
+}
+
+// unix ms of the Monday the week starts.
+pub fn week_start_ms(week: i32) -> i64 {
+    (week as i64 * 7 - 3) * DAY_MS
+}
+
+// a payee's income of a week, aggregated from the committed sponsor and subscribe
+// transactions it received, as payee or share recipient.
+#[derive(Debug, Default, Clone, CqlOrm)]
+pub struct PayeeWeeklySummary {
+    pub uid: xid::Id,
+    pub week: i32,
+    pub income: i64,                     // total amount received
+    pub sponsors: i32,                   // number of sponsor transactions
+    pub subscribers: i32,                // number of distinct subscribing payers
+    pub top_payers: Vec<(xid::Id, i64)>, // payers by amount in descending order, anonymous excluded
+    pub created_at: i64,
+
+    pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+}
+
+impl PayeeWeeklySummary {
+    pub fn with_pk(uid: xid::Id, week: i32) -> Self {
+        Self {
+            uid,
+            week,
+            ..Default::default()
+        }
+    }
+
+    // aggregates the payee's transactions of the week, transactions of other kinds or
+    // not committed are ignored.
+    pub fn summarize(uid: xid::Id, week: i32, txns: &[Transaction], top_n: usize) -> Self {
+        let mut doc = Self::with_pk(uid, week);
+        let mut subscribers: HashSet<xid::Id> = HashSet::new();
+        let mut payers: HashMap<xid::Id, i64> = HashMap::new();
+        for txn in txns {
+            if txn.status != 3
+                || !(txn.is_kind_of(Some(TransactionKind::Sponsor))
+                    || txn.is_kind_of(Some(TransactionKind::Subscribe)))
+            {
+                continue;
+            }
+
+            let mut received = txn
+                .sub_payees()
+                .into_iter()
+                .filter(|(payee, _)| *payee == uid)
+                .map(|(_, amount)| amount)
+                .sum::<i64>();
+            if txn.payee == uid {
+                received += txn.amount - txn.sys_fee - txn.sub_shares;
+            }
+            if received <= 0 {
+                continue;
+            }
+
+            doc.income += received;
+            if txn.is_kind_of(Some(TransactionKind::Sponsor)) {
+                doc.sponsors += 1;
+            } else {
+                subscribers.insert(txn.uid);
+            }
+            if !txn.anonymous {
+                *payers.entry(txn.uid).or_default() += received;
+            }
+        }
+
+        doc.subscribers = subscribers.len() as i32;
+        let mut top: Vec<(xid::Id, i64)> = payers.into_iter().collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then(a.0 .0.cmp(&b.0 .0)));
+        top.truncate(top_n.min(MAX_TOP_PAYERS));
+        doc.top_payers = top;
+        doc
+    }
+
+    pub async fn get_one(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let query = format!(
+            "SELECT {} FROM payee_weekly_summary WHERE uid=? AND week=? LIMIT 1",
+            fields.join(",")
+        );
+        let params = (self.uid.to_cql(), self.week);
+        let res = db.execute(query, params).await?.single_row()?;
+
+        let mut cols = ColumnsMap::with_capacity(fields.len());
+        cols.fill(res, &fields)?;
+        self.fill(&cols);
+
+        Ok(())
+    }
+
+    pub async fn exists(db: &scylladb::ScyllaDB, uid: xid::Id, week: i32) -> anyhow::Result<bool> {
+        let query = "SELECT week FROM payee_weekly_summary WHERE uid=? AND week=? LIMIT 1";
+        let params = (uid.to_cql(), week);
+        let rows = db.execute_iter(query, params).await?;
+        Ok(!rows.is_empty())
+    }
+
+    // returns false if the summary of the week exists, it is written once.
+    pub async fn save(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        self.created_at = unix_ms() as i64;
+
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let mut cols_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut vals_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut params: Vec<&CqlValue> = Vec::with_capacity(fields.len());
+        let cols = self.to();
+
+        for field in &fields {
+            cols_name.push(field);
+            vals_name.push("?");
+            params.push(cols.get(field).unwrap());
+        }
+
+        let query = format!(
+            "INSERT INTO payee_weekly_summary ({}) VALUES ({}) IF NOT EXISTS",
+            cols_name.join(","),
+            vals_name.join(",")
+        );
+
+        let res = db.execute(query, params).await?;
+        Ok(extract_applied(res))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn txn(uid: xid::Id, payee: xid::Id, kind: TransactionKind, amount: i64) -> Transaction {
+        Transaction {
+            uid,
+            id: xid::new(),
+            payee,
+            status: 3,
+            kind: kind.as_ref().to_string(),
+            amount,
+            sys_fee: amount / 10,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn week_of_works() {
+        // 1970-01-01 is a Thursday in week 0, 1970-01-05 is the Monday of week 1.
+        assert_eq!(0, week_of(0));
+        assert_eq!(0, week_of(4 * DAY_MS - 1));
+        assert_eq!(1, week_of(4 * DAY_MS));
+        assert_eq!(-3 * DAY_MS, week_start_ms(0));
+        assert_eq!(4 * DAY_MS, week_start_ms(1));
+
+        // 2023-06-05T00:00:00Z is a Monday.
+        let monday = 1685923200000i64;
+        let week = week_of(monday);
+        assert_eq!(monday, week_start_ms(week));
+        assert_eq!(week, week_of(monday + 7 * DAY_MS - 1));
+        assert_eq!(week + 1, week_of(monday + 7 * DAY_MS));
+        assert_eq!(week - 1, week_of(monday - 1));
+    }
+
+    #[test]
+    fn summarize_works() {
+        let payee = xid::new();
+        let alice = xid::new();
+        let bob = xid::new();
+        let carol = xid::new();
+
+        let mut anonymous = txn(carol, payee, TransactionKind::Sponsor, 1000);
+        anonymous.anonymous = true;
+        let mut pending = txn(alice, payee, TransactionKind::Sponsor, 1000);
+        pending.status = 2;
+        let mut shared = txn(bob, xid::new(), TransactionKind::Subscribe, 100);
+        shared.shares = vec![(payee, 5000)];
+        shared.sub_shares = 50;
+
+        let txns = vec![
+            txn(alice, payee, TransactionKind::Sponsor, 100),
+            txn(alice, payee, TransactionKind::Subscribe, 200),
+            txn(bob, payee, TransactionKind::Subscribe, 300),
+            txn(bob, payee, TransactionKind::Subscribe, 300),
+            txn(alice, payee, TransactionKind::Spend, 500),
+            anonymous,
+            pending,
+            shared,
+        ];
+
+        let doc = PayeeWeeklySummary::summarize(payee, 100, &txns, 5);
+        assert_eq!(payee, doc.uid);
+        assert_eq!(100, doc.week);
+        assert_eq!(90 + 180 + 270 + 270 + 900 + 50, doc.income);
+        assert_eq!(2, doc.sponsors);
+        assert_eq!(2, doc.subscribers);
+        assert_eq!(vec![(bob, 590), (alice, 270)], doc.top_payers);
+
+        let doc = PayeeWeeklySummary::summarize(payee, 100, &txns, 1);
+        assert_eq!(vec![(bob, 590)], doc.top_payers);
+
+        let doc = PayeeWeeklySummary::summarize(payee, 100, &[], 5);
+        assert_eq!(0, doc.income);
+        assert!(doc.top_payers.is_empty());
+    }
+}
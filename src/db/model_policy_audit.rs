@@ -0,0 +1,168 @@
+use axum_web::{context::unix_ms, erring::HTTPError};
+use scylla_orm::{ColumnsMap, CqlValue, ToCqlVal};
+use scylla_orm_macros::CqlOrm;
+
+use crate::db::scylladb::{self, extract_applied};
+
+// audit log of the wallet policies applied by the policy job.
+// a rule is applied at most once per period to a wallet, the row is saved before the transaction.
+#[derive(Debug, Default, Clone, CqlOrm)]
+pub struct PolicyAudit {
+    pub uid: xid::Id,
+    pub rule: String,
+    pub period: i32,          // days since unix epoch / rule's period days
+    pub amount: i64,          // negative for debits from the wallet
+    pub balance: i64,         // wallet's balance when the rule was evaluated
+    pub last_active_at: i64,  // unix ms of the wallet's last activity, policy transactions excluded
+    pub txn: Option<xid::Id>, // the adjustment transaction
+    pub status: i8,           // 0: applying, 1: applied, -1: failed
+    pub error: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+
+    pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+}
+
+impl PolicyAudit {
+    pub fn with_pk(uid: xid::Id, rule: String, period: i32) -> Self {
+        Self {
+            uid,
+            rule,
+            period,
+            ..Default::default()
+        }
+    }
+
+    pub async fn get_one(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let query = format!(
+            "SELECT {} FROM policy_audit WHERE uid=? AND rule=? AND period=? LIMIT 1",
+            fields.join(",")
+        );
+        let params = (self.uid.to_cql(), self.rule.to_cql(), self.period);
+        let res = db.execute(query, params).await?.single_row()?;
+
+        let mut cols = ColumnsMap::with_capacity(fields.len());
+        cols.fill(res, &fields)?;
+        self.fill(&cols);
+
+        Ok(())
+    }
+
+    // returns false if the rule has been applied to the wallet in the period.
+    pub async fn save(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        if self.amount == 0 {
+            return Err(HTTPError::new(400, format!("Invalid amount {}", self.amount)).into());
+        }
+
+        self.status = 0;
+        self.created_at = unix_ms() as i64;
+        self.updated_at = self.created_at;
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let mut cols_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut vals_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut params: Vec<&CqlValue> = Vec::with_capacity(fields.len());
+        let cols = self.to();
+
+        for field in &fields {
+            let val = cols.get(field).unwrap();
+            if val == &CqlValue::Empty {
+                continue;
+            }
+
+            cols_name.push(field);
+            vals_name.push("?");
+            params.push(val);
+        }
+
+        let query = format!(
+            "INSERT INTO policy_audit ({}) VALUES ({}) IF NOT EXISTS",
+            cols_name.join(","),
+            vals_name.join(",")
+        );
+
+        let res = db.execute(query, params).await?;
+        Ok(extract_applied(res))
+    }
+
+    // status: 1 applied, -1 failed with the error.
+    pub async fn finish(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        txn: Option<xid::Id>,
+        status: i8,
+        error: String,
+    ) -> anyhow::Result<bool> {
+        if status != 1 && status != -1 {
+            return Err(HTTPError::new(400, format!("Invalid status {}", status)).into());
+        }
+
+        let updated_at = unix_ms() as i64;
+        let query = "UPDATE policy_audit SET txn=?,status=?,error=?,updated_at=? WHERE uid=? AND rule=? AND period=? IF status=0";
+        let params = (
+            txn.map(|id| id.to_cql()),
+            status,
+            error.to_cql(),
+            updated_at,
+            self.uid.to_cql(),
+            self.rule.to_cql(),
+            self.period,
+        );
+        let res = db.execute(query, params).await?;
+        let ok = extract_applied(res);
+        if ok {
+            self.txn = txn;
+            self.status = status;
+            self.error = error;
+            self.updated_at = updated_at;
+        }
+        Ok(ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conf;
+
+    use super::*;
+
+    async fn get_db() -> scylladb::ScyllaDB {
+        let cfg = conf::Conf::new().unwrap_or_else(|err| panic!("config error: {}", err));
+        let res = scylladb::ScyllaDB::new(cfg.scylla, "walletbase_test").await;
+        res.unwrap()
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn policy_audit_model_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+
+        let mut doc = PolicyAudit::with_pk(uid, "award_decay".to_string(), 100);
+        assert!(doc.get_one(&db).await.is_err());
+        assert!(doc.save(&db).await.is_err());
+
+        doc.amount = -10;
+        doc.balance = 1000;
+        assert!(doc.save(&db).await.unwrap());
+        assert!(!doc.save(&db).await.unwrap());
+
+        let txn = xid::new();
+        assert!(doc.finish(&db, Some(txn), 0, "".to_string()).await.is_err());
+        assert!(doc.finish(&db, Some(txn), 1, "".to_string()).await.unwrap());
+        assert!(!doc
+            .finish(&db, None, -1, "failed".to_string())
+            .await
+            .unwrap());
+
+        let mut doc2 = PolicyAudit::with_pk(uid, "award_decay".to_string(), 100);
+        doc2.get_one(&db).await.unwrap();
+        assert_eq!(-10, doc2.amount);
+        assert_eq!(1, doc2.status);
+        assert_eq!(Some(txn), doc2.txn);
+    }
+}
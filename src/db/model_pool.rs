@@ -0,0 +1,385 @@
+use axum_web::{context::unix_ms, erring::HTTPError};
+use scylla_orm::{ColumnsMap, CqlValue, FieldSet, ToCqlVal};
+use scylla_orm_macros::CqlOrm;
+
+use crate::db::{
+    retry_lwt,
+    scylladb::{self, extract_applied},
+};
+
+// contributions hold the payers' funds until the pool is finalized.
+pub const MAX_POOL_TTL_SECS: i64 = 90 * 24 * 3600;
+
+// a crowdfunding pool of the owner, contributions are prepared sponsor transactions to the owner.
+// they are committed when the pool is finalized with the goal reached, or cancelled otherwise.
+#[derive(Debug, Default, Clone, CqlOrm)]
+pub struct Pool {
+    pub id: xid::Id,
+    pub owner: xid::Id,
+    pub goal: i64,
+    pub raised: i64,
+    pub contributors: i32,
+    pub status: i8, // 0: open, 2: finalizing, 1: succeeded, -1: failed
+    pub expire_at: i64,
+    pub description: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+
+    pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+}
+
+impl Pool {
+    pub fn with_pk(id: xid::Id) -> Self {
+        Self {
+            id,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expire_at <= now
+    }
+
+    pub fn select_fields(select_fields: FieldSet<PoolField>, with_pk: bool) -> Vec<String> {
+        if select_fields.is_empty() {
+            return Self::fields();
+        }
+
+        let mut select_fields = select_fields.with(PoolField::Owner).with(PoolField::Status);
+        if with_pk {
+            select_fields.insert(PoolField::Id);
+        }
+
+        select_fields.names()
+    }
+
+    pub async fn get_one(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        select_fields: FieldSet<PoolField>,
+    ) -> anyhow::Result<()> {
+        let fields = Self::select_fields(select_fields, false);
+        self._fields = fields.clone();
+
+        let query = format!("SELECT {} FROM pool WHERE id=? LIMIT 1", fields.join(","));
+        let params = (self.id.to_cql(),);
+        let res = db.execute(query, params).await?.single_row()?;
+
+        let mut cols = ColumnsMap::with_capacity(fields.len());
+        cols.fill(res, &fields)?;
+        self.fill(&cols);
+
+        Ok(())
+    }
+
+    pub async fn save(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        if self.goal <= 0 {
+            return Err(HTTPError::new(400, format!("Invalid goal {}", self.goal)).into());
+        }
+
+        self.id = xid::new();
+        self.raised = 0;
+        self.contributors = 0;
+        self.status = 0;
+        self.created_at = unix_ms() as i64;
+        self.updated_at = self.created_at;
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let mut cols_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut vals_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut params: Vec<&CqlValue> = Vec::with_capacity(fields.len());
+        let cols = self.to();
+
+        for field in &fields {
+            cols_name.push(field);
+            vals_name.push("?");
+            params.push(cols.get(field).unwrap());
+        }
+
+        let query = format!(
+            "INSERT INTO pool ({}) VALUES ({}) IF NOT EXISTS",
+            cols_name.join(","),
+            vals_name.join(",")
+        );
+
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Err(
+                HTTPError::new(409, "Pool save failed, please try again".to_string()).into(),
+            );
+        }
+
+        Ok(true)
+    }
+
+    // adds a counted contribution, fails if the pool is not open.
+    pub async fn increment(&mut self, db: &scylladb::ScyllaDB, amount: i64) -> anyhow::Result<()> {
+        if amount <= 0 {
+            return Err(HTTPError::new(400, format!("Invalid amount {}", amount)).into());
+        }
+
+        let query = "UPDATE pool SET raised=?,contributors=?,updated_at=? WHERE id=? IF status=0 AND raised=?";
+        let mut retry = retry_lwt("increment_pool");
+        while retry.next().await {
+            self.get_one(
+                db,
+                [
+                    PoolField::Raised,
+                    PoolField::Contributors,
+                    PoolField::ExpireAt,
+                ]
+                .into(),
+            )
+            .await?;
+            self.check_open(unix_ms() as i64)?;
+
+            let updated_at = unix_ms() as i64;
+            let params = (
+                self.raised + amount,
+                self.contributors + 1,
+                updated_at,
+                self.id.to_cql(),
+                self.raised,
+            );
+            let res = db.execute(query, params).await?;
+            if extract_applied(res) {
+                self.raised += amount;
+                self.contributors += 1;
+                self.updated_at = updated_at;
+                return Ok(());
+            }
+        }
+
+        Err(HTTPError::new(429, format!("Failed to contribute to pool {}", self.id)).into())
+    }
+
+    pub fn check_open(&self, now: i64) -> Result<(), HTTPError> {
+        if self.status != 0 {
+            return Err(HTTPError::new(
+                400,
+                format!("Pool {} is not open, status {}", self.id, self.status),
+            ));
+        }
+        if self.is_expired(now) {
+            return Err(HTTPError::new(400, format!("Pool {} expired", self.id)));
+        }
+        Ok(())
+    }
+
+    // closes the pool for contributions before finalizing,
+    // it can be closed when the goal is reached or the pool is expired.
+    pub async fn close(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        self.get_one(db, FieldSet::new()).await?;
+        if self.status != 0 {
+            return Ok(false);
+        }
+        if self.raised < self.goal && !self.is_expired(unix_ms() as i64) {
+            return Err(HTTPError::new(
+                400,
+                format!(
+                    "Pool {} is open until {}, raised {} of {}",
+                    self.id, self.expire_at, self.raised, self.goal
+                ),
+            )
+            .into());
+        }
+
+        let updated_at = unix_ms() as i64;
+        let query = "UPDATE pool SET status=2,updated_at=? WHERE id=? IF status=0 AND raised=?";
+        let params = (updated_at, self.id.to_cql(), self.raised);
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Err(HTTPError::new(
+                409,
+                format!("Pool {} is changing, please try again", self.id),
+            )
+            .into());
+        }
+
+        self.status = 2;
+        self.updated_at = updated_at;
+        Ok(true)
+    }
+
+    // status: 1 succeeded, -1 failed.
+    pub async fn finish(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        status: i8,
+        raised: i64,
+    ) -> anyhow::Result<bool> {
+        if status != 1 && status != -1 {
+            return Err(HTTPError::new(400, format!("Invalid status {}", status)).into());
+        }
+
+        let updated_at = unix_ms() as i64;
+        let query = "UPDATE pool SET status=?,raised=?,updated_at=? WHERE id=? IF status=2";
+        let params = (status, raised, updated_at, self.id.to_cql());
+        let res = db.execute(query, params).await?;
+        let ok = extract_applied(res);
+        if ok {
+            self.status = status;
+            self.raised = raised;
+            self.updated_at = updated_at;
+        }
+        Ok(ok)
+    }
+}
+
+// a contribution is saved after its transaction is prepared, and counted after the pool is incremented.
+#[derive(Debug, Default, Clone, CqlOrm)]
+pub struct PoolContribution {
+    pub pool: xid::Id,
+    pub txn: xid::Id,
+    pub uid: xid::Id, // payer of the txn
+    pub amount: i64,
+    pub status: i8, // 0: pending, 1: counted, 2: committed, -1: cancelled
+    pub created_at: i64,
+    pub updated_at: i64,
+
+    pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+}
+
+impl PoolContribution {
+    pub fn with_pk(pool: xid::Id, txn: xid::Id) -> Self {
+        Self {
+            pool,
+            txn,
+            ..Default::default()
+        }
+    }
+
+    pub async fn save(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        self.status = 0;
+        self.created_at = unix_ms() as i64;
+        self.updated_at = self.created_at;
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let mut cols_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut vals_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut params: Vec<&CqlValue> = Vec::with_capacity(fields.len());
+        let cols = self.to();
+
+        for field in &fields {
+            cols_name.push(field);
+            vals_name.push("?");
+            params.push(cols.get(field).unwrap());
+        }
+
+        let query = format!(
+            "INSERT INTO pool_contribution ({}) VALUES ({}) IF NOT EXISTS",
+            cols_name.join(","),
+            vals_name.join(",")
+        );
+
+        let res = db.execute(query, params).await?;
+        Ok(extract_applied(res))
+    }
+
+    // the one that changes the status owns the transaction.
+    pub async fn update_status(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        from: i8,
+        to: i8,
+    ) -> anyhow::Result<bool> {
+        let updated_at = unix_ms() as i64;
+        let query =
+            "UPDATE pool_contribution SET status=?,updated_at=? WHERE pool=? AND txn=? IF status=?";
+        let params = (to, updated_at, self.pool.to_cql(), self.txn.to_cql(), from);
+        let res = db.execute(query, params).await?;
+        let ok = extract_applied(res);
+        if ok {
+            self.status = to;
+            self.updated_at = updated_at;
+        }
+        Ok(ok)
+    }
+
+    pub async fn list(db: &scylladb::ScyllaDB, pool: xid::Id) -> anyhow::Result<Vec<Self>> {
+        let fields = Self::fields();
+        let query = format!(
+            "SELECT {} FROM pool_contribution WHERE pool=?",
+            fields.join(",")
+        );
+        let rows = db
+            .execute_iter(db.list_query(&query), (pool.to_cql(),))
+            .await?;
+
+        let mut res: Vec<Self> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Self::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conf;
+
+    use super::*;
+
+    async fn get_db() -> scylladb::ScyllaDB {
+        let cfg = conf::Conf::new().unwrap_or_else(|err| panic!("config error: {}", err));
+        let res = scylladb::ScyllaDB::new(cfg.scylla, "walletbase_test").await;
+        res.unwrap()
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn pool_model_works() {
+        let db = get_db().await;
+        let now = unix_ms() as i64;
+
+        let mut doc = Pool {
+            owner: xid::new(),
+            goal: 100,
+            expire_at: now + 3600 * 1000,
+            ..Default::default()
+        };
+        assert!(doc.save(&db).await.unwrap());
+        assert!(doc.close(&db).await.is_err());
+
+        doc.increment(&db, 60).await.unwrap();
+        doc.increment(&db, 40).await.unwrap();
+        assert_eq!(100, doc.raised);
+        assert_eq!(2, doc.contributors);
+
+        assert!(doc.close(&db).await.unwrap());
+        assert!(!doc.close(&db).await.unwrap());
+        assert!(doc.increment(&db, 1).await.is_err());
+
+        let mut c = PoolContribution {
+            pool: doc.id,
+            txn: xid::new(),
+            uid: xid::new(),
+            amount: 60,
+            ..Default::default()
+        };
+        assert!(c.save(&db).await.unwrap());
+        assert!(!c.save(&db).await.unwrap());
+        assert!(c.update_status(&db, 0, 1).await.unwrap());
+        assert!(!c.update_status(&db, 0, -1).await.unwrap());
+        let list = PoolContribution::list(&db, doc.id).await.unwrap();
+        assert_eq!(1, list.len());
+        assert_eq!(1, list[0].status);
+
+        assert!(doc.finish(&db, 0, 60).await.is_err());
+        assert!(doc.finish(&db, 1, 60).await.unwrap());
+        assert!(!doc.finish(&db, -1, 60).await.unwrap());
+
+        let mut doc2 = Pool::with_pk(doc.id);
+        doc2.get_one(&db, FieldSet::new()).await.unwrap();
+        assert_eq!(1, doc2.status);
+        assert_eq!(60, doc2.raised);
+    }
+}
@@ -0,0 +1,621 @@
+use axum_web::{context::unix_ms, erring::HTTPError};
+use rand_core::{OsRng, RngCore};
+use scylla_orm::{ColumnsMap, CqlValue, FieldSet, ToCqlVal};
+use scylla_orm_macros::CqlOrm;
+
+use crate::db::{
+    retry_lwt,
+    scylladb::{self, extract_applied},
+};
+
+// unclaimed shares are refunded to the sender after the redpacket expired.
+pub const MAX_REDPACKET_TTL_SECS: i64 = 7 * 24 * 3600;
+pub const MAX_REDPACKET_SHARES: i32 = 1000;
+
+// splits the amount into count shares, every share is at least 1.
+// random shares are drawn by the double average method: a share is in [1, 2 * average - 1] of the
+// remaining amount, so that the expectation of every share is the same regardless of the order.
+pub fn split_shares(amount: i64, count: i32, random: bool) -> Result<Vec<i64>, HTTPError> {
+    if count < 1 || count > MAX_REDPACKET_SHARES {
+        return Err(HTTPError::new(
+            400,
+            format!(
+                "Invalid count {}, it should be in [1, {}]",
+                count, MAX_REDPACKET_SHARES
+            ),
+        ));
+    }
+    if amount < count as i64 {
+        return Err(HTTPError::new(
+            400,
+            format!("Invalid amount {}, it should be at least {}", amount, count),
+        ));
+    }
+
+    let mut shares: Vec<i64> = Vec::with_capacity(count as usize);
+    let mut remaining = amount;
+    for i in 0..count as i64 {
+        let left = count as i64 - i;
+        let share = if left == 1 {
+            remaining
+        } else if random {
+            let max = (2 * remaining / left - 1).max(1);
+            1 + (OsRng.next_u64() % max as u64) as i64
+        } else {
+            // the first shares take the remainder of the division.
+            remaining / left + if remaining % left > 0 { 1 } else { 0 }
+        };
+        shares.push(share);
+        remaining -= share;
+    }
+    Ok(shares)
+}
+
+// lucky money of the sender, the amount is escrowed to the system by a redpacket transaction,
+// and the shares are paid by redpacket transactions from the system to the recipients.
+#[derive(Debug, Default, Clone, CqlOrm)]
+pub struct Redpacket {
+    pub id: xid::Id,
+    pub sender: xid::Id,
+    pub txn: xid::Id, // the escrow transaction of the sender
+    pub amount: i64,
+    pub random: bool,
+    pub allocations: Vec<i64>, // shares in the claiming order
+    pub claimed: i32,
+    pub claimed_amount: i64,
+    pub status: i8, // 0: open, 2: settling, 1: settled
+    pub refund_txn: xid::Id,
+    pub expire_at: i64,
+    pub description: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+
+    pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+}
+
+impl Redpacket {
+    pub fn with_pk(id: xid::Id) -> Self {
+        Self {
+            id,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expire_at <= now
+    }
+
+    pub fn remaining(&self) -> i64 {
+        self.amount - self.claimed_amount
+    }
+
+    pub fn select_fields(select_fields: FieldSet<RedpacketField>, with_pk: bool) -> Vec<String> {
+        if select_fields.is_empty() {
+            return Self::fields();
+        }
+
+        let mut select_fields = select_fields
+            .with(RedpacketField::Sender)
+            .with(RedpacketField::Status);
+        if with_pk {
+            select_fields.insert(RedpacketField::Id);
+        }
+
+        select_fields.names()
+    }
+
+    pub async fn get_one(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        select_fields: FieldSet<RedpacketField>,
+    ) -> anyhow::Result<()> {
+        let fields = Self::select_fields(select_fields, false);
+        self._fields = fields.clone();
+
+        let query = format!(
+            "SELECT {} FROM redpacket WHERE id=? LIMIT 1",
+            fields.join(",")
+        );
+        let params = (self.id.to_cql(),);
+        let res = db.execute(query, params).await?.single_row()?;
+
+        let mut cols = ColumnsMap::with_capacity(fields.len());
+        cols.fill(res, &fields)?;
+        self.fill(&cols);
+
+        Ok(())
+    }
+
+    // the id is generated by the caller, it is referenced by the escrow transaction.
+    pub async fn save(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        if self.allocations.iter().sum::<i64>() != self.amount {
+            return Err(HTTPError::new(
+                400,
+                format!("Invalid allocations for amount {}", self.amount),
+            )
+            .into());
+        }
+
+        self.claimed = 0;
+        self.claimed_amount = 0;
+        self.status = 0;
+        self.created_at = unix_ms() as i64;
+        self.updated_at = self.created_at;
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let mut cols_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut vals_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut params: Vec<&CqlValue> = Vec::with_capacity(fields.len());
+        let cols = self.to();
+
+        for field in &fields {
+            cols_name.push(field);
+            vals_name.push("?");
+            params.push(cols.get(field).unwrap());
+        }
+
+        let query = format!(
+            "INSERT INTO redpacket ({}) VALUES ({}) IF NOT EXISTS",
+            cols_name.join(","),
+            vals_name.join(",")
+        );
+
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Err(
+                HTTPError::new(409, "Redpacket save failed, please try again".to_string()).into(),
+            );
+        }
+
+        Ok(true)
+    }
+
+    pub fn check_open(&self, now: i64) -> Result<(), HTTPError> {
+        if self.status != 0 {
+            return Err(HTTPError::new(
+                400,
+                format!("Redpacket {} is not open, status {}", self.id, self.status),
+            ));
+        }
+        if self.is_expired(now) {
+            return Err(HTTPError::new(
+                400,
+                format!("Redpacket {} expired", self.id),
+            ));
+        }
+        if self.claimed as usize >= self.allocations.len() {
+            return Err(HTTPError::new(
+                400,
+                format!("Redpacket {} is empty", self.id),
+            ));
+        }
+        Ok(())
+    }
+
+    // takes the next share, returns the amount of it.
+    // concurrent claims are serialized by the claimed count.
+    pub async fn take_share(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<i64> {
+        let query = "UPDATE redpacket SET claimed=?,claimed_amount=?,updated_at=? WHERE id=? IF status=0 AND claimed=?";
+        let mut retry = retry_lwt("take_redpacket_share");
+        while retry.next().await {
+            self.get_one(
+                db,
+                [
+                    RedpacketField::Allocations,
+                    RedpacketField::Claimed,
+                    RedpacketField::ClaimedAmount,
+                    RedpacketField::ExpireAt,
+                ]
+                .into(),
+            )
+            .await?;
+            self.check_open(unix_ms() as i64)?;
+
+            let share = self.allocations[self.claimed as usize];
+            let updated_at = unix_ms() as i64;
+            let params = (
+                self.claimed + 1,
+                self.claimed_amount + share,
+                updated_at,
+                self.id.to_cql(),
+                self.claimed,
+            );
+            let res = db.execute(query, params).await?;
+            if extract_applied(res) {
+                self.claimed += 1;
+                self.claimed_amount += share;
+                self.updated_at = updated_at;
+                return Ok(share);
+            }
+        }
+
+        Err(HTTPError::new(429, format!("Failed to claim redpacket {}", self.id)).into())
+    }
+
+    // gives back the share just taken by take_share, if no other share was taken since.
+    pub async fn give_back_share(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        share: i64,
+    ) -> anyhow::Result<bool> {
+        let updated_at = unix_ms() as i64;
+        let query = "UPDATE redpacket SET claimed=?,claimed_amount=?,updated_at=? WHERE id=? IF status=0 AND claimed=? AND claimed_amount=?";
+        let params = (
+            self.claimed - 1,
+            self.claimed_amount - share,
+            updated_at,
+            self.id.to_cql(),
+            self.claimed,
+            self.claimed_amount,
+        );
+        let res = db.execute(query, params).await?;
+        let ok = extract_applied(res);
+        if ok {
+            self.claimed -= 1;
+            self.claimed_amount -= share;
+            self.updated_at = updated_at;
+        }
+        Ok(ok)
+    }
+
+    // closes the expired redpacket for claims before settling, the claimed amount is final.
+    pub async fn close(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        self.get_one(db, FieldSet::new()).await?;
+        if self.status != 0 {
+            return Ok(false);
+        }
+        if !self.is_expired(unix_ms() as i64) {
+            return Err(HTTPError::new(
+                400,
+                format!("Redpacket {} is open until {}", self.id, self.expire_at),
+            )
+            .into());
+        }
+
+        let updated_at = unix_ms() as i64;
+        let query =
+            "UPDATE redpacket SET status=2,updated_at=? WHERE id=? IF status=0 AND claimed=?";
+        let params = (updated_at, self.id.to_cql(), self.claimed);
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Err(HTTPError::new(
+                409,
+                format!("Redpacket {} is changing, please try again", self.id),
+            )
+            .into());
+        }
+
+        self.status = 2;
+        self.updated_at = updated_at;
+        Ok(true)
+    }
+
+    // records the refund transaction of the remaining amount, at most once.
+    pub async fn set_refund_txn(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        txn: xid::Id,
+    ) -> anyhow::Result<bool> {
+        let updated_at = unix_ms() as i64;
+        let query = "UPDATE redpacket SET refund_txn=?,updated_at=? WHERE id=? IF status=2 AND refund_txn=?";
+        let params = (
+            txn.to_cql(),
+            updated_at,
+            self.id.to_cql(),
+            xid::Id::default().to_cql(),
+        );
+        let res = db.execute(query, params).await?;
+        let ok = extract_applied(res);
+        if ok {
+            self.refund_txn = txn;
+            self.updated_at = updated_at;
+        }
+        Ok(ok)
+    }
+
+    pub async fn settle(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        let updated_at = unix_ms() as i64;
+        let query = "UPDATE redpacket SET status=1,updated_at=? WHERE id=? IF status=2";
+        let params = (updated_at, self.id.to_cql());
+        let res = db.execute(query, params).await?;
+        let ok = extract_applied(res);
+        if ok {
+            self.status = 1;
+            self.updated_at = updated_at;
+        }
+        Ok(ok)
+    }
+
+    // scans all redpackets by token, for the expiry job.
+    pub async fn scan(
+        db: &scylladb::ScyllaDB,
+        page_size: u16,
+        page_token: Option<xid::Id>,
+    ) -> anyhow::Result<Vec<Self>> {
+        let fields = vec![
+            "id".to_string(),
+            "sender".to_string(),
+            "status".to_string(),
+            "expire_at".to_string(),
+        ];
+        let rows = match page_token {
+            Some(id) => {
+                let query = db.list_query(&format!(
+                    "SELECT {} FROM redpacket WHERE token(id)>token(?) LIMIT ?",
+                    fields.join(",")
+                ));
+                let params = (id.to_cql(), page_size as i32);
+                db.execute_iter(query, params).await?
+            }
+            None => {
+                let query = db.list_query(&format!(
+                    "SELECT {} FROM redpacket LIMIT ?",
+                    fields.join(",")
+                ));
+                let params = (page_size as i32,);
+                db.execute_iter(query, params).await?
+            }
+        };
+
+        let mut res: Vec<Self> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Self::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok(res)
+    }
+}
+
+// a claim is saved before the share is taken, so that a recipient claims a redpacket at most once.
+#[derive(Debug, Default, Clone, CqlOrm)]
+pub struct RedpacketClaim {
+    pub redpacket: xid::Id,
+    pub uid: xid::Id,
+    pub txn: xid::Id, // redpacket transaction from the system
+    pub amount: i64,
+    pub status: i8, // 0: pending, 1: paid
+    pub created_at: i64,
+    pub updated_at: i64,
+
+    pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+}
+
+impl RedpacketClaim {
+    pub fn with_pk(redpacket: xid::Id, uid: xid::Id) -> Self {
+        Self {
+            redpacket,
+            uid,
+            ..Default::default()
+        }
+    }
+
+    pub async fn get_one(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let query = format!(
+            "SELECT {} FROM redpacket_claim WHERE redpacket=? AND uid=? LIMIT 1",
+            fields.join(",")
+        );
+        let params = (self.redpacket.to_cql(), self.uid.to_cql());
+        let res = db.execute(query, params).await?.single_row()?;
+
+        let mut cols = ColumnsMap::with_capacity(fields.len());
+        cols.fill(res, &fields)?;
+        self.fill(&cols);
+
+        Ok(())
+    }
+
+    // returns false if the recipient has claimed the redpacket.
+    pub async fn save(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        self.amount = 0;
+        self.status = 0;
+        self.created_at = unix_ms() as i64;
+        self.updated_at = self.created_at;
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let mut cols_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut vals_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut params: Vec<&CqlValue> = Vec::with_capacity(fields.len());
+        let cols = self.to();
+
+        for field in &fields {
+            cols_name.push(field);
+            vals_name.push("?");
+            params.push(cols.get(field).unwrap());
+        }
+
+        let query = format!(
+            "INSERT INTO redpacket_claim ({}) VALUES ({}) IF NOT EXISTS",
+            cols_name.join(","),
+            vals_name.join(",")
+        );
+
+        let res = db.execute(query, params).await?;
+        Ok(extract_applied(res))
+    }
+
+    // the claim is released if no share was taken, so that it can be retried.
+    pub async fn delete(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        let query = "DELETE FROM redpacket_claim WHERE redpacket=? AND uid=? IF amount=0";
+        let params = (self.redpacket.to_cql(), self.uid.to_cql());
+        let res = db.execute(query, params).await?;
+        Ok(extract_applied(res))
+    }
+
+    // returns false if the amount was set.
+    pub async fn set_amount(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        amount: i64,
+    ) -> anyhow::Result<bool> {
+        let updated_at = unix_ms() as i64;
+        let query =
+            "UPDATE redpacket_claim SET amount=?,updated_at=? WHERE redpacket=? AND uid=? IF amount=0";
+        let params = (
+            amount,
+            updated_at,
+            self.redpacket.to_cql(),
+            self.uid.to_cql(),
+        );
+        let res = db.execute(query, params).await?;
+        let ok = extract_applied(res);
+        if ok {
+            self.amount = amount;
+            self.updated_at = updated_at;
+        }
+        Ok(ok)
+    }
+
+    // the payout transaction id is claimed before prepared, so that a retried claim resumes it.
+    // returns false if the claim has a transaction already.
+    pub async fn set_txn(&mut self, db: &scylladb::ScyllaDB, txn: xid::Id) -> anyhow::Result<bool> {
+        let updated_at = unix_ms() as i64;
+        let query = "UPDATE redpacket_claim SET txn=?,updated_at=? WHERE redpacket=? AND uid=? IF status=0 AND txn=?";
+        let params = (
+            txn.to_cql(),
+            updated_at,
+            self.redpacket.to_cql(),
+            self.uid.to_cql(),
+            xid::Id::default().to_cql(),
+        );
+        let res = db.execute(query, params).await?;
+        let ok = extract_applied(res);
+        if ok {
+            self.txn = txn;
+            self.updated_at = updated_at;
+        }
+        Ok(ok)
+    }
+
+    pub async fn paid(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        let updated_at = unix_ms() as i64;
+        let query = "UPDATE redpacket_claim SET status=1,updated_at=? WHERE redpacket=? AND uid=? IF status=0 AND txn=?";
+        let params = (
+            updated_at,
+            self.redpacket.to_cql(),
+            self.uid.to_cql(),
+            self.txn.to_cql(),
+        );
+        let res = db.execute(query, params).await?;
+        let ok = extract_applied(res);
+        if ok {
+            self.status = 1;
+            self.updated_at = updated_at;
+        }
+        Ok(ok)
+    }
+
+    pub async fn list(db: &scylladb::ScyllaDB, redpacket: xid::Id) -> anyhow::Result<Vec<Self>> {
+        let fields = Self::fields();
+        let query = format!(
+            "SELECT {} FROM redpacket_claim WHERE redpacket=?",
+            fields.join(",")
+        );
+        let rows = db
+            .execute_iter(db.list_query(&query), (redpacket.to_cql(),))
+            .await?;
+
+        let mut res: Vec<Self> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Self::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conf;
+
+    use super::*;
+
+    async fn get_db() -> scylladb::ScyllaDB {
+        let cfg = conf::Conf::new().unwrap_or_else(|err| panic!("config error: {}", err));
+        let res = scylladb::ScyllaDB::new(cfg.scylla, "walletbase_test").await;
+        res.unwrap()
+    }
+
+    #[test]
+    fn split_shares_works() {
+        assert_eq!(vec![4, 3, 3], split_shares(10, 3, false).unwrap());
+        assert_eq!(vec![1], split_shares(1, 1, true).unwrap());
+        assert_eq!(vec![1, 1, 1], split_shares(3, 3, true).unwrap());
+        assert!(split_shares(2, 3, false).is_err());
+        assert!(split_shares(100, 0, false).is_err());
+        assert!(split_shares(1_000_000, MAX_REDPACKET_SHARES + 1, false).is_err());
+
+        for _ in 0..100 {
+            let shares = split_shares(1000, 7, true).unwrap();
+            assert_eq!(7, shares.len());
+            assert_eq!(1000, shares.iter().sum::<i64>());
+            assert!(shares.iter().all(|s| *s >= 1));
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn redpacket_model_works() {
+        let db = get_db().await;
+        let now = unix_ms() as i64;
+
+        let mut doc = Redpacket {
+            id: xid::new(),
+            sender: xid::new(),
+            txn: xid::new(),
+            amount: 10,
+            allocations: split_shares(10, 2, false).unwrap(),
+            expire_at: now + 3600 * 1000,
+            ..Default::default()
+        };
+        assert!(doc.save(&db).await.unwrap());
+        assert!(doc.save(&db).await.is_err());
+        assert!(doc.close(&db).await.is_err());
+
+        let mut c = RedpacketClaim::with_pk(doc.id, xid::new());
+        assert!(c.save(&db).await.unwrap());
+        assert!(!c.save(&db).await.unwrap());
+        assert_eq!(5, doc.take_share(&db).await.unwrap());
+        assert!(c.set_amount(&db, 5).await.unwrap());
+        assert!(!c.set_amount(&db, 5).await.unwrap());
+        assert!(!c.delete(&db).await.unwrap());
+        let txn = xid::new();
+        assert!(c.set_txn(&db, txn).await.unwrap());
+        assert!(!c.set_txn(&db, xid::new()).await.unwrap());
+        assert_eq!(txn, c.txn);
+        assert!(c.paid(&db).await.unwrap());
+        assert!(!c.paid(&db).await.unwrap());
+
+        assert_eq!(5, doc.take_share(&db).await.unwrap());
+        assert!(doc.give_back_share(&db, 5).await.unwrap());
+        assert_eq!(1, doc.claimed);
+        assert_eq!(5, doc.take_share(&db).await.unwrap());
+        assert!(doc.take_share(&db).await.is_err());
+        assert_eq!(0, doc.remaining());
+
+        let list = RedpacketClaim::list(&db, doc.id).await.unwrap();
+        assert_eq!(1, list.len());
+        assert_eq!(1, list[0].status);
+        assert_eq!(txn, list[0].txn);
+
+        let mut doc2 = Redpacket::with_pk(doc.id);
+        doc2.get_one(&db, FieldSet::new()).await.unwrap();
+        assert_eq!(2, doc2.claimed);
+        assert_eq!(10, doc2.claimed_amount);
+        assert_eq!(0, doc2.status);
+    }
+}
@@ -0,0 +1,447 @@
+use axum_web::{context::unix_ms, erring::HTTPError};
+use scylla_orm::{ColumnsMap, CqlValue, ToCqlVal};
+use scylla_orm_macros::CqlOrm;
+
+use crate::db::{
+    scylladb::{self, extract_applied},
+    DAY_MS,
+};
+
+pub const MAX_SUBSCRIPTION_PERIOD_DAYS: i32 = 366;
+
+// a recurring subscription of the subscriber to the payee, the first period is paid when
+// subscribed, and the next ones are renewed by subscribe transactions of the renewal job.
+// a renewal failed for insufficient balance makes the subscription past_due, it is retried
+// until the grace window ends, and then the subscription lapses.
+#[derive(Debug, Default, Clone, CqlOrm)]
+pub struct Subscription {
+    pub uid: xid::Id, // subscriber, payer of the renewals
+    pub payee: xid::Id,
+    pub id: xid::Id, // referenced by the payloads of the subscribe transactions
+    pub amount: i64,
+    pub period_days: i32,
+    pub status: i8, // 0: active, 1: past_due, 2: renewing, -1: canceled, -2: lapsed
+    pub next_renewal_at: i64,
+    pub grace_until: i64,
+    pub retries: i32,
+    pub last_txn: xid::Id,
+    pub description: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+
+    pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+}
+
+impl Subscription {
+    pub fn with_pk(uid: xid::Id, payee: xid::Id) -> Self {
+        Self {
+            uid,
+            payee,
+            ..Default::default()
+        }
+    }
+
+    pub fn period_ms(&self) -> i64 {
+        self.period_days as i64 * DAY_MS
+    }
+
+    // active or past_due, the subscriber has access to the payee's content.
+    pub fn is_live(&self) -> bool {
+        self.status == 0 || self.status == 1
+    }
+
+    pub async fn get_one(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let query = format!(
+            "SELECT {} FROM subscription WHERE uid=? AND payee=? LIMIT 1",
+            fields.join(",")
+        );
+        let params = (self.uid.to_cql(), self.payee.to_cql());
+        let res = db.execute(query, params).await?.single_row()?;
+
+        let mut cols = ColumnsMap::with_capacity(fields.len());
+        cols.fill(res, &fields)?;
+        self.fill(&cols);
+
+        Ok(())
+    }
+
+    // saves a new subscription with the first period paid by last_txn, a canceled or lapsed
+    // subscription of the payee is replaced.
+    pub async fn save(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        if self.period_days < 1 || self.period_days > MAX_SUBSCRIPTION_PERIOD_DAYS {
+            return Err(HTTPError::new(
+                400,
+                format!(
+                    "Invalid period_days {}, it should be in [1, {}]",
+                    self.period_days, MAX_SUBSCRIPTION_PERIOD_DAYS
+                ),
+            )
+            .into());
+        }
+
+        let mut prev = Self::with_pk(self.uid, self.payee);
+        let exists = match prev.get_one(db).await {
+            Ok(_) => true,
+            Err(err) => {
+                let err: HTTPError = err.into();
+                if err.code != 404 {
+                    return Err(err.into());
+                }
+                false
+            }
+        };
+        if exists && prev.status >= 0 {
+            return Err(HTTPError::new(
+                409,
+                format!(
+                    "Subscription to {} exists, status {}",
+                    self.payee, prev.status
+                ),
+            )
+            .into());
+        }
+
+        self.status = 0;
+        self.retries = 0;
+        self.grace_until = 0;
+        self.created_at = unix_ms() as i64;
+        self.updated_at = self.created_at;
+        self.next_renewal_at = self.created_at + self.period_ms();
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let mut cols_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut vals_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut params: Vec<&CqlValue> = Vec::with_capacity(fields.len());
+        let cols = self.to();
+
+        for field in &fields {
+            cols_name.push(field);
+            vals_name.push("?");
+            params.push(cols.get(field).unwrap());
+        }
+
+        let res = if exists {
+            let status = CqlValue::TinyInt(prev.status);
+            let mut sets: Vec<String> = Vec::with_capacity(fields.len());
+            let mut update_params: Vec<&CqlValue> = Vec::with_capacity(fields.len() + 1);
+            for (i, field) in cols_name.iter().enumerate() {
+                if *field == "uid" || *field == "payee" {
+                    continue;
+                }
+                sets.push(format!("{}=?", field));
+                update_params.push(params[i]);
+            }
+            update_params.push(cols.get("uid").unwrap());
+            update_params.push(cols.get("payee").unwrap());
+            update_params.push(&status);
+
+            let query = format!(
+                "UPDATE subscription SET {} WHERE uid=? AND payee=? IF status=?",
+                sets.join(",")
+            );
+            db.execute(query, update_params).await?
+        } else {
+            let query = format!(
+                "INSERT INTO subscription ({}) VALUES ({}) IF NOT EXISTS",
+                cols_name.join(","),
+                vals_name.join(",")
+            );
+            db.execute(query, params).await?
+        };
+
+        if !extract_applied(res) {
+            return Err(HTTPError::new(
+                409,
+                "Subscription save failed, please try again".to_string(),
+            )
+            .into());
+        }
+
+        Ok(true)
+    }
+
+    // claims the due renewal by the prepared transaction, at most once.
+    pub async fn claim_renewal(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        txn: xid::Id,
+    ) -> anyhow::Result<bool> {
+        let updated_at = unix_ms() as i64;
+        let query = "UPDATE subscription SET status=2,last_txn=?,updated_at=? WHERE uid=? AND payee=? IF status=? AND next_renewal_at=?";
+        let params = (
+            txn.to_cql(),
+            updated_at,
+            self.uid.to_cql(),
+            self.payee.to_cql(),
+            self.status,
+            self.next_renewal_at,
+        );
+        let res = db.execute(query, params).await?;
+        let ok = extract_applied(res);
+        if ok {
+            self.status = 2;
+            self.last_txn = txn;
+            self.updated_at = updated_at;
+        }
+        Ok(ok)
+    }
+
+    // the renewal transaction is committed, the next period starts at now.
+    pub async fn renewed(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        let updated_at = unix_ms() as i64;
+        let next_renewal_at = updated_at + self.period_ms();
+        let query = "UPDATE subscription SET status=0,retries=0,grace_until=0,next_renewal_at=?,updated_at=? WHERE uid=? AND payee=? IF status=2 AND last_txn=?";
+        let params = (
+            next_renewal_at,
+            updated_at,
+            self.uid.to_cql(),
+            self.payee.to_cql(),
+            self.last_txn.to_cql(),
+        );
+        let res = db.execute(query, params).await?;
+        let ok = extract_applied(res);
+        if ok {
+            self.status = 0;
+            self.retries = 0;
+            self.grace_until = 0;
+            self.next_renewal_at = next_renewal_at;
+            self.updated_at = updated_at;
+        }
+        Ok(ok)
+    }
+
+    // the renewal failed for insufficient balance, it is retried at next_renewal_at.
+    pub async fn set_past_due(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        grace_until: i64,
+        next_renewal_at: i64,
+    ) -> anyhow::Result<bool> {
+        let updated_at = unix_ms() as i64;
+        let query = "UPDATE subscription SET status=1,retries=?,grace_until=?,next_renewal_at=?,updated_at=? WHERE uid=? AND payee=? IF status=? AND next_renewal_at=?";
+        let params = (
+            self.retries + 1,
+            grace_until,
+            next_renewal_at,
+            updated_at,
+            self.uid.to_cql(),
+            self.payee.to_cql(),
+            self.status,
+            self.next_renewal_at,
+        );
+        let res = db.execute(query, params).await?;
+        let ok = extract_applied(res);
+        if ok {
+            self.status = 1;
+            self.retries += 1;
+            self.grace_until = grace_until;
+            self.next_renewal_at = next_renewal_at;
+            self.updated_at = updated_at;
+        }
+        Ok(ok)
+    }
+
+    // the grace window ended without a successful renewal.
+    pub async fn lapse(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        let updated_at = unix_ms() as i64;
+        let query = "UPDATE subscription SET status=-2,updated_at=? WHERE uid=? AND payee=? IF status IN (0,1) AND next_renewal_at=?";
+        let params = (
+            updated_at,
+            self.uid.to_cql(),
+            self.payee.to_cql(),
+            self.next_renewal_at,
+        );
+        let res = db.execute(query, params).await?;
+        let ok = extract_applied(res);
+        if ok {
+            self.status = -2;
+            self.updated_at = updated_at;
+        }
+        Ok(ok)
+    }
+
+    // the renewal transaction was canceled by the subscriber before committed.
+    pub async fn renewal_canceled(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        let updated_at = unix_ms() as i64;
+        let query = "UPDATE subscription SET status=-1,updated_at=? WHERE uid=? AND payee=? IF status=2 AND last_txn=?";
+        let params = (
+            updated_at,
+            self.uid.to_cql(),
+            self.payee.to_cql(),
+            self.last_txn.to_cql(),
+        );
+        let res = db.execute(query, params).await?;
+        let ok = extract_applied(res);
+        if ok {
+            self.status = -1;
+            self.updated_at = updated_at;
+        }
+        Ok(ok)
+    }
+
+    // canceled by the subscriber, the paid period is not refunded.
+    // a renewing subscription can be canceled after the renewal.
+    pub async fn cancel(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        self.get_one(db).await?;
+        if self.status < 0 {
+            return Ok(false);
+        }
+        if self.status == 2 {
+            return Err(HTTPError::new(
+                409,
+                format!("Subscription {} is renewing, please try again", self.id),
+            )
+            .into());
+        }
+
+        let updated_at = unix_ms() as i64;
+        let query =
+            "UPDATE subscription SET status=-1,updated_at=? WHERE uid=? AND payee=? IF status IN (0,1)";
+        let params = (updated_at, self.uid.to_cql(), self.payee.to_cql());
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Err(HTTPError::new(
+                409,
+                format!("Subscription {} is changing, please try again", self.id),
+            )
+            .into());
+        }
+
+        self.status = -1;
+        self.updated_at = updated_at;
+        Ok(true)
+    }
+
+    // scans all subscriptions by token, for the renewal job.
+    pub async fn scan(
+        db: &scylladb::ScyllaDB,
+        page_size: u16,
+        page_token: Option<(xid::Id, xid::Id)>,
+    ) -> anyhow::Result<Vec<Self>> {
+        let fields = vec![
+            "uid".to_string(),
+            "payee".to_string(),
+            "status".to_string(),
+            "next_renewal_at".to_string(),
+            "grace_until".to_string(),
+        ];
+        let rows = match page_token {
+            Some((uid, payee)) => {
+                let query = db.list_query(&format!(
+                    "SELECT {} FROM subscription WHERE token(uid,payee)>token(?,?) LIMIT ?",
+                    fields.join(",")
+                ));
+                let params = (uid.to_cql(), payee.to_cql(), page_size as i32);
+                db.execute_iter(query, params).await?
+            }
+            None => {
+                let query = db.list_query(&format!(
+                    "SELECT {} FROM subscription LIMIT ?",
+                    fields.join(",")
+                ));
+                let params = (page_size as i32,);
+                db.execute_iter(query, params).await?
+            }
+        };
+
+        let mut res: Vec<Self> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Self::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conf;
+
+    use super::*;
+
+    async fn get_db() -> scylladb::ScyllaDB {
+        let cfg = conf::Conf::new().unwrap_or_else(|err| panic!("config error: {}", err));
+        let res = scylladb::ScyllaDB::new(cfg.scylla, "walletbase_test").await;
+        res.unwrap()
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn subscription_model_works() {
+        let db = get_db().await;
+        let now = unix_ms() as i64;
+
+        let mut doc = Subscription {
+            uid: xid::new(),
+            payee: xid::new(),
+            id: xid::new(),
+            amount: 100,
+            period_days: 30,
+            last_txn: xid::new(),
+            ..Default::default()
+        };
+        assert!(doc.save(&db).await.unwrap());
+        assert!(doc.save(&db).await.is_err());
+
+        let mut got = Subscription::with_pk(doc.uid, doc.payee);
+        got.get_one(&db).await.unwrap();
+        assert_eq!(0, got.status);
+        assert_eq!(doc.id, got.id);
+        assert!(got.next_renewal_at >= now + 30 * DAY_MS);
+
+        // insufficient balance, retried in the grace window.
+        let grace_until = now + 3 * DAY_MS;
+        assert!(got
+            .set_past_due(&db, grace_until, now + DAY_MS)
+            .await
+            .unwrap());
+        assert!(!doc
+            .set_past_due(&db, grace_until, now + DAY_MS)
+            .await
+            .unwrap());
+        got.get_one(&db).await.unwrap();
+        assert_eq!(1, got.status);
+        assert_eq!(1, got.retries);
+        assert_eq!(grace_until, got.grace_until);
+        assert!(got.is_live());
+
+        // renewed in the grace window.
+        let txn = xid::new();
+        assert!(got.claim_renewal(&db, txn).await.unwrap());
+        assert!(!doc.claim_renewal(&db, xid::new()).await.unwrap());
+        assert!(got.renewed(&db).await.unwrap());
+        got.get_one(&db).await.unwrap();
+        assert_eq!(0, got.status);
+        assert_eq!(0, got.retries);
+        assert_eq!(0, got.grace_until);
+        assert_eq!(txn, got.last_txn);
+
+        // lapsed after the grace window.
+        assert!(!doc.lapse(&db).await.unwrap());
+        assert!(got.set_past_due(&db, now, now).await.unwrap());
+        assert!(got.lapse(&db).await.unwrap());
+        got.get_one(&db).await.unwrap();
+        assert_eq!(-2, got.status);
+        assert!(!got.is_live());
+        assert!(!got.cancel(&db).await.unwrap());
+
+        // subscribes again.
+        let mut doc = Subscription {
+            id: xid::new(),
+            last_txn: xid::new(),
+            ..doc
+        };
+        assert!(doc.save(&db).await.unwrap());
+        assert!(doc.cancel(&db).await.unwrap());
+        assert_eq!(-1, doc.status);
+    }
+}
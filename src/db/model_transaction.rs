@@ -1,20 +1,53 @@
 use anyhow::anyhow;
-use futures::{future::BoxFuture, join};
+use futures::{
+    future::{join_all, BoxFuture},
+    join,
+    stream::{Stream, StreamExt},
+};
 use futures_util::FutureExt;
-use std::str::FromStr;
-use strum_macros::{AsRefStr, EnumString};
-
-use axum_web::erring::HTTPError;
-use scylla_orm::{ColumnsMap, CqlValue, ToCqlVal};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    str::FromStr,
+    sync::atomic::{AtomicBool, AtomicI64, Ordering},
+};
+use strum::EnumCount;
+use strum_macros::{AsRefStr, EnumCount, EnumString};
+
+use axum_web::{context::unix_ms, erring::HTTPError};
+use scylla_orm::{ColumnsMap, CqlValue, FieldSet, FromCqlVal, ToCqlVal};
 use scylla_orm_macros::CqlOrm;
 
-use super::{income_fee_rate, Credit, CreditKind, HMacTag, Wallet, MAX_ID, SYS_FEE_RATE, SYS_ID};
+use super::{
+    apply_bps, day_of, income_fee_rate,
+    kinds::{self, KindRules, Party},
+    retry_lwt, shed_load, Credit, DailyTxnStats, HMacTag, TxnHistory, Wallet, WalletHold,
+    WalletWebhook, WebhookEvent, BPS_DENOMINATOR, MAX_ID, SYS_ID, WEBHOOK_EVENT_INCOME,
+    WEBHOOK_EVENT_SUB_SHARES,
+};
 use crate::db::scylladb::{self, extract_applied};
 
-// user's wallet.topup can be negative to MAX_OVERDRAW.
-const MAX_OVERDRAW: i64 = 100;
+// max number of share recipients of a sponsor or subscribe transaction.
+pub const MAX_SUB_PAYEES: usize = 10;
+
+// wallet legs of a committing transaction, share recipients are "sub:{uid}".
+pub const LEG_PAYEE: &str = "payee";
+pub const LEG_SYSTEM: &str = "system";
+
+fn sub_leg(uid: &xid::Id) -> String {
+    format!("sub:{}", uid)
+}
+
+// a partly committed transaction can be resumed after its commit is idle for the lease.
+const COMMIT_LEASE_MS: i64 = 30 * 1000;
+// all partly committed transactions are in one partition of transaction_committing, it should be small.
+const COMMITTING_BUCKET: i8 = 0;
+// max number of transactions scanned per page when listing prepared transactions.
+pub const MAX_PENDING_SCAN: usize = 1000;
+// max number of derived transactions listed of a transaction, e.g. partial refunds.
+pub const MAX_CHILDREN: u16 = 100;
 
-#[derive(AsRefStr, Debug, EnumString, PartialEq)]
+#[derive(AsRefStr, Clone, Copy, Debug, EnumCount, EnumString, PartialEq)]
 #[strum(serialize_all = "lowercase")]
 pub enum TransactionKind {
     Award,
@@ -24,7 +57,75 @@ pub enum TransactionKind {
     Spend,
     Sponsor,
     Subscribe,
-    // Redpacket, // TODO
+    Adjustment, // admin only, requires a second approver
+    Sweep,      // sweeps the whole balance to the system when the wallet is closed
+    Redpacket,  // lucky money escrowed by the system
+    Chargeback, // refunds a disputed transaction, and claws back the payee's income
+}
+
+// max amount of a transaction per kind, it is set from conf at startup.
+// indexed by the kind, 0 for the kind's default.
+#[allow(clippy::declare_interior_mutable_const)]
+const UNSET_MAX_AMOUNT: AtomicI64 = AtomicI64::new(0);
+static MAX_AMOUNTS: [AtomicI64; TransactionKind::COUNT] =
+    [UNSET_MAX_AMOUNT; TransactionKind::COUNT];
+
+// min credits of a user payer per kind, it is set from conf at startup.
+// indexed by the kind, -1 for the kind's default, see KindRules::requires_credits.
+#[allow(clippy::declare_interior_mutable_const)]
+const UNSET_MIN_CREDITS: AtomicI64 = AtomicI64::new(-1);
+static MIN_CREDITS: [AtomicI64; TransactionKind::COUNT] =
+    [UNSET_MIN_CREDITS; TransactionKind::COUNT];
+
+// prepared spends can be canceled by the caller within the window after prepared,
+// 0 for no deadline. it is set from conf at startup.
+static CANCEL_WINDOW_MS: AtomicI64 = AtomicI64::new(0);
+// expired spends are indexed by the day of the id, the recovery job scans the days back.
+pub const EXPIRED_SPEND_LOOKBACK_DAYS: i32 = 7;
+
+pub fn set_cancel_window(secs: i64) {
+    CANCEL_WINDOW_MS.store(secs.max(0) * 1000, Ordering::Relaxed);
+}
+
+pub fn cancel_window_ms() -> i64 {
+    CANCEL_WINDOW_MS.load(Ordering::Relaxed)
+}
+
+// unix ms when the xid was generated.
+fn xid_ms(id: &xid::Id) -> i64 {
+    let mut secs = [0u8; 4];
+    secs.copy_from_slice(&id.0[..4]);
+    u32::from_be_bytes(secs) as i64 * 1000
+}
+
+// limits are keyed by kind, e.g. {"sponsor": 100000}, kinds not in limits are kept.
+pub fn set_max_amounts(limits: &HashMap<String, i64>) -> anyhow::Result<()> {
+    for (kind, max) in limits {
+        let kind = TransactionKind::from_str(kind)
+            .map_err(|_| anyhow!("Invalid transaction kind {} in max amounts", kind))?;
+        if *max <= 0 {
+            return Err(anyhow!("Invalid max amount {} for {}", max, kind.as_ref()));
+        }
+        MAX_AMOUNTS[kind as usize].store(*max, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+// min credits per kind, 0 disables the credit gating of the kind.
+pub fn set_min_credits(limits: &HashMap<String, i64>) -> anyhow::Result<()> {
+    let mut kinds: Vec<(TransactionKind, i64)> = Vec::with_capacity(limits.len());
+    for (kind, min) in limits {
+        let kind = TransactionKind::from_str(kind)
+            .map_err(|_| anyhow!("Invalid transaction kind {} in min credits", kind))?;
+        if *min < 0 {
+            return Err(anyhow!("Invalid min credits {} for {}", min, kind.as_ref()));
+        }
+        kinds.push((kind, *min));
+    }
+    for (kind, min) in kinds {
+        MIN_CREDITS[kind as usize].store(min, Ordering::Relaxed);
+    }
+    Ok(())
 }
 
 impl ToString for TransactionKind {
@@ -34,115 +135,148 @@ impl ToString for TransactionKind {
 }
 
 impl TransactionKind {
-    pub fn check_payer(&self, uid: xid::Id) -> anyhow::Result<()> {
-        match self {
-            TransactionKind::Award | TransactionKind::Topup => {
-                if uid != SYS_ID {
-                    return Err(HTTPError::new(
-                        400,
-                        format!("Invalid payer {} for {} transaction", uid, self.as_ref()),
-                    )
-                    .into());
-                }
+    pub fn rules(&self) -> &'static dyn KindRules {
+        kinds::rules(self)
+    }
 
-                Ok(())
-            }
-            _ => {
-                if uid == SYS_ID {
-                    return Err(HTTPError::new(
-                        400,
-                        format!("Invalid payer {} for {} transaction", uid, self.as_ref()),
-                    )
-                    .into());
-                }
+    pub fn max_amount(&self) -> i64 {
+        match MAX_AMOUNTS[*self as usize].load(Ordering::Relaxed) {
+            0 => self.rules().default_max_amount(),
+            max => max,
+        }
+    }
 
-                Ok(())
-            }
+    // credits a user payer needs to pay by the kind, 0 if not gated.
+    pub fn min_credits(&self) -> i64 {
+        match MIN_CREDITS[*self as usize].load(Ordering::Relaxed) {
+            -1 => self.rules().requires_credits() as i64,
+            min => min,
         }
     }
 
-    pub fn check_payee(&self, uid: xid::Id) -> anyhow::Result<()> {
-        match self {
-            TransactionKind::Spend | TransactionKind::Withdraw | TransactionKind::Refund => {
-                if uid != SYS_ID {
-                    return Err(HTTPError::new(
-                        400,
-                        format!("Invalid payee {} for {} transaction", uid, self.as_ref()),
-                    )
-                    .into());
-                }
+    // 403 with the "min_credits" policy in the error data, so that clients can tell it from
+    // other rejections, e.g. a first withdraw before any topup.
+    pub fn check_credits(&self, wallet: &Wallet) -> Result<(), HTTPError> {
+        let min_credits = self.min_credits();
+        if wallet.is_system() || wallet.credits >= min_credits {
+            return Ok(());
+        }
 
-                Ok(())
-            }
-            _ => {
-                if uid == SYS_ID {
-                    return Err(HTTPError::new(
-                        400,
-                        format!("Invalid payee {} for {} transaction", uid, self.as_ref()),
-                    )
-                    .into());
-                }
+        let mut err = HTTPError::new(
+            403,
+            format!(
+                "Require {} credits for {} transaction, got {}",
+                min_credits,
+                self.as_ref(),
+                wallet.credits
+            ),
+        );
+        err.data = Some(serde_json::json!({
+            "policy": "min_credits",
+            "kind": self.as_ref(),
+            "min_credits": min_credits,
+            "credits": wallet.credits,
+        }));
+        Err(err)
+    }
 
-                Ok(())
-            }
+    // the error data carries the applicable limit, so that clients can show it.
+    pub fn check_amount(&self, amount: i64) -> Result<(), HTTPError> {
+        let max_amount = self.max_amount();
+        if amount > 0 && amount <= max_amount {
+            return Ok(());
         }
+
+        let mut err = HTTPError::new(
+            400,
+            format!(
+                "Invalid amount {} for {} transaction, it should be in [1, {}]",
+                amount,
+                self.as_ref(),
+                max_amount
+            ),
+        );
+        err.data = Some(serde_json::json!({
+            "kind": self.as_ref(),
+            "amount": amount,
+            "min_amount": 1,
+            "max_amount": max_amount,
+        }));
+        Err(err)
     }
 
-    pub fn check_sub_payee(&self, uid: xid::Id) -> anyhow::Result<()> {
-        match self {
-            TransactionKind::Sponsor | TransactionKind::Subscribe => Ok(()),
-            _ => Err(HTTPError::new(
+    fn check_party(&self, party: Party, side: &str, uid: xid::Id) -> anyhow::Result<()> {
+        let ok = match party {
+            Party::System => uid == SYS_ID,
+            Party::User => uid != SYS_ID,
+            // one of payer and payee should be the system if required, checked in prepare.
+            Party::Any => true,
+        };
+        if !ok {
+            return Err(HTTPError::new(
                 400,
-                format!(
-                    "Invalid sub_payee {} for {} transaction",
-                    uid,
-                    self.as_ref()
-                ),
+                format!("Invalid {} {} for {} transaction", side, uid, self.as_ref()),
             )
-            .into()),
+            .into());
         }
+        Ok(())
     }
 
-    pub fn sub_payer_balance(&self, wallet: &mut Wallet, amount: i64) -> anyhow::Result<()> {
-        assert!(amount > 0);
-        if wallet.is_system() {
-            match self {
-                TransactionKind::Award => {
-                    wallet.award -= amount;
-                }
-                TransactionKind::Topup => {
-                    wallet.topup -= amount;
-                }
-                _ => {
-                    return Err(HTTPError::new(
-                        400,
-                        format!("Invalid {} transaction", self.as_ref()),
-                    )
-                    .into());
-                }
-            }
+    pub fn check_payer(&self, uid: xid::Id) -> anyhow::Result<()> {
+        self.check_party(self.rules().payer(), "payer", uid)
+    }
 
+    pub fn check_payee(&self, uid: xid::Id) -> anyhow::Result<()> {
+        self.check_party(self.rules().payee(), "payee", uid)
+    }
+
+    pub fn check_sub_payee(&self, uid: xid::Id) -> anyhow::Result<()> {
+        if self.rules().allows_sub_payees() {
             return Ok(());
         }
+        Err(HTTPError::new(
+            400,
+            format!(
+                "Invalid sub_payee {} for {} transaction",
+                uid,
+                self.as_ref()
+            ),
+        )
+        .into())
+    }
 
-        if wallet.credits == 0
-            && self != &TransactionKind::Spend
-            && self != &TransactionKind::Subscribe
-        {
+    pub fn sub_payer_balance(&self, wallet: &mut Wallet, amount: i64) -> anyhow::Result<()> {
+        if amount <= 0 {
             return Err(HTTPError::new(
                 400,
-                format!("Require credits for {} transaction", self.as_ref()),
+                format!(
+                    "Invalid amount {} for {} transaction",
+                    amount,
+                    self.as_ref()
+                ),
             )
             .into());
         }
 
-        let quota = match self {
-            TransactionKind::Withdraw => wallet.income,
-            TransactionKind::Refund => wallet.topup,
-            TransactionKind::Spend => wallet.balance() + MAX_OVERDRAW,
-            _ => wallet.balance(),
-        };
+        let rules = self.rules();
+        if wallet.is_system() {
+            if !rules.debit_system(wallet, amount) {
+                return Err(
+                    HTTPError::new(400, format!("Invalid {} transaction", self.as_ref())).into(),
+                );
+            }
+            return Ok(());
+        }
+
+        self.check_credits(wallet)?;
+
+        if !rules.checks_balance() {
+            rules.debit_payer(wallet, amount);
+            return Ok(());
+        }
 
+        // active holds are reserved, they are not available to other transactions.
+        let quota = rules.payer_quota(wallet) - wallet._held;
         let b = wallet.balance();
         if b <= 0 || quota < amount {
             return Err(HTTPError::new(
@@ -157,132 +291,129 @@ impl TransactionKind {
             .into());
         }
 
-        match self {
-            TransactionKind::Withdraw => {
-                wallet.income -= amount;
-            }
-            TransactionKind::Refund => {
-                wallet.topup -= amount;
-            }
-            TransactionKind::Spend | TransactionKind::Sponsor | TransactionKind::Subscribe => {
-                wallet.award -= amount;
-                if wallet.award < 0 {
-                    wallet.topup -= -wallet.award;
-                    wallet.award = 0;
-                    if wallet.topup < 0 {
-                        wallet.income -= -wallet.topup;
-                        wallet.topup = 0;
-
-                        if wallet.income < 0 {
-                            // overdraw will be recorded on topup
-                            (wallet.topup, wallet.income) = (wallet.income, 0);
-                        }
-                    }
-                }
-            }
-            _ => {
-                return Err(HTTPError::new(
-                    400,
-                    format!(
-                        "Invalid payer {} for {} transaction",
-                        wallet.uid,
-                        self.as_ref()
-                    ),
-                )
-                .into());
-            }
+        if !rules.debit_payer(wallet, amount) {
+            return Err(HTTPError::new(
+                400,
+                format!(
+                    "Invalid payer {} for {} transaction",
+                    wallet.uid,
+                    self.as_ref()
+                ),
+            )
+            .into());
         }
-
         Ok(())
     }
 
     pub fn rollback_payer_balance(&self, wallet: &mut Wallet, amount: i64) -> anyhow::Result<()> {
-        match self {
-            TransactionKind::Award => {
-                wallet.award += amount;
-            }
-            TransactionKind::Topup | TransactionKind::Refund => {
-                wallet.topup += amount;
-            }
-            TransactionKind::Withdraw => {
-                wallet.income += amount;
-            }
-            TransactionKind::Spend | TransactionKind::Sponsor | TransactionKind::Subscribe => {
-                // can not rollback to award or income balance.
-                wallet.topup += amount;
-            }
-        }
-
+        self.rules().rollback_payer(wallet, amount);
         Ok(())
     }
 
     pub fn add_payee_balance(&self, wallet: &mut Wallet, amount: i64) -> anyhow::Result<()> {
-        match self {
-            TransactionKind::Award => {
-                wallet.award += amount;
-            }
-            TransactionKind::Topup | TransactionKind::Refund | TransactionKind::Withdraw => {
-                wallet.topup += amount;
-            }
-            TransactionKind::Spend | TransactionKind::Sponsor | TransactionKind::Subscribe => {
-                wallet.income += amount;
-            }
-        }
-
+        self.rules().credit_payee(wallet, amount);
         Ok(())
     }
 
-    pub fn fee_and_shares(&self, amount: i64, credits: i64, has_sub_payee: bool) -> (i64, i64) {
-        match self {
-            TransactionKind::Withdraw => {
-                let mut sys_fee = (amount as f32 * SYS_FEE_RATE) as i64;
-                if sys_fee < 1 {
-                    sys_fee = 1;
-                }
-                (sys_fee, 0)
-            }
+    // the part of the payee's amount credited to its income balance.
+    pub fn payee_income(&self, payee: xid::Id, amount: i64) -> i64 {
+        let mut wallet = Wallet::with_pk(payee);
+        self.rules().credit_payee(&mut wallet, amount);
+        wallet.income.max(0)
+    }
 
-            TransactionKind::Sponsor | TransactionKind::Subscribe => {
-                let rate = income_fee_rate(credits);
-                let mut sys_fee = (amount as f32 * rate) as i64;
+    // shares are the basis points of every share recipient, empty if no sub payee.
+    // returns (sys_fee, sub_shares), sub_shares is the total of all recipients.
+    pub fn fee_and_shares(&self, amount: i64, credits: i64, shares: &[u16]) -> (i64, i64) {
+        self.rules().fee_and_shares(amount, credits, shares)
+    }
+}
 
-                let sub_shares = if has_sub_payee { sys_fee } else { 0 };
-                if sys_fee < 1 {
-                    sys_fee = 1;
-                }
-                (sys_fee, sub_shares)
-            }
-            _ => (0i64, 0i64),
-        }
+// the result of a simulated transaction, nothing is written.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Simulation {
+    pub amount: i64,
+    pub sys_fee: i64,
+    pub sub_shares: i64,
+    pub shares: Vec<(xid::Id, i64)>, // share recipients with the amount they would receive
+    pub balance: i64,                // payer's balance after the transaction
+    pub overdraw: bool,
+}
+
+// invariant violations mean bugs or corrupted data, they should not be retried by the caller.
+// the transaction is marked in transaction_recovery table to be recovered manually.
+#[derive(Debug)]
+pub struct InvariantError {
+    pub uid: xid::Id,
+    pub id: xid::Id,
+    pub reason: String,
+}
+
+impl fmt::Display for InvariantError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "transaction {}, {} invariant violated: {}",
+            self.uid, self.id, self.reason
+        )
+    }
+}
+
+impl std::error::Error for InvariantError {}
+
+impl From<InvariantError> for HTTPError {
+    fn from(err: InvariantError) -> Self {
+        HTTPError::new(500, err.to_string())
     }
 }
 
+// indexes derived transactions by the transaction they reference, it is written at prepare.
 #[derive(Debug, Default, Clone, CqlOrm)]
-pub struct PayeeTransaction {
-    pub payee: xid::Id,
-    pub txn: xid::Id,
+pub struct TransactionRef {
+    pub ref_uid: xid::Id,
+    pub ref_txn: xid::Id,
+    pub id: xid::Id,
     pub uid: xid::Id,
 }
 
-impl PayeeTransaction {
-    pub fn new(payee: xid::Id, txn: xid::Id, uid: xid::Id) -> Self {
-        Self { payee, txn, uid }
+impl TransactionRef {
+    pub fn new(ref_uid: xid::Id, ref_txn: xid::Id, uid: xid::Id, id: xid::Id) -> Self {
+        Self {
+            ref_uid,
+            ref_txn,
+            id,
+            uid,
+        }
     }
 
-    pub async fn save(&self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
-        if self.payee == SYS_ID {
-            return Ok(false);
-        }
+    pub async fn save(&self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+        let query = "INSERT INTO transaction_ref (ref_uid,ref_txn,id,uid) VALUES (?,?,?,?)";
+        let params = (
+            self.ref_uid.to_cql(),
+            self.ref_txn.to_cql(),
+            self.id.to_cql(),
+            self.uid.to_cql(),
+        );
+        let _ = db.execute(query, params).await?;
+        Ok(())
+    }
 
-        let query = "INSERT INTO payee_transaction (payee,txn,uid) VALUES (?,?,?) IF NOT EXISTS";
-        let params = (self.payee.to_cql(), self.txn.to_cql(), self.uid.to_cql());
-        let res = db.execute(query, params).await?;
-        Ok(extract_applied(res))
+    pub async fn delete(&self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+        let query = "DELETE FROM transaction_ref WHERE ref_uid=? AND ref_txn=? AND id=?";
+        let params = (
+            self.ref_uid.to_cql(),
+            self.ref_txn.to_cql(),
+            self.id.to_cql(),
+        );
+        let _ = db.execute(query, params).await?;
+        Ok(())
     }
 
+    // returns the derived transactions' (uid, id) in descending order of id.
     pub async fn list(
         db: &scylladb::ScyllaDB,
-        payee: xid::Id,
+        ref_uid: xid::Id,
+        ref_txn: xid::Id,
         page_size: u16,
         page_token: Option<xid::Id>,
     ) -> anyhow::Result<Vec<Self>> {
@@ -291,150 +422,573 @@ impl PayeeTransaction {
             None => MAX_ID,
         };
 
-        let query = "SELECT payee,txn,uid FROM payee_transaction WHERE payee=? AND txn<? LIMIT ? USING TIMEOUT 3s";
-        let params = (payee.to_cql(), token.to_cql(), page_size as i32);
+        let fields = Self::fields();
+        let query = db.list_query(&format!(
+            "SELECT {} FROM transaction_ref WHERE ref_uid=? AND ref_txn=? AND id<? LIMIT ?",
+            fields.join(",")
+        ));
+        let params = (
+            ref_uid.to_cql(),
+            ref_txn.to_cql(),
+            token.to_cql(),
+            page_size as i32,
+        );
         let rows = db.execute_iter(query, params).await?;
 
-        let fields = vec!["payee".to_string(), "txn".to_string(), "uid".to_string()];
         let mut res: Vec<Self> = Vec::with_capacity(rows.len());
         for row in rows {
             let mut doc = Self::default();
-            let mut cols = ColumnsMap::with_capacity(3);
+            let mut cols = ColumnsMap::with_capacity(fields.len());
             cols.fill(row, &fields)?;
             doc.fill(&cols);
             res.push(doc);
         }
-
         Ok(res)
     }
 }
 
+// indexes the payer's transactions by kind, it is written at prepare,
+// so that listing by kind does not filter the payer's whole partition.
 #[derive(Debug, Default, Clone, CqlOrm)]
-pub struct Transaction {
+pub struct TransactionByKind {
     pub uid: xid::Id,
-    pub id: xid::Id,
-    pub sequence: i64,
-    pub payee: xid::Id,
-    pub sub_payee: Option<xid::Id>,
-    pub status: i8,
     pub kind: String,
-    pub amount: i64,
-    pub sys_fee: i64,
-    pub sub_shares: i64,
-    pub description: String,
-    pub payload: Vec<u8>,
-
-    pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+    pub id: xid::Id,
 }
 
-impl Transaction {
-    pub fn with_pk(uid: xid::Id, id: xid::Id) -> Self {
+impl TransactionByKind {
+    pub fn new(uid: xid::Id, kind: &str, id: xid::Id) -> Self {
         Self {
             uid,
+            kind: kind.to_string(),
             id,
-            ..Default::default()
         }
     }
 
-    pub fn with_uid(uid: xid::Id) -> Self {
-        Self {
-            uid,
-            ..Default::default()
-        }
+    pub async fn save(&self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+        let query = "INSERT INTO transaction_by_kind (uid,kind,id) VALUES (?,?,?)";
+        let params = (self.uid.to_cql(), self.kind.clone(), self.id.to_cql());
+        let _ = db.execute(query, params).await?;
+        Ok(())
     }
 
-    pub fn select_fields(select_fields: Vec<String>, with_pk: bool) -> anyhow::Result<Vec<String>> {
-        if select_fields.is_empty() {
-            return Ok(Self::fields());
-        }
+    pub async fn delete(&self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+        let query = "DELETE FROM transaction_by_kind WHERE uid=? AND kind=? AND id=?";
+        let params = (self.uid.to_cql(), self.kind.clone(), self.id.to_cql());
+        let _ = db.execute(query, params).await?;
+        Ok(())
+    }
 
-        let fields = Self::fields();
-        for field in &select_fields {
-            if !fields.contains(field) {
-                return Err(HTTPError::new(400, format!("Invalid field: {}", field)).into());
-            }
-        }
+    // returns transaction ids in descending order.
+    pub async fn list(
+        db: &scylladb::ScyllaDB,
+        uid: xid::Id,
+        kind: &str,
+        page_size: u16,
+        page_token: Option<xid::Id>,
+    ) -> anyhow::Result<Vec<xid::Id>> {
+        let token = match page_token {
+            Some(id) => id,
+            None => MAX_ID,
+        };
 
-        let mut select_fields = select_fields;
-        let field = "status".to_string();
-        if !select_fields.contains(&field) {
-            select_fields.push(field);
-        }
-        let field = "kind".to_string();
-        if !select_fields.contains(&field) {
-            select_fields.push(field);
-        }
-        if with_pk {
-            let field = "uid".to_string();
-            if !select_fields.contains(&field) {
-                select_fields.push(field);
-            }
-            let field = "id".to_string();
-            if !select_fields.contains(&field) {
-                select_fields.push(field);
-            }
+        let query = db.list_query(
+            "SELECT uid,kind,id FROM transaction_by_kind WHERE uid=? AND kind=? AND id<? LIMIT ?",
+        );
+        let params = (
+            uid.to_cql(),
+            kind.to_string(),
+            token.to_cql(),
+            page_size as i32,
+        );
+        let rows = db.execute_iter(query, params).await?;
+
+        let fields = vec!["uid".to_string(), "kind".to_string(), "id".to_string()];
+        let mut res: Vec<xid::Id> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Self::default();
+            let mut cols = ColumnsMap::with_capacity(3);
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            res.push(doc.id);
         }
 
-        Ok(select_fields)
+        Ok(res)
     }
+}
 
-    // do it after transaction commited.
-    pub fn credits(&self) -> Vec<Credit> {
-        let kind = TransactionKind::from_str(&self.kind);
-        if self.status != 3 || self.uid == SYS_ID || kind.is_err() {
-            return Vec::new();
+// balances of a wallet after a transaction is applied, the running balance of statements.
+// wallet snapshots are not stored per transaction.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Balance {
+    pub award: i64,
+    pub topup: i64,
+    pub income: i64,
+}
+
+impl Balance {
+    pub fn of(wallet: &Wallet) -> Self {
+        Self {
+            award: wallet.award,
+            topup: wallet.topup,
+            income: wallet.income,
         }
+    }
 
-        let kind = kind.unwrap();
-        let mut logs: Vec<Credit> = Vec::with_capacity(3);
-        match kind {
-            TransactionKind::Spend | TransactionKind::Sponsor | TransactionKind::Subscribe => {
-                logs.push(Credit {
-                    uid: self.uid,
-                    txn: self.id,
-                    kind: CreditKind::Payout.to_string(),
-                    amount: self.amount,
-                    description: format!("payer.{}", self.kind),
-                    ..Default::default()
-                });
-            }
-            _ => {}
+    fn from_cols(award: Option<i64>, topup: Option<i64>, income: Option<i64>) -> Option<Self> {
+        match (award, topup, income) {
+            (Some(award), Some(topup), Some(income)) => Some(Self {
+                award,
+                topup,
+                income,
+            }),
+            _ => None,
         }
+    }
+}
 
-        match kind {
-            TransactionKind::Sponsor | TransactionKind::Subscribe => {
-                logs.push(Credit {
-                    uid: self.payee,
-                    txn: self.id,
-                    kind: CreditKind::Income.to_string(),
-                    amount: self.amount - self.sys_fee - self.sub_shares,
-                    description: format!("payee.{}", self.kind),
-                    ..Default::default()
-                });
+#[derive(Debug, Default, Clone, CqlOrm)]
+pub struct PayeeTransaction {
+    pub payee: xid::Id,
+    pub txn: xid::Id,
+    pub uid: xid::Id,
+    pub balance_award: Option<i64>, // payee's balances after committed, None if unknown
+    pub balance_topup: Option<i64>,
+    pub balance_income: Option<i64>,
+}
 
-                if self.sub_shares > 0 && self.sub_payee.is_some() {
-                    logs.push(Credit {
-                        uid: self.sub_payee.unwrap(),
-                        txn: self.id,
-                        kind: CreditKind::Income.to_string(),
-                        amount: self.sub_shares,
-                        description: format!("sub_payee.{}", self.kind),
-                        ..Default::default()
-                    });
-                }
-            }
-            _ => {}
+impl PayeeTransaction {
+    pub fn new(payee: xid::Id, txn: xid::Id, uid: xid::Id) -> Self {
+        Self {
+            payee,
+            txn,
+            uid,
+            ..Default::default()
         }
+    }
 
-        logs
+    pub fn with_balance(mut self, balance: Option<Balance>) -> Self {
+        if let Some(balance) = balance {
+            self.balance_award = Some(balance.award);
+            self.balance_topup = Some(balance.topup);
+            self.balance_income = Some(balance.income);
+        }
+        self
     }
 
-    pub async fn get_one(
-        &mut self,
+    pub fn balance(&self) -> Option<Balance> {
+        Balance::from_cols(self.balance_award, self.balance_topup, self.balance_income)
+    }
+
+    pub async fn save(&self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        if self.payee == SYS_ID {
+            return Ok(false);
+        }
+
+        let res = match self.balance() {
+            Some(balance) => {
+                let query = "INSERT INTO payee_transaction (payee,txn,uid,balance_award,balance_topup,balance_income) VALUES (?,?,?,?,?,?) IF NOT EXISTS";
+                let params = (
+                    self.payee.to_cql(),
+                    self.txn.to_cql(),
+                    self.uid.to_cql(),
+                    balance.award,
+                    balance.topup,
+                    balance.income,
+                );
+                db.execute(query, params).await?
+            }
+            None => {
+                let query =
+                    "INSERT INTO payee_transaction (payee,txn,uid) VALUES (?,?,?) IF NOT EXISTS";
+                let params = (self.payee.to_cql(), self.txn.to_cql(), self.uid.to_cql());
+                db.execute(query, params).await?
+            }
+        };
+        Ok(extract_applied(res))
+    }
+
+    pub async fn list(
+        db: &scylladb::ScyllaDB,
+        payee: xid::Id,
+        page_size: u16,
+        page_token: Option<xid::Id>,
+    ) -> anyhow::Result<Vec<Self>> {
+        let token = match page_token {
+            Some(id) => id,
+            None => MAX_ID,
+        };
+
+        let fields = Self::fields();
+        let query = db.list_query(&format!(
+            "SELECT {} FROM payee_transaction WHERE payee=? AND txn<? LIMIT ?",
+            fields.join(",")
+        ));
+        let params = (payee.to_cql(), token.to_cql(), page_size as i32);
+        let rows = db.execute_iter(query, params).await?;
+
+        let mut res: Vec<Self> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Self::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            res.push(doc);
+        }
+
+        Ok(res)
+    }
+
+    // indexes awards from the system in ascending order, so that the first award is found directly.
+    pub async fn save_system_award(&self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        if self.uid != SYS_ID || self.payee == SYS_ID {
+            return Ok(false);
+        }
+
+        let query = "INSERT INTO payee_system_award (payee,txn) VALUES (?,?) IF NOT EXISTS";
+        let params = (self.payee.to_cql(), self.txn.to_cql());
+        let res = db.execute(query, params).await?;
+        Ok(extract_applied(res))
+    }
+
+    pub async fn first_system_award(
+        db: &scylladb::ScyllaDB,
+        payee: xid::Id,
+    ) -> anyhow::Result<Option<xid::Id>> {
+        let query = "SELECT txn FROM payee_system_award WHERE payee=? LIMIT 1";
+        let params = (payee.to_cql(),);
+        let rows = db.execute_iter(query, params).await?;
+        match rows
+            .into_iter()
+            .next()
+            .and_then(|row| row.columns.into_iter().next())
+        {
+            Some(Some(v)) => Ok(Some(xid::Id::from_cql(&v)?)),
+            _ => Ok(None),
+        }
+    }
+}
+
+// committed amounts between a payer and a payee, system transactions are not counted.
+// counters are kept in both payer_payee_total and payee_payer_total for the reciprocal query.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PayerPayeeTotal {
+    pub payer: xid::Id,
+    pub payee: xid::Id,
+    pub amount: i64,
+    pub txns: i64,
+}
+
+impl PayerPayeeTotal {
+    // counter updates are not idempotent, it should be called once after the transaction committed.
+    // anonymous transactions are not counted on the payee side, so that the payer is not exposed.
+    pub async fn incr(
+        db: &scylladb::ScyllaDB,
+        payer: xid::Id,
+        payee: xid::Id,
+        amount: i64,
+        anonymous: bool,
+    ) -> anyhow::Result<()> {
+        if payer == SYS_ID || payee == SYS_ID || amount <= 0 {
+            return Ok(());
+        }
+
+        let query =
+            "UPDATE payer_payee_total SET amount=amount+?,txns=txns+1 WHERE payer=? AND payee=?";
+        let params = (amount, payer.to_cql(), payee.to_cql());
+        db.execute(query, params).await?;
+        if anonymous {
+            return Ok(());
+        }
+
+        let query =
+            "UPDATE payee_payer_total SET amount=amount+?,txns=txns+1 WHERE payee=? AND payer=?";
+        let params = (amount, payee.to_cql(), payer.to_cql());
+        db.execute(query, params).await?;
+        Ok(())
+    }
+
+    pub async fn get(
+        db: &scylladb::ScyllaDB,
+        payer: xid::Id,
+        payee: xid::Id,
+    ) -> anyhow::Result<Self> {
+        let query = "SELECT amount,txns FROM payer_payee_total WHERE payer=? AND payee=? LIMIT 1";
+        let params = (payer.to_cql(), payee.to_cql());
+        let rows = db.execute_iter(query, params).await?;
+        let mut doc = Self {
+            payer,
+            payee,
+            ..Default::default()
+        };
+        if let Some(row) = rows.into_iter().next() {
+            doc.amount = counter_of(row.columns.first());
+            doc.txns = counter_of(row.columns.get(1));
+        }
+        Ok(doc)
+    }
+
+    // reads from the payee side, anonymous transactions are not counted.
+    pub async fn get_income(
+        db: &scylladb::ScyllaDB,
+        payer: xid::Id,
+        payee: xid::Id,
+    ) -> anyhow::Result<Self> {
+        let query = "SELECT amount,txns FROM payee_payer_total WHERE payee=? AND payer=? LIMIT 1";
+        let params = (payee.to_cql(), payer.to_cql());
+        let rows = db.execute_iter(query, params).await?;
+        let mut doc = Self {
+            payer,
+            payee,
+            ..Default::default()
+        };
+        if let Some(row) = rows.into_iter().next() {
+            doc.amount = counter_of(row.columns.first());
+            doc.txns = counter_of(row.columns.get(1));
+        }
+        Ok(doc)
+    }
+
+    // lists totals of the payer grouped by payee.
+    pub async fn list_by_payer(
+        db: &scylladb::ScyllaDB,
+        payer: xid::Id,
+        page_size: u16,
+        page_token: Option<xid::Id>,
+    ) -> anyhow::Result<Vec<Self>> {
+        let token = page_token.unwrap_or_default();
+        let query = db.list_query(
+            "SELECT payee,amount,txns FROM payer_payee_total WHERE payer=? AND payee>? LIMIT ?",
+        );
+        let params = (payer.to_cql(), token.to_cql(), page_size as i32);
+        let rows = db.execute_iter(query, params).await?;
+
+        let mut res: Vec<Self> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let payee = match row.columns.first() {
+                Some(Some(v)) => xid::Id::from_cql(v)?,
+                _ => continue,
+            };
+            res.push(Self {
+                payer,
+                payee,
+                amount: counter_of(row.columns.get(1)),
+                txns: counter_of(row.columns.get(2)),
+            });
+        }
+        Ok(res)
+    }
+
+    // lists totals of the payee grouped by payer, anonymous transactions are not counted.
+    pub async fn list_by_payee(
+        db: &scylladb::ScyllaDB,
+        payee: xid::Id,
+        page_size: u16,
+        page_token: Option<xid::Id>,
+    ) -> anyhow::Result<Vec<Self>> {
+        let token = page_token.unwrap_or_default();
+        let query = db.list_query(
+            "SELECT payer,amount,txns FROM payee_payer_total WHERE payee=? AND payer>? LIMIT ?",
+        );
+        let params = (payee.to_cql(), token.to_cql(), page_size as i32);
+        let rows = db.execute_iter(query, params).await?;
+
+        let mut res: Vec<Self> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let payer = match row.columns.first() {
+                Some(Some(v)) => xid::Id::from_cql(v)?,
+                _ => continue,
+            };
+            res.push(Self {
+                payer,
+                payee,
+                amount: counter_of(row.columns.get(1)),
+                txns: counter_of(row.columns.get(2)),
+            });
+        }
+        Ok(res)
+    }
+}
+
+// committed totals of all transactions per day and kind, for the ops dashboard.
+// the day is from the transaction id, so that a transaction is always counted in the same day.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SystemDailyTotal {
+    pub day: i32,
+    pub kind: String,
+    pub amount: i64,
+    pub sys_fee: i64,
+    pub txns: i64,
+}
+
+impl SystemDailyTotal {
+    // counter updates are not idempotent, it should be called once after the transaction committed.
+    pub async fn incr(
         db: &scylladb::ScyllaDB,
-        select_fields: Vec<String>,
+        day: i32,
+        kind: &str,
+        amount: i64,
+        sys_fee: i64,
     ) -> anyhow::Result<()> {
-        let fields = Self::select_fields(select_fields, false)?;
+        if amount <= 0 {
+            return Ok(());
+        }
+
+        let query = "UPDATE system_daily_total SET amount=amount+?,sys_fee=sys_fee+?,txns=txns+1 WHERE day=? AND kind=?";
+        let params = (amount, sys_fee, day, kind.to_string());
+        db.execute(query, params).await?;
+        Ok(())
+    }
+
+    // lists totals in days [first_day, last_day], newest day first.
+    pub async fn list(
+        db: &scylladb::ScyllaDB,
+        first_day: i32,
+        last_day: i32,
+    ) -> anyhow::Result<Vec<Self>> {
+        let query =
+            db.list_query("SELECT kind,amount,sys_fee,txns FROM system_daily_total WHERE day=?");
+        let mut res: Vec<Self> = Vec::new();
+        let mut day = last_day;
+        while day >= first_day {
+            let rows = db.execute_iter(query.as_str(), (day,)).await?;
+            for row in rows {
+                let kind = match row.columns.first() {
+                    Some(Some(v)) => v.as_text().cloned().unwrap_or_default(),
+                    _ => continue,
+                };
+                res.push(Self {
+                    day,
+                    kind,
+                    amount: counter_of(row.columns.get(1)),
+                    sys_fee: counter_of(row.columns.get(2)),
+                    txns: counter_of(row.columns.get(3)),
+                });
+            }
+            day -= 1;
+        }
+        Ok(res)
+    }
+}
+
+pub(super) fn counter_of(val: Option<&Option<CqlValue>>) -> i64 {
+    match val {
+        Some(Some(v)) => v.as_counter().map(|c| c.0).unwrap_or(0),
+        _ => 0,
+    }
+}
+
+#[derive(Debug, Default, Clone, CqlOrm)]
+pub struct Transaction {
+    pub uid: xid::Id,
+    pub id: xid::Id,
+    pub sequence: i64,
+    pub payee: xid::Id,
+    pub sub_payee: Option<xid::Id>,
+    pub status: i8,
+    pub kind: String,
+    pub amount: i64,
+    pub sys_fee: i64,
+    pub sub_shares: i64,
+    pub shares: Vec<(xid::Id, u16)>, // share recipients with basis points
+    pub anonymous: bool,             // hide payer from payee-facing listings
+    pub description: String,
+    pub moderation: i8, // 0: not moderated or allowed, 1: description redacted, 2: blocked
+    pub ref_uid: Option<xid::Id>, // payer of ref_txn
+    pub ref_txn: Option<xid::Id>, // the original transaction of a refund, adjustment or bonus
+    pub payload: Vec<u8>,
+    pub updated_at: i64,       // unix ms when the transaction was last updated
+    pub legs: HashSet<String>, // wallet legs applied by the commit, see commit_legs_of
+    pub balance_award: Option<i64>, // payer's balances after prepared, None for the system
+    pub balance_topup: Option<i64>,
+    pub balance_income: Option<i64>,
+
+    pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+    pub _hold: Option<xid::Id>, // the hold being captured, it is not counted as held
+    pub _payee_balance: Option<Balance>, // the payee's balances by list_by_payee
+    pub _actor: String,       // who changes the status, recorded in txn_history
+    pub _expected_sequence: Option<i64>, // the caller's payer wallet sequence, see check_expected_payer
+    pub _expected_balance: Option<i64>,  // the caller's payer wallet balance
+}
+
+impl Transaction {
+    pub fn with_pk(uid: xid::Id, id: xid::Id) -> Self {
+        Self {
+            uid,
+            id,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_uid(uid: xid::Id) -> Self {
+        Self {
+            uid,
+            ..Default::default()
+        }
+    }
+
+    pub fn select_fields(select_fields: FieldSet<TransactionField>, with_pk: bool) -> Vec<String> {
+        if select_fields.is_empty() {
+            return Self::fields();
+        }
+
+        let mut select_fields = select_fields
+            .with(TransactionField::Status)
+            .with(TransactionField::Kind);
+        if with_pk {
+            select_fields.insert(TransactionField::Uid);
+            select_fields.insert(TransactionField::Id);
+        }
+
+        select_fields.names()
+    }
+
+    // returns share recipients with the amount they received.
+    // legacy transactions only have a single sub_payee that takes all sub_shares.
+    pub fn sub_payees(&self) -> Vec<(xid::Id, i64)> {
+        if !self.shares.is_empty() {
+            return self
+                .shares
+                .iter()
+                .map(|(uid, bps)| (*uid, apply_bps(self.amount, *bps)))
+                .collect();
+        }
+
+        match self.sub_payee {
+            Some(uid) if self.sub_shares > 0 => vec![(uid, self.sub_shares)],
+            _ => Vec::new(),
+        }
+    }
+
+    // the payer's balances after the transaction is prepared, None for transactions of the
+    // system or before the balances were recorded.
+    pub fn balance(&self) -> Option<Balance> {
+        Balance::from_cols(self.balance_award, self.balance_topup, self.balance_income)
+    }
+
+    fn set_balance(&mut self, payer_wallet: &Wallet) {
+        if self.uid != SYS_ID {
+            let balance = Balance::of(payer_wallet);
+            self.balance_award = Some(balance.award);
+            self.balance_topup = Some(balance.topup);
+            self.balance_income = Some(balance.income);
+        }
+    }
+
+    // do it after transaction commited.
+    pub fn credits(&self) -> Vec<Credit> {
+        match TransactionKind::from_str(&self.kind) {
+            Ok(kind) if self.status == 3 && self.uid != SYS_ID => kind.rules().credits(self),
+            _ => Vec::new(),
+        }
+    }
+
+    pub async fn get_one(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        select_fields: FieldSet<TransactionField>,
+    ) -> anyhow::Result<()> {
+        let fields = Self::select_fields(select_fields, false);
         self._fields = fields.clone();
 
         let query = format!(
@@ -455,6 +1009,40 @@ impl Transaction {
         let query = "DELETE FROM transaction WHERE uid=? AND id=?";
         let params = (self.uid.to_cql(), self.id.to_cql());
         let _ = db.execute(query.to_string(), params).await?;
+        TransactionByKind::new(self.uid, &self.kind, self.id)
+            .delete(db)
+            .await?;
+        if let (Some(ref_uid), Some(ref_txn)) = (self.ref_uid, self.ref_txn) {
+            TransactionRef::new(ref_uid, ref_txn, self.uid, self.id)
+                .delete(db)
+                .await?;
+        }
+        Ok(())
+    }
+
+    // the referenced transaction should exist, ref_uid is required with ref_txn.
+    async fn check_ref(&self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+        let (ref_uid, ref_txn) = match (self.ref_uid, self.ref_txn) {
+            (None, None) => return Ok(()),
+            (Some(ref_uid), Some(ref_txn)) => (ref_uid, ref_txn),
+            _ => {
+                return Err(HTTPError::new(
+                    400,
+                    "ref_uid and ref_txn should be given together".to_string(),
+                )
+                .into())
+            }
+        };
+
+        let query = "SELECT status FROM transaction WHERE uid=? AND id=? LIMIT 1";
+        let params = (ref_uid.to_cql(), ref_txn.to_cql());
+        if db.execute_iter(query, params).await?.is_empty() {
+            return Err(HTTPError::new(
+                400,
+                format!("Referenced transaction {} not found", ref_txn),
+            )
+            .into());
+        }
         Ok(())
     }
 
@@ -464,47 +1052,97 @@ impl Transaction {
         from: i8,
         to: i8,
     ) -> anyhow::Result<bool> {
-        let query = "UPDATE transaction SET status=? WHERE uid=? AND id=? IF status=?";
-        let params = (to, self.uid.to_cql(), self.id.to_cql(), from);
+        let updated_at = unix_ms() as i64;
+        let query = "UPDATE transaction SET status=?,updated_at=? WHERE uid=? AND id=? IF status=?";
+        let params = (to, updated_at, self.uid.to_cql(), self.id.to_cql(), from);
         let res = db.execute(query.to_string(), params).await?;
         let res = extract_applied(res);
         if res {
             self.status = to;
+            self.updated_at = updated_at;
+            self.save_history(db, from, to).await;
         } else {
             // get the current status
-            self.get_one(db, vec!["status".to_string()]).await?;
+            self.get_one(
+                db,
+                [TransactionField::Status, TransactionField::UpdatedAt].into(),
+            )
+            .await?;
         }
         Ok(res)
     }
 
-    pub async fn prepare(
-        &mut self,
-        db: &scylladb::ScyllaDB,
-        mac: &HMacTag,
-        payee: xid::Id,
-        kind: TransactionKind,
-        amount: i64,
-    ) -> anyhow::Result<()> {
-        if amount <= 0 {
+    // the history is for support investigations, failing to save it should not fail the transition.
+    async fn save_history(&self, db: &scylladb::ScyllaDB, from: i8, to: i8) {
+        let mut doc = TxnHistory {
+            uid: self.uid,
+            id: self.id,
+            changed_at: self.updated_at,
+            to_status: to,
+            from_status: from,
+            actor: self._actor.clone(),
+            ..Default::default()
+        };
+        if let Err(err) = doc.save(db).await {
+            log::error!(target: "scylladb",
+                action = "save_txn_history",
+                uid = self.uid.to_string(),
+                id = self.id.to_string(),
+                from = from,
+                to = to;
+                "{}", err,
+            );
+        }
+    }
+
+    // checks the transaction before reading the payer's wallet.
+    fn check(&self, payee: xid::Id, kind: &TransactionKind, amount: i64) -> anyhow::Result<()> {
+        kind.check_amount(amount)?;
+        if self.uid == payee {
+            return Err(HTTPError::new(400, format!("payee {} is same as payer", payee)).into());
+        }
+
+        kind.check_payer(self.uid)?;
+        kind.check_payee(payee)?;
+        if kind.rules().requires_system_party() && self.uid != SYS_ID && payee != SYS_ID {
             return Err(HTTPError::new(
                 400,
                 format!(
-                    "Invalid amount {} for {} transaction",
-                    amount,
+                    "Invalid {} transaction, payer or payee should be system",
                     kind.as_ref()
                 ),
             )
             .into());
         }
-        if self.uid == payee {
-            return Err(HTTPError::new(400, format!("payee {} is same as payer", payee)).into());
+        if self.anonymous && !kind.rules().allows_anonymous() {
+            return Err(HTTPError::new(
+                400,
+                format!("Invalid anonymous for {} transaction", kind.as_ref()),
+            )
+            .into());
+        }
+        if self.sub_payee.is_some() && !self.shares.is_empty() {
+            return Err(HTTPError::new(
+                400,
+                "sub_payee and shares can not be used together".to_string(),
+            )
+            .into());
+        }
+        if self.shares.len() > MAX_SUB_PAYEES {
+            return Err(HTTPError::new(
+                400,
+                format!("Too many shares, expected at most {}", MAX_SUB_PAYEES),
+            )
+            .into());
         }
 
-        kind.check_payer(self.uid)?;
-        kind.check_payee(payee)?;
+        let mut sub_ids: Vec<xid::Id> = self.shares.iter().map(|(id, _)| *id).collect();
         if let Some(id) = self.sub_payee {
-            kind.check_sub_payee(id)?;
-            if id == payee || id == SYS_ID || id == self.uid {
+            sub_ids.push(id);
+        }
+        for (i, id) in sub_ids.iter().enumerate() {
+            kind.check_sub_payee(*id)?;
+            if *id == payee || *id == SYS_ID || *id == self.uid || sub_ids[..i].contains(id) {
                 return Err(HTTPError::new(
                     400,
                     format!("Invalid sub_payee {} for {} transaction", id, kind.as_ref()),
@@ -512,16 +1150,115 @@ impl Transaction {
                 .into());
             }
         }
+        for (id, bps) in &self.shares {
+            if *bps == 0 || *bps as i64 > BPS_DENOMINATOR {
+                return Err(HTTPError::new(
+                    400,
+                    format!("Invalid share {} for sub_payee {}", bps, id),
+                )
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+
+    // computes the fee and shares, and deducts the amount from the payer's wallet in memory.
+    // returns (sys_fee, sub_shares).
+    fn apply_payer_balance(
+        &mut self,
+        kind: &TransactionKind,
+        payer_wallet: &mut Wallet,
+        amount: i64,
+    ) -> anyhow::Result<(i64, i64)> {
+        if kind.rules().requires_open_payer() {
+            payer_wallet.check_open()?;
+        }
+        if let Some(id) = self.sub_payee {
+            // a single sub_payee shares the same rate as the system fee.
+            self.shares = vec![(id, income_fee_rate(payer_wallet.credits))];
+        }
+        let bps: Vec<u16> = self.shares.iter().map(|(_, bps)| *bps).collect();
+        let (sys_fee, sub_shares) = kind.fee_and_shares(amount, payer_wallet.credits, &bps);
+        if sys_fee + sub_shares > amount {
+            return Err(HTTPError::new(
+                400,
+                format!(
+                    "Invalid shares for {} transaction, fee {} and shares {} exceed amount {}",
+                    kind.as_ref(),
+                    sys_fee,
+                    sub_shares,
+                    amount
+                ),
+            )
+            .into());
+        }
+        kind.sub_payer_balance(payer_wallet, amount)?;
+        Ok((sys_fee, sub_shares))
+    }
+
+    // runs the same checks as prepare against the current wallet state without any writes.
+    pub async fn simulate(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        mac: &HMacTag,
+        payee: xid::Id,
+        kind: TransactionKind,
+        amount: i64,
+    ) -> anyhow::Result<Simulation> {
+        self.check(payee, &kind, amount)?;
 
         let mut payer_wallet = Wallet::with_pk(self.uid);
         payer_wallet.get_one(db).await?;
         payer_wallet.verify_checksum(mac)?;
+        payer_wallet._held = WalletHold::held_amount(db, self.uid, self._hold).await?;
 
-        let (sys_fee, sub_shares) =
-            kind.fee_and_shares(amount, payer_wallet.credits, self.sub_payee.is_some());
-        kind.sub_payer_balance(&mut payer_wallet, amount)?;
+        let (sys_fee, sub_shares) = self.apply_payer_balance(&kind, &mut payer_wallet, amount)?;
+        self.payee = payee;
+        self.kind = kind.as_ref().to_string();
+        self.amount = amount;
+        self.sys_fee = sys_fee;
+        self.sub_shares = sub_shares;
+
+        let balance = payer_wallet.balance();
+        Ok(Simulation {
+            amount,
+            sys_fee,
+            sub_shares,
+            shares: self.sub_payees(),
+            balance,
+            overdraw: balance < 0,
+        })
+    }
 
-        self.id = xid::new();
+    pub async fn prepare(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        mac: &HMacTag,
+        payee: xid::Id,
+        kind: TransactionKind,
+        amount: i64,
+    ) -> anyhow::Result<()> {
+        shed_load()?;
+        self.check(payee, &kind, amount)?;
+        self.check_ref(db).await?;
+
+        if payee != SYS_ID {
+            Wallet::check_open_by_uid(db, payee).await?;
+        }
+
+        let mut payer_wallet = Wallet::with_pk(self.uid);
+        payer_wallet.get_one(db).await?;
+        payer_wallet.verify_checksum(mac)?;
+        payer_wallet._held = WalletHold::held_amount(db, self.uid, self._hold).await?;
+
+        let (sys_fee, sub_shares) = self.apply_payer_balance(&kind, &mut payer_wallet, amount)?;
+        payer_wallet.pending_out += amount;
+
+        // the id is generated before the prepare when it is claimed by the caller.
+        if self.id == xid::Id::default() {
+            self.id = xid::new();
+        }
         self.sequence = payer_wallet.sequence;
         self.payee = payee;
         self.status = 0;
@@ -529,6 +1266,8 @@ impl Transaction {
         self.amount = amount;
         self.sys_fee = sys_fee;
         self.sub_shares = sub_shares;
+        self.set_balance(&payer_wallet);
+        self.updated_at = unix_ms() as i64;
 
         let fields = Self::fields();
         self._fields = fields.iter().map(|f| f.to_string()).collect();
@@ -556,13 +1295,47 @@ impl Transaction {
 
         // can not use: BATCH with conditions cannot span multiple tables
         let res = db.execute(insert_query, insert_params).await?;
-        if extract_applied(res) {
+        if !extract_applied(res) {
+            // the claimed id is being prepared by another caller.
+            return Err(
+                HTTPError::new(409, format!("Transaction {} is being prepared", self.id)).into(),
+            );
+        }
+
+        if let Err(err) = TransactionByKind::new(self.uid, &self.kind, self.id)
+            .save(db)
+            .await
+        {
+            self.delete(db).await?;
+            return Err(err);
+        }
+        if let (Some(ref_uid), Some(ref_txn)) = (self.ref_uid, self.ref_txn) {
+            if let Err(err) = TransactionRef::new(ref_uid, ref_txn, self.uid, self.id)
+                .save(db)
+                .await
+            {
+                self.delete(db).await?;
+                return Err(err);
+            }
+        }
+
+        let mut retry = retry_lwt("prepare_transaction");
+        while retry.next().await {
             payer_wallet.next_checksum(mac, self.id);
-            let res = payer_wallet.update_balance(db).await?;
-            if res {
+            if payer_wallet.update_balance(db).await? {
                 self.set_status(db, 0, 1).await?;
+                self.save_cancel_deadline(db).await;
                 return Ok(());
             }
+
+            // the wallet was updated by another transaction, apply to its latest state.
+            if let Err(err) = self
+                .reapply_payer_balance(db, mac, &kind, &mut payer_wallet, amount)
+                .await
+            {
+                self.delete(db).await?;
+                return Err(err);
+            }
         }
 
         self.delete(db).await?;
@@ -573,6 +1346,57 @@ impl Transaction {
         .into())
     }
 
+    // re-reads the payer's wallet and applies the amount again,
+    // the prepared transaction is updated if its sequence, fee or shares changed.
+    async fn reapply_payer_balance(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        mac: &HMacTag,
+        kind: &TransactionKind,
+        payer_wallet: &mut Wallet,
+        amount: i64,
+    ) -> anyhow::Result<()> {
+        payer_wallet.get_one(db).await?;
+        payer_wallet.verify_checksum(mac)?;
+        payer_wallet._held = WalletHold::held_amount(db, self.uid, self._hold).await?;
+        let (sys_fee, sub_shares) = self.apply_payer_balance(kind, payer_wallet, amount)?;
+        payer_wallet.pending_out += amount;
+
+        if self.sequence == payer_wallet.sequence
+            && self.sys_fee == sys_fee
+            && self.sub_shares == sub_shares
+        {
+            return Ok(());
+        }
+
+        self.sequence = payer_wallet.sequence;
+        self.sys_fee = sys_fee;
+        self.sub_shares = sub_shares;
+        self.set_balance(payer_wallet);
+        self.updated_at = unix_ms() as i64;
+        let cols = self.to();
+        let query = "UPDATE transaction SET sequence=?,sys_fee=?,sub_shares=?,shares=?,balance_award=?,balance_topup=?,balance_income=?,updated_at=? WHERE uid=? AND id=? IF status=0";
+        let params = (
+            self.sequence,
+            self.sys_fee,
+            self.sub_shares,
+            cols.get("shares").unwrap(),
+            self.balance_award,
+            self.balance_topup,
+            self.balance_income,
+            self.updated_at,
+            self.uid.to_cql(),
+            self.id.to_cql(),
+        );
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Err(
+                HTTPError::new(409, format!("Transaction {} is not preparing", self.id)).into(),
+            );
+        }
+        Ok(())
+    }
+
     // do it after prepared.
     pub async fn cancel(&mut self, db: &scylladb::ScyllaDB, mac: &HMacTag) -> anyhow::Result<()> {
         let kind = TransactionKind::from_str(&self.kind)?;
@@ -591,6 +1415,7 @@ impl Transaction {
             .into());
         }
 
+        self.check_expected_payer(db).await?;
         let ok = self.set_status(db, 1, -1).await?;
         if !ok {
             if self.status < 0 {
@@ -607,10 +1432,20 @@ impl Transaction {
 
         let mut ok = false;
         let mut payer_wallet = Wallet::with_pk(self.uid);
-        for _ in 0..5 {
+        let mut retry = retry_lwt("cancel_transaction");
+        while retry.next().await {
             payer_wallet.get_one(db).await?;
             payer_wallet.verify_checksum(mac)?;
+            // the expected sequence is the condition of the wallet's update.
+            if let Err(err) =
+                payer_wallet.check_expected(self._expected_sequence, self._expected_balance)
+            {
+                self.set_status(db, -1, 1).await?;
+                return Err(err.into());
+            }
             kind.rollback_payer_balance(&mut payer_wallet, self.amount)?;
+            // transactions prepared before pending_out existed were not counted.
+            payer_wallet.pending_out = (payer_wallet.pending_out - self.amount).max(0);
             payer_wallet.next_checksum(mac, self.id);
             ok = payer_wallet.update_balance(db).await?;
             if ok {
@@ -638,6 +1473,31 @@ impl Transaction {
         .into())
     }
 
+    // checks the caller's expected sequence and balance of the payer's wallet, 409 with the
+    // current ones on mismatch. the loaded sequence is the condition of an LWT on the wallet,
+    // so that a transaction of the payer written after the check fails it.
+    async fn check_expected_payer(&self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+        if self._expected_sequence.is_none() && self._expected_balance.is_none() {
+            return Ok(());
+        }
+
+        let mut payer_wallet = Wallet::with_pk(self.uid);
+        let mut retry = retry_lwt("check_expected_payer");
+        while retry.next().await {
+            payer_wallet.get_one(db).await?;
+            payer_wallet.check_expected(self._expected_sequence, self._expected_balance)?;
+            if payer_wallet.check_sequence(db).await? {
+                return Ok(());
+            }
+        }
+
+        Err(HTTPError::new(
+            429,
+            format!("Wallet {} is busy, please try again", self.uid),
+        )
+        .into())
+    }
+
     // do it after prepared.
     // returns payee's wallet.
     pub async fn commit(
@@ -648,16 +1508,37 @@ impl Transaction {
         let kind = TransactionKind::from_str(&self.kind)?;
         kind.check_payee(self.payee)?;
 
-        if self.sub_shares > 0 && self.sub_payee.is_none() {
-            panic!("No sub_payee with sub_shares");
+        let sub_payees = self.sub_payees();
+        if self.sub_shares > 0 && sub_payees.is_empty() {
+            return Err(self
+                .invariant_error(db, "No sub_payee with sub_shares".to_string())
+                .await);
+        }
+        if self.amount - self.sys_fee - self.sub_shares < 0 {
+            return Err(self
+                .invariant_error(
+                    db,
+                    format!(
+                        "sys_fee {} and sub_shares {} exceed amount {}",
+                        self.sys_fee, self.sub_shares, self.amount
+                    ),
+                )
+                .await);
         }
 
+        self.check_expected_payer(db).await?;
         let ok = self.set_status(db, 1, 2).await?;
         if !ok {
             if self.status == 3 {
-                // already committed
+                // already committed, repair the payee index and credits in case the last commit was interrupted.
+                self.save_payee_index(db, &HashMap::new()).await;
+                self.save_credits(db).await?;
+                self.delete_committing(db).await;
                 return Ok(None);
             }
+            if self.status == 2 {
+                return self.resume_commit(db, mac).await;
+            }
 
             return Err(HTTPError::new(
                 500,
@@ -666,6 +1547,107 @@ impl Transaction {
             .into());
         }
 
+        self.commit_legs(db, mac, &kind, false).await
+    }
+
+    // resumes the pending legs of an interrupted commit. the transaction is claimed by
+    // updating its updated_at after COMMIT_LEASE_MS, so that it is resumed by one caller at a time.
+    pub async fn resume_commit(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        mac: &HMacTag,
+    ) -> anyhow::Result<Option<Wallet>> {
+        let kind = TransactionKind::from_str(&self.kind)?;
+        self.get_one(
+            db,
+            [
+                TransactionField::Status,
+                TransactionField::UpdatedAt,
+                TransactionField::Legs,
+            ]
+            .into(),
+        )
+        .await?;
+        match self.status {
+            2 => {}
+            3 => {
+                self.save_payee_index(db, &HashMap::new()).await;
+                self.save_credits(db).await?;
+                self.delete_committing(db).await;
+                return Ok(None);
+            }
+            status => {
+                return Err(HTTPError::new(
+                    500,
+                    format!("Invalid status {} for committing transaction", status),
+                )
+                .into())
+            }
+        }
+
+        let now = unix_ms() as i64;
+        let busy = || HTTPError::new(409, format!("Transaction {} is being committed", self.id));
+        if self.updated_at > now - COMMIT_LEASE_MS {
+            return Err(busy().into());
+        }
+        let query =
+            "UPDATE transaction SET updated_at=? WHERE uid=? AND id=? IF status=? AND updated_at=?";
+        let params = (
+            now,
+            self.uid.to_cql(),
+            self.id.to_cql(),
+            2i8,
+            self.updated_at,
+        );
+        if !extract_applied(db.execute(query, params).await?) {
+            return Err(busy().into());
+        }
+        self.updated_at = now;
+        self.commit_legs(db, mac, &kind, true).await
+    }
+
+    // wallet legs of the transaction: the payee, the system's fee and every share recipient.
+    pub fn commit_legs_of(&self) -> Vec<String> {
+        let mut legs = vec![LEG_PAYEE.to_string()];
+        if self.sys_fee > 0 && self.payee != SYS_ID {
+            legs.push(LEG_SYSTEM.to_string());
+        }
+        for (uid, amount) in self.sub_payees() {
+            if amount > 0 {
+                legs.push(sub_leg(&uid));
+            }
+        }
+        legs
+    }
+
+    // legs not applied yet by the committing transaction.
+    pub fn pending_legs(&self) -> Vec<String> {
+        self.commit_legs_of()
+            .into_iter()
+            .filter(|leg| !self.legs.contains(leg))
+            .collect()
+    }
+
+    // applies the pending legs of the committing transaction. every applied leg is recorded
+    // in legs, so that an interrupted commit is resumed without applying a leg twice.
+    // returns 202 with the pending legs if some legs failed, they are finished by the
+    // recovery job or a retried commit. the transaction is prepared again only if no wallet
+    // was written, a failed or timed out write may still be applied.
+    async fn commit_legs(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        mac: &HMacTag,
+        kind: &TransactionKind,
+        resuming: bool,
+    ) -> anyhow::Result<Option<Wallet>> {
+        let pending = self.pending_legs();
+        let is_pending = |leg: &str| pending.iter().any(|l| l == leg);
+        let sub_payees: Vec<(xid::Id, i64)> = self
+            .sub_payees()
+            .into_iter()
+            .filter(|(uid, amount)| *amount > 0 && is_pending(&sub_leg(uid)))
+            .collect();
+
         let mut payee_wallet = Wallet::with_pk(self.payee);
         let res = payee_wallet.get_one(db).await;
         if res.is_err() {
@@ -683,19 +1665,38 @@ impl Transaction {
         }
 
         let payee_wallet_is_sys = payee_wallet.is_system();
-        let fut_payee: BoxFuture<'_, anyhow::Result<()>> = async {
+        let this = &*self;
+        // whether a wallet may be written by a leg, the interrupted commit being resumed may
+        // have written any wallet.
+        let written = AtomicBool::new(resuming);
+        let written = &written;
+        // returns true if the leg is applied by this commit.
+        let fut_payee: BoxFuture<'_, anyhow::Result<bool>> = async {
+            if !is_pending(LEG_PAYEE) {
+                return Ok(false);
+            }
+
             let mut ok = false;
-            for _ in 0..5 {
+            let mut retry = retry_lwt("commit_transaction");
+            while retry.next().await {
+                if this.leg_applied(db, this.payee, LEG_PAYEE).await? {
+                    written.store(true, Ordering::Relaxed);
+                    this.save_leg(db, LEG_PAYEE, this.payee).await?;
+                    return Ok(false);
+                }
                 payee_wallet.verify_checksum(mac)?;
                 kind.add_payee_balance(
                     &mut payee_wallet,
-                    self.amount - self.sys_fee - self.sub_shares,
+                    this.amount - this.sys_fee - this.sub_shares,
                 )?;
                 if payee_wallet.is_system() {
-                    payee_wallet.income += self.sys_fee;
+                    payee_wallet.income += this.sys_fee;
                 }
-                payee_wallet.next_checksum(mac, self.id);
-                ok = payee_wallet.update_balance(db).await?;
+                payee_wallet.next_checksum(mac, this.id);
+                written.store(true, Ordering::Relaxed);
+                ok = payee_wallet
+                    .commit_balance(db, &this.leg_mark(LEG_PAYEE))
+                    .await?;
                 if ok {
                     break;
                 }
@@ -705,8 +1706,8 @@ impl Transaction {
             if !ok {
                 log::error!(target: "scylladb",
                     action = "commit_transaction",
-                    uid = self.uid.to_string(),
-                    id = self.id.to_string(),
+                    uid = this.uid.to_string(),
+                    id = this.id.to_string(),
                     wallet = payee_wallet.uid.to_string();
                     "payee_wallet committing failed",
                 );
@@ -715,159 +1716,598 @@ impl Transaction {
                     payee_wallet.uid.to_string()
                 ));
             }
-            Ok(())
+            this.save_leg(db, LEG_PAYEE, this.payee).await?;
+            Ok(true)
         }
         .boxed();
 
         let fut_sys: BoxFuture<'_, anyhow::Result<()>> = async {
-            if self.sys_fee > 0 && !payee_wallet_is_sys {
-                let mut ok = false;
-                let mut sys_wallet = Wallet::with_pk(SYS_ID);
-                for _ in 0..5 {
-                    sys_wallet.get_one(db).await?;
-                    sys_wallet.verify_checksum(mac)?;
-                    sys_wallet.income += self.sys_fee;
-                    sys_wallet.next_checksum(mac, self.id);
-
-                    ok = sys_wallet.update_balance(db).await?;
-                    if ok {
-                        break;
-                    }
-                }
+            if !is_pending(LEG_SYSTEM) || payee_wallet_is_sys {
+                return Ok(());
+            }
 
-                if !ok {
-                    log::error!(target: "scylladb",
-                        action = "commit_transaction",
-                        uid = self.uid.to_string(),
-                        id = self.id.to_string(),
-                        wallet = sys_wallet.uid.to_string();
-                        "sys_wallet committing failed",
-                    );
-                    return Err(anyhow!(
-                        "sys_wallet committing failed, {}",
-                        sys_wallet.uid.to_string()
-                    ));
+            let mut ok = false;
+            let mut sys_wallet = Wallet::with_pk(SYS_ID);
+            let mut retry = retry_lwt("commit_transaction");
+            while retry.next().await {
+                sys_wallet.get_one(db).await?;
+                if this.leg_applied(db, SYS_ID, LEG_SYSTEM).await? {
+                    written.store(true, Ordering::Relaxed);
+                    return this.save_leg(db, LEG_SYSTEM, SYS_ID).await;
                 }
+                sys_wallet.verify_checksum(mac)?;
+                sys_wallet.income += this.sys_fee;
+                sys_wallet.next_checksum(mac, this.id);
+
+                written.store(true, Ordering::Relaxed);
+                ok = sys_wallet
+                    .commit_balance(db, &this.leg_mark(LEG_SYSTEM))
+                    .await?;
+                if ok {
+                    break;
+                }
+            }
+
+            if !ok {
+                log::error!(target: "scylladb",
+                    action = "commit_transaction",
+                    uid = this.uid.to_string(),
+                    id = this.id.to_string(),
+                    wallet = sys_wallet.uid.to_string();
+                    "sys_wallet committing failed",
+                );
+                return Err(anyhow!(
+                    "sys_wallet committing failed, {}",
+                    sys_wallet.uid.to_string()
+                ));
             }
-            Ok(())
+            this.save_leg(db, LEG_SYSTEM, SYS_ID).await
         }
         .boxed();
 
-        let fut_sub: BoxFuture<'_, anyhow::Result<()>> = async {
-            if self.sub_shares > 0 {
-                let mut ok = false;
-                let mut sub_wallet = Wallet::with_pk(self.sub_payee.unwrap());
-                let res = sub_wallet.get_one(db).await;
-                if res.is_err() {
-                    // create payee wallet if not exists
-                    let res = sub_wallet.save(db).await?;
-                    log::info!(target: "scylladb",
-                        action = "create_wallet",
-                        uid = sub_wallet.uid.to_string(),
-                        txn_uid = self.uid.to_string(),
-                        txn_id = self.id.to_string(),
-                        txn_kind = self.kind,
-                        result = res;
-                        "",
-                    );
-                }
-
-                for _ in 0..5 {
-                    sub_wallet.verify_checksum(mac)?;
-                    sub_wallet.income += self.sub_shares;
-                    sub_wallet.next_checksum(mac, self.id);
+        let fut_sub: BoxFuture<'_, Vec<(String, xid::Id, anyhow::Result<Option<Balance>>)>> =
+            async {
+                join_all(sub_payees.iter().map(|(uid, amount)| async move {
+                    let leg = sub_leg(uid);
+                    let res = match this
+                        .add_sub_payee_income(db, mac, *uid, *amount, written)
+                        .await
+                    {
+                        Ok(balance) => this.save_leg(db, &leg, *uid).await.map(|_| balance),
+                        Err(err) => Err(err),
+                    };
+                    (leg, *uid, res)
+                }))
+                .await
+            }
+            .boxed();
 
-                    ok = sub_wallet.update_balance(db).await?;
-                    if ok {
-                        break;
+        let (a, b, c) = join!(fut_payee, fut_sys, fut_sub);
+        // balances of the wallets applied by this commit, for the running balance of the payees.
+        let mut balances: HashMap<xid::Id, Balance> = HashMap::new();
+        if let Ok(true) = a {
+            balances.insert(self.payee, Balance::of(&payee_wallet));
+        }
+        let mut results = vec![
+            (LEG_PAYEE.to_string(), a.map(|_| ())),
+            (LEG_SYSTEM.to_string(), b),
+        ];
+        for (leg, uid, res) in c {
+            results.push((
+                leg,
+                res.map(|balance| {
+                    if let Some(balance) = balance {
+                        balances.insert(uid, balance);
                     }
-                    sub_wallet.get_one(db).await?;
+                }),
+            ));
+        }
+        let mut errs: Vec<String> = Vec::new();
+        for (leg, res) in results {
+            match res {
+                Ok(()) if is_pending(&leg) => {
+                    self.legs.insert(leg);
                 }
+                Ok(()) => {}
+                Err(err) => errs.push(err.to_string()),
+            }
+        }
 
-                if !ok {
-                    log::error!(target: "scylladb",
-                        action = "commit_transaction",
-                        uid = self.uid.to_string(),
-                        id = self.id.to_string(),
-                        wallet = sub_wallet.uid.to_string();
-                        "sub_wallet committing failed",
-                    );
-                    return Err(anyhow!(
-                        "sub_wallet committing failed, {}",
-                        sub_wallet.uid.to_string()
-                    ));
-                }
+        if errs.is_empty() {
+            self.set_status(db, 2, 3).await?;
+            self.release_pending_out(db).await;
+            self.save_payer_payee_total(db).await;
+            self.save_system_daily_total(db).await;
+            self.save_daily_txn_stats(db).await;
+            self.save_payee_index(db, &balances).await;
+            self.save_webhook_events(db, kind).await;
+            self.save_credits(db).await?;
+            self.delete_committing(db).await;
+            return Ok(Some(payee_wallet));
+        }
+
+        if !written.load(Ordering::Relaxed) {
+            // no wallet written, the transaction is prepared again to be committed or canceled.
+            self.set_status(db, 2, 1).await?;
+            return Err(HTTPError::new(
+                500,
+                format!("committing transaction failed, errors: {:?}", errs),
+            )
+            .into());
+        }
+
+        self.save_committing(db).await;
+        let pending = self.pending_legs();
+        let mut legs: Vec<&String> = self.legs.iter().collect();
+        legs.sort();
+        Err(HTTPError {
+            code: 202,
+            message: format!(
+                "committing transaction not finished, pending legs {:?}, errors: {:?}",
+                pending, errs
+            ),
+            data: Some(serde_json::json!({
+                "uid": self.uid.to_string(),
+                "id": self.id.to_string(),
+                "legs": legs,
+                "pending_legs": pending,
+            })),
+        }
+        .into())
+    }
+
+    // records the applied leg, then clears the mark of the transaction on the leg's wallet.
+    async fn save_leg(
+        &self,
+        db: &scylladb::ScyllaDB,
+        leg: &str,
+        wallet: xid::Id,
+    ) -> anyhow::Result<()> {
+        let query = "UPDATE transaction SET legs=legs+? WHERE uid=? AND id=?";
+        let params = (vec![leg.to_string()], self.uid.to_cql(), self.id.to_cql());
+        db.execute(query, params).await?;
+        Wallet::clear_committing(db, wallet, &self.leg_mark(leg)).await;
+        Ok(())
+    }
+
+    // mark of the leg on its wallet while the leg is applied but not recorded.
+    fn leg_mark(&self, leg: &str) -> String {
+        format!("{}/{}", self.id, leg)
+    }
+
+    // whether the leg was applied by an interrupted or a concurrent commit. the wallet is
+    // marked in the same LWT that applies the leg, and the mark is cleared after the leg is
+    // recorded, so the recorded legs are read again if the wallet is not marked.
+    async fn leg_applied(
+        &self,
+        db: &scylladb::ScyllaDB,
+        wallet: xid::Id,
+        leg: &str,
+    ) -> anyhow::Result<bool> {
+        if Wallet::is_committing(db, wallet, &self.leg_mark(leg)).await? {
+            return Ok(true);
+        }
+        let mut doc = Self::with_pk(self.uid, self.id);
+        doc.get_one(db, [TransactionField::Legs].into()).await?;
+        Ok(doc.legs.contains(leg))
+    }
+
+    // queues the partly committed transaction for the recovery job.
+    async fn save_committing(&self, db: &scylladb::ScyllaDB) {
+        let query =
+            "INSERT INTO transaction_committing (bucket,id,uid,created_at) VALUES (?,?,?,?)";
+        let params = (
+            COMMITTING_BUCKET,
+            self.id.to_cql(),
+            self.uid.to_cql(),
+            unix_ms() as i64,
+        );
+        if let Err(err) = db.execute(query, params).await {
+            log::error!(target: "scylladb",
+                action = "save_transaction_committing",
+                uid = self.uid.to_string(),
+                id = self.id.to_string();
+                "{}", err,
+            );
+        }
+    }
+
+    async fn delete_committing(&self, db: &scylladb::ScyllaDB) {
+        let query = "DELETE FROM transaction_committing WHERE bucket=? AND id=?";
+        let params = (COMMITTING_BUCKET, self.id.to_cql());
+        if let Err(err) = db.execute(query, params).await {
+            log::error!(target: "scylladb",
+                action = "delete_transaction_committing",
+                uid = self.uid.to_string(),
+                id = self.id.to_string();
+                "{}", err,
+            );
+        }
+    }
+
+    // unix ms after which the prepared spend can not be canceled by the caller, it is
+    // canceled or committed by the recovery job then. None for other kinds or no window.
+    pub fn cancel_deadline(&self) -> Option<i64> {
+        let window = cancel_window_ms();
+        if window > 0 && self.kind == TransactionKind::Spend.as_ref() {
+            Some(xid_ms(&self.id) + window)
+        } else {
+            None
+        }
+    }
+
+    // 409 if the cancel deadline passed, the transaction stays prepared.
+    pub fn check_cancel_deadline(&self, now_ms: i64) -> Result<(), HTTPError> {
+        match self.cancel_deadline() {
+            Some(deadline) if now_ms > deadline => {
+                let mut err = HTTPError::new(
+                    409,
+                    format!(
+                        "Transaction {} can not be canceled after the deadline",
+                        self.id
+                    ),
+                );
+                err.data = Some(serde_json::json!({
+                    "uid": self.uid.to_string(),
+                    "id": self.id.to_string(),
+                    "cancel_deadline": deadline,
+                }));
+                Err(err)
             }
-            Ok(())
+            _ => Ok(()),
         }
-        .boxed();
+    }
 
-        let (a, b, c) = join!(fut_payee, fut_sys, fut_sub);
-        let mut errs: Vec<String> = Vec::new();
-        if let Err(err) = a {
-            errs.push(err.to_string());
+    async fn save_cancel_deadline(&self, db: &scylladb::ScyllaDB) {
+        if self.cancel_deadline().is_none() {
+            return;
         }
-        if let Err(err) = b {
-            errs.push(err.to_string());
+
+        let query = "INSERT INTO transaction_expiring (day,id,uid) VALUES (?,?,?)";
+        let params = (day_of(&self.id), self.id.to_cql(), self.uid.to_cql());
+        if let Err(err) = db.execute(query, params).await {
+            log::error!(target: "scylladb",
+                action = "save_transaction_expiring",
+                uid = self.uid.to_string(),
+                id = self.id.to_string();
+                "{}", err,
+            );
         }
-        if let Err(err) = c {
-            errs.push(err.to_string());
+    }
+
+    pub async fn delete_cancel_deadline(&self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+        let query = "DELETE FROM transaction_expiring WHERE day=? AND id=?";
+        let params = (day_of(&self.id), self.id.to_cql());
+        db.execute(query, params).await?;
+        Ok(())
+    }
+
+    // returns (uid, id) of the spends prepared on the day and before the id in ascending order,
+    // they may have been committed or canceled.
+    pub async fn list_expiring(
+        db: &scylladb::ScyllaDB,
+        day: i32,
+        before: xid::Id,
+        page_size: u16,
+        page_token: Option<xid::Id>,
+    ) -> anyhow::Result<Vec<(xid::Id, xid::Id)>> {
+        let token = page_token.unwrap_or(xid::Id([0u8; 12]));
+        let query = db.list_query(
+            "SELECT id,uid FROM transaction_expiring WHERE day=? AND id>? AND id<? LIMIT ?",
+        );
+        let params = (day, token.to_cql(), before.to_cql(), page_size as i32);
+        let rows = db.execute_iter(query, params).await?;
+
+        let fields = vec!["id".to_string(), "uid".to_string()];
+        let mut res: Vec<(xid::Id, xid::Id)> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut cols = ColumnsMap::with_capacity(2);
+            cols.fill(row, &fields)?;
+            res.push((cols.get_as("uid")?, cols.get_as("id")?));
         }
+        Ok(res)
+    }
 
-        if errs.is_empty() {
-            self.set_status(db, 2, 3).await?;
-            let _ = PayeeTransaction::new(self.payee, self.id, self.uid)
+    // returns (uid, id) of the partly committed transactions in ascending order of id.
+    pub async fn list_committing(
+        db: &scylladb::ScyllaDB,
+        page_size: u16,
+        page_token: Option<xid::Id>,
+    ) -> anyhow::Result<Vec<(xid::Id, xid::Id)>> {
+        let token = page_token.unwrap_or(xid::Id([0u8; 12]));
+        let query = db.list_query(
+            "SELECT id,uid FROM transaction_committing WHERE bucket=? AND id>? LIMIT ?",
+        );
+        let params = (COMMITTING_BUCKET, token.to_cql(), page_size as i32);
+        let rows = db.execute_iter(query, params).await?;
+
+        let fields = vec!["id".to_string(), "uid".to_string()];
+        let mut res: Vec<(xid::Id, xid::Id)> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut cols = ColumnsMap::with_capacity(2);
+            cols.fill(row, &fields)?;
+            res.push((cols.get_as("uid")?, cols.get_as("id")?));
+        }
+        Ok(res)
+    }
+
+    // marks the transaction for recovery and returns an InvariantError.
+    async fn invariant_error(&self, db: &scylladb::ScyllaDB, reason: String) -> anyhow::Error {
+        log::error!(target: "scylladb",
+            action = "invariant_violation",
+            uid = self.uid.to_string(),
+            id = self.id.to_string(),
+            status = self.status;
+            "{}", reason,
+        );
+
+        let query =
+            "INSERT INTO transaction_recovery (uid,id,status,reason,created_at) VALUES (?,?,?,?,?)";
+        let params = (
+            self.uid.to_cql(),
+            self.id.to_cql(),
+            self.status,
+            reason.to_cql(),
+            unix_ms() as i64,
+        );
+        if let Err(err) = db.execute(query, params).await {
+            log::error!(target: "scylladb",
+                action = "mark_transaction_recovery",
+                uid = self.uid.to_string(),
+                id = self.id.to_string();
+                "{}", err,
+            );
+        }
+
+        // HTTPError from anyhow::Error is 500 with the message.
+        InvariantError {
+            uid: self.uid,
+            id: self.id,
+            reason,
+        }
+        .into()
+    }
+
+    // credits are keyed by (uid, txn) and saved with IF NOT EXISTS,
+    // so that they are written exactly once even on retried commits.
+    async fn save_credits(&self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+        let mut credits = self.credits();
+        Credit::save_all(db, &mut credits).await
+    }
+
+    // not repaired on the already committed path, counters can not be written exactly once.
+    async fn save_payer_payee_total(&self, db: &scylladb::ScyllaDB) {
+        if let Err(err) =
+            PayerPayeeTotal::incr(db, self.uid, self.payee, self.amount, self.anonymous).await
+        {
+            log::error!(target: "scylladb",
+                action = "save_payer_payee_total",
+                uid = self.uid.to_string(),
+                id = self.id.to_string(),
+                payee = self.payee.to_string();
+                "{}", err,
+            );
+        }
+    }
+
+    async fn save_system_daily_total(&self, db: &scylladb::ScyllaDB) {
+        if let Err(err) =
+            SystemDailyTotal::incr(db, day_of(&self.id), &self.kind, self.amount, self.sys_fee)
+                .await
+        {
+            log::error!(target: "scylladb",
+                action = "save_system_daily_total",
+                uid = self.uid.to_string(),
+                id = self.id.to_string();
+                "{}", err,
+            );
+        }
+    }
+
+    async fn save_daily_txn_stats(&self, db: &scylladb::ScyllaDB) {
+        if let Err(err) = DailyTxnStats::incr(db, self).await {
+            log::error!(target: "scylladb",
+                action = "save_daily_txn_stats",
+                uid = self.uid.to_string(),
+                id = self.id.to_string();
+                "{}", err,
+            );
+        }
+    }
+
+    // index the committed transaction for payee and sub payees, so that list_by_payee is consistent.
+    // the payees' balances applied by the commit are recorded as their running balances.
+    // the sync-to-payee-transaction binary is only used for backfill.
+    pub async fn save_payee_index(
+        &self,
+        db: &scylladb::ScyllaDB,
+        balances: &HashMap<xid::Id, Balance>,
+    ) {
+        let mut payees = vec![self.payee];
+        payees.extend(self.sub_payees().into_iter().map(|(uid, _)| uid));
+        for payee in payees {
+            if let Err(err) = PayeeTransaction::new(payee, self.id, self.uid)
+                .with_balance(balances.get(&payee).copied())
                 .save(db)
-                .await;
-            if let Some(sub_payee) = self.sub_payee {
-                let _ = PayeeTransaction::new(sub_payee, self.id, self.uid)
-                    .save(db)
-                    .await;
+                .await
+            {
+                log::error!(target: "scylladb",
+                    action = "save_payee_transaction",
+                    uid = self.uid.to_string(),
+                    id = self.id.to_string(),
+                    payee = payee.to_string();
+                    "{}", err,
+                );
             }
-            return Ok(Some(payee_wallet));
         }
 
-        Err(HTTPError::new(
-            500,
-            format!("committing transaction partly applied, errors: {:?}", errs),
-        )
-        .into())
+        if self.uid == SYS_ID && self.kind == TransactionKind::Award.as_ref() {
+            if let Err(err) = PayeeTransaction::new(self.payee, self.id, self.uid)
+                .save_system_award(db)
+                .await
+            {
+                log::error!(target: "scylladb",
+                    action = "save_payee_system_award",
+                    uid = self.uid.to_string(),
+                    id = self.id.to_string(),
+                    payee = self.payee.to_string();
+                    "{}", err,
+                );
+            }
+        }
+    }
+
+    // queues the income events of the payee and sub payees with enabled webhooks, they are
+    // delivered by the webhook job. not repaired on the already committed path.
+    async fn save_webhook_events(&self, db: &scylladb::ScyllaDB, kind: &TransactionKind) {
+        let mut events = vec![(
+            self.payee,
+            WEBHOOK_EVENT_INCOME,
+            kind.payee_income(self.payee, self.amount - self.sys_fee - self.sub_shares),
+        )];
+        events.extend(
+            self.sub_payees()
+                .into_iter()
+                .map(|(uid, amount)| (uid, WEBHOOK_EVENT_SUB_SHARES, amount)),
+        );
+
+        for (uid, event_kind, amount) in events {
+            if uid == SYS_ID || amount <= 0 {
+                continue;
+            }
+
+            let mut webhook = WalletWebhook::with_pk(uid);
+            let res = match webhook.get_one(db).await {
+                Ok(true) if webhook.is_enabled() => {
+                    WebhookEvent::new(uid, event_kind, self.uid, self.id, amount)
+                        .save(db)
+                        .await
+                }
+                Ok(_) => Ok(()),
+                Err(err) => Err(err),
+            };
+            if let Err(err) = res {
+                log::error!(target: "scylladb",
+                    action = "save_webhook_event",
+                    uid = self.uid.to_string(),
+                    id = self.id.to_string(),
+                    payee = uid.to_string();
+                    "{}", err,
+                );
+            }
+        }
+    }
+
+    // pending_out is informational, failing to release it should not fail the commit.
+    async fn release_pending_out(&self, db: &scylladb::ScyllaDB) {
+        let mut payer_wallet = Wallet::with_pk(self.uid);
+        let mut retry = retry_lwt("release_pending_out");
+        while retry.next().await {
+            if payer_wallet.get_one(db).await.is_err() {
+                break;
+            }
+            payer_wallet.pending_out = (payer_wallet.pending_out - self.amount).max(0);
+            if let Ok(true) = payer_wallet.update_pending_out(db).await {
+                return;
+            }
+        }
+
+        log::error!(target: "scylladb",
+            action = "release_pending_out",
+            uid = self.uid.to_string(),
+            id = self.id.to_string(),
+            amount = self.amount;
+            "release pending_out failed",
+        );
+    }
+
+    async fn add_sub_payee_income(
+        &self,
+        db: &scylladb::ScyllaDB,
+        mac: &HMacTag,
+        uid: xid::Id,
+        amount: i64,
+        written: &AtomicBool,
+    ) -> anyhow::Result<Option<Balance>> {
+        let mut ok = false;
+        let mut sub_wallet = Wallet::with_pk(uid);
+        let res = sub_wallet.get_one(db).await;
+        if res.is_err() {
+            // create payee wallet if not exists
+            let res = sub_wallet.save(db).await?;
+            log::info!(target: "scylladb",
+                action = "create_wallet",
+                uid = sub_wallet.uid.to_string(),
+                txn_uid = self.uid.to_string(),
+                txn_id = self.id.to_string(),
+                txn_kind = self.kind,
+                result = res;
+                "",
+            );
+        }
+
+        let mut retry = retry_lwt("commit_transaction");
+        while retry.next().await {
+            // applied by an interrupted commit, its running balance is unknown.
+            if self.leg_applied(db, uid, &sub_leg(&uid)).await? {
+                written.store(true, Ordering::Relaxed);
+                return Ok(None);
+            }
+            sub_wallet.verify_checksum(mac)?;
+            sub_wallet.income += amount;
+            sub_wallet.next_checksum(mac, self.id);
+
+            written.store(true, Ordering::Relaxed);
+            ok = sub_wallet
+                .commit_balance(db, &self.leg_mark(&sub_leg(&uid)))
+                .await?;
+            if ok {
+                break;
+            }
+            sub_wallet.get_one(db).await?;
+        }
+
+        if !ok {
+            log::error!(target: "scylladb",
+                action = "commit_transaction",
+                uid = self.uid.to_string(),
+                id = self.id.to_string(),
+                wallet = sub_wallet.uid.to_string();
+                "sub_wallet committing failed",
+            );
+            return Err(anyhow!(
+                "sub_wallet committing failed, {}",
+                sub_wallet.uid.to_string()
+            ));
+        }
+        Ok(Some(Balance::of(&sub_wallet)))
     }
 
     pub async fn list(
         db: &scylladb::ScyllaDB,
         uid: xid::Id,
-        select_fields: Vec<String>,
+        select_fields: FieldSet<TransactionField>,
         page_size: u16,
         page_token: Option<xid::Id>,
         kind: Option<TransactionKind>,
     ) -> anyhow::Result<Vec<Self>> {
-        let fields = Self::select_fields(select_fields, true)?;
+        let fields = Self::select_fields(select_fields, true);
 
         let token = match page_token {
             Some(id) => id,
             None => MAX_ID,
         };
 
-        let rows = if let Some(kind) = kind {
-            let query = format!(
-                    "SELECT {} FROM transaction WHERE uid=? AND kind=? AND id<? LIMIT ? ALLOW FILTERING USING TIMEOUT 3s",
-                    fields.clone().join(","));
-            let params = (
-                uid.to_cql(),
-                kind.to_string(),
-                token.to_cql(),
-                page_size as i32,
-            );
-            db.execute_iter(query, params).await?
-        } else {
-            let query = format!(
-                "SELECT {} FROM transaction WHERE uid=? AND id<? LIMIT ? USING TIMEOUT 3s",
-                fields.clone().join(",")
-            );
-            let params = (uid.to_cql(), token.to_cql(), page_size as i32);
-            db.execute_iter(query, params).await?
-        };
+        if let Some(kind) = kind {
+            return Self::list_by_kind(db, uid, fields, kind, page_size, page_token).await;
+        }
+
+        let query = db.list_query(&format!(
+            "SELECT {} FROM transaction WHERE uid=? AND id<? LIMIT ?",
+            fields.clone().join(",")
+        ));
+        let params = (uid.to_cql(), token.to_cql(), page_size as i32);
+        let rows = db.execute_iter(query, params).await?;
 
         let mut res: Vec<Self> = Vec::with_capacity(rows.len());
         for row in rows {
@@ -879,43 +2319,254 @@ impl Transaction {
             res.push(doc);
         }
 
-        Ok(res)
+        Ok(res)
+    }
+
+    // reads the ids from transaction_by_kind, then the transactions in the payer's partition.
+    async fn list_by_kind(
+        db: &scylladb::ScyllaDB,
+        uid: xid::Id,
+        fields: Vec<String>,
+        kind: TransactionKind,
+        page_size: u16,
+        page_token: Option<xid::Id>,
+    ) -> anyhow::Result<Vec<Self>> {
+        let ids = TransactionByKind::list(db, uid, kind.as_ref(), page_size, page_token).await?;
+        if ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let query = db.list_query(&format!(
+            "SELECT {} FROM transaction WHERE uid=? AND id IN ?",
+            fields.join(",")
+        ));
+        let mut res: Vec<Self> = Vec::with_capacity(ids.len());
+        // scylla limits the number of clustering keys restricted by IN.
+        for chunk in ids.chunks(100) {
+            let params = (
+                uid.to_cql(),
+                chunk
+                    .iter()
+                    .map(|id| id.to_cql())
+                    .collect::<Vec<CqlValue>>(),
+            );
+            let rows = db.execute_iter(query.clone(), params).await?;
+            for row in rows {
+                let mut doc = Self::default();
+                let mut cols = ColumnsMap::with_capacity(fields.len());
+                cols.fill(row, &fields)?;
+                doc.fill(&cols);
+                doc._fields = fields.clone();
+                res.push(doc);
+            }
+        }
+        // a transaction deleted after a failed prepare may still be indexed, it is skipped.
+        res.sort_by(|a, b| b.id.partial_cmp(&a.id).unwrap());
+        Ok(res)
+    }
+
+    // returns the payer's prepared (status 1) transactions in descending order,
+    // and the page token of the next page, None if the partition is exhausted.
+    // at most MAX_PENDING_SCAN transactions are scanned per page, so a page may be short.
+    pub async fn list_pending(
+        db: &scylladb::ScyllaDB,
+        uid: xid::Id,
+        select_fields: FieldSet<TransactionField>,
+        page_size: u16,
+        page_token: Option<xid::Id>,
+    ) -> anyhow::Result<(Vec<Self>, Option<xid::Id>)> {
+        let fields = Self::select_fields(select_fields, true);
+        let mut token = match page_token {
+            Some(id) => id,
+            None => MAX_ID,
+        };
+
+        let query = db.list_query(&format!(
+            "SELECT {} FROM transaction WHERE uid=? AND id<? LIMIT ?",
+            fields.clone().join(",")
+        ));
+        let batch = (page_size as usize).clamp(100, MAX_PENDING_SCAN);
+        let mut scanned: usize = 0;
+        let mut res: Vec<Self> = Vec::with_capacity(page_size as usize);
+        while scanned < MAX_PENDING_SCAN {
+            let params = (uid.to_cql(), token.to_cql(), batch as i32);
+            let rows = db.execute_iter(query.as_str(), params).await?;
+            let exhausted = rows.len() < batch;
+            for row in rows {
+                let mut doc = Self::default();
+                let mut cols = ColumnsMap::with_capacity(fields.len());
+                cols.fill(row, &fields)?;
+                doc.fill(&cols);
+                doc._fields = fields.clone();
+                scanned += 1;
+                token = doc.id;
+                if doc.status == 1 {
+                    res.push(doc);
+                    if res.len() >= page_size as usize {
+                        return Ok((res, Some(token)));
+                    }
+                }
+            }
+
+            if exhausted {
+                return Ok((res, None));
+            }
+        }
+
+        Ok((res, Some(token)))
     }
 
+    // returns the payee's (and sub payee's) committed transactions in descending order,
+    // filtered by the kind if any, and the page token of the next page, None if exhausted.
+    // the payee index has no kind, so at most MAX_PENDING_SCAN transactions are scanned per
+    // page when filtering, and a page may be short.
     pub async fn list_by_payee(
         db: &scylladb::ScyllaDB,
         payee: xid::Id,
-        select_fields: Vec<String>,
+        select_fields: FieldSet<TransactionField>,
         page_size: u16,
         page_token: Option<xid::Id>,
-    ) -> anyhow::Result<Vec<Self>> {
-        let fields = Self::select_fields(select_fields, false)?;
+        kind: Option<TransactionKind>,
+    ) -> anyhow::Result<(Vec<Self>, Option<xid::Id>)> {
+        let fields = Self::select_fields(select_fields, false);
+        let query = format!(
+            "SELECT {} FROM transaction WHERE uid=? AND id=? LIMIT 1",
+            fields.join(",")
+        );
+        let batch = match kind {
+            None => page_size as usize,
+            Some(_) => (page_size as usize).clamp(100, MAX_PENDING_SCAN),
+        };
+
+        let mut token = page_token;
+        let mut scanned: usize = 0;
+        let mut res: Vec<Self> = Vec::with_capacity(page_size as usize);
+        while scanned < MAX_PENDING_SCAN {
+            let txns = PayeeTransaction::list(db, payee, batch as u16, token).await?;
+            let exhausted = txns.len() < batch;
+            for txn in txns {
+                scanned += 1;
+                token = Some(txn.txn);
+                let mut doc = Self::with_pk(txn.uid, txn.txn);
+                let params = (doc.uid.to_cql(), doc.id.to_cql());
+                let row = db.execute(query.as_str(), params).await?.single_row()?;
+                let mut cols = ColumnsMap::with_capacity(fields.len());
+                cols.fill(row, &fields)?;
+                doc.fill(&cols);
+                doc._fields = fields.clone();
+                doc._payee_balance = txn.balance();
+                if doc.is_kind_of(kind) {
+                    res.push(doc);
+                    if res.len() >= page_size as usize {
+                        return Ok((res, token));
+                    }
+                }
+            }
+
+            if exhausted {
+                return Ok((res, None));
+            }
+        }
 
-        let txns = PayeeTransaction::list(db, payee, page_size, page_token).await?;
+        Ok((res, token))
+    }
+
+    // returns the transactions derived from the referenced one in descending order,
+    // at most MAX_CHILDREN. a transaction deleted after a failed prepare is skipped.
+    pub async fn list_children(
+        db: &scylladb::ScyllaDB,
+        ref_uid: xid::Id,
+        ref_txn: xid::Id,
+        select_fields: FieldSet<TransactionField>,
+    ) -> anyhow::Result<Vec<Self>> {
+        let fields = Self::select_fields(select_fields, true);
         let query = format!(
             "SELECT {} FROM transaction WHERE uid=? AND id=? LIMIT 1",
             fields.join(",")
         );
-        let mut res: Vec<Self> = Vec::with_capacity(txns.len());
-        for txn in txns {
-            let mut doc = Self::with_pk(txn.uid, txn.txn);
-            let params = (doc.uid.to_cql(), doc.id.to_cql());
-            let row = db.execute(query.clone(), params).await?.single_row()?;
+
+        let refs = TransactionRef::list(db, ref_uid, ref_txn, MAX_CHILDREN, None).await?;
+        let mut res: Vec<Self> = Vec::with_capacity(refs.len());
+        for r in refs {
+            let params = (r.uid.to_cql(), r.id.to_cql());
+            if let Some(row) = db
+                .execute_iter(query.as_str(), params)
+                .await?
+                .into_iter()
+                .next()
+            {
+                let mut doc = Self::default();
+                let mut cols = ColumnsMap::with_capacity(fields.len());
+                cols.fill(row, &fields)?;
+                doc.fill(&cols);
+                doc._fields = fields.clone();
+                res.push(doc);
+            }
+        }
+        Ok(res)
+    }
+
+    // any kind matches None.
+    pub fn is_kind_of(&self, kind: Option<TransactionKind>) -> bool {
+        match kind {
+            Some(kind) => self.kind == kind.as_ref(),
+            None => true,
+        }
+    }
+
+    // streams transactions with ids in [start, end] of the payer, or of all payers if uid is None.
+    // rows are paged by the driver, the stream should be limited by the caller.
+    pub async fn stream(
+        db: &scylladb::ScyllaDB,
+        uid: Option<xid::Id>,
+        start: xid::Id,
+        end: xid::Id,
+    ) -> anyhow::Result<impl Stream<Item = anyhow::Result<Self>>> {
+        let fields = Self::fields();
+        let rows = match uid {
+            Some(uid) => {
+                let query = format!(
+                    "SELECT {} FROM transaction WHERE uid=? AND id>=? AND id<=?",
+                    fields.join(",")
+                );
+                let params = (uid.to_cql(), start.to_cql(), end.to_cql());
+                db.stream(query, params).await?
+            }
+            None => {
+                let query = db.scan_query(&format!(
+                    "SELECT {} FROM transaction WHERE id>=? AND id<=? ALLOW FILTERING",
+                    fields.join(",")
+                ));
+                let params = (start.to_cql(), end.to_cql());
+                db.stream(query, params).await?
+            }
+        };
+
+        Ok(rows.map(move |row| {
+            let mut doc = Self::default();
             let mut cols = ColumnsMap::with_capacity(fields.len());
-            cols.fill(row, &fields)?;
+            cols.fill(row?, &fields)?;
             doc.fill(&cols);
             doc._fields = fields.clone();
-            res.push(doc);
-        }
-
-        Ok(res)
+            Ok(doc)
+        }))
     }
 
+    // returns the first award from the system to the payee, it carries the referral payload.
+    // awards committed before payee_system_award existed are found by scanning payee_transaction,
+    // and then indexed.
     pub async fn first_from_system(
         db: &scylladb::ScyllaDB,
         payee: xid::Id,
+        select_fields: FieldSet<TransactionField>,
     ) -> anyhow::Result<Self> {
-        let query = "SELECT payee,txn,uid FROM payee_transaction WHERE payee=? AND uid=? LIMIT 1000 ALLOW FILTERING BYPASS CACHE USING TIMEOUT 3s";
+        if let Some(txn) = PayeeTransaction::first_system_award(db, payee).await? {
+            let mut doc = Self::with_pk(SYS_ID, txn);
+            doc.get_one(db, select_fields).await?;
+            return Ok(doc);
+        }
+
+        let query = db.scan_query("SELECT payee,txn,uid FROM payee_transaction WHERE payee=? AND uid=? LIMIT 1000 ALLOW FILTERING");
         let params = (payee.to_cql(), SYS_ID.to_cql());
         let rows = db.execute_iter(query, params).await?;
 
@@ -931,9 +2582,9 @@ impl Transaction {
         res.sort_by(|a, b| a.txn.partial_cmp(&b.txn).unwrap());
         for txn in res {
             let mut doc = Self::with_pk(txn.uid, txn.txn);
-            doc.get_one(db, vec!["kind".to_string(), "payload".to_string()])
-                .await?;
+            doc.get_one(db, select_fields).await?;
             if doc.kind == TransactionKind::Award.as_ref() {
+                txn.save_system_award(db).await?;
                 return Ok(doc);
             }
         }
@@ -947,6 +2598,7 @@ mod tests {
     // use faster_hex::hex_string;
 
     use crate::conf;
+    use crate::db::{CreditKind, SYS_FEE_RATE};
 
     use super::*;
 
@@ -956,6 +2608,276 @@ mod tests {
         res.unwrap()
     }
 
+    #[test]
+    fn check_amount_works() {
+        assert!(TransactionKind::Spend.check_amount(1).is_ok());
+        assert!(TransactionKind::Spend.check_amount(0).is_err());
+        assert!(TransactionKind::Spend.check_amount(-1).is_err());
+
+        let max = TransactionKind::Withdraw.max_amount();
+        assert!(TransactionKind::Withdraw.check_amount(max).is_ok());
+        let err = TransactionKind::Withdraw.check_amount(max + 1).unwrap_err();
+        assert_eq!(400, err.code);
+        let data = err.data.unwrap();
+        assert_eq!("withdraw", data["kind"]);
+        assert_eq!(max, data["max_amount"]);
+
+        let limits = HashMap::from([("unknown".to_string(), 100)]);
+        assert!(set_max_amounts(&limits).is_err());
+        let limits = HashMap::from([("refund".to_string(), 0)]);
+        assert!(set_max_amounts(&limits).is_err());
+        let limits = HashMap::from([("refund".to_string(), 1_000_000)]);
+        set_max_amounts(&limits).unwrap();
+        assert_eq!(1_000_000, TransactionKind::Refund.max_amount());
+    }
+
+    #[test]
+    fn check_credits_works() {
+        assert_eq!(1, TransactionKind::Withdraw.min_credits());
+        assert_eq!(0, TransactionKind::Spend.min_credits());
+
+        let mut wallet = Wallet::with_pk(xid::new());
+        wallet.award = 100;
+        let err = TransactionKind::Redpacket
+            .check_credits(&wallet)
+            .unwrap_err();
+        assert_eq!(403, err.code);
+        let data = err.data.unwrap();
+        assert_eq!("min_credits", data["policy"]);
+        assert_eq!("redpacket", data["kind"]);
+        assert_eq!(1, data["min_credits"]);
+        assert_eq!(0, data["credits"]);
+        assert!(TransactionKind::Redpacket
+            .check_credits(&Wallet::with_pk(SYS_ID))
+            .is_ok());
+
+        let limits = HashMap::from([("unknown".to_string(), 1)]);
+        assert!(set_min_credits(&limits).is_err());
+        let limits = HashMap::from([("redpacket".to_string(), -1)]);
+        assert!(set_min_credits(&limits).is_err());
+        assert_eq!(1, TransactionKind::Redpacket.min_credits());
+
+        let limits = HashMap::from([("redpacket".to_string(), 3)]);
+        set_min_credits(&limits).unwrap();
+        wallet.credits = 2;
+        assert!(TransactionKind::Redpacket.check_credits(&wallet).is_err());
+        wallet.credits = 3;
+        assert!(TransactionKind::Redpacket.check_credits(&wallet).is_ok());
+
+        let limits = HashMap::from([("redpacket".to_string(), 0)]);
+        set_min_credits(&limits).unwrap();
+        wallet.credits = 0;
+        assert!(TransactionKind::Redpacket.check_credits(&wallet).is_ok());
+
+        let limits = HashMap::from([("redpacket".to_string(), 1)]);
+        set_min_credits(&limits).unwrap();
+    }
+
+    #[test]
+    fn cancel_deadline_works() {
+        let mut txn = Transaction::with_uid(xid::new());
+        txn.id = xid::new();
+        txn.kind = TransactionKind::Spend.as_ref().to_string();
+        let prepared_at = xid_ms(&txn.id);
+        assert!(txn.cancel_deadline().is_none());
+        assert!(txn.check_cancel_deadline(i64::MAX).is_ok());
+
+        set_cancel_window(3600);
+        assert_eq!(Some(prepared_at + 3600 * 1000), txn.cancel_deadline());
+        assert!(txn.check_cancel_deadline(prepared_at).is_ok());
+        assert!(txn.check_cancel_deadline(prepared_at + 3600 * 1000).is_ok());
+        let err = txn
+            .check_cancel_deadline(prepared_at + 3600 * 1000 + 1)
+            .unwrap_err();
+        assert_eq!(409, err.code);
+
+        // only spends have the deadline.
+        txn.kind = TransactionKind::Sponsor.as_ref().to_string();
+        assert!(txn.cancel_deadline().is_none());
+        set_cancel_window(0);
+    }
+
+    #[test]
+    fn commit_legs_works() {
+        let payee = xid::new();
+        let sub = xid::new();
+        let mut txn = Transaction {
+            uid: xid::new(),
+            id: xid::new(),
+            payee,
+            kind: "sponsor".to_string(),
+            amount: 100,
+            sys_fee: 10,
+            sub_shares: 20,
+            shares: vec![(sub, 2000)],
+            ..Default::default()
+        };
+        let sub_leg = format!("sub:{}", sub);
+        assert_eq!(
+            vec![
+                LEG_PAYEE.to_string(),
+                LEG_SYSTEM.to_string(),
+                sub_leg.clone()
+            ],
+            txn.commit_legs_of()
+        );
+        assert_eq!(txn.commit_legs_of(), txn.pending_legs());
+
+        txn.legs.insert(LEG_PAYEE.to_string());
+        txn.legs.insert(sub_leg);
+        assert_eq!(vec![LEG_SYSTEM.to_string()], txn.pending_legs());
+
+        // the system takes the fee as the payee.
+        let txn = Transaction {
+            uid: xid::new(),
+            payee: SYS_ID,
+            kind: "spend".to_string(),
+            amount: 100,
+            sys_fee: 10,
+            ..Default::default()
+        };
+        assert_eq!(vec![LEG_PAYEE.to_string()], txn.commit_legs_of());
+    }
+
+    #[test]
+    fn sweep_balance_works() {
+        let mut wallet = Wallet {
+            uid: xid::new(),
+            award: 10,
+            topup: 20,
+            income: 5,
+            ..Default::default()
+        };
+        assert!(TransactionKind::Sweep
+            .sub_payer_balance(&mut wallet, 36)
+            .is_err());
+        TransactionKind::Sweep
+            .sub_payer_balance(&mut wallet, 35)
+            .unwrap();
+        assert_eq!((0, 0, 0), (wallet.award, wallet.topup, wallet.income));
+        assert!(TransactionKind::Sweep
+            .sub_payer_balance(&mut wallet, 1)
+            .is_err());
+
+        let mut sys_wallet = Wallet::with_pk(SYS_ID);
+        TransactionKind::Sweep
+            .add_payee_balance(&mut sys_wallet, 35)
+            .unwrap();
+        assert_eq!(35, sys_wallet.income);
+    }
+
+    #[test]
+    fn redpacket_balance_works() {
+        let uid = xid::new();
+        let kind = TransactionKind::Redpacket;
+        assert!(kind.check_payer(uid).is_ok());
+        assert!(kind.check_payer(SYS_ID).is_ok());
+        assert!(kind.check_payee(uid).is_ok());
+        assert!(kind.check_payee(SYS_ID).is_ok());
+        assert!(kind.check_sub_payee(uid).is_err());
+        assert_eq!(1_000_000, kind.max_amount());
+
+        // the sender escrows to the system's award.
+        let mut wallet = Wallet {
+            uid,
+            award: 10,
+            topup: 20,
+            credits: 1,
+            ..Default::default()
+        };
+        let mut sys_wallet = Wallet::with_pk(SYS_ID);
+        kind.sub_payer_balance(&mut wallet, 15).unwrap();
+        kind.add_payee_balance(&mut sys_wallet, 15).unwrap();
+        assert_eq!((0, 15, 0), (wallet.award, wallet.topup, wallet.income));
+        assert_eq!(15, sys_wallet.award);
+
+        // recipients claim shares as award.
+        let mut recipient = Wallet::with_pk(xid::new());
+        kind.sub_payer_balance(&mut sys_wallet, 10).unwrap();
+        kind.add_payee_balance(&mut recipient, 10).unwrap();
+        assert_eq!(5, sys_wallet.award);
+        assert_eq!(10, recipient.award);
+
+        kind.rollback_payer_balance(&mut sys_wallet, 10).unwrap();
+        assert_eq!(15, sys_wallet.award);
+        assert_eq!((0, 0), kind.fee_and_shares(100, 0, &[]));
+
+        let txn = Transaction {
+            uid,
+            id: xid::new(),
+            payee: SYS_ID,
+            kind: kind.to_string(),
+            status: 3,
+            amount: 15,
+            ..Default::default()
+        };
+        let credits = txn.credits();
+        assert_eq!(1, credits.len());
+        assert_eq!(CreditKind::Payout.to_string(), credits[0].kind);
+        assert_eq!("payer.redpacket", credits[0].description);
+    }
+
+    #[test]
+    fn is_kind_of_works() {
+        let txn = Transaction {
+            kind: TransactionKind::Sponsor.to_string(),
+            ..Default::default()
+        };
+        assert!(txn.is_kind_of(None));
+        assert!(txn.is_kind_of(Some(TransactionKind::Sponsor)));
+        assert!(!txn.is_kind_of(Some(TransactionKind::Subscribe)));
+    }
+
+    #[test]
+    fn payee_income_works() {
+        let payee = xid::new();
+        assert_eq!(90, TransactionKind::Sponsor.payee_income(payee, 90));
+        assert_eq!(90, TransactionKind::Spend.payee_income(payee, 90));
+        assert_eq!(0, TransactionKind::Award.payee_income(payee, 90));
+        assert_eq!(0, TransactionKind::Topup.payee_income(payee, 90));
+        assert_eq!(0, TransactionKind::Chargeback.payee_income(payee, 90));
+        assert_eq!(90, TransactionKind::Chargeback.payee_income(SYS_ID, 90));
+        assert_eq!(0, TransactionKind::Sponsor.payee_income(payee, -1));
+    }
+
+    #[test]
+    fn chargeback_balance_works() {
+        let uid = xid::new();
+        let kind = TransactionKind::Chargeback;
+        assert!(kind.check_payer(uid).is_ok());
+        assert!(kind.check_payer(SYS_ID).is_ok());
+        assert!(kind.check_sub_payee(uid).is_err());
+        assert!(!kind.rules().disputable());
+        assert!(TransactionKind::Spend.rules().disputable());
+        assert!(!TransactionKind::Award.rules().disputable());
+
+        // the refund is paid from the system's income as topup.
+        let mut sys_wallet = Wallet {
+            income: 100,
+            ..Wallet::with_pk(SYS_ID)
+        };
+        let mut payer = Wallet::with_pk(uid);
+        kind.sub_payer_balance(&mut sys_wallet, 30).unwrap();
+        kind.add_payee_balance(&mut payer, 30).unwrap();
+        assert_eq!(70, sys_wallet.income);
+        assert_eq!((0, 30, 0), (payer.award, payer.topup, payer.income));
+
+        // the clawback is forced regardless of the balance and credits.
+        let mut payee = Wallet {
+            uid: xid::new(),
+            income: 10,
+            ..Default::default()
+        };
+        payee._held = 20;
+        kind.sub_payer_balance(&mut payee, 25).unwrap();
+        kind.add_payee_balance(&mut sys_wallet, 25).unwrap();
+        assert_eq!((0, -15, 0), (payee.award, payee.topup, payee.income));
+        assert_eq!(95, sys_wallet.income);
+
+        kind.rollback_payer_balance(&mut sys_wallet, 30).unwrap();
+        assert_eq!(125, sys_wallet.income);
+    }
+
     #[test]
     fn transaction_kind_works() {
         {
@@ -966,6 +2888,10 @@ mod tests {
             assert_eq!("subscribe", TransactionKind::Subscribe.as_ref());
             assert_eq!("withdraw", TransactionKind::Withdraw.as_ref());
             assert_eq!("refund", TransactionKind::Refund.as_ref());
+            assert_eq!("adjustment", TransactionKind::Adjustment.as_ref());
+            assert_eq!("sweep", TransactionKind::Sweep.as_ref());
+            assert_eq!("redpacket", TransactionKind::Redpacket.as_ref());
+            assert_eq!("chargeback", TransactionKind::Chargeback.as_ref());
             assert_eq!(
                 TransactionKind::Award,
                 TransactionKind::from_str("award").unwrap()
@@ -996,6 +2922,9 @@ mod tests {
             assert!(TransactionKind::Subscribe.check_payer(SYS_ID).is_err());
             assert!(TransactionKind::Withdraw.check_payer(SYS_ID).is_err());
             assert!(TransactionKind::Refund.check_payer(SYS_ID).is_err());
+
+            assert!(TransactionKind::Adjustment.check_payer(uid).is_ok());
+            assert!(TransactionKind::Adjustment.check_payer(SYS_ID).is_ok());
         }
 
         // check_payee
@@ -1003,10 +2932,12 @@ mod tests {
             assert!(TransactionKind::Spend.check_payee(SYS_ID).is_ok());
             assert!(TransactionKind::Withdraw.check_payee(SYS_ID).is_ok());
             assert!(TransactionKind::Refund.check_payee(SYS_ID).is_ok());
+            assert!(TransactionKind::Sweep.check_payee(SYS_ID).is_ok());
 
             assert!(TransactionKind::Spend.check_payee(uid).is_err());
             assert!(TransactionKind::Withdraw.check_payee(uid).is_err());
             assert!(TransactionKind::Refund.check_payee(uid).is_err());
+            assert!(TransactionKind::Sweep.check_payee(uid).is_err());
 
             assert!(TransactionKind::Award.check_payee(uid).is_ok());
             assert!(TransactionKind::Topup.check_payee(uid).is_ok());
@@ -1017,6 +2948,9 @@ mod tests {
             assert!(TransactionKind::Topup.check_payee(SYS_ID).is_err());
             assert!(TransactionKind::Sponsor.check_payee(SYS_ID).is_err());
             assert!(TransactionKind::Subscribe.check_payee(SYS_ID).is_err());
+
+            assert!(TransactionKind::Adjustment.check_payee(uid).is_ok());
+            assert!(TransactionKind::Adjustment.check_payee(SYS_ID).is_ok());
         }
 
         // check_sub_payee
@@ -1050,6 +2984,27 @@ mod tests {
                 .sub_payer_balance(&mut sys_wallet, 100)
                 .is_err());
             assert_eq!(-300, sys_wallet.balance());
+            assert!(TransactionKind::Adjustment
+                .sub_payer_balance(&mut sys_wallet, 100)
+                .is_ok());
+            assert_eq!(-300, sys_wallet.award);
+            assert!(TransactionKind::Adjustment
+                .rollback_payer_balance(&mut sys_wallet, 100)
+                .is_ok());
+            assert_eq!(-300, sys_wallet.balance());
+
+            // adjustment does not require credits and can not overdraw
+            let mut wallet = Wallet::with_pk(xid::new());
+            wallet.award = 50;
+            wallet.income = 50;
+            assert!(TransactionKind::Adjustment
+                .sub_payer_balance(&mut wallet, 101)
+                .is_err());
+            assert!(TransactionKind::Adjustment
+                .sub_payer_balance(&mut wallet, 80)
+                .is_ok());
+            assert_eq!(0, wallet.award);
+            assert_eq!(20, wallet.income);
 
             // user wallet without credits
             let mut wallet = Wallet::with_pk(xid::new());
@@ -1245,75 +3200,503 @@ mod tests {
         {
             assert_eq!(
                 (1i64, 0i64),
-                TransactionKind::Withdraw.fee_and_shares(1, 0, false)
+                TransactionKind::Withdraw.fee_and_shares(1, 0, &[])
             );
             assert_eq!(
                 (1i64, 0i64),
-                TransactionKind::Withdraw.fee_and_shares(1000, 0, false)
+                TransactionKind::Withdraw.fee_and_shares(1000, 0, &[])
             );
             assert_eq!(
                 (10i64, 0i64),
-                TransactionKind::Withdraw.fee_and_shares(10000, 10000, false)
+                TransactionKind::Withdraw.fee_and_shares(10000, 10000, &[])
             );
 
             assert_eq!(
                 (1i64, 0i64),
-                TransactionKind::Sponsor.fee_and_shares(1, 0, false)
+                TransactionKind::Sponsor.fee_and_shares(1, 0, &[])
             );
             assert_eq!(
                 (1i64, 0i64),
-                TransactionKind::Sponsor.fee_and_shares(1, 10000, false)
+                TransactionKind::Sponsor.fee_and_shares(1, 10000, &[])
             );
             assert_eq!(
                 (30i64, 0i64),
-                TransactionKind::Sponsor.fee_and_shares(100, 9999, false)
+                TransactionKind::Sponsor.fee_and_shares(100, 9999, &[])
             );
             assert_eq!(
                 (27i64, 0i64),
-                TransactionKind::Sponsor.fee_and_shares(100, 10000, false)
+                TransactionKind::Sponsor.fee_and_shares(100, 10000, &[])
             );
             assert_eq!(
                 (24i64, 0i64),
-                TransactionKind::Sponsor.fee_and_shares(100, 100000, false)
+                TransactionKind::Sponsor.fee_and_shares(100, 100000, &[])
             );
             assert_eq!(
                 (15i64, 0i64),
-                TransactionKind::Sponsor.fee_and_shares(100, 100000000, false)
+                TransactionKind::Sponsor.fee_and_shares(100, 100000000, &[])
             );
             assert_eq!(
                 (15i64, 0i64),
-                TransactionKind::Sponsor.fee_and_shares(101, 100000000, false)
+                TransactionKind::Sponsor.fee_and_shares(101, 100000000, &[])
             );
 
             assert_eq!(
                 (1i64, 0i64),
-                TransactionKind::Subscribe.fee_and_shares(1, 0, true)
+                TransactionKind::Subscribe.fee_and_shares(1, 0, &[income_fee_rate(0)])
             );
             assert_eq!(
                 (1i64, 0i64),
-                TransactionKind::Subscribe.fee_and_shares(1, 10000, true)
+                TransactionKind::Subscribe.fee_and_shares(1, 10000, &[income_fee_rate(10000)])
             );
             assert_eq!(
                 (30i64, 30i64),
-                TransactionKind::Sponsor.fee_and_shares(100, 9999, true)
+                TransactionKind::Sponsor.fee_and_shares(100, 9999, &[income_fee_rate(9999)])
             );
             assert_eq!(
                 (27i64, 27i64),
-                TransactionKind::Subscribe.fee_and_shares(100, 10000, true)
+                TransactionKind::Subscribe.fee_and_shares(100, 10000, &[income_fee_rate(10000)])
             );
             assert_eq!(
                 (24i64, 24i64),
-                TransactionKind::Subscribe.fee_and_shares(100, 100000, true)
+                TransactionKind::Subscribe.fee_and_shares(100, 100000, &[income_fee_rate(100000)])
             );
             assert_eq!(
                 (15i64, 15i64),
-                TransactionKind::Subscribe.fee_and_shares(100, 100000000, true)
+                TransactionKind::Subscribe.fee_and_shares(
+                    100,
+                    100000000,
+                    &[income_fee_rate(100000000)]
+                )
             );
             assert_eq!(
                 (15i64, 15i64),
-                TransactionKind::Subscribe.fee_and_shares(101, 100000000, true)
+                TransactionKind::Subscribe.fee_and_shares(
+                    101,
+                    100000000,
+                    &[income_fee_rate(100000000)]
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn fee_and_shares_golden_works() {
+        // generated by the f32 implementation replaced by the basis points one, that is
+        // `(amount as f32 * rate) as i64` with rates 0.001 and 0.3 down to 0.09.
+        // (kind, amount, credits, has_sub_payee, sys_fee, sub_shares)
+        let vectors: [(TransactionKind, i64, i64, bool, i64, i64); 21] = [
+            (TransactionKind::Withdraw, 1, 0, false, 1, 0),
+            (TransactionKind::Withdraw, 999, 0, false, 1, 0),
+            (TransactionKind::Withdraw, 1001, 0, false, 1, 0),
+            (TransactionKind::Withdraw, 2000, 0, false, 2, 0),
+            (TransactionKind::Withdraw, 123456789, 0, false, 123456, 0),
+            (TransactionKind::Sponsor, 3, 0, false, 1, 0),
+            (TransactionKind::Sponsor, 3, 0, true, 1, 0),
+            (TransactionKind::Sponsor, 10, 0, true, 3, 3),
+            (TransactionKind::Sponsor, 33, 0, true, 9, 9),
+            (TransactionKind::Sponsor, 100, 10000, true, 27, 27),
+            (TransactionKind::Sponsor, 100, 1000000, true, 21, 21),
+            (TransactionKind::Sponsor, 300, 1000000, true, 62, 62),
+            (TransactionKind::Sponsor, 16777217, 0, false, 5033165, 0),
+            (
+                TransactionKind::Sponsor,
+                99999999,
+                10000,
+                true,
+                27000002,
+                27000002,
+            ),
+            (TransactionKind::Subscribe, 100, 100000, false, 24, 0),
+            (TransactionKind::Subscribe, 1000, 10000000, false, 180, 0),
+            (
+                TransactionKind::Subscribe,
+                123456789,
+                1000000,
+                false,
+                25925926,
+                0,
+            ),
+            (
+                TransactionKind::Subscribe,
+                1000000000000,
+                10000000000,
+                false,
+                90000007168,
+                0,
+            ),
+            (TransactionKind::Spend, 123456789, 0, false, 0, 0),
+            (TransactionKind::Award, 123456789, 0, false, 0, 0),
+            (TransactionKind::Topup, 123456789, 0, false, 0, 0),
+        ];
+        // the vectors where the f32 results differ from the basis points ones: f32 loses
+        // precision on large amounts, and 0.21f32 is below 0.21.
+        // (kind, amount, credits, has_sub_payee, sys_fee, sub_shares)
+        let differs: [(TransactionKind, i64, i64, bool, i64, i64); 4] = [
+            (TransactionKind::Sponsor, 300, 1000000, true, 63, 63),
+            (
+                TransactionKind::Sponsor,
+                99999999,
+                10000,
+                true,
+                26999999,
+                26999999,
+            ),
+            (
+                TransactionKind::Subscribe,
+                123456789,
+                1000000,
+                false,
+                25925925,
+                0,
+            ),
+            (
+                TransactionKind::Subscribe,
+                1000000000000,
+                10000000000,
+                false,
+                90000000000,
+                0,
+            ),
+        ];
+
+        let mut differed = 0;
+        for (kind, amount, credits, has_sub_payee, sys_fee, sub_shares) in vectors {
+            let shares: Vec<u16> = if has_sub_payee {
+                vec![income_fee_rate(credits)]
+            } else {
+                vec![]
+            };
+            let expected = match differs
+                .iter()
+                .find(|d| d.0 == kind && d.1 == amount && d.2 == credits && d.3 == has_sub_payee)
+            {
+                Some(d) => {
+                    differed += 1;
+                    assert_ne!((sys_fee, sub_shares), (d.4, d.5));
+                    (d.4, d.5)
+                }
+                None => (sys_fee, sub_shares),
+            };
+            assert_eq!(
+                expected,
+                kind.fee_and_shares(amount, credits, &shares),
+                "{} {} {} {}",
+                kind.as_ref(),
+                amount,
+                credits,
+                has_sub_payee
+            );
+        }
+        assert_eq!(differs.len(), differed);
+    }
+
+    // vectors are shared with other language ports, see tests/vectors/balance_math.json.
+    #[test]
+    fn balance_math_vectors_works() {
+        let doc: serde_json::Value =
+            serde_json::from_str(std::include_str!("../../tests/vectors/balance_math.json"))
+                .unwrap();
+
+        for v in doc["fee_and_shares"].as_array().unwrap() {
+            let kind = TransactionKind::from_str(v["kind"].as_str().unwrap()).unwrap();
+            let shares: Vec<u16> = v["shares"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|s| s.as_u64().unwrap() as u16)
+                .collect();
+            assert_eq!(
+                (
+                    v["sys_fee"].as_i64().unwrap(),
+                    v["sub_shares"].as_i64().unwrap()
+                ),
+                kind.fee_and_shares(
+                    v["amount"].as_i64().unwrap(),
+                    v["credits"].as_i64().unwrap(),
+                    &shares
+                ),
+                "{}",
+                v
             );
         }
+
+        for v in doc["sub_payer_balance"].as_array().unwrap() {
+            let kind = TransactionKind::from_str(v["kind"].as_str().unwrap()).unwrap();
+            let mut wallet = Wallet::with_pk(xid::new());
+            wallet.award = v["wallet"]["award"].as_i64().unwrap();
+            wallet.topup = v["wallet"]["topup"].as_i64().unwrap();
+            wallet.income = v["wallet"]["income"].as_i64().unwrap();
+            wallet.credits = v["wallet"]["credits"].as_i64().unwrap();
+            wallet.max_overdraw = v["wallet"]["max_overdraw"].as_i64();
+
+            let res = kind.sub_payer_balance(&mut wallet, v["amount"].as_i64().unwrap());
+            assert_eq!(v["ok"].as_bool().unwrap(), res.is_ok(), "{}", v);
+            if res.is_ok() {
+                assert_eq!(
+                    (
+                        v["expect"]["award"].as_i64().unwrap(),
+                        v["expect"]["topup"].as_i64().unwrap(),
+                        v["expect"]["income"].as_i64().unwrap()
+                    ),
+                    (wallet.award, wallet.topup, wallet.income),
+                    "{}",
+                    v
+                );
+            }
+        }
+    }
+
+    mod balance_props {
+        use proptest::prelude::*;
+
+        use super::*;
+
+        const PAYER_KINDS: [TransactionKind; 6] = [
+            TransactionKind::Spend,
+            TransactionKind::Sponsor,
+            TransactionKind::Subscribe,
+            TransactionKind::Adjustment,
+            TransactionKind::Withdraw,
+            TransactionKind::Refund,
+        ];
+
+        // topup is overdrawn within the wallet's limit only.
+        fn arb_wallet() -> impl Strategy<Value = Wallet> {
+            (
+                0i64..1_000_000_000,
+                -10_000i64..1_000_000_000,
+                0i64..1_000_000_000,
+                0i64..100_000,
+                0i64..10_000,
+            )
+                .prop_map(|(award, topup, income, credits, max_overdraw)| {
+                    let mut wallet = Wallet::with_pk(xid::Id([1; 12]));
+                    wallet.award = award;
+                    wallet.topup = topup.max(-max_overdraw);
+                    wallet.income = income;
+                    wallet.credits = credits;
+                    wallet.max_overdraw = Some(max_overdraw);
+                    wallet
+                })
+        }
+
+        proptest! {
+            #[test]
+            fn sub_payer_balance_conserves_total(
+                wallet in arb_wallet(),
+                k in 0usize..PAYER_KINDS.len(),
+                amount in -10i64..2_000_000_000,
+            ) {
+                let kind = &PAYER_KINDS[k];
+                let mut w = wallet.clone();
+                match kind.sub_payer_balance(&mut w, amount) {
+                    Ok(()) => {
+                        prop_assert!(amount > 0);
+                        // the amount is taken from the buckets, nothing is created or lost.
+                        prop_assert_eq!(
+                            wallet.award + wallet.topup + wallet.income - amount,
+                            w.award + w.topup + w.income
+                        );
+                        // an overdrawn topup may be paid off by income.
+                        prop_assert!(w.award <= wallet.award);
+                        prop_assert!(w.income <= wallet.income);
+                        prop_assert!(w.award >= 0);
+                        prop_assert!(w.income >= 0);
+                        prop_assert!(w.award + w.topup + w.income >= -wallet.overdraw_limit());
+                        match kind {
+                            // only income can be withdrawn.
+                            TransactionKind::Withdraw => {
+                                prop_assert_eq!((wallet.award, wallet.topup), (w.award, w.topup));
+                            }
+                            // only topup can be refunded.
+                            TransactionKind::Refund => {
+                                prop_assert_eq!((wallet.award, wallet.income), (w.award, w.income));
+                                prop_assert!(w.topup >= 0);
+                            }
+                            // only spend may overdraw.
+                            TransactionKind::Spend => {}
+                            _ => {
+                                prop_assert!(w.award + w.topup + w.income >= 0);
+                            }
+                        }
+                    }
+                    Err(_) => {
+                        // nothing is changed on failure.
+                        prop_assert_eq!(
+                            (wallet.award, wallet.topup, wallet.income),
+                            (w.award, w.topup, w.income)
+                        );
+                    }
+                }
+            }
+
+            #[test]
+            fn rollback_after_sub_restores_balance(
+                wallet in arb_wallet(),
+                k in 0usize..PAYER_KINDS.len(),
+                amount in 1i64..2_000_000_000,
+            ) {
+                let kind = &PAYER_KINDS[k];
+                let mut w = wallet.clone();
+                if kind.sub_payer_balance(&mut w, amount).is_ok() {
+                    kind.rollback_payer_balance(&mut w, amount).unwrap();
+                    prop_assert_eq!(wallet.balance(), w.balance());
+                    match kind {
+                        // rolled back to the same balance.
+                        TransactionKind::Withdraw | TransactionKind::Refund => {
+                            prop_assert_eq!(
+                                (wallet.award, wallet.topup, wallet.income),
+                                (w.award, w.topup, w.income)
+                            );
+                        }
+                        // rolled back to topup, award and income are never increased.
+                        _ => {
+                            prop_assert!(w.award <= wallet.award);
+                            prop_assert!(w.income <= wallet.income);
+                        }
+                    }
+                }
+            }
+
+            #[test]
+            fn user_wallet_can_not_pay_award_or_topup(
+                wallet in arb_wallet(),
+                amount in 1i64..1_000_000,
+            ) {
+                for kind in [TransactionKind::Award, TransactionKind::Topup] {
+                    let mut w = wallet.clone();
+                    prop_assert!(kind.sub_payer_balance(&mut w, amount).is_err());
+                    prop_assert_eq!(wallet.balance(), w.balance());
+                }
+            }
+
+            #[test]
+            fn fee_and_shares_rounding(
+                amount in 1i64..1_000_000_000_000,
+                credits in 0i64..100_000_000_000,
+                shares in proptest::collection::vec(0u16..3300, 0..3),
+            ) {
+                let (sys_fee, sub_shares) =
+                    TransactionKind::Withdraw.fee_and_shares(amount, credits, &shares);
+                prop_assert_eq!(0, sub_shares);
+                prop_assert_eq!((amount * SYS_FEE_RATE as i64 / BPS_DENOMINATOR).max(1), sys_fee);
+
+                for kind in [TransactionKind::Sponsor, TransactionKind::Subscribe] {
+                    let rate = income_fee_rate(credits) as i64;
+                    let (sys_fee, sub_shares) = kind.fee_and_shares(amount, credits, &shares);
+                    prop_assert!(sys_fee >= 1);
+                    prop_assert!(sys_fee <= amount);
+                    // rounded toward zero, and never more than one unit lost per recipient.
+                    prop_assert!(sys_fee * BPS_DENOMINATOR <= (amount * rate).max(BPS_DENOMINATOR));
+                    prop_assert!(sys_fee * BPS_DENOMINATOR > amount * rate - BPS_DENOMINATOR);
+                    let total_bps: i64 = shares.iter().map(|s| *s as i64).sum();
+                    prop_assert!(sub_shares * BPS_DENOMINATOR <= amount * total_bps);
+                    prop_assert!(sub_shares + shares.len() as i64 >= amount * total_bps / BPS_DENOMINATOR);
+                    prop_assert!(sub_shares <= amount);
+
+                    // more amount never gets less fee.
+                    let (next_fee, next_shares) = kind.fee_and_shares(amount + 1, credits, &shares);
+                    prop_assert!(next_fee >= sys_fee);
+                    prop_assert!(next_shares >= sub_shares);
+                }
+
+                for kind in [TransactionKind::Spend, TransactionKind::Award, TransactionKind::Topup] {
+                    prop_assert_eq!((0, 0), kind.fee_and_shares(amount, credits, &shares));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn invariant_error_works() {
+        let mut wallet = Wallet::with_pk(xid::new());
+        wallet.award = 100;
+        let res = TransactionKind::Spend.sub_payer_balance(&mut wallet, 0);
+        assert!(res.is_err());
+        assert!(res.unwrap_err().to_string().contains("Invalid amount 0"));
+        assert_eq!(100, wallet.award);
+
+        let err: anyhow::Error = InvariantError {
+            uid: xid::new(),
+            id: xid::new(),
+            reason: "No sub_payee with sub_shares".to_string(),
+        }
+        .into();
+        assert!(err.downcast_ref::<InvariantError>().is_some());
+        let err = HTTPError::from(err);
+        assert_eq!(500, err.code);
+        assert!(err.message.contains("No sub_payee with sub_shares"));
+    }
+
+    #[test]
+    fn sub_payees_works() {
+        let a = xid::new();
+        let b = xid::new();
+        assert_eq!(
+            (15i64, 25i64),
+            TransactionKind::Sponsor.fee_and_shares(101, 100000000, &[1000, 1500])
+        );
+
+        let mut txn = Transaction {
+            uid: xid::new(),
+            payee: xid::new(),
+            kind: TransactionKind::Sponsor.to_string(),
+            amount: 101,
+            status: 3,
+            ..Default::default()
+        };
+        assert!(txn.sub_payees().is_empty());
+
+        // legacy transaction
+        txn.sub_payee = Some(a);
+        assert!(txn.sub_payees().is_empty());
+        txn.sub_shares = 15;
+        assert_eq!(vec![(a, 15i64)], txn.sub_payees());
+
+        txn.sub_payee = None;
+        txn.sub_shares = 25;
+        txn.shares = vec![(a, 1000), (b, 1500)];
+        assert_eq!(vec![(a, 10i64), (b, 15i64)], txn.sub_payees());
+
+        let credits = txn.credits();
+        assert_eq!(4, credits.len());
+        assert_eq!(101 - 15 - 25, credits[1].amount);
+        assert_eq!(a, credits[2].uid);
+        assert_eq!(10, credits[2].amount);
+        assert_eq!(b, credits[3].uid);
+        assert_eq!(15, credits[3].amount);
+    }
+
+    #[test]
+    fn balance_works() {
+        let wallet = Wallet {
+            award: 100,
+            topup: 50,
+            income: -10,
+            ..Default::default()
+        };
+        let mut txn = Transaction::with_uid(xid::new());
+        assert_eq!(None, txn.balance());
+        txn.set_balance(&wallet);
+        assert_eq!(
+            Some(Balance {
+                award: 100,
+                topup: 50,
+                income: -10
+            }),
+            txn.balance()
+        );
+        txn.balance_topup = None;
+        assert_eq!(None, txn.balance());
+
+        let mut txn = Transaction::with_uid(SYS_ID);
+        txn.set_balance(&wallet);
+        assert_eq!(None, txn.balance());
+
+        let index = PayeeTransaction::new(xid::new(), xid::new(), xid::new());
+        assert_eq!(None, index.balance());
+        let index = index.with_balance(Some(Balance::of(&wallet)));
+        assert_eq!(Some(Balance::of(&wallet)), index.balance());
     }
 
     #[tokio::test(flavor = "current_thread")]
@@ -1357,6 +3740,15 @@ mod tests {
                 .await;
             assert!(res.is_err());
             assert!(res.unwrap_err().to_string().contains("Invalid sub_payee"));
+
+            txn.sub_payee = None;
+            txn.anonymous = true;
+            let res = txn
+                .prepare(&db, &mac, payee, TransactionKind::Award, 1)
+                .await;
+            assert!(res.is_err());
+            assert!(res.unwrap_err().to_string().contains("Invalid anonymous"));
+            txn.anonymous = false;
         }
 
         // prepare and commit
@@ -1404,6 +3796,42 @@ mod tests {
             first_txn = txn;
         }
 
+        // referenced transactions
+        {
+            let mut txn = Transaction::with_uid(SYS_ID);
+            txn.ref_txn = Some(first_txn.id);
+            let res = txn
+                .prepare(&db, &mac, payee, TransactionKind::Award, 10)
+                .await;
+            assert!(res.unwrap_err().to_string().contains("given together"));
+
+            txn.ref_uid = Some(payee);
+            let res = txn
+                .prepare(&db, &mac, payee, TransactionKind::Award, 10)
+                .await;
+            assert!(res.unwrap_err().to_string().contains("not found"));
+
+            txn.ref_uid = Some(SYS_ID);
+            txn.prepare(&db, &mac, payee, TransactionKind::Award, 10)
+                .await
+                .unwrap();
+            txn.commit(&db, &mac).await.unwrap();
+
+            let children = Transaction::list_children(&db, SYS_ID, first_txn.id, FieldSet::new())
+                .await
+                .unwrap();
+            assert_eq!(1, children.len());
+            assert_eq!(txn.id, children[0].id);
+            assert_eq!(Some(first_txn.id), children[0].ref_txn);
+            assert_eq!(Some(SYS_ID), children[0].ref_uid);
+            assert!(
+                Transaction::list_children(&db, SYS_ID, txn.id, FieldSet::new())
+                    .await
+                    .unwrap()
+                    .is_empty()
+            );
+        }
+
         // prepare and cancel
         {
             let mut payer_wallet = Wallet::with_pk(xid::new());
@@ -1443,6 +3871,7 @@ mod tests {
             assert_eq!(600, payer_wallet.award);
             assert_eq!(0, payer_wallet.topup);
             assert_eq!(2, payer_wallet.sequence);
+            assert_eq!(400, payer_wallet.pending_out);
             assert_eq!(txn.id, payer_wallet.txn);
             assert_eq!(1, txn.status);
 
@@ -1454,9 +3883,10 @@ mod tests {
             assert_eq!(600, payer_wallet.award);
             assert_eq!(400, payer_wallet.topup);
             assert_eq!(3, payer_wallet.sequence);
+            assert_eq!(0, payer_wallet.pending_out);
             assert_eq!(txn.id, payer_wallet.txn);
 
-            txn.get_one(&db, vec![]).await.unwrap();
+            txn.get_one(&db, FieldSet::new()).await.unwrap();
             assert_eq!(-2, txn.status);
 
             let mut txn: Transaction = Transaction::with_uid(payer_wallet.uid);
@@ -1494,6 +3924,7 @@ mod tests {
             assert_eq!(0, payer_wallet.award);
             assert_eq!(200, payer_wallet.topup);
             assert_eq!(6, payer_wallet.sequence);
+            assert_eq!(600, payer_wallet.pending_out);
             assert_eq!(txn2.id, payer_wallet.txn);
             assert_eq!(3, txn2.status);
 
@@ -1505,9 +3936,10 @@ mod tests {
             assert_eq!(0, payer_wallet.award);
             assert_eq!(800, payer_wallet.topup);
             assert_eq!(7, payer_wallet.sequence);
+            assert_eq!(0, payer_wallet.pending_out);
             assert_eq!(txn1.id, payer_wallet.txn);
 
-            txn1.get_one(&db, vec![]).await.unwrap();
+            txn1.get_one(&db, FieldSet::new()).await.unwrap();
             assert_eq!(-2, txn1.status);
         }
 
@@ -1541,19 +3973,55 @@ mod tests {
             assert_eq!(10, payer_wallet.credits);
             assert_eq!(1, payer_wallet.sequence);
 
+            let mut sim: Transaction = Transaction::with_uid(payer_wallet.uid);
+            let res = sim
+                .simulate(&db, &mac, payee_wallet.uid, TransactionKind::Sponsor, 100)
+                .await
+                .unwrap();
+            assert_eq!(100, res.amount);
+            assert_eq!(30, res.sys_fee);
+            assert_eq!(0, res.sub_shares);
+            assert_eq!(900, res.balance);
+            assert!(!res.overdraw);
+            assert!(payer_wallet.get_one(&db).await.is_ok());
+            assert_eq!(1000, payer_wallet.balance());
+            assert_eq!(1, payer_wallet.sequence);
+
             let mut txn: Transaction = Transaction::with_uid(payer_wallet.uid);
             txn.prepare(&db, &mac, payee_wallet.uid, TransactionKind::Sponsor, 100)
                 .await
                 .unwrap();
             txn.commit(&db, &mac).await.unwrap();
 
-            let mut credits = txn.credits();
+            let total = PayerPayeeTotal::get(&db, payer_wallet.uid, payee_wallet.uid)
+                .await
+                .unwrap();
+            assert_eq!(100, total.amount);
+            assert_eq!(1, total.txns);
+            let totals = PayerPayeeTotal::list_by_payee(&db, payee_wallet.uid, 10, None)
+                .await
+                .unwrap();
+            assert_eq!(vec![total.clone()], totals);
+            let income = PayerPayeeTotal::get_income(&db, payer_wallet.uid, payee_wallet.uid)
+                .await
+                .unwrap();
+            assert_eq!(total, income);
+
+            // daily totals are shared by all tests, only check they are counted.
+            let day = day_of(&txn.id);
+            let daily = SystemDailyTotal::list(&db, day, day).await.unwrap();
+            let sponsor = daily.iter().find(|t| t.kind == "sponsor").unwrap();
+            assert!(sponsor.amount >= 100);
+            assert!(sponsor.sys_fee >= txn.sys_fee);
+            assert!(sponsor.txns >= 1);
+
+            // credits are saved in commit
+            let credits = txn.credits();
             assert_eq!(2, credits.len());
             assert_eq!(100, credits[0].amount);
             assert_eq!("payout", credits[0].kind);
             assert_eq!(70, credits[1].amount);
             assert_eq!("income", credits[1].kind);
-            Credit::save_all(&db, &mut credits).await.unwrap();
 
             assert!(payer_wallet.get_one(&db).await.is_ok());
             assert_eq!(900, payer_wallet.award);
@@ -1577,6 +4045,13 @@ mod tests {
             assert_eq!(10, payee_wallet.credits);
             assert_eq!(1, payee_wallet.sequence);
 
+            // credits of sub_payee should be initialized before commit.
+            sub_payee_wallet.save(&db).await.unwrap();
+            let mut credit = Credit::with_pk(sub_payee_wallet.uid, xid::new());
+            credit.kind = CreditKind::Award.to_string();
+            credit.amount = 1;
+            credit.save(&db).await.unwrap();
+
             let mut txn: Transaction = Transaction::with_uid(payer_wallet.uid);
             txn.sub_payee = Some(sub_payee_wallet.uid);
             txn.prepare(&db, &mac, payee_wallet.uid, TransactionKind::Subscribe, 200)
@@ -1584,12 +4059,8 @@ mod tests {
                 .unwrap();
             txn.commit(&db, &mac).await.unwrap();
 
-            let mut credit = Credit::with_pk(sub_payee_wallet.uid, xid::new());
-            credit.kind = CreditKind::Award.to_string();
-            credit.amount = 1; // will be ignored.
-            credit.save(&db).await.unwrap();
-
-            let mut credits = txn.credits();
+            // credits are saved in commit
+            let credits = txn.credits();
             assert_eq!(3, credits.len());
             assert_eq!(200, credits[0].amount);
             assert_eq!("payout", credits[0].kind);
@@ -1597,7 +4068,6 @@ mod tests {
             assert_eq!("income", credits[1].kind);
             assert_eq!(60, credits[2].amount);
             assert_eq!("income", credits[2].kind);
-            Credit::save_all(&db, &mut credits).await.unwrap();
 
             assert!(payer_wallet.get_one(&db).await.is_ok());
             assert_eq!(700, payer_wallet.award);
@@ -1616,6 +4086,83 @@ mod tests {
             assert_eq!(60, sub_payee_wallet.balance());
             assert_eq!(61, sub_payee_wallet.credits);
             assert_eq!(1, sub_payee_wallet.sequence);
+
+            // payee index is written in commit
+            let index = PayeeTransaction::list(&db, payee_wallet.uid, 10, None)
+                .await
+                .unwrap();
+            assert_eq!(2, index.len());
+            assert_eq!(txn.id, index[0].txn);
+            assert_eq!(Some(Balance::of(&payee_wallet)), index[0].balance());
+            let index = PayeeTransaction::list(&db, sub_payee_wallet.uid, 10, None)
+                .await
+                .unwrap();
+            assert_eq!(1, index.len());
+            assert_eq!(txn.id, index[0].txn);
+            assert_eq!(payer_wallet.uid, index[0].uid);
+            assert_eq!(Some(Balance::of(&sub_payee_wallet)), index[0].balance());
+
+            // running balances of the payer are recorded in prepare
+            assert_eq!(Some(Balance::of(&payer_wallet)), txn.balance());
+
+            // income and shares listings are filtered by kind
+            for (payee, kind, expected) in [
+                (payee_wallet.uid, None, 2),
+                (payee_wallet.uid, Some(TransactionKind::Subscribe), 1),
+                (payee_wallet.uid, Some(TransactionKind::Sponsor), 1),
+                (payee_wallet.uid, Some(TransactionKind::Award), 0),
+                (sub_payee_wallet.uid, None, 1),
+                (sub_payee_wallet.uid, Some(TransactionKind::Subscribe), 1),
+                (sub_payee_wallet.uid, Some(TransactionKind::Sponsor), 0),
+            ] {
+                let (txns, next) =
+                    Transaction::list_by_payee(&db, payee, FieldSet::new(), 10, None, kind)
+                        .await
+                        .unwrap();
+                assert_eq!(expected, txns.len());
+                assert!(next.is_none());
+                assert!(txns.iter().all(|t| t.is_kind_of(kind)));
+            }
+            let (txns, next) = Transaction::list_by_payee(
+                &db,
+                payee_wallet.uid,
+                FieldSet::new(),
+                1,
+                None,
+                Some(TransactionKind::Subscribe),
+            )
+            .await
+            .unwrap();
+            assert_eq!(1, txns.len());
+            assert_eq!(txn.id, txns[0].id);
+            assert_eq!(Some(txn.id), next);
+            let (txns, next) = Transaction::list_by_payee(
+                &db,
+                payee_wallet.uid,
+                FieldSet::new(),
+                1,
+                next,
+                Some(TransactionKind::Subscribe),
+            )
+            .await
+            .unwrap();
+            assert!(txns.is_empty());
+            assert!(next.is_none());
+
+            // kind index is written in prepare
+            let txns = Transaction::list(
+                &db,
+                payer_wallet.uid,
+                FieldSet::new(),
+                10,
+                None,
+                Some(TransactionKind::Subscribe),
+            )
+            .await
+            .unwrap();
+            assert_eq!(1, txns.len());
+            assert_eq!(txn.id, txns[0].id);
+            assert_eq!(200, txns[0].amount);
         }
 
         {
@@ -1630,10 +4177,122 @@ mod tests {
                 .unwrap();
             txn.commit(&db, &mac).await.unwrap();
 
-            let first = Transaction::first_from_system(&db, first_txn.payee)
+            let first = Transaction::first_from_system(&db, first_txn.payee, FieldSet::new())
                 .await
                 .unwrap();
             assert_eq!(first_txn.id, first.id);
+            assert_eq!(
+                Some(first_txn.id),
+                PayeeTransaction::first_system_award(&db, first_txn.payee)
+                    .await
+                    .unwrap()
+            );
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn resume_commit_sub_leg_works() {
+        let db = get_db().await;
+        let mac = HMacTag::new([1u8; 32]);
+        // make sure system wallet exists.
+        {
+            let mut wallet: Wallet = Default::default();
+            wallet.save(&db).await.unwrap();
         }
+
+        let payer = xid::new();
+        let mut txn = Transaction::with_uid(SYS_ID);
+        txn.prepare(&db, &mac, payer, TransactionKind::Award, 1000)
+            .await
+            .unwrap();
+        txn.commit(&db, &mac).await.unwrap();
+
+        let sub_payee = xid::new();
+        let mut txn = Transaction::with_uid(payer);
+        txn.sub_payee = Some(sub_payee);
+        txn.prepare(&db, &mac, xid::new(), TransactionKind::Subscribe, 200)
+            .await
+            .unwrap();
+        let leg = sub_leg(&sub_payee);
+        assert!(txn.pending_legs().contains(&leg));
+        let (_, amount) = txn.sub_payees()[0];
+        assert!(amount > 0);
+
+        // the interrupted commit credited the sub payee but did not record the leg.
+        txn.add_sub_payee_income(&db, &mac, sub_payee, amount, &AtomicBool::new(false))
+            .await
+            .unwrap();
+        // another transaction of the sub payee overwrites wallet.txn meanwhile.
+        let mut award = Transaction::with_uid(SYS_ID);
+        award
+            .prepare(&db, &mac, sub_payee, TransactionKind::Award, 100)
+            .await
+            .unwrap();
+        award.commit(&db, &mac).await.unwrap();
+        let query = "UPDATE transaction SET status=?,updated_at=? WHERE uid=? AND id=?";
+        let params = (
+            2i8,
+            unix_ms() as i64 - COMMIT_LEASE_MS - 1000,
+            txn.uid.to_cql(),
+            txn.id.to_cql(),
+        );
+        db.execute(query, params).await.unwrap();
+
+        txn.resume_commit(&db, &mac).await.unwrap();
+        assert_eq!(3, txn.status);
+        assert!(txn.legs.contains(&leg));
+
+        let mut sub_wallet = Wallet::with_pk(sub_payee);
+        sub_wallet.get_one(&db).await.unwrap();
+        sub_wallet.verify_checksum(&mac).unwrap();
+        assert_eq!(amount, sub_wallet.income);
+        assert_eq!(100, sub_wallet.award);
+        assert_eq!(2, sub_wallet.sequence);
+        assert_eq!(award.id, sub_wallet.txn);
+        assert!(!Wallet::is_committing(&db, sub_payee, &txn.leg_mark(&leg))
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn expected_payer_works() {
+        let db = get_db().await;
+        let mac = HMacTag::new([1u8; 32]);
+        // make sure system wallet exists.
+        {
+            let mut wallet: Wallet = Default::default();
+            wallet.save(&db).await.unwrap();
+        }
+
+        let payer = xid::new();
+        let mut txn = Transaction::with_uid(SYS_ID);
+        txn.prepare(&db, &mac, payer, TransactionKind::Award, 1000)
+            .await
+            .unwrap();
+        txn.commit(&db, &mac).await.unwrap();
+
+        let mut txn = Transaction::with_uid(payer);
+        txn.prepare(&db, &mac, SYS_ID, TransactionKind::Spend, 300)
+            .await
+            .unwrap();
+        let mut payer_wallet = Wallet::with_pk(payer);
+        payer_wallet.get_one(&db).await.unwrap();
+
+        // stale sequence, neither committed nor canceled.
+        txn._expected_sequence = Some(payer_wallet.sequence - 1);
+        let err: HTTPError = txn.commit(&db, &mac).await.unwrap_err().into();
+        assert_eq!(409, err.code);
+        assert_eq!(payer_wallet.sequence, err.data.unwrap()["sequence"]);
+        let err: HTTPError = txn.cancel(&db, &mac).await.unwrap_err().into();
+        assert_eq!(409, err.code);
+        txn.get_one(&db, FieldSet::new()).await.unwrap();
+        assert_eq!(1, txn.status);
+
+        txn._expected_sequence = Some(payer_wallet.sequence);
+        txn._expected_balance = Some(700);
+        txn.commit(&db, &mac).await.unwrap();
+        assert_eq!(3, txn.status);
     }
 }
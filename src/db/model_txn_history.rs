@@ -0,0 +1,118 @@
+use scylla_orm::{ColumnsMap, CqlValue, ToCqlVal};
+use scylla_orm_macros::CqlOrm;
+
+use crate::db::scylladb;
+
+// max transitions listed of a transaction, a transaction has a few in its lifecycle.
+pub const MAX_TXN_HISTORY: u16 = 1000;
+
+// a status transition of a transaction, written by Transaction::set_status for support
+// investigations. transitions of a transaction are ordered by changed_at.
+#[derive(Debug, Default, Clone, CqlOrm, PartialEq)]
+pub struct TxnHistory {
+    pub uid: xid::Id,
+    pub id: xid::Id,
+    pub changed_at: i64, // unix ms
+    pub to_status: i8,
+    pub from_status: i8,
+    pub actor: String, // x-auth-user of the request or the job name, empty if unknown
+
+    pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+}
+
+impl TxnHistory {
+    pub async fn save(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let mut cols_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut vals_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut params: Vec<&CqlValue> = Vec::with_capacity(fields.len());
+        let cols = self.to();
+
+        for field in &fields {
+            cols_name.push(field);
+            vals_name.push("?");
+            params.push(cols.get(field).unwrap());
+        }
+
+        let query = format!(
+            "INSERT INTO txn_history ({}) VALUES ({})",
+            cols_name.join(","),
+            vals_name.join(",")
+        );
+
+        db.execute(query, params).await?;
+        Ok(())
+    }
+
+    // lists the transitions of the transaction, oldest first.
+    pub async fn list(
+        db: &scylladb::ScyllaDB,
+        uid: xid::Id,
+        id: xid::Id,
+    ) -> anyhow::Result<Vec<Self>> {
+        let fields = Self::fields();
+        let query = format!(
+            "SELECT {} FROM txn_history WHERE uid=? AND id=? LIMIT ?",
+            fields.join(",")
+        );
+        let params = (uid.to_cql(), id.to_cql(), MAX_TXN_HISTORY as i32);
+        let rows = db.execute_iter(query, params).await?;
+
+        let mut res: Vec<Self> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Self::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conf;
+
+    use super::*;
+
+    async fn get_db() -> scylladb::ScyllaDB {
+        let cfg = conf::Conf::new().unwrap_or_else(|err| panic!("config error: {}", err));
+        let res = scylladb::ScyllaDB::new(cfg.scylla, "walletbase_test").await;
+        res.unwrap()
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn txn_history_model_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+        let id = xid::new();
+
+        let mut history: Vec<TxnHistory> = Vec::new();
+        for (i, (from, to)) in [(0i8, 1i8), (1, 2), (2, 3)].into_iter().enumerate() {
+            let mut doc = TxnHistory {
+                uid,
+                id,
+                changed_at: 1000 + i as i64,
+                to_status: to,
+                from_status: from,
+                actor: "recovery".to_string(),
+                ..Default::default()
+            };
+            doc.save(&db).await.unwrap();
+            history.push(doc);
+        }
+
+        let res = TxnHistory::list(&db, uid, id).await.unwrap();
+        assert_eq!(history, res);
+        assert!(TxnHistory::list(&db, uid, xid::new())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -1,15 +1,43 @@
 use hmac::{Hmac, Mac};
 use sha3::Sha3_256;
+use std::{
+    collections::HashSet,
+    sync::atomic::{AtomicI64, AtomicU16, Ordering},
+};
 use subtle::ConstantTimeEq;
 
-use axum_web::erring::HTTPError;
-use scylla_orm::{ColumnsMap, CqlValue, ToCqlVal};
+use axum_web::{context::unix_ms, erring::HTTPError};
+use scylla_orm::{ColumnsMap, CqlValue, FromCqlVal, ToCqlVal};
 use scylla_orm_macros::CqlOrm;
 
-use crate::db::scylladb::{self, extract_applied};
+use crate::db::{
+    model_liability::balance_delta,
+    scylladb::{self, extract_applied},
+    Balance, Liability, PayeeTransaction, Transaction,
+};
 
 pub const SYS_ID: xid::Id = xid::Id([0u8; 12]);
-pub const SYS_FEE_RATE: f32 = 0.001; // 0.1%
+// fee rates are in basis points, 1 bp = 0.01%
+pub const BPS_DENOMINATOR: i64 = 10_000;
+pub const SYS_FEE_RATE: u16 = 10; // 0.1%
+
+// length of the HMAC tag of page tokens.
+pub const CURSOR_TAG_LEN: usize = 8;
+
+// user's wallet.topup can be negative to max overdraw, it is set from conf at startup.
+static MAX_OVERDRAW: AtomicI64 = AtomicI64::new(100);
+
+pub fn set_max_overdraw(val: i64) {
+    MAX_OVERDRAW.store(val, Ordering::Relaxed);
+}
+
+// number of recent outgoing transactions matched against the wallet's sequence on get_one,
+// 0 disables the check. it is set from conf at startup.
+static INTEGRITY_CHECK_DEPTH: AtomicU16 = AtomicU16::new(0);
+
+pub fn set_integrity_check_depth(depth: u16) {
+    INTEGRITY_CHECK_DEPTH.store(depth, Ordering::Relaxed);
+}
 
 #[derive(Debug, Default, Clone, CqlOrm)]
 pub struct Wallet {
@@ -21,22 +49,80 @@ pub struct Wallet {
     pub credits: i64,
     pub txn: xid::Id,
     pub checksum: Vec<u8>,
+    pub pending_out: i64, // prepared but uncommitted outgoing amount, not in checksum
+    pub max_overdraw: Option<i64>, // overrides the global max overdraw, not in checksum
+    pub closed_at: i64,   // unix ms when the wallet was closed, 0 for open wallets, not in checksum
+    pub updated_at: i64,  // unix ms when the wallet was last updated, not in checksum
 
     pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+    pub _pending_out: Option<i64>, // pending_out loaded from db, None if the column is null
+    pub _held: i64,           // amount of active holds, loaded by WalletHold::held_amount
+    pub _balance: Option<Balance>, // balances loaded from db, the base of liability deltas
 }
 
-pub fn income_fee_rate(credits: i64) -> f32 {
+pub fn income_fee_rate(credits: i64) -> u16 {
     match credits {
-        ..=9999 => 0.3,
-        10000..=99999 => 0.27,             // LV4
-        100000..=999999 => 0.24,           // LV5
-        1000000..=9999999 => 0.21,         // LV6
-        10000000..=99999999 => 0.18,       // LV7
-        100000000..=999999999 => 0.15,     // LV8
-        1000000000..=9999999999 => 0.12,   // LV9
-        10000000000..=99999999999 => 0.09, // LV10
-        _ => 0.09,
+        ..=9999 => 3000,
+        10000..=99999 => 2700,            // LV4
+        100000..=999999 => 2400,          // LV5
+        1000000..=9999999 => 2100,        // LV6
+        10000000..=99999999 => 1800,      // LV7
+        100000000..=999999999 => 1500,    // LV8
+        1000000000..=9999999999 => 1200,  // LV9
+        10000000000..=99999999999 => 900, // LV10
+        _ => 900,
+    }
+}
+
+// matches the wallet's sequence and last txn against its recent outgoing transactions,
+// given as (id, sequence, status). a transaction records the wallet's sequence before it is prepared,
+// preparing increases the sequence by 1 and canceling by 1 more. incoming transactions also
+// increase the sequence, so gaps are expected, but the sequence never repeats or goes backwards.
+pub fn match_sequence(
+    sequence: i64,
+    txn: xid::Id,
+    outgo: &[(xid::Id, i64, i8)],
+) -> Result<(), String> {
+    // preparing transactions may not have been applied to the wallet.
+    let mut applied: Vec<(i64, xid::Id)> = outgo
+        .iter()
+        .filter(|t| t.2 != 0)
+        .map(|t| (t.1, t.0))
+        .collect();
+    applied.sort();
+    for w in applied.windows(2) {
+        if w[0].0 == w[1].0 {
+            return Err(format!(
+                "transactions {} and {} have the same sequence {}",
+                w[0].1, w[1].1, w[0].0
+            ));
+        }
+    }
+    if let Some((seq, id)) = applied.last() {
+        if *seq >= sequence {
+            return Err(format!(
+                "transaction {} sequence {} is ahead of wallet sequence {}",
+                id, seq, sequence
+            ));
+        }
+    }
+
+    if let Some((id, seq, status)) = outgo.iter().find(|t| t.0 == txn) {
+        let max_step = if *status < 0 { 2 } else { 1 };
+        let step = sequence - seq;
+        if step < 1 || step > max_step {
+            return Err(format!(
+                "wallet sequence {} does not match its last transaction {} sequence {}",
+                sequence, id, seq
+            ));
+        }
     }
+    Ok(())
+}
+
+// amount * bps / 10000, rounded toward zero.
+pub fn apply_bps(amount: i64, bps: u16) -> i64 {
+    (amount as i128 * bps as i128 / BPS_DENOMINATOR as i128) as i64
 }
 
 impl Wallet {
@@ -55,8 +141,76 @@ impl Wallet {
         self.award + self.topup + self.income
     }
 
+    pub fn is_closed(&self) -> bool {
+        self.closed_at > 0
+    }
+
+    // closed wallets can not prepare transactions except the sweep on closing.
+    pub fn check_open(&self) -> Result<(), HTTPError> {
+        if self.is_closed() {
+            return Err(closed_error(self.uid));
+        }
+        Ok(())
+    }
+
+    // checks the caller's stale state of the wallet against the loaded one, 409 with the
+    // current sequence and balances in data on mismatch.
+    pub fn check_expected(
+        &self,
+        sequence: Option<i64>,
+        balance: Option<i64>,
+    ) -> Result<(), HTTPError> {
+        let sequence_ok = sequence.map_or(true, |v| v == self.sequence);
+        let balance_ok = balance.map_or(true, |v| v == self.balance());
+        if sequence_ok && balance_ok {
+            return Ok(());
+        }
+
+        Err(HTTPError {
+            code: 409,
+            message: format!(
+                "wallet {} changed, sequence {}, balance {}",
+                self.uid,
+                self.sequence,
+                self.balance()
+            ),
+            data: Some(serde_json::json!({
+                "uid": self.uid.to_string(),
+                "sequence": self.sequence,
+                "balance": self.balance(),
+                "award": self.award,
+                "topup": self.topup,
+                "income": self.income,
+            })),
+        })
+    }
+
+    // dead wallets have zero balances and credits, nothing in flight, and no activity since
+    // idle_before, they can be archived. the system wallet is never dead.
+    pub fn is_dead(&self, idle_before: i64) -> bool {
+        !self.is_system()
+            && self.award == 0
+            && self.topup == 0
+            && self.income == 0
+            && self.credits == 0
+            && self.pending_out == 0
+            && self.updated_at > 0
+            && self.updated_at < idle_before
+    }
+
+    pub fn overdraw_limit(&self) -> i64 {
+        self.max_overdraw
+            .unwrap_or_else(|| MAX_OVERDRAW.load(Ordering::Relaxed))
+    }
+
+    // legacy wallets created before checksums have no checksum at sequence 0,
+    // see backfill_checksum.
+    pub fn has_checksum(&self) -> bool {
+        self.sequence > 0 || !self.checksum.is_empty()
+    }
+
     pub fn verify_checksum(&self, mac: &HMacTag) -> anyhow::Result<()> {
-        if self.sequence == 0 {
+        if !self.has_checksum() {
             return Ok(());
         }
         let tag = mac.tag64(self);
@@ -83,18 +237,238 @@ impl Wallet {
             fields.join(",")
         );
         let params = (self.uid.to_cql(),);
-        let res = db.execute(query, params).await?.single_row()?;
+        let res = match db.execute(query.clone(), params).await?.single_row() {
+            Ok(row) => row,
+            // archived wallets are restored transparently on access.
+            Err(err) => {
+                if self.is_system() || !Self::restore(db, self.uid).await? {
+                    return Err(err.into());
+                }
+                let params = (self.uid.to_cql(),);
+                db.execute(query, params).await?.single_row()?
+            }
+        };
 
         let mut cols = ColumnsMap::with_capacity(fields.len());
         cols.fill(res, &fields)?;
         self.fill(&cols);
+        self._pending_out = if cols.has("pending_out") {
+            Some(self.pending_out)
+        } else {
+            None
+        };
+        self._balance = Some(Balance::of(self));
+
+        // the system wallet is updated by most transactions, it is not checked.
+        let depth = INTEGRITY_CHECK_DEPTH.load(Ordering::Relaxed);
+        if depth > 0 && !self.is_system() && self.sequence > 0 {
+            if let Some(issue) = self.check_sequence(db, depth).await? {
+                log::error!(target: "integrity",
+                    action = "check_sequence",
+                    uid = self.uid.to_string(),
+                    sequence = self.sequence,
+                    txn = self.txn.to_string();
+                    "{}", issue,
+                );
+                return Err(HTTPError::new(
+                    500,
+                    format!("wallet {} integrity check failed: {}", self.uid, issue),
+                )
+                .into());
+            }
+        }
 
         Ok(())
     }
 
+    // returns the mismatch if the sequence can not be matched against the last `depth` outgoing transactions.
+    pub async fn check_sequence(
+        &self,
+        db: &scylladb::ScyllaDB,
+        depth: u16,
+    ) -> anyhow::Result<Option<String>> {
+        let query = db.list_query("SELECT id,sequence,status FROM transaction WHERE uid=? LIMIT ?");
+        let params = (self.uid.to_cql(), depth as i32);
+        let rows = db.execute_iter(query, params).await?;
+
+        let fields = vec![
+            "id".to_string(),
+            "sequence".to_string(),
+            "status".to_string(),
+        ];
+        let mut outgo: Vec<(xid::Id, i64, i8)> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Transaction::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            outgo.push((doc.id, doc.sequence, doc.status));
+        }
+
+        Ok(match_sequence(self.sequence, self.txn, &outgo).err())
+    }
+
+    // returns the mismatch if the txn that last updated the wallet can not be found.
+    // it is either the wallet's outgoing transaction, or an incoming one found by the payee index.
+    // incoming transactions are indexed after committed, a recently updated wallet may be reported.
+    pub async fn check_last_txn(&self, db: &scylladb::ScyllaDB) -> anyhow::Result<Option<String>> {
+        if self.sequence == 0 {
+            return Ok(None);
+        }
+
+        let query = "SELECT status FROM transaction WHERE uid=? AND id=? LIMIT 1";
+        let params = (self.uid.to_cql(), self.txn.to_cql());
+        if !db.execute_iter(query, params).await?.is_empty() {
+            return Ok(None);
+        }
+
+        let query = "SELECT uid FROM payee_transaction WHERE payee=? AND txn=? LIMIT 1";
+        let params = (self.uid.to_cql(), self.txn.to_cql());
+        let rows = db.execute_iter(query, params).await?;
+        let payer = match rows.into_iter().next() {
+            None => return Ok(Some(format!("last transaction {} not found", self.txn))),
+            Some(row) => {
+                let mut doc = PayeeTransaction::default();
+                let mut cols = ColumnsMap::with_capacity(1);
+                cols.fill(row, &vec!["uid".to_string()])?;
+                doc.fill(&cols);
+                doc.uid
+            }
+        };
+
+        let query = "SELECT status FROM transaction WHERE uid=? AND id=? LIMIT 1";
+        let params = (payer.to_cql(), self.txn.to_cql());
+        let rows = db.execute_iter(query, params).await?;
+        match rows.into_iter().next() {
+            None => Ok(Some(format!(
+                "last transaction {} of payer {} not found",
+                self.txn, payer
+            ))),
+            Some(row) => {
+                let mut doc = Transaction::default();
+                let mut cols = ColumnsMap::with_capacity(1);
+                cols.fill(row, &vec!["status".to_string()])?;
+                doc.fill(&cols);
+                // the payee is credited after the transaction is committing.
+                if doc.status < 2 {
+                    return Ok(Some(format!(
+                        "last transaction {} of payer {} is not committed, status {}",
+                        self.txn, payer, doc.status
+                    )));
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    // scans wallets in token order, the page token is the last scanned uid.
+    pub async fn scan(
+        db: &scylladb::ScyllaDB,
+        page_size: u16,
+        page_token: Option<xid::Id>,
+    ) -> anyhow::Result<Vec<Self>> {
+        let fields = Self::fields();
+        let rows = match page_token {
+            Some(uid) => {
+                let query = db.list_query(&format!(
+                    "SELECT {} FROM wallet WHERE token(uid)>token(?) LIMIT ?",
+                    fields.join(",")
+                ));
+                let params = (uid.to_cql(), page_size as i32);
+                db.execute_iter(query, params).await?
+            }
+            None => {
+                let query =
+                    db.list_query(&format!("SELECT {} FROM wallet LIMIT ?", fields.join(",")));
+                let params = (page_size as i32,);
+                db.execute_iter(query, params).await?
+            }
+        };
+
+        let mut res: Vec<Self> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Self::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc._fields = fields.clone();
+            doc._pending_out = if cols.has("pending_out") {
+                Some(doc.pending_out)
+            } else {
+                None
+            };
+            doc._balance = Some(Balance::of(&doc));
+            res.push(doc);
+        }
+
+        Ok(res)
+    }
+
+    // writes the checksum of a legacy wallet without changing its sequence and balances,
+    // returns false if the wallet was updated meanwhile, it has a checksum then.
+    pub async fn backfill_checksum(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        mac: &HMacTag,
+    ) -> anyhow::Result<bool> {
+        if self.has_checksum() {
+            return Ok(false);
+        }
+
+        let checksum = mac.tag64(self);
+        let query = "UPDATE wallet SET checksum=? WHERE uid=? IF sequence=0 AND award=? AND topup=? AND income=? AND txn=?";
+        let params = (
+            checksum.to_cql(),
+            self.uid.to_cql(),
+            self.award,
+            self.topup,
+            self.income,
+            self.txn.to_cql(),
+        );
+        let res = db.execute(query, params).await?;
+        let ok = extract_applied(res);
+        if ok {
+            self.checksum = checksum;
+        }
+        Ok(ok)
+    }
+
     // should be call after next_checksum
     pub async fn update_balance(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
-        let query = "UPDATE wallet SET sequence=?,award=?,topup=?,income=?,txn=?,checksum=? WHERE uid=? IF sequence=?";
+        let updated_at = unix_ms() as i64;
+        let query = "UPDATE wallet SET sequence=?,award=?,topup=?,income=?,txn=?,checksum=?,pending_out=?,updated_at=? WHERE uid=? IF sequence=? AND pending_out=?";
+        let params = (
+            self.sequence,
+            self.award,
+            self.topup,
+            self.income,
+            self.txn.to_cql(),
+            self.checksum.to_cql(),
+            self.pending_out,
+            updated_at,
+            self.uid.to_cql(),
+            self.sequence - 1,
+            self._pending_out,
+        );
+
+        let res = db.execute(query.to_string(), params).await?;
+        let ok = extract_applied(res);
+        if ok {
+            self.balance_updated(db, updated_at).await;
+        }
+        Ok(ok)
+    }
+
+    // updates the balance for a leg of the committing transaction, and adds the leg's mark to
+    // the committing set of the wallet in the same LWT. the mark is cleared after the leg is
+    // recorded on the transaction, see is_committing. should be call after next_checksum.
+    pub async fn commit_balance(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        mark: &str,
+    ) -> anyhow::Result<bool> {
+        let updated_at = unix_ms() as i64;
+        let query = "UPDATE wallet SET sequence=?,award=?,topup=?,income=?,txn=?,checksum=?,pending_out=?,updated_at=?,committing=committing+? WHERE uid=? IF sequence=? AND pending_out=?";
         let params = (
             self.sequence,
             self.award,
@@ -102,17 +476,281 @@ impl Wallet {
             self.income,
             self.txn.to_cql(),
             self.checksum.to_cql(),
+            self.pending_out,
+            updated_at,
+            vec![mark.to_string()],
             self.uid.to_cql(),
             self.sequence - 1,
+            self._pending_out,
         );
 
         let res = db.execute(query.to_string(), params).await?;
+        let ok = extract_applied(res);
+        if ok {
+            self.balance_updated(db, updated_at).await;
+        }
+        Ok(ok)
+    }
+
+    async fn balance_updated(&mut self, db: &scylladb::ScyllaDB, updated_at: i64) {
+        self._pending_out = Some(self.pending_out);
+        self.updated_at = updated_at;
+        let balance = Balance::of(self);
+        if let Some(before) = self._balance {
+            self.incr_liability(db, &balance_delta(&before, &balance))
+                .await;
+        }
+        self._balance = Some(balance);
+    }
+
+    // whether the wallet has the mark of a committing transaction's leg, i.e. the leg was
+    // applied to the wallet and not recorded on the transaction yet. unlike wallet.txn, the
+    // mark is not overwritten by the later transactions of the wallet.
+    pub async fn is_committing(
+        db: &scylladb::ScyllaDB,
+        uid: xid::Id,
+        mark: &str,
+    ) -> anyhow::Result<bool> {
+        let query = "SELECT committing FROM wallet WHERE uid=? LIMIT 1";
+        let params = (uid.to_cql(),);
+        let row = db.execute(query, params).await?.single_row()?;
+        match row.columns.first() {
+            Some(Some(v)) => Ok(HashSet::<String>::from_cql(v)?.contains(mark)),
+            _ => Ok(false),
+        }
+    }
+
+    // clears the mark of the committing transaction's leg after the leg is recorded. a mark
+    // left by a failure is harmless, the recorded leg is never applied again.
+    pub async fn clear_committing(db: &scylladb::ScyllaDB, uid: xid::Id, mark: &str) {
+        let query = "UPDATE wallet SET committing=committing-? WHERE uid=? IF EXISTS";
+        let params = (vec![mark.to_string()], uid.to_cql());
+        if let Err(err) = db.execute(query, params).await {
+            log::error!(target: "scylladb",
+                action = "clear_wallet_committing",
+                uid = uid.to_string(),
+                mark = mark;
+                "{}", err,
+            );
+        }
+    }
+
+    // the wallet is committed already, the liability drift is corrected by the reconciliation.
+    async fn incr_liability(&self, db: &scylladb::ScyllaDB, delta: &Balance) {
+        if self.is_system() || *delta == Balance::default() {
+            return;
+        }
+        if let Err(err) = Liability::incr(db, delta).await {
+            log::error!(target: "liability",
+                action = "incr_liability",
+                uid = self.uid.to_string(),
+                sequence = self.sequence;
+                "{}", err,
+            );
+        }
+    }
+
+    // writes the unchanged sequence in an LWT, false if the wallet was updated since loaded.
+    // a check of the loaded wallet is ordered before the following updates of the wallet.
+    pub async fn check_sequence(&self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        let query = "UPDATE wallet SET sequence=? WHERE uid=? IF sequence=?";
+        let params = (self.sequence, self.uid.to_cql(), self.sequence);
+        let res = db.execute(query, params).await?;
         Ok(extract_applied(res))
     }
 
+    // pending_out is not protected by checksum, so it can be updated without sequence.
+    pub async fn update_pending_out(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        let updated_at = unix_ms() as i64;
+        let query = "UPDATE wallet SET pending_out=?,updated_at=? WHERE uid=? IF pending_out=?";
+        let params = (
+            self.pending_out,
+            updated_at,
+            self.uid.to_cql(),
+            self._pending_out,
+        );
+
+        let res = db.execute(query.to_string(), params).await?;
+        let ok = extract_applied(res);
+        if ok {
+            self._pending_out = Some(self.pending_out);
+            self.updated_at = updated_at;
+        }
+        Ok(ok)
+    }
+
+    // None to use the global max overdraw.
+    pub async fn update_max_overdraw(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        max_overdraw: Option<i64>,
+    ) -> anyhow::Result<()> {
+        if let Some(val) = max_overdraw {
+            if val < 0 {
+                return Err(HTTPError::new(400, format!("Invalid max_overdraw {}", val)).into());
+            }
+        }
+
+        let query = "UPDATE wallet SET max_overdraw=? WHERE uid=? IF EXISTS";
+        let params = (max_overdraw, self.uid.to_cql());
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Err(HTTPError::new(404, format!("wallet {} not found", self.uid)).into());
+        }
+
+        self.max_overdraw = max_overdraw;
+        Ok(())
+    }
+
+    // returns an error if the wallet exists and is closed, e.g. for the payee of a transaction.
+    pub async fn check_open_by_uid(db: &scylladb::ScyllaDB, uid: xid::Id) -> anyhow::Result<()> {
+        let query = "SELECT closed_at FROM wallet WHERE uid=? LIMIT 1";
+        let params = (uid.to_cql(),);
+        let rows = db.execute_iter(query, params).await?;
+        if let Some(row) = rows.into_iter().next() {
+            let mut doc = Self::with_pk(uid);
+            let mut cols = ColumnsMap::with_capacity(1);
+            cols.fill(row, &vec!["closed_at".to_string()])?;
+            doc.fill(&cols);
+            doc.check_open()?;
+        }
+        Ok(())
+    }
+
+    // marks the wallet closed if it was not updated since loaded, returns false on conflict.
+    // the balance should have been swept, so that no more outgoing transactions can be prepared.
+    pub async fn close(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        if self.is_closed() {
+            return Err(closed_error(self.uid).into());
+        }
+
+        let closed_at = unix_ms() as i64;
+        let query =
+            "UPDATE wallet SET closed_at=?,updated_at=? WHERE uid=? IF sequence=? AND pending_out=?";
+        let params = (
+            closed_at,
+            closed_at,
+            self.uid.to_cql(),
+            self.sequence,
+            self._pending_out,
+        );
+        let res = db.execute(query, params).await?;
+        let ok = extract_applied(res);
+        if ok {
+            self.closed_at = closed_at;
+            self.updated_at = closed_at;
+        }
+        Ok(ok)
+    }
+
+    // columns of the wallet table, committing is only accessed by the commit of transactions.
+    pub fn table_fields() -> Vec<String> {
+        let mut fields = Self::fields();
+        fields.push("committing".to_string());
+        fields
+    }
+
+    // columns of the wallet_archive table.
+    pub fn archive_fields() -> Vec<String> {
+        let mut fields = Self::fields();
+        fields.push("archived_at".to_string());
+        fields
+    }
+
+    // moves the dead wallet into wallet_archive if it was not updated since loaded,
+    // returns false on conflict.
+    pub async fn archive(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        if self.is_system() {
+            return Err(
+                HTTPError::new(400, "Can not archive the system wallet".to_string()).into(),
+            );
+        }
+
+        let fields = Self::fields();
+        let cols = self.to();
+        let mut cols_name: Vec<&str> = Vec::with_capacity(fields.len() + 1);
+        let mut vals_name: Vec<&str> = Vec::with_capacity(fields.len() + 1);
+        let mut params: Vec<&CqlValue> = Vec::with_capacity(fields.len() + 1);
+        for field in &fields {
+            let val = cols.get(field).unwrap();
+            if val == &CqlValue::Empty {
+                continue;
+            }
+
+            cols_name.push(field);
+            vals_name.push("?");
+            params.push(val);
+        }
+        let archived_at = CqlValue::BigInt(unix_ms() as i64);
+        cols_name.push("archived_at");
+        vals_name.push("?");
+        params.push(&archived_at);
+
+        // the archived row is written first, so that the wallet can always be restored.
+        let query = format!(
+            "INSERT INTO wallet_archive ({}) VALUES ({})",
+            cols_name.join(","),
+            vals_name.join(",")
+        );
+        db.execute(query, params).await?;
+
+        let query =
+            "DELETE FROM wallet WHERE uid=? IF sequence=? AND pending_out=? AND updated_at=?";
+        let params = (
+            self.uid.to_cql(),
+            self.sequence,
+            self._pending_out,
+            self.updated_at,
+        );
+        let res = db.execute(query, params).await?;
+        if extract_applied(res) {
+            return Ok(true);
+        }
+
+        // the wallet was updated after loaded, it is alive.
+        let query = "DELETE FROM wallet_archive WHERE uid=?";
+        let params = (self.uid.to_cql(),);
+        db.execute(query, params).await?;
+        Ok(false)
+    }
+
+    // moves the archived wallet back, returns false if it was not archived.
+    // updated_at is reset so that it will not be archived again soon.
+    pub async fn restore(db: &scylladb::ScyllaDB, uid: xid::Id) -> anyhow::Result<bool> {
+        let fields = Self::fields();
+        let query = format!(
+            "SELECT {} FROM wallet_archive WHERE uid=? LIMIT 1",
+            fields.join(",")
+        );
+        let params = (uid.to_cql(),);
+        let row = match db.execute_iter(query, params).await?.into_iter().next() {
+            Some(row) => row,
+            None => return Ok(false),
+        };
+
+        let mut doc = Self::with_pk(uid);
+        let mut cols = ColumnsMap::with_capacity(fields.len());
+        cols.fill(row, &fields)?;
+        doc.fill(&cols);
+        // the wallet may have been restored by a concurrent access, it is the same row.
+        doc.save(db).await?;
+
+        let query = "DELETE FROM wallet_archive WHERE uid=?";
+        let params = (uid.to_cql(),);
+        db.execute(query, params).await?;
+        log::info!(target: "archive",
+            action = "restore_wallet",
+            uid = uid.to_string(),
+            sequence = doc.sequence;
+            "",
+        );
+        Ok(true)
+    }
+
     pub async fn save(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
         let fields = Self::fields();
         self._fields = fields.clone();
+        self.updated_at = unix_ms() as i64;
 
         let mut cols_name: Vec<&str> = Vec::with_capacity(fields.len());
         let mut vals_name: Vec<&str> = Vec::with_capacity(fields.len());
@@ -120,9 +758,14 @@ impl Wallet {
         let cols = self.to();
 
         for field in &fields {
+            let val = cols.get(field).unwrap();
+            if val == &CqlValue::Empty {
+                continue;
+            }
+
             cols_name.push(field);
             vals_name.push("?");
-            params.push(cols.get(field).unwrap());
+            params.push(val);
         }
 
         let query = format!(
@@ -132,10 +775,22 @@ impl Wallet {
         );
 
         let res = db.execute(query, params).await?;
-        Ok(extract_applied(res))
+        let ok = extract_applied(res);
+        if ok {
+            self._pending_out = Some(self.pending_out);
+            let balance = Balance::of(self);
+            self.incr_liability(db, &balance).await;
+            self._balance = Some(balance);
+        }
+        Ok(ok)
     }
 }
 
+// 410 Gone, clients should stop retrying with a closed wallet.
+pub fn closed_error(uid: xid::Id) -> HTTPError {
+    HTTPError::new(410, format!("wallet {} is closed", uid))
+}
+
 pub struct HMacTag {
     hmac: Hmac<Sha3_256>,
 }
@@ -146,6 +801,13 @@ impl HMacTag {
         HMacTag { hmac }
     }
 
+    // returns false if the key is not loaded (all zero key).
+    pub fn is_loaded(&self) -> bool {
+        let wallet = Wallet::default();
+        let tag = self.tag64(&wallet);
+        tag.len() == 8 && tag != HMacTag::new([0u8; 32]).tag64(&wallet)
+    }
+
     // HMAC(uid, sequence, award, balance_charge, income, balance_ywd, updated_by)
     pub fn tag64(&self, wallet: &Wallet) -> Vec<u8> {
         let digest = self
@@ -164,6 +826,43 @@ impl HMacTag {
         tag.extend_from_slice(&digest[..8]);
         tag
     }
+
+    // HMAC("cursor", uid, kind, cursor), binds a page token to the listing.
+    pub fn tag_cursor(&self, uid: &xid::Id, kind: &str, cursor: &xid::Id) -> Vec<u8> {
+        let digest = self
+            .hmac
+            .clone()
+            .chain_update(b"cursor")
+            .chain_update(uid.as_bytes())
+            .chain_update((kind.len() as u32).to_be_bytes())
+            .chain_update(kind.as_bytes())
+            .chain_update(cursor.as_bytes())
+            .finalize()
+            .into_bytes();
+
+        let mut tag: Vec<u8> = Vec::with_capacity(CURSOR_TAG_LEN);
+        tag.extend_from_slice(&digest[..CURSOR_TAG_LEN]);
+        tag
+    }
+
+    pub fn verify_cursor(&self, uid: &xid::Id, kind: &str, cursor: &xid::Id, tag: &[u8]) -> bool {
+        bool::from(self.tag_cursor(uid, kind, cursor).ct_eq(tag))
+    }
+
+    // HMAC("derive", label), a 32 bytes key for other purposes, e.g. signing receipts.
+    pub fn derive_key(&self, label: &str) -> [u8; 32] {
+        let digest = self
+            .hmac
+            .clone()
+            .chain_update(b"derive")
+            .chain_update(label.as_bytes())
+            .finalize()
+            .into_bytes();
+
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&digest[..32]);
+        key
+    }
 }
 
 #[cfg(test)]
@@ -178,26 +877,208 @@ mod tests {
         res.unwrap()
     }
 
+    #[test]
+    fn check_expected_works() {
+        let wallet = Wallet {
+            uid: xid::new(),
+            sequence: 3,
+            award: 10,
+            topup: 20,
+            income: 5,
+            ..Default::default()
+        };
+        assert!(wallet.check_expected(None, None).is_ok());
+        assert!(wallet.check_expected(Some(3), None).is_ok());
+        assert!(wallet.check_expected(None, Some(35)).is_ok());
+        assert!(wallet.check_expected(Some(3), Some(35)).is_ok());
+
+        let err = wallet.check_expected(Some(2), Some(35)).unwrap_err();
+        assert_eq!(409, err.code);
+        let data = err.data.unwrap();
+        assert_eq!(3, data["sequence"]);
+        assert_eq!(35, data["balance"]);
+        assert_eq!(20, data["topup"]);
+        assert_eq!(
+            409,
+            wallet.check_expected(Some(3), Some(30)).unwrap_err().code
+        );
+    }
+
     #[test]
     fn income_fee_rate_works() {
-        assert_eq!(0.3f32, income_fee_rate(-1));
-        assert_eq!(0.3f32, income_fee_rate(0));
-        assert_eq!(0.3f32, income_fee_rate(9999));
-        assert_eq!(0.27f32, income_fee_rate(9999 + 1));
-        assert_eq!(0.27f32, income_fee_rate(99999));
-        assert_eq!(0.24f32, income_fee_rate(99999 + 1));
-        assert_eq!(0.24f32, income_fee_rate(999999));
-        assert_eq!(0.21f32, income_fee_rate(999999 + 1));
-        assert_eq!(0.21f32, income_fee_rate(9999999));
-        assert_eq!(0.18f32, income_fee_rate(9999999 + 1));
-        assert_eq!(0.18f32, income_fee_rate(99999999));
-        assert_eq!(0.15f32, income_fee_rate(99999999 + 1));
-        assert_eq!(0.15f32, income_fee_rate(999999999));
-        assert_eq!(0.12f32, income_fee_rate(999999999 + 1));
-        assert_eq!(0.12f32, income_fee_rate(9999999999));
-        assert_eq!(0.09f32, income_fee_rate(9999999999 + 1));
-        assert_eq!(0.09f32, income_fee_rate(99999999999));
-        assert_eq!(0.09f32, income_fee_rate(99999999999 + 1));
+        assert_eq!(3000, income_fee_rate(-1));
+        assert_eq!(3000, income_fee_rate(0));
+        assert_eq!(3000, income_fee_rate(9999));
+        assert_eq!(2700, income_fee_rate(9999 + 1));
+        assert_eq!(2700, income_fee_rate(99999));
+        assert_eq!(2400, income_fee_rate(99999 + 1));
+        assert_eq!(2400, income_fee_rate(999999));
+        assert_eq!(2100, income_fee_rate(999999 + 1));
+        assert_eq!(2100, income_fee_rate(9999999));
+        assert_eq!(1800, income_fee_rate(9999999 + 1));
+        assert_eq!(1800, income_fee_rate(99999999));
+        assert_eq!(1500, income_fee_rate(99999999 + 1));
+        assert_eq!(1500, income_fee_rate(999999999));
+        assert_eq!(1200, income_fee_rate(999999999 + 1));
+        assert_eq!(1200, income_fee_rate(9999999999));
+        assert_eq!(900, income_fee_rate(9999999999 + 1));
+        assert_eq!(900, income_fee_rate(99999999999));
+        assert_eq!(900, income_fee_rate(99999999999 + 1));
+    }
+
+    #[test]
+    fn tag_cursor_works() {
+        let mac = HMacTag::new([1u8; 32]);
+        let (uid, cursor) = (xid::new(), xid::new());
+
+        let tag = mac.tag_cursor(&uid, "list_outgo", &cursor);
+        assert_eq!(CURSOR_TAG_LEN, tag.len());
+        assert!(mac.verify_cursor(&uid, "list_outgo", &cursor, &tag));
+        assert!(!mac.verify_cursor(&uid, "list_income", &cursor, &tag));
+        assert!(!mac.verify_cursor(&xid::new(), "list_outgo", &cursor, &tag));
+        assert!(!mac.verify_cursor(&uid, "list_outgo", &xid::new(), &tag));
+        assert!(!mac.verify_cursor(&uid, "list_outgo", &cursor, &tag[1..]));
+        assert!(!HMacTag::new([2u8; 32]).verify_cursor(&uid, "list_outgo", &cursor, &tag));
+    }
+
+    #[test]
+    fn derive_key_works() {
+        let mac = HMacTag::new([1u8; 32]);
+        let key = mac.derive_key("receipt");
+        assert_eq!(key, mac.derive_key("receipt"));
+        assert_ne!(key, mac.derive_key("receipts"));
+        assert_ne!(key, HMacTag::new([2u8; 32]).derive_key("receipt"));
+    }
+
+    #[test]
+    fn check_open_works() {
+        let mut wallet = Wallet::with_pk(xid::new());
+        assert!(!wallet.is_closed());
+        assert!(wallet.check_open().is_ok());
+
+        wallet.closed_at = 1;
+        assert!(wallet.is_closed());
+        assert_eq!(410, wallet.check_open().unwrap_err().code);
+    }
+
+    #[test]
+    fn match_sequence_works() {
+        let (t1, t2, t3, t4) = (xid::new(), xid::new(), xid::new(), xid::new());
+        assert!(match_sequence(0, xid::Id::default(), &[]).is_ok());
+        assert!(match_sequence(1, t1, &[(t1, 0, 1)]).is_ok());
+        assert!(match_sequence(2, t1, &[(t1, 0, 1)]).is_err());
+        assert!(match_sequence(0, t1, &[(t1, 0, 1)]).is_err());
+
+        // canceled transaction increases the sequence once more.
+        assert!(match_sequence(2, t1, &[(t1, 0, -2)]).is_ok());
+        assert!(match_sequence(1, t1, &[(t1, 0, -1)]).is_ok());
+        assert!(match_sequence(3, t1, &[(t1, 0, -2)]).is_err());
+
+        // incoming transactions increase the sequence.
+        let outgo = [(t3, 5, 3), (t2, 2, -2), (t1, 0, 3)];
+        assert!(match_sequence(9, t4, &outgo).is_ok());
+        assert!(match_sequence(6, t3, &outgo).is_ok());
+        assert!(match_sequence(7, t3, &outgo).is_err());
+        assert!(match_sequence(5, t4, &outgo).is_err());
+        assert!(match_sequence(9, t4, &[(t2, 2, 3), (t1, 2, 3)]).is_err());
+
+        // concurrently prepared transactions may be out of id order.
+        assert!(match_sequence(3, t2, &[(t2, 2, 3), (t1, 3, 1)]).is_err());
+        assert!(match_sequence(4, t1, &[(t2, 2, 3), (t1, 3, 1)]).is_ok());
+
+        // preparing transaction is not applied yet.
+        assert!(match_sequence(3, t2, &[(t3, 3, 0), (t2, 2, 3)]).is_ok());
+    }
+
+    #[test]
+    fn apply_bps_works() {
+        assert_eq!(0, apply_bps(0, 3000));
+        assert_eq!(0, apply_bps(3, 3000));
+        assert_eq!(9, apply_bps(33, 3000));
+        assert_eq!(30, apply_bps(100, 3000));
+        assert_eq!(0, apply_bps(999, SYS_FEE_RATE));
+        assert_eq!(1, apply_bps(1001, SYS_FEE_RATE));
+        // f32 can not represent these amounts exactly.
+        assert_eq!(5033165, apply_bps(16777217, 3000));
+        assert_eq!(26999999, apply_bps(99999999, 2700));
+        assert_eq!(25925925, apply_bps(123456789, 2100));
+        assert_eq!(90000000000, apply_bps(1000000000000, 900));
+        assert_eq!(1351079888211148, apply_bps(9007199254740993, 1500));
+        assert_eq!(i64::MAX, apply_bps(i64::MAX, 10000));
+    }
+
+    #[test]
+    fn overdraw_limit_works() {
+        let mut wallet = Wallet::with_pk(xid::new());
+        assert_eq!(100, wallet.overdraw_limit());
+        wallet.max_overdraw = Some(0);
+        assert_eq!(0, wallet.overdraw_limit());
+        wallet.max_overdraw = Some(1000);
+        assert_eq!(1000, wallet.overdraw_limit());
+    }
+
+    #[test]
+    fn is_dead_works() {
+        let now = 1_700_000_000_000i64;
+        let mut wallet = Wallet {
+            uid: xid::new(),
+            sequence: 3,
+            updated_at: now - 1,
+            ..Default::default()
+        };
+        assert!(wallet.is_dead(now));
+        assert!(!wallet.is_dead(now - 1));
+        assert!(!Wallet::default().is_dead(now));
+
+        wallet.credits = 1;
+        assert!(!wallet.is_dead(now));
+        wallet.credits = 0;
+        wallet.income = -1;
+        assert!(!wallet.is_dead(now));
+        wallet.income = 0;
+        wallet.pending_out = 1;
+        assert!(!wallet.is_dead(now));
+        wallet.pending_out = 0;
+        wallet.updated_at = 0;
+        assert!(!wallet.is_dead(now));
+
+        let fields = Wallet::archive_fields();
+        assert_eq!(Wallet::fields().len() + 1, fields.len());
+        assert_eq!("archived_at", fields.last().unwrap());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn wallet_archive_works() {
+        let db = get_db().await;
+
+        let mac = HMacTag::new([1u8; 32]);
+        let mut wallet = Wallet::with_pk(xid::new());
+        wallet.next_checksum(&mac, xid::new());
+        wallet.save(&db).await.unwrap();
+        wallet.get_one(&db).await.unwrap();
+        assert!(wallet.is_dead(unix_ms() as i64 + 1));
+
+        // conflict
+        let mut stale = wallet.clone();
+        stale.updated_at -= 1;
+        assert!(!stale.archive(&db).await.unwrap());
+        assert!(!Wallet::restore(&db, wallet.uid).await.unwrap());
+
+        assert!(wallet.archive(&db).await.unwrap());
+        assert!(Wallet::check_open_by_uid(&db, wallet.uid).await.is_ok());
+
+        let mut restored = Wallet::with_pk(wallet.uid);
+        restored.get_one(&db).await.unwrap();
+        assert_eq!(wallet.sequence, restored.sequence);
+        assert_eq!(wallet.checksum, restored.checksum);
+        assert!(restored.updated_at >= wallet.updated_at);
+        assert!(restored.verify_checksum(&mac).is_ok());
+        assert!(!Wallet::restore(&db, wallet.uid).await.unwrap());
+
+        let mut missing = Wallet::with_pk(xid::new());
+        assert!(missing.get_one(&db).await.is_err());
+        assert!(Wallet::with_pk(SYS_ID).archive(&db).await.is_err());
     }
 
     #[tokio::test(flavor = "current_thread")]
@@ -225,5 +1106,27 @@ mod tests {
 
         wallet.uid = xid::new();
         assert!(wallet.verify_checksum(&mac).is_err());
+
+        // a legacy wallet with balances at sequence 0.
+        let mut legacy = Wallet::with_pk(xid::new());
+        legacy.award = -100;
+        legacy.save(&db).await.unwrap();
+        legacy.get_one(&db).await.unwrap();
+        assert!(!legacy.has_checksum());
+        assert!(legacy.verify_checksum(&mac).is_ok());
+        assert!(legacy.backfill_checksum(&db, &mac).await.unwrap());
+        assert!(!legacy.backfill_checksum(&db, &mac).await.unwrap());
+
+        let mut doc = Wallet::with_pk(legacy.uid);
+        doc.get_one(&db).await.unwrap();
+        assert_eq!(0, doc.sequence);
+        assert_eq!(-100, doc.award);
+        assert!(doc.has_checksum());
+        assert!(doc.verify_checksum(&mac).is_ok());
+        doc.award = -1000;
+        assert!(doc.verify_checksum(&mac).is_err());
+
+        assert!(mac.is_loaded());
+        assert!(!HMacTag::new([0u8; 32]).is_loaded());
     }
 }
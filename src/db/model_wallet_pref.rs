@@ -0,0 +1,133 @@
+use axum_web::{context::unix_ms, erring::HTTPError};
+use scylla_orm::{ColumnsMap, CqlValue, ToCqlVal};
+use scylla_orm_macros::CqlOrm;
+
+use crate::db::scylladb;
+
+// display preferences of a wallet, they do not affect amounts in the wallet.
+#[derive(Debug, Default, Clone, CqlOrm)]
+pub struct WalletPref {
+    pub uid: xid::Id,
+    pub display_currency: String, // alpha code in upper case, e.g. USD, empty for unset
+    pub locale: String,           // BCP 47 language tag, e.g. zh-CN, empty for unset
+    pub reminders_opt_out: bool,  // opt out of reminders of pending checkouts
+    pub updated_at: i64,
+
+    pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+}
+
+impl WalletPref {
+    pub fn with_pk(uid: xid::Id) -> Self {
+        Self {
+            uid,
+            ..Default::default()
+        }
+    }
+
+    // returns false if the preferences are not set, self is left with empty values.
+    pub async fn get_one(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let query = format!(
+            "SELECT {} FROM wallet_pref WHERE uid=? LIMIT 1",
+            fields.join(",")
+        );
+        let params = (self.uid.to_cql(),);
+        let rows = db.execute_iter(query, params).await?;
+        match rows.into_iter().next() {
+            None => Ok(false),
+            Some(row) => {
+                let mut cols = ColumnsMap::with_capacity(fields.len());
+                cols.fill(row, &fields)?;
+                self.fill(&cols);
+                Ok(true)
+            }
+        }
+    }
+
+    // updates the given fields only, other fields are kept.
+    pub async fn upsert(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        cols: ColumnsMap,
+    ) -> anyhow::Result<()> {
+        let valid_fields = ["display_currency", "locale", "reminders_opt_out"];
+        let update_fields = cols.keys();
+        for field in &update_fields {
+            if !valid_fields.contains(&field.as_str()) {
+                return Err(HTTPError::new(400, format!("Invalid field: {}", field)).into());
+            }
+        }
+        if update_fields.is_empty() {
+            return Err(HTTPError::new(400, "No fields to update".to_string()).into());
+        }
+
+        let mut set_fields: Vec<String> = Vec::with_capacity(update_fields.len() + 1);
+        let mut params: Vec<CqlValue> = Vec::with_capacity(update_fields.len() + 2);
+
+        let new_updated_at = unix_ms() as i64;
+        set_fields.push("updated_at=?".to_string());
+        params.push(new_updated_at.to_cql());
+
+        for field in &update_fields {
+            set_fields.push(format!("{}=?", field));
+            params.push(cols.get(field).unwrap().to_owned());
+        }
+
+        let query = format!(
+            "UPDATE wallet_pref SET {} WHERE uid=?",
+            set_fields.join(",")
+        );
+        params.push(self.uid.to_cql());
+        db.execute(query, params).await?;
+
+        self.get_one(db).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conf;
+
+    use super::*;
+
+    async fn get_db() -> scylladb::ScyllaDB {
+        let cfg = conf::Conf::new().unwrap_or_else(|err| panic!("config error: {}", err));
+        let res = scylladb::ScyllaDB::new(cfg.scylla, "walletbase_test").await;
+        res.unwrap()
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn wallet_pref_model_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+
+        let mut doc = WalletPref::with_pk(uid);
+        assert!(!doc.get_one(&db).await.unwrap());
+        assert_eq!("", doc.display_currency);
+
+        assert!(doc.upsert(&db, ColumnsMap::new()).await.is_err());
+        let mut cols = ColumnsMap::new();
+        cols.set_as("uid", &xid::new());
+        assert!(doc.upsert(&db, cols).await.is_err());
+
+        let mut cols = ColumnsMap::new();
+        cols.set_as("display_currency", &"USD".to_string());
+        doc.upsert(&db, cols).await.unwrap();
+        assert_eq!("USD", doc.display_currency);
+        assert_eq!("", doc.locale);
+
+        let mut cols = ColumnsMap::new();
+        cols.set_as("locale", &"zh-CN".to_string());
+        doc.upsert(&db, cols).await.unwrap();
+
+        let mut doc2 = WalletPref::with_pk(uid);
+        assert!(doc2.get_one(&db).await.unwrap());
+        assert_eq!("USD", doc2.display_currency);
+        assert_eq!("zh-CN", doc2.locale);
+        assert!(doc2.updated_at > 0);
+    }
+}
@@ -0,0 +1,322 @@
+use axum_web::context::unix_ms;
+use scylla_orm::{ColumnsMap, CqlValue, ToCqlVal};
+use scylla_orm_macros::CqlOrm;
+
+use crate::db::scylladb::{self, extract_applied};
+
+// all pending webhook events are in one partition of webhook_event, they are removed once
+// delivered or dropped, it should be small.
+const QUEUE_BUCKET: i8 = 0;
+
+// kinds of webhook events.
+pub const WEBHOOK_EVENT_INCOME: &str = "income";
+pub const WEBHOOK_EVENT_SUB_SHARES: &str = "sub_shares";
+pub const WEBHOOK_EVENT_SUBSCRIPTION_LAPSED: &str = "subscription_lapsed";
+
+// a wallet's webhook, income events of the wallet are posted to the url signed by the secret.
+// it is disabled after consecutive delivery failures, and enabled again by registering.
+#[derive(Debug, Default, Clone, CqlOrm)]
+pub struct WalletWebhook {
+    pub uid: xid::Id,
+    pub url: String,
+    pub secret: Vec<u8>, // the signing secret encrypted by the wallet, see webhook::seal_secret
+    pub failures: i32,   // consecutive failed deliveries
+    pub disabled_at: i64, // unix ms, 0 for enabled webhooks
+    pub created_at: i64,
+    pub updated_at: i64,
+
+    pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+}
+
+impl WalletWebhook {
+    pub fn with_pk(uid: xid::Id) -> Self {
+        Self {
+            uid,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.url.is_empty() && self.disabled_at == 0
+    }
+
+    // returns false if the webhook is not registered.
+    pub async fn get_one(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let query = format!(
+            "SELECT {} FROM wallet_webhook WHERE uid=? LIMIT 1",
+            fields.join(",")
+        );
+        let params = (self.uid.to_cql(),);
+        let rows = db.execute_iter(query, params).await?;
+        match rows.into_iter().next() {
+            None => Ok(false),
+            Some(row) => {
+                let mut cols = ColumnsMap::with_capacity(fields.len());
+                cols.fill(row, &fields)?;
+                self.fill(&cols);
+                Ok(true)
+            }
+        }
+    }
+
+    // registers the webhook, a registered one is replaced and enabled again.
+    pub async fn save(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+        let now = unix_ms() as i64;
+        if self.created_at == 0 {
+            self.created_at = now;
+        }
+        self.updated_at = now;
+        self.failures = 0;
+        self.disabled_at = 0;
+
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let mut cols_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut vals_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut params: Vec<&CqlValue> = Vec::with_capacity(fields.len());
+        let cols = self.to();
+
+        for field in &fields {
+            cols_name.push(field);
+            vals_name.push("?");
+            params.push(cols.get(field).unwrap());
+        }
+
+        let query = format!(
+            "INSERT INTO wallet_webhook ({}) VALUES ({})",
+            cols_name.join(","),
+            vals_name.join(",")
+        );
+
+        db.execute(query, params).await?;
+        Ok(())
+    }
+
+    pub async fn delete(&self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+        let query = "DELETE FROM wallet_webhook WHERE uid=?";
+        let params = (self.uid.to_cql(),);
+        db.execute(query, params).await?;
+        Ok(())
+    }
+
+    // records the result of a delivery, the webhook is disabled when its consecutive
+    // failures reach disable_after. returns false if it was re-registered or deleted meanwhile.
+    pub async fn record_delivery(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        ok: bool,
+        disable_after: u32,
+    ) -> anyhow::Result<bool> {
+        let (failures, disabled_at) = if ok {
+            (0, 0)
+        } else if self.failures + 1 >= disable_after.max(1) as i32 {
+            (self.failures + 1, unix_ms() as i64)
+        } else {
+            (self.failures + 1, 0)
+        };
+        if failures == self.failures && disabled_at == self.disabled_at {
+            return Ok(true);
+        }
+
+        let query =
+            "UPDATE wallet_webhook SET failures=?,disabled_at=? WHERE uid=? IF updated_at=?";
+        let params = (failures, disabled_at, self.uid.to_cql(), self.updated_at);
+        let res = db.execute(query, params).await?;
+        let ok = extract_applied(res);
+        if ok {
+            self.failures = failures;
+            self.disabled_at = disabled_at;
+        }
+        Ok(ok)
+    }
+}
+
+// an event queued for delivery to the webhook of the wallet uid.
+#[derive(Debug, Default, Clone, CqlOrm, PartialEq)]
+pub struct WebhookEvent {
+    pub bucket: i8,
+    pub id: xid::Id,
+    pub uid: xid::Id,     // receiver of the income
+    pub kind: String,     // income, sub_shares or subscription_lapsed
+    pub txn_uid: xid::Id, // payer of the transaction
+    pub txn: xid::Id,
+    pub amount: i64,
+    pub attempts: i32,
+    pub next_at: i64, // unix ms of the next attempt
+    pub created_at: i64,
+
+    pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+}
+
+impl WebhookEvent {
+    pub fn new(uid: xid::Id, kind: &str, txn_uid: xid::Id, txn: xid::Id, amount: i64) -> Self {
+        let now = unix_ms() as i64;
+        Self {
+            bucket: QUEUE_BUCKET,
+            id: xid::new(),
+            uid,
+            kind: kind.to_string(),
+            txn_uid,
+            txn,
+            amount,
+            next_at: now,
+            created_at: now,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_due(&self, now: i64) -> bool {
+        self.next_at <= now
+    }
+
+    pub async fn save(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let mut cols_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut vals_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut params: Vec<&CqlValue> = Vec::with_capacity(fields.len());
+        let cols = self.to();
+
+        for field in &fields {
+            cols_name.push(field);
+            vals_name.push("?");
+            params.push(cols.get(field).unwrap());
+        }
+
+        let query = format!(
+            "INSERT INTO webhook_event ({}) VALUES ({})",
+            cols_name.join(","),
+            vals_name.join(",")
+        );
+
+        db.execute(query, params).await?;
+        Ok(())
+    }
+
+    // scans pending events in the order of id, the page token is the last scanned id.
+    pub async fn scan(
+        db: &scylladb::ScyllaDB,
+        page_size: u16,
+        page_token: Option<xid::Id>,
+    ) -> anyhow::Result<Vec<Self>> {
+        let fields = Self::fields();
+        let query = db.list_query(&format!(
+            "SELECT {} FROM webhook_event WHERE bucket=? AND id>? LIMIT ?",
+            fields.join(",")
+        ));
+        let params = (
+            QUEUE_BUCKET,
+            page_token.unwrap_or_default().to_cql(),
+            page_size as i32,
+        );
+        let rows = db.execute_iter(query, params).await?;
+
+        let mut res: Vec<Self> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Self::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok(res)
+    }
+
+    // schedules the next attempt after a failed one.
+    pub async fn retry_at(&mut self, db: &scylladb::ScyllaDB, next_at: i64) -> anyhow::Result<()> {
+        let query = "UPDATE webhook_event SET attempts=?,next_at=? WHERE bucket=? AND id=?";
+        let params = (self.attempts + 1, next_at, QUEUE_BUCKET, self.id.to_cql());
+        db.execute(query, params).await?;
+        self.attempts += 1;
+        self.next_at = next_at;
+        Ok(())
+    }
+
+    // removes the delivered or dropped event.
+    pub async fn delete(&self, db: &scylladb::ScyllaDB) -> anyhow::Result<()> {
+        let query = "DELETE FROM webhook_event WHERE bucket=? AND id=?";
+        let params = (QUEUE_BUCKET, self.id.to_cql());
+        db.execute(query, params).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conf;
+
+    use super::*;
+
+    async fn get_db() -> scylladb::ScyllaDB {
+        let cfg = conf::Conf::new().unwrap_or_else(|err| panic!("config error: {}", err));
+        let res = scylladb::ScyllaDB::new(cfg.scylla, "walletbase_test").await;
+        res.unwrap()
+    }
+
+    #[test]
+    fn webhook_event_works() {
+        let event = WebhookEvent::new(xid::new(), WEBHOOK_EVENT_INCOME, xid::new(), xid::new(), 10);
+        assert!(event.is_due(event.next_at));
+        assert!(!event.is_due(event.next_at - 1));
+
+        let mut webhook = WalletWebhook::with_pk(xid::new());
+        assert!(!webhook.is_enabled());
+        webhook.url = "https://example.com/hook".to_string();
+        assert!(webhook.is_enabled());
+        webhook.disabled_at = 1;
+        assert!(!webhook.is_enabled());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn webhook_model_works() {
+        let db = get_db().await;
+        let uid = xid::new();
+
+        let mut webhook = WalletWebhook::with_pk(uid);
+        assert!(!webhook.get_one(&db).await.unwrap());
+        webhook.url = "https://example.com/hook".to_string();
+        webhook.secret = vec![1, 2, 3];
+        webhook.save(&db).await.unwrap();
+
+        let mut doc = WalletWebhook::with_pk(uid);
+        assert!(doc.get_one(&db).await.unwrap());
+        assert!(doc.is_enabled());
+        assert!(doc.record_delivery(&db, false, 2).await.unwrap());
+        assert_eq!(1, doc.failures);
+        assert!(doc.is_enabled());
+        assert!(doc.record_delivery(&db, false, 2).await.unwrap());
+        assert!(!doc.is_enabled());
+
+        webhook.save(&db).await.unwrap();
+        assert!(!doc.record_delivery(&db, true, 2).await.unwrap());
+        assert!(doc.get_one(&db).await.unwrap());
+        assert!(doc.is_enabled());
+        assert_eq!(0, doc.failures);
+
+        let mut event = WebhookEvent::new(uid, WEBHOOK_EVENT_INCOME, xid::new(), xid::new(), 10);
+        event.save(&db).await.unwrap();
+        let res = WebhookEvent::scan(&db, 1000, None).await.unwrap();
+        assert!(res.contains(&event));
+
+        event.retry_at(&db, event.next_at + 1000).await.unwrap();
+        let res = WebhookEvent::scan(&db, 1000, None).await.unwrap();
+        let saved = res.iter().find(|e| e.id == event.id).unwrap();
+        assert_eq!(1, saved.attempts);
+        assert_eq!(event.next_at, saved.next_at);
+
+        event.delete(&db).await.unwrap();
+        let res = WebhookEvent::scan(&db, 1000, None).await.unwrap();
+        assert!(!res.iter().any(|e| e.id == event.id));
+
+        doc.delete(&db).await.unwrap();
+        assert!(!doc.get_one(&db).await.unwrap());
+    }
+}
@@ -0,0 +1,333 @@
+use axum_web::{context::unix_ms, erring::HTTPError};
+use scylla_orm::{ColumnsMap, CqlValue, FieldSet, ToCqlVal};
+use scylla_orm_macros::CqlOrm;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use crate::db::{
+    scylladb::{self, extract_applied},
+    MAX_ID,
+};
+
+// withdrawals with amount >= threshold should be reviewed by admin, 0 disables the review.
+static REVIEW_THRESHOLD: AtomicI64 = AtomicI64::new(0);
+
+pub fn set_withdraw_review_threshold(val: i64) {
+    REVIEW_THRESHOLD.store(val, Ordering::Relaxed);
+}
+
+// all pending reviews are in one partition of withdrawal_review_queue, it should be small.
+const QUEUE_BUCKET: i8 = 0;
+
+// manual review for large withdraw transactions.
+// the transaction is prepared when the review is created, and committed or canceled by the reviewer.
+#[derive(Debug, Default, Clone, CqlOrm)]
+pub struct WithdrawalReview {
+    pub id: xid::Id, // withdraw transaction id
+    pub uid: xid::Id,
+    pub amount: i64,
+    pub reviewer: String,
+    pub note: String,
+    pub status: i8, // -1: rejected, 0: pending review, 1: approved
+    pub created_at: i64,
+    pub updated_at: i64,
+
+    pub _fields: Vec<String>, // selected fields，`_` 前缀字段会被 CqlOrm 忽略
+}
+
+impl WithdrawalReview {
+    pub fn with_pk(id: xid::Id) -> Self {
+        Self {
+            id,
+            ..Default::default()
+        }
+    }
+
+    pub fn required(amount: i64) -> bool {
+        let threshold = REVIEW_THRESHOLD.load(Ordering::Relaxed);
+        threshold > 0 && amount >= threshold
+    }
+
+    pub fn select_fields(
+        select_fields: FieldSet<WithdrawalReviewField>,
+        with_pk: bool,
+    ) -> Vec<String> {
+        if select_fields.is_empty() {
+            return Self::fields();
+        }
+
+        let mut select_fields = select_fields
+            .with(WithdrawalReviewField::Uid)
+            .with(WithdrawalReviewField::Status);
+        if with_pk {
+            select_fields.insert(WithdrawalReviewField::Id);
+        }
+
+        select_fields.names()
+    }
+
+    pub async fn get_one(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        select_fields: FieldSet<WithdrawalReviewField>,
+    ) -> anyhow::Result<()> {
+        let fields = Self::select_fields(select_fields, false);
+        self._fields = fields.clone();
+
+        let query = format!(
+            "SELECT {} FROM withdrawal_review WHERE id=? LIMIT 1",
+            fields.join(",")
+        );
+        let params = (self.id.to_cql(),);
+        let res = db.execute(query, params).await?.single_row()?;
+
+        let mut cols = ColumnsMap::with_capacity(fields.len());
+        cols.fill(res, &fields)?;
+        self.fill(&cols);
+
+        Ok(())
+    }
+
+    // returns the review status of the withdraw transaction, None if it is not under review.
+    pub async fn status_of(db: &scylladb::ScyllaDB, id: xid::Id) -> anyhow::Result<Option<i8>> {
+        let query = "SELECT status FROM withdrawal_review WHERE id=? LIMIT 1";
+        let params = (id.to_cql(),);
+        let rows = db.execute_iter(query, params).await?;
+        match rows.into_iter().next() {
+            None => Ok(None),
+            Some(row) => {
+                let fields = vec!["status".to_string()];
+                let mut cols = ColumnsMap::with_capacity(1);
+                cols.fill(row, &fields)?;
+                Ok(Some(cols.get_as("status")?))
+            }
+        }
+    }
+
+    pub async fn save(&mut self, db: &scylladb::ScyllaDB) -> anyhow::Result<bool> {
+        if self.amount <= 0 {
+            return Err(HTTPError::new(400, format!("Invalid amount {}", self.amount)).into());
+        }
+
+        self.status = 0;
+        self.reviewer = "".to_string();
+        self.note = "".to_string();
+        self.created_at = unix_ms() as i64;
+        self.updated_at = self.created_at;
+        let fields = Self::fields();
+        self._fields = fields.clone();
+
+        let mut cols_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut vals_name: Vec<&str> = Vec::with_capacity(fields.len());
+        let mut params: Vec<&CqlValue> = Vec::with_capacity(fields.len());
+        let cols = self.to();
+
+        for field in &fields {
+            cols_name.push(field);
+            vals_name.push("?");
+            params.push(cols.get(field).unwrap());
+        }
+
+        let query = format!(
+            "INSERT INTO withdrawal_review ({}) VALUES ({}) IF NOT EXISTS",
+            cols_name.join(","),
+            vals_name.join(",")
+        );
+
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Err(HTTPError::new(
+                409,
+                format!("Withdrawal review {} already exists", self.id),
+            )
+            .into());
+        }
+
+        let query = "INSERT INTO withdrawal_review_queue (bucket,id,uid,amount,created_at) VALUES (?,?,?,?,?)";
+        let params = (
+            QUEUE_BUCKET,
+            self.id.to_cql(),
+            self.uid.to_cql(),
+            self.amount,
+            self.created_at,
+        );
+        db.execute(query, params).await?;
+        Ok(true)
+    }
+
+    // status: 1 approved, -1 rejected.
+    pub async fn decide(
+        &mut self,
+        db: &scylladb::ScyllaDB,
+        reviewer: String,
+        status: i8,
+        note: String,
+    ) -> anyhow::Result<()> {
+        if status != 1 && status != -1 {
+            return Err(HTTPError::new(400, format!("Invalid status {}", status)).into());
+        }
+        if reviewer.is_empty() {
+            return Err(HTTPError::new(400, "reviewer is required".to_string()).into());
+        }
+
+        let updated_at = unix_ms() as i64;
+        let query = "UPDATE withdrawal_review SET reviewer=?,note=?,status=?,updated_at=? WHERE id=? IF status=0";
+        let params = (
+            reviewer.to_cql(),
+            note.to_cql(),
+            status,
+            updated_at,
+            self.id.to_cql(),
+        );
+        let res = db.execute(query, params).await?;
+        if !extract_applied(res) {
+            return Err(HTTPError::new(
+                409,
+                format!("Withdrawal review {} has been decided", self.id),
+            )
+            .into());
+        }
+
+        self.reviewer = reviewer;
+        self.note = note;
+        self.status = status;
+        self.updated_at = updated_at;
+
+        // the decided review is still in withdrawal_review, removing it from the queue is best effort.
+        let query = "DELETE FROM withdrawal_review_queue WHERE bucket=? AND id=?";
+        let params = (QUEUE_BUCKET, self.id.to_cql());
+        if let Err(err) = db.execute(query, params).await {
+            log::error!(target: "scylladb",
+                action = "delete_withdrawal_review_queue",
+                uid = self.uid.to_string(),
+                id = self.id.to_string();
+                "{}", err,
+            );
+        }
+        Ok(())
+    }
+
+    // lists pending reviews, oldest first.
+    // returns reviews with id, uid, amount and created_at only.
+    pub async fn list_pending(
+        db: &scylladb::ScyllaDB,
+        page_size: u16,
+        page_token: Option<xid::Id>,
+    ) -> anyhow::Result<Vec<Self>> {
+        let fields = vec![
+            "id".to_string(),
+            "uid".to_string(),
+            "amount".to_string(),
+            "created_at".to_string(),
+        ];
+
+        let rows = match page_token {
+            Some(id) => {
+                let query = db.list_query("SELECT id,uid,amount,created_at FROM withdrawal_review_queue WHERE bucket=? AND id>? LIMIT ?");
+                let params = (QUEUE_BUCKET, id.to_cql(), page_size as i32);
+                db.execute_iter(query, params).await?
+            }
+            None => {
+                let query = db.list_query("SELECT id,uid,amount,created_at FROM withdrawal_review_queue WHERE bucket=? AND id<? LIMIT ?");
+                let params = (QUEUE_BUCKET, MAX_ID.to_cql(), page_size as i32);
+                db.execute_iter(query, params).await?
+            }
+        };
+
+        let mut res: Vec<Self> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut doc = Self::default();
+            let mut cols = ColumnsMap::with_capacity(fields.len());
+            cols.fill(row, &fields)?;
+            doc.fill(&cols);
+            doc._fields = fields.clone();
+            res.push(doc);
+        }
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conf;
+
+    use super::*;
+
+    async fn get_db() -> scylladb::ScyllaDB {
+        let cfg = conf::Conf::new().unwrap_or_else(|err| panic!("config error: {}", err));
+        let res = scylladb::ScyllaDB::new(cfg.scylla, "walletbase_test").await;
+        res.unwrap()
+    }
+
+    #[test]
+    fn required_works() {
+        set_withdraw_review_threshold(0);
+        assert!(!WithdrawalReview::required(1_000_000));
+
+        set_withdraw_review_threshold(1000);
+        assert!(!WithdrawalReview::required(999));
+        assert!(WithdrawalReview::required(1000));
+        assert!(WithdrawalReview::required(1001));
+
+        set_withdraw_review_threshold(0);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn withdrawal_review_model_works() {
+        let db = get_db().await;
+
+        let mut doc = WithdrawalReview {
+            id: xid::new(),
+            uid: xid::new(),
+            amount: 0,
+            ..Default::default()
+        };
+        assert!(doc.save(&db).await.is_err());
+        assert_eq!(
+            None,
+            WithdrawalReview::status_of(&db, doc.id).await.unwrap()
+        );
+
+        doc.amount = 10000;
+        doc.save(&db).await.unwrap();
+        assert!(doc.save(&db).await.is_err());
+        assert_eq!(
+            Some(0),
+            WithdrawalReview::status_of(&db, doc.id).await.unwrap()
+        );
+
+        let pending = WithdrawalReview::list_pending(&db, 1000, None)
+            .await
+            .unwrap();
+        assert!(pending.iter().any(|v| v.id == doc.id));
+
+        let mut doc2 = WithdrawalReview::with_pk(doc.id);
+        doc2.get_one(&db, FieldSet::new()).await.unwrap();
+        assert_eq!(10000, doc2.amount);
+        assert_eq!(0, doc2.status);
+
+        assert!(doc2
+            .decide(&db, "".to_string(), 1, "".to_string())
+            .await
+            .is_err());
+        doc2.decide(&db, "bob".to_string(), -1, "suspicious".to_string())
+            .await
+            .unwrap();
+        assert_eq!(-1, doc2.status);
+        assert!(doc2
+            .decide(&db, "carol".to_string(), 1, "".to_string())
+            .await
+            .is_err());
+
+        let pending = WithdrawalReview::list_pending(&db, 1000, None)
+            .await
+            .unwrap();
+        assert!(!pending.iter().any(|v| v.id == doc.id));
+
+        doc.get_one(&db, FieldSet::new()).await.unwrap();
+        assert_eq!(-1, doc.status);
+        assert_eq!("bob", doc.reviewer);
+        assert_eq!("suspicious", doc.note);
+    }
+}
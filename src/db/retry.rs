@@ -0,0 +1,140 @@
+use rand_core::{OsRng, RngCore};
+use std::{
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use super::shed;
+
+// LWT updates conflict when concurrent transactions touch the same wallet,
+// retries are spread by exponential backoff with full jitter to reduce the contention.
+static MAX_ATTEMPTS: AtomicU32 = AtomicU32::new(5);
+static BACKOFF_MS: AtomicU64 = AtomicU64::new(10);
+const MAX_BACKOFF_MS: u64 = 1000;
+
+static LWT_CALLS: AtomicU64 = AtomicU64::new(0);
+static LWT_RETRIES: AtomicU64 = AtomicU64::new(0);
+static LWT_EXHAUSTED: AtomicU64 = AtomicU64::new(0);
+
+pub fn set_lwt_retry(max_attempts: u32, backoff_ms: u64) {
+    MAX_ATTEMPTS.store(max_attempts.max(1), Ordering::Relaxed);
+    BACKOFF_MS.store(backoff_ms, Ordering::Relaxed);
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RetryMetrics {
+    pub calls: u64,     // retry loops started
+    pub retries: u64,   // attempts after the first one
+    pub exhausted: u64, // loops that ran out of attempts
+}
+
+pub fn lwt_retry_metrics() -> RetryMetrics {
+    RetryMetrics {
+        calls: LWT_CALLS.load(Ordering::Relaxed),
+        retries: LWT_RETRIES.load(Ordering::Relaxed),
+        exhausted: LWT_EXHAUSTED.load(Ordering::Relaxed),
+    }
+}
+
+// retry_lwt returns an attempt counter for a LWT retry loop:
+//
+//     let mut retry = retry_lwt("commit_transaction");
+//     while retry.next().await {
+//         ok = wallet.update_balance(db).await?;
+//         if ok {
+//             break;
+//         }
+//     }
+pub fn retry_lwt(action: &'static str) -> RetryLwt {
+    RetryLwt {
+        action,
+        attempt: 0,
+        max_attempts: MAX_ATTEMPTS.load(Ordering::Relaxed),
+        backoff_ms: BACKOFF_MS.load(Ordering::Relaxed),
+    }
+}
+
+pub struct RetryLwt {
+    action: &'static str,
+    attempt: u32,
+    max_attempts: u32,
+    backoff_ms: u64,
+}
+
+impl RetryLwt {
+    // returns false when attempts are exhausted, sleeps before every retry.
+    pub async fn next(&mut self) -> bool {
+        if self.attempt >= self.max_attempts {
+            LWT_EXHAUSTED.fetch_add(1, Ordering::Relaxed);
+            log::warn!(target: "scylladb",
+                action = self.action,
+                attempts = self.attempt;
+                "lwt retry exhausted",
+            );
+            return false;
+        }
+
+        shed::record_lwt(self.attempt > 0);
+        if self.attempt == 0 {
+            LWT_CALLS.fetch_add(1, Ordering::Relaxed);
+        } else {
+            LWT_RETRIES.fetch_add(1, Ordering::Relaxed);
+            let delay = self.delay_ms();
+            if delay > 0 {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+            }
+        }
+        self.attempt += 1;
+        true
+    }
+
+    // full jitter: random in [0, min(cap, base * 2^(attempt-1))].
+    fn delay_ms(&self) -> u64 {
+        let ceil = backoff_ceil(self.backoff_ms, self.attempt);
+        if ceil == 0 {
+            return 0;
+        }
+        OsRng.next_u64() % (ceil + 1)
+    }
+}
+
+fn backoff_ceil(base_ms: u64, attempt: u32) -> u64 {
+    let exp = attempt.saturating_sub(1).min(16);
+    base_ms.saturating_mul(1 << exp).min(MAX_BACKOFF_MS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_ceil_works() {
+        assert_eq!(10, backoff_ceil(10, 1));
+        assert_eq!(20, backoff_ceil(10, 2));
+        assert_eq!(80, backoff_ceil(10, 4));
+        assert_eq!(MAX_BACKOFF_MS, backoff_ceil(10, 10));
+        assert_eq!(MAX_BACKOFF_MS, backoff_ceil(10, 100));
+        assert_eq!(0, backoff_ceil(0, 3));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn retry_lwt_works() {
+        let mut retry = RetryLwt {
+            action: "test",
+            attempt: 0,
+            max_attempts: 3,
+            backoff_ms: 1,
+        };
+        let before = lwt_retry_metrics();
+        let mut n = 0;
+        while retry.next().await {
+            n += 1;
+        }
+        assert_eq!(3, n);
+
+        let after = lwt_retry_metrics();
+        assert!(after.calls > before.calls);
+        assert!(after.retries >= before.retries + 2);
+        assert!(after.exhausted > before.exhausted);
+    }
+}
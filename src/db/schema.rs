@@ -0,0 +1,159 @@
+use super::scylladb;
+use super::{
+    AdjustmentApproval, ApiKey, Blob, Budget, Charge, Credit, Customer, Dispute, JobLease,
+    PayeeTransaction, PayeeWeeklySummary, PolicyAudit, Pool, PoolContribution, Redpacket,
+    RedpacketClaim, Subscription, Transaction, TransactionByKind, TransactionRef, TxnHistory,
+    Wallet, WalletHold, WalletPref, WalletWebhook, WebhookEvent, WithdrawalReview,
+};
+
+// tables mapped by the CqlOrm models, and the model fields as expected columns.
+// tables without a model, e.g. counters and indexes, are not checked.
+pub fn expected_tables() -> Vec<(&'static str, Vec<String>)> {
+    vec![
+        ("wallet", Wallet::table_fields()),
+        ("wallet_archive", Wallet::archive_fields()),
+        ("wallet_pref", WalletPref::fields()),
+        ("wallet_hold", WalletHold::fields()),
+        ("wallet_webhook", WalletWebhook::fields()),
+        ("webhook_event", WebhookEvent::fields()),
+        ("transaction", Transaction::fields()),
+        ("transaction_by_kind", TransactionByKind::fields()),
+        ("transaction_ref", TransactionRef::fields()),
+        ("txn_history", TxnHistory::fields()),
+        ("payee_transaction", PayeeTransaction::fields()),
+        ("payee_weekly_summary", PayeeWeeklySummary::fields()),
+        ("credit", Credit::fields()),
+        ("charge", Charge::fields()),
+        ("api_key", ApiKey::fields()),
+        ("customer", Customer::fields()),
+        ("budget", Budget::fields()),
+        ("adjustment_approval", AdjustmentApproval::fields()),
+        ("withdrawal_review", WithdrawalReview::fields()),
+        ("policy_audit", PolicyAudit::fields()),
+        ("pool", Pool::fields()),
+        ("pool_contribution", PoolContribution::fields()),
+        ("redpacket", Redpacket::fields()),
+        ("redpacket_claim", RedpacketClaim::fields()),
+        ("subscription", Subscription::fields()),
+        ("dispute", Dispute::fields()),
+        ("blob", Blob::fields()),
+        ("job_lease", JobLease::fields()),
+    ]
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Column {
+    pub name: String,
+    pub kind: String, // partition_key, clustering, regular or static
+    pub cql_type: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TableSchema {
+    pub table: String,
+    pub exists: bool,
+    pub columns: Vec<Column>, // actual columns of the table
+    pub missing: Vec<String>, // model fields without a column
+    pub extra: Vec<String>,   // columns without a model field
+}
+
+impl TableSchema {
+    pub fn diff(table: &str, expected: &[String], columns: Vec<Column>) -> Self {
+        let missing = expected
+            .iter()
+            .filter(|f| !columns.iter().any(|c| &c.name == *f))
+            .cloned()
+            .collect();
+        let extra = columns
+            .iter()
+            .filter(|c| !expected.contains(&c.name))
+            .map(|c| c.name.clone())
+            .collect();
+        Self {
+            table: table.to_string(),
+            exists: !columns.is_empty(),
+            columns,
+            missing,
+            extra,
+        }
+    }
+
+    pub fn drifted(&self) -> bool {
+        !self.exists || !self.missing.is_empty() || !self.extra.is_empty()
+    }
+}
+
+// introspects the tables of the keyspace in system_schema, and compares them with the models.
+pub async fn inspect(db: &scylladb::ScyllaDB) -> anyhow::Result<Vec<TableSchema>> {
+    let tables = expected_tables();
+    let mut res: Vec<TableSchema> = Vec::with_capacity(tables.len());
+    for (table, expected) in tables {
+        let query = "SELECT column_name,kind,type FROM system_schema.columns WHERE keyspace_name=? AND table_name=?";
+        let rows = db
+            .execute_iter(query, (db.keyspace(), table.to_string()))
+            .await?;
+
+        let mut columns: Vec<Column> = Vec::with_capacity(rows.len());
+        for row in rows {
+            let text = |i: usize| -> String {
+                row.columns
+                    .get(i)
+                    .and_then(|v| v.as_ref())
+                    .and_then(|v| v.as_text())
+                    .cloned()
+                    .unwrap_or_default()
+            };
+            columns.push(Column {
+                name: text(0),
+                kind: text(1),
+                cql_type: text(2),
+            });
+        }
+        res.push(TableSchema::diff(table, &expected, columns));
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(name: &str) -> Column {
+        Column {
+            name: name.to_string(),
+            kind: "regular".to_string(),
+            cql_type: "bigint".to_string(),
+        }
+    }
+
+    #[test]
+    fn diff_works() {
+        let expected = vec!["uid".to_string(), "sequence".to_string()];
+
+        let res = TableSchema::diff("wallet", &expected, vec![column("uid"), column("sequence")]);
+        assert!(res.exists);
+        assert!(!res.drifted());
+
+        let res = TableSchema::diff("wallet", &expected, vec![column("uid"), column("legacy")]);
+        assert!(res.drifted());
+        assert_eq!(vec!["sequence".to_string()], res.missing);
+        assert_eq!(vec!["legacy".to_string()], res.extra);
+
+        let res = TableSchema::diff("wallet", &expected, vec![]);
+        assert!(!res.exists);
+        assert!(res.drifted());
+        assert_eq!(expected, res.missing);
+    }
+
+    #[test]
+    fn expected_tables_works() {
+        for (table, fields) in expected_tables() {
+            assert!(!fields.is_empty(), "{} has no fields", table);
+            assert!(
+                fields.iter().all(|f| !f.starts_with('_')),
+                "{} has ignored fields",
+                table
+            );
+        }
+    }
+}
@@ -1,11 +1,20 @@
 use futures::{stream::StreamExt, Stream};
 use scylla::{
-    frame::value::{BatchValues, ValueList},
+    frame::value::{BatchValues, SerializedValues, ValueList},
     statement::{Consistency, SerialConsistency},
-    transport::{iterator::RowIterator, query_result::QueryResult, Compression, ExecutionProfile},
+    transport::{
+        errors::{DbError, QueryError},
+        iterator::RowIterator,
+        query_result::QueryResult,
+        Compression, ExecutionProfile,
+    },
     CachingSession, Metrics, Session, SessionBuilder,
 };
-use std::{sync::Arc, time::Duration};
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 pub use scylla::{
     batch::Batch,
@@ -14,10 +23,64 @@ pub use scylla::{
     Bytes,
 };
 
+use axum_web::{context, erring::HTTPError};
+
+use super::{
+    breaker::{Breaker, BreakerConf, BreakerStatus},
+    shadow::{self, ShadowQuery},
+    shed,
+};
 use crate::conf;
 
 pub struct ScyllaDB {
     session: CachingSession,
+    keyspace: String,
+    hints: QueryHints,
+    breaker: Breaker,
+    #[cfg(feature = "fault-injection")]
+    pub faults: super::fault::Faults,
+}
+
+// hints appended to list and scan queries, not all Scylla/Cassandra deployments support them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryHints {
+    pub timeout_ms: u64,    // `USING TIMEOUT`, 0 disables it
+    pub bypass_cache: bool, // `BYPASS CACHE` for scans
+}
+
+impl QueryHints {
+    pub fn apply(&self, query: &str, scan: bool) -> String {
+        let mut query = query.trim_end().to_string();
+        if scan && self.bypass_cache {
+            query.push_str(" BYPASS CACHE");
+        }
+        if self.timeout_ms > 0 {
+            query.push_str(&format!(" USING TIMEOUT {}ms", self.timeout_ms));
+        }
+        query
+    }
+}
+
+// max length of a keyspace name.
+const MAX_KEYSPACE_LEN: usize = 48;
+
+// each tenant has its own keyspace "{keyspace}_{tenant}" with the same schema,
+// so that the system wallet (SYS_ID) and all the tables are scoped by tenant.
+// the default tenant (empty) uses the keyspace.
+pub fn tenant_keyspace(keyspace: &str, tenant: &str) -> anyhow::Result<String> {
+    if tenant.is_empty() {
+        return Ok(keyspace.to_string());
+    }
+
+    let keyspace = format!("{}_{}", keyspace, tenant);
+    if keyspace.len() > MAX_KEYSPACE_LEN
+        || !tenant
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+    {
+        return Err(anyhow::Error::msg(format!("invalid tenant {:?}", tenant)));
+    }
+    Ok(keyspace)
 }
 
 impl ScyllaDB {
@@ -45,19 +108,85 @@ impl ScyllaDB {
 
         Ok(Self {
             session: CachingSession::from(session, 100000),
+            keyspace: keyspace.to_string(),
+            hints: QueryHints {
+                timeout_ms: cfg.query_timeout_ms,
+                bypass_cache: cfg.bypass_cache,
+            },
+            breaker: Breaker::new(BreakerConf {
+                error_rate: cfg.breaker_error_rate,
+                min_requests: cfg.breaker_min_requests,
+                window: Duration::from_secs(cfg.breaker_window_secs),
+                open: Duration::from_secs(cfg.breaker_open_secs),
+                half_open_probes: cfg.breaker_half_open_probes,
+            }),
+            #[cfg(feature = "fault-injection")]
+            faults: Default::default(),
         })
     }
 
+    // the SELECT query with the configured hints, for queries of a partition.
+    pub fn list_query(&self, query: &str) -> String {
+        self.hints.apply(query, false)
+    }
+
+    // the SELECT query with the configured hints, for queries across partitions.
+    pub fn scan_query(&self, query: &str) -> String {
+        self.hints.apply(query, true)
+    }
+
+    pub fn keyspace(&self) -> &str {
+        &self.keyspace
+    }
+
     pub fn metrics(&self) -> Arc<Metrics> {
         self.session.get_session().get_metrics()
     }
 
+    pub fn breaker_status(&self) -> BreakerStatus {
+        self.breaker.status()
+    }
+
+    // returns the coordinator's unix time in milliseconds.
+    pub async fn now_ms(&self) -> anyhow::Result<i64> {
+        let res = self
+            .session
+            .execute("SELECT toUnixTimestamp(now()) FROM system.local", ())
+            .await?
+            .single_row()?;
+        res.columns[0]
+            .as_ref()
+            .and_then(|v| v.as_bigint())
+            .ok_or_else(|| anyhow::Error::msg("invalid timestamp from system.local"))
+    }
+
     pub async fn execute(
         &self,
         query: impl Into<Query>,
         params: impl ValueList,
     ) -> anyhow::Result<QueryResult> {
-        let res = self.session.execute(query, params).await?;
+        let query: Query = query.into();
+        #[cfg(feature = "fault-injection")]
+        self.faults.inject(&query.contents).await?;
+        let shadow_query = match shadow::shadow_query(&query.contents) {
+            Some(ShadowQuery::Write(contents)) => {
+                // keeps the consistency and page size of the primary query.
+                let mut shadow_query = query.clone();
+                shadow_query.contents = contents;
+                shadow_query
+            }
+            _ => {
+                return self
+                    .guarded(async { Ok(self.session.execute(query, params).await?) })
+                    .await
+            }
+        };
+
+        let values = params.serialized()?.into_owned();
+        let res = self
+            .guarded(async { Ok(self.session.execute(query, values.clone()).await?) })
+            .await?;
+        self.mirror_write(shadow_query, values, &res).await;
         Ok(res)
     }
 
@@ -66,6 +195,44 @@ impl ScyllaDB {
         query: impl Into<Query>,
         params: impl ValueList,
     ) -> anyhow::Result<Vec<Row>> {
+        let query: Query = query.into();
+        #[cfg(feature = "fault-injection")]
+        self.faults.inject(&query.contents).await?;
+        let shadow_query = match shadow::shadow_query(&query.contents) {
+            Some(ShadowQuery::Read(contents)) => {
+                // keeps the consistency and page size of the primary query.
+                let mut shadow_query = query.clone();
+                shadow_query.contents = contents;
+                shadow_query
+            }
+            _ => return self.guarded(self.collect_rows(query, params)).await,
+        };
+
+        let values = params.serialized()?.into_owned();
+        let rows = self
+            .guarded(self.collect_rows(query, values.clone()))
+            .await?;
+        match within_deadline(self.collect_rows(shadow_query, values)).await {
+            Ok(shadow_rows) => {
+                let matched = rows == shadow_rows;
+                shadow::record_sample(matched);
+                if !matched {
+                    log::warn!(target: "shadow",
+                        rows = rows.len(),
+                        shadow_rows = shadow_rows.len();
+                        "shadow read mismatched",
+                    );
+                }
+            }
+            Err(err) => {
+                shadow::record_error();
+                log::warn!(target: "shadow", "shadow read failed: {}", err);
+            }
+        }
+        Ok(rows)
+    }
+
+    async fn collect_rows(&self, query: Query, params: impl ValueList) -> anyhow::Result<Vec<Row>> {
         let mut rows_stream = self.session.execute_iter(query, params).await?;
 
         let (capacity, _) = rows_stream.size_hint();
@@ -76,13 +243,47 @@ impl ScyllaDB {
         Ok(rows)
     }
 
+    // mirrors the applied write to the secondary table, outside of the circuit breaker.
+    // a conditional write that is not applied to the secondary is a mismatch.
+    async fn mirror_write(&self, query: Query, values: SerializedValues, primary: &QueryResult) {
+        let conditional = is_conditional(primary);
+        if conditional && !is_applied(primary) {
+            return;
+        }
+
+        let contents = query.contents.clone();
+        match within_deadline(async { Ok(self.session.execute(query, values).await?) }).await {
+            Ok(res) => {
+                shadow::record_write(true);
+                if conditional && !is_applied(&res) {
+                    shadow::record_mismatch();
+                    log::warn!(target: "shadow",
+                        query = contents;
+                        "shadow write not applied",
+                    );
+                }
+            }
+            Err(err) => {
+                shadow::record_write(false);
+                log::warn!(target: "shadow",
+                    query = contents;
+                    "shadow write failed: {}", err,
+                );
+            }
+        }
+    }
+
     pub async fn stream(
         &self,
         query: impl Into<Query>,
         params: impl ValueList,
     ) -> anyhow::Result<RowIterator> {
-        let stream = self.session.execute_iter(query, params).await?;
-        Ok(stream)
+        let query: Query = query.into();
+        #[cfg(feature = "fault-injection")]
+        self.faults.inject(&query.contents).await?;
+        // only the first page is bounded by the request's deadline.
+        self.guarded(async { Ok(self.session.execute_iter(query, params).await?) })
+            .await
     }
 
     // https://opensource.docs.scylladb.com/master/cql/dml.html#batch-statement
@@ -95,11 +296,85 @@ impl ScyllaDB {
     ) -> anyhow::Result<QueryResult> {
         let mut batch: Batch = Default::default();
         for statement in statements {
+            #[cfg(feature = "fault-injection")]
+            self.faults.inject(statement).await?;
             batch.append_statement(statement);
         }
-        let res = self.session.batch(&batch, values).await?;
-        Ok(res)
+        self.guarded(async { Ok(self.session.batch(&batch, values).await?) })
+            .await
+    }
+
+    // runs the query within the request's deadline, unless the circuit breaker is open.
+    async fn guarded<T>(&self, fut: impl Future<Output = anyhow::Result<T>>) -> anyhow::Result<T> {
+        self.breaker.allow()?;
+        let res = within_deadline(fut).await;
+        let ok = !matches!(&res, Err(err) if is_unavailable(err));
+        self.breaker.record(ok);
+        shed::record_query(ok);
+        res
+    }
+}
+
+// errors of unreachable or overloaded nodes, as opposed to errors of the query itself
+// or the request's deadline.
+fn is_unavailable(err: &anyhow::Error) -> bool {
+    match err.downcast_ref::<QueryError>() {
+        Some(QueryError::DbError(err, _)) => matches!(
+            err,
+            DbError::Unavailable { .. }
+                | DbError::Overloaded
+                | DbError::IsBootstrapping
+                | DbError::ReadTimeout { .. }
+                | DbError::WriteTimeout { .. }
+        ),
+        Some(QueryError::IoError(_))
+        | Some(QueryError::TimeoutError)
+        | Some(QueryError::RequestTimeout(_))
+        | Some(QueryError::TooManyOrphanedStreamIds(_))
+        | Some(QueryError::UnableToAllocStreamId) => true,
+        _ => false,
+    }
+}
+
+fn deadline_exceeded() -> anyhow::Error {
+    HTTPError::new(504, "Request deadline exceeded".to_string()).into()
+}
+
+// bounds the query by the remaining time of the request's deadline, see context::deadline.
+// the budget is applied on the client side, rendering it into `USING TIMEOUT` would make
+// every query text unique and defeat the prepared statement cache.
+// queries are not sent once the deadline is exhausted.
+async fn within_deadline<T>(fut: impl Future<Output = anyhow::Result<T>>) -> anyhow::Result<T> {
+    let deadline = match context::deadline() {
+        Some(deadline) => deadline,
+        None => return fut.await,
+    };
+
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    if remaining.is_zero() {
+        return Err(deadline_exceeded());
     }
+    tokio::time::timeout(remaining, fut)
+        .await
+        .map_err(|_| deadline_exceeded())?
+}
+
+// results of conditional (LWT) statements start with the "[applied]" column.
+fn is_conditional(res: &QueryResult) -> bool {
+    res.col_specs
+        .first()
+        .map(|c| c.name == "[applied]")
+        .unwrap_or(false)
+}
+
+fn is_applied(res: &QueryResult) -> bool {
+    res.rows
+        .as_ref()
+        .and_then(|rows| rows.first())
+        .and_then(|row| row.columns.first())
+        .and_then(|v| v.as_ref())
+        .and_then(|v| v.as_boolean())
+        == Some(true)
 }
 
 pub fn extract_applied(res: QueryResult) -> bool {
@@ -162,6 +437,76 @@ mod tests {
         .await
     }
 
+    #[test]
+    fn tenant_keyspace_works() {
+        assert_eq!("walletbase", tenant_keyspace("walletbase", "").unwrap());
+        assert_eq!(
+            "walletbase_yiwen",
+            tenant_keyspace("walletbase", "yiwen").unwrap()
+        );
+        assert_eq!(
+            "walletbase_test_app_2",
+            tenant_keyspace("walletbase_test", "app_2").unwrap()
+        );
+        assert!(tenant_keyspace("walletbase", "Yiwen").is_err());
+        assert!(tenant_keyspace("walletbase", "yi-wen").is_err());
+        assert!(tenant_keyspace("walletbase", "yiwen;DROP").is_err());
+        assert!(tenant_keyspace("walletbase", &"a".repeat(38)).is_err());
+        assert!(tenant_keyspace("walletbase", &"a".repeat(37)).is_ok());
+    }
+
+    #[test]
+    fn query_hints_works() {
+        let query = "SELECT id FROM transaction WHERE uid=? LIMIT ?";
+        let hints = QueryHints {
+            timeout_ms: 3000,
+            bypass_cache: true,
+        };
+        assert_eq!(
+            "SELECT id FROM transaction WHERE uid=? LIMIT ? USING TIMEOUT 3000ms",
+            hints.apply(query, false)
+        );
+        assert_eq!(
+            "SELECT id FROM transaction WHERE uid=? LIMIT ? BYPASS CACHE USING TIMEOUT 3000ms",
+            hints.apply(query, true)
+        );
+
+        let hints = QueryHints {
+            timeout_ms: 0,
+            bypass_cache: false,
+        };
+        assert_eq!(query, hints.apply(query, false));
+        assert_eq!(query, hints.apply(query, true));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn within_deadline_works() {
+        assert_eq!(1, within_deadline(async { Ok(1) }).await.unwrap());
+
+        let res = context::with_deadline(
+            Instant::now() + Duration::from_secs(1),
+            within_deadline(async { Ok(1) }),
+        )
+        .await;
+        assert_eq!(1, res.unwrap());
+
+        // exhausted deadline, the query is not sent.
+        let res = context::with_deadline(Instant::now(), within_deadline(async { Ok(1) })).await;
+        let err: HTTPError = res.unwrap_err().into();
+        assert_eq!(504, err.code);
+
+        let res = context::with_deadline(
+            Instant::now() + Duration::from_millis(20),
+            within_deadline(async {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                Ok(1)
+            }),
+        )
+        .await;
+        let err: HTTPError = res.unwrap_err().into();
+        assert_eq!(504, err.code);
+    }
+
     #[tokio::test(flavor = "current_thread")]
     async fn exec_cqls_works() {
         let db = get_db().await;
@@ -0,0 +1,244 @@
+use rand_core::{OsRng, RngCore};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock,
+    },
+};
+
+// Shadow writes mirror the writes of primary tables to their secondary tables during a
+// table layout migration, e.g. a new transaction index table, so that the secondary can be
+// validated before the cutover. The secondary must have the same columns and primary key.
+// Sampled reads of a primary are compared with the secondary and mismatches are counted.
+// Shadow failures never fail the primary query, they are logged and counted.
+// BATCH statements are not mirrored.
+
+// (secondary table per primary table, sample rate of reads in basis points)
+static SHADOW: RwLock<Option<(HashMap<String, String>, u32)>> = RwLock::new(None);
+
+static SHADOW_WRITES: AtomicU64 = AtomicU64::new(0);
+static SHADOW_ERRORS: AtomicU64 = AtomicU64::new(0);
+static SHADOW_SAMPLES: AtomicU64 = AtomicU64::new(0);
+static SHADOW_MISMATCHES: AtomicU64 = AtomicU64::new(0);
+
+pub fn set_shadow_tables(tables: &HashMap<String, String>, sample_bps: u32) -> anyhow::Result<()> {
+    for (primary, secondary) in tables {
+        for name in [primary, secondary] {
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+            {
+                return Err(anyhow::anyhow!("Invalid shadow table name {:?}", name));
+            }
+        }
+        if primary == secondary || tables.contains_key(secondary) {
+            return Err(anyhow::anyhow!(
+                "Invalid shadow table {} for {}",
+                secondary,
+                primary
+            ));
+        }
+    }
+    if sample_bps > 10000 {
+        return Err(anyhow::anyhow!("Invalid shadow sample_bps {}", sample_bps));
+    }
+
+    *SHADOW.write().unwrap() = if tables.is_empty() {
+        None
+    } else {
+        Some((tables.clone(), sample_bps))
+    };
+    Ok(())
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ShadowMetrics {
+    pub writes: u64,     // writes mirrored to secondary tables
+    pub errors: u64,     // failed shadow queries
+    pub samples: u64,    // reads compared with secondary tables
+    pub mismatches: u64, // compared reads or conditional writes that differ
+}
+
+pub fn shadow_metrics() -> ShadowMetrics {
+    ShadowMetrics {
+        writes: SHADOW_WRITES.load(Ordering::Relaxed),
+        errors: SHADOW_ERRORS.load(Ordering::Relaxed),
+        samples: SHADOW_SAMPLES.load(Ordering::Relaxed),
+        mismatches: SHADOW_MISMATCHES.load(Ordering::Relaxed),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ShadowQuery {
+    Write(String),
+    Read(String),
+}
+
+// the query rewritten for the secondary table, if it is on a shadowed primary table.
+// reads are only returned when sampled.
+pub(crate) fn shadow_query(query: &str) -> Option<ShadowQuery> {
+    let shadow = SHADOW.read().unwrap();
+    let (tables, sample_bps) = shadow.as_ref()?;
+    let (start, end, read) = table_of(query)?;
+    let secondary = tables.get(&query[start..end])?;
+    if read && (*sample_bps == 0 || OsRng.next_u32() % 10000 >= *sample_bps) {
+        return None;
+    }
+
+    let rewritten = format!("{}{}{}", &query[..start], secondary, &query[end..]);
+    Some(if read {
+        ShadowQuery::Read(rewritten)
+    } else {
+        ShadowQuery::Write(rewritten)
+    })
+}
+
+pub(crate) fn record_write(ok: bool) {
+    SHADOW_WRITES.fetch_add(1, Ordering::Relaxed);
+    if !ok {
+        SHADOW_ERRORS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub(crate) fn record_error() {
+    SHADOW_ERRORS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn record_sample(matched: bool) {
+    SHADOW_SAMPLES.fetch_add(1, Ordering::Relaxed);
+    if !matched {
+        SHADOW_MISMATCHES.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub(crate) fn record_mismatch() {
+    SHADOW_MISMATCHES.fetch_add(1, Ordering::Relaxed);
+}
+
+// byte range of the table name in a CQL statement, and whether it is a read.
+fn table_of(query: &str) -> Option<(usize, usize, bool)> {
+    let query_upper = query.to_ascii_uppercase();
+    let trimmed = query_upper.trim_start();
+    let offset = query_upper.len() - trimmed.len();
+    let (keyword, read) = if trimmed.starts_with("INSERT ") {
+        (" INTO ", false)
+    } else if trimmed.starts_with("UPDATE ") {
+        ("UPDATE ", false)
+    } else if trimmed.starts_with("DELETE ") {
+        (" FROM ", false)
+    } else if trimmed.starts_with("SELECT ") {
+        (" FROM ", true)
+    } else {
+        return None;
+    };
+
+    let start = offset + trimmed.find(keyword)? + keyword.len();
+    let start = start + (query[start..].len() - query[start..].trim_start().len());
+    let len = query[start..]
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(query.len() - start);
+    if len == 0 {
+        return None;
+    }
+    Some((start, start + len, read))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_of_works() {
+        for (query, table, read) in [
+            (
+                "INSERT INTO wallet (uid,sequence) VALUES (?,?)",
+                "wallet",
+                false,
+            ),
+            (
+                "INSERT INTO wallet(uid) VALUES (?) IF NOT EXISTS",
+                "wallet",
+                false,
+            ),
+            (
+                "UPDATE transaction SET status=? WHERE uid=? AND id=? IF status=?",
+                "transaction",
+                false,
+            ),
+            (
+                "DELETE FROM transaction_expiring WHERE day=? AND id=?",
+                "transaction_expiring",
+                false,
+            ),
+            (
+                "DELETE payload FROM transaction WHERE uid=?",
+                "transaction",
+                false,
+            ),
+            (
+                "SELECT uid,id FROM payee_transaction WHERE payee=? LIMIT 10",
+                "payee_transaction",
+                true,
+            ),
+            (" select * from wallet where uid=?", "wallet", true),
+        ] {
+            let (start, end, r) = table_of(query).unwrap();
+            assert_eq!(table, &query[start..end]);
+            assert_eq!(read, r);
+        }
+        assert!(table_of("TRUNCATE wallet").is_none());
+        assert!(table_of("SELECT toUnixTimestamp(now())").is_none());
+    }
+
+    #[test]
+    fn shadow_query_works() {
+        assert!(
+            set_shadow_tables(&HashMap::from([("wallet".to_string(), "".to_string())]), 0).is_err()
+        );
+        assert!(set_shadow_tables(
+            &HashMap::from([("wallet".to_string(), "wallet".to_string())]),
+            0
+        )
+        .is_err());
+        assert!(set_shadow_tables(
+            &HashMap::from([("wallet".to_string(), "Wallet-2".to_string())]),
+            0
+        )
+        .is_err());
+        assert!(set_shadow_tables(
+            &HashMap::from([("wallet".to_string(), "wallet_v2".to_string())]),
+            10001
+        )
+        .is_err());
+
+        let tables = HashMap::from([("transaction".to_string(), "transaction_v2".to_string())]);
+        set_shadow_tables(&tables, 0).unwrap();
+        assert_eq!(
+            Some(ShadowQuery::Write(
+                "UPDATE transaction_v2 SET status=? WHERE uid=? AND id=?".to_string()
+            )),
+            shadow_query("UPDATE transaction SET status=? WHERE uid=? AND id=?")
+        );
+        assert_eq!(
+            None,
+            shadow_query("UPDATE transaction_expiring SET uid=? WHERE day=?")
+        );
+        assert_eq!(None, shadow_query("SELECT * FROM transaction WHERE uid=?"));
+
+        set_shadow_tables(&tables, 10000).unwrap();
+        assert_eq!(
+            Some(ShadowQuery::Read(
+                "SELECT * FROM transaction_v2 WHERE uid=?".to_string()
+            )),
+            shadow_query("SELECT * FROM transaction WHERE uid=?")
+        );
+
+        set_shadow_tables(&HashMap::new(), 0).unwrap();
+        assert_eq!(
+            None,
+            shadow_query("UPDATE transaction SET status=? WHERE uid=?")
+        );
+    }
+}
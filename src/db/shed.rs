@@ -0,0 +1,303 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum_web::erring::HTTPError;
+
+// load shedding rejects new prepares and commits early while ScyllaDB is degraded, before
+// LWT retries amplify the load until everything times out. It tracks the LWT retry rate
+// and the query error rate of a window, and sheds for at least the next window once
+// either exceeds its threshold. Unlike the circuit breaker, reads and the completion of
+// prepared transactions are not rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShedConf {
+    pub retry_rate: u32, // LWT retries per 100 LWT updates in a window to shed, 0 disables it
+    pub error_rate: u32, // percent of failed queries in a window to shed, 0 disables it
+    pub min_requests: u32, // LWT updates or queries in a window before the rate applies
+    pub window: Duration,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Counts {
+    lwt_calls: u32,
+    lwt_retries: u32,
+    queries: u32,
+    errors: u32,
+}
+
+impl Counts {
+    fn exceeds(&self, conf: &ShedConf) -> bool {
+        let min = conf.min_requests.max(1);
+        (conf.retry_rate > 0
+            && self.lwt_calls >= min
+            && self.lwt_retries as u64 * 100 >= conf.retry_rate as u64 * self.lwt_calls as u64)
+            || (conf.error_rate > 0
+                && self.queries >= min
+                && self.errors as u64 * 100 >= conf.error_rate as u64 * self.queries as u64)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ShedStatus {
+    pub shedding: bool,
+    pub retry_after_secs: u64,
+    pub shed: u64,     // times the shedding started
+    pub rejected: u64, // requests rejected while shedding
+}
+
+struct Shedder {
+    conf: ShedConf,
+    since: Option<Instant>,
+    counts: Counts,
+    shedding: bool,
+    shed: u64,
+    rejected: u64,
+}
+
+impl Shedder {
+    const fn new(conf: ShedConf) -> Self {
+        Self {
+            conf,
+            since: None,
+            counts: Counts {
+                lwt_calls: 0,
+                lwt_retries: 0,
+                queries: 0,
+                errors: 0,
+            },
+            shedding: false,
+            shed: 0,
+            rejected: 0,
+        }
+    }
+
+    fn disabled(&self) -> bool {
+        self.conf.retry_rate == 0 && self.conf.error_rate == 0
+    }
+
+    // starts a new window once the current one elapsed, the shedding of the new window
+    // is decided by the elapsed one, or stops if the elapsed one is stale.
+    fn roll(&mut self, now: Instant) {
+        let since = match self.since {
+            Some(since) => since,
+            None => {
+                self.since = Some(now);
+                return;
+            }
+        };
+
+        let elapsed = now.duration_since(since);
+        if elapsed < self.conf.window {
+            return;
+        }
+
+        let shedding = elapsed < self.conf.window * 2 && self.counts.exceeds(&self.conf);
+        if shedding && !self.shedding {
+            self.start();
+        } else if !shedding && self.shedding {
+            log::info!(target: "scylladb", "load shedding stopped");
+        }
+        self.shedding = shedding;
+        self.since = Some(now);
+        self.counts = Counts::default();
+    }
+
+    fn record(&mut self, now: Instant, f: impl FnOnce(&mut Counts)) {
+        if self.disabled() {
+            return;
+        }
+
+        self.roll(now);
+        f(&mut self.counts);
+        if !self.shedding && self.counts.exceeds(&self.conf) {
+            self.shedding = true;
+            self.start();
+        }
+    }
+
+    fn start(&mut self) {
+        self.shed += 1;
+        log::warn!(target: "scylladb",
+            lwt_calls = self.counts.lwt_calls,
+            lwt_retries = self.counts.lwt_retries,
+            queries = self.counts.queries,
+            errors = self.counts.errors;
+            "load shedding started",
+        );
+    }
+
+    fn allow(&mut self, now: Instant) -> Result<(), HTTPError> {
+        if self.disabled() {
+            return Ok(());
+        }
+
+        self.roll(now);
+        if !self.shedding {
+            return Ok(());
+        }
+        self.rejected += 1;
+        Err(HTTPError::new(
+            503,
+            format!(
+                "ScyllaDB overloaded, retry after {}s",
+                self.retry_after_secs(now)
+            ),
+        ))
+    }
+
+    fn retry_after_secs(&self, now: Instant) -> u64 {
+        let remaining = match self.since {
+            Some(since) => self.conf.window.saturating_sub(now.duration_since(since)),
+            None => Duration::ZERO,
+        };
+        ((remaining.as_millis() as u64 + 999) / 1000).max(1)
+    }
+
+    fn status(&mut self, now: Instant) -> ShedStatus {
+        if !self.disabled() {
+            self.roll(now);
+        }
+        ShedStatus {
+            shedding: self.shedding,
+            retry_after_secs: if self.shedding {
+                self.retry_after_secs(now)
+            } else {
+                0
+            },
+            shed: self.shed,
+            rejected: self.rejected,
+        }
+    }
+}
+
+static SHEDDER: Mutex<Shedder> = Mutex::new(Shedder::new(ShedConf {
+    retry_rate: 0,
+    error_rate: 0,
+    min_requests: 50,
+    window: Duration::from_secs(10),
+}));
+
+pub fn set_load_shedding(conf: ShedConf) {
+    let mut shedder = SHEDDER.lock().unwrap();
+    *shedder = Shedder::new(conf);
+}
+
+// returns 503 if new prepares and commits should be rejected.
+pub fn shed_load() -> Result<(), HTTPError> {
+    SHEDDER.lock().unwrap().allow(Instant::now())
+}
+
+pub fn shed_status() -> ShedStatus {
+    SHEDDER.lock().unwrap().status(Instant::now())
+}
+
+pub(super) fn record_lwt(retry: bool) {
+    SHEDDER.lock().unwrap().record(Instant::now(), |c| {
+        if retry {
+            c.lwt_retries += 1;
+        } else {
+            c.lwt_calls += 1;
+        }
+    });
+}
+
+// records the outcome of a sent query, ok is false only if ScyllaDB was unavailable.
+pub(super) fn record_query(ok: bool) {
+    SHEDDER.lock().unwrap().record(Instant::now(), |c| {
+        c.queries += 1;
+        c.errors += !ok as u32;
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shedder() -> Shedder {
+        Shedder::new(ShedConf {
+            retry_rate: 100,
+            error_rate: 50,
+            min_requests: 4,
+            window: Duration::from_secs(10),
+        })
+    }
+
+    #[test]
+    fn shedder_sheds_on_retry_rate() {
+        let mut s = shedder();
+        let now = Instant::now();
+        for _ in 0..3 {
+            s.record(now, |c| c.lwt_calls += 1);
+            s.record(now, |c| c.lwt_retries += 1);
+        }
+        assert!(s.allow(now).is_ok());
+
+        s.record(now, |c| c.lwt_calls += 1);
+        assert!(s.allow(now).is_ok());
+        s.record(now, |c| c.lwt_retries += 1);
+        let err = s.allow(now).unwrap_err();
+        assert_eq!(503, err.code);
+
+        let status = s.status(now + Duration::from_secs(3));
+        assert!(status.shedding);
+        assert_eq!(7, status.retry_after_secs);
+        assert_eq!(1, status.shed);
+        assert_eq!(1, status.rejected);
+
+        // the next window is shed too, since the elapsed one exceeded the rate.
+        let next = now + Duration::from_secs(10);
+        assert!(s.allow(next).is_err());
+        assert_eq!(1, s.status(next).shed);
+
+        // then it stops after a window below the rates.
+        for _ in 0..4 {
+            s.record(next, |c| c.lwt_calls += 1);
+        }
+        let next = next + Duration::from_secs(10);
+        assert!(s.allow(next).is_ok());
+        assert_eq!(2, s.status(next).rejected);
+    }
+
+    #[test]
+    fn shedder_sheds_on_error_rate() {
+        let mut s = shedder();
+        let now = Instant::now();
+        for ok in [true, false, true] {
+            s.record(now, |c| {
+                c.queries += 1;
+                c.errors += !ok as u32;
+            });
+        }
+        assert!(s.allow(now).is_ok());
+        s.record(now, |c| {
+            c.queries += 1;
+            c.errors += 1;
+        });
+        assert!(s.allow(now).is_err());
+
+        // a stale window does not shed.
+        assert!(s.allow(now + Duration::from_secs(20)).is_ok());
+        assert!(!s.status(now + Duration::from_secs(20)).shedding);
+    }
+
+    #[test]
+    fn shedder_disabled() {
+        let mut s = Shedder::new(ShedConf {
+            retry_rate: 0,
+            error_rate: 0,
+            min_requests: 1,
+            window: Duration::from_secs(10),
+        });
+        let now = Instant::now();
+        for _ in 0..10 {
+            s.record(now, |c| {
+                c.lwt_retries += 1;
+                c.queries += 1;
+                c.errors += 1;
+            });
+        }
+        assert!(s.allow(now).is_ok());
+        assert_eq!(ShedStatus::default(), s.status(now));
+    }
+}
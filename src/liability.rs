@@ -0,0 +1,122 @@
+use std::{sync::Arc, time::Duration};
+
+use axum_web::context::unix_ms;
+
+use crate::{api::AppState, conf, db};
+
+#[derive(Debug, Default, Clone)]
+pub struct RunStats {
+    pub scanned: u64,
+    pub expected: db::Balance, // sum of user wallets
+    pub drift: db::Balance,    // maintained totals minus expected, corrected
+}
+
+// adds the balances of user wallets, the system wallet is not a liability.
+pub fn sum_balances(total: &mut db::Balance, wallets: &[db::Wallet]) {
+    for wallet in wallets.iter().filter(|w| !w.is_system()) {
+        total.award += wallet.award;
+        total.topup += wallet.topup;
+        total.income += wallet.income;
+    }
+}
+
+// sums the balances of all wallets and reconciles the maintained liabilities against it.
+// archived wallets have zero balances, they are not scanned.
+pub async fn run_once(
+    db: &db::scylladb::ScyllaDB,
+    cfg: &conf::Liability,
+) -> anyhow::Result<RunStats> {
+    let mut stats = RunStats::default();
+    let page_size = cfg.page_size.max(1);
+    let mut page_token: Option<xid::Id> = None;
+    loop {
+        let wallets = db::Wallet::scan(db, page_size, page_token).await?;
+        let has_next = wallets.len() >= page_size as usize;
+        page_token = wallets.last().map(|w| w.uid);
+        stats.scanned += wallets.len() as u64;
+        sum_balances(&mut stats.expected, &wallets);
+
+        if !has_next {
+            break;
+        }
+    }
+
+    for doc in db::Liability::reconcile(db, &stats.expected).await? {
+        match doc.bucket.as_str() {
+            "award" => stats.drift.award = doc.drift,
+            "topup" => stats.drift.topup = doc.drift,
+            _ => stats.drift.income = doc.drift,
+        }
+    }
+    Ok(stats)
+}
+
+// runs the reconciliation every interval in the background.
+pub fn spawn(app: Arc<AppState>, cfg: conf::Liability) {
+    tokio::spawn(async move {
+        let interval = cfg.interval_secs.max(3600);
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            if !db::JobLease::hold(&app.scylla, "liability", interval).await {
+                continue;
+            }
+            let start = unix_ms();
+            match run_once(&app.scylla, &cfg).await {
+                Ok(stats) => log::info!(target: "liability",
+                    scanned = stats.scanned,
+                    award = stats.expected.award,
+                    topup = stats.expected.topup,
+                    income = stats.expected.income,
+                    award_drift = stats.drift.award,
+                    topup_drift = stats.drift.topup,
+                    income_drift = stats.drift.income,
+                    elapsed = unix_ms() - start;
+                    "",
+                ),
+                Err(err) => log::error!(target: "liability", "liability job failed: {}", err),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sum_balances_works() {
+        let wallets = vec![
+            db::Wallet {
+                uid: db::SYS_ID,
+                award: -300,
+                topup: -50,
+                income: 7,
+                ..Default::default()
+            },
+            db::Wallet {
+                uid: xid::new(),
+                award: 100,
+                topup: 50,
+                ..Default::default()
+            },
+            db::Wallet {
+                uid: xid::new(),
+                award: 200,
+                topup: -10,
+                income: 3,
+                ..Default::default()
+            },
+        ];
+        let mut total = db::Balance::default();
+        sum_balances(&mut total, &wallets);
+        assert_eq!(
+            db::Balance {
+                award: 300,
+                topup: 40,
+                income: 3,
+            },
+            total
+        );
+    }
+}
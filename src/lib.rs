@@ -1,2 +1,23 @@
+pub mod alert;
+pub mod api;
+pub mod archive;
 pub mod conf;
+pub mod crypto;
 pub mod db;
+pub mod liability;
+pub mod moderation;
+pub mod money;
+pub mod notify;
+pub mod policy;
+pub mod receipt;
+pub mod recovery;
+pub mod redpacket;
+pub mod reminder;
+pub mod router;
+pub mod stripe;
+pub mod subscription;
+pub mod summary;
+pub mod webhook;
+
+#[cfg(feature = "client")]
+pub mod client;
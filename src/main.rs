@@ -1,13 +1,28 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, str::FromStr, sync::Arc};
 
 use structured_logger::{async_json::new_writer, Builder};
-use tokio::{io, signal};
+use tokio::{io, signal, sync::watch};
 
+mod alert;
 mod api;
+mod archive;
 mod conf;
 mod crypto;
 mod db;
+mod liability;
+mod moderation;
+mod money;
+mod notify;
+mod policy;
+mod receipt;
+mod recovery;
+mod redpacket;
+mod reminder;
 mod router;
+mod stripe;
+mod subscription;
+mod summary;
+mod webhook;
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 4)]
 async fn main() -> anyhow::Result<()> {
@@ -20,9 +35,89 @@ async fn main() -> anyhow::Result<()> {
 
     log::debug!("{:?}", cfg);
 
+    let role = match role_arg(std::env::args().skip(1))? {
+        Some(role) => role,
+        None => cfg.role,
+    };
     let server_cfg = cfg.server.clone();
     let server_env = cfg.env.clone();
-    let (app_state, app) = router::new(cfg).await?;
+    let policy_cfg = cfg.policy.clone();
+    let reminder_cfg = cfg.reminder.clone();
+    let summary_cfg = cfg.summary.clone();
+    let recovery_cfg = cfg.recovery.clone();
+    let alert_cfg = cfg.alert.clone();
+    let redpacket_cfg = cfg.redpacket.clone();
+    let subscription_cfg = cfg.subscription.clone();
+    let archive_cfg = cfg.archive.clone();
+    let liability_cfg = cfg.liability.clone();
+    let webhook_cfg = cfg.webhook.clone();
+    let (app_states, app) = router::new(cfg).await?;
+    if role.runs_jobs() {
+        if policy_cfg.enabled {
+            for app_state in &app_states {
+                policy::spawn(app_state.clone(), policy_cfg.clone())?;
+            }
+        }
+        let sink: Arc<dyn notify::Sink> = Arc::new(notify::LogSink);
+        if reminder_cfg.enabled {
+            for app_state in &app_states {
+                reminder::spawn(app_state.clone(), reminder_cfg.clone(), sink.clone());
+            }
+        }
+        if recovery_cfg.enabled {
+            for app_state in &app_states {
+                recovery::spawn(app_state.clone(), recovery_cfg.clone());
+            }
+        }
+        if summary_cfg.enabled {
+            for app_state in &app_states {
+                summary::spawn(app_state.clone(), summary_cfg.clone(), sink.clone());
+            }
+        }
+        if alert_cfg.enabled {
+            for app_state in &app_states {
+                alert::spawn(app_state.clone(), alert_cfg.clone(), sink.clone());
+            }
+        }
+        if redpacket_cfg.enabled {
+            for app_state in &app_states {
+                redpacket::spawn(app_state.clone(), redpacket_cfg.clone());
+            }
+        }
+        if subscription_cfg.enabled {
+            for app_state in &app_states {
+                subscription::spawn(app_state.clone(), subscription_cfg.clone(), sink.clone());
+            }
+        }
+        if archive_cfg.enabled {
+            for app_state in &app_states {
+                archive::spawn(app_state.clone(), archive_cfg.clone());
+            }
+        }
+        if liability_cfg.enabled {
+            for app_state in &app_states {
+                liability::spawn(app_state.clone(), liability_cfg.clone());
+            }
+        }
+        if webhook_cfg.enabled {
+            for app_state in &app_states {
+                webhook::spawn(app_state.clone(), webhook_cfg.clone())?;
+            }
+        }
+    }
+    let app_state = app_states[0].clone();
+
+    // a worker runs the jobs until the signal, without listeners.
+    if !role.serves() {
+        log::info!(
+            "{}@{} start {} worker",
+            api::APP_NAME,
+            api::APP_VERSION,
+            server_env
+        );
+        shutdown_signal(app_state, server_cfg.graceful_shutdown).await;
+        return Ok(());
+    }
 
     let addr = SocketAddr::from(([0, 0, 0, 0], server_cfg.port));
     log::info!(
@@ -32,14 +127,123 @@ async fn main() -> anyhow::Result<()> {
         server_env,
         &addr
     );
-    axum::Server::bind(&addr)
+
+    // both listeners shut down on the same signal.
+    let (shutdown_tx, shutdown_rx) = watch::channel(());
+    let graceful_shutdown = server_cfg.graceful_shutdown;
+    tokio::spawn(async move {
+        shutdown_signal(app_state, graceful_shutdown).await;
+        let _ = shutdown_tx.send(());
+    });
+
+    let tcp = async {
+        axum::Server::bind(&addr)
+            .http1_only(!server_cfg.http2)
+            .serve(app.clone().into_make_service())
+            .with_graceful_shutdown(shutdown_wait(shutdown_rx.clone()))
+            .await
+            .map_err(anyhow::Error::from)
+    };
+    let uds = async {
+        if server_cfg.unix_socket.is_empty() {
+            return Ok(());
+        }
+        log::info!(
+            "{}@{} start at {}",
+            api::APP_NAME,
+            api::APP_VERSION,
+            &server_cfg.unix_socket
+        );
+        serve_unix(
+            &server_cfg.unix_socket,
+            server_cfg.http2,
+            app.clone(),
+            shutdown_rx.clone(),
+        )
+        .await
+    };
+    tokio::try_join!(tcp, uds)?;
+
+    Ok(())
+}
+
+// parses `--role <role>` or `--role=<role>` of the command line.
+fn role_arg(args: impl Iterator<Item = String>) -> anyhow::Result<Option<conf::Role>> {
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        let val = if arg == "--role" {
+            args.next()
+                .ok_or_else(|| anyhow::anyhow!("missing value of --role"))?
+        } else if let Some(val) = arg.strip_prefix("--role=") {
+            val.to_string()
+        } else {
+            continue;
+        };
+        return conf::Role::from_str(&val)
+            .map(Some)
+            .map_err(anyhow::Error::msg);
+    }
+    Ok(None)
+}
+
+async fn shutdown_wait(mut rx: watch::Receiver<()>) {
+    let _ = rx.changed().await;
+}
+
+#[cfg(unix)]
+async fn serve_unix(
+    path: &str,
+    http2: bool,
+    app: axum::Router,
+    shutdown_rx: watch::Receiver<()>,
+) -> anyhow::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            anyhow::bail!("{} exists and is not a unix socket", path);
+        }
+        std::fs::remove_file(path)?;
+    }
+
+    let listener = tokio::net::UnixListener::bind(path)?;
+    hyper::Server::builder(UnixAccept(listener))
+        .http1_only(!http2)
         .serve(app.into_make_service())
-        .with_graceful_shutdown(shutdown_signal(app_state, server_cfg.graceful_shutdown))
+        .with_graceful_shutdown(shutdown_wait(shutdown_rx))
         .await?;
-
+    let _ = std::fs::remove_file(path);
     Ok(())
 }
 
+#[cfg(not(unix))]
+async fn serve_unix(
+    path: &str,
+    _http2: bool,
+    _app: axum::Router,
+    _shutdown_rx: watch::Receiver<()>,
+) -> anyhow::Result<()> {
+    anyhow::bail!("unix socket {} is not supported on this platform", path)
+}
+
+#[cfg(unix)]
+struct UnixAccept(tokio::net::UnixListener);
+
+#[cfg(unix)]
+impl hyper::server::accept::Accept for UnixAccept {
+    type Conn = tokio::net::UnixStream;
+    type Error = std::io::Error;
+
+    fn poll_accept(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Result<Self::Conn, Self::Error>>> {
+        self.0
+            .poll_accept(cx)
+            .map(|res| Some(res.map(|(stream, _)| stream)))
+    }
+}
+
 async fn shutdown_signal(_app: Arc<api::AppState>, _wait_secs: usize) {
     let ctrl_c = async {
         signal::ctrl_c()
@@ -65,3 +269,35 @@ async fn shutdown_signal(_app: Arc<api::AppState>, _wait_secs: usize) {
 
     log::info!("signal received, Goodbye!");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(v: &[&str]) -> impl Iterator<Item = String> {
+        v.iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn role_arg_works() {
+        assert_eq!(None, role_arg(args(&[])).unwrap());
+        assert_eq!(None, role_arg(args(&["--other", "x"])).unwrap());
+        assert_eq!(
+            Some(conf::Role::Worker),
+            role_arg(args(&["--role", "worker"])).unwrap()
+        );
+        assert_eq!(
+            Some(conf::Role::Serve),
+            role_arg(args(&["--role=serve"])).unwrap()
+        );
+        assert!(role_arg(args(&["--role"])).is_err());
+        assert!(role_arg(args(&["--role", "jobs"])).is_err());
+
+        assert!(conf::Role::All.serves() && conf::Role::All.runs_jobs());
+        assert!(!conf::Role::Worker.serves());
+        assert!(!conf::Role::Serve.runs_jobs());
+    }
+}
@@ -0,0 +1,213 @@
+use async_trait::async_trait;
+use reqwest::header;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::conf;
+
+// flags stored on transactions whose user-supplied text was moderated.
+pub const FLAG_NONE: i8 = 0;
+pub const FLAG_REDACTED: i8 = 1;
+pub const FLAG_BLOCKED: i8 = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    Allow,
+    Redact(String), // the redacted text to store instead
+    Block,
+}
+
+// checks user-supplied text, e.g. descriptions of sponsorships, before it is stored.
+#[async_trait]
+pub trait Moderator: Send + Sync {
+    async fn check(&self, kind: &str, uid: &xid::Id, text: &str) -> anyhow::Result<Verdict>;
+}
+
+#[derive(Serialize)]
+struct CheckRequest<'a> {
+    kind: &'a str,
+    uid: String,
+    text: &'a str,
+}
+
+// response of the moderation service, action is "allow", "redact" or "block".
+#[derive(Debug, Deserialize)]
+struct CheckResponse {
+    action: String,
+    #[serde(default)]
+    text: String,
+}
+
+impl CheckResponse {
+    fn verdict(self) -> anyhow::Result<Verdict> {
+        match self.action.as_str() {
+            "allow" => Ok(Verdict::Allow),
+            "redact" => Ok(Verdict::Redact(self.text)),
+            "block" => Ok(Verdict::Block),
+            action => Err(anyhow::anyhow!("invalid moderation action {}", action)),
+        }
+    }
+}
+
+// posts the text as JSON to the moderation service configured in conf.
+pub struct HttpModerator {
+    http: reqwest::Client,
+    url: String,
+    token: String,
+}
+
+impl HttpModerator {
+    pub fn new(cfg: &conf::Moderation) -> anyhow::Result<Self> {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(cfg.timeout_secs.max(1)))
+            .build()?;
+        Ok(Self {
+            http,
+            url: cfg.url.clone(),
+            token: cfg.token.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl Moderator for HttpModerator {
+    async fn check(&self, kind: &str, uid: &xid::Id, text: &str) -> anyhow::Result<Verdict> {
+        let body = serde_json::to_vec(&CheckRequest {
+            kind,
+            uid: uid.to_string(),
+            text,
+        })?;
+        let mut req = self
+            .http
+            .post(&self.url)
+            .header(header::ACCEPT, "application/json")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(body);
+        if !self.token.is_empty() {
+            req = req.bearer_auth(&self.token);
+        }
+
+        let res = req.send().await?.error_for_status()?;
+        let body = res.bytes().await?;
+        serde_json::from_slice::<CheckResponse>(&body)?.verdict()
+    }
+}
+
+pub struct Moderation {
+    moderator: Box<dyn Moderator>,
+    fail_open: bool, // keeps the text if the check fails, otherwise it is blocked
+}
+
+impl Moderation {
+    pub fn new(moderator: Box<dyn Moderator>, fail_open: bool) -> Self {
+        Self {
+            moderator,
+            fail_open,
+        }
+    }
+
+    // returns None if moderation is not configured.
+    pub fn from_conf(cfg: &conf::Moderation) -> anyhow::Result<Option<Self>> {
+        if cfg.url.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Self::new(
+            Box::new(HttpModerator::new(cfg)?),
+            cfg.fail_open,
+        )))
+    }
+
+    // returns the text to store and its moderation flag.
+    pub async fn moderate(&self, kind: &str, uid: &xid::Id, text: String) -> (String, i8) {
+        if text.is_empty() {
+            return (text, FLAG_NONE);
+        }
+
+        match self.moderator.check(kind, uid, &text).await {
+            Ok(Verdict::Allow) => (text, FLAG_NONE),
+            Ok(Verdict::Redact(redacted)) => (redacted, FLAG_REDACTED),
+            Ok(Verdict::Block) => (String::new(), FLAG_BLOCKED),
+            Err(err) => {
+                log::warn!(target: "moderation",
+                    kind = kind,
+                    uid = uid.to_string(),
+                    fail_open = self.fail_open;
+                    "{}", err,
+                );
+                if self.fail_open {
+                    (text, FLAG_NONE)
+                } else {
+                    (String::new(), FLAG_BLOCKED)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockModerator;
+
+    #[async_trait]
+    impl Moderator for MockModerator {
+        async fn check(&self, _kind: &str, _uid: &xid::Id, text: &str) -> anyhow::Result<Verdict> {
+            match text {
+                "spam" => Ok(Verdict::Block),
+                "call 123" => Ok(Verdict::Redact("call ***".to_string())),
+                "timeout" => Err(anyhow::anyhow!("timeout")),
+                _ => Ok(Verdict::Allow),
+            }
+        }
+    }
+
+    #[test]
+    fn verdict_works() {
+        let res: CheckResponse = serde_json::from_str(r#"{"action":"allow"}"#).unwrap();
+        assert_eq!(Verdict::Allow, res.verdict().unwrap());
+        let res: CheckResponse =
+            serde_json::from_str(r#"{"action":"redact","text":"***"}"#).unwrap();
+        assert_eq!(Verdict::Redact("***".to_string()), res.verdict().unwrap());
+        let res: CheckResponse = serde_json::from_str(r#"{"action":"block"}"#).unwrap();
+        assert_eq!(Verdict::Block, res.verdict().unwrap());
+        let res: CheckResponse = serde_json::from_str(r#"{"action":"review"}"#).unwrap();
+        assert!(res.verdict().is_err());
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn moderate_works() {
+        let uid = xid::new();
+        let m = Moderation::new(Box::new(MockModerator), true);
+        assert_eq!(
+            ("thanks".to_string(), FLAG_NONE),
+            m.moderate("sponsor", &uid, "thanks".to_string()).await
+        );
+        assert_eq!(
+            ("call ***".to_string(), FLAG_REDACTED),
+            m.moderate("sponsor", &uid, "call 123".to_string()).await
+        );
+        assert_eq!(
+            (String::new(), FLAG_BLOCKED),
+            m.moderate("sponsor", &uid, "spam".to_string()).await
+        );
+        assert_eq!(
+            ("timeout".to_string(), FLAG_NONE),
+            m.moderate("sponsor", &uid, "timeout".to_string()).await
+        );
+        assert_eq!(
+            (String::new(), FLAG_NONE),
+            m.moderate("sponsor", &uid, String::new()).await
+        );
+
+        let m = Moderation::new(Box::new(MockModerator), false);
+        assert_eq!(
+            (String::new(), FLAG_BLOCKED),
+            m.moderate("subscribe", &uid, "timeout".to_string()).await
+        );
+        assert_eq!(
+            ("thanks".to_string(), FLAG_NONE),
+            m.moderate("subscribe", &uid, "thanks".to_string()).await
+        );
+    }
+}
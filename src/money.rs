@@ -0,0 +1,198 @@
+// formats amounts in the smallest currency unit for display, without floating point.
+
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+use utoipa::ToSchema;
+
+use crate::conf;
+
+pub const DEFAULT_LOCALE: &str = "en";
+
+// max exponent of the coin, amounts of i64 have at most 19 digits.
+pub const MAX_EXPONENT: u8 = 18;
+
+// the coin of wallet amounts, it is set from conf at startup.
+static DENOMINATION: RwLock<Option<Denomination>> = RwLock::new(None);
+
+// the coin of wallet amounts, so that downstream systems do not hardcode the conversion.
+// amounts are integers in the smallest unit, displayed as amount / 10^exponent coins.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, ToSchema)]
+pub struct Denomination {
+    pub name: String,
+    pub symbol: String,
+    pub unit: String, // name of the smallest unit
+    pub exponent: u8,
+}
+
+impl Default for Denomination {
+    fn default() -> Self {
+        Self::from(&conf::Denomination::default())
+    }
+}
+
+impl From<&conf::Denomination> for Denomination {
+    fn from(cfg: &conf::Denomination) -> Self {
+        Self {
+            name: cfg.name.clone(),
+            symbol: cfg.symbol.clone(),
+            unit: cfg.unit.clone(),
+            exponent: cfg.exponent,
+        }
+    }
+}
+
+impl Denomination {
+    // formats the amount in the smallest unit as coins, e.g. 12345 with exponent 2 is "123.45".
+    pub fn format(&self, amount: i64, locale: &str) -> String {
+        format_amount(amount, self.exponent, locale)
+    }
+}
+
+pub fn set_denomination(cfg: &conf::Denomination) -> anyhow::Result<()> {
+    if cfg.name.is_empty() || cfg.unit.is_empty() {
+        anyhow::bail!("Invalid denomination in conf, name and unit are required");
+    }
+    if cfg.exponent > MAX_EXPONENT {
+        anyhow::bail!(
+            "Invalid denomination exponent in conf: {}, expected at most {}",
+            cfg.exponent,
+            MAX_EXPONENT
+        );
+    }
+    *DENOMINATION.write().unwrap() = Some(Denomination::from(cfg));
+    Ok(())
+}
+
+pub fn denomination() -> Denomination {
+    DENOMINATION.read().unwrap().clone().unwrap_or_default()
+}
+
+// (group separator, decimal separator) of the BCP 47 language tag, by its primary language
+// and region. unknown locales fall back to the English separators.
+pub fn separators(locale: &str) -> (&'static str, &'static str) {
+    let tag = locale.replace('_', "-").to_ascii_lowercase();
+    let mut parts = tag.split('-');
+    let lang = parts.next().unwrap_or_default();
+    let region = parts.find(|p| p.len() == 2).unwrap_or_default();
+
+    match (lang, region) {
+        ("de", "ch") | ("it", "ch") => ("’", "."),
+        ("fr", "ch") => ("\u{202f}", "."),
+        ("es", "mx") => (",", "."),
+        ("de", _)
+        | ("es", _)
+        | ("it", _)
+        | ("pt", _)
+        | ("nl", _)
+        | ("id", _)
+        | ("tr", _)
+        | ("da", _)
+        | ("el", _) => (".", ","),
+        ("fr", _) => ("\u{202f}", ","),
+        ("ru", _) | ("uk", _) | ("pl", _) | ("cs", _) | ("sv", _) | ("nb", _) | ("fi", _) => {
+            ("\u{a0}", ",")
+        }
+        _ => (",", "."),
+    }
+}
+
+// formats the amount with the currency's decimals and the locale's separators,
+// e.g. 123456 with 2 decimals is "1,234.56" in "en" and "1.234,56" in "de".
+pub fn format_amount(amount: i64, decimals: u8, locale: &str) -> String {
+    let (group, decimal) = separators(locale);
+    let digits = amount.unsigned_abs().to_string();
+    let decimals = decimals as usize;
+    let digits = if digits.len() <= decimals {
+        format!("{}{}", "0".repeat(decimals + 1 - digits.len()), digits)
+    } else {
+        digits
+    };
+
+    let (int, frac) = digits.split_at(digits.len() - decimals);
+    let mut rt = String::with_capacity(digits.len() + int.len() / 3 * group.len() + 2);
+    if amount < 0 {
+        rt.push('-');
+    }
+    for (i, c) in int.chars().enumerate() {
+        if i > 0 && (int.len() - i) % 3 == 0 {
+            rt.push_str(group);
+        }
+        rt.push(c);
+    }
+    if !frac.is_empty() {
+        rt.push_str(decimal);
+        rt.push_str(frac);
+    }
+    rt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn separators_works() {
+        assert_eq!((",", "."), separators(""));
+        assert_eq!((",", "."), separators("en-US"));
+        assert_eq!((",", "."), separators("zh-Hans-CN"));
+        assert_eq!((".", ","), separators("de"));
+        assert_eq!((".", ","), separators("pt_BR"));
+        assert_eq!(("’", "."), separators("de-CH"));
+        assert_eq!(("\u{202f}", ","), separators("fr-FR"));
+        assert_eq!(("\u{a0}", ","), separators("ru"));
+        assert_eq!((",", "."), separators("es-MX"));
+    }
+
+    #[test]
+    fn denomination_works() {
+        let cfg = conf::Denomination::default();
+        assert_eq!("Yiwen Coin", denomination().name);
+        assert_eq!(0, denomination().exponent);
+        assert_eq!("1,234", denomination().format(1234, "en"));
+
+        assert!(set_denomination(&conf::Denomination {
+            name: "".to_string(),
+            ..cfg.clone()
+        })
+        .is_err());
+        assert!(set_denomination(&conf::Denomination {
+            exponent: 19,
+            ..cfg.clone()
+        })
+        .is_err());
+
+        set_denomination(&conf::Denomination {
+            symbol: "YC".to_string(),
+            unit: "cent".to_string(),
+            exponent: 2,
+            ..cfg.clone()
+        })
+        .unwrap();
+        let d = denomination();
+        assert_eq!("cent", d.unit);
+        assert_eq!("12.34", d.format(1234, "en"));
+        assert_eq!("12,34", d.format(1234, "de"));
+        set_denomination(&cfg).unwrap();
+        assert_eq!(Denomination::default(), denomination());
+    }
+
+    #[test]
+    fn format_amount_works() {
+        assert_eq!("0.00", format_amount(0, 2, "en"));
+        assert_eq!("0.05", format_amount(5, 2, "en"));
+        assert_eq!("1.00", format_amount(100, 2, "en"));
+        assert_eq!("1,234.56", format_amount(123456, 2, "en"));
+        assert_eq!("-1,234.56", format_amount(-123456, 2, "en"));
+        assert_eq!("1.234,56", format_amount(123456, 2, "de"));
+        assert_eq!("1\u{a0}234,56", format_amount(123456, 2, "ru"));
+        assert_eq!("0", format_amount(0, 0, "ja"));
+        assert_eq!("999", format_amount(999, 0, "ja"));
+        assert_eq!("123,456", format_amount(123456, 0, "ja"));
+        assert_eq!("1,234,567", format_amount(1234567, 0, "ko"));
+        assert_eq!("1.234", format_amount(1234, 3, "en"));
+        assert_eq!(
+            "-9,223,372,036,854,775.808",
+            format_amount(i64::MIN, 3, "en")
+        );
+    }
+}
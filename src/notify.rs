@@ -0,0 +1,36 @@
+use serde::Serialize;
+
+// Outbound notifications to users, e.g. reminders of pending checkouts.
+// The wallet does not deliver them, they are handed to a sink and delivered by the
+// notification service.
+#[derive(Debug, Clone, Serialize)]
+pub struct Notification {
+    pub kind: &'static str,
+    pub uid: String,
+    pub ref_id: String, // the object notified about, e.g. the charge id
+    pub locale: String, // the user's preferred locale, empty for unset
+    pub payload: serde_json::Value,
+}
+
+pub trait Sink: Send + Sync {
+    fn send(&self, notification: &Notification) -> anyhow::Result<()>;
+}
+
+// writes notifications as structured logs with the "notification" target,
+// they are shipped to the notification service by the log collector.
+pub struct LogSink;
+
+impl Sink for LogSink {
+    fn send(&self, notification: &Notification) -> anyhow::Result<()> {
+        let payload = serde_json::to_string(&notification.payload)?;
+        log::info!(target: "notification",
+            kind = notification.kind,
+            uid = notification.uid.as_str(),
+            ref_id = notification.ref_id.as_str(),
+            locale = notification.locale.as_str(),
+            payload = payload.as_str();
+            "",
+        );
+        Ok(())
+    }
+}
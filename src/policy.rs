@@ -0,0 +1,539 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use axum_web::{
+    context::unix_ms,
+    object::{cbor_from_slice, cbor_to_vec},
+};
+use scylla_orm::FieldSet;
+
+use crate::{api::AppState, conf, crypto, db};
+
+// description prefix of policy transactions, followed by "<kind>:<rule name>".
+pub const DESCRIPTION_PREFIX: &str = "policy.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Adjust {
+    Debit(i64),  // from the wallet to the system
+    Credit(i64), // from the system to the wallet
+}
+
+impl Adjust {
+    // signed amount, negative for debits.
+    pub fn amount(&self) -> i64 {
+        match self {
+            Adjust::Debit(v) => -v,
+            Adjust::Credit(v) => *v,
+        }
+    }
+}
+
+pub trait Rule: Send + Sync {
+    fn name(&self) -> &str;
+    fn kind(&self) -> &'static str;
+    // the rule is applied at most once per period to a wallet.
+    fn period_days(&self) -> i64;
+    // returns None if the wallet is not affected.
+    fn evaluate(&self, wallet: &db::Wallet, inactive_days: i64) -> Option<Adjust>;
+}
+
+// debits bps of the award balance from inactive wallets, at least 1.
+pub struct DecayRule {
+    pub name: String,
+    pub inactive_days: i64,
+    pub bps: u16,
+    pub period_days: i64,
+}
+
+impl Rule for DecayRule {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn kind(&self) -> &'static str {
+        "decay"
+    }
+
+    fn period_days(&self) -> i64 {
+        self.period_days
+    }
+
+    fn evaluate(&self, wallet: &db::Wallet, inactive_days: i64) -> Option<Adjust> {
+        if inactive_days < self.inactive_days || wallet.award <= 0 {
+            return None;
+        }
+        let amount = db::apply_bps(wallet.award, self.bps)
+            .max(1)
+            .min(wallet.award)
+            .min(db::TransactionKind::Adjustment.max_amount());
+        Some(Adjust::Debit(amount))
+    }
+}
+
+// debits all the award balance from inactive wallets.
+pub struct ExpiryRule {
+    pub name: String,
+    pub inactive_days: i64,
+    pub period_days: i64,
+}
+
+impl Rule for ExpiryRule {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn kind(&self) -> &'static str {
+        "expiry"
+    }
+
+    fn period_days(&self) -> i64 {
+        self.period_days
+    }
+
+    fn evaluate(&self, wallet: &db::Wallet, inactive_days: i64) -> Option<Adjust> {
+        if inactive_days < self.inactive_days || wallet.award <= 0 {
+            return None;
+        }
+        let amount = wallet
+            .award
+            .min(db::TransactionKind::Adjustment.max_amount());
+        Some(Adjust::Debit(amount))
+    }
+}
+
+// credits bps of the topup balance as award, regardless of the activity.
+pub struct InterestRule {
+    pub name: String,
+    pub bps: u16,
+    pub period_days: i64,
+    pub min_balance: i64,
+}
+
+impl Rule for InterestRule {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn kind(&self) -> &'static str {
+        "interest"
+    }
+
+    fn period_days(&self) -> i64 {
+        self.period_days
+    }
+
+    fn evaluate(&self, wallet: &db::Wallet, _inactive_days: i64) -> Option<Adjust> {
+        if wallet.topup <= 0 || wallet.topup < self.min_balance {
+            return None;
+        }
+        let amount =
+            db::apply_bps(wallet.topup, self.bps).min(db::TransactionKind::Adjustment.max_amount());
+        if amount <= 0 {
+            return None;
+        }
+        Some(Adjust::Credit(amount))
+    }
+}
+
+pub fn rules_from(cfg: &[conf::PolicyRule]) -> anyhow::Result<Vec<Box<dyn Rule>>> {
+    let mut names: HashSet<&str> = HashSet::with_capacity(cfg.len());
+    let mut rules: Vec<Box<dyn Rule>> = Vec::with_capacity(cfg.len());
+    for r in cfg {
+        if r.name.is_empty() || r.name.len() > 64 || !names.insert(&r.name) {
+            anyhow::bail!("invalid or duplicate policy rule name {:?}", r.name);
+        }
+        if r.period_days < 1 {
+            anyhow::bail!("invalid period_days of policy rule {}", r.name);
+        }
+        if r.bps as i64 > db::BPS_DENOMINATOR {
+            anyhow::bail!("invalid bps of policy rule {}", r.name);
+        }
+        if r.inactive_days < 0 || r.min_balance < 0 {
+            anyhow::bail!("invalid policy rule {}", r.name);
+        }
+
+        let rule: Box<dyn Rule> = match r.kind.as_str() {
+            "decay" => {
+                if r.bps == 0 || r.inactive_days == 0 {
+                    anyhow::bail!("decay rule {} requires bps and inactive_days", r.name);
+                }
+                Box::new(DecayRule {
+                    name: r.name.clone(),
+                    inactive_days: r.inactive_days,
+                    bps: r.bps,
+                    period_days: r.period_days,
+                })
+            }
+            "expiry" => {
+                if r.inactive_days == 0 {
+                    anyhow::bail!("expiry rule {} requires inactive_days", r.name);
+                }
+                Box::new(ExpiryRule {
+                    name: r.name.clone(),
+                    inactive_days: r.inactive_days,
+                    period_days: r.period_days,
+                })
+            }
+            "interest" => {
+                if r.bps == 0 {
+                    anyhow::bail!("interest rule {} requires bps", r.name);
+                }
+                Box::new(InterestRule {
+                    name: r.name.clone(),
+                    bps: r.bps,
+                    period_days: r.period_days,
+                    min_balance: r.min_balance,
+                })
+            }
+            kind => anyhow::bail!("unknown kind {} of policy rule {}", kind, r.name),
+        };
+        rules.push(rule);
+    }
+    Ok(rules)
+}
+
+// payload of policy transactions.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct PolicyPayload {
+    pub rule: String,
+    pub period: i32,
+    pub last_active_at: i64,
+}
+
+impl PolicyPayload {
+    pub fn to_payload(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(crypto::wrap_cbor_tag(&cbor_to_vec(self)?))
+    }
+
+    pub fn from_payload(payload: &[u8]) -> Option<Self> {
+        cbor_from_slice::<Self>(crypto::unwrap_cbor_tag(payload)).ok()
+    }
+}
+
+pub fn period_of(now_ms: i64, period_days: i64) -> i32 {
+    (now_ms / db::DAY_MS / period_days) as i32
+}
+
+// unix ms of the xid.
+pub fn time_of(id: &xid::Id) -> i64 {
+    let mut secs = [0u8; 4];
+    secs.copy_from_slice(&id.0[..4]);
+    u32::from_be_bytes(secs) as i64 * 1000
+}
+
+// the wallet's last activity in unix ms, policy transactions are not counted.
+async fn last_active_at(db: &db::scylladb::ScyllaDB, wallet: &db::Wallet) -> i64 {
+    let at = time_of(&wallet.txn);
+    let fields: FieldSet<db::TransactionField> = [
+        db::TransactionField::Kind,
+        db::TransactionField::Description,
+        db::TransactionField::Payload,
+    ]
+    .into();
+    // debits are from the wallet, credits are from the system.
+    for uid in [wallet.uid, db::SYS_ID] {
+        let mut txn = db::Transaction::with_pk(uid, wallet.txn);
+        if txn.get_one(db, fields).await.is_ok() {
+            // only adjustments are generated by the system.
+            if txn.kind == db::TransactionKind::Adjustment.as_ref()
+                && txn.description.starts_with(DESCRIPTION_PREFIX)
+            {
+                if let Some(p) = PolicyPayload::from_payload(&txn.payload) {
+                    return p.last_active_at;
+                }
+            }
+            return at;
+        }
+    }
+    at
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct RunStats {
+    pub scanned: u64,
+    pub applied: u64,
+    pub failed: u64,
+}
+
+// scans all wallets and applies the rules once.
+pub async fn run_once(
+    db: &db::scylladb::ScyllaDB,
+    mac: &db::HMacTag,
+    rules: &[Box<dyn Rule>],
+    page_size: u16,
+) -> anyhow::Result<RunStats> {
+    let mut stats = RunStats::default();
+    let mut page_token: Option<xid::Id> = None;
+    loop {
+        let wallets = db::Wallet::scan(db, page_size, page_token).await?;
+        let has_next = wallets.len() >= page_size as usize;
+        page_token = wallets.last().map(|w| w.uid);
+
+        for mut wallet in wallets {
+            if wallet.is_system() || wallet.is_closed() || wallet.sequence == 0 {
+                continue;
+            }
+            stats.scanned += 1;
+
+            let last_active_at = last_active_at(db, &wallet).await;
+            for rule in rules {
+                let now = unix_ms() as i64;
+                let inactive_days = (now - last_active_at) / db::DAY_MS;
+                let adjust = match rule.evaluate(&wallet, inactive_days) {
+                    Some(adjust) => adjust,
+                    None => continue,
+                };
+
+                match apply(db, mac, rule.as_ref(), &wallet, adjust, last_active_at, now).await {
+                    Ok(false) => {}
+                    Ok(true) => {
+                        stats.applied += 1;
+                        // reloads the wallet for the next rule.
+                        if let Err(err) = wallet.get_one(db).await {
+                            log::error!(target: "policy",
+                                uid = wallet.uid.to_string();
+                                "{}", err);
+                            break;
+                        }
+                    }
+                    Err(err) => {
+                        stats.failed += 1;
+                        log::error!(target: "policy",
+                            uid = wallet.uid.to_string(),
+                            rule = rule.name();
+                            "{}", err);
+                    }
+                }
+            }
+        }
+
+        if !has_next {
+            return Ok(stats);
+        }
+    }
+}
+
+// returns false if the rule has been applied to the wallet in the period.
+async fn apply(
+    db: &db::scylladb::ScyllaDB,
+    mac: &db::HMacTag,
+    rule: &dyn Rule,
+    wallet: &db::Wallet,
+    adjust: Adjust,
+    last_active_at: i64,
+    now: i64,
+) -> anyhow::Result<bool> {
+    let period = period_of(now, rule.period_days());
+    let mut audit = db::PolicyAudit::with_pk(wallet.uid, rule.name().to_string(), period);
+    audit.amount = adjust.amount();
+    audit.balance = wallet.balance();
+    audit.last_active_at = last_active_at;
+    if !audit.save(db).await? {
+        return Ok(false);
+    }
+
+    let (payer, payee) = match adjust {
+        Adjust::Debit(_) => (wallet.uid, db::SYS_ID),
+        Adjust::Credit(_) => (db::SYS_ID, wallet.uid),
+    };
+    let mut txn = db::Transaction::with_uid(payer);
+    txn.description = format!("{}{}:{}", DESCRIPTION_PREFIX, rule.kind(), rule.name());
+    txn.payload = PolicyPayload {
+        rule: rule.name().to_string(),
+        period,
+        last_active_at,
+    }
+    .to_payload()?;
+
+    let res = match txn
+        .prepare(
+            db,
+            mac,
+            payee,
+            db::TransactionKind::Adjustment,
+            adjust.amount().abs(),
+        )
+        .await
+    {
+        Ok(_) => txn.commit(db, mac).await.map(|_| ()),
+        Err(err) => Err(err),
+    };
+    // the txn id is set after prepared.
+    let id = if txn.id == xid::Id::default() {
+        None
+    } else {
+        Some(txn.id)
+    };
+
+    match res {
+        Ok(_) => {
+            audit.finish(db, id, 1, "".to_string()).await?;
+            log::info!(target: "policy",
+                uid = wallet.uid.to_string(),
+                rule = rule.name(),
+                period = period,
+                amount = audit.amount,
+                txn = txn.id.to_string();
+                "",
+            );
+            Ok(true)
+        }
+        Err(err) => {
+            audit.finish(db, id, -1, err.to_string()).await?;
+            Err(err)
+        }
+    }
+}
+
+// runs the policy job every interval in the background.
+pub fn spawn(app: Arc<AppState>, cfg: conf::Policy) -> anyhow::Result<()> {
+    let rules = rules_from(&cfg.rules)?;
+    if rules.is_empty() {
+        log::warn!(target: "policy", "policy enabled without rules");
+        return Ok(());
+    }
+
+    tokio::spawn(async move {
+        let interval = cfg.interval_secs.max(60);
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            if !db::JobLease::hold(&app.scylla, "policy", interval).await {
+                continue;
+            }
+            let start = unix_ms();
+            match run_once(&app.scylla, &app.mac, &rules, cfg.page_size.max(1)).await {
+                Ok(stats) => log::info!(target: "policy",
+                    scanned = stats.scanned,
+                    applied = stats.applied,
+                    failed = stats.failed,
+                    elapsed = unix_ms() - start;
+                    "",
+                ),
+                Err(err) => log::error!(target: "policy", "policy job failed: {}", err),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wallet(award: i64, topup: i64) -> db::Wallet {
+        db::Wallet {
+            uid: xid::new(),
+            sequence: 1,
+            award,
+            topup,
+            ..Default::default()
+        }
+    }
+
+    fn rule(kind: &str) -> conf::PolicyRule {
+        conf::PolicyRule {
+            name: kind.to_string(),
+            kind: kind.to_string(),
+            inactive_days: 90,
+            bps: 1000,
+            period_days: 30,
+            min_balance: 0,
+        }
+    }
+
+    #[test]
+    fn decay_rule_works() {
+        let r = DecayRule {
+            name: "decay".to_string(),
+            inactive_days: 90,
+            bps: 1000,
+            period_days: 30,
+        };
+        assert_eq!(None, r.evaluate(&wallet(1000, 0), 89));
+        assert_eq!(None, r.evaluate(&wallet(0, 1000), 90));
+        assert_eq!(Some(Adjust::Debit(100)), r.evaluate(&wallet(1000, 0), 90));
+        assert_eq!(Some(Adjust::Debit(1)), r.evaluate(&wallet(5, 0), 100));
+        assert_eq!(-100, Adjust::Debit(100).amount());
+    }
+
+    #[test]
+    fn expiry_rule_works() {
+        let r = ExpiryRule {
+            name: "expiry".to_string(),
+            inactive_days: 365,
+            period_days: 1,
+        };
+        assert_eq!(None, r.evaluate(&wallet(1000, 0), 364));
+        assert_eq!(
+            Some(Adjust::Debit(1000)),
+            r.evaluate(&wallet(1000, 50), 365)
+        );
+        assert_eq!(None, r.evaluate(&wallet(-1, 50), 365));
+    }
+
+    #[test]
+    fn interest_rule_works() {
+        let r = InterestRule {
+            name: "interest".to_string(),
+            bps: 50,
+            period_days: 30,
+            min_balance: 1000,
+        };
+        assert_eq!(None, r.evaluate(&wallet(0, 999), 0));
+        assert_eq!(Some(Adjust::Credit(5)), r.evaluate(&wallet(0, 1000), 0));
+        assert_eq!(None, r.evaluate(&wallet(0, -100), 0));
+
+        let r = InterestRule {
+            min_balance: 0,
+            ..r
+        };
+        assert_eq!(None, r.evaluate(&wallet(0, 100), 0));
+        assert_eq!(5, Adjust::Credit(5).amount());
+    }
+
+    #[test]
+    fn rules_from_works() {
+        let rules = rules_from(&[rule("decay"), rule("expiry"), rule("interest")]).unwrap();
+        assert_eq!(3, rules.len());
+        assert_eq!("decay", rules[0].kind());
+        assert_eq!("interest", rules[2].name());
+
+        assert!(rules_from(&[rule("decay"), rule("decay")]).is_err());
+        assert!(rules_from(&[rule("unknown")]).is_err());
+        assert!(rules_from(&[conf::PolicyRule {
+            period_days: 0,
+            ..rule("decay")
+        }])
+        .is_err());
+        assert!(rules_from(&[conf::PolicyRule {
+            bps: 10001,
+            ..rule("interest")
+        }])
+        .is_err());
+        assert!(rules_from(&[conf::PolicyRule {
+            inactive_days: 0,
+            ..rule("expiry")
+        }])
+        .is_err());
+    }
+
+    #[test]
+    fn policy_payload_works() {
+        let p = PolicyPayload {
+            rule: "decay".to_string(),
+            period: 650,
+            last_active_at: 1683792000000,
+        };
+        let data = p.to_payload().unwrap();
+        assert_eq!(crypto::CBOR_TAG, data[..3]);
+        assert_eq!(Some(p), PolicyPayload::from_payload(&data));
+        assert_eq!(None, PolicyPayload::from_payload(b"abc"));
+
+        assert_eq!(0, period_of(db::DAY_MS - 1, 1));
+        assert_eq!(1, period_of(db::DAY_MS * 59, 30));
+        let id = xid::new();
+        let now = unix_ms() as i64;
+        assert!((now - time_of(&id)).abs() < 2000);
+    }
+}
@@ -0,0 +1,187 @@
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+use utoipa::ToSchema;
+
+use axum_web::erring::HTTPError;
+use axum_web::object::cbor_to_vec;
+
+use crate::{api::v2::xid_time, conf, db, money};
+
+// the signing key of receipts is derived from the wallet key by the label.
+const KEY_LABEL: &str = "receipt";
+
+// seller and tax fields, it is set from conf at startup.
+static CONF: RwLock<Option<conf::Receipt>> = RwLock::new(None);
+
+pub fn set_conf(cfg: &conf::Receipt) {
+    *CONF.write().unwrap() = Some(cfg.clone());
+}
+
+fn get_conf() -> conf::Receipt {
+    CONF.read().unwrap().clone().unwrap_or_default()
+}
+
+// the receipt of a completed charge. ids are strings, so that the signed CBOR is the same
+// whatever the format of the request.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize, ToSchema)]
+pub struct Receipt {
+    pub number: String,    // "<yyyymmdd>-<charge id>"
+    pub issued_at: String, // RFC3339 creation time of the charge
+    pub uid: String,
+    pub charge: String,
+    pub txn: String,   // topup transaction of the charge
+    pub quantity: i64, // in the smallest unit of the denomination
+    pub amount: i64,   // in the smallest currency unit, tax included
+    pub amount_refunded: i64,
+    pub currency: String,
+    pub provider: String,
+    pub livemode: bool,
+    pub seller: String,
+    pub tax_id: String,
+    pub tax_name: String,
+    pub tax_rate_bps: u16,
+    pub tax_amount: i64,
+    pub denomination: money::Denomination,
+}
+
+impl Receipt {
+    // only committed (status 3) or refunded (status -1) charges have receipts.
+    pub fn from_charge(doc: &db::Charge) -> Result<Self, HTTPError> {
+        if doc.status != 3 && doc.status != -1 {
+            return Err(HTTPError::new(
+                400,
+                format!("Charge {} is not completed, status {}", doc.id, doc.status),
+            ));
+        }
+
+        let cfg = get_conf();
+        let issued_at = xid_time(&doc.id);
+        Ok(Self {
+            number: format!("{}-{}", issued_at[..10].replace('-', ""), doc.id),
+            issued_at,
+            uid: doc.uid.to_string(),
+            charge: doc.id.to_string(),
+            txn: doc.txn.map(|id| id.to_string()).unwrap_or_default(),
+            quantity: doc.quantity,
+            amount: doc.amount,
+            amount_refunded: doc.amount_refunded,
+            currency: doc.currency.clone(),
+            provider: doc.provider.clone(),
+            livemode: doc.is_livemode(),
+            seller: cfg.seller,
+            tax_id: cfg.tax_id,
+            tax_name: cfg.tax_name,
+            tax_rate_bps: cfg.tax_rate_bps,
+            tax_amount: included_tax(doc.amount, cfg.tax_rate_bps),
+            denomination: money::denomination(),
+        })
+    }
+}
+
+// tax included in the amount at the rate in basis points, the net amount is rounded half up.
+pub fn included_tax(amount: i64, bps: u16) -> i64 {
+    if amount <= 0 || bps == 0 {
+        return 0;
+    }
+
+    let d = db::BPS_DENOMINATOR as i128 + bps as i128;
+    let net = (amount as i128 * db::BPS_DENOMINATOR as i128 * 2 + d) / (2 * d);
+    amount - net as i64
+}
+
+fn signing_key(mac: &db::HMacTag) -> SigningKey {
+    SigningKey::from_bytes(&mac.derive_key(KEY_LABEL))
+}
+
+// the Ed25519 public key verifying receipts.
+pub fn public_key(mac: &db::HMacTag) -> Vec<u8> {
+    signing_key(mac).verifying_key().to_bytes().to_vec()
+}
+
+// returns the CBOR of the receipt and its Ed25519 signature.
+pub fn sign(mac: &db::HMacTag, receipt: &Receipt) -> Result<(Vec<u8>, Vec<u8>), HTTPError> {
+    let signed = cbor_to_vec(receipt)?;
+    let signature = signing_key(mac).sign(&signed);
+    Ok((signed, signature.to_bytes().to_vec()))
+}
+
+pub fn verify(public_key: &[u8], signed: &[u8], signature: &[u8]) -> bool {
+    let key = match <[u8; 32]>::try_from(public_key) {
+        Ok(key) => key,
+        Err(_) => return false,
+    };
+    let key = match VerifyingKey::from_bytes(&key) {
+        Ok(key) => key,
+        Err(_) => return false,
+    };
+    match Signature::from_slice(signature) {
+        Ok(signature) => key.verify(signed, &signature).is_ok(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn included_tax_works() {
+        assert_eq!(0, included_tax(106, 0));
+        assert_eq!(0, included_tax(0, 600));
+        assert_eq!(6, included_tax(106, 600));
+        assert_eq!(60, included_tax(1060, 600));
+        assert_eq!(1, included_tax(10, 1300));
+        assert_eq!(1667, included_tax(10000, 2000));
+    }
+
+    #[test]
+    fn receipt_works() {
+        let mut doc = db::Charge {
+            uid: xid::new(),
+            id: xid::new(),
+            status: 2,
+            quantity: 1000,
+            currency: "usd".to_string(),
+            amount: 1060,
+            provider: "stripe".to_string(),
+            ..Default::default()
+        };
+        assert!(Receipt::from_charge(&doc).is_err());
+
+        doc.status = 3;
+        doc.txn = Some(xid::new());
+        set_conf(&conf::Receipt {
+            seller: "Yiwen AI".to_string(),
+            tax_id: "123".to_string(),
+            tax_name: "VAT".to_string(),
+            tax_rate_bps: 600,
+        });
+        let receipt = Receipt::from_charge(&doc).unwrap();
+        assert_eq!(doc.id.to_string(), receipt.charge);
+        assert_eq!(doc.txn.unwrap().to_string(), receipt.txn);
+        assert!(receipt.number.ends_with(&format!("-{}", doc.id)));
+        assert_eq!(8, receipt.number.find('-').unwrap());
+        assert!(receipt.issued_at.starts_with(&receipt.number[..4]));
+        assert_eq!(60, receipt.tax_amount);
+        assert_eq!("VAT", receipt.tax_name);
+        assert!(receipt.livemode);
+        assert_eq!(money::Denomination::default(), receipt.denomination);
+        set_conf(&conf::Receipt::default());
+
+        let mac = db::HMacTag::new([1u8; 32]);
+        let (signed, signature) = sign(&mac, &receipt).unwrap();
+        let key = public_key(&mac);
+        assert!(verify(&key, &signed, &signature));
+        assert!(!verify(&key, &signed[1..], &signature));
+        assert!(!verify(&key, &signed, &signature[1..]));
+        assert!(!verify(
+            &public_key(&db::HMacTag::new([2u8; 32])),
+            &signed,
+            &signature
+        ));
+
+        let decoded: Receipt = axum_web::object::cbor_from_slice(&signed).unwrap();
+        assert_eq!(receipt, decoded);
+    }
+}
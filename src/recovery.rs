@@ -0,0 +1,186 @@
+use std::{sync::Arc, time::Duration};
+
+use axum_web::{context::unix_ms, erring::HTTPError};
+use scylla_orm::FieldSet;
+
+use crate::{api::AppState, conf, db};
+
+// the actor of the job's status transitions in txn_history.
+const ACTOR: &str = "recovery";
+
+#[derive(Debug, Default, Clone)]
+pub struct RunStats {
+    pub scanned: u64,
+    pub resumed: u64, // committed
+    pub pending: u64, // still partly committed, or being committed by others
+    pub failed: u64,
+    pub expired: u64, // prepared spends after the cancel deadline, canceled or committed
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ExpiredSpend {
+    Keep,
+    Cancel,
+    Commit,
+}
+
+fn expired_spend_of(action: &str) -> anyhow::Result<ExpiredSpend> {
+    match action {
+        "" => Ok(ExpiredSpend::Keep),
+        "cancel" => Ok(ExpiredSpend::Cancel),
+        "commit" => Ok(ExpiredSpend::Commit),
+        _ => Err(anyhow::anyhow!("Invalid expired_spend action {}", action)),
+    }
+}
+
+// resumes the partly committed transactions, and settles the expired spends once.
+pub async fn run_once(
+    db: &db::scylladb::ScyllaDB,
+    mac: &db::HMacTag,
+    cfg: &conf::Recovery,
+) -> anyhow::Result<RunStats> {
+    let mut stats = RunStats::default();
+    resume_committing(db, mac, cfg, &mut stats).await?;
+    let action = expired_spend_of(&cfg.expired_spend)?;
+    if action != ExpiredSpend::Keep && db::cancel_window_ms() > 0 {
+        settle_expired_spends(db, mac, cfg, action, &mut stats).await?;
+    }
+    Ok(stats)
+}
+
+async fn resume_committing(
+    db: &db::scylladb::ScyllaDB,
+    mac: &db::HMacTag,
+    cfg: &conf::Recovery,
+    stats: &mut RunStats,
+) -> anyhow::Result<()> {
+    let page_size = cfg.page_size.max(1);
+    let mut page_token: Option<xid::Id> = None;
+    loop {
+        let txns = db::Transaction::list_committing(db, page_size, page_token).await?;
+        let has_next = txns.len() >= page_size as usize;
+        page_token = txns.last().map(|(_, id)| *id);
+
+        for (uid, id) in txns {
+            stats.scanned += 1;
+            let mut txn = db::Transaction::with_pk(uid, id);
+            txn._actor = ACTOR.to_string();
+            let res = match txn.get_one(db, FieldSet::new()).await {
+                Ok(()) => txn.resume_commit(db, mac).await.map(|_| ()),
+                Err(err) => Err(err),
+            };
+            match res.map_err(HTTPError::from) {
+                Ok(()) => stats.resumed += 1,
+                Err(err) if err.code == 202 || err.code == 409 => stats.pending += 1,
+                Err(err) => {
+                    stats.failed += 1;
+                    log::error!(target: "recovery",
+                        uid = uid.to_string(),
+                        id = id.to_string();
+                        "{}", err.message);
+                }
+            }
+        }
+
+        if !has_next {
+            return Ok(());
+        }
+    }
+}
+
+// cancels or commits the spends prepared before the cancel window, the index rows of
+// spends committed or canceled by the callers are removed.
+async fn settle_expired_spends(
+    db: &db::scylladb::ScyllaDB,
+    mac: &db::HMacTag,
+    cfg: &conf::Recovery,
+    action: ExpiredSpend,
+    stats: &mut RunStats,
+) -> anyhow::Result<()> {
+    let page_size = cfg.page_size.max(1);
+    let cutoff = unix_ms() as i64 - db::cancel_window_ms();
+    let before = crate::api::export::xid_at(cutoff, 0);
+    let last_day = db::day_of(&before);
+    for day in (last_day - db::EXPIRED_SPEND_LOOKBACK_DAYS)..=last_day {
+        let mut page_token: Option<xid::Id> = None;
+        loop {
+            let txns =
+                db::Transaction::list_expiring(db, day, before, page_size, page_token).await?;
+            let has_next = txns.len() >= page_size as usize;
+            page_token = txns.last().map(|(_, id)| *id);
+
+            for (uid, id) in txns {
+                stats.scanned += 1;
+                let mut txn = db::Transaction::with_pk(uid, id);
+                txn._actor = ACTOR.to_string();
+                let res = match txn.get_one(db, FieldSet::new()).await {
+                    Ok(()) if txn.status != 1 => Ok(false),
+                    Ok(()) if action == ExpiredSpend::Cancel => {
+                        txn.cancel(db, mac).await.map(|_| true)
+                    }
+                    Ok(()) => txn.commit(db, mac).await.map(|_| true),
+                    Err(err) => Err(err),
+                };
+                match res.map_err(HTTPError::from) {
+                    Ok(settled) => {
+                        if settled {
+                            stats.expired += 1;
+                            log::info!(target: "recovery",
+                                action = "settle_expired_spend",
+                                uid = uid.to_string(),
+                                id = id.to_string(),
+                                cancel = action == ExpiredSpend::Cancel;
+                                "",
+                            );
+                        }
+                        txn.delete_cancel_deadline(db).await?;
+                    }
+                    // the commit is resumed by resume_committing.
+                    Err(err) if err.code == 202 || err.code == 404 => {
+                        txn.delete_cancel_deadline(db).await?;
+                    }
+                    Err(err) if err.code == 409 => stats.pending += 1,
+                    Err(err) => {
+                        stats.failed += 1;
+                        log::error!(target: "recovery",
+                            uid = uid.to_string(),
+                            id = id.to_string();
+                            "{}", err.message);
+                    }
+                }
+            }
+
+            if !has_next {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+// runs the recovery job every interval in the background.
+pub fn spawn(app: Arc<AppState>, cfg: conf::Recovery) {
+    tokio::spawn(async move {
+        let interval = cfg.interval_secs.max(10);
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            if !db::JobLease::hold(&app.scylla, "recovery", interval).await {
+                continue;
+            }
+            let start = unix_ms();
+            match run_once(&app.scylla, &app.mac, &cfg).await {
+                Ok(stats) => log::info!(target: "recovery",
+                    scanned = stats.scanned,
+                    resumed = stats.resumed,
+                    pending = stats.pending,
+                    failed = stats.failed,
+                    expired = stats.expired,
+                    elapsed = unix_ms() - start;
+                    "",
+                ),
+                Err(err) => log::error!(target: "recovery", "recovery job failed: {}", err),
+            }
+        }
+    });
+}
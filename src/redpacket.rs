@@ -0,0 +1,188 @@
+use std::{sync::Arc, time::Duration};
+
+use axum_web::context::unix_ms;
+use axum_web::erring::HTTPError;
+use axum_web::object::{cbor_to_vec, PackObject};
+use scylla_orm::FieldSet;
+
+use crate::{
+    api::{AppState, TransactionPayload},
+    conf, db,
+};
+
+// whether the redpacket should be settled at now, expired redpackets are closed for claims
+// and the unclaimed remainder is refunded to the sender.
+pub fn due(doc: &db::Redpacket, now: i64) -> bool {
+    (doc.status == 0 || doc.status == 2) && doc.is_expired(now)
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct RunStats {
+    pub scanned: u64,
+    pub settled: u64,
+    pub refunded: i64, // total refunded amount
+    pub failed: u64,
+}
+
+// scans redpackets and settles the due ones.
+pub async fn run_once(
+    db: &db::scylladb::ScyllaDB,
+    mac: &db::HMacTag,
+    cfg: &conf::Redpacket,
+) -> anyhow::Result<RunStats> {
+    let mut stats = RunStats::default();
+    let now = unix_ms() as i64;
+    let page_size = cfg.page_size.max(1);
+    let mut page_token: Option<xid::Id> = None;
+    loop {
+        let docs = db::Redpacket::scan(db, page_size, page_token).await?;
+        let has_next = docs.len() >= page_size as usize;
+        page_token = docs.last().map(|d| d.id);
+
+        for doc in docs {
+            stats.scanned += 1;
+            if !due(&doc, now) {
+                continue;
+            }
+
+            let mut doc = db::Redpacket::with_pk(doc.id);
+            match settle(db, mac, &mut doc).await {
+                Ok(refunded) => {
+                    stats.settled += 1;
+                    stats.refunded += refunded;
+                }
+                Err(err) => {
+                    stats.failed += 1;
+                    log::error!(target: "redpacket",
+                        id = doc.id.to_string(),
+                        sender = doc.sender.to_string();
+                        "{}", err);
+                }
+            }
+        }
+
+        if !has_next {
+            return Ok(stats);
+        }
+    }
+}
+
+// closes the expired redpacket, commits the escrow if it was left prepared, and refunds the
+// remaining amount to the sender by a redpacket transaction from the system.
+// it is idempotent, the refund transaction is recorded before committed, so that it can be retried.
+// returns the refunded amount.
+pub async fn settle(
+    db: &db::scylladb::ScyllaDB,
+    mac: &db::HMacTag,
+    doc: &mut db::Redpacket,
+) -> anyhow::Result<i64> {
+    doc.close(db).await?;
+    if doc.status != 2 {
+        return Ok(0);
+    }
+
+    let mut escrow = db::Transaction::with_pk(doc.sender, doc.txn);
+    escrow.get_one(db, FieldSet::new()).await?;
+    match escrow.status {
+        1 | 2 => {
+            escrow.commit(db, mac).await?;
+        }
+        3 => {}
+        status => {
+            return Err(HTTPError::new(
+                500,
+                format!(
+                    "Invalid escrow transaction {} of redpacket {}, status {}",
+                    escrow.id, doc.id, status
+                ),
+            )
+            .into());
+        }
+    }
+
+    let remaining = doc.remaining();
+    if remaining > 0 {
+        if doc.refund_txn == xid::Id::default() {
+            let mut txn = db::Transaction::with_uid(db::SYS_ID);
+            txn.description = "payee.redpacket.refund".to_string();
+            txn.payload = cbor_to_vec(&TransactionPayload {
+                kind: "redpacket".to_string(),
+                id: PackObject::Cbor(doc.id),
+                provider: None,
+                currency: None,
+                amount: None,
+            })
+            .unwrap_or_default();
+            txn.prepare(
+                db,
+                mac,
+                doc.sender,
+                db::TransactionKind::Redpacket,
+                remaining,
+            )
+            .await?;
+            if !doc.set_refund_txn(db, txn.id).await? {
+                // refunded by others.
+                txn.cancel(db, mac).await?;
+                doc.get_one(db, FieldSet::new()).await?;
+            }
+        }
+
+        let mut refund = db::Transaction::with_pk(db::SYS_ID, doc.refund_txn);
+        refund.get_one(db, FieldSet::new()).await?;
+        if refund.status == 1 || refund.status == 2 {
+            refund.commit(db, mac).await?;
+        }
+    }
+
+    doc.settle(db).await?;
+    Ok(remaining)
+}
+
+// runs the expiry job every interval in the background.
+pub fn spawn(app: Arc<AppState>, cfg: conf::Redpacket) {
+    tokio::spawn(async move {
+        let interval = cfg.interval_secs.max(60);
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            if !db::JobLease::hold(&app.scylla, "redpacket", interval).await {
+                continue;
+            }
+            let start = unix_ms();
+            match run_once(&app.scylla, &app.mac, &cfg).await {
+                Ok(stats) => log::info!(target: "redpacket",
+                    scanned = stats.scanned,
+                    settled = stats.settled,
+                    refunded = stats.refunded,
+                    failed = stats.failed,
+                    elapsed = unix_ms() - start;
+                    "",
+                ),
+                Err(err) => log::error!(target: "redpacket", "redpacket job failed: {}", err),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn due_works() {
+        let now = 1_700_000_000_000i64;
+        let mut doc = db::Redpacket {
+            status: 0,
+            expire_at: now + 1000,
+            ..Default::default()
+        };
+        assert!(!due(&doc, now));
+        assert!(due(&doc, now + 1000));
+
+        doc.status = 2;
+        assert!(due(&doc, now + 1000));
+        doc.status = 1;
+        assert!(!due(&doc, now + 1000));
+    }
+}
@@ -0,0 +1,198 @@
+use serde_json::json;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use axum_web::context::unix_ms;
+use scylla_orm::FieldSet;
+
+use crate::{
+    api::AppState,
+    conf, db,
+    notify::{Notification, Sink},
+};
+
+pub const NOTIFICATION_KIND: &str = "charge_reminder";
+
+// whether a reminder of the charge is due at now, charges are reminded when they are still
+// preparing and will expire within lead_ms.
+pub fn due(charge: &db::Charge, now: i64, lead_ms: i64, max_per_charge: i8) -> bool {
+    charge.status == 0
+        && charge.expire_at > now
+        && charge.expire_at - now <= lead_ms
+        && charge.reminders < max_per_charge
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct RunStats {
+    pub scanned: u64,
+    pub sent: u64,
+    pub skipped: u64, // opted out or capped
+    pub failed: u64,
+}
+
+// scans preparing charges and sends the due reminders once.
+pub async fn run_once(
+    db: &db::scylladb::ScyllaDB,
+    sink: &dyn Sink,
+    cfg: &conf::Reminder,
+) -> anyhow::Result<RunStats> {
+    let mut stats = RunStats::default();
+    let now = unix_ms() as i64;
+    let day = (now / db::DAY_MS) as i32;
+    let page_size = cfg.page_size.max(1);
+    // charges expire within the max expiry after created, see Charge::save.
+    let start = now - db::max_charge_expire_ms() - db::DAY_MS;
+    let mut user_reminders: HashMap<xid::Id, i64> = HashMap::new();
+    let mut page_token: Option<xid::Id> = None;
+    loop {
+        let charges = db::Charge::list_by_day(
+            db,
+            start,
+            now + 1,
+            Some(0),
+            Some(db::livemode()),
+            page_size,
+            page_token,
+        )
+        .await?;
+        let has_next = charges.len() >= page_size as usize;
+        page_token = charges.last().map(|c| c.id);
+
+        for doc in charges {
+            stats.scanned += 1;
+            let mut charge = db::Charge::with_pk(doc.uid, doc.id);
+            if let Err(err) = charge.get_one(db, FieldSet::new()).await {
+                stats.failed += 1;
+                log::error!(target: "reminder",
+                    uid = doc.uid.to_string(),
+                    id = doc.id.to_string();
+                    "{}", err);
+                continue;
+            }
+            if !due(&charge, now, cfg.lead_secs * 1000, cfg.max_per_charge) {
+                continue;
+            }
+
+            match remind(db, sink, cfg, &mut charge, day, &mut user_reminders).await {
+                Ok(true) => stats.sent += 1,
+                Ok(false) => stats.skipped += 1,
+                Err(err) => {
+                    stats.failed += 1;
+                    log::error!(target: "reminder",
+                        uid = charge.uid.to_string(),
+                        id = charge.id.to_string();
+                        "{}", err);
+                }
+            }
+        }
+
+        if !has_next {
+            return Ok(stats);
+        }
+    }
+}
+
+// returns false if the user opted out, reached the daily cap, or the reminder was claimed by others.
+async fn remind(
+    db: &db::scylladb::ScyllaDB,
+    sink: &dyn Sink,
+    cfg: &conf::Reminder,
+    charge: &mut db::Charge,
+    day: i32,
+    user_reminders: &mut HashMap<xid::Id, i64>,
+) -> anyhow::Result<bool> {
+    let mut pref = db::WalletPref::with_pk(charge.uid);
+    pref.get_one(db).await?;
+    if pref.reminders_opt_out {
+        return Ok(false);
+    }
+
+    let sent = match user_reminders.get(&charge.uid) {
+        Some(n) => *n,
+        None => db::Charge::reminders_of_day(db, charge.uid, day).await?,
+    };
+    if sent >= cfg.max_per_user_daily {
+        return Ok(false);
+    }
+
+    // claims the reminder before sending, so that it is sent at most once.
+    if !charge.mark_reminded(db).await? {
+        return Ok(false);
+    }
+    user_reminders.insert(charge.uid, sent + 1);
+
+    sink.send(&Notification {
+        kind: NOTIFICATION_KIND,
+        uid: charge.uid.to_string(),
+        ref_id: charge.id.to_string(),
+        locale: pref.locale,
+        payload: json!({
+            "provider": charge.provider,
+            "quantity": charge.quantity,
+            "currency": charge.currency,
+            "amount": charge.amount,
+            "expire_at": charge.expire_at,
+            "reminders": charge.reminders,
+        }),
+    })?;
+    Ok(true)
+}
+
+// runs the reminder job every interval in the background.
+pub fn spawn(app: Arc<AppState>, cfg: conf::Reminder, sink: Arc<dyn Sink>) {
+    tokio::spawn(async move {
+        let interval = cfg.interval_secs.max(60);
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            if !db::JobLease::hold(&app.scylla, "reminder", interval).await {
+                continue;
+            }
+            let start = unix_ms();
+            match run_once(&app.scylla, sink.as_ref(), &cfg).await {
+                Ok(stats) => log::info!(target: "reminder",
+                    scanned = stats.scanned,
+                    sent = stats.sent,
+                    skipped = stats.skipped,
+                    failed = stats.failed,
+                    elapsed = unix_ms() - start;
+                    "",
+                ),
+                Err(err) => log::error!(target: "reminder", "reminder job failed: {}", err),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn due_works() {
+        let now = 1_700_000_000_000i64;
+        let lead = 3600 * 1000;
+        let mut charge = db::Charge {
+            status: 0,
+            expire_at: now + 1000,
+            ..Default::default()
+        };
+        assert!(due(&charge, now, lead, 1));
+        assert!(!due(&charge, now, lead, 0));
+
+        charge.reminders = 1;
+        assert!(!due(&charge, now, lead, 1));
+        assert!(due(&charge, now, lead, 2));
+
+        charge.reminders = 0;
+        charge.expire_at = now + lead + 1;
+        assert!(!due(&charge, now, lead, 1));
+        charge.expire_at = now + lead;
+        assert!(due(&charge, now, lead, 1));
+        charge.expire_at = now;
+        assert!(!due(&charge, now, lead, 1));
+
+        charge.expire_at = now + 1000;
+        charge.status = 1;
+        assert!(!due(&charge, now, lead, 1));
+    }
+}
@@ -1,40 +1,212 @@
-use axum::{middleware, routing, Router};
-use std::{fs, sync::Arc};
-use tower::ServiceBuilder;
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing, Router,
+};
+use std::{collections::HashMap, fs, sync::Arc, time::Duration};
+use tower::{Service, ServiceBuilder};
 use tower_http::{
     catch_panic::CatchPanicLayer,
     compression::{predicate::SizeAbove, CompressionLayer},
 };
 
-use axum_web::context;
+use axum_web::context::{self, extract_header};
 use axum_web::encoding;
+use axum_web::erring::HTTPError;
 
-use crate::api;
+use crate::alert;
+use crate::api::{self, transaction::TransactionOutput};
 use crate::conf;
 use crate::crypto;
 use crate::db;
+use crate::moderation;
+use crate::money;
+use crate::receipt;
+use crate::stripe;
+
+// header of the tenant resolved by the gateway, requests without it are served by the default tenant.
+pub const TENANT_HEADER: &str = "x-auth-tenant";
 
-pub async fn new(cfg: conf::Conf) -> anyhow::Result<(Arc<api::AppState>, Router)> {
-    let app_state = Arc::new(new_app_state(cfg).await?);
+// returns the app states of all tenants, the default tenant first.
+pub async fn new(cfg: conf::Conf) -> anyhow::Result<(Vec<Arc<api::AppState>>, Router)> {
+    let mac = Arc::new(new_mac(&cfg)?);
+    set_globals(&cfg)?;
+    let stripe = if cfg.stripe.secret_key.is_empty() {
+        None
+    } else {
+        Some(Arc::new(stripe::Client::new(&cfg.stripe)?))
+    };
+    let moderation = moderation::Moderation::from_conf(&cfg.moderation)?.map(Arc::new);
 
+    let keyspace = if cfg.env == "test" {
+        "walletbase_test"
+    } else {
+        "walletbase"
+    };
+
+    let app_state = Arc::new(
+        new_app_state(
+            &cfg,
+            mac.clone(),
+            stripe.clone(),
+            moderation.clone(),
+            keyspace,
+            "",
+        )
+        .await?,
+    );
+    let mut states = vec![app_state.clone()];
+    let mut tenants: HashMap<String, Router> = HashMap::with_capacity(cfg.tenants.len());
+    for tenant in &cfg.tenants {
+        let state = Arc::new(
+            new_app_state(
+                &cfg,
+                mac.clone(),
+                stripe.clone(),
+                moderation.clone(),
+                keyspace,
+                tenant,
+            )
+            .await?,
+        );
+        tenants.insert(tenant.clone(), new_router(state.clone()));
+        states.push(state);
+    }
+
+    let app = new_router(app_state).layer(middleware::from_fn_with_state(
+        Arc::new(tenants),
+        dispatch_tenant,
+    ));
+    Ok((states, app))
+}
+
+// each tenant has its own router with the app state of its keyspace.
+async fn dispatch_tenant(
+    State(tenants): State<Arc<HashMap<String, Router>>>,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let tenant = extract_header(req.headers(), TENANT_HEADER, || "".to_string());
+    if tenant.is_empty() {
+        return next.run(req).await;
+    }
+
+    match tenants.get(&tenant) {
+        Some(router) => match router.clone().call(req).await {
+            Ok(res) => res,
+            Err(err) => match err {},
+        },
+        None => HTTPError::new(403, format!("Invalid tenant {}", tenant)).into_response(),
+    }
+}
+
+// sets the Retry-After header on 503 responses while the ScyllaDB circuit breaker is open
+// or the load is shed.
+async fn retry_after(
+    State(app): State<Arc<api::AppState>>,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let mut res = next.run(req).await;
+    if res.status() == StatusCode::SERVICE_UNAVAILABLE
+        && !res.headers().contains_key(header::RETRY_AFTER)
+    {
+        let breaker = app.scylla.breaker_status();
+        let shed = db::shed_status();
+        let secs = if breaker.state == "open" {
+            Some(breaker.retry_after_secs)
+        } else if shed.shedding {
+            Some(shed.retry_after_secs)
+        } else {
+            None
+        };
+        if let Some(secs) = secs {
+            res.headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(secs.max(1)));
+        }
+    }
+    res
+}
+
+fn new_router(app_state: Arc<api::AppState>) -> Router {
     let mds = ServiceBuilder::new()
         .layer(CatchPanicLayer::new())
         .layer(middleware::from_fn(context::middleware))
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            retry_after,
+        ))
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            api::audit::middleware,
+        ))
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            api::api_key::middleware,
+        ))
         .layer(CompressionLayer::new().compress_when(SizeAbove::new(encoding::MIN_ENCODING_SIZE)));
 
-    let app = Router::new()
+    let router = Router::new()
         .route("/", routing::get(api::version))
         .route("/healthz", routing::get(api::healthz))
+        .route("/metrics", routing::get(api::metrics))
         .route("/currencies", routing::get(api::currency::currencies))
+        .route(
+            "/v1/format_amount",
+            routing::get(api::currency::format_amount),
+        )
+        .route("/openapi.json", routing::get(api::openapi::openapi))
         .nest(
             "/v1/wallet",
             Router::new()
                 .route("/", routing::get(api::wallet::get))
+                .route("/denomination", routing::get(api::wallet::denomination))
                 .route("/list_credits", routing::post(api::wallet::list_credits))
+                .route(
+                    "/credits/summary",
+                    routing::get(api::wallet::credits_summary),
+                )
+                .route("/summary/weekly", routing::get(api::wallet::weekly_summary))
                 .route("/award", routing::post(api::wallet::award))
                 .route("/spend", routing::post(api::wallet::spend))
                 .route("/sponsor", routing::post(api::wallet::sponsor))
-                .route("/subscribe", routing::post(api::wallet::subscribe)),
+                .route("/subscribe", routing::post(api::wallet::subscribe))
+                .route("/simulate", routing::post(api::wallet::simulate))
+                .route(
+                    "/preferences",
+                    routing::get(api::wallet_pref::get).put(api::wallet_pref::update),
+                )
+                .route(
+                    "/webhook",
+                    routing::get(api::webhook::get)
+                        .put(api::webhook::update)
+                        .delete(api::webhook::delete),
+                )
+                .route(
+                    "/api_key",
+                    routing::post(api::api_key::create).delete(api::api_key::revoke),
+                )
+                .route("/api_key/list", routing::get(api::api_key::list))
+                .route("/withdraw", routing::post(api::withdrawal::withdraw))
+                .route("/hold", routing::post(api::hold::hold))
+                .route("/hold/capture", routing::post(api::hold::capture))
+                .route("/hold/release", routing::post(api::hold::release))
+                .route(
+                    "/redpacket",
+                    routing::post(api::redpacket::create).get(api::redpacket::get),
+                )
+                .route("/redpacket/claim", routing::post(api::redpacket::claim))
+                .route(
+                    "/subscription",
+                    routing::post(api::subscription::create).get(api::subscription::get),
+                )
+                .route(
+                    "/subscription/cancel",
+                    routing::post(api::subscription::cancel),
+                ),
         )
         .nest(
             "/v1/charge",
@@ -46,73 +218,227 @@ pub async fn new(cfg: conf::Conf) -> anyhow::Result<(Arc<api::AppState>, Router)
                         .patch(api::charge::update),
                 )
                 .route("/list", routing::post(api::charge::list))
-                // .route("/refund", routing::post(api::charge::refund))
-                .route("/complete", routing::post(api::charge::complete)),
+                .route("/refund", routing::post(api::charge::refund))
+                .route("/complete", routing::post(api::charge::complete))
+                .route("/receipt", routing::get(api::charge::receipt))
+                .route("/receipt/key", routing::get(api::charge::receipt_key)),
         )
         .nest(
             "/v1/transaction",
             Router::new()
-                .route("/", routing::get(api::transaction::get))
-                .route("/list_outgo", routing::post(api::transaction::list_outgo))
-                .route("/list_income", routing::post(api::transaction::list_income))
-                .route("/commit", routing::post(api::transaction::commit))
-                .route("/cancel", routing::post(api::transaction::cancel)),
+                .route(
+                    "/",
+                    routing::get(api::transaction::get::<TransactionOutput>),
+                )
+                .route(
+                    "/first_from_system",
+                    routing::get(api::transaction::first_from_system::<TransactionOutput>),
+                )
+                .route(
+                    "/children",
+                    routing::get(api::transaction::list_children::<TransactionOutput>),
+                )
+                .route(
+                    "/pending",
+                    routing::get(api::transaction::list_pending::<TransactionOutput>),
+                )
+                .route(
+                    "/list_outgo",
+                    routing::post(api::transaction::list_outgo::<TransactionOutput>),
+                )
+                .route(
+                    "/list_income",
+                    routing::post(api::transaction::list_income::<TransactionOutput>),
+                )
+                .route("/aggregate", routing::get(api::transaction::aggregate))
+                .route(
+                    "/aggregate_income",
+                    routing::get(api::transaction::aggregate_income),
+                )
+                .route(
+                    "/commit",
+                    routing::post(api::transaction::commit::<TransactionOutput>),
+                )
+                .route(
+                    "/cancel",
+                    routing::post(api::transaction::cancel::<TransactionOutput>),
+                )
+                .route("/dispute", routing::post(api::dispute::dispute)),
+        )
+        .nest(
+            "/v2/transaction",
+            Router::new()
+                .route("/", routing::get(api::v2::transaction::get))
+                .route(
+                    "/first_from_system",
+                    routing::get(api::v2::transaction::first_from_system),
+                )
+                .route(
+                    "/children",
+                    routing::get(api::v2::transaction::list_children),
+                )
+                .route("/pending", routing::get(api::v2::transaction::list_pending))
+                .route(
+                    "/list_outgo",
+                    routing::post(api::v2::transaction::list_outgo),
+                )
+                .route(
+                    "/list_income",
+                    routing::post(api::v2::transaction::list_income),
+                )
+                .route("/commit", routing::post(api::v2::transaction::commit))
+                .route("/cancel", routing::post(api::v2::transaction::cancel)),
         )
         .nest(
             "/v1/customer",
-            Router::new().route(
-                "/",
-                routing::post(api::customer::upsert).get(api::customer::get),
-            ),
+            Router::new()
+                .route(
+                    "/",
+                    routing::post(api::customer::upsert)
+                        .get(api::customer::get)
+                        .delete(api::customer::delete),
+                )
+                .route("/list", routing::get(api::customer::list))
+                .route("/by_customer", routing::get(api::customer::get_by_customer)),
         )
-        .route_layer(mds)
-        .with_state(app_state.clone());
+        .nest(
+            "/v1/pool",
+            Router::new()
+                .route("/", routing::post(api::pool::create).get(api::pool::get))
+                .route("/contribute", routing::post(api::pool::contribute))
+                .route("/finalize", routing::post(api::pool::finalize)),
+        )
+        .nest(
+            "/v1/admin",
+            Router::new()
+                .route(
+                    "/budget",
+                    routing::post(api::budget::create)
+                        .get(api::budget::get)
+                        .patch(api::budget::update)
+                        .delete(api::budget::delete),
+                )
+                .route("/charges", routing::get(api::charge::list_by_day))
+                .route("/system_stats", routing::get(api::wallet::system_stats))
+                .route("/stats/daily", routing::get(api::wallet::daily_stats))
+                .route("/liabilities", routing::get(api::wallet::liabilities))
+                .route("/schema", routing::get(api::schema))
+                .route(
+                    "/export/transactions",
+                    routing::get(api::export::transactions),
+                )
+                .route(
+                    "/wallet/max_overdraw",
+                    routing::post(api::wallet::update_max_overdraw),
+                )
+                .route("/wallet/close", routing::post(api::wallet::close))
+                .route("/wallet/integrity", routing::get(api::wallet::integrity))
+                .route(
+                    "/wallet/checksum/backfill",
+                    routing::post(api::wallet::backfill_checksum),
+                )
+                .route(
+                    "/wallet/adjust",
+                    routing::post(api::adjustment::adjust).get(api::adjustment::get),
+                )
+                .route(
+                    "/wallet/adjust/approve",
+                    routing::post(api::adjustment::approve),
+                )
+                .route("/withdrawal", routing::get(api::withdrawal::get))
+                .route(
+                    "/withdrawal/queue",
+                    routing::get(api::withdrawal::list_pending),
+                )
+                .route("/withdrawal/review", routing::post(api::withdrawal::review))
+                .route("/dispute", routing::get(api::dispute::get))
+                .route("/dispute/queue", routing::get(api::dispute::list_pending))
+                .route("/dispute/resolve", routing::post(api::dispute::resolve))
+                .route("/audit_log", routing::get(api::audit::list))
+                .route(
+                    "/transaction/history",
+                    routing::get(api::transaction::history),
+                ),
+        );
+
+    #[cfg(feature = "penny-test")]
+    let router = router.route("/v1/admin/penny_test", routing::post(api::penny_test::run));
 
-    Ok((app_state, app))
+    router.route_layer(mds).with_state(app_state)
 }
 
-async fn new_app_state(cfg: conf::Conf) -> anyhow::Result<api::AppState> {
+pub fn new_mac(cfg: &conf::Conf) -> anyhow::Result<db::HMacTag> {
     let aad = cfg.keys.aad.as_bytes();
 
-    let decryptor = {
+    let kek = {
         // Should use KMS on production.
         let mkek = std::env::var("YIWEN_MKEK")
             .unwrap_or("YiWenAI-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-LLc".to_string()); // default to test key
         let mkek = crypto::base64url_decode(&mkek)?;
-        let decryptor = crypto::Encrypt0::new(mkek.try_into().unwrap(), b"");
 
-        let kek = read_key(&decryptor, aad, &cfg.keys.kek)?;
-        crypto::Encrypt0::new(kek.get_private()?, b"")
+        let kek = read_key(mkek.try_into().unwrap(), aad, &cfg.keys.kek)?;
+        kek.get_private()?
     };
 
-    let mac = {
-        let wallet_key = read_key(
-            &decryptor,
-            aad,
-            &fs::read_to_string(cfg.keys.wallet_key_file)?,
-        )?;
-        db::HMacTag::new(wallet_key.get_private()?)
-    };
+    let wallet_key = read_key(kek, aad, &fs::read_to_string(&cfg.keys.wallet_key_file)?)?;
+    Ok(db::HMacTag::new(wallet_key.get_private()?))
+}
 
-    let keyspace = if cfg.env == "test" {
-        "walletbase_test"
-    } else {
-        "walletbase"
-    };
-    let scylla = db::scylladb::ScyllaDB::new(cfg.scylla, keyspace).await?;
+fn set_globals(cfg: &conf::Conf) -> anyhow::Result<()> {
+    db::set_max_overdraw(cfg.wallet.max_overdraw);
+    db::set_integrity_check_depth(cfg.wallet.integrity_check_depth);
+    db::set_withdraw_review_threshold(cfg.wallet.withdraw_review_threshold);
+    db::set_lwt_retry(cfg.wallet.lwt_max_attempts, cfg.wallet.lwt_backoff_ms);
+    db::set_job_lease(cfg.worker.lease);
+    db::set_load_shedding(db::ShedConf {
+        retry_rate: cfg.load_shedding.retry_rate,
+        error_rate: cfg.load_shedding.error_rate,
+        min_requests: cfg.load_shedding.min_requests,
+        window: Duration::from_secs(cfg.load_shedding.window_secs),
+    });
+    db::set_livemode(cfg.wallet.livemode);
+    db::set_charge_expiry(
+        cfg.wallet.charge_expire_secs,
+        cfg.wallet.charge_min_expire_secs,
+        cfg.wallet.charge_max_expire_secs,
+    )?;
+    db::set_max_amounts(&cfg.wallet.max_amounts)?;
+    db::set_min_credits(&cfg.wallet.min_credits)?;
+    db::set_cancel_window(cfg.wallet.cancel_window_secs);
+    db::set_award_quotas(cfg.wallet.award_quota, &cfg.wallet.award_quotas)?;
+    db::set_shadow_tables(&cfg.shadow.tables, cfg.shadow.sample_bps)?;
+    db::set_audit_log_ttl(cfg.audit_log.ttl_days)?;
+    api::currency::set_enabled_currencies(&cfg.wallet.currencies)?;
+    api::set_max_payload_size(cfg.wallet.max_payload_size);
+    api::export::set_export_limits(cfg.wallet.export_rate_limit, cfg.wallet.export_max_rows);
+    alert::set_thresholds(&cfg.alert);
+    receipt::set_conf(&cfg.receipt);
+    money::set_denomination(&cfg.denomination)?;
+    Ok(())
+}
+
+async fn new_app_state(
+    cfg: &conf::Conf,
+    mac: Arc<db::HMacTag>,
+    stripe: Option<Arc<stripe::Client>>,
+    moderation: Option<Arc<moderation::Moderation>>,
+    keyspace: &str,
+    tenant: &str,
+) -> anyhow::Result<api::AppState> {
+    let keyspace = db::scylladb::tenant_keyspace(keyspace, tenant)?;
+    let scylla = db::scylladb::ScyllaDB::new(cfg.scylla.clone(), &keyspace).await?;
 
     Ok(api::AppState {
         scylla: Arc::new(scylla),
-        mac: Arc::new(mac),
+        mac,
+        tenant: tenant.to_string(),
+        stripe,
+        moderation,
     })
 }
 
-fn read_key(
-    decryptor: &crypto::Encrypt0,
-    aad: &[u8],
-    ciphertext: &str,
-) -> anyhow::Result<crypto::Key> {
-    let key = crypto::base64url_decode(ciphertext.trim())?;
-    let key = decryptor.decrypt(crypto::unwrap_cbor_tag(&key), aad)?;
-    crypto::Key::from_slice(&key)
+// key files are wrapped by A256GCM or A256KW, negotiated by the COSE alg header.
+fn read_key(key: [u8; 32], aad: &[u8], ciphertext: &str) -> anyhow::Result<crypto::Key> {
+    let data = crypto::base64url_decode(ciphertext.trim())?;
+    crypto::decrypt_key(key, crypto::unwrap_cbor_tag(&data), aad)
 }
@@ -0,0 +1,264 @@
+use reqwest::header;
+use serde::Deserialize;
+use std::{collections::HashMap, time::Duration};
+
+use axum_web::erring::HTTPError;
+
+use crate::{conf, db};
+
+// Stripe API client for creating Checkout sessions of charges.
+// Sessions are created with the charge id as the idempotency key and client_reference_id,
+// so that a retried charge creation never creates two sessions, and the webhook handler of
+// the caller can map the session back to the charge.
+pub struct Client {
+    http: reqwest::Client,
+    api_base: String,
+    secret_key: String,
+    success_url: String,
+    cancel_url: String,
+    unit_quantity: i64,
+    prices: HashMap<String, String>, // lowercase currency -> price id
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CheckoutSession {
+    pub id: String,
+    pub url: Option<String>,
+    pub currency: Option<String>,
+    pub amount_total: Option<i64>,
+    pub livemode: bool,
+}
+
+#[cfg(feature = "penny-test")]
+#[derive(Debug, Clone, Deserialize)]
+pub struct PaymentIntent {
+    pub id: String,
+    pub status: String, // "succeeded" when the payment is completed
+    pub amount: i64,
+    pub currency: String,
+    pub livemode: bool,
+}
+
+#[cfg(feature = "penny-test")]
+#[derive(Debug, Clone, Deserialize)]
+pub struct Refund {
+    pub id: String,
+    pub status: Option<String>, // "succeeded" or "pending" when accepted
+    pub amount: i64,
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: ErrorObject,
+}
+
+#[derive(Deserialize)]
+struct ErrorObject {
+    code: Option<String>,
+    message: Option<String>,
+}
+
+impl Client {
+    pub fn new(cfg: &conf::Stripe) -> anyhow::Result<Self> {
+        if cfg.unit_quantity <= 0 {
+            anyhow::bail!("invalid stripe unit_quantity {}", cfg.unit_quantity);
+        }
+
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(cfg.timeout_secs.max(1)))
+            .build()?;
+        Ok(Self {
+            http,
+            api_base: cfg.api_base.trim_end_matches('/').to_string(),
+            secret_key: cfg.secret_key.clone(),
+            success_url: cfg.success_url.clone(),
+            cancel_url: cfg.cancel_url.clone(),
+            unit_quantity: cfg.unit_quantity,
+            prices: cfg
+                .prices
+                .iter()
+                .map(|(k, v)| (k.to_lowercase(), v.clone()))
+                .collect(),
+        })
+    }
+
+    // live keys are "sk_live_..." or restricted "rk_live_...".
+    pub fn livemode(&self) -> bool {
+        self.secret_key.starts_with("sk_live_") || self.secret_key.starts_with("rk_live_")
+    }
+
+    // returns the price id and the line item quantity of the charge's quantity in the currency,
+    // a price is the price of unit_quantity of the wallet's topup.
+    pub fn line_item(&self, currency: &str, quantity: i64) -> Result<(String, i64), HTTPError> {
+        let price = self.prices.get(&currency.to_lowercase()).ok_or_else(|| {
+            HTTPError::new(400, format!("No checkout price for currency {}", currency))
+        })?;
+        if quantity <= 0 || quantity % self.unit_quantity != 0 {
+            return Err(HTTPError::new(
+                400,
+                format!(
+                    "Invalid quantity {}, it should be a multiple of {}",
+                    quantity, self.unit_quantity
+                ),
+            ));
+        }
+        Ok((price.clone(), quantity / self.unit_quantity))
+    }
+
+    // creates the Checkout session of the charge, returns the session and its raw JSON
+    // object, which is stored as the charge_payload.
+    pub async fn create_checkout_session(
+        &self,
+        charge: &db::Charge,
+    ) -> anyhow::Result<(CheckoutSession, Vec<u8>)> {
+        let (price, quantity) = self.line_item(&charge.currency, charge.quantity)?;
+        let form = [
+            ("mode", "payment".to_string()),
+            ("success_url", self.success_url.clone()),
+            ("cancel_url", self.cancel_url.clone()),
+            ("client_reference_id", charge.id.to_string()),
+            ("line_items[0][price]", price),
+            ("line_items[0][quantity]", quantity.to_string()),
+            ("metadata[uid]", charge.uid.to_string()),
+            ("metadata[charge]", charge.id.to_string()),
+        ];
+
+        let body = self
+            .post_form(
+                "/v1/checkout/sessions",
+                &charge.id.to_string(),
+                &form,
+                "checkout",
+            )
+            .await?;
+        let session: CheckoutSession = serde_json::from_slice(&body)?;
+        Ok((session, body))
+    }
+
+    // creates and confirms a PaymentIntent of the penny test charge with a test card,
+    // it succeeds only in the Stripe sandbox. returns the PaymentIntent and its raw JSON object.
+    #[cfg(feature = "penny-test")]
+    pub async fn create_test_payment(
+        &self,
+        charge: &db::Charge,
+    ) -> anyhow::Result<(PaymentIntent, Vec<u8>)> {
+        let form = [
+            ("amount", charge.amount.to_string()),
+            ("currency", charge.currency.to_lowercase()),
+            ("confirm", "true".to_string()),
+            ("payment_method", "pm_card_visa".to_string()),
+            ("automatic_payment_methods[enabled]", "true".to_string()),
+            (
+                "automatic_payment_methods[allow_redirects]",
+                "never".to_string(),
+            ),
+            ("metadata[charge]", charge.id.to_string()),
+            ("metadata[penny_test]", "true".to_string()),
+        ];
+
+        let body = self
+            .post_form(
+                "/v1/payment_intents",
+                &charge.id.to_string(),
+                &form,
+                "payment",
+            )
+            .await?;
+        let intent: PaymentIntent = serde_json::from_slice(&body)?;
+        Ok((intent, body))
+    }
+
+    // refunds the amount of the PaymentIntent.
+    #[cfg(feature = "penny-test")]
+    pub async fn create_refund(
+        &self,
+        payment_intent: &str,
+        amount: i64,
+        idempotency_key: &str,
+    ) -> anyhow::Result<Refund> {
+        let form = [
+            ("payment_intent", payment_intent.to_string()),
+            ("amount", amount.to_string()),
+        ];
+
+        let body = self
+            .post_form("/v1/refunds", idempotency_key, &form, "refund")
+            .await?;
+        let refund: Refund = serde_json::from_slice(&body)?;
+        Ok(refund)
+    }
+
+    // posts the form to the Stripe API, returns the raw JSON object on success.
+    async fn post_form(
+        &self,
+        path: &str,
+        idempotency_key: &str,
+        form: &[(&str, String)],
+        op: &str,
+    ) -> anyhow::Result<Vec<u8>> {
+        let res = self
+            .http
+            .post(format!("{}{}", self.api_base, path))
+            .bearer_auth(&self.secret_key)
+            .header("idempotency-key", idempotency_key)
+            .header(header::ACCEPT, "application/json")
+            .form(form)
+            .send()
+            .await
+            .map_err(|err| HTTPError::new(502, format!("Stripe request failed: {}", err)))?;
+
+        let status = res.status();
+        let body = res.bytes().await?.to_vec();
+        if !status.is_success() {
+            let msg = match serde_json::from_slice::<ErrorBody>(&body) {
+                Ok(v) => format!(
+                    "{}: {}",
+                    v.error.code.unwrap_or_default(),
+                    v.error.message.unwrap_or_default()
+                ),
+                Err(_) => String::from_utf8_lossy(&body).to_string(),
+            };
+            return Err(HTTPError::new(
+                502,
+                format!("Stripe {} failed, status {}, {}", op, status.as_u16(), msg),
+            )
+            .into());
+        }
+        Ok(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_item_works() {
+        let cfg = conf::Stripe {
+            secret_key: "sk_test_123".to_string(),
+            unit_quantity: 100,
+            prices: HashMap::from([("USD".to_string(), "price_usd".to_string())]),
+            ..Default::default()
+        };
+        let cli = Client::new(&cfg).unwrap();
+        assert!(!cli.livemode());
+
+        assert_eq!(
+            ("price_usd".to_string(), 10),
+            cli.line_item("usd", 1000).unwrap()
+        );
+        assert_eq!(
+            ("price_usd".to_string(), 1),
+            cli.line_item("USD", 100).unwrap()
+        );
+        assert_eq!(400, cli.line_item("usd", 150).unwrap_err().code);
+        assert_eq!(400, cli.line_item("usd", 0).unwrap_err().code);
+        assert_eq!(400, cli.line_item("eur", 1000).unwrap_err().code);
+
+        let cfg = conf::Stripe {
+            secret_key: "rk_live_123".to_string(),
+            ..Default::default()
+        };
+        assert!(Client::new(&cfg).unwrap().livemode());
+    }
+}
@@ -0,0 +1,377 @@
+use serde_json::json;
+use std::{sync::Arc, time::Duration};
+
+use axum_web::context::unix_ms;
+use axum_web::erring::HTTPError;
+use axum_web::object::{cbor_to_vec, PackObject};
+use scylla_orm::FieldSet;
+
+use crate::{
+    api::{AppState, TransactionPayload},
+    conf, db,
+    notify::{Notification, Sink},
+};
+
+pub const NOTIFICATION_PAST_DUE: &str = "subscription_past_due";
+pub const NOTIFICATION_LAPSED: &str = "subscription_lapsed";
+
+// whether the subscription should be renewed at now, or a claimed renewal should be resumed.
+pub fn due(doc: &db::Subscription, now: i64) -> bool {
+    doc.status == 2 || (doc.is_live() && doc.next_renewal_at <= now)
+}
+
+// the grace window and the next retry of a renewal failed for insufficient balance at now.
+// the grace window starts at the first failure, the last retry is at the end of it.
+// returns None if the grace window ended, the subscription lapses.
+pub fn past_due_of(
+    doc: &db::Subscription,
+    now: i64,
+    grace_ms: i64,
+    retry_ms: i64,
+) -> Option<(i64, i64)> {
+    let grace_until = if doc.status == 1 {
+        doc.grace_until
+    } else {
+        now + grace_ms
+    };
+    if now >= grace_until {
+        return None;
+    }
+    Some((grace_until, (now + retry_ms.max(1000)).min(grace_until)))
+}
+
+pub fn subscription_payload(id: xid::Id) -> Vec<u8> {
+    cbor_to_vec(&TransactionPayload {
+        kind: "subscription".to_string(),
+        id: PackObject::Cbor(id),
+        provider: None,
+        currency: None,
+        amount: None,
+    })
+    .unwrap_or_default()
+}
+
+fn is_insufficient_balance(err: &HTTPError) -> bool {
+    err.code == 400 && err.message.starts_with("Insufficient balance")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Renewal {
+    Renewed,
+    PastDue,
+    Lapsed,
+    Skipped, // renewed, canceled or being renewed by others
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct RunStats {
+    pub scanned: u64,
+    pub renewed: u64,
+    pub past_due: u64, // failed for insufficient balance, retried in the grace window
+    pub lapsed: u64,
+    pub failed: u64,
+}
+
+// scans subscriptions and renews the due ones once.
+pub async fn run_once(
+    db: &db::scylladb::ScyllaDB,
+    mac: &db::HMacTag,
+    sink: &dyn Sink,
+    cfg: &conf::Subscription,
+) -> anyhow::Result<RunStats> {
+    let mut stats = RunStats::default();
+    let now = unix_ms() as i64;
+    let page_size = cfg.page_size.max(1);
+    let mut page_token: Option<(xid::Id, xid::Id)> = None;
+    loop {
+        let docs = db::Subscription::scan(db, page_size, page_token).await?;
+        let has_next = docs.len() >= page_size as usize;
+        page_token = docs.last().map(|d| (d.uid, d.payee));
+
+        for doc in docs {
+            stats.scanned += 1;
+            if !due(&doc, now) {
+                continue;
+            }
+
+            let mut doc = db::Subscription::with_pk(doc.uid, doc.payee);
+            match renew(db, mac, sink, cfg, &mut doc, now).await {
+                Ok(Renewal::Renewed) => stats.renewed += 1,
+                Ok(Renewal::PastDue) => stats.past_due += 1,
+                Ok(Renewal::Lapsed) => stats.lapsed += 1,
+                Ok(Renewal::Skipped) => {}
+                Err(err) => {
+                    stats.failed += 1;
+                    log::error!(target: "subscription",
+                        uid = doc.uid.to_string(),
+                        payee = doc.payee.to_string();
+                        "{}", err);
+                }
+            }
+        }
+
+        if !has_next {
+            return Ok(stats);
+        }
+    }
+}
+
+// renews the due subscription by a subscribe transaction from the subscriber to the payee.
+// the transaction is claimed by the subscription before committed, so that an interrupted
+// renewal is resumed by the next run rather than charged again.
+async fn renew(
+    db: &db::scylladb::ScyllaDB,
+    mac: &db::HMacTag,
+    sink: &dyn Sink,
+    cfg: &conf::Subscription,
+    doc: &mut db::Subscription,
+    now: i64,
+) -> anyhow::Result<Renewal> {
+    doc.get_one(db).await?;
+    if doc.status == 2 {
+        return resume(db, mac, doc).await;
+    }
+    if !due(doc, now) {
+        return Ok(Renewal::Skipped);
+    }
+
+    let mut txn = db::Transaction::with_uid(doc.uid);
+    txn.description = if doc.description.is_empty() {
+        "payer.subscription".to_string()
+    } else {
+        doc.description.clone()
+    };
+    txn.payload = subscription_payload(doc.id);
+    if let Err(err) = txn
+        .prepare(
+            db,
+            mac,
+            doc.payee,
+            db::TransactionKind::Subscribe,
+            doc.amount,
+        )
+        .await
+    {
+        let err: HTTPError = err.into();
+        if !is_insufficient_balance(&err) {
+            return Err(err.into());
+        }
+
+        return match past_due_of(doc, now, cfg.grace_secs * 1000, cfg.retry_secs * 1000) {
+            Some((grace_until, next_renewal_at)) => {
+                if !doc.set_past_due(db, grace_until, next_renewal_at).await? {
+                    return Ok(Renewal::Skipped);
+                }
+                // the subscriber is notified once when the grace window starts.
+                if doc.retries == 1 {
+                    notify(db, sink, doc, NOTIFICATION_PAST_DUE).await?;
+                }
+                Ok(Renewal::PastDue)
+            }
+            None => {
+                if !doc.lapse(db).await? {
+                    return Ok(Renewal::Skipped);
+                }
+                notify(db, sink, doc, NOTIFICATION_LAPSED).await?;
+                queue_lapsed_event(db, doc).await?;
+                Ok(Renewal::Lapsed)
+            }
+        };
+    }
+
+    if !doc.claim_renewal(db, txn.id).await? {
+        // renewed or canceled by others.
+        txn.cancel(db, mac).await?;
+        return Ok(Renewal::Skipped);
+    }
+    resume(db, mac, doc).await
+}
+
+// commits the claimed renewal transaction and starts the next period, it is idempotent.
+async fn resume(
+    db: &db::scylladb::ScyllaDB,
+    mac: &db::HMacTag,
+    doc: &mut db::Subscription,
+) -> anyhow::Result<Renewal> {
+    let mut txn = db::Transaction::with_pk(doc.uid, doc.last_txn);
+    txn.get_one(db, FieldSet::new()).await?;
+    match txn.status {
+        1 | 2 => {
+            txn.commit(db, mac).await?;
+        }
+        3 => {}
+        -2 => {
+            // canceled by the subscriber in the cancel window.
+            doc.renewal_canceled(db).await?;
+            return Ok(Renewal::Skipped);
+        }
+        status => {
+            return Err(HTTPError::new(
+                500,
+                format!(
+                    "Invalid renewal transaction {} of subscription {}, status {}",
+                    txn.id, doc.id, status
+                ),
+            )
+            .into());
+        }
+    }
+
+    if !doc.renewed(db).await? {
+        return Ok(Renewal::Skipped);
+    }
+    Ok(Renewal::Renewed)
+}
+
+async fn notify(
+    db: &db::scylladb::ScyllaDB,
+    sink: &dyn Sink,
+    doc: &db::Subscription,
+    kind: &'static str,
+) -> anyhow::Result<()> {
+    let mut pref = db::WalletPref::with_pk(doc.uid);
+    pref.get_one(db).await?;
+    sink.send(&Notification {
+        kind,
+        uid: doc.uid.to_string(),
+        ref_id: doc.id.to_string(),
+        locale: pref.locale,
+        payload: json!({
+            "payee": doc.payee.to_string(),
+            "amount": doc.amount,
+            "period_days": doc.period_days,
+            "retries": doc.retries,
+            "grace_until": doc.grace_until,
+            "next_renewal_at": doc.next_renewal_at,
+        }),
+    })
+}
+
+// queues the lapse event to the payee's webhook, if it is enabled.
+async fn queue_lapsed_event(
+    db: &db::scylladb::ScyllaDB,
+    doc: &db::Subscription,
+) -> anyhow::Result<()> {
+    let mut webhook = db::WalletWebhook::with_pk(doc.payee);
+    if !webhook.get_one(db).await? || !webhook.is_enabled() {
+        return Ok(());
+    }
+
+    db::WebhookEvent::new(
+        doc.payee,
+        db::WEBHOOK_EVENT_SUBSCRIPTION_LAPSED,
+        doc.uid,
+        doc.last_txn,
+        doc.amount,
+    )
+    .save(db)
+    .await
+}
+
+// runs the renewal job every interval in the background.
+pub fn spawn(app: Arc<AppState>, cfg: conf::Subscription, sink: Arc<dyn Sink>) {
+    tokio::spawn(async move {
+        let interval = cfg.interval_secs.max(60);
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            if !db::JobLease::hold(&app.scylla, "subscription", interval).await {
+                continue;
+            }
+            let start = unix_ms();
+            match run_once(&app.scylla, &app.mac, sink.as_ref(), &cfg).await {
+                Ok(stats) => log::info!(target: "subscription",
+                    scanned = stats.scanned,
+                    renewed = stats.renewed,
+                    past_due = stats.past_due,
+                    lapsed = stats.lapsed,
+                    failed = stats.failed,
+                    elapsed = unix_ms() - start;
+                    "",
+                ),
+                Err(err) => log::error!(target: "subscription", "subscription job failed: {}", err),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn due_works() {
+        let now = 1_700_000_000_000i64;
+        let mut doc = db::Subscription {
+            status: 0,
+            next_renewal_at: now + 1000,
+            ..Default::default()
+        };
+        assert!(!due(&doc, now));
+        assert!(due(&doc, now + 1000));
+
+        doc.status = 1;
+        assert!(!due(&doc, now));
+        assert!(due(&doc, now + 1000));
+
+        // a claimed renewal is resumed regardless of the time.
+        doc.status = 2;
+        assert!(due(&doc, now));
+
+        doc.status = -1;
+        assert!(!due(&doc, now + 1000));
+        doc.status = -2;
+        assert!(!due(&doc, now + 1000));
+    }
+
+    #[test]
+    fn past_due_of_works() {
+        let now = 1_700_000_000_000i64;
+        let grace = 3 * db::DAY_MS;
+        let retry = db::DAY_MS;
+        let mut doc = db::Subscription {
+            status: 0,
+            next_renewal_at: now,
+            ..Default::default()
+        };
+
+        // the grace window starts at the first failure.
+        assert_eq!(
+            Some((now + grace, now + retry)),
+            past_due_of(&doc, now, grace, retry)
+        );
+        // lapses at once without a grace window.
+        assert_eq!(None, past_due_of(&doc, now, 0, retry));
+
+        doc.status = 1;
+        doc.retries = 1;
+        doc.grace_until = now + grace;
+        assert_eq!(
+            Some((now + grace, now + 2 * retry)),
+            past_due_of(&doc, now + retry, grace, retry)
+        );
+        // the last retry is at the end of the grace window.
+        assert_eq!(
+            Some((now + grace, now + grace)),
+            past_due_of(&doc, now + grace - 1000, grace, retry)
+        );
+        assert_eq!(None, past_due_of(&doc, now + grace, grace, retry));
+        assert_eq!(None, past_due_of(&doc, now + grace + 1, grace, retry));
+    }
+
+    #[test]
+    fn is_insufficient_balance_works() {
+        assert!(is_insufficient_balance(&HTTPError::new(
+            400,
+            "Insufficient balance for subscribe transaction, expected 100, got 10".to_string(),
+        )));
+        assert!(!is_insufficient_balance(&HTTPError::new(
+            400,
+            "Invalid payee".to_string(),
+        )));
+        assert!(!is_insufficient_balance(&HTTPError::new(
+            409,
+            "Insufficient balance".to_string(),
+        )));
+    }
+}
@@ -0,0 +1,150 @@
+use serde_json::json;
+use std::{sync::Arc, time::Duration};
+
+use axum_web::context::unix_ms;
+use scylla_orm::FieldSet;
+
+use crate::{
+    api::{export::xid_at, AppState},
+    conf, db,
+    notify::{Notification, Sink},
+};
+
+pub const NOTIFICATION_KIND: &str = "payee_weekly_summary";
+
+#[derive(Debug, Default, Clone)]
+pub struct RunStats {
+    pub scanned: u64,
+    pub written: u64,
+    pub skipped: u64, // idle, no income or summarized already
+    pub failed: u64,
+}
+
+// summarizes the last complete week of every wallet with income once.
+pub async fn run_once(
+    db: &db::scylladb::ScyllaDB,
+    sink: &dyn Sink,
+    cfg: &conf::Summary,
+) -> anyhow::Result<RunStats> {
+    let mut stats = RunStats::default();
+    let week = db::week_of(unix_ms() as i64) - 1;
+    let page_size = cfg.page_size.max(1);
+    let mut page_token: Option<xid::Id> = None;
+    loop {
+        let wallets = db::Wallet::scan(db, page_size, page_token).await?;
+        let has_next = wallets.len() >= page_size as usize;
+        page_token = wallets.last().map(|w| w.uid);
+
+        for wallet in wallets {
+            stats.scanned += 1;
+            // wallets not updated since the week started received nothing in the week.
+            if wallet.is_system() || wallet.updated_at < db::week_start_ms(week) {
+                stats.skipped += 1;
+                continue;
+            }
+
+            match summarize(db, sink, cfg, wallet.uid, week).await {
+                Ok(true) => stats.written += 1,
+                Ok(false) => stats.skipped += 1,
+                Err(err) => {
+                    stats.failed += 1;
+                    log::error!(target: "summary",
+                        uid = wallet.uid.to_string(),
+                        week = week;
+                        "{}", err);
+                }
+            }
+        }
+
+        if !has_next {
+            return Ok(stats);
+        }
+    }
+}
+
+// returns false if the payee has no income in the week or it is summarized already.
+async fn summarize(
+    db: &db::scylladb::ScyllaDB,
+    sink: &dyn Sink,
+    cfg: &conf::Summary,
+    uid: xid::Id,
+    week: i32,
+) -> anyhow::Result<bool> {
+    if db::PayeeWeeklySummary::exists(db, uid, week).await? {
+        return Ok(false);
+    }
+
+    let start = xid_at(db::week_start_ms(week), 0);
+    let mut token = Some(xid_at(db::week_start_ms(week + 1), 0));
+    let mut txns: Vec<db::Transaction> = Vec::new();
+    let page_size = cfg.page_size.max(1);
+    'pages: loop {
+        let index = db::PayeeTransaction::list(db, uid, page_size, token).await?;
+        let exhausted = index.len() < page_size as usize;
+        for item in index {
+            if item.txn.0 < start.0 {
+                break 'pages;
+            }
+            token = Some(item.txn);
+            let mut txn = db::Transaction::with_pk(item.uid, item.txn);
+            txn.get_one(db, FieldSet::new()).await?;
+            txns.push(txn);
+        }
+        if exhausted {
+            break;
+        }
+    }
+
+    let mut doc = db::PayeeWeeklySummary::summarize(uid, week, &txns, cfg.top_payers);
+    if doc.income <= 0 || !doc.save(db).await? {
+        return Ok(false);
+    }
+
+    let mut pref = db::WalletPref::with_pk(uid);
+    pref.get_one(db).await?;
+    sink.send(&Notification {
+        kind: NOTIFICATION_KIND,
+        uid: uid.to_string(),
+        ref_id: week.to_string(),
+        locale: pref.locale,
+        payload: json!({
+            "week": week,
+            "start_at": db::week_start_ms(week),
+            "income": doc.income,
+            "sponsors": doc.sponsors,
+            "subscribers": doc.subscribers,
+            "top_payers": doc
+                .top_payers
+                .iter()
+                .map(|(payer, amount)| json!({"uid": payer.to_string(), "amount": amount}))
+                .collect::<Vec<_>>(),
+        }),
+    })?;
+    Ok(true)
+}
+
+// runs the summary job every interval in the background.
+pub fn spawn(app: Arc<AppState>, cfg: conf::Summary, sink: Arc<dyn Sink>) {
+    tokio::spawn(async move {
+        let interval = cfg.interval_secs.max(60);
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            if !db::JobLease::hold(&app.scylla, "summary", interval).await {
+                continue;
+            }
+            let start = unix_ms();
+            match run_once(&app.scylla, sink.as_ref(), &cfg).await {
+                Ok(stats) => log::info!(target: "summary",
+                    scanned = stats.scanned,
+                    written = stats.written,
+                    skipped = stats.skipped,
+                    failed = stats.failed,
+                    elapsed = unix_ms() - start;
+                    "",
+                ),
+                Err(err) => log::error!(target: "summary", "summary job failed: {}", err),
+            }
+        }
+    });
+}
@@ -0,0 +1,259 @@
+use hmac::{Hmac, Mac};
+use reqwest::header;
+use serde::Serialize;
+use sha3::Sha3_256;
+use std::{sync::Arc, time::Duration};
+
+use axum_web::context::unix_ms;
+
+use crate::{api::AppState, conf, crypto, db};
+
+const SECRET_KID: &[u8] = b"webhook";
+// header of the signature "t={unix secs},v1={base64url HMAC-SHA3-256 of "{t}.{body}"}".
+pub const SIGNATURE_HEADER: &str = "x-walletbase-signature";
+
+// the signing secret is stored encrypted by a key derived from the wallet's key,
+// bound to the webhook's wallet.
+pub fn seal_secret(mac: &db::HMacTag, uid: &xid::Id, secret: &[u8]) -> anyhow::Result<Vec<u8>> {
+    crypto::Encrypt0::new(mac.derive_key("webhook"), SECRET_KID).encrypt(secret, &uid.0)
+}
+
+pub fn open_secret(mac: &db::HMacTag, uid: &xid::Id, sealed: &[u8]) -> anyhow::Result<Vec<u8>> {
+    crypto::Encrypt0::new(mac.derive_key("webhook"), SECRET_KID).decrypt(sealed, &uid.0)
+}
+
+// the value of SIGNATURE_HEADER, receivers verify it with the secret they registered.
+pub fn sign(secret: &[u8], timestamp: i64, body: &[u8]) -> String {
+    let mut hmac = Hmac::<Sha3_256>::new_from_slice(secret).expect("HMAC accepts any key size");
+    hmac.update(timestamp.to_string().as_bytes());
+    hmac.update(b".");
+    hmac.update(body);
+    let tag = hmac.finalize().into_bytes();
+    format!("t={},v1={}", timestamp, crypto::base64url_encode(&tag))
+}
+
+// delay before the next attempt after `attempts` failed ones, doubled per attempt.
+pub fn backoff_ms(cfg: &conf::Webhook, attempts: i32) -> i64 {
+    let secs = cfg
+        .backoff_secs
+        .max(1)
+        .saturating_mul(1u64 << attempts.clamp(0, 30))
+        .min(cfg.max_backoff_secs.max(1));
+    secs as i64 * 1000
+}
+
+#[derive(Debug, Serialize)]
+pub struct EventBody {
+    pub id: String,
+    pub kind: String,
+    pub uid: String,
+    pub txn_uid: String,
+    pub txn: String,
+    pub amount: i64,
+    pub created_at: i64,
+}
+
+impl EventBody {
+    pub fn from(event: &db::WebhookEvent) -> Self {
+        Self {
+            id: event.id.to_string(),
+            kind: event.kind.clone(),
+            uid: event.uid.to_string(),
+            txn_uid: event.txn_uid.to_string(),
+            txn: event.txn.to_string(),
+            amount: event.amount,
+            created_at: event.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct RunStats {
+    pub scanned: u64,
+    pub delivered: u64,
+    pub retried: u64,
+    pub dropped: u64, // attempts exhausted or the webhook is disabled
+    pub failed: u64,
+}
+
+async fn deliver(
+    http: &reqwest::Client,
+    mac: &db::HMacTag,
+    webhook: &db::WalletWebhook,
+    event: &db::WebhookEvent,
+) -> anyhow::Result<()> {
+    let secret = open_secret(mac, &webhook.uid, &webhook.secret)?;
+    let body = serde_json::to_vec(&EventBody::from(event))?;
+    let signature = sign(&secret, unix_ms() as i64 / 1000, &body);
+    http.post(&webhook.url)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, signature)
+        .header("x-walletbase-event", event.id.to_string())
+        .body(body)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+// delivers the due events, a failed one is retried with backoff until max_attempts.
+async fn process(
+    db: &db::scylladb::ScyllaDB,
+    mac: &db::HMacTag,
+    cfg: &conf::Webhook,
+    http: &reqwest::Client,
+    event: &mut db::WebhookEvent,
+    stats: &mut RunStats,
+) -> anyhow::Result<()> {
+    let mut webhook = db::WalletWebhook::with_pk(event.uid);
+    if !webhook.get_one(db).await? || !webhook.is_enabled() {
+        event.delete(db).await?;
+        stats.dropped += 1;
+        return Ok(());
+    }
+
+    let res = deliver(http, mac, &webhook, event).await;
+    webhook
+        .record_delivery(db, res.is_ok(), cfg.disable_after)
+        .await?;
+    match res {
+        Ok(()) => {
+            event.delete(db).await?;
+            stats.delivered += 1;
+        }
+        Err(err) => {
+            log::warn!(target: "webhook",
+                uid = event.uid.to_string(),
+                id = event.id.to_string(),
+                attempts = event.attempts + 1,
+                disabled = !webhook.is_enabled();
+                "{}", err,
+            );
+            if event.attempts + 1 >= cfg.max_attempts.max(1) as i32 {
+                event.delete(db).await?;
+                stats.dropped += 1;
+            } else {
+                let next_at = unix_ms() as i64 + backoff_ms(cfg, event.attempts);
+                event.retry_at(db, next_at).await?;
+                stats.retried += 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+// scans the pending events and delivers the due ones.
+pub async fn run_once(
+    db: &db::scylladb::ScyllaDB,
+    mac: &db::HMacTag,
+    cfg: &conf::Webhook,
+    http: &reqwest::Client,
+) -> anyhow::Result<RunStats> {
+    let mut stats = RunStats::default();
+    let now = unix_ms() as i64;
+    let page_size = cfg.page_size.max(1);
+    let mut page_token: Option<xid::Id> = None;
+    loop {
+        let events = db::WebhookEvent::scan(db, page_size, page_token).await?;
+        let has_next = events.len() >= page_size as usize;
+        page_token = events.last().map(|e| e.id);
+
+        for mut event in events {
+            stats.scanned += 1;
+            if !event.is_due(now) {
+                continue;
+            }
+
+            if let Err(err) = process(db, mac, cfg, http, &mut event, &mut stats).await {
+                stats.failed += 1;
+                log::error!(target: "webhook",
+                    uid = event.uid.to_string(),
+                    id = event.id.to_string();
+                    "{}", err);
+            }
+        }
+
+        if !has_next {
+            return Ok(stats);
+        }
+    }
+}
+
+// runs the delivery job every interval in the background.
+pub fn spawn(app: Arc<AppState>, cfg: conf::Webhook) -> anyhow::Result<()> {
+    // redirects are not followed, webhooks should be registered with their final url.
+    let http = reqwest::Client::builder()
+        .timeout(Duration::from_secs(cfg.timeout_secs.max(1)))
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?;
+    tokio::spawn(async move {
+        let interval = cfg.interval_secs.max(1);
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            if !db::JobLease::hold(&app.scylla, "webhook", interval).await {
+                continue;
+            }
+            let start = unix_ms();
+            match run_once(&app.scylla, &app.mac, &cfg, &http).await {
+                Ok(stats) => {
+                    if stats.delivered + stats.retried + stats.dropped + stats.failed > 0 {
+                        log::info!(target: "webhook",
+                            scanned = stats.scanned,
+                            delivered = stats.delivered,
+                            retried = stats.retried,
+                            dropped = stats.dropped,
+                            failed = stats.failed,
+                            elapsed = unix_ms() - start;
+                            "",
+                        )
+                    }
+                }
+                Err(err) => log::error!(target: "webhook", "webhook job failed: {}", err),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seal_secret_works() {
+        let mac = db::HMacTag::new([1u8; 32]);
+        let uid = xid::new();
+        let sealed = seal_secret(&mac, &uid, b"whsec_0123456789").unwrap();
+        assert_eq!(
+            b"whsec_0123456789".to_vec(),
+            open_secret(&mac, &uid, &sealed).unwrap()
+        );
+        assert!(open_secret(&mac, &xid::new(), &sealed).is_err());
+        assert!(open_secret(&db::HMacTag::new([2u8; 32]), &uid, &sealed).is_err());
+    }
+
+    #[test]
+    fn sign_works() {
+        let sig = sign(b"secret", 1700000000, b"{}");
+        assert!(sig.starts_with("t=1700000000,v1="));
+        assert_eq!(sig, sign(b"secret", 1700000000, b"{}"));
+        assert_ne!(sig, sign(b"secret2", 1700000000, b"{}"));
+        assert_ne!(sig, sign(b"secret", 1700000001, b"{}"));
+        assert_ne!(sig, sign(b"secret", 1700000000, b"{ }"));
+    }
+
+    #[test]
+    fn backoff_ms_works() {
+        let cfg = conf::Webhook {
+            backoff_secs: 30,
+            max_backoff_secs: 3600,
+            ..Default::default()
+        };
+        assert_eq!(30_000, backoff_ms(&cfg, 0));
+        assert_eq!(60_000, backoff_ms(&cfg, 1));
+        assert_eq!(240_000, backoff_ms(&cfg, 3));
+        assert_eq!(3_600_000, backoff_ms(&cfg, 10));
+        assert_eq!(3_600_000, backoff_ms(&cfg, 100));
+    }
+}
@@ -0,0 +1,228 @@
+use serde::Serialize;
+
+use axum_web::object::PackObject;
+use walletbase::api::{charge::ChargeOutput, transaction::TransactionOutput, wallet};
+
+mod common;
+
+#[derive(Serialize)]
+struct ChargeInput {
+    uid: PackObject<xid::Id>,
+    provider: String,
+    quantity: i64,
+    currency: String,
+    amount: i64,
+    charge_id: String,
+    charge_payload: PackObject<Vec<u8>>,
+}
+
+#[derive(Serialize)]
+struct CompleteChargeInput {
+    uid: PackObject<xid::Id>,
+    id: PackObject<xid::Id>,
+    currency: String,
+    amount: i64,
+    charge_id: String,
+    charge_payload: PackObject<Vec<u8>>,
+}
+
+#[derive(Serialize)]
+struct SpendInput {
+    uid: PackObject<xid::Id>,
+    amount: i64,
+    description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    auto_commit: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct TransactionInput {
+    uid: PackObject<xid::Id>,
+    id: PackObject<xid::Id>,
+}
+
+#[derive(Serialize)]
+struct Pagination {
+    uid: PackObject<xid::Id>,
+    page_size: u16,
+}
+
+#[derive(Serialize)]
+struct ProviderObject {
+    id: String,
+    livemode: bool,
+}
+
+fn provider_payload(charge_id: &str) -> PackObject<Vec<u8>> {
+    let mut data: Vec<u8> = Vec::new();
+    ciborium::into_writer(
+        &ProviderObject {
+            id: charge_id.to_string(),
+            livemode: true,
+        },
+        &mut data,
+    )
+    .unwrap();
+    PackObject::Cbor(data)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore]
+async fn charge_spend_commit_works() {
+    let app = common::TestApp::new().await;
+    let uid = xid::new();
+    let charge_id = format!("pi_{}", xid::new());
+
+    // charge -> complete, the wallet is topped up with the charge's quantity.
+    let res = app
+        .post::<_, ChargeOutput>(
+            "/v1/charge",
+            &ChargeInput {
+                uid: PackObject::Cbor(uid),
+                provider: "stripe".to_string(),
+                quantity: 1000,
+                currency: "usd".to_string(),
+                amount: 100,
+                charge_id: charge_id.clone(),
+                charge_payload: provider_payload(&charge_id),
+            },
+        )
+        .await
+        .unwrap();
+    let charge = res.result;
+    assert_eq!(1, charge.status);
+    assert!(charge.livemode);
+    let id = charge.id.unwrap();
+
+    let res = app
+        .post::<_, ChargeOutput>(
+            "/v1/charge/complete",
+            &CompleteChargeInput {
+                uid: PackObject::Cbor(uid),
+                id: PackObject::Cbor(id),
+                currency: "usd".to_string(),
+                amount: 100,
+                charge_id: "pi_mismatch".to_string(),
+                charge_payload: provider_payload(&charge_id),
+            },
+        )
+        .await;
+    assert_eq!(400, res.unwrap_err().code);
+
+    let res = app
+        .post::<_, ChargeOutput>(
+            "/v1/charge/complete",
+            &CompleteChargeInput {
+                uid: PackObject::Cbor(uid),
+                id: PackObject::Cbor(id),
+                currency: "usd".to_string(),
+                amount: 100,
+                charge_id: charge_id.clone(),
+                charge_payload: provider_payload(&charge_id),
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(3, res.result.status);
+    assert!(res.result.txn.is_some());
+
+    let res = app
+        .get::<wallet::WalletOutput>(&format!("/v1/wallet?uid={}", uid))
+        .await
+        .unwrap();
+    let w = res.result;
+    assert_eq!(0, w.award);
+    assert_eq!(1000, w.topup);
+
+    // spend is prepared, the amount is pending until committed.
+    let res = app
+        .post::<_, wallet::WalletOutput>(
+            "/v1/wallet/spend",
+            &SpendInput {
+                uid: PackObject::Cbor(uid),
+                amount: 300,
+                description: "integration test".to_string(),
+                auto_commit: None,
+            },
+        )
+        .await
+        .unwrap();
+    let w = res.result;
+    assert_eq!(700, w.award + w.topup);
+    assert_eq!(300, w.pending_out);
+    let spend_txn = w.txn.unwrap();
+
+    let res = app
+        .post::<_, wallet::WalletOutput>(
+            "/v1/wallet/spend",
+            &SpendInput {
+                uid: PackObject::Cbor(uid),
+                amount: 10_000,
+                description: "insufficient balance".to_string(),
+                auto_commit: None,
+            },
+        )
+        .await;
+    assert!(res.is_err());
+
+    let res = app
+        .post::<_, TransactionOutput>(
+            "/v1/transaction/commit",
+            &TransactionInput {
+                uid: PackObject::Cbor(uid),
+                id: PackObject::Cbor(spend_txn),
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(3, res.result.status);
+    assert_eq!(300, res.result.amount);
+
+    let res = app
+        .get::<wallet::WalletOutput>(&format!("/v1/wallet?uid={}", uid))
+        .await
+        .unwrap();
+    let w = res.result;
+    assert_eq!(700, w.award + w.topup);
+    assert_eq!(0, w.pending_out);
+
+    // committed spends are recorded as payout credits.
+    let res = app
+        .post::<_, Vec<wallet::CreditOutput>>(
+            "/v1/wallet/list_credits",
+            &Pagination {
+                uid: PackObject::Cbor(uid),
+                page_size: 10,
+            },
+        )
+        .await
+        .unwrap();
+    let payout = res
+        .result
+        .iter()
+        .find(|c| c.txn.unwrap_ref() == &spend_txn)
+        .expect("payout credit not found");
+    assert_eq!("payout", payout.kind);
+    assert_eq!(300, payout.amount);
+
+    // auto_commit commits the spend in the same request.
+    let res = app
+        .post::<_, wallet::WalletOutput>(
+            "/v1/wallet/spend",
+            &SpendInput {
+                uid: PackObject::Cbor(uid),
+                amount: 200,
+                description: "auto commit".to_string(),
+                auto_commit: Some(true),
+            },
+        )
+        .await
+        .unwrap();
+    let w = res.result;
+    assert_eq!(500, w.award + w.topup);
+    assert_eq!(0, w.pending_out);
+    let txn = w.transaction.expect("committed transaction");
+    assert_eq!(3, txn.status);
+    assert_eq!(200, txn.amount);
+    assert_eq!(w.txn.unwrap(), txn.id.unwrap());
+}
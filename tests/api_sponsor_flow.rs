@@ -0,0 +1,184 @@
+use serde::Serialize;
+
+use axum_web::object::PackObject;
+use walletbase::api::{
+    charge::ChargeOutput,
+    pool::{ContributionOutput, PoolOutput},
+    transaction::{AggregateOutput, TransactionOutput},
+};
+
+mod common;
+
+#[derive(Serialize)]
+struct ChargeInput {
+    uid: PackObject<xid::Id>,
+    provider: String,
+    quantity: i64,
+    currency: String,
+    amount: i64,
+    charge_id: String,
+    charge_payload: PackObject<Vec<u8>>,
+}
+
+#[derive(Serialize)]
+struct CompleteChargeInput {
+    uid: PackObject<xid::Id>,
+    id: PackObject<xid::Id>,
+    currency: String,
+    amount: i64,
+    charge_id: String,
+    charge_payload: PackObject<Vec<u8>>,
+}
+
+#[derive(Serialize)]
+struct PoolInput {
+    owner: PackObject<xid::Id>,
+    goal: i64,
+    ttl_secs: i64,
+}
+
+#[derive(Serialize)]
+struct ContributeInput {
+    id: PackObject<xid::Id>,
+    uid: PackObject<xid::Id>,
+    amount: i64,
+    anonymous: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct TransactionInput {
+    uid: PackObject<xid::Id>,
+    id: PackObject<xid::Id>,
+}
+
+#[derive(Serialize)]
+struct ProviderObject {
+    id: String,
+    livemode: bool,
+}
+
+fn provider_payload(charge_id: &str) -> PackObject<Vec<u8>> {
+    let mut data: Vec<u8> = Vec::new();
+    ciborium::into_writer(
+        &ProviderObject {
+            id: charge_id.to_string(),
+            livemode: true,
+        },
+        &mut data,
+    )
+    .unwrap();
+    PackObject::Cbor(data)
+}
+
+async fn topup(app: &common::TestApp, uid: xid::Id, quantity: i64) {
+    let charge_id = format!("pi_{}", xid::new());
+    let res = app
+        .post::<_, ChargeOutput>(
+            "/v1/charge",
+            &ChargeInput {
+                uid: PackObject::Cbor(uid),
+                provider: "stripe".to_string(),
+                quantity,
+                currency: "usd".to_string(),
+                amount: 100,
+                charge_id: charge_id.clone(),
+                charge_payload: provider_payload(&charge_id),
+            },
+        )
+        .await
+        .unwrap();
+    let id = res.result.id.unwrap();
+
+    let res = app
+        .post::<_, ChargeOutput>(
+            "/v1/charge/complete",
+            &CompleteChargeInput {
+                uid: PackObject::Cbor(uid),
+                id: PackObject::Cbor(id),
+                currency: "usd".to_string(),
+                amount: 100,
+                charge_id: charge_id.clone(),
+                charge_payload: provider_payload(&charge_id),
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(3, res.result.status);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore]
+async fn anonymous_sponsor_commit_works() {
+    let app = common::TestApp::new().await;
+    let payer = xid::new();
+    let owner = xid::new();
+    topup(&app, payer, 1000).await;
+
+    // the pool contribution is a prepared anonymous sponsor, committed by the transaction api.
+    let res = app
+        .post::<_, PoolOutput>(
+            "/v1/pool",
+            &PoolInput {
+                owner: PackObject::Cbor(owner),
+                goal: 1000,
+                ttl_secs: 3600,
+            },
+        )
+        .await
+        .unwrap();
+    let pool = res.result.id.unwrap();
+
+    let res = app
+        .post::<_, ContributionOutput>(
+            "/v1/pool/contribute",
+            &ContributeInput {
+                id: PackObject::Cbor(pool),
+                uid: PackObject::Cbor(payer),
+                amount: 300,
+                anonymous: Some(true),
+            },
+        )
+        .await
+        .unwrap();
+    let txn = res.result.txn.unwrap();
+
+    let res = app
+        .post::<_, TransactionOutput>(
+            "/v1/transaction/commit",
+            &TransactionInput {
+                uid: PackObject::Cbor(payer),
+                id: PackObject::Cbor(txn),
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(3, res.result.status);
+    assert_eq!(300, res.result.amount);
+
+    // counted on the payer side only, the payee side does not expose the payer.
+    let res = app
+        .get::<Vec<AggregateOutput>>(&format!(
+            "/v1/transaction/aggregate?uid={}&payee={}",
+            payer, owner
+        ))
+        .await
+        .unwrap();
+    assert_eq!(300, res.result[0].amount);
+    assert_eq!(1, res.result[0].txns);
+
+    let res = app
+        .get::<Vec<AggregateOutput>>(&format!(
+            "/v1/transaction/aggregate_income?uid={}&payer={}",
+            owner, payer
+        ))
+        .await
+        .unwrap();
+    assert_eq!(0, res.result[0].amount);
+    assert_eq!(0, res.result[0].txns);
+
+    let res = app
+        .get::<Vec<AggregateOutput>>(&format!("/v1/transaction/aggregate_income?uid={}", owner))
+        .await
+        .unwrap();
+    assert!(res.result.iter().all(|r| r.payer.unwrap_ref() != &payer));
+}
@@ -0,0 +1,271 @@
+// Golden tests of the CBOR encoding of the API outputs, downstream services decode
+// them by map keys and types.
+//
+// Adding a field only requires regenerating the golden file, renaming or removing a
+// field or changing its type also requires bumping `api::OUTPUT_SCHEMA_VERSION`:
+//
+//     UPDATE_GOLDEN=1 cargo test --test cbor_schema
+
+use ciborium::value::Value;
+use serde::Serialize;
+use std::{collections::BTreeMap, fs};
+
+use axum_web::object::{cbor_to_vec, PackObject};
+use walletbase::api::{
+    charge::ChargeOutput, customer::CustomerOutput, transaction::TransactionOutput,
+    wallet::WalletOutput, OUTPUT_SCHEMA_VERSION,
+};
+
+const GOLDEN_FILE: &str = "tests/golden/cbor_schema.json";
+
+type Schema = BTreeMap<String, String>;
+
+#[derive(Debug, Default, serde::Deserialize, Serialize)]
+struct Golden {
+    version: u32,
+    outputs: BTreeMap<String, Schema>,
+}
+
+fn id() -> PackObject<xid::Id> {
+    PackObject::Cbor(xid::new())
+}
+
+fn bytes() -> PackObject<Vec<u8>> {
+    PackObject::Cbor(vec![0xa0])
+}
+
+// fixtures are struct literals with every optional field set, a new field fails to
+// compile until it is added here.
+fn transaction_output() -> TransactionOutput {
+    TransactionOutput {
+        id: id(),
+        sequence: 1,
+        payee: id(),
+        sub_payee: Some(id()),
+        payer: Some(id()),
+        status: 3,
+        kind: "sponsor".to_string(),
+        amount: 100,
+        sys_fee: 10,
+        sub_shares: 5,
+        net_amount: Some(85),
+        your_share: Some(5),
+        shares: Some(vec![(id(), 500)]),
+        anonymous: Some(false),
+        description: Some("description".to_string()),
+        moderation: Some(0),
+        ref_uid: Some(id()),
+        ref_txn: Some(id()),
+        payload: Some(bytes()),
+        updated_at: Some(1),
+        balance_award: Some(100),
+        balance_topup: Some(0),
+        balance_income: Some(-10),
+    }
+}
+
+fn wallet_output() -> WalletOutput {
+    WalletOutput {
+        sequence: 1,
+        award: 100,
+        topup: 100,
+        income: -10,
+        credits: 1,
+        txn: id(),
+        pending_out: 0,
+        max_overdraw: 100,
+        sys_fee_rate: 1000,
+        income_fee_rate: 1000,
+        closed_at: 0,
+        updated_at: 1,
+        transaction: Some(transaction_output()),
+        cancel_deadline: Some(1),
+    }
+}
+
+fn charge_output() -> ChargeOutput {
+    ChargeOutput {
+        uid: id(),
+        id: id(),
+        status: 3,
+        quantity: 1000,
+        provider: "stripe".to_string(),
+        livemode: true,
+        updated_at: Some(1),
+        expire_at: Some(1),
+        currency: Some("usd".to_string()),
+        amount: Some(100),
+        amount_refunded: Some(0),
+        charge_id: Some("pi_1".to_string()),
+        charge_payload: Some(bytes()),
+        txn: Some(id()),
+        txn_refunded: Some(id()),
+        failure_code: Some("card_declined".to_string()),
+        failure_msg: Some("declined".to_string()),
+        provider_fee: Some(3),
+        net_amount: Some(97),
+        reminders: Some(1),
+        reminded_at: Some(1),
+        external_ref: Some("order-1".to_string()),
+        checkout_url: Some("https://checkout.stripe.com".to_string()),
+    }
+}
+
+fn customer_output() -> CustomerOutput {
+    CustomerOutput {
+        uid: id(),
+        provider: "stripe".to_string(),
+        customer: "cus_1".to_string(),
+        livemode: true,
+        created_at: Some(1),
+        updated_at: Some(1),
+        payload: Some(bytes()),
+        customers: Some(vec!["cus_1".to_string()]),
+        charges_enabled: Some(true),
+        payouts_enabled: Some(false),
+    }
+}
+
+// "[T]" for arrays of T, "(T1,T2)" for tuples, nested maps are pinned by their own outputs.
+fn type_of(val: &Value) -> String {
+    match val {
+        Value::Integer(_) => "int".to_string(),
+        Value::Bytes(_) => "bytes".to_string(),
+        Value::Float(_) => "float".to_string(),
+        Value::Text(_) => "text".to_string(),
+        Value::Bool(_) => "bool".to_string(),
+        Value::Null => "null".to_string(),
+        Value::Tag(tag, v) => format!("tag({}){}", tag, type_of(v)),
+        Value::Map(_) => "map".to_string(),
+        Value::Array(items) => {
+            let types: Vec<String> = items.iter().map(type_of).collect();
+            match types.first() {
+                Some(t) if types.iter().all(|v| v == t) => format!("[{}]", t),
+                _ => format!("({})", types.join(",")),
+            }
+        }
+        _ => "unknown".to_string(),
+    }
+}
+
+fn schema_of<T: Serialize>(val: &T) -> Schema {
+    let data = cbor_to_vec(val).unwrap();
+    let val: Value = ciborium::from_reader(&data[..]).unwrap();
+    match val {
+        Value::Map(entries) => entries
+            .iter()
+            .map(|(k, v)| (k.as_text().unwrap().to_string(), type_of(v)))
+            .collect(),
+        _ => panic!("output should be encoded as a CBOR map"),
+    }
+}
+
+fn current() -> Golden {
+    let mut outputs = BTreeMap::new();
+    outputs.insert("WalletOutput".to_string(), schema_of(&wallet_output()));
+    outputs.insert(
+        "TransactionOutput".to_string(),
+        schema_of(&transaction_output()),
+    );
+    outputs.insert("ChargeOutput".to_string(), schema_of(&charge_output()));
+    outputs.insert("CustomerOutput".to_string(), schema_of(&customer_output()));
+    Golden {
+        version: OUTPUT_SCHEMA_VERSION,
+        outputs,
+    }
+}
+
+// removed fields and changed types of the outputs, added fields are compatible.
+fn incompatible(golden: &Golden, current: &Golden) -> Vec<String> {
+    let mut res = Vec::new();
+    for (name, schema) in &golden.outputs {
+        let fields = match current.outputs.get(name) {
+            Some(fields) => fields,
+            None => {
+                res.push(format!("{} removed", name));
+                continue;
+            }
+        };
+        for (key, ty) in schema {
+            match fields.get(key) {
+                None => res.push(format!("{}.{} removed", name, key)),
+                Some(t) if t != ty => {
+                    res.push(format!("{}.{} changed from {} to {}", name, key, ty, t))
+                }
+                _ => {}
+            }
+        }
+    }
+    res
+}
+
+#[test]
+fn cbor_schema_is_compatible() {
+    let current = current();
+    if std::env::var("UPDATE_GOLDEN").is_ok() {
+        let data = serde_json::to_string_pretty(&current).unwrap();
+        fs::write(GOLDEN_FILE, data + "\n").unwrap();
+        return;
+    }
+
+    let golden: Golden = serde_json::from_str(&fs::read_to_string(GOLDEN_FILE).unwrap()).unwrap();
+    let changes = incompatible(&golden, &current);
+    assert!(
+        changes.is_empty() || golden.version < OUTPUT_SCHEMA_VERSION,
+        "incompatible changes of the CBOR outputs: {:?}, bump api::OUTPUT_SCHEMA_VERSION and regenerate {} with UPDATE_GOLDEN=1",
+        changes,
+        GOLDEN_FILE
+    );
+    assert!(
+        golden.version == current.version && golden.outputs == current.outputs,
+        "{} is outdated, regenerate it with UPDATE_GOLDEN=1",
+        GOLDEN_FILE
+    );
+}
+
+#[test]
+fn incompatible_works() {
+    let mut golden = Golden::default();
+    golden.outputs.insert(
+        "WalletOutput".to_string(),
+        Schema::from([
+            ("sequence".to_string(), "int".to_string()),
+            ("txn".to_string(), "bytes".to_string()),
+        ]),
+    );
+
+    let mut current = Golden::default();
+    current.outputs.insert(
+        "WalletOutput".to_string(),
+        Schema::from([
+            ("sequence".to_string(), "int".to_string()),
+            ("txn".to_string(), "bytes".to_string()),
+            ("credits".to_string(), "int".to_string()),
+        ]),
+    );
+    assert!(incompatible(&golden, &current).is_empty());
+
+    current
+        .outputs
+        .get_mut("WalletOutput")
+        .unwrap()
+        .insert("txn".to_string(), "text".to_string());
+    current
+        .outputs
+        .get_mut("WalletOutput")
+        .unwrap()
+        .remove("sequence");
+    assert_eq!(
+        vec![
+            "WalletOutput.sequence removed".to_string(),
+            "WalletOutput.txn changed from bytes to text".to_string()
+        ],
+        incompatible(&golden, &current)
+    );
+
+    current.outputs.clear();
+    assert_eq!(
+        vec!["WalletOutput removed".to_string()],
+        incompatible(&golden, &current)
+    );
+}
@@ -0,0 +1,172 @@
+// Integration test harness: starts ScyllaDB in docker, applies the cql schema,
+// and builds the axum app from `router::new` against it.
+//
+// Tests using it are ignored by default, run them with docker available:
+//
+//     cargo test --test api_charge_flow -- --ignored
+#![allow(dead_code)] // shared by test crates, each uses a part of it.
+
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+    Router,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{fs, sync::Arc, time::Duration};
+use testcontainers::{
+    clients, core::WaitFor, images::generic::GenericImage, Container, RunnableImage,
+};
+use tower::ServiceExt;
+
+use axum_web::erring::{ErrorResponse, HTTPError, SuccessResponse};
+use walletbase::{api::AppState, conf, router};
+
+const SCYLLA_IMAGE: &str = "scylladb/scylla";
+const SCYLLA_TAG: &str = "5.2";
+const CQL_PORT: u16 = 9042;
+
+pub struct TestApp {
+    pub app: Router,
+    pub state: Arc<AppState>, // the default tenant's state
+    _scylla: Container<'static, GenericImage>,
+}
+
+impl TestApp {
+    pub async fn new() -> Self {
+        // the docker client must outlive the container, it is leaked for the whole test process.
+        let docker: &'static clients::Cli = Box::leak(Box::new(clients::Cli::default()));
+        let image = GenericImage::new(SCYLLA_IMAGE, SCYLLA_TAG)
+            .with_exposed_port(CQL_PORT)
+            .with_wait_for(WaitFor::Nothing);
+        let args: Vec<String> = [
+            "--smp",
+            "1",
+            "--memory",
+            "512M",
+            "--overprovisioned",
+            "1",
+            "--developer-mode",
+            "1",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let container = docker.run(RunnableImage::from((image, args)));
+        let node = format!("127.0.0.1:{}", container.get_host_port_ipv4(CQL_PORT));
+
+        apply_schema(&node).await;
+
+        let mut cfg = conf::Conf::new().unwrap_or_else(|err| panic!("config error: {}", err));
+        cfg.env = "test".to_string();
+        cfg.scylla.nodes = vec![node];
+        let (mut states, app) = router::new(cfg)
+            .await
+            .unwrap_or_else(|err| panic!("router error: {}", err));
+
+        Self {
+            app,
+            state: states.remove(0),
+            _scylla: container,
+        }
+    }
+
+    pub async fn get<O: DeserializeOwned>(
+        &self,
+        uri: &str,
+    ) -> Result<SuccessResponse<O>, HTTPError> {
+        self.request::<(), O>(Method::GET, uri, None).await
+    }
+
+    pub async fn post<I: Serialize, O: DeserializeOwned>(
+        &self,
+        uri: &str,
+        input: &I,
+    ) -> Result<SuccessResponse<O>, HTTPError> {
+        self.request(Method::POST, uri, Some(input)).await
+    }
+
+    // sends the request over CBOR, as the services behind the gateway do.
+    async fn request<I: Serialize, O: DeserializeOwned>(
+        &self,
+        method: Method,
+        uri: &str,
+        input: Option<&I>,
+    ) -> Result<SuccessResponse<O>, HTTPError> {
+        let mut body: Vec<u8> = Vec::new();
+        if let Some(input) = input {
+            ciborium::into_writer(input, &mut body).unwrap();
+        }
+
+        let req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/cbor")
+            .header(header::ACCEPT, "application/cbor")
+            .header("x-request-id", xid::new().to_string())
+            .header("x-auth-user", xid::new().to_string())
+            .body(Body::from(body))
+            .unwrap();
+
+        let res = self.app.clone().oneshot(req).await.unwrap();
+        let status = res.status();
+        let data = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        if status == StatusCode::OK {
+            return Ok(ciborium::from_reader(&data[..])
+                .unwrap_or_else(|err| panic!("{} decode error: {}", uri, err)));
+        }
+
+        // error responses are always encoded in JSON.
+        let err: ErrorResponse = serde_json::from_slice(&data)
+            .unwrap_or_else(|err| panic!("{} status {} decode error: {}", uri, status, err));
+        Err(err.error)
+    }
+}
+
+// scylla takes a while to accept CQL connections after the container started.
+async fn connect(node: &str) -> scylla::Session {
+    let mut last_err = String::new();
+    for _ in 0..120 {
+        match scylla::SessionBuilder::new().known_node(node).build().await {
+            Ok(session) => return session,
+            Err(err) => last_err = err.to_string(),
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    panic!("scylla {} not ready: {}", node, last_err);
+}
+
+async fn apply_schema(node: &str) {
+    let session = connect(node).await;
+    for file in [
+        "./cql/schema_keyspace_test.cql",
+        "./cql/schema_table.cql",
+        "./cql/init_data.cql",
+    ] {
+        for stmt in cql_statements(&fs::read_to_string(file).unwrap()) {
+            if let Some(keyspace) = stmt.strip_prefix("USE ") {
+                session.use_keyspace(keyspace.trim(), false).await.unwrap();
+                continue;
+            }
+            session
+                .query(stmt.as_str(), &[])
+                .await
+                .unwrap_or_else(|err| panic!("{}: {}\n{}", file, err, stmt));
+        }
+    }
+}
+
+fn cql_statements(content: &str) -> Vec<String> {
+    let stripped: Vec<&str> = content
+        .lines()
+        .map(|line| match line.find("--") {
+            Some(i) => &line[..i],
+            None => line,
+        })
+        .collect();
+    stripped
+        .join("\n")
+        .split(';')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
@@ -0,0 +1,236 @@
+// Recovery and retry semantics of commit and cancel under partial failure,
+// faults are injected in the ScyllaDB wrapper of the app.
+//
+//     cargo test --features fault-injection --test fault_injection -- --ignored
+use serde::Serialize;
+use std::time::Duration;
+
+use axum_web::object::PackObject;
+use walletbase::api::{transaction::TransactionOutput, wallet};
+
+mod common;
+
+#[derive(Serialize)]
+struct AwardInput {
+    payee: PackObject<xid::Id>,
+    amount: i64,
+    credits: u64,
+}
+
+#[derive(Serialize)]
+struct SpendInput {
+    uid: PackObject<xid::Id>,
+    amount: i64,
+    description: String,
+}
+
+#[derive(Serialize)]
+struct TransactionInput {
+    uid: PackObject<xid::Id>,
+    id: PackObject<xid::Id>,
+}
+
+#[derive(Serialize)]
+struct Pagination {
+    uid: PackObject<xid::Id>,
+    page_size: u16,
+}
+
+// awards 1000 to a new wallet and prepares a spend of 300, returns (uid, txn).
+async fn prepare_spend(app: &common::TestApp) -> (xid::Id, xid::Id) {
+    let uid = xid::new();
+    app.post::<_, wallet::WalletOutput>(
+        "/v1/wallet/award",
+        &AwardInput {
+            payee: PackObject::Cbor(uid),
+            amount: 1000,
+            credits: 10,
+        },
+    )
+    .await
+    .unwrap();
+
+    let res = app
+        .post::<_, wallet::WalletOutput>(
+            "/v1/wallet/spend",
+            &SpendInput {
+                uid: PackObject::Cbor(uid),
+                amount: 300,
+                description: "fault injection".to_string(),
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(300, res.result.pending_out);
+    (uid, res.result.txn.unwrap())
+}
+
+async fn get_wallet(app: &common::TestApp, uid: xid::Id) -> wallet::WalletOutput {
+    app.get::<wallet::WalletOutput>(&format!("/v1/wallet?uid={}", uid))
+        .await
+        .unwrap()
+        .result
+}
+
+async fn get_status(app: &common::TestApp, uid: xid::Id, id: xid::Id) -> i8 {
+    app.get::<TransactionOutput>(&format!("/v1/transaction?uid={}&id={}", uid, id))
+        .await
+        .unwrap()
+        .result
+        .status
+}
+
+async fn payout_credits(app: &common::TestApp, uid: xid::Id, txn: xid::Id) -> Vec<i64> {
+    app.post::<_, Vec<wallet::CreditOutput>>(
+        "/v1/wallet/list_credits",
+        &Pagination {
+            uid: PackObject::Cbor(uid),
+            page_size: 100,
+        },
+    )
+    .await
+    .unwrap()
+    .result
+    .iter()
+    .filter(|c| c.kind == "payout" && c.txn.unwrap_ref() == &txn)
+    .map(|c| c.amount)
+    .collect()
+}
+
+fn txn_input(uid: xid::Id, id: xid::Id) -> TransactionInput {
+    TransactionInput {
+        uid: PackObject::Cbor(uid),
+        id: PackObject::Cbor(id),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore]
+async fn commit_retry_repairs_credits() {
+    let app = common::TestApp::new().await;
+    let faults = &app.state.scylla.faults;
+    let (uid, txn) = prepare_spend(&app).await;
+
+    // the transaction is committed, but saving its credits failed.
+    faults.fail_nth("INSERT INTO credit", 1);
+    let res = app
+        .post::<_, TransactionOutput>("/v1/transaction/commit", &txn_input(uid, txn))
+        .await;
+    assert_eq!(500, res.unwrap_err().code);
+    faults.clear();
+
+    assert_eq!(3, get_status(&app, uid, txn).await);
+    let w = get_wallet(&app, uid).await;
+    assert_eq!(700, w.award + w.topup);
+    assert_eq!(0, w.pending_out);
+    assert!(payout_credits(&app, uid, txn).await.is_empty());
+
+    // committing again repairs the credits without applying the balance twice.
+    app.post::<_, TransactionOutput>("/v1/transaction/commit", &txn_input(uid, txn))
+        .await
+        .unwrap();
+    let w = get_wallet(&app, uid).await;
+    assert_eq!(700, w.award + w.topup);
+    assert_eq!(vec![300], payout_credits(&app, uid, txn).await);
+
+    app.post::<_, TransactionOutput>("/v1/transaction/commit", &txn_input(uid, txn))
+        .await
+        .unwrap();
+    assert_eq!(vec![300], payout_credits(&app, uid, txn).await);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore]
+async fn commit_partly_applied_is_not_applied_again() {
+    let app = common::TestApp::new().await;
+    let faults = &app.state.scylla.faults;
+    let (uid, txn) = prepare_spend(&app).await;
+    let sys = get_wallet(&app, xid::Id::default()).await;
+
+    // the payee's (system) wallet update fails, it may be applied, so the transaction stays
+    // committing and is queued for the recovery job.
+    faults.fail_nth("UPDATE wallet SET sequence", 1);
+    let res = app
+        .post::<_, TransactionOutput>("/v1/transaction/commit", &txn_input(uid, txn))
+        .await;
+    assert_eq!(202, res.unwrap_err().code);
+    faults.clear();
+
+    assert_eq!(2, get_status(&app, uid, txn).await);
+    let w = get_wallet(&app, uid).await;
+    assert_eq!(700, w.award + w.topup);
+    assert_eq!(300, w.pending_out);
+
+    // a committing transaction is resumed after the commit lease, it is never canceled.
+    let res = app
+        .post::<_, TransactionOutput>("/v1/transaction/commit", &txn_input(uid, txn))
+        .await;
+    assert_eq!(409, res.unwrap_err().code);
+    let res = app
+        .post::<_, TransactionOutput>("/v1/transaction/cancel", &txn_input(uid, txn))
+        .await;
+    assert!(res.is_err());
+    assert_eq!(2, get_status(&app, uid, txn).await);
+    assert_eq!(
+        sys.sequence,
+        get_wallet(&app, xid::Id::default()).await.sequence
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore]
+async fn cancel_partly_applied_is_not_rolled_back_again() {
+    let app = common::TestApp::new().await;
+    let faults = &app.state.scylla.faults;
+    let (uid, txn) = prepare_spend(&app).await;
+
+    // the payer's wallet is rolled back, but the final status update fails.
+    faults.fail_nth("UPDATE transaction SET status", 2);
+    let res = app
+        .post::<_, TransactionOutput>("/v1/transaction/cancel", &txn_input(uid, txn))
+        .await;
+    assert_eq!(500, res.unwrap_err().code);
+    faults.clear();
+
+    assert_eq!(-1, get_status(&app, uid, txn).await);
+    let w = get_wallet(&app, uid).await;
+    assert_eq!(1000, w.award + w.topup);
+    assert_eq!(0, w.pending_out);
+
+    // neither cancel nor commit applies to a canceling transaction.
+    let res = app
+        .post::<_, TransactionOutput>("/v1/transaction/cancel", &txn_input(uid, txn))
+        .await;
+    assert!(res.is_err());
+    let res = app
+        .post::<_, TransactionOutput>("/v1/transaction/commit", &txn_input(uid, txn))
+        .await;
+    assert!(res.is_err());
+    let w = get_wallet(&app, uid).await;
+    assert_eq!(1000, w.award + w.topup);
+    assert_eq!(0, w.pending_out);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore]
+async fn concurrent_commits_apply_once() {
+    let app = common::TestApp::new().await;
+    let faults = &app.state.scylla.faults;
+    let (uid, txn) = prepare_spend(&app).await;
+
+    // widens the window between reading and updating the transaction status.
+    faults.delay("UPDATE transaction SET status", Duration::from_millis(200));
+    let input = txn_input(uid, txn);
+    let (a, b) = tokio::join!(
+        app.post::<_, TransactionOutput>("/v1/transaction/commit", &input),
+        app.post::<_, TransactionOutput>("/v1/transaction/commit", &input),
+    );
+    faults.clear();
+    assert!(a.is_ok() || b.is_ok());
+
+    assert_eq!(3, get_status(&app, uid, txn).await);
+    let w = get_wallet(&app, uid).await;
+    assert_eq!(700, w.award + w.topup);
+    assert_eq!(0, w.pending_out);
+    assert_eq!(vec![300], payout_credits(&app, uid, txn).await);
+}